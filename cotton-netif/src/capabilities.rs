@@ -0,0 +1,117 @@
+use std::io::{Error, ErrorKind};
+
+/// What this crate can find out on the current host
+///
+/// See [`probe_capabilities`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities {
+    /// Whether [`get_interfaces`](crate::get_interfaces) can list the
+    /// network interfaces
+    pub snapshot: bool,
+
+    /// Whether
+    /// [`get_interfaces_async`](crate::get_interfaces_async) can get
+    /// change notifications from the operating system (on Linux, via
+    /// netlink)
+    pub notifications: bool,
+
+    /// If change notifications aren't available, the reason why
+    ///
+    /// Typically `PermissionDenied` in a restricted container, or
+    /// `Unsupported` on platforms (or cargo feature sets) without
+    /// notification support at all.
+    pub notification_error: Option<ErrorKind>,
+}
+
+impl Capabilities {
+    /// Whether [`get_interfaces_async`](crate::get_interfaces_async)
+    /// will fall back to polling
+    ///
+    /// That is, whether change notifications are unavailable but
+    /// snapshots are; changes are then noticed only at the polling
+    /// interval.
+    #[must_use]
+    pub fn polling(&self) -> bool {
+        self.snapshot && !self.notifications
+    }
+}
+
+/** Find out what the available mechanisms for listing interfaces can do
+
+Inside containers, or under restrictive security policies, some ways
+of obtaining network interfaces can be forbidden, and the resulting
+errors can be hard to interpret. This function tries each mechanism
+once, without needing an async runtime, and reports which ones work.
+
+```rust
+# use cotton_netif::*;
+let caps = probe_capabilities();
+if caps.polling() {
+    println!("No change notifications ({:?}), will poll",
+             caps.notification_error);
+}
+```
+ */
+#[must_use]
+pub fn probe_capabilities() -> Capabilities {
+    probe_inner(
+        crate::get_interfaces().map(|_| ()),
+        #[cfg(all(target_os = "linux", feature = "async"))]
+        crate::linux_netlink::probe(),
+        #[cfg(not(all(target_os = "linux", feature = "async")))]
+        Err(Error::from(ErrorKind::Unsupported)),
+    )
+}
+
+fn probe_inner(
+    snapshot: Result<(), Error>,
+    notifications: Result<(), Error>,
+) -> Capabilities {
+    Capabilities {
+        snapshot: snapshot.is_ok(),
+        notifications: notifications.is_ok(),
+        notification_error: notifications.err().map(|e| e.kind()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn everything_works() {
+        let c = probe_inner(Ok(()), Ok(()));
+        assert!(c.snapshot);
+        assert!(c.notifications);
+        assert_eq!(c.notification_error, None);
+        assert!(!c.polling());
+    }
+
+    #[test]
+    fn notifications_forbidden() {
+        let c =
+            probe_inner(Ok(()), Err(Error::from(ErrorKind::PermissionDenied)));
+        assert!(c.snapshot);
+        assert!(!c.notifications);
+        assert_eq!(c.notification_error, Some(ErrorKind::PermissionDenied));
+        assert!(c.polling());
+    }
+
+    #[test]
+    fn nothing_works() {
+        let c = probe_inner(
+            Err(Error::from(ErrorKind::PermissionDenied)),
+            Err(Error::from(ErrorKind::PermissionDenied)),
+        );
+        assert!(!c.snapshot);
+        assert!(!c.notifications);
+        assert!(!c.polling());
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn zzz_instantiate() {
+        let c = probe_capabilities();
+        assert!(c.snapshot);
+    }
+}
@@ -1,4 +1,7 @@
-use crate::network_event::{Flags, InterfaceIndex, NetworkEvent};
+use crate::network_event::{
+    AddressFlags, AddressOrigin, Flags, InterfaceIndex, NetworkEvent,
+    OperState,
+};
 use nix::ifaddrs;
 use nix::net::if_::InterfaceFlags;
 use std::collections::HashSet;
@@ -38,18 +41,18 @@ The output of that program on an example system might look like this (notice
 that interface `eno1` has three different addresses):
 
 ```text
-NewLink(InterfaceIndex(1), "lo", UP | LOOPBACK | RUNNING)
-NewLink(InterfaceIndex(2), "eno1", UP | BROADCAST | RUNNING | MULTICAST)
-NewLink(InterfaceIndex(3), "eno2", UP | BROADCAST | RUNNING | MULTICAST)
-NewLink(InterfaceIndex(4), "imp0", UP | POINTTOPOINT | MULTICAST)
-NewLink(InterfaceIndex(5), "docker0", UP | BROADCAST | MULTICAST)
-NewAddr(InterfaceIndex(1), 127.0.0.1, 8)
-NewAddr(InterfaceIndex(2), 192.168.168.15, 24)
-NewAddr(InterfaceIndex(2), 169.254.100.100, 16)
-NewAddr(InterfaceIndex(4), 169.254.0.1, 24)
-NewAddr(InterfaceIndex(5), 172.17.0.1, 16)
-NewAddr(InterfaceIndex(1), ::1, 128)
-NewAddr(InterfaceIndex(2), fe80::fac0:2a3b:d68e:80a2, 64)
+NewLink(InterfaceIndex(1), "lo", UP | LOOPBACK | RUNNING, Up)
+NewLink(InterfaceIndex(2), "eno1", UP | BROADCAST | RUNNING | MULTICAST, Up)
+NewLink(InterfaceIndex(3), "eno2", UP | BROADCAST | RUNNING | MULTICAST, Up)
+NewLink(InterfaceIndex(4), "imp0", UP | POINTTOPOINT | MULTICAST, LowerLayerDown)
+NewLink(InterfaceIndex(5), "docker0", UP | BROADCAST | MULTICAST, LowerLayerDown)
+NewAddr(InterfaceIndex(1), 127.0.0.1, 8, empty, Unknown)
+NewAddr(InterfaceIndex(2), 192.168.168.15, 24, MULTICAST, Unknown)
+NewAddr(InterfaceIndex(2), 169.254.100.100, 16, MULTICAST, LinkLocal)
+NewAddr(InterfaceIndex(4), 169.254.0.1, 24, empty, LinkLocal)
+NewAddr(InterfaceIndex(5), 172.17.0.1, 16, MULTICAST, Unknown)
+NewAddr(InterfaceIndex(1), ::1, 128, empty, Unknown)
+NewAddr(InterfaceIndex(2), fe80::fac0:2a3b:d68e:80a2, 64, MULTICAST, LinkLocal)
 ```
 
 As another example, here is how to list all available
@@ -60,7 +63,7 @@ multicast-capable interfaces:
 # #[cfg(not(miri))]
 for name in get_interfaces()?
     .filter_map(|e| match e {
-        NetworkEvent::NewLink(_i, name, flags)
+        NetworkEvent::NewLink(_i, name, flags, _)
             if flags.contains(Flags::RUNNING | Flags::UP | Flags::MULTICAST)
                 => Some(name),
         _ => None,
@@ -78,10 +81,13 @@ getifaddrs(3).
  */
 pub fn get_interfaces(
 ) -> Result<impl Iterator<Item = NetworkEvent>, std::io::Error> {
-    get_interfaces_inner(
+    let events: Vec<_> = get_interfaces_inner(
         nix::ifaddrs::getifaddrs,
         nix::net::if_::if_nametoindex::<str>,
-    )
+    )?
+    .collect();
+    crate::names::observe_snapshot(&events);
+    Ok(events.into_iter())
 }
 
 fn get_interfaces_inner(
@@ -107,15 +113,25 @@ fn get_interfaces_inner2(
 
         if let Ok(index) = nametoindex(&name[..]) {
             if let Some(index) = core::num::NonZeroU32::new(index) {
+                let flags = map_interface_flags(ifaddr.flags);
                 if indexes.insert(index) {
                     // New entry
                     msgs.push(NetworkEvent::NewLink(
                         InterfaceIndex(index),
                         name,
-                        map_interface_flags(ifaddr.flags),
+                        flags,
+                        OperState::from_flags(flags),
                     ));
                 }
 
+                // getifaddrs doesn't expose per-address flags, so the
+                // only hints available come from the interface
+                let address_flags = if flags.multicast_suitable() {
+                    AddressFlags::MULTICAST
+                } else {
+                    AddressFlags::empty()
+                };
+
                 if let (Some(addr), Some(mask)) =
                     (ifaddr.address, ifaddr.netmask)
                 {
@@ -131,19 +147,24 @@ fn get_interfaces_inner2(
                                 .leading_ones()
                                     & 0xFF)
                                     as u8,
+                                address_flags,
+                                AddressOrigin::from_address(&ip),
                             ));
                         }
                     } else if let Some(ipv6) = addr.as_sockaddr_in6() {
                         if let Some(netmask) = mask.as_sockaddr_in6() {
+                            let ip = IpAddr::from(ipv6.ip());
                             msgs.push(NetworkEvent::NewAddr(
                                 InterfaceIndex(index),
-                                IpAddr::from(ipv6.ip()),
+                                ip,
                                 (u128::from_be_bytes(
                                     netmask.as_ref().sin6_addr.s6_addr,
                                 )
                                 .leading_ones()
                                     & 0xFF)
                                     as u8,
+                                address_flags,
+                                AddressOrigin::from_address(&ip),
                             ));
                         }
                     }
@@ -154,6 +175,105 @@ fn get_interfaces_inner2(
     msgs.into_iter()
 }
 
+/** Obtain the current list of network interfaces, then poll for changes
+
+This is a substitute for
+[`get_interfaces_async`](crate::get_interfaces_async) on systems where
+change notifications aren't available -- for instance, in containers
+where netlink sockets are forbidden, or on platforms without netlink
+at all. (Indeed, on Linux `get_interfaces_async` falls back to this
+automatically.)
+
+The stream starts with the same events as [`get_interfaces`] would
+return. Thereafter, `getifaddrs()` is called again every `interval`,
+and the differences from the previous snapshot are reported as
+[`NetworkEvent::NewLink`], [`NetworkEvent::NewAddr`],
+[`NetworkEvent::DelAddr`] and [`NetworkEvent::DelLink`] events, just
+as if they had been notified. Changes which come and go entirely
+between two polls are, of course, never seen.
+
+# Errors
+
+Returns Err if the initial `getifaddrs()` call fails. Later failures
+are reported as Err items in the stream, and polling continues.
+
+ */
+#[cfg(feature = "async")]
+pub fn poll_interfaces(
+    interval: std::time::Duration,
+) -> Result<
+    impl futures_util::Stream<Item = Result<NetworkEvent, std::io::Error>>,
+    std::io::Error,
+> {
+    poll_interfaces_inner(interval, || Ok(get_interfaces()?.collect()))
+}
+
+#[cfg(feature = "async")]
+fn poll_interfaces_inner<
+    F: FnMut() -> Result<Vec<NetworkEvent>, std::io::Error>,
+>(
+    interval: std::time::Duration,
+    mut list_fn: F,
+) -> Result<
+    impl futures_util::Stream<Item = Result<NetworkEvent, std::io::Error>>,
+    std::io::Error,
+> {
+    let mut current = list_fn()?;
+    Ok(Box::pin(async_stream::stream! {
+        for event in current.iter() {
+            yield Ok(event.clone());
+        }
+        loop {
+            tokio::time::sleep(interval).await;
+            match list_fn() {
+                Ok(latest) => {
+                    for event in changes(&current, &latest) {
+                        yield Ok(event);
+                    }
+                    current = latest;
+                }
+                Err(e) => yield Err(e),
+            }
+        }
+    }))
+}
+
+/// The events which turn snapshot `old` into snapshot `new`
+///
+/// Departures are reported before arrivals, addresses are removed
+/// before their links, and arrivals keep the order of `new` (so links
+/// are announced before their addresses). A link or address whose
+/// flags have changed is announced again.
+#[cfg(feature = "async")]
+fn changes(old: &[NetworkEvent], new: &[NetworkEvent]) -> Vec<NetworkEvent> {
+    let mut result = Vec::new();
+    for event in old {
+        if let NetworkEvent::NewAddr(ix, addr, prefix, _, _) = event {
+            if !new.iter().any(|e| {
+                matches!(e, NetworkEvent::NewAddr(i, a, p, _, _)
+                         if i == ix && a == addr && p == prefix)
+            }) {
+                result.push(NetworkEvent::DelAddr(*ix, *addr, *prefix));
+            }
+        }
+    }
+    for event in old {
+        if let NetworkEvent::NewLink(ix, _, _, _) = event {
+            if !new.iter().any(
+                |e| matches!(e, NetworkEvent::NewLink(i, _, _, _) if i == ix),
+            ) {
+                result.push(NetworkEvent::DelLink(*ix));
+            }
+        }
+    }
+    for event in new {
+        if !old.contains(event) {
+            result.push(event.clone());
+        }
+    }
+    result
+}
+
 fn map_interface_flags(flags: InterfaceFlags) -> Flags {
     let mut newflags = Flags::default();
     for (iff, newf) in [
@@ -208,6 +328,19 @@ mod tests {
         );
     }
 
+    #[test]
+    fn oper_state_from_flags() {
+        assert_eq!(
+            OperState::from_flags(Flags::UP | Flags::RUNNING),
+            OperState::Up
+        );
+        assert_eq!(
+            OperState::from_flags(Flags::UP),
+            OperState::LowerLayerDown
+        );
+        assert_eq!(OperState::from_flags(Flags::empty()), OperState::Down);
+    }
+
     #[test]
     fn flag_loopback() {
         assert_eq!(
@@ -265,7 +398,8 @@ mod tests {
             NetworkEvent::NewLink(
                 make_index(1),
                 "eth0".to_string(),
-                Flags::UP
+                Flags::UP,
+                OperState::LowerLayerDown
             )
         );
 
@@ -276,7 +410,9 @@ mod tests {
             NetworkEvent::NewAddr(
                 make_index(1),
                 Ipv4Addr::new(192, 168, 100, 1).into(),
-                24
+                24,
+                AddressFlags::empty(),
+                AddressOrigin::Unknown
             )
         );
 
@@ -285,6 +421,62 @@ mod tests {
         assert!(fin.is_none());
     }
 
+    fn single_address_flags(flags: InterfaceFlags) -> Option<AddressFlags> {
+        let addr = SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 2), 80);
+        let mask = SocketAddrV4::new(Ipv4Addr::new(255, 255, 255, 0), 80);
+
+        let ifaddr = ifaddrs::InterfaceAddress {
+            interface_name: "tun0".to_string(),
+            flags,
+            address: Some(addr.into()),
+            netmask: Some(mask.into()),
+            broadcast: None,
+            destination: None,
+        };
+
+        get_interfaces_inner2(vec![ifaddr], index_1).find_map(|e| match e {
+            NetworkEvent::NewAddr(_, _, _, f, _) => Some(f),
+            _ => None,
+        })
+    }
+
+    #[test]
+    fn address_origin() {
+        let origin =
+            |a: &str| AddressOrigin::from_address(&a.parse().unwrap());
+        assert_eq!(origin("169.254.99.99"), AddressOrigin::LinkLocal);
+        assert_eq!(origin("fe80::1"), AddressOrigin::LinkLocal);
+        assert_eq!(origin("febf::1"), AddressOrigin::LinkLocal);
+        assert_eq!(origin("fec0::1"), AddressOrigin::Unknown);
+        assert_eq!(origin("192.168.100.1"), AddressOrigin::Unknown);
+        assert!(!AddressOrigin::LinkLocal.is_assigned());
+        assert!(!AddressOrigin::Unknown.is_assigned());
+        assert!(AddressOrigin::Static.is_assigned());
+        assert!(AddressOrigin::Dynamic.is_assigned());
+    }
+
+    #[test]
+    fn multicast_address() {
+        assert_eq!(
+            single_address_flags(
+                InterfaceFlags::IFF_UP | InterfaceFlags::IFF_MULTICAST
+            ),
+            Some(AddressFlags::MULTICAST)
+        );
+    }
+
+    #[test]
+    fn p2p_address_not_multicast() {
+        assert_eq!(
+            single_address_flags(
+                InterfaceFlags::IFF_UP
+                    | InterfaceFlags::IFF_MULTICAST
+                    | InterfaceFlags::IFF_POINTOPOINT
+            ),
+            Some(AddressFlags::empty())
+        );
+    }
+
     #[test]
     fn bad_index_ignored() {
         let addr = SocketAddrV4::new(Ipv4Addr::new(192, 168, 100, 1), 80);
@@ -363,7 +555,8 @@ mod tests {
             NetworkEvent::NewLink(
                 make_index(1),
                 "eth0".to_string(),
-                Flags::UP
+                Flags::UP,
+                OperState::LowerLayerDown
             )
         );
 
@@ -374,7 +567,9 @@ mod tests {
             NetworkEvent::NewAddr(
                 make_index(1),
                 Ipv4Addr::new(192, 168, 100, 1).into(),
-                24
+                24,
+                AddressFlags::empty(),
+                AddressOrigin::Unknown
             )
         );
 
@@ -466,7 +661,9 @@ mod tests {
             NetworkEvent::NewAddr(
                 make_index(1),
                 Ipv4Addr::new(169, 254, 99, 99).into(),
-                16
+                16,
+                AddressFlags::empty(),
+                AddressOrigin::LinkLocal
             )
         );
 
@@ -515,7 +712,8 @@ mod tests {
             NetworkEvent::NewLink(
                 make_index(2),
                 "eth1".to_string(),
-                Flags::UP | Flags::RUNNING
+                Flags::UP | Flags::RUNNING,
+                OperState::Up
             )
         );
 
@@ -526,7 +724,9 @@ mod tests {
             NetworkEvent::NewAddr(
                 make_index(2),
                 Ipv4Addr::new(169, 254, 99, 99).into(),
-                16
+                16,
+                AddressFlags::empty(),
+                AddressOrigin::LinkLocal
             )
         );
 
@@ -581,7 +781,8 @@ mod tests {
             NetworkEvent::NewLink(
                 make_index(1),
                 "eth0".to_string(),
-                Flags::UP
+                Flags::UP,
+                OperState::LowerLayerDown
             )
         );
 
@@ -594,7 +795,9 @@ mod tests {
             NetworkEvent::NewAddr(
                 make_index(1),
                 Ipv4Addr::new(192, 168, 100, 1).into(),
-                24
+                24,
+                AddressFlags::empty(),
+                AddressOrigin::Unknown
             )
         );
 
@@ -607,7 +810,9 @@ mod tests {
             NetworkEvent::NewAddr(
                 make_index(1),
                 Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1).into(),
-                32
+                32,
+                AddressFlags::empty(),
+                AddressOrigin::Unknown
             )
         );
     }
@@ -664,9 +869,155 @@ mod tests {
         assert!(s.is_err());
     }
 
+    #[cfg(feature = "async")]
+    fn eth0(flags: Flags) -> NetworkEvent {
+        NetworkEvent::NewLink(
+            make_index(2),
+            "eth0".to_string(),
+            flags,
+            OperState::from_flags(flags),
+        )
+    }
+
+    #[cfg(feature = "async")]
+    fn addr(ip: &str) -> NetworkEvent {
+        NetworkEvent::NewAddr(
+            make_index(2),
+            ip.parse().unwrap(),
+            24,
+            AddressFlags::MULTICAST,
+            AddressOrigin::Unknown,
+        )
+    }
+
+    #[test]
+    #[cfg(feature = "async")]
+    fn no_changes() {
+        let snapshot = [eth0(Flags::UP), addr("192.168.1.2")];
+        assert!(changes(&snapshot, &snapshot).is_empty());
+    }
+
+    #[test]
+    #[cfg(feature = "async")]
+    fn changes_new_address() {
+        let old = [eth0(Flags::UP), addr("192.168.1.2")];
+        let new = [eth0(Flags::UP), addr("192.168.1.2"), addr("10.0.0.1")];
+        assert_eq!(changes(&old, &new), [addr("10.0.0.1")]);
+    }
+
+    #[test]
+    #[cfg(feature = "async")]
+    fn changes_del_address() {
+        let old = [eth0(Flags::UP), addr("192.168.1.2"), addr("10.0.0.1")];
+        let new = [eth0(Flags::UP), addr("10.0.0.1")];
+        assert_eq!(
+            changes(&old, &new),
+            [NetworkEvent::DelAddr(
+                make_index(2),
+                "192.168.1.2".parse().unwrap(),
+                24
+            )]
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "async")]
+    fn changes_link_flags() {
+        let old = [eth0(Flags::UP), addr("192.168.1.2")];
+        let new = [eth0(Flags::UP | Flags::RUNNING), addr("192.168.1.2")];
+        assert_eq!(changes(&old, &new), [eth0(Flags::UP | Flags::RUNNING)]);
+    }
+
+    #[test]
+    #[cfg(feature = "async")]
+    fn changes_link_gone() {
+        let old = [eth0(Flags::UP), addr("192.168.1.2")];
+        assert_eq!(
+            changes(&old, &[]),
+            [
+                NetworkEvent::DelAddr(
+                    make_index(2),
+                    "192.168.1.2".parse().unwrap(),
+                    24
+                ),
+                NetworkEvent::DelLink(make_index(2)),
+            ]
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "async")]
+    fn changes_link_arrives() {
+        let new = [eth0(Flags::UP), addr("192.168.1.2")];
+        assert_eq!(changes(&[], &new), new);
+    }
+
+    #[test]
+    #[cfg(feature = "async")]
+    fn poll_passes_through_initial_error() {
+        let s =
+            poll_interfaces_inner(std::time::Duration::from_millis(1), || {
+                Err(std::io::Error::from(std::io::ErrorKind::PermissionDenied))
+            });
+        assert!(s.is_err());
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "async")]
+    #[cfg_attr(miri, ignore)]
+    async fn poll_reports_changes() {
+        use futures_util::StreamExt;
+
+        let mut snapshots = vec![
+            Ok(vec![eth0(Flags::UP), addr("192.168.1.2")]),
+            Err(std::io::Error::from(std::io::ErrorKind::Other)),
+            Ok(vec![eth0(Flags::UP), addr("10.0.0.1")]),
+        ]
+        .into_iter();
+        let s = poll_interfaces_inner(
+            std::time::Duration::from_millis(1),
+            move || snapshots.next().unwrap_or_else(|| Ok(Vec::new())),
+        )
+        .unwrap();
+        let events = s.take(7).collect::<Vec<_>>().await;
+        let mut events = events.into_iter();
+
+        assert_eq!(events.next().unwrap().unwrap(), eth0(Flags::UP));
+        assert_eq!(events.next().unwrap().unwrap(), addr("192.168.1.2"));
+        assert!(events.next().unwrap().is_err());
+        assert_eq!(
+            events.next().unwrap().unwrap(),
+            NetworkEvent::DelAddr(
+                make_index(2),
+                "192.168.1.2".parse().unwrap(),
+                24
+            )
+        );
+        assert_eq!(events.next().unwrap().unwrap(), addr("10.0.0.1"));
+        assert_eq!(
+            events.next().unwrap().unwrap(),
+            NetworkEvent::DelAddr(
+                make_index(2),
+                "10.0.0.1".parse().unwrap(),
+                24
+            )
+        );
+        assert_eq!(
+            events.next().unwrap().unwrap(),
+            NetworkEvent::DelLink(make_index(2))
+        );
+    }
+
     #[test]
     #[cfg_attr(miri, ignore)]
     fn zzz_instantiate() {
         assert!(get_interfaces().is_ok());
     }
+
+    #[tokio::test]
+    #[cfg(feature = "async")]
+    #[cfg_attr(miri, ignore)]
+    async fn zzz_instantiate_poll() {
+        assert!(poll_interfaces(std::time::Duration::from_secs(1)).is_ok());
+    }
 }
@@ -5,7 +5,17 @@
 //! listing (i.e., a snapshot of the current list of network
 //! interfaces) using [`get_interfaces`] and dynamic/asynchronous
 //! listing (i.e., getting events as network interfaces and addresses
-//! come and go) using [`get_interfaces_async`].
+//! come and go) using [`get_interfaces_async`]. Where the operating
+//! system won't provide change notifications (for instance, in some
+//! containers), `get_interfaces_async` falls back to polling; use
+//! [`probe_capabilities`] to find out whether that will happen.
+//! Consumers which would rather see the complete, current list of
+//! interfaces whenever anything changes, can use [`watch_interfaces`]
+//! instead.
+//!
+//! For testing code which consumes these events, the `testing` feature
+//! provides [`scripted::ScriptedNetif`], which plays back a script of
+//! events instead of asking the operating system.
 //!
 //! At present this crate *only works on Linux* (and maybe BSD) but
 //! the structure is such that adding compatibility with other
@@ -34,7 +44,10 @@ extern crate alloc;
 /** Events passed to interface observers
  */
 pub mod network_event;
-pub use network_event::{Flags, InterfaceIndex, NetworkEvent};
+pub use network_event::{
+    AddressFlags, AddressOrigin, Flags, InterfaceIndex, NetworkEvent,
+    OperState,
+};
 
 /** Dynamic listing using Linux's netlink socket
  */
@@ -43,7 +56,9 @@ pub mod linux_netlink;
 
 #[cfg(all(target_os = "linux", feature = "async"))]
 #[doc(inline)]
-pub use linux_netlink::get_interfaces_async;
+pub use linux_netlink::{
+    get_interfaces_async, get_interfaces_async_with_poll_interval,
+};
 
 /** Static listing using Linux/glibc's getifaddrs(3)
  */
@@ -54,6 +69,39 @@ pub mod getifaddrs;
 #[doc(inline)]
 pub use getifaddrs::get_interfaces;
 
+#[cfg(all(feature = "async", not(target_os = "none")))]
+#[doc(inline)]
+pub use getifaddrs::poll_interfaces;
+
+#[cfg(all(feature = "sync", not(target_os = "none")))]
+mod names;
+
+/** Finding out which listing mechanisms work on this host
+ */
+#[cfg(all(feature = "sync", not(target_os = "none")))]
+pub mod capabilities;
+
+#[cfg(all(feature = "sync", not(target_os = "none")))]
+#[doc(inline)]
+pub use capabilities::{probe_capabilities, Capabilities};
+
+/** Whole-system snapshots of the interfaces, rather than individual events
+ */
+#[cfg(all(
+    any(feature = "async", feature = "testing"),
+    not(target_os = "none")
+))]
+pub mod watch;
+
+#[cfg(all(target_os = "linux", feature = "async"))]
+#[doc(inline)]
+pub use watch::watch_interfaces;
+
+/** Simulated network interfaces, for deterministic testing
+ */
+#[cfg(all(feature = "testing", not(target_os = "none")))]
+pub mod scripted;
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -165,6 +213,48 @@ mod tests {
         assert!(Flags::POINTTOPOINT.ne(&Flags::empty()));
     }
 
+    #[test]
+    fn test_flags_multicast_suitable() {
+        assert!((Flags::UP | Flags::MULTICAST).multicast_suitable());
+        assert!(!Flags::UP.multicast_suitable());
+        assert!(!(Flags::MULTICAST | Flags::POINTTOPOINT).multicast_suitable());
+    }
+
+    #[test]
+    fn test_address_flags_default() {
+        assert_eq!(AddressFlags::default(), AddressFlags::empty());
+    }
+
+    #[test]
+    fn test_address_flags_remove() {
+        let mut f = AddressFlags::MULTICAST;
+        f.remove(AddressFlags::MULTICAST);
+        assert_eq!(f, AddressFlags::empty());
+        assert!(!f.contains(AddressFlags::MULTICAST));
+    }
+
+    #[test]
+    fn test_address_flags_remove_state() {
+        let mut f = AddressFlags::MULTICAST | AddressFlags::TEMPORARY;
+        f.remove(AddressFlags::MULTICAST);
+        assert_eq!(f, AddressFlags::TEMPORARY);
+        assert!(!f.contains(AddressFlags::MULTICAST));
+    }
+
+    #[test]
+    fn test_address_flags_stable() {
+        assert!(AddressFlags::empty().is_stable());
+        assert!(AddressFlags::MULTICAST.is_stable());
+        for f in [
+            AddressFlags::TEMPORARY,
+            AddressFlags::TENTATIVE,
+            AddressFlags::DEPRECATED,
+        ] {
+            assert!(!f.is_stable());
+            assert!(!(f | AddressFlags::MULTICAST).is_stable());
+        }
+    }
+
     #[test]
     #[cfg(feature = "std")]
     fn test_flags_debug() {
@@ -1,12 +1,19 @@
-use crate::network_event::{Flags, InterfaceIndex, NetworkEvent};
+use crate::getifaddrs::poll_interfaces;
+use crate::network_event::{
+    AddressFlags, AddressOrigin, Flags, InterfaceIndex, NetworkEvent,
+    OperState,
+};
 use async_stream::stream;
+use futures_util::future::Either;
 use futures_util::stream;
 use futures_util::stream::Stream;
+use futures_util::StreamExt;
 use neli::{
     consts::{
         nl::{NlmF, NlmFFlags},
         rtnl::{
-            Arphrd, Ifa, IfaFFlags, Iff, IffFlags, Ifla, RtAddrFamily, Rtm,
+            Arphrd, Ifa, IfaF, IfaFFlags, Iff, IffFlags, Ifla, RtAddrFamily,
+            Rtm,
         },
         socket::NlFamily,
     },
@@ -20,12 +27,49 @@ use neli::{
     socket::NlSocketHandle,
     types::NlBuffer,
     types::RtBuffer,
+    FromBytesWithInput,
 };
 use std::{
+    collections::HashMap,
+    io::Cursor,
     io::Error,
     io::ErrorKind,
     net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    os::fd::AsRawFd,
+    pin::Pin,
+    task::Poll,
+    time::Duration,
 };
+use tokio::io::{AsyncRead, ReadBuf};
+
+/// Length of `struct nlmsghdr`, see netlink(7)
+const NLMSG_HDRLEN: usize = 16;
+
+/// Length of `struct rtattr`, see rtnetlink(7)
+const RTA_HDRLEN: usize = 4;
+
+/// Length of `struct ifinfomsg`, the fixed part of a link message
+const IFINFOMSG_LEN: usize = 16;
+
+/// Length of `struct ifaddrmsg`, the fixed part of an address message
+const IFADDRMSG_LEN: usize = 8;
+
+/// The netlink message type carrying an error (or acknowledgement)
+const NLMSG_ERROR: u16 = 2;
+
+/// Message types below this are netlink control messages
+const NLMSG_MIN_TYPE: u16 = 0x10;
+
+/// Longest interface name, including its terminating NUL (`IFNAMSIZ`)
+const IFNAMSIZ: usize = 16;
+
+/// Socket option for strict checking of requests (Linux 4.20 and later)
+///
+/// Not in all versions of the libc crate, so defined here.
+const NETLINK_GET_STRICT_CHK: libc::c_int = 12;
+
+/// Largest netlink datagram read at once (as neli's `MAX_NL_LENGTH`)
+const MAX_NL_LENGTH: usize = 32768;
 
 fn ip(ip_bytes: &[u8]) -> Option<IpAddr> {
     match ip_bytes.len() {
@@ -47,6 +91,175 @@ fn ip(ip_bytes: &[u8]) -> Option<IpAddr> {
     }
 }
 
+fn invalid_message() -> Error {
+    Error::new(ErrorKind::InvalidData, "malformed netlink message")
+}
+
+/// Round a netlink length up to the 4-byte alignment
+const fn align(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+/// Check that the lengths in a buffer of netlink messages are consistent
+///
+/// The messages are later parsed by neli, which trusts the length
+/// fields (of messages, and of the attributes within them) and can
+/// panic if they're inconsistent. The kernel shouldn't ever send
+/// messages like that, but making sure is cheap. `fixed_len` is the
+/// size of the fixed part of each (non-control) message, which comes
+/// before its attributes.
+fn validate_messages(mut bytes: &[u8], fixed_len: usize) -> Result<(), Error> {
+    while !bytes.is_empty() {
+        let header = bytes.get(..NLMSG_HDRLEN).ok_or_else(invalid_message)?;
+        let len =
+            u32::from_ne_bytes([header[0], header[1], header[2], header[3]])
+                as usize;
+        let nl_type = u16::from_ne_bytes([header[4], header[5]]);
+        if len < NLMSG_HDRLEN || len > bytes.len() {
+            return Err(invalid_message());
+        }
+        let payload = &bytes[NLMSG_HDRLEN..len];
+        if nl_type == NLMSG_ERROR {
+            // An error code, then the header of the failed request,
+            // then (perhaps) the failed request's payload
+            let echoed = payload
+                .get(4 + NLMSG_HDRLEN..)
+                .ok_or_else(invalid_message)?;
+            let code = i32::from_ne_bytes([
+                payload[0], payload[1], payload[2], payload[3],
+            ]);
+            if code == 0 {
+                // neli asserts that an ack echoes nothing further
+                if !echoed.is_empty() {
+                    return Err(invalid_message());
+                }
+            } else if !echoed.is_empty() {
+                validate_payload(echoed, fixed_len)?;
+            }
+        } else if nl_type >= NLMSG_MIN_TYPE {
+            validate_payload(payload, fixed_len)?;
+        }
+        bytes = bytes.get(align(len)..).unwrap_or(&[]);
+    }
+    Ok(())
+}
+
+/// Check the fixed part, and the attribute lengths, of a message payload
+fn validate_payload(payload: &[u8], fixed_len: usize) -> Result<(), Error> {
+    let mut attrs = payload.get(fixed_len..).ok_or_else(invalid_message)?;
+    while !attrs.is_empty() {
+        let header = attrs.get(..RTA_HDRLEN).ok_or_else(invalid_message)?;
+        let len = u16::from_ne_bytes([header[0], header[1]]) as usize;
+        if len < RTA_HDRLEN || len > attrs.len() {
+            return Err(invalid_message());
+        }
+        attrs = attrs.get(align(len)..).unwrap_or(&[]);
+    }
+    Ok(())
+}
+
+/// Validate, then parse, a buffer of netlink messages
+fn parse_messages<'a, P: FromBytesWithInput<'a, Input = usize>>(
+    bytes: &'a [u8],
+    fixed_len: usize,
+) -> Result<NlBuffer<Rtm, P>, Error> {
+    validate_messages(bytes, fixed_len)?;
+    NlBuffer::from_bytes_with_input(&mut Cursor::new(bytes), bytes.len())
+        .map_err(map_rx_error)
+}
+
+/// Translate a buffer of RTM_NEWLINK/RTM_DELLINK messages
+fn parse_link_messages(bytes: &[u8]) -> Result<Vec<NetworkEvent>, Error> {
+    let msgs = parse_messages::<Ifinfomsg>(bytes, IFINFOMSG_LEN)?;
+    Ok(msgs
+        .into_iter()
+        .filter_map(|msg| translate_link_message(&msg))
+        .collect())
+}
+
+/// Translate a buffer of RTM_NEWADDR/RTM_DELADDR messages
+fn parse_addr_messages(bytes: &[u8]) -> Result<Vec<NetworkEvent>, Error> {
+    let msgs = parse_messages::<Ifaddrmsg>(bytes, IFADDRMSG_LEN)?;
+    Ok(msgs
+        .into_iter()
+        .filter_map(|msg| translate_addr_message(&msg))
+        .collect())
+}
+
+/// Feed arbitrary bytes through both message parsers
+///
+/// Only for the fuzz target in `cotton-netif/fuzz`, which builds with
+/// `--cfg fuzzing`; any panic is a bug.
+#[cfg(fuzzing)]
+#[doc(hidden)]
+pub fn fuzz_parse_messages(bytes: &[u8]) {
+    _ = parse_link_messages(bytes);
+    _ = parse_addr_messages(bytes);
+}
+
+/// Read one datagram from a netlink socket, without parsing it
+async fn recv_raw(
+    ss: &mut NlSocket,
+    buffer: &mut [u8],
+) -> Result<usize, Error> {
+    futures_util::future::poll_fn(|cx| {
+        let mut read_buf = ReadBuf::new(buffer);
+        match Pin::new(&mut *ss).poll_read(cx, &mut read_buf) {
+            Poll::Ready(Ok(())) => Poll::Ready(Ok(read_buf.filled().len())),
+            Poll::Ready(Err(e)) => Poll::Ready(Err(e)),
+            Poll::Pending => Poll::Pending,
+        }
+    })
+    .await
+}
+
+/// An interface name from an `IFLA_IFNAME` attribute
+///
+/// The kernel sends a NUL-terminated string of at most `IFNAMSIZ`
+/// bytes; anything else (empty, overlong, or not UTF-8) is rejected.
+fn interface_name(payload: &[u8]) -> Option<String> {
+    let name = payload.split(|b| *b == 0).next()?;
+    if name.is_empty() || name.len() >= IFNAMSIZ {
+        return None;
+    }
+    String::from_utf8(name.to_vec()).ok()
+}
+
+/// An interface index from a message, which must be positive
+fn interface_index(index: libc::c_int) -> Option<InterfaceIndex> {
+    u32::try_from(index)
+        .ok()
+        .and_then(core::num::NonZeroU32::new)
+        .map(InterfaceIndex)
+}
+
+/// Is a prefix length possible for this address?
+fn prefix_fits(addr: &IpAddr, prefix: u8) -> bool {
+    match addr {
+        IpAddr::V4(_) => prefix <= 32,
+        IpAddr::V6(_) => prefix <= 128,
+    }
+}
+
+/// Ask the kernel to check our requests strictly
+///
+/// Only available on Linux 4.20 and later; on older kernels, the
+/// option is silently left off.
+fn enable_strict_checking(s: &NlSocketHandle) {
+    let one: libc::c_int = 1;
+    // SAFETY: the option value is a valid c_int, and its size is
+    // passed correctly; the fd remains owned by `s`
+    let _ = unsafe {
+        libc::setsockopt(
+            s.as_raw_fd(),
+            libc::SOL_NETLINK,
+            NETLINK_GET_STRICT_CHK,
+            core::ptr::addr_of!(one).cast(),
+            core::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+}
+
 fn map_rx_error(err: DeError) -> Error {
     if let DeError::Wrapped(WrappedError::IOError(io_error)) = err {
         io_error
@@ -80,7 +293,22 @@ fn map_flags(flags: &IffFlags) -> Flags {
     newflags
 }
 
-#[allow(clippy::cast_sign_loss)]
+/// An operational status from an `IFLA_OPERSTATE` attribute
+///
+/// The values are the kernel's `IF_OPER_*` constants, in RFC 2863
+/// order; any the kernel adds later are `Unknown`.
+fn oper_state(value: u8) -> OperState {
+    match value {
+        1 => OperState::NotPresent,
+        2 => OperState::Down,
+        3 => OperState::LowerLayerDown,
+        4 => OperState::Testing,
+        5 => OperState::Dormant,
+        6 => OperState::Up,
+        _ => OperState::Unknown,
+    }
+}
+
 fn translate_link_message(
     msg: &Nlmsghdr<Rtm, Ifinfomsg>,
 ) -> Option<NetworkEvent> {
@@ -89,23 +317,25 @@ fn translate_link_message(
             Rtm::Newlink => {
                 let handle = p.rtattrs.get_attr_handle();
                 let name = handle
-                    .get_attr_payload_as_with_len::<String>(Ifla::Ifname)
-                    .ok();
+                    .get_attr_payload_as_with_len::<&[u8]>(Ifla::Ifname)
+                    .ok()
+                    .and_then(interface_name);
                 if let Some(name) = name {
                     let newflags = map_flags(&p.ifi_flags);
-                    return core::num::NonZeroU32::new(p.ifi_index as u32)
-                        .map(|ix| {
-                            NetworkEvent::NewLink(
-                                InterfaceIndex(ix),
-                                name,
-                                newflags,
-                            )
-                        });
+                    let oper = handle
+                        .get_attr_payload_as::<u8>(Ifla::Operstate)
+                        .map_or_else(
+                            |_| OperState::from_flags(newflags),
+                            oper_state,
+                        );
+                    return interface_index(p.ifi_index).map(|ix| {
+                        NetworkEvent::NewLink(ix, name, newflags, oper)
+                    });
                 }
             }
             Rtm::Dellink => {
-                return core::num::NonZeroU32::new(p.ifi_index as u32)
-                    .map(|ix| NetworkEvent::DelLink(InterfaceIndex(ix)));
+                return interface_index(p.ifi_index)
+                    .map(NetworkEvent::DelLink);
             }
             _ => (),
         }
@@ -113,7 +343,57 @@ fn translate_link_message(
     None
 }
 
-#[allow(clippy::cast_sign_loss)]
+/// The address's lifecycle state, from the kernel's IFA flags
+fn map_addr_state(flags: &IfaFFlags) -> AddressFlags {
+    let mut newflags = AddressFlags::default();
+    for (ifa, newf) in [
+        (&IfaF::Temporary, AddressFlags::TEMPORARY),
+        (&IfaF::Tentative, AddressFlags::TENTATIVE),
+        (&IfaF::Deprecated, AddressFlags::DEPRECATED),
+    ] {
+        if flags.contains(ifa) {
+            newflags |= newf;
+        }
+    }
+    newflags
+}
+
+/// Per-address hints, from the kernel's IFA flags
+///
+/// These can only be address-level hints: whether the *interface* is
+/// suitable for multicast is taken into account later, in
+/// `add_link_hints()`.
+fn map_addr_flags(
+    flags: &IfaFFlags,
+    addr: &IpAddr,
+    local: Option<IpAddr>,
+) -> AddressFlags {
+    let mut newflags = map_addr_state(flags);
+
+    // On point-to-point links, IFA_ADDRESS is the *peer's* address
+    // and IFA_LOCAL is ours; on all other links they're the same (or
+    // IFA_LOCAL is absent).
+    let peer = local.is_some_and(|l| l != *addr);
+    if !peer
+        && !flags.contains(&IfaF::Tentative)
+        && !flags.contains(&IfaF::Dadfailed)
+    {
+        newflags |= AddressFlags::MULTICAST;
+    }
+    newflags
+}
+
+/// How the address was configured, from the kernel's IFA flags
+fn address_origin(flags: &IfaFFlags, addr: &IpAddr) -> AddressOrigin {
+    match AddressOrigin::from_address(addr) {
+        AddressOrigin::Unknown if flags.contains(&IfaF::Permanent) => {
+            AddressOrigin::Static
+        }
+        AddressOrigin::Unknown => AddressOrigin::Dynamic,
+        origin => origin,
+    }
+}
+
 fn translate_addr_message(
     msg: &Nlmsghdr<Rtm, Ifaddrmsg>,
 ) -> Option<NetworkEvent> {
@@ -124,26 +404,31 @@ fn translate_addr_message(
             .ok()
             .and_then(ip)
         {
+            if !prefix_fits(&addr, p.ifa_prefixlen) {
+                return None;
+            }
             match msg.nl_type {
                 Rtm::Newaddr => {
-                    return core::num::NonZeroU32::new(p.ifa_index as u32)
-                        .map(|ix| {
-                            NetworkEvent::NewAddr(
-                                InterfaceIndex(ix),
-                                addr,
-                                p.ifa_prefixlen,
-                            )
-                        });
+                    let local = handle
+                        .get_attr_payload_as_with_len::<&[u8]>(Ifa::Local)
+                        .ok()
+                        .and_then(ip);
+                    let flags = map_addr_flags(&p.ifa_flags, &addr, local);
+                    let origin = address_origin(&p.ifa_flags, &addr);
+                    return interface_index(p.ifa_index).map(|ix| {
+                        NetworkEvent::NewAddr(
+                            ix,
+                            addr,
+                            p.ifa_prefixlen,
+                            flags,
+                            origin,
+                        )
+                    });
                 }
                 Rtm::Deladdr => {
-                    return core::num::NonZeroU32::new(p.ifa_index as u32)
-                        .map(|ix| {
-                            NetworkEvent::DelAddr(
-                                InterfaceIndex(ix),
-                                addr,
-                                p.ifa_prefixlen,
-                            )
-                        });
+                    return interface_index(p.ifa_index).map(|ix| {
+                        NetworkEvent::DelAddr(ix, addr, p.ifa_prefixlen)
+                    });
                 }
                 _ => (),
             }
@@ -155,19 +440,18 @@ fn translate_addr_message(
 fn get_links(
     mut ss: NlSocket,
 ) -> impl Stream<Item = Result<NetworkEvent, Error>> {
-    let mut buffer = Vec::new();
+    let mut buffer = vec![0u8; MAX_NL_LENGTH];
     stream! {
         loop {
-            let res: Result<NlBuffer<Rtm, Ifinfomsg>, DeError> =
-                ss.recv(&mut buffer).await;
+            let res = recv_raw(&mut ss, &mut buffer)
+                .await
+                .and_then(|n| parse_link_messages(&buffer[..n]));
             match res {
-                Ok(msgs) =>
-                    for msg in msgs {
-                        if let Some(event) = translate_link_message(&msg) {
-                            yield Ok(event);
-                        }
+                Ok(events) =>
+                    for event in events {
+                        yield Ok(event);
                     },
-                Err(e) => yield Err(map_rx_error(e))
+                Err(e) => yield Err(e)
             }
         }
     }
@@ -176,19 +460,18 @@ fn get_links(
 fn get_addrs(
     mut ss: NlSocket,
 ) -> impl Stream<Item = Result<NetworkEvent, Error>> {
-    let mut buffer = Vec::new();
+    let mut buffer = vec![0u8; MAX_NL_LENGTH];
     stream! {
         loop {
-            let res: Result<NlBuffer<Rtm, Ifaddrmsg>, DeError> =
-                ss.recv(&mut buffer).await;
+            let res = recv_raw(&mut ss, &mut buffer)
+                .await
+                .and_then(|n| parse_addr_messages(&buffer[..n]));
             match res {
-                Ok(msgs) =>
-                    for msg in msgs {
-                        if let Some(event) = translate_addr_message(&msg) {
-                            yield Ok(event);
-                        }
+                Ok(events) =>
+                    for event in events {
+                        yield Ok(event);
                     },
-                Err(e) => yield Err(map_rx_error(e))
+                Err(e) => yield Err(e)
             }
         }
     }
@@ -248,7 +531,7 @@ let mut s = get_interfaces_async()?;
 
 while let Some(e) = s.next().await {
     match e {
-        Ok(NetworkEvent::NewLink(_i, name, flags)) => {
+        Ok(NetworkEvent::NewLink(_i, name, flags, _)) => {
             if flags.contains(Flags::RUNNING | Flags::UP | Flags::MULTICAST) {
                 println!("New multicast-capable interface: {}", name);
             }
@@ -262,12 +545,47 @@ while let Some(e) = s.next().await {
 # Ok::<(), std::io::Error>(())
 ```
 
+# Fallback
+
+Where netlink sockets can't be opened -- for instance, in some
+containers, or under a restrictive seccomp policy -- the stream
+instead comes from polling `getifaddrs()` every
+[`DEFAULT_POLL_INTERVAL`], as in [`poll_interfaces`]. Use
+[`get_interfaces_async_with_poll_interval`] to choose a different
+interval, or [`probe_capabilities`](crate::probe_capabilities) to find
+out in advance which will happen.
+
 # Errors
 
-Returns Err if the underlying netlink socket failed to open, see netlink(7).
+Returns Err if neither the underlying netlink socket (see netlink(7))
+nor `getifaddrs()` (see getifaddrs(3)) works; the error is the one
+from netlink.
 
  */
 pub fn get_interfaces_async(
+) -> Result<impl Stream<Item = Result<NetworkEvent, Error>>, Error> {
+    get_interfaces_async_with_poll_interval(DEFAULT_POLL_INTERVAL)
+}
+
+/// How often the fallback in [`get_interfaces_async`] polls, if netlink
+/// is unavailable
+pub const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/** Like [`get_interfaces_async`], but specifying how often to poll if
+netlink is unavailable
+
+The interval has no effect if netlink is available: change
+notifications then arrive as they happen.
+
+# Errors
+
+Returns Err if neither the underlying netlink socket (see netlink(7))
+nor `getifaddrs()` (see getifaddrs(3)) works; the error is the one
+from netlink.
+
+ */
+pub fn get_interfaces_async_with_poll_interval(
+    interval: Duration,
 ) -> Result<impl Stream<Item = Result<NetworkEvent, Error>>, Error> {
     /* Pass through to an inner function for testability. Hopefully
      * the compiler notices that in cfg(not test) builds, this is the
@@ -276,12 +594,57 @@ pub fn get_interfaces_async(
      * resolved and inlined, and users will have paid no performance
      * cost for the testability.
      */
-    get_interfaces_async_inner(
-        NlSocketHandle::connect,
-        link_sender,
-        addr_sender,
-        NlSocket::new::<NlSocketHandle>,
-    )
+    let s = with_fallback(
+        get_interfaces_async_inner(
+            NlSocketHandle::connect,
+            link_sender,
+            addr_sender,
+            NlSocket::new::<NlSocketHandle>,
+        ),
+        || poll_interfaces(interval),
+    )?;
+
+    // Keep InterfaceIndex::name() in step with the events
+    Ok(s.inspect(|r| {
+        if let Ok(event) = r {
+            crate::names::observe(event);
+        }
+    }))
+}
+
+/// Use the netlink stream if there is one, otherwise the fallback
+fn with_fallback<S, P, F>(
+    netlink: Result<S, Error>,
+    fallback: F,
+) -> Result<Either<S, P>, Error>
+where
+    S: Stream<Item = Result<NetworkEvent, Error>>,
+    P: Stream<Item = Result<NetworkEvent, Error>>,
+    F: FnOnce() -> Result<P, Error>,
+{
+    match netlink {
+        Ok(s) => Ok(Either::Left(s)),
+        Err(e) => fallback().map(Either::Right).map_err(|_| e),
+    }
+}
+
+/// Check whether netlink can provide change notifications
+///
+/// This opens and requests dumps from the same sockets as
+/// [`get_interfaces_async`] does, but needs no async runtime.
+pub(crate) fn probe() -> Result<(), Error> {
+    probe_inner(NlSocketHandle::connect, link_sender, addr_sender)
+}
+
+fn probe_inner(
+    handle_fn: HandleFn,
+    send_link_fn: SendLinkMessageFn,
+    send_addr_fn: SendAddrMessageFn,
+) -> Result<(), Error> {
+    open_link_handle(handle_fn, send_link_fn)?;
+    open_ipv4addr_handle(handle_fn, send_addr_fn)?;
+    open_ipv6addr_handle(handle_fn, send_addr_fn)?;
+    Ok(())
 }
 
 /// The type of `NlSocketHandle::connect`
@@ -331,10 +694,19 @@ fn create_link_socket(
     send_link_fn: SendLinkMessageFn,
     socket_fn: SocketFn,
 ) -> Result<NlSocket, Error> {
+    socket_fn(open_link_handle(handle_fn, send_link_fn)?)
+}
+
+fn open_link_handle(
+    handle_fn: HandleFn,
+    send_link_fn: SendLinkMessageFn,
+) -> Result<NlSocketHandle, Error> {
     let mut s = handle_fn(NlFamily::Route, None, &[1])?; // =RTNLGRP_LINK
+    enable_strict_checking(&s);
+    // Strict checking insists that the type (like the flags) is zero
     let ifinfomsg = Ifinfomsg::new(
         RtAddrFamily::Unspecified,
-        Arphrd::Ether,
+        Arphrd::Netrom, // =0
         0,
         IffFlags::empty(),
         IffFlags::empty(),
@@ -349,7 +721,7 @@ fn create_link_socket(
         NlPayload::Payload(ifinfomsg),
     );
     send_link_fn(&mut s, nl_link_header).map_err(map_tx_error)?;
-    socket_fn(s)
+    Ok(s)
 }
 
 fn create_ipv4addr_socket(
@@ -357,7 +729,15 @@ fn create_ipv4addr_socket(
     send_addr_fn: SendAddrMessageFn,
     socket_fn: SocketFn,
 ) -> Result<NlSocket, Error> {
+    socket_fn(open_ipv4addr_handle(handle_fn, send_addr_fn)?)
+}
+
+fn open_ipv4addr_handle(
+    handle_fn: HandleFn,
+    send_addr_fn: SendAddrMessageFn,
+) -> Result<NlSocketHandle, Error> {
     let mut s = handle_fn(NlFamily::Route, None, &[5])?; // =RTNLGRP_IPV4_IFADDR
+    enable_strict_checking(&s);
     let ifaddrmsg = Ifaddrmsg {
         ifa_family: RtAddrFamily::Inet,
         ifa_prefixlen: 0,
@@ -375,7 +755,7 @@ fn create_ipv4addr_socket(
         NlPayload::Payload(ifaddrmsg),
     );
     send_addr_fn(&mut s, nl_addr4_header).map_err(map_tx_error)?;
-    socket_fn(s)
+    Ok(s)
 }
 
 fn create_ipv6addr_socket(
@@ -383,7 +763,15 @@ fn create_ipv6addr_socket(
     send_addr_fn: SendAddrMessageFn,
     socket_fn: SocketFn,
 ) -> Result<NlSocket, Error> {
+    socket_fn(open_ipv6addr_handle(handle_fn, send_addr_fn)?)
+}
+
+fn open_ipv6addr_handle(
+    handle_fn: HandleFn,
+    send_addr_fn: SendAddrMessageFn,
+) -> Result<NlSocketHandle, Error> {
     let mut s = handle_fn(NlFamily::Route, None, &[9])?; // =RTNLGRP_IPV6_IFADDR
+    enable_strict_checking(&s);
     let ifaddrmsg = Ifaddrmsg {
         ifa_family: RtAddrFamily::Inet6,
         ifa_prefixlen: 0,
@@ -401,7 +789,37 @@ fn create_ipv6addr_socket(
         NlPayload::Payload(ifaddrmsg),
     );
     send_addr_fn(&mut s, nl_addr6_header).map_err(map_tx_error)?;
-    socket_fn(s)
+    Ok(s)
+}
+
+/// Clear `AddressFlags::MULTICAST` on addresses whose interface isn't
+/// suitable for multicast
+///
+/// Addresses on interfaces not (yet) seen are passed through unchanged.
+fn add_link_hints<S: Stream<Item = Result<NetworkEvent, Error>>>(
+    s: S,
+) -> impl Stream<Item = Result<NetworkEvent, Error>> {
+    let mut links = HashMap::new();
+    s.map(move |event| {
+        match event {
+            Ok(NetworkEvent::NewLink(ix, _, flags, _)) => {
+                links.insert(ix, flags.multicast_suitable());
+            }
+            Ok(NetworkEvent::DelLink(ix)) => {
+                links.remove(&ix);
+            }
+            Ok(NetworkEvent::NewAddr(ix, addr, prefix, mut flags, origin)) => {
+                if links.get(&ix) == Some(&false) {
+                    flags.remove(AddressFlags::MULTICAST);
+                }
+                return Ok(NetworkEvent::NewAddr(
+                    ix, addr, prefix, flags, origin,
+                ));
+            }
+            _ => (),
+        }
+        event
+    })
 }
 
 fn get_interfaces_async_inner2(
@@ -409,13 +827,13 @@ fn get_interfaces_async_inner2(
     addr4_socket: NlSocket,
     addr6_socket: NlSocket,
 ) -> impl Stream<Item = Result<NetworkEvent, Error>> {
-    stream::select(
+    add_link_hints(stream::select(
         Box::pin(get_links(link_socket)),
         stream::select(
             Box::pin(get_addrs(addr4_socket)),
             Box::pin(get_addrs(addr6_socket)),
         ),
-    )
+    ))
 }
 
 #[cfg(test)]
@@ -628,7 +1046,41 @@ mod tests {
             NetworkEvent::NewLink(
                 make_index(3),
                 "eth0".to_string(),
-                Flags::default()
+                Flags::default(),
+                OperState::Down
+            )
+        );
+    }
+
+    #[test]
+    fn test_link_message_no_carrier() {
+        let mut buf = RtBuffer::new();
+        buf.push(Rtattr::new(None, Ifla::Ifname, "eth0".to_string()).unwrap());
+        buf.push(Rtattr::new(None, Ifla::Operstate, 3u8).unwrap());
+
+        let msg = Nlmsghdr::new(
+            None,
+            Rtm::Newlink,
+            NlmFFlags::empty(),
+            None,
+            None,
+            NlPayload::Payload(Ifinfomsg::new(
+                RtAddrFamily::Inet,
+                Arphrd::Ether,
+                3,
+                IffFlags::new(&[Iff::Up, Iff::Multicast]),
+                IffFlags::empty(),
+                buf,
+            )),
+        );
+
+        assert_eq!(
+            translate_link_message(&msg).unwrap(),
+            NetworkEvent::NewLink(
+                make_index(3),
+                "eth0".to_string(),
+                Flags::UP | Flags::MULTICAST,
+                OperState::LowerLayerDown
             )
         );
     }
@@ -774,11 +1226,469 @@ mod tests {
             NetworkEvent::NewAddr(
                 make_index(2),
                 ip(&[255, 255, 0, 0]).unwrap(),
-                24
+                24,
+                AddressFlags::MULTICAST,
+                AddressOrigin::Dynamic
             )
         );
     }
 
+    fn link_bytes(index: libc::c_int, name: &[u8]) -> Vec<u8> {
+        let mut buf = RtBuffer::new();
+        buf.push(Rtattr::new(None, Ifla::Ifname, name.to_vec()).unwrap());
+        let msg = Nlmsghdr::new(
+            None,
+            Rtm::Newlink,
+            NlmFFlags::empty(),
+            None,
+            None,
+            NlPayload::Payload(Ifinfomsg::new(
+                RtAddrFamily::Inet,
+                Arphrd::Ether,
+                index,
+                IffFlags::empty(),
+                IffFlags::empty(),
+                buf,
+            )),
+        );
+        let mut v = std::io::Cursor::new(Vec::new());
+        msg.to_bytes(&mut v).unwrap();
+        v.into_inner()
+    }
+
+    #[test]
+    fn test_parse_link_messages() {
+        let mut bytes = link_bytes(3, b"eth0\0");
+        bytes.extend(link_bytes(4, b"eth1\0"));
+        let events = parse_link_messages(&bytes).unwrap();
+        assert_eq!(
+            events,
+            vec![
+                NetworkEvent::NewLink(
+                    make_index(3),
+                    "eth0".to_string(),
+                    Flags::default(),
+                    OperState::Down
+                ),
+                NetworkEvent::NewLink(
+                    make_index(4),
+                    "eth1".to_string(),
+                    Flags::default(),
+                    OperState::Down
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_link_messages_bad_names() {
+        for name in [&b""[..], b"\0", b"\xFF\xFE\0", b"abcdefghijklmnop\0"] {
+            let bytes = link_bytes(3, name);
+            assert!(parse_link_messages(&bytes).unwrap().is_empty());
+        }
+    }
+
+    #[test]
+    fn test_parse_link_messages_negative_index() {
+        let bytes = link_bytes(-1, b"eth0\0");
+        assert!(parse_link_messages(&bytes).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_validate_truncated_header() {
+        let bytes = link_bytes(3, b"eth0\0");
+        let e = parse_link_messages(&bytes[0..10]).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_validate_overlong_message() {
+        let mut bytes = link_bytes(3, b"eth0\0");
+        bytes[0] += 4;
+        let e = parse_link_messages(&bytes).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_validate_undersized_message() {
+        let mut bytes = link_bytes(3, b"eth0\0");
+        bytes[0..4].copy_from_slice(&4u32.to_ne_bytes());
+        let e = parse_link_messages(&bytes).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_validate_truncated_payload() {
+        let mut bytes = link_bytes(3, b"eth0\0");
+        bytes.truncate(NLMSG_HDRLEN + 8);
+        let len = bytes.len() as u32;
+        bytes[0..4].copy_from_slice(&len.to_ne_bytes());
+        let e = parse_link_messages(&bytes).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_validate_bad_attribute_lengths() {
+        let rta = NLMSG_HDRLEN + IFINFOMSG_LEN;
+        for len in [0u16, 3, 100] {
+            let mut bytes = link_bytes(3, b"eth0\0");
+            bytes[rta..rta + 2].copy_from_slice(&len.to_ne_bytes());
+            let e = parse_link_messages(&bytes).unwrap_err();
+            assert_eq!(e.kind(), ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn test_validate_short_error_message() {
+        let mut bytes = vec![0u8; NLMSG_HDRLEN + 4];
+        let len = bytes.len() as u32;
+        bytes[0..4].copy_from_slice(&len.to_ne_bytes());
+        bytes[4..6].copy_from_slice(&NLMSG_ERROR.to_ne_bytes());
+        let e = parse_addr_messages(&bytes).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_validate_ack_with_payload() {
+        // Found by the fuzzer: an ack (error code zero) followed by
+        // more than the echoed header
+        let mut bytes = vec![0u8; NLMSG_HDRLEN + 4 + NLMSG_HDRLEN + 8];
+        let len = bytes.len() as u32;
+        bytes[0..4].copy_from_slice(&len.to_ne_bytes());
+        bytes[4..6].copy_from_slice(&NLMSG_ERROR.to_ne_bytes());
+        let e = parse_addr_messages(&bytes).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidData);
+
+        // ...whereas a plain ack is fine
+        let mut bytes = vec![0u8; NLMSG_HDRLEN + 4 + NLMSG_HDRLEN];
+        let len = bytes.len() as u32;
+        bytes[0..4].copy_from_slice(&len.to_ne_bytes());
+        bytes[4..6].copy_from_slice(&NLMSG_ERROR.to_ne_bytes());
+        assert!(parse_addr_messages(&bytes).is_ok());
+    }
+
+    #[test]
+    fn test_validate_control_message() {
+        // NLMSG_DONE, with its (ignored) payload
+        let mut bytes = vec![0u8; NLMSG_HDRLEN + 4];
+        let len = bytes.len() as u32;
+        bytes[0..4].copy_from_slice(&len.to_ne_bytes());
+        bytes[4..6].copy_from_slice(&3u16.to_ne_bytes());
+        assert!(validate_messages(&bytes, IFADDRMSG_LEN).is_ok());
+    }
+
+    #[test]
+    fn test_parse_arbitrary_bytes() {
+        // A quick smoke test, run with every "cargo test"; for proper
+        // coverage-guided fuzzing, see cotton-netif/fuzz.
+        //
+        // A fixed-seed xorshift, so that any failure is reproducible
+        let mut state = 0x2545_F491_4F6C_DD1Du64;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+        let template = link_bytes(3, b"eth0\0");
+        for _ in 0..20000 {
+            let mut bytes = if next() & 1 == 0 {
+                template.clone()
+            } else {
+                vec![0u8; (next() % 64) as usize]
+            };
+            for _ in 0..(next() % 8) {
+                if !bytes.is_empty() {
+                    let i = (next() as usize) % bytes.len();
+                    bytes[i] = next() as u8;
+                }
+            }
+            let _ = parse_link_messages(&bytes);
+            let _ = parse_addr_messages(&bytes);
+        }
+    }
+
+    #[test]
+    fn test_interface_name() {
+        assert_eq!(interface_name(b"eth0\0"), Some("eth0".to_string()));
+        assert_eq!(interface_name(b"eth0"), Some("eth0".to_string()));
+        assert_eq!(interface_name(b"lo\0junk"), Some("lo".to_string()));
+        assert_eq!(
+            interface_name(b"abcdefghijklmno\0"),
+            Some("abcdefghijklmno".to_string())
+        );
+        assert_eq!(interface_name(b"abcdefghijklmnop\0"), None);
+        assert_eq!(interface_name(b""), None);
+        assert_eq!(interface_name(b"\0"), None);
+        assert_eq!(interface_name(b"\xC0\0"), None);
+    }
+
+    #[test]
+    fn test_interface_index() {
+        assert_eq!(interface_index(7), Some(make_index(7)));
+        assert_eq!(interface_index(0), None);
+        assert_eq!(interface_index(-7), None);
+    }
+
+    #[test]
+    fn test_addr_message_bad_prefix() {
+        let mut buf = RtBuffer::new();
+        buf.push(
+            Rtattr::new(None, Ifa::Address, 0xFFFF_0000u32.to_be()).unwrap(),
+        );
+
+        let msg = Nlmsghdr::new(
+            None,
+            Rtm::Newaddr,
+            NlmFFlags::empty(),
+            None,
+            None,
+            NlPayload::Payload(Ifaddrmsg {
+                ifa_family: RtAddrFamily::Inet,
+                ifa_prefixlen: 33,
+                ifa_flags: IfaFFlags::empty(),
+                ifa_scope: 0,
+                ifa_index: 2,
+                rtattrs: buf,
+            }),
+        );
+
+        assert!(translate_addr_message(&msg).is_none());
+    }
+
+    #[test]
+    fn test_prefix_fits() {
+        let v4 = ip(&[10, 0, 0, 1]).unwrap();
+        let v6 = ip(&[0u8; 16]).unwrap();
+        assert!(prefix_fits(&v4, 32));
+        assert!(!prefix_fits(&v4, 33));
+        assert!(prefix_fits(&v6, 128));
+        assert!(!prefix_fits(&v6, 129));
+    }
+
+    #[test]
+    fn test_oper_state() {
+        assert_eq!(oper_state(0), OperState::Unknown);
+        assert_eq!(oper_state(1), OperState::NotPresent);
+        assert_eq!(oper_state(2), OperState::Down);
+        assert_eq!(oper_state(3), OperState::LowerLayerDown);
+        assert_eq!(oper_state(4), OperState::Testing);
+        assert_eq!(oper_state(5), OperState::Dormant);
+        assert_eq!(oper_state(6), OperState::Up);
+        assert_eq!(oper_state(7), OperState::Unknown);
+
+        assert!(OperState::Up.has_carrier());
+        assert!(OperState::Unknown.has_carrier());
+        assert!(!OperState::LowerLayerDown.has_carrier());
+        assert!(!OperState::Dormant.has_carrier());
+    }
+
+    #[test]
+    fn test_address_origin() {
+        let addr = ip(&[192, 168, 1, 2]).unwrap();
+        let permanent = IfaFFlags::new(&[IfaF::Permanent]);
+        assert_eq!(address_origin(&permanent, &addr), AddressOrigin::Static);
+        assert_eq!(
+            address_origin(&IfaFFlags::empty(), &addr),
+            AddressOrigin::Dynamic
+        );
+
+        let addr = ip(&[169, 254, 3, 4]).unwrap();
+        assert_eq!(
+            address_origin(&IfaFFlags::empty(), &addr),
+            AddressOrigin::LinkLocal
+        );
+        assert_eq!(
+            address_origin(&permanent, &addr),
+            AddressOrigin::LinkLocal
+        );
+
+        let mut v6 = [0u8; 16];
+        v6[0] = 0xFE;
+        v6[1] = 0x80;
+        v6[15] = 1;
+        let addr = ip(&v6).unwrap();
+        assert_eq!(
+            address_origin(&permanent, &addr),
+            AddressOrigin::LinkLocal
+        );
+        v6[0] = 0x20;
+        v6[1] = 0x01;
+        let addr = ip(&v6).unwrap();
+        assert_eq!(address_origin(&permanent, &addr), AddressOrigin::Static);
+    }
+
+    #[test]
+    fn test_addr_flags_tentative() {
+        let addr = ip(&[10, 0, 0, 1]).unwrap();
+        let flags = IfaFFlags::new(&[IfaF::Tentative]);
+        assert_eq!(
+            map_addr_flags(&flags, &addr, Some(addr)),
+            AddressFlags::TENTATIVE
+        );
+    }
+
+    #[test]
+    fn test_addr_flags_temporary_deprecated() {
+        let addr = ip(&[10, 0, 0, 1]).unwrap();
+        let flags = IfaFFlags::new(&[IfaF::Temporary, IfaF::Deprecated]);
+        assert_eq!(
+            map_addr_flags(&flags, &addr, None),
+            AddressFlags::MULTICAST
+                | AddressFlags::TEMPORARY
+                | AddressFlags::DEPRECATED
+        );
+    }
+
+    #[test]
+    fn test_addr_state() {
+        assert_eq!(map_addr_state(&IfaFFlags::empty()), AddressFlags::empty());
+        assert_eq!(
+            map_addr_state(&IfaFFlags::new(&[IfaF::Permanent])),
+            AddressFlags::empty()
+        );
+        assert_eq!(
+            map_addr_state(&IfaFFlags::new(&[IfaF::Dadfailed])),
+            AddressFlags::empty()
+        );
+    }
+
+    #[test]
+    fn test_addr_flags_multicast() {
+        let addr = ip(&[10, 0, 0, 1]).unwrap();
+        assert_eq!(
+            map_addr_flags(&IfaFFlags::empty(), &addr, Some(addr)),
+            AddressFlags::MULTICAST
+        );
+    }
+
+    #[test]
+    fn test_addr_flags_dad_failed() {
+        let addr = ip(&[10, 0, 0, 1]).unwrap();
+        let flags = IfaFFlags::new(&[IfaF::Dadfailed]);
+        assert_eq!(map_addr_flags(&flags, &addr, None), AddressFlags::empty());
+    }
+
+    #[test]
+    fn test_addr_flags_peer() {
+        let peer = ip(&[10, 0, 0, 1]).unwrap();
+        let local = ip(&[10, 0, 0, 2]).unwrap();
+        assert_eq!(
+            map_addr_flags(&IfaFFlags::empty(), &peer, Some(local)),
+            AddressFlags::empty()
+        );
+    }
+
+    #[test]
+    fn test_addr_message_p2p() {
+        let mut buf = RtBuffer::new();
+        buf.push(
+            Rtattr::new(None, Ifa::Address, 0x0A00_0001u32.to_be()).unwrap(),
+        );
+        buf.push(
+            Rtattr::new(None, Ifa::Local, 0x0A00_0002u32.to_be()).unwrap(),
+        );
+
+        let msg = Nlmsghdr::new(
+            None,
+            Rtm::Newaddr,
+            NlmFFlags::empty(),
+            None,
+            None,
+            NlPayload::Payload(Ifaddrmsg {
+                ifa_family: RtAddrFamily::Inet,
+                ifa_prefixlen: 32,
+                ifa_flags: IfaFFlags::empty(),
+                ifa_scope: 0,
+                ifa_index: 2,
+                rtattrs: buf,
+            }),
+        );
+
+        let Some(NetworkEvent::NewAddr(_, _, _, flags, _)) =
+            translate_addr_message(&msg)
+        else {
+            panic!("expected NewAddr");
+        };
+        assert!(!flags.contains(AddressFlags::MULTICAST));
+    }
+
+    fn link_hint_events(link_flags: Flags) -> Vec<NetworkEvent> {
+        let events = vec![
+            Ok(NetworkEvent::NewAddr(
+                make_index(3),
+                ip(&[10, 0, 0, 3]).unwrap(),
+                24,
+                AddressFlags::MULTICAST,
+                AddressOrigin::Static,
+            )),
+            Ok(NetworkEvent::NewLink(
+                make_index(2),
+                "wg0".to_string(),
+                link_flags,
+                OperState::from_flags(link_flags),
+            )),
+            Ok(NetworkEvent::NewAddr(
+                make_index(2),
+                ip(&[10, 0, 0, 2]).unwrap(),
+                24,
+                AddressFlags::MULTICAST,
+                AddressOrigin::Static,
+            )),
+            Ok(NetworkEvent::DelLink(make_index(2))),
+            Ok(NetworkEvent::NewAddr(
+                make_index(2),
+                ip(&[10, 0, 0, 2]).unwrap(),
+                24,
+                AddressFlags::MULTICAST,
+                AddressOrigin::Static,
+            )),
+        ];
+        tokio_test::block_on(
+            add_link_hints(stream::iter(events))
+                .map(Result::unwrap)
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    fn address_flags(events: &[NetworkEvent]) -> Vec<AddressFlags> {
+        events
+            .iter()
+            .filter_map(|e| match e {
+                NetworkEvent::NewAddr(_, _, _, f, _) => Some(*f),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn link_hints_p2p() {
+        let events = link_hint_events(
+            Flags::UP | Flags::MULTICAST | Flags::POINTTOPOINT,
+        );
+        assert_eq!(events.len(), 5);
+        assert_eq!(
+            address_flags(&events),
+            vec![
+                AddressFlags::MULTICAST, // unknown link
+                AddressFlags::empty(),
+                AddressFlags::MULTICAST, // link has gone
+            ]
+        );
+    }
+
+    #[test]
+    fn link_hints_no_multicast() {
+        let events = link_hint_events(Flags::UP);
+        assert_eq!(address_flags(&events)[1], AddressFlags::empty());
+    }
+
+    #[test]
+    fn link_hints_multicast() {
+        let events = link_hint_events(Flags::UP | Flags::MULTICAST);
+        assert_eq!(address_flags(&events), vec![AddressFlags::MULTICAST; 3]);
+    }
+
     #[test]
     fn test_addr_message_del() {
         let mut buf = RtBuffer::new();
@@ -1323,6 +2233,67 @@ mod tests {
         assert!(s.is_err());
     }
 
+    type EventStream = stream::Empty<Result<NetworkEvent, Error>>;
+
+    #[test]
+    fn netlink_preferred_to_fallback() {
+        let s = with_fallback::<EventStream, EventStream, _>(
+            Ok(stream::empty()),
+            || panic!("fallback not expected"),
+        );
+        assert!(matches!(s, Ok(Either::Left(_))));
+    }
+
+    #[test]
+    fn fallback_used_if_netlink_fails() {
+        let s = with_fallback::<EventStream, EventStream, _>(
+            Err(Error::from(ErrorKind::PermissionDenied)),
+            || Ok(stream::empty()),
+        );
+        assert!(matches!(s, Ok(Either::Right(_))));
+    }
+
+    #[test]
+    fn netlink_error_reported_if_fallback_fails() {
+        let s = with_fallback::<EventStream, EventStream, _>(
+            Err(Error::from(ErrorKind::PermissionDenied)),
+            || Err(Error::from(ErrorKind::Other)),
+        );
+        assert_eq!(s.err().unwrap().kind(), ErrorKind::PermissionDenied);
+    }
+
+    #[test]
+    fn probe_passes_on_handle_error() {
+        let r = probe_inner(failing_handle_fn, link_sender, addr_sender);
+        assert_eq!(r.err().unwrap().kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn probe_passes_on_addr_error() {
+        let r = probe_inner(
+            NlSocketHandle::connect,
+            link_sender,
+            failing_addr_sender,
+        );
+        assert!(r.is_err());
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn zzz_probe() {
+        assert!(probe().is_ok());
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn zzz_instantiate_with_poll_interval() {
+        assert!(get_interfaces_async_with_poll_interval(Duration::from_secs(
+            1
+        ))
+        .is_ok());
+    }
+
     #[tokio::test]
     #[cfg_attr(miri, ignore)]
     async fn zzz_instantiate() {
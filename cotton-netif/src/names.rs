@@ -0,0 +1,276 @@
+use crate::network_event::{InterfaceIndex, NetworkEvent};
+use std::collections::BTreeMap;
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+/// The type of `nix::net::if_::if_indextoname`
+type IndexToNameFn = fn(libc::c_uint) -> nix::Result<std::ffi::CString>;
+
+/// The type of `nix::net::if_::if_nametoindex`
+type NameToIndexFn = fn(&str) -> nix::Result<libc::c_uint>;
+
+/// Interface names, as last reported by an event source
+struct NameCache(RwLock<BTreeMap<InterfaceIndex, String>>);
+
+/// The cache behind [`InterfaceIndex::name`] and
+/// [`InterfaceIndex::from_name`]
+static NAMES: NameCache = NameCache::new();
+
+impl NameCache {
+    const fn new() -> Self {
+        Self(RwLock::new(BTreeMap::new()))
+    }
+
+    fn read(&self) -> RwLockReadGuard<'_, BTreeMap<InterfaceIndex, String>> {
+        self.0.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, BTreeMap<InterfaceIndex, String>> {
+        self.0.write().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn insert(&self, index: InterfaceIndex, name: &str) {
+        let mut names = self.write();
+        // A renamed interface keeps its index, but a name can move to
+        // a new index if its interface is deleted and recreated
+        names.retain(|i, n| *i == index || n != name);
+        names.insert(index, name.to_string());
+    }
+
+    #[cfg(any(test, all(target_os = "linux", feature = "async")))]
+    fn observe(&self, event: &NetworkEvent) {
+        match event {
+            NetworkEvent::NewLink(ix, name, _, _) => self.insert(*ix, name),
+            NetworkEvent::DelLink(ix) => {
+                self.write().remove(ix);
+            }
+            _ => (),
+        }
+    }
+
+    fn observe_snapshot(&self, events: &[NetworkEvent]) {
+        let mut names = self.write();
+        names.clear();
+        for event in events {
+            if let NetworkEvent::NewLink(ix, name, _, _) = event {
+                names.insert(*ix, name.clone());
+            }
+        }
+    }
+
+    fn name(
+        &self,
+        index: InterfaceIndex,
+        indextoname: IndexToNameFn,
+    ) -> Option<String> {
+        if let Some(name) = self.read().get(&index) {
+            return Some(name.clone());
+        }
+        let name = indextoname(index.0.get()).ok()?.into_string().ok()?;
+        self.insert(index, &name);
+        Some(name)
+    }
+
+    fn index(
+        &self,
+        name: &str,
+        nametoindex: NameToIndexFn,
+    ) -> Option<InterfaceIndex> {
+        if let Some((ix, _)) = self.read().iter().find(|(_, n)| *n == name) {
+            return Some(*ix);
+        }
+        let index = InterfaceIndex(core::num::NonZeroU32::new(
+            nametoindex(name).ok()?,
+        )?);
+        self.insert(index, name);
+        Some(index)
+    }
+}
+
+/// Update the name cache from an event in a stream of changes
+#[cfg(all(target_os = "linux", feature = "async"))]
+pub(crate) fn observe(event: &NetworkEvent) {
+    NAMES.observe(event);
+}
+
+/// Replace the name cache with the links in a complete snapshot
+pub(crate) fn observe_snapshot(events: &[NetworkEvent]) {
+    NAMES.observe_snapshot(events);
+}
+
+impl InterfaceIndex {
+    /// The name of this interface (e.g. "eth0"), if it exists
+    ///
+    /// Answered from a cache kept up to date by
+    /// [`get_interfaces`](crate::get_interfaces) and by the streams
+    /// from `get_interfaces_async`, so that names agree with the
+    /// events the application has seen, without a system call each
+    /// time. Interfaces not (yet) in the cache are looked up with
+    /// if_indextoname(3), and the answer is remembered.
+    #[must_use]
+    pub fn name(&self) -> Option<String> {
+        NAMES.name(*self, nix::net::if_::if_indextoname)
+    }
+
+    /// The index of the interface called `name`, if there is one
+    ///
+    /// The inverse of [`InterfaceIndex::name`], using the same cache,
+    /// and falling back to if_nametoindex(3).
+    #[must_use]
+    pub fn from_name(name: &str) -> Option<InterfaceIndex> {
+        NAMES.index(name, nix::net::if_::if_nametoindex::<str>)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Flags, OperState};
+    use std::ffi::CString;
+
+    fn make_index(i: u32) -> InterfaceIndex {
+        InterfaceIndex(core::num::NonZeroU32::new(i).unwrap())
+    }
+
+    fn new_link(i: u32, name: &str) -> NetworkEvent {
+        NetworkEvent::NewLink(
+            make_index(i),
+            name.to_string(),
+            Flags::UP,
+            OperState::LowerLayerDown,
+        )
+    }
+
+    fn no_indextoname(_: libc::c_uint) -> nix::Result<CString> {
+        Err(nix::Error::ENXIO)
+    }
+
+    fn no_nametoindex(_: &str) -> nix::Result<libc::c_uint> {
+        Err(nix::Error::ENODEV)
+    }
+
+    fn fake_indextoname(i: libc::c_uint) -> nix::Result<CString> {
+        Ok(CString::new(format!("eth{}", i - 1)).unwrap())
+    }
+
+    fn fake_nametoindex(name: &str) -> nix::Result<libc::c_uint> {
+        match name.strip_prefix("eth").map(str::parse::<libc::c_uint>) {
+            Some(Ok(i)) => Ok(i + 1),
+            _ => Err(nix::Error::ENODEV),
+        }
+    }
+
+    fn name(c: &NameCache, i: u32) -> Option<String> {
+        c.name(make_index(i), no_indextoname)
+    }
+
+    fn index(c: &NameCache, name: &str) -> Option<u32> {
+        c.index(name, no_nametoindex).map(|ix| ix.0.get())
+    }
+
+    #[test]
+    fn empty_cache_knows_nothing() {
+        let c = NameCache::new();
+        assert_eq!(name(&c, 1), None);
+        assert_eq!(index(&c, "eth0"), None);
+    }
+
+    #[test]
+    fn new_link_is_cached() {
+        let c = NameCache::new();
+        c.observe(&new_link(2, "eth0"));
+        assert_eq!(name(&c, 2), Some("eth0".to_string()));
+        assert_eq!(index(&c, "eth0"), Some(2));
+    }
+
+    #[test]
+    fn del_link_is_forgotten() {
+        let c = NameCache::new();
+        c.observe(&new_link(2, "eth0"));
+        c.observe(&NetworkEvent::DelLink(make_index(2)));
+        assert_eq!(name(&c, 2), None);
+        assert_eq!(index(&c, "eth0"), None);
+    }
+
+    #[test]
+    fn rename_is_followed() {
+        let c = NameCache::new();
+        c.observe(&new_link(2, "eth0"));
+        c.observe(&new_link(2, "lan0"));
+        assert_eq!(name(&c, 2), Some("lan0".to_string()));
+        assert_eq!(index(&c, "eth0"), None);
+        assert_eq!(index(&c, "lan0"), Some(2));
+    }
+
+    #[test]
+    fn reused_name_moves() {
+        let c = NameCache::new();
+        c.observe(&new_link(2, "usb0"));
+        c.observe(&new_link(7, "usb0"));
+        assert_eq!(name(&c, 2), None);
+        assert_eq!(index(&c, "usb0"), Some(7));
+    }
+
+    #[test]
+    fn address_events_ignored() {
+        let c = NameCache::new();
+        c.observe(&new_link(2, "eth0"));
+        c.observe(&NetworkEvent::DelAddr(
+            make_index(2),
+            std::net::IpAddr::from([10, 0, 0, 1]),
+            8,
+        ));
+        assert_eq!(name(&c, 2), Some("eth0".to_string()));
+    }
+
+    #[test]
+    fn snapshot_replaces_cache() {
+        let c = NameCache::new();
+        c.observe(&new_link(2, "eth0"));
+        c.observe(&new_link(3, "eth1"));
+        c.observe_snapshot(&[new_link(3, "eth1"), new_link(4, "wlan0")]);
+        assert_eq!(name(&c, 2), None);
+        assert_eq!(name(&c, 3), Some("eth1".to_string()));
+        assert_eq!(index(&c, "wlan0"), Some(4));
+    }
+
+    #[test]
+    fn misses_are_looked_up_and_remembered() {
+        let c = NameCache::new();
+        assert_eq!(
+            c.name(make_index(3), fake_indextoname),
+            Some("eth2".to_string())
+        );
+        assert_eq!(c.index("eth5", fake_nametoindex), Some(make_index(6)));
+        assert_eq!(name(&c, 3), Some("eth2".to_string()));
+        assert_eq!(index(&c, "eth2"), Some(3));
+        assert_eq!(name(&c, 6), Some("eth5".to_string()));
+    }
+
+    #[test]
+    fn cache_beats_lookup() {
+        let c = NameCache::new();
+        c.observe(&new_link(3, "lan0"));
+        assert_eq!(
+            c.name(make_index(3), fake_indextoname),
+            Some("lan0".to_string())
+        );
+        assert_eq!(c.index("lan0", fake_nametoindex), Some(make_index(3)));
+    }
+
+    #[test]
+    fn zero_index_is_none() {
+        let c = NameCache::new();
+        assert_eq!(c.index("eth-1", |_| Ok(0)), None);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn agrees_with_get_interfaces() {
+        for event in crate::get_interfaces().unwrap() {
+            if let NetworkEvent::NewLink(ix, name, _, _) = event {
+                assert_eq!(ix.name().as_deref(), Some(name.as_str()));
+                assert_eq!(InterfaceIndex::from_name(&name), Some(ix));
+            }
+        }
+    }
+}
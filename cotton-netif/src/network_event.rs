@@ -39,6 +39,15 @@ impl Flags {
     pub fn contains(&self, other: Self) -> bool {
         (self.0 & other.0) == other.0
     }
+
+    /// Check whether addresses on this interface should be used for multicast
+    ///
+    /// Point-to-point links, such as VPN tunnels (WireGuard, OpenVPN
+    /// "tun" devices), often claim to be multicast-capable, but in
+    /// practice multicast discovery traffic is not wanted on them.
+    pub fn multicast_suitable(&self) -> bool {
+        self.contains(Self::MULTICAST) && !self.contains(Self::POINTTOPOINT)
+    }
 }
 
 impl BitOr for Flags {
@@ -54,21 +63,221 @@ impl BitOrAssign for Flags {
     }
 }
 
+/// Hints describing how a particular address on an interface can be used
+///
+/// These are derived from the interface type and, on Linux, from the
+/// kernel's per-address flags (`IFA_FLAGS`); they are hints only, and
+/// platforms that can't provide a particular hint leave it clear.
+#[derive(Default, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct AddressFlags(u32);
+
+impl AddressFlags {
+    #[doc = "Address is suitable for sending and receiving multicast"]
+    pub const MULTICAST: Self = Self(0x1);
+
+    #[doc = "Address is a temporary (privacy) address"]
+    pub const TEMPORARY: Self = Self(0x2);
+
+    #[doc = "Address is still undergoing duplicate-address detection"]
+    pub const TENTATIVE: Self = Self(0x4);
+
+    #[doc = "Address is deprecated and shouldn't be used for new connections"]
+    pub const DEPRECATED: Self = Self(0x8);
+
+    #[doc = "An empty set of flags"]
+    pub fn empty() -> Self {
+        Self(0)
+    }
+
+    #[doc = "Check whether a subset of flags are set"]
+    pub fn contains(&self, other: Self) -> bool {
+        (self.0 & other.0) == other.0
+    }
+
+    #[doc = "Remove a subset of flags"]
+    pub fn remove(&mut self, other: Self) {
+        self.0 &= !other.0;
+    }
+
+    /// Is the address one that peers can rely on reaching later?
+    ///
+    /// Temporary (privacy) addresses are rotated, and expire a day or
+    /// so after being replaced; deprecated ones are on their way out;
+    /// and tentative ones might yet turn out to be duplicates. An
+    /// address with none of those flags is stable, and is the one to
+    /// prefer when handing out an address, such as in a URL, which
+    /// peers might keep hold of.
+    pub fn is_stable(&self) -> bool {
+        let unstable = Self::TEMPORARY | Self::TENTATIVE | Self::DEPRECATED;
+        (self.0 & unstable.0) == 0
+    }
+}
+
+impl BitOr for AddressFlags {
+    type Output = Self;
+    fn bitor(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+}
+
+impl BitOrAssign for AddressFlags {
+    fn bitor_assign(&mut self, other: Self) {
+        self.0 |= other.0;
+    }
+}
+
 use no_std_net::IpAddr as IpAddress;
 
+/// How an address came to be configured
+///
+/// On Linux, addresses which the kernel reports as permanent
+/// (`IFA_F_PERMANENT`) are `Static`, and all others -- which have a
+/// limited lifetime, such as a DHCP lease or an IPv6 router
+/// advertisement -- are `Dynamic`. Link-local addresses are
+/// recognised by their range whatever configured them. Platforms
+/// without that information report everything other than link-local
+/// addresses as `Unknown`.
+///
+/// This lets services tell an address handed out by the network from
+/// one picked by the host itself: for instance, an SSDP service might
+/// wait for a `Static` or `Dynamic` address before announcing
+/// anything, rather than settling for 169.254.x.x.
+#[derive(Default, Clone, Copy, PartialEq, Eq, Debug)]
+#[non_exhaustive]
+pub enum AddressOrigin {
+    /// Not known on this platform
+    #[default]
+    Unknown,
+
+    /// Configured statically, with no expiry
+    Static,
+
+    /// Configured with a limited lifetime, typically by DHCP (or, for
+    /// IPv6, autoconfiguration)
+    Dynamic,
+
+    /// A link-local address (169.254.0.0/16 or fe80::/10), such as a
+    /// host assigns itself when no DHCP server answers
+    LinkLocal,
+}
+
+impl AddressOrigin {
+    /// The origin that can be deduced from an address alone
+    ///
+    /// That is, `LinkLocal` for link-local addresses, and `Unknown`
+    /// for everything else.
+    pub fn from_address(addr: &IpAddress) -> Self {
+        let link_local = match addr {
+            IpAddress::V4(v4) => v4.is_link_local(),
+            IpAddress::V6(v6) => (v6.segments()[0] & 0xFFC0) == 0xFE80,
+        };
+        if link_local {
+            Self::LinkLocal
+        } else {
+            Self::Unknown
+        }
+    }
+
+    /// Was the address assigned by the network, or configured by hand?
+    ///
+    /// True for `Static` and `Dynamic` addresses; false for
+    /// link-local ones, and for those of unknown origin.
+    pub fn is_assigned(&self) -> bool {
+        matches!(self, Self::Static | Self::Dynamic)
+    }
+}
+
+/// Whether an interface can actually carry traffic
+///
+/// This is the RFC 2863 "operational status", as Linux reports it in
+/// `IFLA_OPERSTATE`. It is distinct from [`Flags::UP`], which only
+/// says that the interface has been enabled administratively: an
+/// Ethernet interface which is up but has no cable plugged in is
+/// `LowerLayerDown`, because it has no carrier.
+///
+/// Drivers which can't tell (such as loopback, and many virtual
+/// interfaces) report `Unknown`; platforms without the information
+/// deduce a state from [`Flags::RUNNING`] instead, see
+/// [`OperState::from_flags`].
+#[derive(Default, Clone, Copy, PartialEq, Eq, Debug)]
+#[non_exhaustive]
+pub enum OperState {
+    /// Not reported by the driver or platform
+    #[default]
+    Unknown,
+
+    /// Some component (typically hardware) is missing
+    NotPresent,
+
+    /// Down
+    Down,
+
+    /// Down because of the state of a lower layer, such as having
+    /// no carrier
+    LowerLayerDown,
+
+    /// In some test mode
+    Testing,
+
+    /// Waiting for some external event, such as 802.1X authentication
+    Dormant,
+
+    /// Able to pass packets
+    Up,
+}
+
+impl OperState {
+    /// The state that can be deduced from interface flags alone
+    ///
+    /// That is, `Up` for running interfaces; `LowerLayerDown` for
+    /// interfaces which are enabled but not running; and `Down` for
+    /// the rest.
+    pub fn from_flags(flags: Flags) -> Self {
+        if flags.contains(Flags::RUNNING) {
+            Self::Up
+        } else if flags.contains(Flags::UP) {
+            Self::LowerLayerDown
+        } else {
+            Self::Down
+        }
+    }
+
+    /// Could the interface be passing packets?
+    ///
+    /// True for `Up`, and also for `Unknown`, because interfaces
+    /// whose drivers don't track carrier (such as loopback) are
+    /// reported that way even when they're working normally.
+    pub fn has_carrier(&self) -> bool {
+        matches!(self, Self::Up | Self::Unknown)
+    }
+}
+
 /** Event when a new interface or address is detected, or when one disappears
  */
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum NetworkEvent {
-    /** A new network interface is detected. */
-    NewLink(InterfaceIndex, alloc::string::String, Flags),
+    /** A new network interface is detected, or an existing one changes state.
+     *
+     * The fields are the interface, its name, its flags, and its
+     * operational status.
+     */
+    NewLink(InterfaceIndex, alloc::string::String, Flags, OperState),
 
     /** A previously-seen interface has gone away (e.g. USB unplug). */
     DelLink(InterfaceIndex),
 
     /** An interface has a new address; note that each interface can have several addresses.
+     *
+     * The fields are the interface, the address, the prefix length,
+     * hints about how the address can be used, and how it was
+     * configured.
+     *
+     * An address can be reported again, without being deleted in
+     * between, when its hints change: for instance, when an IPv6
+     * address finishes duplicate-address detection (losing
+     * `AddressFlags::TENTATIVE`) or is deprecated.
      */
-    NewAddr(InterfaceIndex, IpAddress, u8),
+    NewAddr(InterfaceIndex, IpAddress, u8, AddressFlags, AddressOrigin),
 
     /** A previously-active address has been deactivated. */
     DelAddr(InterfaceIndex, IpAddress, u8),
@@ -0,0 +1,451 @@
+use super::{AddressFlags, AddressOrigin, InterfaceIndex, NetworkEvent};
+use futures_util::Stream;
+use no_std_net::IpAddr;
+use std::io::{Error, ErrorKind};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::time::Duration;
+
+struct Shared {
+    script: Vec<(Duration, Result<NetworkEvent, ErrorKind>)>,
+    now: Duration,
+    wakers: Vec<Waker>,
+}
+
+/** A simulated network-interface backend, driven by a script
+
+Each scripted [`NetworkEvent`] (or error) has a _virtual_ timestamp,
+measured from the start of the script. Time only passes when
+[`ScriptedNetif::advance`] or [`ScriptedNetif::advance_to`] is called,
+so tests which use `ScriptedNetif` behave identically on every run,
+however loaded the machine running them.
+
+[`ScriptedNetif::get_interfaces`] and
+[`ScriptedNetif::get_interfaces_async`] stand in for the real
+[`get_interfaces`](crate::get_interfaces) and
+[`get_interfaces_async`](crate::get_interfaces_async). The stream
+returned by the latter yields each event once the virtual clock has
+reached its timestamp; unlike the real one, it ends once the whole
+script has been delivered.
+
+```rust
+# use cotton_netif::*;
+# use cotton_netif::scripted::ScriptedNetif;
+# use futures_util::{FutureExt, StreamExt};
+# use std::time::Duration;
+# let ix = InterfaceIndex(core::num::NonZeroU32::new(2).unwrap());
+let netif = ScriptedNetif::new()
+    .at(
+        Duration::ZERO,
+        NetworkEvent::NewLink(
+            ix,
+            "eth0".into(),
+            Flags::UP | Flags::RUNNING,
+            OperState::Up,
+        ),
+    )
+    .at(Duration::from_secs(5), NetworkEvent::DelLink(ix));
+
+let mut s = netif.get_interfaces_async()?;
+assert!(s.next().now_or_never().is_some());  // NewLink
+assert!(s.next().now_or_never().is_none());  // DelLink is in the future
+
+netif.advance(Duration::from_secs(5));
+assert!(s.next().now_or_never().is_some());  // DelLink
+# Ok::<(), std::io::Error>(())
+```
+ */
+pub struct ScriptedNetif {
+    shared: Arc<Mutex<Shared>>,
+}
+
+impl Default for ScriptedNetif {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ScriptedNetif {
+    /// Create a new `ScriptedNetif` with an empty script
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            shared: Arc::new(Mutex::new(Shared {
+                script: Vec::new(),
+                now: Duration::ZERO,
+                wakers: Vec::new(),
+            })),
+        }
+    }
+
+    fn push(&self, at: Duration, item: Result<NetworkEvent, ErrorKind>) {
+        let mut shared = self.shared.lock().unwrap();
+        let pos = shared.script.partition_point(|(t, _)| *t <= at);
+        shared.script.insert(pos, (at, item));
+    }
+
+    /// Add an event to the script, to happen at virtual time `at`
+    ///
+    /// Events may be added in any order; they are delivered in
+    /// timestamp order, and events with equal timestamps in the order
+    /// they were added.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned.
+    #[must_use]
+    pub fn at(self, at: Duration, event: NetworkEvent) -> Self {
+        self.push(at, Ok(event));
+        self
+    }
+
+    /// Add an error to the script, to be yielded at virtual time `at`
+    ///
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned.
+    #[must_use]
+    pub fn error_at(self, at: Duration, kind: ErrorKind) -> Self {
+        self.push(at, Err(kind));
+        self
+    }
+
+    /// The current virtual time
+    ///
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned.
+    #[must_use]
+    pub fn now(&self) -> Duration {
+        self.shared.lock().unwrap().now
+    }
+
+    /// Move the virtual clock forwards by `delta`
+    ///
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned.
+    pub fn advance(&self, delta: Duration) {
+        let now = self.now();
+        self.advance_to(now + delta);
+    }
+
+    /// Move the virtual clock forwards to `t`
+    ///
+    /// Has no effect if `t` is in the virtual past.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned.
+    pub fn advance_to(&self, t: Duration) {
+        let mut shared = self.shared.lock().unwrap();
+        if t > shared.now {
+            shared.now = t;
+            for w in shared.wakers.drain(..) {
+                w.wake();
+            }
+        }
+    }
+
+    /// Obtain a snapshot of the simulated interfaces, as at the current time
+    ///
+    /// Like [`get_interfaces`](crate::get_interfaces), this returns
+    /// a [`NetworkEvent::NewLink`] for each interface present,
+    /// followed by a [`NetworkEvent::NewAddr`] for each address
+    /// present; interfaces and addresses which the script has
+    /// already deleted, are not included. Scripted errors are
+    /// ignored.
+    ///
+    /// # Errors
+    ///
+    /// None at present; the `Result` is for compatibility with the
+    /// real `get_interfaces`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned.
+    pub fn get_interfaces(
+        &self,
+    ) -> Result<impl Iterator<Item = NetworkEvent>, Error> {
+        let shared = self.shared.lock().unwrap();
+        let mut links: Vec<(InterfaceIndex, NetworkEvent)> = Vec::new();
+        let mut addrs: Vec<(
+            InterfaceIndex,
+            IpAddr,
+            u8,
+            AddressFlags,
+            AddressOrigin,
+        )> = Vec::new();
+        for (_, item) in
+            shared.script.iter().take_while(|(t, _)| *t <= shared.now)
+        {
+            match item {
+                Ok(e @ NetworkEvent::NewLink(ix, _, _, _)) => {
+                    links.retain(|l| l.0 != *ix);
+                    links.push((*ix, e.clone()));
+                }
+                Ok(NetworkEvent::DelLink(ix)) => {
+                    links.retain(|l| l.0 != *ix);
+                    addrs.retain(|a| a.0 != *ix);
+                }
+                Ok(NetworkEvent::NewAddr(ix, addr, prefix, flags, origin)) => {
+                    addrs.retain(|a| a.0 != *ix || a.1 != *addr);
+                    addrs.push((*ix, *addr, *prefix, *flags, *origin));
+                }
+                Ok(NetworkEvent::DelAddr(ix, addr, _)) => {
+                    addrs.retain(|a| a.0 != *ix || a.1 != *addr);
+                }
+                Err(_) => {}
+            }
+        }
+        Ok(links.into_iter().map(|l| l.1).chain(addrs.into_iter().map(
+            |(ix, addr, prefix, flags, origin)| {
+                NetworkEvent::NewAddr(ix, addr, prefix, flags, origin)
+            },
+        )))
+    }
+
+    /// Obtain a stream of the scripted events
+    ///
+    /// Each stream starts from the beginning of the script, so
+    /// events whose timestamps have already passed are yielded
+    /// immediately -- just as the real
+    /// [`get_interfaces_async`](crate::get_interfaces_async)
+    /// announces all existing interfaces and addresses as if they
+    /// were new.
+    ///
+    /// # Errors
+    ///
+    /// None at present; the `Result` is for compatibility with the
+    /// real `get_interfaces_async`.
+    pub fn get_interfaces_async(&self) -> Result<ScriptedStream, Error> {
+        Ok(ScriptedStream {
+            shared: self.shared.clone(),
+            next: 0,
+        })
+    }
+}
+
+/// The stream returned by [`ScriptedNetif::get_interfaces_async`]
+pub struct ScriptedStream {
+    shared: Arc<Mutex<Shared>>,
+    next: usize,
+}
+
+impl Stream for ScriptedStream {
+    type Item = Result<NetworkEvent, Error>;
+
+    fn poll_next(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Self::Item>> {
+        let shared = self.shared.clone();
+        let mut shared = shared.lock().unwrap();
+        match shared.script.get(self.next) {
+            None => Poll::Ready(None),
+            Some((t, item)) if *t <= shared.now => {
+                let item = item.clone().map_err(Error::from);
+                self.next += 1;
+                Poll::Ready(Some(item))
+            }
+            Some(_) => {
+                shared.wakers.push(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Flags, OperState};
+    use futures_util::{FutureExt, StreamExt};
+    use no_std_net::Ipv4Addr;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::task::Wake;
+
+    fn make_index(i: u32) -> InterfaceIndex {
+        InterfaceIndex(core::num::NonZeroU32::new(i).unwrap())
+    }
+
+    fn new_link(i: u32) -> NetworkEvent {
+        NetworkEvent::NewLink(
+            make_index(i),
+            format!("eth{i}"),
+            Flags::UP,
+            OperState::LowerLayerDown,
+        )
+    }
+
+    fn new_addr(i: u32, a: u8) -> NetworkEvent {
+        NetworkEvent::NewAddr(
+            make_index(i),
+            IpAddr::V4(Ipv4Addr::new(192, 168, 0, a)),
+            24,
+            AddressFlags::MULTICAST,
+            AddressOrigin::Dynamic,
+        )
+    }
+
+    fn del_addr(i: u32, a: u8) -> NetworkEvent {
+        NetworkEvent::DelAddr(
+            make_index(i),
+            IpAddr::V4(Ipv4Addr::new(192, 168, 0, a)),
+            24,
+        )
+    }
+
+    fn next(s: &mut ScriptedStream) -> Option<Option<NetworkEvent>> {
+        s.next().now_or_never().map(|o| o.map(Result::unwrap))
+    }
+
+    #[derive(Default)]
+    struct CountingWaker(AtomicUsize);
+
+    impl Wake for CountingWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn empty_script_ends() {
+        let n = ScriptedNetif::default();
+        let mut s = n.get_interfaces_async().unwrap();
+        assert_eq!(next(&mut s), Some(None));
+        assert_eq!(n.get_interfaces().unwrap().count(), 0);
+    }
+
+    #[test]
+    fn events_delivered_at_their_times() {
+        let n = ScriptedNetif::new()
+            .at(Duration::from_secs(2), new_addr(1, 3))
+            .at(Duration::ZERO, new_link(1))
+            .at(Duration::from_secs(2), del_addr(1, 3));
+        let mut s = n.get_interfaces_async().unwrap();
+        assert_eq!(next(&mut s), Some(Some(new_link(1))));
+        assert_eq!(next(&mut s), None);
+
+        n.advance(Duration::from_secs(1));
+        assert_eq!(n.now(), Duration::from_secs(1));
+        assert_eq!(next(&mut s), None);
+
+        n.advance(Duration::from_secs(1));
+        assert_eq!(next(&mut s), Some(Some(new_addr(1, 3))));
+        assert_eq!(next(&mut s), Some(Some(del_addr(1, 3))));
+        assert_eq!(next(&mut s), Some(None));
+    }
+
+    #[test]
+    fn clock_does_not_go_backwards() {
+        let n = ScriptedNetif::new();
+        n.advance_to(Duration::from_secs(5));
+        n.advance_to(Duration::from_secs(3));
+        assert_eq!(n.now(), Duration::from_secs(5));
+    }
+
+    #[test]
+    fn each_stream_sees_whole_script() {
+        let n = ScriptedNetif::new()
+            .at(Duration::ZERO, new_link(1))
+            .at(Duration::from_secs(1), new_link(2));
+        n.advance(Duration::from_secs(1));
+        let mut s1 = n.get_interfaces_async().unwrap();
+        let mut s2 = n.get_interfaces_async().unwrap();
+        assert_eq!(next(&mut s1), Some(Some(new_link(1))));
+        assert_eq!(next(&mut s1), Some(Some(new_link(2))));
+        assert_eq!(next(&mut s2), Some(Some(new_link(1))));
+        assert_eq!(next(&mut s2), Some(Some(new_link(2))));
+    }
+
+    #[test]
+    fn errors_are_yielded() {
+        let n = ScriptedNetif::new()
+            .error_at(Duration::ZERO, ErrorKind::ConnectionReset)
+            .at(Duration::ZERO, new_link(1));
+        let mut s = n.get_interfaces_async().unwrap();
+        let e = s.next().now_or_never().unwrap().unwrap();
+        assert_eq!(e.unwrap_err().kind(), ErrorKind::ConnectionReset);
+        assert_eq!(next(&mut s), Some(Some(new_link(1))));
+
+        // Snapshots ignore errors
+        assert_eq!(n.get_interfaces().unwrap().count(), 1);
+    }
+
+    #[test]
+    fn advance_wakes_stream() {
+        let n = ScriptedNetif::new().at(Duration::from_secs(1), new_link(1));
+        let mut s = n.get_interfaces_async().unwrap();
+        let w = Arc::new(CountingWaker::default());
+        let waker = Waker::from(w.clone());
+        let mut cx = Context::from_waker(&waker);
+        assert!(Pin::new(&mut s).poll_next(&mut cx).is_pending());
+        assert_eq!(w.0.load(Ordering::SeqCst), 0);
+
+        n.advance(Duration::ZERO);
+        assert_eq!(w.0.load(Ordering::SeqCst), 0);
+
+        n.advance(Duration::from_millis(500));
+        assert_eq!(w.0.load(Ordering::SeqCst), 1);
+        assert!(Pin::new(&mut s).poll_next(&mut cx).is_pending());
+
+        n.advance(Duration::from_millis(500));
+        assert_eq!(w.0.load(Ordering::SeqCst), 2);
+        assert!(Pin::new(&mut s).poll_next(&mut cx).is_ready());
+    }
+
+    #[test]
+    fn snapshot_reflects_churn() {
+        let n = ScriptedNetif::new()
+            .at(Duration::ZERO, new_link(1))
+            .at(Duration::ZERO, new_addr(1, 3))
+            .at(Duration::ZERO, new_link(2))
+            .at(Duration::ZERO, new_addr(2, 4))
+            .at(Duration::from_secs(1), new_addr(1, 5))
+            .at(Duration::from_secs(2), del_addr(1, 3))
+            .at(Duration::from_secs(3), NetworkEvent::DelLink(make_index(2)))
+            .at(
+                Duration::from_secs(4),
+                NetworkEvent::NewLink(
+                    make_index(1),
+                    "eth1".to_string(),
+                    Flags::UP | Flags::RUNNING,
+                    OperState::Up,
+                ),
+            );
+
+        let v: Vec<_> = n.get_interfaces().unwrap().collect();
+        assert_eq!(
+            v,
+            vec![new_link(1), new_link(2), new_addr(1, 3), new_addr(2, 4)]
+        );
+
+        n.advance_to(Duration::from_secs(2));
+        let v: Vec<_> = n.get_interfaces().unwrap().collect();
+        assert_eq!(
+            v,
+            vec![new_link(1), new_link(2), new_addr(2, 4), new_addr(1, 5)]
+        );
+
+        n.advance_to(Duration::from_secs(3));
+        let v: Vec<_> = n.get_interfaces().unwrap().collect();
+        assert_eq!(v, vec![new_link(1), new_addr(1, 5)]);
+
+        n.advance_to(Duration::from_secs(4));
+        let v: Vec<_> = n.get_interfaces().unwrap().collect();
+        assert_eq!(
+            v,
+            vec![
+                NetworkEvent::NewLink(
+                    make_index(1),
+                    "eth1".to_string(),
+                    Flags::UP | Flags::RUNNING,
+                    OperState::Up,
+                ),
+                new_addr(1, 5)
+            ]
+        );
+    }
+}
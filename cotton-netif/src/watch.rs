@@ -0,0 +1,387 @@
+use crate::network_event::{
+    AddressFlags, AddressOrigin, Flags, InterfaceIndex, NetworkEvent,
+    OperState,
+};
+use futures_util::Stream;
+use no_std_net::IpAddr;
+use std::collections::BTreeMap;
+use std::io::Error;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+/// An address on a network interface, as seen in a [`Snapshot`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Address {
+    /// The address itself
+    pub ip: IpAddr,
+
+    /// Prefix length (netmask), in bits
+    pub prefix: u8,
+
+    /// Hints about how the address can be used
+    pub flags: AddressFlags,
+
+    /// How the address was configured
+    pub origin: AddressOrigin,
+}
+
+/// A network interface, as seen in a [`Snapshot`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Interface {
+    /// Interface name, e.g. "eth0"
+    pub name: String,
+
+    /// Interface state
+    pub flags: Flags,
+
+    /// Operational status, including whether there is carrier
+    pub oper_state: OperState,
+
+    /// The interface's addresses, in the order they appeared
+    pub addresses: Vec<Address>,
+}
+
+/// All the network interfaces present at some moment, by index
+pub type Snapshot = BTreeMap<InterfaceIndex, Interface>;
+
+/// Apply one event to a snapshot, returning whether anything changed
+fn apply(snapshot: &mut Snapshot, event: NetworkEvent) -> bool {
+    match event {
+        NetworkEvent::NewLink(ix, name, flags, oper_state) => {
+            if let Some(interface) = snapshot.get_mut(&ix) {
+                if interface.name == name
+                    && interface.flags == flags
+                    && interface.oper_state == oper_state
+                {
+                    return false;
+                }
+                interface.name = name;
+                interface.flags = flags;
+                interface.oper_state = oper_state;
+            } else {
+                snapshot.insert(
+                    ix,
+                    Interface {
+                        name,
+                        flags,
+                        oper_state,
+                        addresses: Vec::new(),
+                    },
+                );
+            }
+            true
+        }
+        NetworkEvent::DelLink(ix) => snapshot.remove(&ix).is_some(),
+        NetworkEvent::NewAddr(ix, ip, prefix, flags, origin) => {
+            // Addresses on interfaces we haven't heard of are ignored;
+            // the NewLink always comes first
+            let Some(interface) = snapshot.get_mut(&ix) else {
+                return false;
+            };
+            let address = Address {
+                ip,
+                prefix,
+                flags,
+                origin,
+            };
+            if let Some(existing) =
+                interface.addresses.iter_mut().find(|a| a.ip == ip)
+            {
+                if *existing == address {
+                    return false;
+                }
+                *existing = address;
+            } else {
+                interface.addresses.push(address);
+            }
+            true
+        }
+        NetworkEvent::DelAddr(ix, ip, prefix) => {
+            let Some(interface) = snapshot.get_mut(&ix) else {
+                return false;
+            };
+            let before = interface.addresses.len();
+            interface
+                .addresses
+                .retain(|a| !(a.ip == ip && a.prefix == prefix));
+            interface.addresses.len() != before
+        }
+    }
+}
+
+/** A stream of [`Snapshot`]s, built from a stream of [`NetworkEvent`]s
+
+See [`watch`].
+ */
+pub struct Watch<S> {
+    events: Pin<Box<S>>,
+    current: Arc<Snapshot>,
+    changed: bool,
+    error: Option<Error>,
+}
+
+impl<S: Stream<Item = Result<NetworkEvent, Error>>> Stream for Watch<S> {
+    type Item = Result<Arc<Snapshot>, Error>;
+
+    fn poll_next(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Self::Item>> {
+        if let Some(e) = self.error.take() {
+            return Poll::Ready(Some(Err(e)));
+        }
+
+        // Apply every event that's ready now, so that a burst of them
+        // (such as the initial listing) results in just one snapshot
+        let ended = loop {
+            match self.events.as_mut().poll_next(cx) {
+                Poll::Ready(Some(Ok(event))) => {
+                    let this = &mut *self;
+                    if apply(Arc::make_mut(&mut this.current), event) {
+                        this.changed = true;
+                    }
+                }
+                Poll::Ready(Some(Err(e))) => {
+                    if !self.changed {
+                        return Poll::Ready(Some(Err(e)));
+                    }
+                    // Report the changes so far first
+                    self.error = Some(e);
+                    break false;
+                }
+                Poll::Ready(None) => break true,
+                Poll::Pending => break false,
+            }
+        };
+
+        if self.changed {
+            self.changed = false;
+            Poll::Ready(Some(Ok(self.current.clone())))
+        } else if ended {
+            Poll::Ready(None)
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+/** Convert a stream of events into a stream of snapshots
+
+Each item is the complete set of interfaces (with their flags and
+addresses), as of the most recent change. Events which arrive
+together are coalesced into a single snapshot, and events which
+change nothing (such as a repeated [`NetworkEvent::NewLink`] with the
+same flags) don't produce one at all.
+
+Snapshots are immutable and cheap to clone, so they can be shared
+freely; the stream only copies the interface list if a previous
+snapshot is still in use when the next change arrives.
+
+Errors from the underlying stream are passed through, after any
+snapshot reflecting the events received before them.
+
+```rust
+# use cotton_netif::*;
+# use cotton_netif::watch::watch;
+# use futures_util::{stream, FutureExt, StreamExt};
+# let ix = InterfaceIndex(core::num::NonZeroU32::new(2).unwrap());
+let events = stream::iter([
+    Ok(NetworkEvent::NewLink(ix, "eth0".into(), Flags::UP, OperState::LowerLayerDown)),
+    Ok(NetworkEvent::NewLink(ix, "eth0".into(), Flags::UP | Flags::RUNNING, OperState::Up)),
+]);
+let mut s = watch(events);
+let snapshot = s.next().now_or_never().unwrap().unwrap()?;
+assert_eq!(snapshot[&ix].flags, Flags::UP | Flags::RUNNING);
+# Ok::<(), std::io::Error>(())
+```
+ */
+pub fn watch<S>(events: S) -> Watch<S>
+where
+    S: Stream<Item = Result<NetworkEvent, Error>>,
+{
+    Watch {
+        events: Box::pin(events),
+        current: Arc::default(),
+        changed: false,
+        error: None,
+    }
+}
+
+/** Obtain a stream of snapshots of the network interfaces
+
+As [`watch`] applied to [`get_interfaces_async`](crate::get_interfaces_async):
+the first snapshot lists the interfaces and addresses already present,
+and a new one follows whenever any of them change.
+
+# Errors
+
+As [`get_interfaces_async`](crate::get_interfaces_async).
+
+ */
+#[cfg(all(target_os = "linux", feature = "async"))]
+pub fn watch_interfaces(
+) -> Result<Watch<impl Stream<Item = Result<NetworkEvent, Error>>>, Error> {
+    Ok(watch(crate::get_interfaces_async()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::{FutureExt, StreamExt};
+    use no_std_net::Ipv4Addr;
+    use std::collections::VecDeque;
+    use std::io::ErrorKind;
+
+    fn make_index(i: u32) -> InterfaceIndex {
+        InterfaceIndex(core::num::NonZeroU32::new(i).unwrap())
+    }
+
+    fn new_link(i: u32, flags: Flags) -> NetworkEvent {
+        NetworkEvent::NewLink(
+            make_index(i),
+            format!("eth{i}"),
+            flags,
+            OperState::from_flags(flags),
+        )
+    }
+
+    fn new_addr(i: u32, last: u8) -> NetworkEvent {
+        NetworkEvent::NewAddr(
+            make_index(i),
+            IpAddr::V4(Ipv4Addr::new(192, 168, 100, last)),
+            24,
+            AddressFlags::MULTICAST,
+            AddressOrigin::Dynamic,
+        )
+    }
+
+    fn del_addr(i: u32, last: u8) -> NetworkEvent {
+        NetworkEvent::DelAddr(
+            make_index(i),
+            IpAddr::V4(Ipv4Addr::new(192, 168, 100, last)),
+            24,
+        )
+    }
+
+    /// A stream which yields its events in batches: `None` marks the
+    /// end of a batch, where it returns Pending once
+    struct Batches(VecDeque<Option<Result<NetworkEvent, Error>>>);
+
+    impl Stream for Batches {
+        type Item = Result<NetworkEvent, Error>;
+
+        fn poll_next(
+            mut self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+        ) -> Poll<Option<Self::Item>> {
+            match self.0.pop_front() {
+                Some(Some(item)) => Poll::Ready(Some(item)),
+                Some(None) => Poll::Pending,
+                None => Poll::Ready(None),
+            }
+        }
+    }
+
+    fn batches(
+        items: impl IntoIterator<Item = Option<Result<NetworkEvent, Error>>>,
+    ) -> Watch<Batches> {
+        watch(Batches(items.into_iter().collect()))
+    }
+
+    fn next_snapshot(s: &mut Watch<Batches>) -> Arc<Snapshot> {
+        s.next().now_or_never().unwrap().unwrap().unwrap()
+    }
+
+    #[test]
+    fn burst_coalesced() {
+        let mut s = batches([
+            Some(Ok(new_link(1, Flags::UP))),
+            Some(Ok(new_addr(1, 1))),
+            Some(Ok(new_link(2, Flags::UP))),
+            None,
+        ]);
+
+        let snapshot = next_snapshot(&mut s);
+        assert_eq!(snapshot.len(), 2);
+        assert_eq!(snapshot[&make_index(1)].name, "eth1");
+        assert_eq!(snapshot[&make_index(1)].addresses.len(), 1);
+        assert!(snapshot[&make_index(2)].addresses.is_empty());
+
+        assert!(s.next().now_or_never().unwrap().is_none());
+    }
+
+    #[test]
+    fn each_batch_produces_snapshot() {
+        let mut s = batches([
+            Some(Ok(new_link(1, Flags::UP))),
+            None,
+            Some(Ok(new_addr(1, 1))),
+            None,
+            Some(Ok(del_addr(1, 1))),
+            Some(Ok(NetworkEvent::DelLink(make_index(1)))),
+        ]);
+
+        let first = next_snapshot(&mut s);
+        assert!(first[&make_index(1)].addresses.is_empty());
+
+        let second = next_snapshot(&mut s);
+        assert_eq!(
+            second[&make_index(1)].addresses[0].origin,
+            AddressOrigin::Dynamic
+        );
+
+        let third = next_snapshot(&mut s);
+        assert!(third.is_empty());
+
+        // Earlier snapshots are unaffected
+        assert!(first[&make_index(1)].addresses.is_empty());
+        assert_eq!(second[&make_index(1)].addresses.len(), 1);
+    }
+
+    #[test]
+    fn no_change_no_snapshot() {
+        let mut s = batches([
+            Some(Ok(new_link(1, Flags::UP))),
+            Some(Ok(new_addr(1, 1))),
+            None,
+            Some(Ok(new_link(1, Flags::UP))),
+            Some(Ok(new_addr(1, 1))),
+            Some(Ok(del_addr(1, 2))),
+            Some(Ok(new_addr(3, 1))),
+            Some(Ok(NetworkEvent::DelLink(make_index(3)))),
+        ]);
+
+        next_snapshot(&mut s);
+        assert!(s.next().now_or_never().unwrap().is_none());
+    }
+
+    #[test]
+    fn changed_flags_produce_snapshot() {
+        let mut s = batches([
+            Some(Ok(new_link(1, Flags::UP))),
+            None,
+            Some(Ok(new_link(1, Flags::UP | Flags::RUNNING))),
+        ]);
+
+        next_snapshot(&mut s);
+        let snapshot = next_snapshot(&mut s);
+        assert_eq!(snapshot[&make_index(1)].flags, Flags::UP | Flags::RUNNING);
+    }
+
+    #[test]
+    fn error_follows_pending_changes() {
+        let mut s = batches([
+            Some(Ok(new_link(1, Flags::UP))),
+            Some(Err(Error::from(ErrorKind::Other))),
+            Some(Err(Error::from(ErrorKind::BrokenPipe))),
+        ]);
+
+        assert_eq!(next_snapshot(&mut s).len(), 1);
+        let e = s.next().now_or_never().unwrap().unwrap().unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::Other);
+        let e = s.next().now_or_never().unwrap().unwrap().unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::BrokenPipe);
+        assert!(s.next().now_or_never().unwrap().is_none());
+    }
+}
@@ -60,4 +60,17 @@ pub trait AsyncBlockDevice {
         count: u32,
         data: &[u8],
     ) -> impl Future<Output = Result<(), Self::E>>;
+
+    /// # Ensure all written data has reached non-volatile storage
+    ///
+    /// A successful `write_blocks` only means that the device has
+    /// accepted the data: it may still be sitting in a volatile
+    /// write cache. Call this before the device is unplugged or
+    /// powered off.
+    ///
+    /// The default implementation does nothing, which is correct for
+    /// devices without a write cache.
+    fn flush(&mut self) -> impl Future<Output = Result<(), Self::E>> {
+        async { Ok(()) }
+    }
 }
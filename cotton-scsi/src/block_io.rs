@@ -0,0 +1,345 @@
+use super::async_block_device::{AsyncBlockDevice, DeviceInfo};
+use super::read_only::ReadOnlyError;
+use super::scsi_transport::{Error, ScsiError};
+use embedded_io_async::{ErrorKind, ErrorType, Read, Seek, SeekFrom, Write};
+
+/// Errors which can arise from [`BlockIo`]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum BlockIoError<E> {
+    /// The underlying block device reported an error
+    Device(E),
+
+    /// The device's block size is zero, or larger than the `BlockIo`'s
+    /// buffer
+    UnsupportedBlockSize,
+
+    /// An attempt was made to seek before the start of the device
+    InvalidSeek,
+}
+
+impl<E: embedded_io_async::Error> embedded_io_async::Error
+    for BlockIoError<E>
+{
+    fn kind(&self) -> ErrorKind {
+        match self {
+            Self::Device(e) => e.kind(),
+            Self::UnsupportedBlockSize => ErrorKind::Unsupported,
+            Self::InvalidSeek => ErrorKind::InvalidInput,
+        }
+    }
+}
+
+impl<E: embedded_io_async::Error> embedded_io_async::Error
+    for ReadOnlyError<E>
+{
+    fn kind(&self) -> ErrorKind {
+        match self {
+            Self::Device(e) => e.kind(),
+            Self::ReadOnly => ErrorKind::PermissionDenied,
+        }
+    }
+}
+
+impl embedded_io_async::Error for ScsiError {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            Self::DataProtect => ErrorKind::PermissionDenied,
+            Self::InvalidCommandOperationCode => ErrorKind::Unsupported,
+            Self::LogicalBlockAddressOutOfRange
+            | Self::VolumeOverflow
+            | Self::InvalidFieldInCDB
+            | Self::InvalidFieldInParameterList
+            | Self::ParameterNotSupported
+            | Self::ParameterValueInvalid
+            | Self::ParameterListLengthError
+            | Self::IllegalRequest => ErrorKind::InvalidInput,
+            Self::UnrecoveredReadError
+            | Self::ReadRetriesExhausted
+            | Self::ReadErrorTooLong
+            | Self::LogicalBlockNotFound
+            | Self::RecordNotFound
+            | Self::MiscompareDuringVerify
+            | Self::Miscompare
+            | Self::MediumError => ErrorKind::InvalidData,
+            Self::BecomingReady
+            | Self::StartUnitRequired
+            | Self::ManualInterventionRequired
+            | Self::NotReady
+            | Self::LogicalUnitNotSupported => ErrorKind::NotConnected,
+            Self::UnitAttention | Self::Aborted => ErrorKind::Interrupted,
+            _ => ErrorKind::Other,
+        }
+    }
+}
+
+impl<T: PartialEq + Eq + core::fmt::Debug> embedded_io_async::Error
+    for Error<T>
+{
+    fn kind(&self) -> ErrorKind {
+        match self {
+            Self::ProtocolError => ErrorKind::InvalidData,
+            Self::Scsi(e) => e.kind(),
+            _ => ErrorKind::Other,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T: PartialEq + Eq + core::fmt::Debug> From<Error<T>> for std::io::Error {
+    fn from(e: Error<T>) -> Self {
+        let kind = embedded_io_async::Error::kind(&e);
+        Self::new(kind.into(), format!("{:?}", e))
+    }
+}
+
+#[cfg(feature = "std")]
+impl<E: embedded_io_async::Error> From<BlockIoError<E>> for std::io::Error {
+    fn from(e: BlockIoError<E>) -> Self {
+        let kind = embedded_io_async::Error::kind(&e);
+        Self::new(kind.into(), format!("{:?}", e))
+    }
+}
+
+/// Reading and writing an [`AsyncBlockDevice`] as a stream of bytes
+///
+/// Implements the `embedded-io-async` traits [`Read`], [`Write`] and
+/// [`Seek`] over a linear view of the whole device, so that it can be
+/// used by code which knows nothing of blocks.
+///
+/// Reads and writes of whole, aligned blocks go straight to the
+/// device; partial blocks are read into an internal buffer of `N`
+/// bytes (which must be at least the device's block size), and
+/// modified there. A modified block is only written back when another
+/// block is needed, or on [`Write::flush()`] -- so, as with
+/// [`FlushGuard`](crate::FlushGuard), remember to flush before
+/// finishing with the device.
+///
+/// The block size is whatever the device reports, so the default
+/// buffer only suits devices with 512-byte blocks; use, say,
+/// `BlockIo<D, 4096>` to support 4Kn devices (and 2048-byte
+/// optical media) as well.
+pub struct BlockIo<D: AsyncBlockDevice, const N: usize = 512> {
+    device: D,
+    info: Option<DeviceInfo>,
+    position: u64,
+    buffer: [u8; N],
+    /// The block currently held in `buffer`, if any
+    cached: Option<u64>,
+    /// Has `buffer` been modified since it was read?
+    dirty: bool,
+}
+
+impl<D: AsyncBlockDevice, const N: usize> BlockIo<D, N> {
+    /// Wrap a block device, with the stream position at the start
+    pub fn new(device: D) -> Self {
+        Self {
+            device,
+            info: None,
+            position: 0,
+            buffer: [0u8; N],
+            cached: None,
+            dirty: false,
+        }
+    }
+
+    /// The current stream position, in bytes from the start of the device
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Is there a partially-written block not yet written back?
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    /// Return the underlying device
+    ///
+    /// Any partially-written block not yet written back (see
+    /// [`BlockIo::is_dirty()`]) is discarded.
+    pub fn into_inner(self) -> D {
+        self.device
+    }
+
+    async fn info(&mut self) -> Result<DeviceInfo, BlockIoError<D::E>> {
+        if let Some(info) = self.info {
+            return Ok(info);
+        }
+        let info = self
+            .device
+            .device_info()
+            .await
+            .map_err(BlockIoError::Device)?;
+        if info.block_size == 0 || info.block_size as usize > N {
+            return Err(BlockIoError::UnsupportedBlockSize);
+        }
+        self.info = Some(info);
+        Ok(info)
+    }
+
+    /// Is the buffered block one of these `count` blocks?
+    fn cached_within(&self, block: u64, count: u32) -> bool {
+        self.cached
+            .is_some_and(|c| c >= block && c - block < count as u64)
+    }
+
+    /// Write back the buffered block, if it's been modified
+    async fn write_back(&mut self) -> Result<(), BlockIoError<D::E>> {
+        if let (true, Some(block), Some(info)) =
+            (self.dirty, self.cached, self.info)
+        {
+            self.device
+                .write_blocks(
+                    block,
+                    1,
+                    &self.buffer[..info.block_size as usize],
+                )
+                .await
+                .map_err(BlockIoError::Device)?;
+        }
+        self.dirty = false;
+        Ok(())
+    }
+
+    /// Make sure that `block` is the one in the buffer
+    async fn load(
+        &mut self,
+        block: u64,
+        block_size: usize,
+    ) -> Result<(), BlockIoError<D::E>> {
+        if self.cached == Some(block) {
+            return Ok(());
+        }
+        self.write_back().await?;
+        self.cached = None;
+        self.device
+            .read_blocks(block, 1, &mut self.buffer[..block_size])
+            .await
+            .map_err(BlockIoError::Device)?;
+        self.cached = Some(block);
+        Ok(())
+    }
+
+    /// Work out where the next transfer of up to `len` bytes goes
+    ///
+    /// Returns (block, offset within block, bytes available before the
+    /// end of the device, block size).
+    async fn locate(
+        &mut self,
+        len: usize,
+    ) -> Result<(u64, usize, usize, usize), BlockIoError<D::E>> {
+        let info = self.info().await?;
+        let block_size = info.block_size as u64;
+        let size = info.blocks.saturating_mul(block_size);
+        let available =
+            size.saturating_sub(self.position).min(len as u64) as usize;
+        Ok((
+            self.position / block_size,
+            (self.position % block_size) as usize,
+            available,
+            block_size as usize,
+        ))
+    }
+}
+
+impl<D: AsyncBlockDevice, const N: usize> ErrorType for BlockIo<D, N>
+where
+    D::E: embedded_io_async::Error,
+{
+    type Error = BlockIoError<D::E>;
+}
+
+impl<D: AsyncBlockDevice, const N: usize> Read for BlockIo<D, N>
+where
+    D::E: embedded_io_async::Error,
+{
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        let (block, within, available, block_size) =
+            self.locate(buf.len()).await?;
+        let n = if within == 0 && available >= block_size {
+            let count = (available / block_size).min(u32::MAX as usize) as u32;
+            let n = count as usize * block_size;
+            if self.cached_within(block, count) {
+                self.write_back().await?;
+            }
+            self.device
+                .read_blocks(block, count, &mut buf[..n])
+                .await
+                .map_err(BlockIoError::Device)?;
+            n
+        } else if available > 0 {
+            self.load(block, block_size).await?;
+            let n = available.min(block_size - within);
+            buf[..n].copy_from_slice(&self.buffer[within..(within + n)]);
+            n
+        } else {
+            0
+        };
+        self.position += n as u64;
+        Ok(n)
+    }
+}
+
+impl<D: AsyncBlockDevice, const N: usize> Write for BlockIo<D, N>
+where
+    D::E: embedded_io_async::Error,
+{
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        let (block, within, available, block_size) =
+            self.locate(buf.len()).await?;
+        let n = if within == 0 && available >= block_size {
+            let count = (available / block_size).min(u32::MAX as usize) as u32;
+            let n = count as usize * block_size;
+            if self.cached_within(block, count) {
+                // About to be overwritten anyway
+                self.cached = None;
+                self.dirty = false;
+            }
+            self.device
+                .write_blocks(block, count, &buf[..n])
+                .await
+                .map_err(BlockIoError::Device)?;
+            n
+        } else if available > 0 {
+            self.load(block, block_size).await?;
+            let n = available.min(block_size - within);
+            self.buffer[within..(within + n)].copy_from_slice(&buf[..n]);
+            self.dirty = true;
+            n
+        } else {
+            0
+        };
+        self.position += n as u64;
+        Ok(n)
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        self.write_back().await?;
+        self.device.flush().await.map_err(BlockIoError::Device)
+    }
+}
+
+impl<D: AsyncBlockDevice, const N: usize> Seek for BlockIo<D, N>
+where
+    D::E: embedded_io_async::Error,
+{
+    async fn seek(&mut self, pos: SeekFrom) -> Result<u64, Self::Error> {
+        let position = match pos {
+            SeekFrom::Start(n) => Some(n),
+            SeekFrom::End(n) => {
+                let info = self.info().await?;
+                info.blocks
+                    .saturating_mul(info.block_size as u64)
+                    .checked_add_signed(n)
+            }
+            SeekFrom::Current(n) => self.position.checked_add_signed(n),
+        };
+        self.position = position.ok_or(BlockIoError::InvalidSeek)?;
+        Ok(self.position)
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+#[path = "tests/block_io.rs"]
+mod tests;
@@ -0,0 +1,96 @@
+use super::async_block_device::{AsyncBlockDevice, DeviceInfo};
+use super::debug;
+
+/// Wrapping an [`AsyncBlockDevice`] so that writes are always flushed
+///
+/// Tracks whether any blocks have been written since the last
+/// successful [`AsyncBlockDevice::flush()`], and flushes them (if
+/// need be) in [`FlushGuard::release()`] before handing back the
+/// underlying device.
+///
+/// Because flushing is asynchronous, it can't be done in `Drop`: if a
+/// guard with unflushed writes is simply dropped, a warning is
+/// logged, but the data may never reach the medium.
+pub struct FlushGuard<D: AsyncBlockDevice> {
+    device: Option<D>,
+    dirty: bool,
+}
+
+impl<D: AsyncBlockDevice> FlushGuard<D> {
+    /// Wrap a block device
+    pub fn new(device: D) -> Self {
+        Self {
+            device: Some(device),
+            dirty: false,
+        }
+    }
+
+    /// Have there been any writes since the last successful flush?
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    /// Flush any outstanding writes, and return the underlying device
+    ///
+    /// On error, the guard is handed back too, so that the flush can
+    /// be retried.
+    pub async fn release(mut self) -> Result<D, (Self, D::E)> {
+        if let Err(e) = self.flush().await {
+            return Err((self, e));
+        }
+        Ok(self.device.take().unwrap())
+    }
+
+    fn device(&mut self) -> &mut D {
+        // Only None after release(), which consumes self
+        self.device.as_mut().unwrap()
+    }
+}
+
+impl<D: AsyncBlockDevice> AsyncBlockDevice for FlushGuard<D> {
+    type E = D::E;
+
+    async fn device_info(&mut self) -> Result<DeviceInfo, Self::E> {
+        self.device().device_info().await
+    }
+
+    async fn read_blocks(
+        &mut self,
+        offset: u64,
+        count: u32,
+        data: &mut [u8],
+    ) -> Result<(), Self::E> {
+        self.device().read_blocks(offset, count, data).await
+    }
+
+    async fn write_blocks(
+        &mut self,
+        offset: u64,
+        count: u32,
+        data: &[u8],
+    ) -> Result<(), Self::E> {
+        // Even a failed write might have changed some blocks
+        self.dirty = true;
+        self.device().write_blocks(offset, count, data).await
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::E> {
+        if self.dirty {
+            self.device().flush().await?;
+            self.dirty = false;
+        }
+        Ok(())
+    }
+}
+
+impl<D: AsyncBlockDevice> Drop for FlushGuard<D> {
+    fn drop(&mut self) {
+        if self.dirty {
+            debug::println!("FlushGuard dropped with unflushed writes!");
+        }
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+#[path = "tests/flush_guard.rs"]
+mod tests;
@@ -0,0 +1,700 @@
+use crate::scsi_transport::{DataPhase, Error, ScsiTransport};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpStream, ToSocketAddrs};
+
+/// The well-known TCP port of iSCSI targets
+pub const ISCSI_PORT: u16 = 3260;
+
+// Opcodes, RFC 7143 s11.2.1.2 (initiator opcodes are 0x00-0x1F,
+// target opcodes 0x20-0x3F)
+const NOP_OUT: u8 = 0x00;
+const SCSI_COMMAND: u8 = 0x01;
+const TASK_MANAGEMENT_REQUEST: u8 = 0x02;
+const LOGIN_REQUEST: u8 = 0x03;
+const DATA_OUT: u8 = 0x05;
+const LOGOUT_REQUEST: u8 = 0x06;
+const NOP_IN: u8 = 0x20;
+const SCSI_RESPONSE: u8 = 0x21;
+const TASK_MANAGEMENT_RESPONSE: u8 = 0x22;
+const LOGIN_RESPONSE: u8 = 0x23;
+const DATA_IN: u8 = 0x25;
+const LOGOUT_RESPONSE: u8 = 0x26;
+const R2T: u8 = 0x31;
+const ASYNC_MESSAGE: u8 = 0x32;
+const REJECT: u8 = 0x3F;
+
+/// Bit in the opcode byte for "immediate" delivery
+const IMMEDIATE: u8 = 0x40;
+/// The "F" (final) bit in the flags byte of most PDUs
+const FINAL: u8 = 0x80;
+/// The "S" (status) bit in the flags byte of Data-In PDUs
+const DATA_IN_STATUS: u8 = 0x01;
+/// SCSI Command flags: "R" (data in), "W" (data out), and a task
+/// attribute of "simple"
+const COMMAND_READ: u8 = 0x40;
+const COMMAND_WRITE: u8 = 0x20;
+const SIMPLE_TASK: u8 = 0x01;
+/// Login flags: "T" (transit to next stage) and "C" (continue)
+const LOGIN_TRANSIT: u8 = 0x80;
+const LOGIN_CONTINUE: u8 = 0x40;
+/// Login stages
+const SECURITY_NEGOTIATION: u8 = 0;
+const OPERATIONAL_NEGOTIATION: u8 = 1;
+const FULL_FEATURE_PHASE: u8 = 3;
+
+/// The "reserved" value of task tags, meaning "no task"
+const NO_TAG: u32 = 0xFFFF_FFFF;
+
+/// Length of the Basic Header Segment which starts every PDU
+const BHS_LEN: usize = 48;
+
+/// The longest data segment we offer to receive in one PDU
+const MAX_RECV_DATA_SEGMENT_LENGTH: usize = 65536;
+
+/// The target's receive limit, unless it says otherwise (RFC 7143 s13.12)
+const DEFAULT_MAX_SEND_DATA_SEGMENT_LENGTH: usize = 8192;
+
+/// The MaxBurstLength default (RFC 7143 s13.14), used as a transfer size
+const MAX_TRANSFER_SIZE: usize = 262_144;
+
+/// How many Login Request/Response exchanges any one stage may take
+const MAX_LOGIN_EXCHANGES: usize = 8;
+
+/// The SCSI opcode of REQUEST SENSE
+const REQUEST_SENSE: u8 = 0x03;
+
+/// Errors which can arise from the iSCSI transport itself
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum IscsiError {
+    /// The TCP connection failed (or was closed by the target)
+    Io(std::io::ErrorKind),
+
+    /// The target refused the login, with this status class and
+    /// detail (RFC 7143 s11.13.5): for instance, (2, 1) if it requires
+    /// authentication, or (2, 3) if there's no such target name
+    LoginFailed(u8, u8),
+
+    /// The target sent something which doesn't fit the protocol
+    Protocol,
+
+    /// The target rejected a PDU, with this reason code (RFC 7143 s11.17.1)
+    Rejected(u8),
+
+    /// A SCSI command wasn't completed by the target, with this iSCSI
+    /// response code: distinct from a SCSI *status*, which is reported
+    /// as [`Error::CommandFailed`]
+    TargetFailure(u8),
+
+    /// A task-management function failed, with this response code
+    /// (RFC 7143 s11.6.1): for instance 5, "function not supported"
+    TaskManagement(u8),
+}
+
+impl From<std::io::Error> for IscsiError {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e.kind())
+    }
+}
+
+impl From<IscsiError> for Error<IscsiError> {
+    fn from(e: IscsiError) -> Self {
+        match e {
+            IscsiError::Protocol => Error::ProtocolError,
+            e => Error::Transport(e),
+        }
+    }
+}
+
+/// Task-management functions, see [`IscsiTransport::task_management()`]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum TaskManagementFunction {
+    /// Abort all tasks on the logical unit from this session
+    AbortTaskSet = 2,
+    /// Abort all tasks on the logical unit, from any session
+    ClearTaskSet = 3,
+    /// Reset the logical unit
+    LogicalUnitReset = 5,
+    /// Reset the whole target (all its logical units)
+    TargetWarmReset = 6,
+}
+
+/// The names and addresses needed to log in to an iSCSI target
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoginParameters {
+    /// The initiator's own iSCSI name, e.g. "iqn.2024-01.org.example:host"
+    ///
+    /// Targets often restrict access by initiator name, so this must
+    /// match the target's configuration ("ACLs").
+    pub initiator_name: String,
+
+    /// The iSCSI name of the target, e.g. "iqn.2024-01.org.example:disk1"
+    pub target_name: String,
+
+    /// The logical unit to send commands to
+    ///
+    /// Note that the tgt target daemon reserves LUN 0 for a
+    /// "controller" pseudo-device, putting disks at LUN 1 upwards,
+    /// while LIO uses LUN 0 for the first disk.
+    pub lun: u16,
+
+    /// The initiator part of the session identifier
+    ///
+    /// A target treats a second login with the same initiator name
+    /// and ISID as a replacement for the first session, so concurrent
+    /// sessions from the same initiator name need different ISIDs.
+    pub isid: [u8; 6],
+}
+
+impl LoginParameters {
+    /// Parameters for logging in to LUN 0 of a target
+    ///
+    /// The ISID is a fixed one, of the "random" type.
+    pub fn new(initiator_name: &str, target_name: &str) -> Self {
+        Self {
+            initiator_name: initiator_name.to_string(),
+            target_name: target_name.to_string(),
+            lun: 0,
+            isid: [0x80, 0x00, 0xC0, 0x77, 0x00, 0x01],
+        }
+    }
+}
+
+/// A received PDU, with any additional header segments discarded
+struct Pdu {
+    bhs: [u8; BHS_LEN],
+    data: Vec<u8>,
+}
+
+impl Pdu {
+    fn opcode(&self) -> u8 {
+        self.bhs[0] & 0x3F
+    }
+
+    fn flags(&self) -> u8 {
+        self.bhs[1]
+    }
+
+    fn u32_at(&self, offset: usize) -> u32 {
+        u32::from_be_bytes(self.bhs[offset..offset + 4].try_into().unwrap())
+    }
+
+    fn itt(&self) -> u32 {
+        self.u32_at(16)
+    }
+
+    fn stat_sn(&self) -> u32 {
+        self.u32_at(24)
+    }
+}
+
+/// A new Basic Header Segment, with opcode, flags, LUN and task tag
+fn header(opcode: u8, flags: u8, lun: &[u8; 8], itt: u32) -> [u8; BHS_LEN] {
+    let mut bhs = [0u8; BHS_LEN];
+    bhs[0] = opcode;
+    bhs[1] = flags;
+    bhs[8..16].copy_from_slice(lun);
+    bhs[16..20].copy_from_slice(&itt.to_be_bytes());
+    bhs
+}
+
+fn put_u32(bhs: &mut [u8; BHS_LEN], offset: usize, value: u32) {
+    bhs[offset..offset + 4].copy_from_slice(&value.to_be_bytes());
+}
+
+/// The eight-byte LUN field for a logical unit number (SAM-5 s4.7)
+fn encode_lun(lun: u16) -> [u8; 8] {
+    let mut encoded = [0u8; 8];
+    if lun < 256 {
+        // Peripheral device addressing
+        encoded[1] = lun as u8;
+    } else {
+        // Flat space addressing
+        encoded[0..2]
+            .copy_from_slice(&(0x4000 | (lun & 0x3FFF)).to_be_bytes());
+    }
+    encoded
+}
+
+/// The outcome of a command, given its SCSI status byte
+///
+/// Anything but GOOD (zero) -- CHECK CONDITION, BUSY, RESERVATION
+/// CONFLICT and so on -- is a failure.
+fn scsi_status(status: u8, count: usize) -> Result<usize, Error<IscsiError>> {
+    match status {
+        0 => Ok(count),
+        _ => Err(Error::CommandFailed),
+    }
+}
+
+/// Find the value of a key in login or text parameters
+///
+/// The parameters are "key=value" pairs, each terminated by a zero byte.
+fn find_key<'a>(text: &'a [u8], key: &str) -> Option<&'a str> {
+    text.split(|b| *b == 0)
+        .filter_map(|pair| core::str::from_utf8(pair).ok())
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(k, _)| *k == key)
+        .map(|(_, v)| v)
+}
+
+/// A [`ScsiTransport`] which is an iSCSI session with a remote target
+///
+/// This is a deliberately minimal iSCSI initiator (RFC 7143): one
+/// connection per session, no authentication, no header or data
+/// digests, and error-recovery level 0 -- which is to say, any error
+/// in the connection is fatal to the session, and a new session must
+/// be created with [`IscsiTransport::connect()`]. Only one command is
+/// ever outstanding at once.
+///
+/// It's mostly useful for testing the rest of the SCSI stack against
+/// a software target such as Linux's "LIO" or the "tgt" daemon, but
+/// is also enough to use the disks in a NAS which exports them over
+/// iSCSI.
+///
+/// Targets can ask for a response from an initiator even when there's
+/// no command outstanding (LIO does so after 15s of inactivity), but
+/// this transport only reads from the connection when asked to do
+/// something. So that such targets don't drop the connection,
+/// long-lived but mostly-idle users should call
+/// [`IscsiTransport::ping()`] every few seconds.
+///
+/// The session is generic over the underlying stream, so that it can
+/// be used over (for instance) a TLS connection; usually, though, it's
+/// just a `TcpStream`.
+pub struct IscsiTransport<S = TcpStream> {
+    stream: S,
+    lun: [u8; 8],
+    next_itt: u32,
+    cmd_sn: u32,
+    exp_stat_sn: u32,
+    max_send_data_segment_length: usize,
+    sense: Option<Vec<u8>>,
+}
+
+impl IscsiTransport<TcpStream> {
+    /// Connect and log in to an iSCSI target
+    ///
+    /// The address is typically that of [`ISCSI_PORT`] on the target
+    /// host.
+    pub async fn connect<A: ToSocketAddrs>(
+        address: A,
+        parameters: &LoginParameters,
+    ) -> Result<Self, IscsiError> {
+        let stream = TcpStream::connect(address).await?;
+        stream.set_nodelay(true)?;
+        Self::login(stream, parameters).await
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> IscsiTransport<S> {
+    /// Log in to an iSCSI target over an existing connection
+    ///
+    /// On success, the session is in iSCSI's "full feature phase" and
+    /// ready for SCSI commands.
+    pub async fn login(
+        stream: S,
+        parameters: &LoginParameters,
+    ) -> Result<Self, IscsiError> {
+        let mut session = Self {
+            stream,
+            lun: encode_lun(parameters.lun),
+            next_itt: 0,
+            cmd_sn: 1,
+            exp_stat_sn: 0,
+            max_send_data_segment_length: DEFAULT_MAX_SEND_DATA_SEGMENT_LENGTH,
+            sense: None,
+        };
+        let mut tsih = 0u16;
+
+        let keys = format!(
+            "InitiatorName={}\0TargetName={}\0SessionType=Normal\0\
+             AuthMethod=None\0",
+            parameters.initiator_name, parameters.target_name
+        );
+        let (_, stage) = session
+            .login_stage(
+                parameters,
+                &mut tsih,
+                SECURITY_NEGOTIATION,
+                OPERATIONAL_NEGOTIATION,
+                &keys,
+            )
+            .await?;
+
+        // The target may (legitimately) skip straight to full-feature phase
+        if stage != FULL_FEATURE_PHASE {
+            let keys = format!(
+                "HeaderDigest=None\0DataDigest=None\0\
+                 MaxRecvDataSegmentLength={MAX_RECV_DATA_SEGMENT_LENGTH}\0\
+                 InitialR2T=Yes\0ImmediateData=No\0\
+                 ErrorRecoveryLevel=0\0MaxConnections=1\0"
+            );
+            let (text, stage) = session
+                .login_stage(
+                    parameters,
+                    &mut tsih,
+                    OPERATIONAL_NEGOTIATION,
+                    FULL_FEATURE_PHASE,
+                    &keys,
+                )
+                .await?;
+            if stage != FULL_FEATURE_PHASE {
+                return Err(IscsiError::Protocol);
+            }
+            if let Some(n) = find_key(&text, "MaxRecvDataSegmentLength")
+                .and_then(|v| v.parse::<usize>().ok())
+                .filter(|n| *n >= 512)
+            {
+                session.max_send_data_segment_length = n;
+            }
+        }
+        Ok(session)
+    }
+
+    /// Exchange Login Request and Response PDUs until the target moves on
+    ///
+    /// Returns all the parameters the target sent, and the stage it
+    /// moved to.
+    async fn login_stage(
+        &mut self,
+        parameters: &LoginParameters,
+        tsih: &mut u16,
+        current: u8,
+        next: u8,
+        keys: &str,
+    ) -> Result<(Vec<u8>, u8), IscsiError> {
+        let mut text = Vec::new();
+        let mut keys = keys.as_bytes();
+        let mut transit = LOGIN_TRANSIT;
+        for _ in 0..MAX_LOGIN_EXCHANGES {
+            let mut bhs = [0u8; BHS_LEN];
+            bhs[0] = IMMEDIATE | LOGIN_REQUEST;
+            bhs[1] = transit | (current << 2) | next;
+            bhs[8..14].copy_from_slice(&parameters.isid);
+            bhs[14..16].copy_from_slice(&tsih.to_be_bytes());
+            put_u32(&mut bhs, 16, self.next_itt);
+            put_u32(&mut bhs, 24, self.cmd_sn);
+            put_u32(&mut bhs, 28, self.exp_stat_sn);
+            self.send(&bhs, keys).await?;
+            keys = b"";
+
+            let response = self.receive().await?;
+            if response.opcode() != LOGIN_RESPONSE
+                || response.itt() != self.next_itt
+            {
+                return Err(IscsiError::Protocol);
+            }
+            let (class, detail) = (response.bhs[36], response.bhs[37]);
+            if class != 0 {
+                return Err(IscsiError::LoginFailed(class, detail));
+            }
+            *tsih = u16::from_be_bytes([response.bhs[14], response.bhs[15]]);
+            self.exp_stat_sn = response.stat_sn().wrapping_add(1);
+            self.cmd_sn = response.u32_at(28);
+            text.extend_from_slice(&response.data);
+
+            let flags = response.flags();
+            if (flags & LOGIN_CONTINUE) != 0 {
+                // The target has more parameters to send us; we mustn't
+                // transit until it's finished
+                transit = 0;
+                continue;
+            }
+            transit = LOGIN_TRANSIT;
+            if (flags & LOGIN_TRANSIT) != 0 {
+                return Ok((text, flags & 3));
+            }
+        }
+        Err(IscsiError::Protocol)
+    }
+
+    /// Issue a task-management function, and wait for it to complete
+    ///
+    /// As only one command is ever outstanding, and this function
+    /// isn't called while one is, this is mostly useful for
+    /// [`TaskManagementFunction::LogicalUnitReset`], for clearing
+    /// reservations or other state left behind by earlier sessions.
+    pub async fn task_management(
+        &mut self,
+        function: TaskManagementFunction,
+    ) -> Result<(), IscsiError> {
+        let itt = self.new_itt();
+        let mut bhs = header(
+            IMMEDIATE | TASK_MANAGEMENT_REQUEST,
+            FINAL | function as u8,
+            &self.lun,
+            itt,
+        );
+        put_u32(&mut bhs, 20, NO_TAG);
+        put_u32(&mut bhs, 24, self.cmd_sn);
+        put_u32(&mut bhs, 28, self.exp_stat_sn);
+        put_u32(&mut bhs, 32, self.cmd_sn);
+        self.send(&bhs, &[]).await?;
+
+        let response = self.receive_response(itt).await?;
+        if response.opcode() != TASK_MANAGEMENT_RESPONSE {
+            return Err(IscsiError::Protocol);
+        }
+        self.exp_stat_sn = response.stat_sn().wrapping_add(1);
+        self.sense = None;
+        match response.bhs[2] {
+            0 => Ok(()),
+            n => Err(IscsiError::TaskManagement(n)),
+        }
+    }
+
+    /// Check that the target is still there, and keep the connection alive
+    ///
+    /// This also answers any pings from the target received in the
+    /// meantime.
+    pub async fn ping(&mut self) -> Result<(), IscsiError> {
+        let itt = self.new_itt();
+        let mut bhs = header(IMMEDIATE | NOP_OUT, FINAL, &[0u8; 8], itt);
+        put_u32(&mut bhs, 20, NO_TAG);
+        put_u32(&mut bhs, 24, self.cmd_sn);
+        put_u32(&mut bhs, 28, self.exp_stat_sn);
+        self.send(&bhs, &[]).await?;
+
+        let response = self.receive_response(itt).await?;
+        if response.opcode() != NOP_IN {
+            return Err(IscsiError::Protocol);
+        }
+        // A NOP-In answering a NOP-Out uses up a StatSN, just as a
+        // response to a command does (RFC 7143 s11.19.5)
+        self.exp_stat_sn = response.stat_sn().wrapping_add(1);
+        Ok(())
+    }
+
+    /// Log out, ending the session and closing the connection
+    pub async fn logout(mut self) -> Result<(), IscsiError> {
+        let itt = self.new_itt();
+        // Reason code 0: close the session
+        let mut bhs =
+            header(IMMEDIATE | LOGOUT_REQUEST, FINAL, &[0u8; 8], itt);
+        put_u32(&mut bhs, 24, self.cmd_sn);
+        put_u32(&mut bhs, 28, self.exp_stat_sn);
+        self.send(&bhs, &[]).await?;
+
+        let response = self.receive_response(itt).await?;
+        if response.opcode() != LOGOUT_RESPONSE || response.bhs[2] != 0 {
+            return Err(IscsiError::Protocol);
+        }
+        self.stream.shutdown().await?;
+        Ok(())
+    }
+
+    fn new_itt(&mut self) -> u32 {
+        let itt = self.next_itt;
+        self.next_itt = match itt.wrapping_add(1) {
+            NO_TAG => 0,
+            n => n,
+        };
+        itt
+    }
+
+    async fn send(
+        &mut self,
+        bhs: &[u8; BHS_LEN],
+        data: &[u8],
+    ) -> Result<(), IscsiError> {
+        let padding = data.len().wrapping_neg() & 3;
+        let mut pdu = Vec::with_capacity(BHS_LEN + data.len() + padding);
+        pdu.extend_from_slice(bhs);
+        pdu[5..8].copy_from_slice(&(data.len() as u32).to_be_bytes()[1..]);
+        pdu.extend_from_slice(data);
+        pdu.resize(BHS_LEN + data.len() + padding, 0);
+        self.stream.write_all(&pdu).await?;
+        self.stream.flush().await?;
+        Ok(())
+    }
+
+    async fn receive(&mut self) -> Result<Pdu, IscsiError> {
+        let mut bhs = [0u8; BHS_LEN];
+        self.stream.read_exact(&mut bhs).await?;
+        let ahs_len = bhs[4] as usize * 4;
+        let data_len =
+            u32::from_be_bytes([0, bhs[5], bhs[6], bhs[7]]) as usize;
+        if data_len > MAX_RECV_DATA_SEGMENT_LENGTH {
+            return Err(IscsiError::Protocol);
+        }
+        let padding = data_len.wrapping_neg() & 3;
+        let mut data = vec![0u8; ahs_len + data_len + padding];
+        self.stream.read_exact(&mut data).await?;
+        data.truncate(ahs_len + data_len);
+        data.drain(..ahs_len);
+        Ok(Pdu { bhs, data })
+    }
+
+    /// Receive the next PDU which isn't housekeeping
+    ///
+    /// Pings from the target are answered, and asynchronous
+    /// messages are ignored; a Reject is returned as an error.
+    async fn receive_task_pdu(&mut self) -> Result<Pdu, IscsiError> {
+        loop {
+            let pdu = self.receive().await?;
+            match pdu.opcode() {
+                NOP_IN if pdu.itt() == NO_TAG => {
+                    self.answer_nop_in(&pdu).await?;
+                }
+                ASYNC_MESSAGE => {}
+                REJECT => return Err(IscsiError::Rejected(pdu.bhs[2])),
+                _ => return Ok(pdu),
+            }
+        }
+    }
+
+    /// Receive the response to an immediate request
+    async fn receive_response(&mut self, itt: u32) -> Result<Pdu, IscsiError> {
+        let pdu = self.receive_task_pdu().await?;
+        if pdu.itt() != itt {
+            return Err(IscsiError::Protocol);
+        }
+        Ok(pdu)
+    }
+
+    async fn answer_nop_in(&mut self, nop_in: &Pdu) -> Result<(), IscsiError> {
+        let ttt = nop_in.u32_at(20);
+        if ttt == NO_TAG {
+            // Just for information, no answer wanted
+            return Ok(());
+        }
+        let lun = nop_in.bhs[8..16].try_into().unwrap();
+        let mut bhs = header(IMMEDIATE | NOP_OUT, FINAL, &lun, NO_TAG);
+        put_u32(&mut bhs, 20, ttt);
+        put_u32(&mut bhs, 24, self.cmd_sn);
+        put_u32(&mut bhs, 28, self.exp_stat_sn);
+        self.send(&bhs, &nop_in.data).await
+    }
+
+    /// Answer a Ready To Transfer PDU with the data it asks for
+    async fn send_data_out(
+        &mut self,
+        r2t: &Pdu,
+        data: &[u8],
+    ) -> Result<usize, IscsiError> {
+        let ttt = r2t.u32_at(20);
+        let offset = r2t.u32_at(40) as usize;
+        let length = r2t.u32_at(44) as usize;
+        let data = offset
+            .checked_add(length)
+            .and_then(|end| data.get(offset..end))
+            .ok_or(IscsiError::Protocol)?;
+        let chunks = data.chunks(self.max_send_data_segment_length);
+        let count = chunks.len();
+        for (data_sn, chunk) in chunks.enumerate() {
+            let flags = if data_sn + 1 == count { FINAL } else { 0 };
+            let mut bhs = header(DATA_OUT, flags, &self.lun, r2t.itt());
+            put_u32(&mut bhs, 20, ttt);
+            put_u32(&mut bhs, 28, self.exp_stat_sn);
+            put_u32(&mut bhs, 36, data_sn as u32);
+            put_u32(
+                &mut bhs,
+                40,
+                (offset + data_sn * self.max_send_data_segment_length) as u32,
+            );
+            self.send(&bhs, chunk).await?;
+        }
+        Ok(length)
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> ScsiTransport for IscsiTransport<S> {
+    type Error = IscsiError;
+
+    /// Execute one SCSI command
+    ///
+    /// iSCSI targets send sense data along with a CHECK CONDITION
+    /// status, rather than waiting to be asked for it; it's kept, and
+    /// returned (without involving the target) if the next command
+    /// is REQUEST SENSE -- as it is, from
+    /// [`ScsiDevice`](crate::ScsiDevice).
+    async fn command(
+        &mut self,
+        cmd: &[u8],
+        data: DataPhase<'_>,
+    ) -> Result<usize, Error<Self::Error>> {
+        if cmd.is_empty() || cmd.len() > 16 {
+            return Err(Error::ProtocolError);
+        }
+        let mut data = data;
+        let sense = self.sense.take();
+        if let (Some(sense), REQUEST_SENSE, DataPhase::In(buf)) =
+            (sense, cmd[0], &mut data)
+        {
+            let n = sense.len().min(buf.len());
+            buf[..n].copy_from_slice(&sense[..n]);
+            buf[n..].fill(0);
+            return Ok(buf.len());
+        }
+
+        let (flags, length) = match data {
+            DataPhase::In(ref buf) => (COMMAND_READ, buf.len()),
+            DataPhase::Out(buf) => (COMMAND_WRITE, buf.len()),
+            DataPhase::None => (0, 0),
+        };
+        let itt = self.new_itt();
+        let mut bhs =
+            header(SCSI_COMMAND, FINAL | flags | SIMPLE_TASK, &self.lun, itt);
+        put_u32(&mut bhs, 20, length as u32);
+        put_u32(&mut bhs, 24, self.cmd_sn);
+        put_u32(&mut bhs, 28, self.exp_stat_sn);
+        bhs[32..32 + cmd.len()].copy_from_slice(cmd);
+        self.send(&bhs, &[]).await?;
+        self.cmd_sn = self.cmd_sn.wrapping_add(1);
+
+        let mut count = 0;
+        loop {
+            let pdu = self.receive_task_pdu().await?;
+            if pdu.itt() != itt {
+                return Err(Error::ProtocolError);
+            }
+            match (pdu.opcode(), &mut data) {
+                (DATA_IN, DataPhase::In(buf)) => {
+                    let offset = pdu.u32_at(40) as usize;
+                    let dest = offset
+                        .checked_add(pdu.data.len())
+                        .and_then(|end| buf.get_mut(offset..end))
+                        .ok_or(Error::ProtocolError)?;
+                    dest.copy_from_slice(&pdu.data);
+                    count += pdu.data.len();
+                    if (pdu.flags() & DATA_IN_STATUS) != 0 {
+                        self.exp_stat_sn = pdu.stat_sn().wrapping_add(1);
+                        return scsi_status(pdu.bhs[3], count);
+                    }
+                }
+                (R2T, DataPhase::Out(buf)) => {
+                    count += self.send_data_out(&pdu, buf).await?;
+                }
+                (SCSI_RESPONSE, _) => {
+                    self.exp_stat_sn = pdu.stat_sn().wrapping_add(1);
+                    if pdu.bhs[2] != 0 {
+                        return Err(
+                            IscsiError::TargetFailure(pdu.bhs[2]).into()
+                        );
+                    }
+                    // The data segment, if any, is a two-byte length
+                    // followed by that much sense data
+                    if let Some(len) = pdu.data.get(0..2) {
+                        let len = u16::from_be_bytes([len[0], len[1]]);
+                        self.sense = pdu
+                            .data
+                            .get(2..2 + len as usize)
+                            .map(<[u8]>::to_vec);
+                    }
+                    return scsi_status(pdu.bhs[3], count);
+                }
+                _ => return Err(Error::ProtocolError),
+            }
+        }
+    }
+
+    fn max_transfer_size(&self) -> usize {
+        MAX_TRANSFER_SIZE
+    }
+}
+
+#[cfg(test)]
+#[path = "tests/iscsi.rs"]
+mod tests;
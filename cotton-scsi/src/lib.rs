@@ -25,3 +25,23 @@ pub use async_block_device::{AsyncBlockDevice, DeviceInfo};
 /// Implementing AsyncBlockDevice in terms of ScsiDevice
 pub mod scsi_block_device;
 pub use scsi_block_device::ScsiBlockDevice;
+
+/// Making sure that writes to an AsyncBlockDevice reach the medium
+pub mod flush_guard;
+pub use flush_guard::FlushGuard;
+
+/// Guaranteeing that an AsyncBlockDevice is never written to
+pub mod read_only;
+pub use read_only::{ReadOnlyBlockDevice, ReadOnlyError};
+
+/// Reading and writing an AsyncBlockDevice as a stream of bytes
+#[cfg(feature = "embedded-io")]
+pub mod block_io;
+#[cfg(feature = "embedded-io")]
+pub use block_io::{BlockIo, BlockIoError};
+
+/// Accessing SCSI devices over the network, using iSCSI
+#[cfg(feature = "iscsi")]
+pub mod iscsi;
+#[cfg(feature = "iscsi")]
+pub use iscsi::{IscsiError, IscsiTransport, LoginParameters};
@@ -0,0 +1,81 @@
+use super::async_block_device::{AsyncBlockDevice, DeviceInfo};
+
+/// Errors which can arise from [`ReadOnlyBlockDevice`]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(any(feature = "std", feature = "embedded-io"), derive(Debug))]
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum ReadOnlyError<E> {
+    /// The underlying block device reported an error
+    Device(E),
+
+    /// A write was attempted, and refused
+    ReadOnly,
+}
+
+/// Wrapping an [`AsyncBlockDevice`] so that it can never be written to
+///
+/// Every [`write_blocks()`](AsyncBlockDevice::write_blocks) fails
+/// with [`ReadOnlyError::ReadOnly`] without reaching the underlying
+/// device, and [`flush()`](AsyncBlockDevice::flush) does nothing.
+///
+/// Unlike [`ScsiBlockDevice::set_read_only()`](crate::ScsiBlockDevice::set_read_only),
+/// this is enforced by the type system: the underlying device can't be
+/// got at mutably (nor taken back out), so code which is handed a
+/// `ReadOnlyBlockDevice` -- and everything built on top of it -- is
+/// known not to modify the medium.
+pub struct ReadOnlyBlockDevice<D: AsyncBlockDevice> {
+    device: D,
+}
+
+impl<D: AsyncBlockDevice> ReadOnlyBlockDevice<D> {
+    /// Wrap a block device
+    pub fn new(device: D) -> Self {
+        Self { device }
+    }
+
+    /// The underlying block device
+    pub fn get_ref(&self) -> &D {
+        &self.device
+    }
+}
+
+impl<D: AsyncBlockDevice> AsyncBlockDevice for ReadOnlyBlockDevice<D> {
+    type E = ReadOnlyError<D::E>;
+
+    async fn device_info(&mut self) -> Result<DeviceInfo, Self::E> {
+        self.device
+            .device_info()
+            .await
+            .map_err(ReadOnlyError::Device)
+    }
+
+    async fn read_blocks(
+        &mut self,
+        offset: u64,
+        count: u32,
+        data: &mut [u8],
+    ) -> Result<(), Self::E> {
+        self.device
+            .read_blocks(offset, count, data)
+            .await
+            .map_err(ReadOnlyError::Device)
+    }
+
+    async fn write_blocks(
+        &mut self,
+        _offset: u64,
+        _count: u32,
+        _data: &[u8],
+    ) -> Result<(), Self::E> {
+        Err(ReadOnlyError::ReadOnly)
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::E> {
+        // Nothing can have been written, so there's nothing to flush
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+#[path = "tests/read_only.rs"]
+mod tests;
@@ -2,19 +2,74 @@ use super::async_block_device::{AsyncBlockDevice, DeviceInfo};
 use super::debug;
 use super::scsi_device::ScsiDevice;
 use super::scsi_transport::{Error, ScsiError, ScsiTransport};
+use futures::future::join;
 
 /// Implementing [`AsyncBlockDevice`] in terms of [`ScsiDevice`]
+///
+/// The logical block size isn't assumed to be 512 bytes: devices
+/// with 2048-byte blocks (optical drives) or 4096-byte blocks ("4Kn"
+/// disks, and some USB enclosures) work too. Once
+/// [`device_info()`](AsyncBlockDevice::device_info) has been called,
+/// the block size it reported is used to check (and split up) every
+/// read and write; before that, the block size is inferred from the
+/// size of each buffer.
 pub struct ScsiBlockDevice<T: ScsiTransport> {
     /// The underlying SCSI block device
     ///
     /// Made "pub" so that additional SCSI commands can be issued if need be.
     pub scsi: ScsiDevice<T>,
+
+    /// The logical block size, as reported by READ CAPACITY
+    block_size: Option<u32>,
+
+    /// Whether writes are refused without reaching the device
+    read_only: bool,
 }
 
 impl<T: ScsiTransport> ScsiBlockDevice<T> {
     /// Construct a new block device from a generic SCSI device
     pub fn new(scsi: ScsiDevice<T>) -> Self {
-        Self { scsi }
+        Self {
+            scsi,
+            block_size: None,
+            read_only: false,
+        }
+    }
+
+    /// Construct a new block device which refuses all writes
+    ///
+    /// See [`set_read_only()`](Self::set_read_only).
+    pub fn new_read_only(scsi: ScsiDevice<T>) -> Self {
+        Self {
+            read_only: true,
+            ..Self::new(scsi)
+        }
+    }
+
+    /// Refuse (or stop refusing) all writes
+    ///
+    /// While read-only, [`write_blocks()`](AsyncBlockDevice::write_blocks)
+    /// fails with `ScsiError::DataProtect` without sending anything to
+    /// the device, whether or not the device itself is write-protected.
+    ///
+    /// Note that the `scsi` field can still be used to send arbitrary
+    /// commands; for a guarantee that nothing using a block device can
+    /// write to it, see [`ReadOnlyBlockDevice`](crate::ReadOnlyBlockDevice).
+    pub fn set_read_only(&mut self, read_only: bool) {
+        self.read_only = read_only;
+    }
+
+    /// Are writes being refused?
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// The logical block size, if known
+    ///
+    /// This is `None` until
+    /// [`device_info()`](AsyncBlockDevice::device_info) has succeeded.
+    pub fn block_size(&self) -> Option<u32> {
+        self.block_size
     }
 
     /// For testing: query supported SCSI commands on this device
@@ -46,6 +101,143 @@ impl<T: ScsiTransport> ScsiBlockDevice<T> {
         }
         Ok(())
     }
+
+    /// How many blocks of this size fit in one transfer
+    fn blocks_per_transfer(&self, block_size: usize) -> u32 {
+        (self.scsi.max_transfer_size() / block_size)
+            .clamp(1, u32::MAX as usize) as u32
+    }
+
+    /// The block size to use for a transfer of `count` blocks to or
+    /// from a buffer of `len` bytes, and the number of bytes to transfer
+    ///
+    /// If the block size is known, a too-small buffer is an error
+    /// (and any excess is ignored); if not, it's inferred from the
+    /// buffer size.
+    fn transfer_size(
+        &self,
+        count: u32,
+        len: usize,
+    ) -> Result<(usize, usize), Error<T::Error>> {
+        match self.block_size {
+            Some(block_size) => {
+                let block_size = block_size as usize;
+                let n = (count as usize)
+                    .checked_mul(block_size)
+                    .filter(|n| *n <= len)
+                    .ok_or(Error::ProtocolError)?;
+                Ok((block_size, n))
+            }
+            None => {
+                Ok((len.checked_div(count as usize).unwrap_or_default(), len))
+            }
+        }
+    }
+
+    /// Read blocks using a single SCSI command
+    async fn read_once(
+        &mut self,
+        offset: u64,
+        count: u32,
+        data: &mut [u8],
+    ) -> Result<(), Error<T::Error>> {
+        let end = offset
+            .checked_add(count as u64)
+            .ok_or(Error::Scsi(ScsiError::LogicalBlockAddressOutOfRange))?;
+        let sz = if end < u32::MAX as u64 && count < u16::MAX as u32 {
+            self.scsi.read_10(offset as u32, count as u16, data).await?
+        } else {
+            self.scsi.read_16(offset, count, data).await?
+        };
+        if sz < data.len() {
+            return Err(Error::ProtocolError);
+        }
+        Ok(())
+    }
+
+    /// Write blocks using a single SCSI command
+    async fn write_once(
+        &mut self,
+        offset: u64,
+        count: u32,
+        data: &[u8],
+    ) -> Result<(), Error<T::Error>> {
+        let end = offset
+            .checked_add(count as u64)
+            .ok_or(Error::Scsi(ScsiError::LogicalBlockAddressOutOfRange))?;
+        if end < u32::MAX as u64 && count < u16::MAX as u32 {
+            self.scsi
+                .write_10(offset as u32, count as u16, data)
+                .await?;
+        } else {
+            self.scsi.write_16(offset, count, data).await?;
+        }
+        Ok(())
+    }
+
+    /// Read blocks, processing each chunk while the next one is read
+    ///
+    /// The blocks are read in chunks, alternately into each half of
+    /// `buffer`, and `process` is called with the starting block
+    /// number and the contents of each chunk in turn. Each chunk is
+    /// no larger than half the buffer, or than the transport's
+    /// [maximum transfer size](ScsiTransport::max_transfer_size).
+    ///
+    /// The command to read each chunk is issued *before* the previous
+    /// chunk is processed, so that the device can be fetching the
+    /// next chunk while the previous one is being dealt with.
+    ///
+    /// Returns `Error::ProtocolError` if `buffer` is smaller than
+    /// two blocks.
+    pub async fn read_blocks_pipelined<F: FnMut(u64, &[u8])>(
+        &mut self,
+        offset: u64,
+        count: u32,
+        block_size: usize,
+        buffer: &mut [u8],
+        mut process: F,
+    ) -> Result<(), Error<T::Error>> {
+        let half = buffer.len() / 2;
+        if block_size == 0 || half < block_size {
+            return Err(Error::ProtocolError);
+        }
+        if count == 0 {
+            return Ok(());
+        }
+        let chunk_blocks = self
+            .blocks_per_transfer(block_size)
+            .min((half / block_size) as u32);
+        let (mut current, mut next) = buffer.split_at_mut(half);
+
+        let mut block = offset;
+        let mut remaining = count;
+        let mut n = chunk_blocks.min(remaining);
+        self.read_once(block, n, &mut current[0..(n as usize * block_size)])
+            .await?;
+
+        loop {
+            remaining -= n;
+            if remaining == 0 {
+                process(block, &current[0..(n as usize * block_size)]);
+                return Ok(());
+            }
+            let next_block = block + n as u64;
+            let next_n = chunk_blocks.min(remaining);
+            let read = self.read_once(
+                next_block,
+                next_n,
+                &mut next[0..(next_n as usize * block_size)],
+            );
+            let done = &current[0..(n as usize * block_size)];
+            // join() polls the read first, so the command is issued
+            // before the processing starts
+            let (rc, ()) = join(read, async { process(block, done) }).await;
+            rc?;
+            core::mem::swap(&mut current, &mut next);
+            block = next_block;
+            n = next_n;
+        }
+    }
 }
 
 impl<T: ScsiTransport> AsyncBlockDevice for ScsiBlockDevice<T> {
@@ -61,6 +253,10 @@ impl<T: ScsiTransport> AsyncBlockDevice for ScsiBlockDevice<T> {
             }
         };
 
+        if block_size == 0 {
+            return Err(Error::ProtocolError);
+        }
+        self.block_size = Some(block_size);
         Ok(DeviceInfo { blocks, block_size })
     }
 
@@ -70,16 +266,25 @@ impl<T: ScsiTransport> AsyncBlockDevice for ScsiBlockDevice<T> {
         count: u32,
         data: &mut [u8],
     ) -> Result<(), Self::E> {
-        let end = offset
+        offset
             .checked_add(count as u64)
             .ok_or(Error::Scsi(ScsiError::LogicalBlockAddressOutOfRange))?;
-        let sz = if end < u32::MAX as u64 && count < u16::MAX as u32 {
-            self.scsi.read_10(offset as u32, count as u16, data).await?
-        } else {
-            self.scsi.read_16(offset, count, data).await?
-        };
-        if sz < data.len() {
-            return Err(Error::ProtocolError);
+        let (block_size, len) = self.transfer_size(count, data.len())?;
+        let data = &mut data[..len];
+        if block_size == 0 {
+            return self.read_once(offset, count, data).await;
+        }
+        // Requests too large for the transport are split up
+        let chunk_blocks = self.blocks_per_transfer(block_size);
+        let mut block = offset;
+        let mut remaining = count;
+        let mut chunks = data.chunks_mut(chunk_blocks as usize * block_size);
+        while remaining > 0 {
+            let n = chunk_blocks.min(remaining);
+            let chunk = chunks.next().unwrap_or_default();
+            self.read_once(block, n, chunk).await?;
+            block += n as u64;
+            remaining -= n;
         }
         Ok(())
     }
@@ -90,18 +295,43 @@ impl<T: ScsiTransport> AsyncBlockDevice for ScsiBlockDevice<T> {
         count: u32,
         data: &[u8],
     ) -> Result<(), Self::E> {
-        let end = offset
+        if self.read_only {
+            return Err(Error::Scsi(ScsiError::DataProtect));
+        }
+        offset
             .checked_add(count as u64)
             .ok_or(Error::Scsi(ScsiError::LogicalBlockAddressOutOfRange))?;
-        if end < u32::MAX as u64 && count < u16::MAX as u32 {
-            self.scsi
-                .write_10(offset as u32, count as u16, data)
-                .await?;
-        } else {
-            self.scsi.write_16(offset, count, data).await?;
+        let (block_size, len) = self.transfer_size(count, data.len())?;
+        let data = &data[..len];
+        if block_size == 0 {
+            return self.write_once(offset, count, data).await;
+        }
+        let chunk_blocks = self.blocks_per_transfer(block_size);
+        let mut block = offset;
+        let mut remaining = count;
+        let mut chunks = data.chunks(chunk_blocks as usize * block_size);
+        while remaining > 0 {
+            let n = chunk_blocks.min(remaining);
+            let chunk = chunks.next().unwrap_or_default();
+            self.write_once(block, n, chunk).await?;
+            block += n as u64;
+            remaining -= n;
         }
         Ok(())
     }
+
+    async fn flush(&mut self) -> Result<(), Self::E> {
+        // SYNCHRONIZE CACHE (10) with a count of zero covers the whole
+        // device, however large. Devices with no write cache often
+        // don't implement it -- but then there's nothing to flush.
+        match self.scsi.synchronize_cache_10(0, 0).await {
+            Err(Error::Scsi(
+                ScsiError::InvalidCommandOperationCode
+                | ScsiError::IllegalRequest,
+            )) => Ok(()),
+            rc => rc,
+        }
+    }
 }
 
 #[cfg(all(test, feature = "std"))]
@@ -262,6 +262,74 @@ unsafe impl bytemuck::Zeroable for TestUnitReady {}
 // SAFETY: no padding, no disallowed bit patterns
 unsafe impl bytemuck::Pod for TestUnitReady {}
 
+/// SYNCHRONIZE CACHE (10)
+/// Seagate SCSI Commands Reference Manual s3.51
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone)]
+#[repr(C)]
+struct SynchronizeCache10 {
+    operation_code: u8,
+    flags: u8,
+    lba_be: [u8; 4],
+    group: u8,
+    number_of_blocks_be: [u8; 2],
+    control: u8,
+}
+
+impl SynchronizeCache10 {
+    fn new(lba: u32, count: u16) -> Self {
+        assert!(core::mem::size_of::<Self>() == 10);
+        Self {
+            operation_code: 0x35,
+            flags: 0,
+            lba_be: lba.to_be_bytes(),
+            group: 0,
+            number_of_blocks_be: count.to_be_bytes(),
+            control: 0,
+        }
+    }
+}
+
+// SAFETY: all fields zeroable
+unsafe impl bytemuck::Zeroable for SynchronizeCache10 {}
+// SAFETY: no padding, no disallowed bit patterns
+unsafe impl bytemuck::Pod for SynchronizeCache10 {}
+
+/// SYNCHRONIZE CACHE (16)
+/// Seagate SCSI Commands Reference Manual s3.52
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone)]
+#[repr(C)]
+struct SynchronizeCache16 {
+    operation_code: u8,
+    flags: u8,
+    lba_be: [u8; 8],
+    number_of_blocks_be: [u8; 4],
+    group: u8,
+    control: u8,
+}
+
+impl SynchronizeCache16 {
+    fn new(lba: u64, count: u32) -> Self {
+        assert!(core::mem::size_of::<Self>() == 16);
+        Self {
+            operation_code: 0x91,
+            flags: 0,
+            lba_be: lba.to_be_bytes(),
+            number_of_blocks_be: count.to_be_bytes(),
+            group: 0,
+            control: 0,
+        }
+    }
+}
+
+// SAFETY: all fields zeroable
+unsafe impl bytemuck::Zeroable for SynchronizeCache16 {}
+// SAFETY: no padding, no disallowed bit patterns
+unsafe impl bytemuck::Pod for SynchronizeCache16 {}
+
 /// REQUEST SENSE
 /// Seagate SCSI Commands Reference Manual s3.37
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
@@ -585,6 +653,13 @@ impl<T: ScsiTransport> ScsiDevice<T> {
         Self { transport }
     }
 
+    /// The largest data phase, in bytes, that a single command may have
+    ///
+    /// See [`ScsiTransport::max_transfer_size()`].
+    pub fn max_transfer_size(&self) -> usize {
+        self.transport.max_transfer_size()
+    }
+
     async fn try_upgrade_error(
         &mut self,
         e: Error<T::Error>,
@@ -915,6 +990,50 @@ impl<T: ScsiTransport> ScsiDevice<T> {
         }
         rc
     }
+
+    /// Flush the device's write cache to the medium, 32-bit LBA version
+    ///
+    /// Writes are only guaranteed to be on non-volatile storage once
+    /// this has completed successfully. A `count` of zero means "from
+    /// `start_block` to the end of the device".
+    ///
+    /// Devices with no write cache may report
+    /// `ScsiError::InvalidCommandOperationCode` instead.
+    pub async fn synchronize_cache_10(
+        &mut self,
+        start_block: u32,
+        count: u16,
+    ) -> Result<(), Error<T::Error>> {
+        let cmd = SynchronizeCache10::new(start_block, count);
+        let rc = self
+            .transport
+            .command(bytemuck::bytes_of(&cmd), DataPhase::None)
+            .await;
+        match rc {
+            Err(e) => Err(self.try_upgrade_error(e).await),
+            Ok(_) => Ok(()),
+        }
+    }
+
+    /// Flush the device's write cache to the medium, 64-bit LBA version
+    ///
+    /// As for [`ScsiDevice::synchronize_cache_10()`], but able to
+    /// address blocks beyond the first 2TB.
+    pub async fn synchronize_cache_16(
+        &mut self,
+        start_block: u64,
+        count: u32,
+    ) -> Result<(), Error<T::Error>> {
+        let cmd = SynchronizeCache16::new(start_block, count);
+        let rc = self
+            .transport
+            .command(bytemuck::bytes_of(&cmd), DataPhase::None)
+            .await;
+        match rc {
+            Err(e) => Err(self.try_upgrade_error(e).await),
+            Ok(_) => Ok(()),
+        }
+    }
 }
 
 #[cfg(all(test, feature = "std"))]
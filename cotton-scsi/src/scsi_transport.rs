@@ -17,6 +17,12 @@ pub enum DataPhase<'a> {
     None,
 }
 
+/// The largest transfer assumed to be acceptable, if a transport doesn't say
+///
+/// This is the limit many desktop operating systems apply to USB mass
+/// storage, so in practice all devices are expected to cope with it.
+pub const DEFAULT_MAX_TRANSFER_SIZE: usize = 65536;
+
 /// An abstract SCSI communications channel to a single device
 ///
 /// An actual SCSI bus would implement one `ScsiTransport` for each
@@ -51,11 +57,22 @@ pub trait ScsiTransport {
         cmd: &[u8],
         data: DataPhase,
     ) -> impl Future<Output = Result<usize, Error<Self::Error>>>;
+
+    /// The largest data phase, in bytes, that a single command may have
+    ///
+    /// Larger reads and writes issued through
+    /// [`ScsiBlockDevice`](crate::scsi_block_device::ScsiBlockDevice)
+    /// are split into several commands. Transports which know their
+    /// own limits should override the (conservative) default of
+    /// [`DEFAULT_MAX_TRANSFER_SIZE`].
+    fn max_transfer_size(&self) -> usize {
+        DEFAULT_MAX_TRANSFER_SIZE
+    }
 }
 
 /// Errors which can arise during a SCSI command
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
-#[cfg_attr(feature = "std", derive(Debug))]
+#[cfg_attr(any(feature = "std", feature = "embedded-io"), derive(Debug))]
 #[derive(Copy, Clone, PartialEq, Eq)]
 #[non_exhaustive]
 pub enum Error<T: PartialEq + Eq> {
@@ -84,7 +101,7 @@ pub enum Error<T: PartialEq + Eq> {
 /// will never see `ScsiError::Overheat` -- but some are reasonable and
 /// common.
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
-#[cfg_attr(feature = "std", derive(Debug))]
+#[cfg_attr(any(feature = "std", feature = "embedded-io"), derive(Debug))]
 #[derive(Copy, Clone, PartialEq, Eq)]
 #[allow(missing_docs)]
 #[non_exhaustive]
@@ -0,0 +1,383 @@
+use super::*;
+use crate::scsi_device::tests::NoOpWaker;
+use crate::ReadOnlyBlockDevice;
+use embedded_io_async::Error as _;
+use futures::Future;
+use std::pin::pin;
+use std::sync::Arc;
+use std::task::{Poll, Waker};
+
+/// An in-memory block device which records what's done to it
+struct RamDisk {
+    data: Vec<u8>,
+    block_size: u32,
+    reads: Vec<(u64, u32)>,
+    writes: Vec<(u64, u32)>,
+    flushes: usize,
+    fail: Option<ErrorKind>,
+}
+
+impl RamDisk {
+    fn new(blocks: usize, block_size: u32) -> Self {
+        Self {
+            data: (0..(blocks * block_size as usize))
+                .map(|i| i as u8)
+                .collect(),
+            block_size,
+            reads: Vec::new(),
+            writes: Vec::new(),
+            flushes: 0,
+            fail: None,
+        }
+    }
+
+    fn range(&self, offset: u64, count: u32) -> core::ops::Range<usize> {
+        let bs = self.block_size as usize;
+        (offset as usize * bs)..((offset as usize + count as usize) * bs)
+    }
+}
+
+impl AsyncBlockDevice for RamDisk {
+    type E = ErrorKind;
+
+    async fn device_info(&mut self) -> Result<DeviceInfo, Self::E> {
+        Ok(DeviceInfo {
+            blocks: (self.data.len() / self.block_size as usize) as u64,
+            block_size: self.block_size,
+        })
+    }
+
+    async fn read_blocks(
+        &mut self,
+        offset: u64,
+        count: u32,
+        data: &mut [u8],
+    ) -> Result<(), Self::E> {
+        if let Some(e) = self.fail {
+            return Err(e);
+        }
+        self.reads.push((offset, count));
+        let range = self.range(offset, count);
+        data[..range.len()].copy_from_slice(&self.data[range]);
+        Ok(())
+    }
+
+    async fn write_blocks(
+        &mut self,
+        offset: u64,
+        count: u32,
+        data: &[u8],
+    ) -> Result<(), Self::E> {
+        if let Some(e) = self.fail {
+            return Err(e);
+        }
+        self.writes.push((offset, count));
+        let range = self.range(offset, count);
+        let n = range.len();
+        self.data[range].copy_from_slice(&data[..n]);
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::E> {
+        self.flushes += 1;
+        Ok(())
+    }
+}
+
+fn run<F: Future>(fut: F) -> F::Output {
+    let w = Waker::from(Arc::new(NoOpWaker));
+    let mut c = core::task::Context::from_waker(&w);
+    let Poll::Ready(r) = pin!(fut).poll(&mut c) else {
+        panic!("future pended");
+    };
+    r
+}
+
+fn new_io() -> BlockIo<RamDisk> {
+    BlockIo::new(RamDisk::new(4, 512))
+}
+
+#[test]
+fn read_whole_blocks() {
+    let mut io = new_io();
+    let mut buf = [0u8; 1024];
+    assert_eq!(run(io.read(&mut buf)), Ok(1024));
+    assert_eq!(buf[..], io.device.data[..1024]);
+    assert_eq!(io.device.reads, [(0, 2)]);
+    assert_eq!(io.position(), 1024);
+}
+
+#[test]
+fn read_partial_block_is_buffered() {
+    let mut io = new_io();
+    let mut buf = [0u8; 50];
+    assert_eq!(run(io.seek(SeekFrom::Start(100))), Ok(100));
+    assert_eq!(run(io.read(&mut buf)), Ok(50));
+    assert_eq!(buf[..], io.device.data[100..150]);
+    assert_eq!(run(io.read(&mut buf)), Ok(50));
+    assert_eq!(buf[..], io.device.data[150..200]);
+    assert_eq!(io.device.reads, [(0, 1)]);
+}
+
+#[test]
+fn read_stops_at_block_boundary() {
+    let mut io = new_io();
+    let mut buf = [0u8; 100];
+    run(io.seek(SeekFrom::Start(500))).unwrap();
+    assert_eq!(run(io.read(&mut buf)), Ok(12));
+    assert_eq!(buf[..12], io.device.data[500..512]);
+
+    run(io.seek(SeekFrom::Start(500))).unwrap();
+    run(io.read_exact(&mut buf)).unwrap();
+    assert_eq!(buf[..], io.device.data[500..600]);
+    assert_eq!(io.device.reads, [(0, 1), (1, 1)]);
+}
+
+#[test]
+fn read_at_end() {
+    let mut io = new_io();
+    let mut buf = [0u8; 100];
+    run(io.seek(SeekFrom::End(-10))).unwrap();
+    assert_eq!(run(io.read(&mut buf)), Ok(10));
+    assert_eq!(buf[..10], io.device.data[2038..]);
+    assert_eq!(run(io.read(&mut buf)), Ok(0));
+    run(io.seek(SeekFrom::End(1000))).unwrap();
+    assert_eq!(run(io.read(&mut buf)), Ok(0));
+}
+
+#[test]
+fn read_nothing() {
+    let mut io = new_io();
+    assert_eq!(run(io.read(&mut [])), Ok(0));
+    assert!(io.device.reads.is_empty());
+}
+
+#[test]
+fn write_partial_block_is_buffered() {
+    let mut io = new_io();
+    run(io.seek(SeekFrom::Start(5))).unwrap();
+    assert_eq!(run(io.write(&[0xAA; 10])), Ok(10));
+    assert!(io.is_dirty());
+    assert_eq!(io.device.reads, [(0, 1)]);
+    assert!(io.device.writes.is_empty());
+    assert_eq!(io.device.data[5], 5);
+
+    run(io.flush()).unwrap();
+    assert!(!io.is_dirty());
+    assert_eq!(io.device.writes, [(0, 1)]);
+    assert_eq!(io.device.flushes, 1);
+    assert_eq!(io.device.data[4], 4);
+    assert_eq!(io.device.data[5..15], [0xAA; 10]);
+    assert_eq!(io.device.data[15], 15);
+}
+
+#[test]
+fn write_spanning_blocks() {
+    let mut io = new_io();
+    run(io.seek(SeekFrom::Start(500))).unwrap();
+    run(io.write_all(&[0x55; 1100])).unwrap();
+    run(io.flush()).unwrap();
+    assert_eq!(io.position(), 1600);
+    assert_eq!(io.device.data[499], 499u32 as u8);
+    assert!(io.device.data[500..1600].iter().all(|b| *b == 0x55));
+    assert_eq!(io.device.data[1600], 1600u32 as u8);
+    // Head and tail read-modify-written; middle written directly
+    assert_eq!(io.device.reads, [(0, 1), (3, 1)]);
+    assert_eq!(io.device.writes, [(1, 2), (0, 1), (3, 1)]);
+}
+
+#[test]
+fn write_spanning_blocks_at_each_size() {
+    for bs in [512, 2048, 4096] {
+        let mut io: BlockIo<RamDisk, 4096> =
+            BlockIo::new(RamDisk::new(4, bs as u32));
+        run(io.seek(SeekFrom::Start(bs as u64 - 12))).unwrap();
+        run(io.write_all(&vec![0x55; bs + 24])).unwrap();
+        run(io.flush()).unwrap();
+        assert_eq!(io.position(), 2 * bs as u64 + 12);
+        assert_eq!(io.device.data[bs - 13], (bs - 13) as u8);
+        assert!(io.device.data[(bs - 12)..(2 * bs + 12)]
+            .iter()
+            .all(|b| *b == 0x55));
+        assert_eq!(io.device.data[2 * bs + 12], (2 * bs + 12) as u8);
+        assert_eq!(io.device.reads, [(0, 1), (2, 1)]);
+        assert_eq!(io.device.writes, [(1, 1), (0, 1), (2, 1)]);
+
+        let mut buf = [0u8; 16];
+        run(io.seek(SeekFrom::End(-8))).unwrap();
+        assert_eq!(run(io.read(&mut buf)), Ok(8));
+        assert_eq!(buf[..8], io.device.data[(4 * bs - 8)..]);
+    }
+}
+
+#[test]
+fn whole_block_write_supersedes_buffer() {
+    let mut io = new_io();
+    run(io.write(&[1u8; 10])).unwrap();
+    assert!(io.is_dirty());
+    run(io.seek(SeekFrom::Start(0))).unwrap();
+    assert_eq!(run(io.write(&[2u8; 512])), Ok(512));
+    assert!(!io.is_dirty());
+    run(io.flush()).unwrap();
+    assert_eq!(io.device.writes, [(0, 1)]);
+    assert_eq!(io.device.data[..512], [2u8; 512]);
+}
+
+#[test]
+fn whole_block_read_sees_buffered_write() {
+    let mut io = new_io();
+    run(io.seek(SeekFrom::Start(520))).unwrap();
+    run(io.write(&[7u8; 4])).unwrap();
+    run(io.seek(SeekFrom::Start(0))).unwrap();
+    let mut buf = [0u8; 2048];
+    assert_eq!(run(io.read(&mut buf)), Ok(2048));
+    assert_eq!(buf[520..524], [7u8; 4]);
+    assert_eq!(io.device.writes, [(1, 1)]);
+}
+
+#[test]
+fn changing_block_writes_back() {
+    let mut io = new_io();
+    run(io.write(&[9u8; 4])).unwrap();
+    run(io.seek(SeekFrom::Start(1030))).unwrap();
+    let mut buf = [0u8; 2];
+    run(io.read(&mut buf)).unwrap();
+    assert_eq!(io.device.writes, [(0, 1)]);
+    assert_eq!(io.device.reads, [(0, 1), (2, 1)]);
+    assert!(!io.is_dirty());
+}
+
+#[test]
+fn write_at_end() {
+    let mut io = new_io();
+    run(io.seek(SeekFrom::End(0))).unwrap();
+    assert_eq!(run(io.write(&[0u8; 4])), Ok(0));
+    assert!(io.device.writes.is_empty());
+}
+
+#[test]
+fn seek() {
+    let mut io = new_io();
+    assert_eq!(run(io.seek(SeekFrom::Start(10))), Ok(10));
+    assert_eq!(run(io.seek(SeekFrom::Current(-4))), Ok(6));
+    assert_eq!(run(io.seek(SeekFrom::Current(4))), Ok(10));
+    assert_eq!(run(io.seek(SeekFrom::End(-48))), Ok(2000));
+    assert_eq!(
+        run(io.seek(SeekFrom::Current(-2001))),
+        Err(BlockIoError::InvalidSeek)
+    );
+    assert_eq!(
+        run(io.seek(SeekFrom::End(-2049))),
+        Err(BlockIoError::InvalidSeek)
+    );
+    assert_eq!(io.position(), 2000);
+    assert_eq!(run(io.stream_position()), Ok(2000));
+}
+
+#[test]
+fn block_too_large() {
+    let mut io: BlockIo<RamDisk> = BlockIo::new(RamDisk::new(2, 4096));
+    let mut buf = [0u8; 16];
+    assert_eq!(
+        run(io.read(&mut buf)),
+        Err(BlockIoError::UnsupportedBlockSize)
+    );
+    assert_eq!(
+        BlockIoError::<ErrorKind>::UnsupportedBlockSize.kind(),
+        ErrorKind::Unsupported
+    );
+
+    let mut io: BlockIo<RamDisk, 4096> = BlockIo::new(RamDisk::new(2, 4096));
+    assert_eq!(run(io.read(&mut buf)), Ok(16));
+}
+
+#[test]
+fn device_errors() {
+    let mut disk = RamDisk::new(4, 512);
+    disk.fail = Some(ErrorKind::TimedOut);
+    let mut io: BlockIo<RamDisk> = BlockIo::new(disk);
+    let mut buf = [0u8; 16];
+    let e = run(io.read(&mut buf)).unwrap_err();
+    assert_eq!(e, BlockIoError::Device(ErrorKind::TimedOut));
+    assert_eq!(e.kind(), ErrorKind::TimedOut);
+    assert_eq!(io.position(), 0);
+    assert_eq!(
+        run(io.write(&[0u8; 512])),
+        Err(BlockIoError::Device(ErrorKind::TimedOut))
+    );
+}
+
+#[test]
+fn into_inner() {
+    let mut io = new_io();
+    run(io.write(&[0u8; 4])).unwrap();
+    let disk = io.into_inner();
+    assert!(disk.writes.is_empty());
+}
+
+#[test]
+fn read_only_device() {
+    let mut io: BlockIo<_> =
+        BlockIo::new(ReadOnlyBlockDevice::new(RamDisk::new(4, 512)));
+    let mut buf = [0u8; 4];
+    assert_eq!(run(io.read(&mut buf)), Ok(4));
+    assert_eq!(buf, [0, 1, 2, 3]);
+    run(io.seek(SeekFrom::Start(512))).unwrap();
+    let e = run(io.write(&[0u8; 512])).unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::PermissionDenied);
+    assert!(io.into_inner().get_ref().writes.is_empty());
+}
+
+#[test]
+fn read_only_error_kinds() {
+    type E = ReadOnlyError<Error<u8>>;
+    assert_eq!(E::ReadOnly.kind(), ErrorKind::PermissionDenied);
+    assert_eq!(
+        E::Device(Error::ProtocolError).kind(),
+        ErrorKind::InvalidData
+    );
+}
+
+#[test]
+fn scsi_error_kinds() {
+    type E = Error<u8>;
+    assert_eq!(E::CommandFailed.kind(), ErrorKind::Other);
+    assert_eq!(E::ProtocolError.kind(), ErrorKind::InvalidData);
+    assert_eq!(E::Transport(3).kind(), ErrorKind::Other);
+    assert_eq!(
+        E::Scsi(ScsiError::DataProtect).kind(),
+        ErrorKind::PermissionDenied
+    );
+    assert_eq!(
+        E::Scsi(ScsiError::LogicalBlockAddressOutOfRange).kind(),
+        ErrorKind::InvalidInput
+    );
+    assert_eq!(
+        E::Scsi(ScsiError::InvalidCommandOperationCode).kind(),
+        ErrorKind::Unsupported
+    );
+    assert_eq!(
+        E::Scsi(ScsiError::UnrecoveredReadError).kind(),
+        ErrorKind::InvalidData
+    );
+    assert_eq!(E::Scsi(ScsiError::NotReady).kind(), ErrorKind::NotConnected);
+    assert_eq!(
+        E::Scsi(ScsiError::UnitAttention).kind(),
+        ErrorKind::Interrupted
+    );
+    assert_eq!(E::Scsi(ScsiError::Overheat).kind(), ErrorKind::Other);
+}
+
+#[test]
+fn std_io_errors() {
+    let e: std::io::Error = Error::<u8>::Scsi(ScsiError::DataProtect).into();
+    assert_eq!(e.kind(), std::io::ErrorKind::PermissionDenied);
+    assert_eq!(e.to_string(), "Scsi(DataProtect)");
+
+    let e: std::io::Error =
+        BlockIoError::Device(Error::<u8>::ProtocolError).into();
+    assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+
+    let e: std::io::Error = BlockIoError::<ErrorKind>::InvalidSeek.into();
+    assert_eq!(e.kind(), std::io::ErrorKind::InvalidInput);
+}
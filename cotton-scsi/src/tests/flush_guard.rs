@@ -0,0 +1,138 @@
+use super::*;
+use crate::scsi_device::tests::{
+    command_nodata_fails, command_nodata_ok, command_out_ok, ContextExtras,
+    ExtraExpectations, MockScsiTransport, MockScsiTransportInner, NoOpWaker,
+};
+use crate::{ScsiBlockDevice, ScsiDevice};
+use futures::Future;
+use std::pin::pin;
+use std::sync::Arc;
+use std::task::{Poll, Waker};
+
+struct Fixture<'a> {
+    c: &'a mut core::task::Context<'a>,
+    d: FlushGuard<ScsiBlockDevice<MockScsiTransport>>,
+}
+
+fn do_test<
+    SetupFn: FnMut(&mut MockScsiTransportInner),
+    TestFn: FnMut(Fixture),
+>(
+    mut setup: SetupFn,
+    mut test: TestFn,
+) {
+    let w = Waker::from(Arc::new(NoOpWaker));
+    let mut c = core::task::Context::from_waker(&w);
+
+    let mut hc = MockScsiTransport::new();
+
+    setup(&mut hc.inner);
+
+    let f = Fixture {
+        c: &mut c,
+        d: FlushGuard::new(ScsiBlockDevice::new(ScsiDevice::new(hc))),
+    };
+
+    test(f);
+}
+
+fn expect_write(t: &mut MockScsiTransportInner) {
+    t.expect_command_out()
+        .times(1)
+        .withf(|c, _| c[0] == 0x2A)
+        .returning(command_out_ok);
+}
+
+#[test]
+fn test_clean_release_doesnt_flush() {
+    do_test(
+        |t| {
+            t.expect_command_nodata().times(0);
+        },
+        |f| {
+            assert!(!f.d.is_dirty());
+            let fut = pin!(f.d.release());
+            let Poll::Ready(Ok(_)) = fut.poll(f.c) else {
+                panic!("release failed");
+            };
+        },
+    );
+}
+
+#[test]
+fn test_release_flushes() {
+    do_test(
+        |t| {
+            expect_write(t);
+            t.expect_command_nodata()
+                .times(1)
+                .withf(|c| c[0] == 0x35)
+                .returning(command_nodata_ok);
+        },
+        |mut f| {
+            let buf = [0u8; 512];
+            f.c.check_ok(f.d.write_blocks(0, 1, &buf));
+            assert!(f.d.is_dirty());
+            let fut = pin!(f.d.release());
+            let Poll::Ready(Ok(_)) = fut.poll(f.c) else {
+                panic!("release failed");
+            };
+        },
+    );
+}
+
+#[test]
+fn test_flush_clears_dirty() {
+    do_test(
+        |t| {
+            expect_write(t);
+            t.expect_command_nodata()
+                .times(1)
+                .withf(|c| c[0] == 0x35)
+                .returning(command_nodata_ok);
+        },
+        |mut f| {
+            let buf = [0u8; 512];
+            f.c.check_ok(f.d.write_blocks(0, 1, &buf));
+            f.c.check_ok(f.d.flush());
+            assert!(!f.d.is_dirty());
+
+            // Already clean, so no second SYNCHRONIZE CACHE
+            f.c.check_ok(f.d.flush());
+        },
+    );
+}
+
+#[test]
+fn test_release_fails() {
+    do_test(
+        |t| {
+            expect_write(t);
+            t.expect_command_nodata()
+                .times(1)
+                .withf(|c| c[0] == 0x35)
+                .returning(command_nodata_fails);
+            t.expect_request_sense();
+            t.expect_command_nodata()
+                .times(1)
+                .withf(|c| c[0] == 0x35)
+                .returning(command_nodata_ok);
+        },
+        |mut f| {
+            let buf = [0u8; 512];
+            f.c.check_ok(f.d.write_blocks(0, 1, &buf));
+            let mut guard = {
+                let fut = pin!(f.d.release());
+                let Poll::Ready(Err((guard, _))) = fut.poll(f.c) else {
+                    panic!("release should fail");
+                };
+                guard
+            };
+            assert!(guard.is_dirty());
+
+            // Retry succeeds
+            f.c.check_ok(guard.flush());
+            assert!(!guard.is_dirty());
+        },
+    );
+}
@@ -0,0 +1,602 @@
+use super::*;
+use crate::async_block_device::AsyncBlockDevice;
+use crate::scsi_block_device::ScsiBlockDevice;
+use crate::scsi_device::{PeripheralType, ScsiDevice};
+use crate::scsi_transport::ScsiError;
+use tokio::io::{duplex, DuplexStream};
+
+const BLOCK_SIZE: usize = 512;
+const BLOCKS: usize = 64;
+const TARGET_NAME: &str = "iqn.2024-01.org.example:disk";
+const INITIATOR_NAME: &str = "iqn.2024-01.org.example:test";
+// Small limits on the target, so that transfers need several PDUs
+const TARGET_MAX_RECV: usize = 1024;
+const TARGET_MAX_BURST: usize = 4096;
+const DATA_IN_SEGMENT: usize = 1536;
+
+/// Just enough of an iSCSI target to serve a RAM disk
+struct FakeTarget {
+    stream: DuplexStream,
+    disk: Vec<u8>,
+    stat_sn: u32,
+    exp_cmd_sn: u32,
+    /// Split the operational-stage login response using the "C" bit
+    split_login: bool,
+    /// Answer login requests with the wrong Initiator Task Tag
+    wrong_login_itt: bool,
+    /// Send a NOP-In (and expect an answer) before the next response
+    ping_pending: bool,
+    unit_attention: bool,
+    login_keys: Vec<String>,
+    opcodes: Vec<u8>,
+    task_management: Vec<u8>,
+    nop_outs: usize,
+    logged_out: bool,
+}
+
+impl FakeTarget {
+    fn new(stream: DuplexStream) -> Self {
+        Self {
+            stream,
+            disk: (0..BLOCKS * BLOCK_SIZE).map(|i| (i / 7) as u8).collect(),
+            stat_sn: 0x1000,
+            exp_cmd_sn: 0,
+            split_login: false,
+            wrong_login_itt: false,
+            ping_pending: false,
+            unit_attention: false,
+            login_keys: Vec::new(),
+            opcodes: Vec::new(),
+            task_management: Vec::new(),
+            nop_outs: 0,
+            logged_out: false,
+        }
+    }
+
+    async fn read_pdu(&mut self) -> Option<([u8; BHS_LEN], Vec<u8>)> {
+        let mut bhs = [0u8; BHS_LEN];
+        self.stream.read_exact(&mut bhs).await.ok()?;
+        assert_eq!(bhs[4], 0);
+        let len = u32::from_be_bytes([0, bhs[5], bhs[6], bhs[7]]) as usize;
+        let mut data = vec![0u8; (len + 3) & !3];
+        self.stream.read_exact(&mut data).await.unwrap();
+        data.truncate(len);
+        Some((bhs, data))
+    }
+
+    async fn write_pdu(&mut self, mut bhs: [u8; BHS_LEN], data: &[u8]) {
+        bhs[5..8].copy_from_slice(&(data.len() as u32).to_be_bytes()[1..]);
+        self.stream.write_all(&bhs).await.unwrap();
+        let mut data = data.to_vec();
+        data.resize((data.len() + 3) & !3, 0);
+        self.stream.write_all(&data).await.unwrap();
+    }
+
+    /// A target-to-initiator header, with sequence numbers filled in
+    fn response(&mut self, opcode: u8, flags: u8, itt: u32) -> [u8; 48] {
+        let mut bhs = header(opcode, flags, &[0u8; 8], itt);
+        put_u32(&mut bhs, 24, self.stat_sn);
+        put_u32(&mut bhs, 28, self.exp_cmd_sn);
+        put_u32(&mut bhs, 32, self.exp_cmd_sn.wrapping_add(8));
+        bhs
+    }
+
+    fn stat_sn(&mut self) -> u32 {
+        self.stat_sn += 1;
+        self.stat_sn - 1
+    }
+
+    async fn login(&mut self) -> bool {
+        let mut stage = SECURITY_NEGOTIATION;
+        let mut split = self.split_login;
+        loop {
+            let Some((request, data)) = self.read_pdu().await else {
+                return false;
+            };
+            assert_eq!(request[0], IMMEDIATE | LOGIN_REQUEST);
+            assert_eq!((request[1] >> 2) & 3, stage);
+            assert_eq!(&request[8..14], &LoginParameters::new("", "").isid);
+            self.exp_cmd_sn =
+                u32::from_be_bytes(request[24..28].try_into().unwrap());
+            let keys: Vec<String> = data
+                .split(|b| *b == 0)
+                .filter(|k| !k.is_empty())
+                .map(|k| String::from_utf8(k.to_vec()).unwrap())
+                .collect();
+            self.login_keys.extend(keys.iter().cloned());
+
+            let mut itt =
+                u32::from_be_bytes(request[16..20].try_into().unwrap());
+            if self.wrong_login_itt {
+                itt = itt.wrapping_add(1);
+            }
+            let mut bhs = self.response(LOGIN_RESPONSE, 0, itt);
+            bhs[8..14].copy_from_slice(&request[8..14]);
+            put_u32(&mut bhs, 24, self.stat_sn);
+
+            if stage == SECURITY_NEGOTIATION {
+                if !keys.contains(&format!("TargetName={TARGET_NAME}")) {
+                    bhs[36] = 2;
+                    bhs[37] = 3;
+                    self.write_pdu(bhs, &[]).await;
+                    return false;
+                }
+                bhs[1] = LOGIN_TRANSIT | OPERATIONAL_NEGOTIATION;
+                self.write_pdu(
+                    bhs,
+                    b"AuthMethod=None\0TargetPortalGroupTag=1\0",
+                )
+                .await;
+                stage = OPERATIONAL_NEGOTIATION;
+            } else if split {
+                bhs[1] = LOGIN_CONTINUE | (OPERATIONAL_NEGOTIATION << 2);
+                self.write_pdu(bhs, b"HeaderDigest=None\0DataDigest=None\0")
+                    .await;
+                split = false;
+            } else {
+                bhs[1] = LOGIN_TRANSIT
+                    | (OPERATIONAL_NEGOTIATION << 2)
+                    | FULL_FEATURE_PHASE;
+                bhs[14..16].copy_from_slice(&7u16.to_be_bytes());
+                put_u32(&mut bhs, 24, self.stat_sn());
+                let keys = format!(
+                    "MaxRecvDataSegmentLength={TARGET_MAX_RECV}\0\
+                     MaxBurstLength={TARGET_MAX_BURST}\0InitialR2T=Yes\0\
+                     ImmediateData=No\0"
+                );
+                self.write_pdu(bhs, keys.as_bytes()).await;
+                return true;
+            }
+        }
+    }
+
+    async fn check_condition(&mut self, itt: u32, key: u8, asc: u8) {
+        let mut bhs = self.response(SCSI_RESPONSE, FINAL, itt);
+        put_u32(&mut bhs, 24, self.stat_sn());
+        bhs[3] = 2;
+        let mut data = vec![0, 18, 0x70, 0, key, 0, 0, 0, 0, 10];
+        data.extend_from_slice(&[0, 0, 0, 0, asc, 0, 0, 0, 0, 0]);
+        self.write_pdu(bhs, &data).await;
+    }
+
+    async fn good(&mut self, itt: u32) {
+        let mut bhs = self.response(SCSI_RESPONSE, FINAL, itt);
+        put_u32(&mut bhs, 24, self.stat_sn());
+        self.write_pdu(bhs, &[]).await;
+    }
+
+    async fn data_in(&mut self, itt: u32, data: &[u8], with_status: bool) {
+        let count = data.chunks(DATA_IN_SEGMENT).len();
+        for (i, chunk) in data.chunks(DATA_IN_SEGMENT).enumerate() {
+            let last = i + 1 == count;
+            let flags = match (last, with_status) {
+                (true, true) => FINAL | DATA_IN_STATUS,
+                (true, false) => FINAL,
+                _ => 0,
+            };
+            let mut bhs = self.response(DATA_IN, flags, itt);
+            put_u32(&mut bhs, 20, NO_TAG);
+            put_u32(
+                &mut bhs,
+                24,
+                if last && with_status {
+                    self.stat_sn()
+                } else {
+                    0
+                },
+            );
+            put_u32(&mut bhs, 36, i as u32);
+            put_u32(&mut bhs, 40, (i * DATA_IN_SEGMENT) as u32);
+            self.write_pdu(bhs, chunk).await;
+        }
+    }
+
+    /// Ask for, and receive, one burst of a write to the disk at `base`
+    async fn receive_data_out(
+        &mut self,
+        itt: u32,
+        base: usize,
+        offset: usize,
+        length: usize,
+    ) {
+        let ttt = 0x5000 + offset as u32;
+        let mut bhs = self.response(R2T, FINAL, itt);
+        put_u32(&mut bhs, 20, ttt);
+        put_u32(&mut bhs, 40, offset as u32);
+        put_u32(&mut bhs, 44, length as u32);
+        self.write_pdu(bhs, &[]).await;
+
+        let mut received = 0;
+        let mut data_sn = 0;
+        loop {
+            let (pdu, data) = self.read_pdu().await.unwrap();
+            assert_eq!(pdu[0], DATA_OUT);
+            assert_eq!(
+                u32::from_be_bytes(pdu[16..20].try_into().unwrap()),
+                itt
+            );
+            assert_eq!(
+                u32::from_be_bytes(pdu[20..24].try_into().unwrap()),
+                ttt
+            );
+            assert_eq!(
+                u32::from_be_bytes(pdu[36..40].try_into().unwrap()),
+                data_sn
+            );
+            let at =
+                u32::from_be_bytes(pdu[40..44].try_into().unwrap()) as usize;
+            assert_eq!(at, offset + received);
+            assert!(data.len() <= TARGET_MAX_RECV);
+            self.disk[base + at..base + at + data.len()]
+                .copy_from_slice(&data);
+            received += data.len();
+            data_sn += 1;
+            if (pdu[1] & FINAL) != 0 {
+                break;
+            }
+        }
+        assert_eq!(received, length);
+    }
+
+    async fn ping(&mut self) {
+        let mut bhs = self.response(NOP_IN, FINAL, NO_TAG);
+        put_u32(&mut bhs, 20, 0x1234);
+        self.write_pdu(bhs, b"ping").await;
+        let (pdu, data) = self.read_pdu().await.unwrap();
+        assert_eq!(pdu[0], IMMEDIATE | NOP_OUT);
+        assert_eq!(
+            u32::from_be_bytes(pdu[16..20].try_into().unwrap()),
+            NO_TAG
+        );
+        assert_eq!(
+            u32::from_be_bytes(pdu[20..24].try_into().unwrap()),
+            0x1234
+        );
+        assert_eq!(data, b"ping");
+        self.nop_outs += 1;
+    }
+
+    async fn scsi_command(&mut self, pdu: &[u8; BHS_LEN]) {
+        let itt = u32::from_be_bytes(pdu[16..20].try_into().unwrap());
+        let length = u32::from_be_bytes(pdu[20..24].try_into().unwrap());
+        let cmd_sn = u32::from_be_bytes(pdu[24..28].try_into().unwrap());
+        assert_eq!(cmd_sn, self.exp_cmd_sn);
+        self.exp_cmd_sn += 1;
+        let cdb = &pdu[32..48];
+        let opcode = cdb[0];
+        let lba = u32::from_be_bytes(cdb[2..6].try_into().unwrap()) as usize;
+        let blocks = u16::from_be_bytes([cdb[7], cdb[8]]) as usize;
+        self.opcodes.push(opcode);
+
+        if self.ping_pending {
+            self.ping_pending = false;
+            self.ping().await;
+        }
+
+        match opcode {
+            // TEST UNIT READY
+            0x00 => {
+                if self.unit_attention {
+                    self.unit_attention = false;
+                    self.check_condition(itt, 6, 0x29).await;
+                } else {
+                    self.good(itt).await;
+                }
+            }
+            // INQUIRY
+            0x12 => {
+                let mut data = [0u8; 36];
+                data[4] = 31;
+                data[8..16].copy_from_slice(b"COTTON  ");
+                let n = data.len().min(length as usize);
+                self.data_in(itt, &data[..n], false).await;
+                self.good(itt).await;
+            }
+            // READ CAPACITY (10)
+            0x25 => {
+                let mut data = [0u8; 8];
+                data[0..4].copy_from_slice(&(BLOCKS as u32 - 1).to_be_bytes());
+                data[4..8].copy_from_slice(&(BLOCK_SIZE as u32).to_be_bytes());
+                self.data_in(itt, &data, true).await;
+            }
+            // READ (10)
+            0x28 => {
+                assert_eq!(length as usize, blocks * BLOCK_SIZE);
+                let base = lba * BLOCK_SIZE;
+                let data = self.disk[base..base + length as usize].to_vec();
+                self.data_in(itt, &data, true).await;
+            }
+            // WRITE (10)
+            0x2A => {
+                assert_eq!(length as usize, blocks * BLOCK_SIZE);
+                let mut offset = 0;
+                while offset < length as usize {
+                    let n = TARGET_MAX_BURST.min(length as usize - offset);
+                    self.receive_data_out(itt, lba * BLOCK_SIZE, offset, n)
+                        .await;
+                    offset += n;
+                }
+                self.good(itt).await;
+            }
+            // Something the target can't even parse
+            0xFF => {
+                let mut bhs = self.response(REJECT, FINAL, NO_TAG);
+                bhs[2] = 4;
+                put_u32(&mut bhs, 24, self.stat_sn());
+                self.write_pdu(bhs, pdu).await;
+            }
+            // INVALID COMMAND OPERATION CODE
+            _ => self.check_condition(itt, 5, 0x20).await,
+        }
+    }
+
+    /// Serve one connection, until logout or disconnection
+    async fn serve(&mut self) {
+        if !self.login().await {
+            return;
+        }
+        while let Some((pdu, data)) = self.read_pdu().await {
+            let itt = u32::from_be_bytes(pdu[16..20].try_into().unwrap());
+            let exp_stat_sn =
+                u32::from_be_bytes(pdu[28..32].try_into().unwrap());
+            assert_eq!(exp_stat_sn, self.stat_sn);
+            match pdu[0] & 0x3F {
+                SCSI_COMMAND => self.scsi_command(&pdu).await,
+                TASK_MANAGEMENT_REQUEST => {
+                    assert_eq!(pdu[0] & IMMEDIATE, IMMEDIATE);
+                    self.task_management.push(pdu[1] & 0x7F);
+                    self.unit_attention = true;
+                    let mut bhs =
+                        self.response(TASK_MANAGEMENT_RESPONSE, FINAL, itt);
+                    put_u32(&mut bhs, 24, self.stat_sn());
+                    self.write_pdu(bhs, &[]).await;
+                }
+                NOP_OUT => {
+                    if self.ping_pending {
+                        self.ping_pending = false;
+                        self.ping().await;
+                    }
+                    let mut bhs = self.response(NOP_IN, FINAL, itt);
+                    put_u32(&mut bhs, 20, NO_TAG);
+                    put_u32(&mut bhs, 24, self.stat_sn());
+                    self.write_pdu(bhs, &data).await;
+                }
+                LOGOUT_REQUEST => {
+                    let mut bhs = self.response(LOGOUT_RESPONSE, FINAL, itt);
+                    put_u32(&mut bhs, 24, self.stat_sn());
+                    self.write_pdu(bhs, &[]).await;
+                    self.logged_out = true;
+                    return;
+                }
+                op => panic!("unexpected opcode {op:#x}"),
+            }
+        }
+    }
+}
+
+fn parameters() -> LoginParameters {
+    LoginParameters::new(INITIATOR_NAME, TARGET_NAME)
+}
+
+fn setup() -> (FakeTarget, DuplexStream) {
+    let (a, b) = duplex(4096);
+    (FakeTarget::new(a), b)
+}
+
+#[tokio::test]
+async fn login_and_logout() {
+    let (mut target, stream) = setup();
+    let ((), initiator) = tokio::join!(target.serve(), async {
+        let iscsi = IscsiTransport::login(stream, &parameters()).await?;
+        assert_eq!(iscsi.max_send_data_segment_length, TARGET_MAX_RECV);
+        assert_eq!(iscsi.max_transfer_size(), MAX_TRANSFER_SIZE);
+        iscsi.logout().await
+    });
+    assert_eq!(initiator, Ok(()));
+    assert!(target.logged_out);
+    for key in [
+        "InitiatorName=iqn.2024-01.org.example:test",
+        "TargetName=iqn.2024-01.org.example:disk",
+        "SessionType=Normal",
+        "AuthMethod=None",
+        "HeaderDigest=None",
+        "ErrorRecoveryLevel=0",
+    ] {
+        assert!(target.login_keys.iter().any(|k| k == key), "{key}");
+    }
+}
+
+#[tokio::test]
+async fn login_continued() {
+    let (mut target, stream) = setup();
+    target.split_login = true;
+    let ((), initiator) = tokio::join!(target.serve(), async {
+        let iscsi = IscsiTransport::login(stream, &parameters()).await?;
+        assert_eq!(iscsi.max_send_data_segment_length, TARGET_MAX_RECV);
+        iscsi.logout().await
+    });
+    assert_eq!(initiator, Ok(()));
+}
+
+#[tokio::test]
+async fn login_refused() {
+    let (mut target, stream) = setup();
+    let mut p = parameters();
+    p.target_name = "iqn.2024-01.org.example:nonesuch".to_string();
+    let ((), initiator) =
+        tokio::join!(target.serve(), IscsiTransport::login(stream, &p));
+    assert_eq!(initiator.err(), Some(IscsiError::LoginFailed(2, 3)));
+}
+
+#[tokio::test]
+async fn login_wrong_itt() {
+    let (mut target, stream) = setup();
+    target.wrong_login_itt = true;
+    let ((), initiator) = tokio::join!(target.serve(), async {
+        // Dropping any transport closes the connection, ending serve()
+        IscsiTransport::login(stream, &parameters()).await.map(drop)
+    });
+    assert_eq!(initiator, Err(IscsiError::Protocol));
+}
+
+#[tokio::test]
+async fn login_disconnected() {
+    let (target, stream) = setup();
+    drop(target);
+    let rc = IscsiTransport::login(stream, &parameters()).await;
+    assert!(matches!(rc.err(), Some(IscsiError::Io(_))));
+}
+
+#[tokio::test]
+async fn block_device() {
+    let (mut target, stream) = setup();
+    let ((), ()) = tokio::join!(target.serve(), async {
+        let iscsi =
+            IscsiTransport::login(stream, &parameters()).await.unwrap();
+        let mut scsi = ScsiDevice::new(iscsi);
+        let inquiry = scsi.inquiry().await.unwrap();
+        assert_eq!(inquiry.peripheral_type, PeripheralType::Disk);
+        let mut disk = ScsiBlockDevice::new(scsi);
+        let info = disk.device_info().await.unwrap();
+        assert_eq!(info.block_size, BLOCK_SIZE as u32);
+
+        let mut buf = vec![0u8; 20 * BLOCK_SIZE];
+        disk.read_blocks(3, 20, &mut buf).await.unwrap();
+        assert_eq!(buf[0], (3 * BLOCK_SIZE / 7) as u8);
+        assert_eq!(buf[buf.len() - 1], ((23 * BLOCK_SIZE - 1) / 7) as u8);
+
+        let data: Vec<u8> = (0..20 * BLOCK_SIZE).map(|i| i as u8).collect();
+        disk.write_blocks(40, 20, &data).await.unwrap();
+        disk.read_blocks(40, 20, &mut buf).await.unwrap();
+        assert_eq!(buf, data);
+    });
+    assert_eq!(target.disk[40 * BLOCK_SIZE + 1], 1);
+}
+
+#[tokio::test]
+async fn command_failed_with_sense() {
+    let (mut target, stream) = setup();
+    let ((), ()) = tokio::join!(target.serve(), async {
+        let iscsi =
+            IscsiTransport::login(stream, &parameters()).await.unwrap();
+        let mut scsi = ScsiDevice::new(iscsi);
+        let rc = scsi.report_supported_operation_codes(0x28, None).await;
+        assert_eq!(
+            rc,
+            Err(Error::Scsi(ScsiError::InvalidCommandOperationCode))
+        );
+    });
+    // The sense data came with the failure: no REQUEST SENSE needed
+    assert!(!target.opcodes.contains(&REQUEST_SENSE));
+}
+
+#[tokio::test]
+async fn lun_reset() {
+    let (mut target, stream) = setup();
+    let ((), ()) = tokio::join!(target.serve(), async {
+        let mut iscsi =
+            IscsiTransport::login(stream, &parameters()).await.unwrap();
+        iscsi
+            .task_management(TaskManagementFunction::LogicalUnitReset)
+            .await
+            .unwrap();
+        let mut scsi = ScsiDevice::new(iscsi);
+        assert_eq!(
+            scsi.test_unit_ready().await,
+            Err(Error::Scsi(ScsiError::UnitAttention))
+        );
+        assert_eq!(scsi.test_unit_ready().await, Ok(()));
+    });
+    assert_eq!(target.task_management, vec![5]);
+    assert_eq!(target.opcodes, vec![0, 0]);
+}
+
+#[tokio::test]
+async fn nop_in_answered() {
+    let (mut target, stream) = setup();
+    target.ping_pending = true;
+    let ((), ()) = tokio::join!(target.serve(), async {
+        let mut iscsi =
+            IscsiTransport::login(stream, &parameters()).await.unwrap();
+        let mut buf = [0u8; 8];
+        iscsi
+            .command(
+                &[0x25, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+                DataPhase::In(&mut buf),
+            )
+            .await
+            .unwrap();
+        assert_eq!(buf[7], 0);
+        assert_eq!(buf[6], 2);
+        iscsi.ping().await.unwrap();
+    });
+    assert_eq!(target.nop_outs, 1);
+}
+
+#[tokio::test]
+async fn ping_answers_nop_in() {
+    let (mut target, stream) = setup();
+    let ((), ()) = tokio::join!(target.serve(), async {
+        let mut iscsi =
+            IscsiTransport::login(stream, &parameters()).await.unwrap();
+        iscsi.ping().await.unwrap();
+        // ...with the right ExpStatSN, after the ping's response
+        iscsi.ping().await.unwrap();
+        iscsi.logout().await.unwrap();
+    });
+    assert_eq!(target.nop_outs, 0);
+    assert!(target.logged_out);
+
+    let (mut target, stream) = setup();
+    target.ping_pending = true;
+    let ((), ()) = tokio::join!(target.serve(), async {
+        let mut iscsi =
+            IscsiTransport::login(stream, &parameters()).await.unwrap();
+        iscsi.ping().await.unwrap();
+    });
+    assert_eq!(target.nop_outs, 1);
+}
+
+#[tokio::test]
+async fn rejected() {
+    let (mut target, stream) = setup();
+    let ((), ()) = tokio::join!(target.serve(), async {
+        let mut iscsi =
+            IscsiTransport::login(stream, &parameters()).await.unwrap();
+        assert_eq!(
+            iscsi.command(&[0xFF, 0, 0, 0, 0, 0], DataPhase::None).await,
+            Err(Error::Transport(IscsiError::Rejected(4)))
+        );
+        assert_eq!(
+            iscsi.command(&[0u8; 17], DataPhase::None).await,
+            Err(Error::ProtocolError)
+        );
+    });
+}
+
+#[test]
+fn luns() {
+    assert_eq!(encode_lun(0), [0u8; 8]);
+    assert_eq!(encode_lun(1), [0, 1, 0, 0, 0, 0, 0, 0]);
+    assert_eq!(encode_lun(300), [0x41, 0x2C, 0, 0, 0, 0, 0, 0]);
+}
+
+#[test]
+fn keys() {
+    let text = b"HeaderDigest=None\0MaxRecvDataSegmentLength=1024\0";
+    assert_eq!(find_key(text, "MaxRecvDataSegmentLength"), Some("1024"));
+    assert_eq!(find_key(text, "HeaderDigest"), Some("None"));
+    assert_eq!(find_key(text, "DataDigest"), None);
+    assert_eq!(find_key(b"", "DataDigest"), None);
+}
+
+#[test]
+fn errors() {
+    let e: Error<IscsiError> = IscsiError::Protocol.into();
+    assert_eq!(e, Error::ProtocolError);
+    let e: Error<IscsiError> = IscsiError::Rejected(4).into();
+    assert_eq!(e, Error::Transport(IscsiError::Rejected(4)));
+    let e: IscsiError =
+        std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into();
+    assert_eq!(e, IscsiError::Io(std::io::ErrorKind::UnexpectedEof));
+}
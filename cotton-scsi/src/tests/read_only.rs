@@ -0,0 +1,120 @@
+use super::*;
+use crate::scsi_device::tests::{
+    command_in_fails, command_ok_with, ExtraExpectations, MockError,
+    MockScsiTransport, MockScsiTransportInner, NoOpWaker,
+};
+use crate::{ScsiBlockDevice, ScsiDevice};
+use futures::Future;
+use std::pin::pin;
+use std::sync::Arc;
+use std::task::{Poll, Waker};
+
+struct Fixture<'a> {
+    c: &'a mut core::task::Context<'a>,
+    d: ReadOnlyBlockDevice<ScsiBlockDevice<MockScsiTransport>>,
+}
+
+fn do_test<
+    SetupFn: FnMut(&mut MockScsiTransportInner),
+    TestFn: FnMut(Fixture),
+>(
+    mut setup: SetupFn,
+    mut test: TestFn,
+) {
+    let w = Waker::from(Arc::new(NoOpWaker));
+    let mut c = core::task::Context::from_waker(&w);
+
+    let mut hc = MockScsiTransport::new();
+
+    setup(&mut hc.inner);
+
+    let f = Fixture {
+        c: &mut c,
+        d: ReadOnlyBlockDevice::new(ScsiBlockDevice::new(ScsiDevice::new(hc))),
+    };
+
+    test(f);
+}
+
+fn poll<T, F: Future<Output = Result<T, ReadOnlyError<MockError>>>>(
+    c: &mut core::task::Context,
+    fut: F,
+) -> Result<T, ReadOnlyError<MockError>> {
+    let fut = pin!(fut);
+    let Poll::Ready(r) = fut.poll(c) else {
+        panic!("future pending");
+    };
+    r
+}
+
+#[test]
+fn test_read_passes_through() {
+    do_test(
+        |t| {
+            t.expect_command_in()
+                .times(1)
+                .withf(|c, _| c[0] == 0x28)
+                .returning(command_ok_with([43u8; 512]));
+        },
+        |mut f| {
+            let mut buf = [0u8; 512];
+            poll(f.c, f.d.read_blocks(0, 1, &mut buf)).unwrap();
+            assert_eq!(buf[0], 43);
+        },
+    );
+}
+
+#[test]
+fn test_read_error_is_device_error() {
+    do_test(
+        |t| {
+            t.expect_command_in()
+                .times(1)
+                .withf(|c, _| c[0] == 0x28)
+                .returning(command_in_fails);
+            t.expect_request_sense();
+        },
+        |mut f| {
+            let mut buf = [0u8; 512];
+            let r = poll(f.c, f.d.read_blocks(0, 1, &mut buf));
+            assert!(matches!(r, Err(ReadOnlyError::Device(_))));
+        },
+    );
+}
+
+#[test]
+fn test_write_refused() {
+    do_test(
+        |t| {
+            t.expect_command_out().times(0);
+        },
+        |mut f| {
+            let buf = [47u8; 512];
+            let r = poll(f.c, f.d.write_blocks(0, 1, &buf));
+            assert_eq!(r, Err(ReadOnlyError::ReadOnly));
+        },
+    );
+}
+
+#[test]
+fn test_flush_does_nothing() {
+    do_test(
+        |t| {
+            t.expect_command_nodata().times(0);
+        },
+        |mut f| {
+            poll(f.c, f.d.flush()).unwrap();
+        },
+    );
+}
+
+#[test]
+fn test_get_ref() {
+    do_test(
+        |_| {},
+        |f| {
+            assert!(!f.d.get_ref().is_read_only());
+            assert_eq!(f.d.get_ref().block_size(), None);
+        },
+    );
+}
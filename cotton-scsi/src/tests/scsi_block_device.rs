@@ -1,14 +1,20 @@
 use super::*;
 use crate::scsi_device::tests::{
-    command_in_fails, command_in_pends, command_ok_with, command_out_fails,
-    command_out_ok, command_out_pends, ContextExtras, ExtraExpectations,
-    MockScsiTransport, MockScsiTransportInner, NoOpWaker,
+    command_in_fails, command_in_pends, command_nodata_fails,
+    command_nodata_ok, command_nodata_pends, command_ok_with,
+    command_out_fails, command_out_ok, command_out_pends, ContextExtras,
+    ExtraExpectations, MockError, MockScsiTransport, MockScsiTransportInner,
+    NoOpWaker,
 };
 use crate::scsi_device::{
     ReadCapacity10Reply, ReadCapacity16Reply,
     ReportSupportedOperationCodesReply,
 };
-use std::sync::Arc;
+use crate::scsi_transport::DEFAULT_MAX_TRANSFER_SIZE;
+use futures::future;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
 use std::task::Waker;
 
 struct Fixture<'a> {
@@ -20,6 +26,17 @@ fn do_test<
     SetupFn: FnMut(&mut MockScsiTransportInner),
     TestFn: FnMut(Fixture),
 >(
+    setup: SetupFn,
+    test: TestFn,
+) {
+    do_test_with_max_transfer(DEFAULT_MAX_TRANSFER_SIZE, setup, test);
+}
+
+fn do_test_with_max_transfer<
+    SetupFn: FnMut(&mut MockScsiTransportInner),
+    TestFn: FnMut(Fixture),
+>(
+    max_transfer_size: usize,
     mut setup: SetupFn,
     mut test: TestFn,
 ) {
@@ -27,6 +44,7 @@ fn do_test<
     let mut c = core::task::Context::from_waker(&w);
 
     let mut hc = MockScsiTransport::new();
+    hc.max_transfer_size = max_transfer_size;
 
     setup(&mut hc.inner);
 
@@ -464,3 +482,565 @@ fn test_query_commands_pends() {
         },
     );
 }
+
+#[test]
+fn test_flush() {
+    do_test(
+        |t| {
+            t.expect_command_nodata()
+                .times(1)
+                .withf(|c| c[0] == 0x35 && c[2..9].iter().all(|b| *b == 0))
+                .returning(command_nodata_ok);
+        },
+        |mut f| {
+            f.c.check_ok(f.d.flush());
+        },
+    );
+}
+
+#[test]
+fn test_flush_fails() {
+    do_test(
+        |t| {
+            t.expect_command_nodata()
+                .times(1)
+                .withf(|c| c[0] == 0x35)
+                .returning(command_nodata_fails);
+            t.expect_request_sense();
+        },
+        |mut f| {
+            f.c.check_fails(f.d.flush());
+        },
+    );
+}
+
+#[test]
+fn test_flush_unsupported() {
+    do_test(
+        |t| {
+            t.expect_command_nodata()
+                .times(1)
+                .withf(|c| c[0] == 0x35)
+                .returning(command_nodata_fails);
+            t.expect_request_sense_invalid_opcode();
+        },
+        |mut f| {
+            f.c.check_ok(f.d.flush());
+        },
+    );
+}
+
+#[test]
+fn test_flush_pends() {
+    do_test(
+        |t| {
+            t.expect_command_nodata()
+                .times(1)
+                .withf(|c| c[0] == 0x35)
+                .returning(command_nodata_pends);
+        },
+        |mut f| {
+            f.c.check_pends(f.d.flush());
+        },
+    );
+}
+
+/// Is this a READ(10) or WRITE(10) of `count` blocks from `lba`?
+fn is_rw10(c: &[u8], opcode: u8, lba: u32, count: u16) -> bool {
+    c[0] == opcode
+        && u32::from_be_bytes([c[2], c[3], c[4], c[5]]) == lba
+        && u16::from_be_bytes([c[7], c[8]]) == count
+}
+
+/// Reply to a READ(10) by filling each block with its own block number
+fn read_lba_pattern(
+    c: &[u8],
+    d: &mut [u8],
+) -> Pin<Box<dyn Future<Output = Result<usize, MockError>>>> {
+    read_lba_pattern_sized::<512>(c, d)
+}
+
+/// As `read_lba_pattern`, for blocks of `B` bytes
+fn read_lba_pattern_sized<const B: usize>(
+    c: &[u8],
+    d: &mut [u8],
+) -> Pin<Box<dyn Future<Output = Result<usize, MockError>>>> {
+    let lba = u32::from_be_bytes([c[2], c[3], c[4], c[5]]);
+    for (i, block) in d.chunks_mut(B).enumerate() {
+        block.fill((lba as usize + i) as u8);
+    }
+    Box::pin(future::ready(Ok(d.len())))
+}
+
+#[test]
+fn test_read_blocks_split() {
+    do_test_with_max_transfer(
+        1024,
+        |t| {
+            t.expect_command_in()
+                .times(1)
+                .withf(|c, d| is_rw10(c, 0x28, 10, 2) && d.len() == 1024)
+                .returning(read_lba_pattern);
+            t.expect_command_in()
+                .times(1)
+                .withf(|c, d| is_rw10(c, 0x28, 12, 2) && d.len() == 1024)
+                .returning(read_lba_pattern);
+            t.expect_command_in()
+                .times(1)
+                .withf(|c, d| is_rw10(c, 0x28, 14, 1) && d.len() == 512)
+                .returning(read_lba_pattern);
+        },
+        |mut f| {
+            let mut buf = [0u8; 2560];
+            f.c.check_ok(f.d.read_blocks(10, 5, &mut buf));
+            assert_eq!(buf[0], 10);
+            assert_eq!(buf[1023], 11);
+            assert_eq!(buf[1024], 12);
+            assert_eq!(buf[2559], 14);
+        },
+    );
+}
+
+#[test]
+fn test_read_blocks_split_fails() {
+    do_test_with_max_transfer(
+        1024,
+        |t| {
+            t.expect_command_in()
+                .times(1)
+                .withf(|c, _| is_rw10(c, 0x28, 0, 2))
+                .returning(read_lba_pattern);
+            t.expect_command_in()
+                .times(1)
+                .withf(|c, _| is_rw10(c, 0x28, 2, 2))
+                .returning(command_in_fails);
+            t.expect_request_sense();
+        },
+        |mut f| {
+            let mut buf = [0u8; 2560];
+            f.c.check_fails(f.d.read_blocks(0, 5, &mut buf));
+        },
+    );
+}
+
+#[test]
+fn test_read_blocks_block_larger_than_max() {
+    do_test_with_max_transfer(
+        256,
+        |t| {
+            t.expect_command_in()
+                .times(2)
+                .withf(|c, d| c[0] == 0x28 && d.len() == 512)
+                .returning(read_lba_pattern);
+        },
+        |mut f| {
+            let mut buf = [0u8; 1024];
+            f.c.check_ok(f.d.read_blocks(0, 2, &mut buf));
+        },
+    );
+}
+
+#[test]
+fn test_write_blocks_split() {
+    do_test_with_max_transfer(
+        1024,
+        |t| {
+            t.expect_command_out()
+                .times(1)
+                .withf(|c, d| is_rw10(c, 0x2A, 0, 2) && d[0] == 1)
+                .returning(command_out_ok);
+            t.expect_command_out()
+                .times(1)
+                .withf(|c, d| is_rw10(c, 0x2A, 2, 1) && d[0] == 3)
+                .returning(command_out_ok);
+        },
+        |mut f| {
+            let mut buf = [0u8; 1536];
+            buf[0] = 1;
+            buf[1024] = 3;
+            f.c.check_ok(f.d.write_blocks(0, 3, &buf));
+        },
+    );
+}
+
+#[test]
+fn test_write_blocks_split_fails() {
+    do_test_with_max_transfer(
+        512,
+        |t| {
+            t.expect_command_out()
+                .times(1)
+                .withf(|c, _| is_rw10(c, 0x2A, 0, 1))
+                .returning(command_out_fails);
+            t.expect_request_sense();
+        },
+        |mut f| {
+            let buf = [0u8; 1536];
+            f.c.check_fails(f.d.write_blocks(0, 3, &buf));
+        },
+    );
+}
+
+#[test]
+fn test_read_blocks_pipelined() {
+    do_test(
+        |t| {
+            t.expect_command_in()
+                .times(1)
+                .withf(|c, d| is_rw10(c, 0x28, 3, 2) && d.len() == 1024)
+                .returning(read_lba_pattern);
+            t.expect_command_in()
+                .times(1)
+                .withf(|c, d| is_rw10(c, 0x28, 5, 2) && d.len() == 1024)
+                .returning(read_lba_pattern);
+            t.expect_command_in()
+                .times(1)
+                .withf(|c, d| is_rw10(c, 0x28, 7, 1) && d.len() == 512)
+                .returning(read_lba_pattern);
+        },
+        |mut f| {
+            let mut buf = [0u8; 2048];
+            let mut seen = Vec::new();
+            f.c.check_ok(f.d.read_blocks_pipelined(
+                3,
+                5,
+                512,
+                &mut buf,
+                |block, data| seen.push((block, data.len(), data[0])),
+            ));
+            assert_eq!(seen, vec![(3, 1024, 3), (5, 1024, 5), (7, 512, 7)]);
+        },
+    );
+}
+
+#[test]
+fn test_read_blocks_pipelined_issues_read_first() {
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let log2 = log.clone();
+    do_test_with_max_transfer(
+        512,
+        move |t| {
+            let log = log2.clone();
+            t.expect_command_in().times(2).returning(move |c, d| {
+                log.lock().unwrap().push(format!("read {}", c[5]));
+                read_lba_pattern(c, d)
+            });
+        },
+        |mut f| {
+            let mut buf = [0u8; 4096];
+            f.c.check_ok(f.d.read_blocks_pipelined(
+                0,
+                2,
+                512,
+                &mut buf,
+                |block, _| {
+                    log.lock().unwrap().push(format!("process {block}"))
+                },
+            ));
+        },
+    );
+    assert_eq!(
+        *log.lock().unwrap(),
+        vec!["read 0", "read 1", "process 0", "process 1"]
+    );
+}
+
+#[test]
+fn test_read_blocks_pipelined_fails() {
+    do_test(
+        |t| {
+            t.expect_command_in()
+                .times(1)
+                .withf(|c, _| is_rw10(c, 0x28, 0, 1))
+                .returning(read_lba_pattern);
+            t.expect_command_in()
+                .times(1)
+                .withf(|c, _| is_rw10(c, 0x28, 1, 1))
+                .returning(command_in_fails);
+            t.expect_request_sense();
+        },
+        |mut f| {
+            let mut buf = [0u8; 1024];
+            let mut seen = 0;
+            f.c.check_fails(f.d.read_blocks_pipelined(
+                0,
+                3,
+                512,
+                &mut buf,
+                |_, _| seen += 1,
+            ));
+            // The first chunk was processed while the second was read
+            assert_eq!(seen, 1);
+        },
+    );
+}
+
+#[test]
+fn test_read_blocks_pipelined_buffer_too_small() {
+    do_test(
+        |t| {
+            t.expect_command_in().times(0);
+        },
+        |mut f| {
+            let mut buf = [0u8; 1000];
+            f.c.check_fails_custom(
+                f.d.read_blocks_pipelined(0, 3, 512, &mut buf, |_, _| {}),
+                Error::ProtocolError,
+            );
+        },
+    );
+}
+
+#[test]
+fn test_read_blocks_pipelined_nothing() {
+    do_test(
+        |t| {
+            t.expect_command_in().times(0);
+        },
+        |mut f| {
+            let mut buf = [0u8; 1024];
+            let mut seen = 0;
+            f.c.check_ok(f.d.read_blocks_pipelined(
+                0,
+                0,
+                512,
+                &mut buf,
+                |_, _| seen += 1,
+            ));
+            assert_eq!(seen, 0);
+        },
+    );
+}
+
+/// Expect a READ CAPACITY (10) reporting 1000 blocks of `block_size`
+fn expect_capacity(t: &mut MockScsiTransportInner, block_size: u32) {
+    t.expect_command_in()
+        .times(1)
+        .withf(|c, _| c[0] == 0x25)
+        .returning(command_ok_with(ReadCapacity10Reply {
+            lba: 999_u32.to_be_bytes(),
+            block_size: block_size.to_be_bytes(),
+        }));
+}
+
+#[test]
+fn test_device_info_records_block_size() {
+    for block_size in [512, 2048, 4096] {
+        do_test(
+            |t| expect_capacity(t, block_size),
+            |mut f| {
+                assert_eq!(f.d.block_size(), None);
+                let info = f.c.check_ok(f.d.device_info());
+                assert_eq!(info.block_size, block_size);
+                assert_eq!(f.d.block_size(), Some(block_size));
+            },
+        );
+    }
+}
+
+#[test]
+fn test_device_info_zero_block_size() {
+    do_test(
+        |t| expect_capacity(t, 0),
+        |mut f| {
+            f.c.check_fails_custom(f.d.device_info(), Error::ProtocolError);
+            assert_eq!(f.d.block_size(), None);
+        },
+    );
+}
+
+fn read_split_at<const B: usize>() {
+    do_test_with_max_transfer(
+        2 * B,
+        |t| {
+            expect_capacity(t, B as u32);
+            t.expect_command_in()
+                .times(1)
+                .withf(|c, d| is_rw10(c, 0x28, 10, 2) && d.len() == 2 * B)
+                .returning(read_lba_pattern_sized::<B>);
+            t.expect_command_in()
+                .times(1)
+                .withf(|c, d| is_rw10(c, 0x28, 12, 1) && d.len() == B)
+                .returning(read_lba_pattern_sized::<B>);
+        },
+        |mut f| {
+            f.c.check_ok(f.d.device_info());
+            let mut buf = vec![0u8; 3 * B];
+            f.c.check_ok(f.d.read_blocks(10, 3, &mut buf));
+            assert_eq!(buf[0], 10);
+            assert_eq!(buf[B], 11);
+            assert_eq!(buf[3 * B - 1], 12);
+        },
+    );
+}
+
+#[test]
+fn test_read_blocks_split_512() {
+    read_split_at::<512>();
+}
+
+#[test]
+fn test_read_blocks_split_2048() {
+    read_split_at::<2048>();
+}
+
+#[test]
+fn test_read_blocks_split_4096() {
+    read_split_at::<4096>();
+}
+
+fn write_split_at<const B: usize>() {
+    do_test_with_max_transfer(
+        2 * B,
+        |t| {
+            expect_capacity(t, B as u32);
+            t.expect_command_out()
+                .times(1)
+                .withf(|c, d| {
+                    is_rw10(c, 0x2A, 0, 2) && d.len() == 2 * B && d[B] == 2
+                })
+                .returning(command_out_ok);
+            t.expect_command_out()
+                .times(1)
+                .withf(|c, d| is_rw10(c, 0x2A, 2, 1) && d.len() == B)
+                .returning(command_out_ok);
+        },
+        |mut f| {
+            f.c.check_ok(f.d.device_info());
+            let mut buf = vec![0u8; 3 * B];
+            buf[B] = 2;
+            f.c.check_ok(f.d.write_blocks(0, 3, &buf));
+        },
+    );
+}
+
+#[test]
+fn test_write_blocks_split_512() {
+    write_split_at::<512>();
+}
+
+#[test]
+fn test_write_blocks_split_2048() {
+    write_split_at::<2048>();
+}
+
+#[test]
+fn test_write_blocks_split_4096() {
+    write_split_at::<4096>();
+}
+
+#[test]
+fn test_read_blocks_uses_known_block_size() {
+    // With 4096-byte blocks, an 8192-byte buffer holds two blocks, not
+    // the sixteen it would if 512-byte blocks were assumed
+    do_test(
+        |t| {
+            expect_capacity(t, 4096);
+            t.expect_command_in()
+                .times(1)
+                .withf(|c, d| is_rw10(c, 0x28, 4, 1) && d.len() == 4096)
+                .returning(read_lba_pattern_sized::<4096>);
+        },
+        |mut f| {
+            f.c.check_ok(f.d.device_info());
+            let mut buf = vec![0u8; 8192];
+            f.c.check_ok(f.d.read_blocks(4, 1, &mut buf));
+            assert_eq!(buf[4095], 4);
+            assert_eq!(buf[4096], 0);
+        },
+    );
+}
+
+#[test]
+fn test_read_blocks_buffer_smaller_than_block() {
+    do_test(
+        |t| {
+            expect_capacity(t, 4096);
+            t.expect_command_in().times(0).withf(|c, _| c[0] != 0x25);
+        },
+        |mut f| {
+            f.c.check_ok(f.d.device_info());
+            let mut buf = [0u8; 512];
+            f.c.check_fails_custom(
+                f.d.read_blocks(0, 1, &mut buf),
+                Error::ProtocolError,
+            );
+        },
+    );
+}
+
+#[test]
+fn test_write_blocks_buffer_smaller_than_blocks() {
+    do_test(
+        |t| {
+            expect_capacity(t, 2048);
+            t.expect_command_out().times(0);
+        },
+        |mut f| {
+            f.c.check_ok(f.d.device_info());
+            let buf = [0u8; 2048];
+            f.c.check_fails_custom(
+                f.d.write_blocks(0, 2, &buf),
+                Error::ProtocolError,
+            );
+        },
+    );
+}
+
+#[test]
+fn test_read_only_refuses_writes() {
+    let w = Waker::from(Arc::new(NoOpWaker));
+    let mut c = core::task::Context::from_waker(&w);
+
+    let mut hc = MockScsiTransport::new();
+    hc.inner.expect_command_out().times(0);
+
+    let mut d = ScsiBlockDevice::new_read_only(ScsiDevice::new(hc));
+    assert!(d.is_read_only());
+    let buf = [47u8; 512];
+    c.check_fails_custom(
+        d.write_blocks(0, 1, &buf),
+        Error::Scsi(ScsiError::DataProtect),
+    );
+}
+
+#[test]
+fn test_read_only_still_reads() {
+    let w = Waker::from(Arc::new(NoOpWaker));
+    let mut c = core::task::Context::from_waker(&w);
+
+    let mut hc = MockScsiTransport::new();
+    hc.inner
+        .expect_command_in()
+        .times(1)
+        .withf(|c, _| c[0] == 0x28)
+        .returning(command_ok_with([43u8; 512]));
+
+    let mut d = ScsiBlockDevice::new_read_only(ScsiDevice::new(hc));
+    let mut buf = [0u8; 512];
+    c.check_ok(d.read_blocks(0, 1, &mut buf));
+    assert_eq!(buf[0], 43);
+}
+
+#[test]
+fn test_set_read_only() {
+    do_test(
+        |t| {
+            t.expect_command_out()
+                .times(1)
+                .withf(|c, d| c[0] == 0x2A && d[0] == 47)
+                .returning(command_out_ok);
+        },
+        |mut f| {
+            let buf = [47u8; 512];
+            assert!(!f.d.is_read_only());
+            f.d.set_read_only(true);
+            f.c.check_fails_custom(
+                f.d.write_blocks(0, 1, &buf),
+                Error::Scsi(ScsiError::DataProtect),
+            );
+            f.d.set_read_only(false);
+            f.c.check_ok(f.d.write_blocks(0, 1, &buf));
+        },
+    );
+}
@@ -1,4 +1,5 @@
 use super::*;
+use crate::scsi_transport::DEFAULT_MAX_TRANSFER_SIZE;
 use futures::future;
 use mockall::mock;
 use std::fmt::{Debug, Formatter};
@@ -38,12 +39,14 @@ mock! {
 
 pub struct MockScsiTransport {
     pub inner: MockScsiTransportInner,
+    pub max_transfer_size: usize,
 }
 
 impl MockScsiTransport {
     pub fn new() -> Self {
         Self {
             inner: MockScsiTransportInner::new(),
+            max_transfer_size: DEFAULT_MAX_TRANSFER_SIZE,
         }
     }
 }
@@ -68,6 +71,10 @@ impl ScsiTransport for MockScsiTransport {
             DataPhase::None => self.inner.command_nodata(cmd),
         }
     }
+
+    fn max_transfer_size(&self) -> usize {
+        self.max_transfer_size
+    }
 }
 
 struct Fixture<'a> {
@@ -112,6 +119,7 @@ impl<T> PollExtras<T> for Poll<T> {
 
 pub trait ExtraExpectations {
     fn expect_request_sense(&mut self);
+    fn expect_request_sense_invalid_opcode(&mut self);
 }
 
 impl ExtraExpectations for MockScsiTransportInner {
@@ -126,6 +134,17 @@ impl ExtraExpectations for MockScsiTransportInner {
                 ..Default::default()
             }));
     }
+
+    fn expect_request_sense_invalid_opcode(&mut self) {
+        self.expect_command_in()
+            .times(1)
+            .withf(|c, _| c[0] == 3)
+            .returning(command_ok_with(RequestSenseReply {
+                sense_key: 5,
+                additional_sense_code: 0x20,
+                ..Default::default()
+            }));
+    }
 }
 
 pub trait ContextExtras {
@@ -204,19 +223,19 @@ impl ContextExtras for core::task::Context<'_> {
     }
 }
 
-fn command_nodata_ok(
+pub fn command_nodata_ok(
     _: &[u8],
 ) -> Pin<Box<dyn Future<Output = Result<usize, MockError>>>> {
     Box::pin(future::ready(Ok(0)))
 }
 
-fn command_nodata_fails(
+pub fn command_nodata_fails(
     _: &[u8],
 ) -> Pin<Box<dyn Future<Output = Result<usize, MockError>>>> {
     Box::pin(future::ready(Err(Error::CommandFailed)))
 }
 
-fn command_nodata_pends(
+pub fn command_nodata_pends(
     _: &[u8],
 ) -> Pin<Box<dyn Future<Output = Result<usize, MockError>>>> {
     Box::pin(future::pending())
@@ -834,6 +853,102 @@ fn test_write_16_error_pends() {
     );
 }
 
+#[test]
+fn test_synchronize_cache_10() {
+    do_test(
+        |t| {
+            t.expect_command_nodata()
+                .times(1)
+                .withf(|c| {
+                    c.len() == 10 && c[0] == 0x35 && c[5] == 81 && c[8] == 2
+                })
+                .returning(command_nodata_ok);
+        },
+        |mut f| {
+            f.c.check_ok(f.d.synchronize_cache_10(81, 2));
+        },
+    );
+}
+
+#[test]
+fn test_synchronize_cache_10_fails() {
+    do_test(
+        |t| {
+            t.expect_command_nodata()
+                .times(1)
+                .withf(|c| c[0] == 0x35)
+                .returning(command_nodata_fails);
+            t.expect_request_sense();
+        },
+        |mut f| {
+            f.c.check_fails(f.d.synchronize_cache_10(0, 0));
+        },
+    );
+}
+
+#[test]
+fn test_synchronize_cache_10_pends() {
+    do_test(
+        |t| {
+            t.expect_command_nodata()
+                .times(1)
+                .withf(|c| c[0] == 0x35)
+                .returning(command_nodata_pends);
+        },
+        |mut f| {
+            f.c.check_pends(f.d.synchronize_cache_10(0, 0));
+        },
+    );
+}
+
+#[test]
+fn test_synchronize_cache_16() {
+    do_test(
+        |t| {
+            t.expect_command_nodata()
+                .times(1)
+                .withf(|c| {
+                    c.len() == 16 && c[0] == 0x91 && c[9] == 81 && c[13] == 2
+                })
+                .returning(command_nodata_ok);
+        },
+        |mut f| {
+            f.c.check_ok(f.d.synchronize_cache_16(81, 2));
+        },
+    );
+}
+
+#[test]
+fn test_synchronize_cache_16_fails() {
+    do_test(
+        |t| {
+            t.expect_command_nodata()
+                .times(1)
+                .withf(|c| c[0] == 0x91)
+                .returning(command_nodata_fails);
+            t.expect_request_sense();
+        },
+        |mut f| {
+            f.c.check_fails(f.d.synchronize_cache_16(0, 0));
+        },
+    );
+}
+
+#[test]
+fn test_synchronize_cache_16_pends() {
+    do_test(
+        |t| {
+            t.expect_command_nodata()
+                .times(1)
+                .withf(|c| c[0] == 0x91)
+                .returning(command_nodata_pends);
+        },
+        |mut f| {
+            f.c.check_pends(f.d.synchronize_cache_16(0, 0));
+        },
+    );
+}
+
 #[test]
 fn test_report_supported_operation_codes() {
     do_test(
@@ -1076,3 +1191,13 @@ fn test_protocol_error_not_sensed() {
         },
     );
 }
+
+#[test]
+fn max_transfer_size() {
+    let mut t = MockScsiTransport::new();
+    let d = ScsiDevice::new(MockScsiTransport::new());
+    assert_eq!(d.max_transfer_size(), DEFAULT_MAX_TRANSFER_SIZE);
+    t.max_transfer_size = 4096;
+    let d = ScsiDevice::new(t);
+    assert_eq!(d.max_transfer_size(), 4096);
+}
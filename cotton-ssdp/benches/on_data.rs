@@ -0,0 +1,160 @@
+//! How long the engine takes to handle an incoming search
+//!
+//! The engine holds many advertisements (a media server might have
+//! dozens of embedded devices and services), and every search which
+//! arrives is matched against them; this measures `Engine::on_data`
+//! for the different kinds of search, so that the cost of matching
+//! can be seen not to grow with the number of advertisements.
+
+use cotton_netif::{
+    AddressFlags, AddressOrigin, Flags, InterfaceIndex, NetworkEvent,
+};
+use cotton_ssdp::engine::{Callback, CallbackError, Engine};
+use cotton_ssdp::refresh_timer::StdTimebase;
+use cotton_ssdp::udp::{self, Multicast, TargetedSend};
+use cotton_ssdp::{Advertisement, Notification};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use no_std_net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::time::Instant;
+
+struct NoCallback;
+
+impl Callback for NoCallback {
+    fn on_notification(
+        &self,
+        _notification: &Notification,
+    ) -> Result<(), CallbackError> {
+        Ok(())
+    }
+}
+
+struct NoSocket;
+
+impl TargetedSend for NoSocket {
+    fn send_with<F>(
+        &self,
+        size: usize,
+        _to: &SocketAddr,
+        _from: &IpAddr,
+        f: F,
+    ) -> Result<(), udp::Error>
+    where
+        F: FnOnce(&mut [u8]) -> usize,
+    {
+        let mut buffer = vec![0u8; size];
+        f(&mut buffer);
+        Ok(())
+    }
+}
+
+impl Multicast for NoSocket {
+    fn join_multicast_group(
+        &self,
+        _multicast_address: &IpAddr,
+        _interface: InterfaceIndex,
+    ) -> Result<(), udp::Error> {
+        Ok(())
+    }
+
+    fn leave_multicast_group(
+        &self,
+        _multicast_address: &IpAddr,
+        _interface: InterfaceIndex,
+    ) -> Result<(), udp::Error> {
+        Ok(())
+    }
+}
+
+const LOCAL_IP: Ipv4Addr = Ipv4Addr::new(192, 168, 100, 1);
+
+fn engine_with(services: usize) -> Engine<NoCallback, StdTimebase> {
+    let mut e = Engine::new(0, Instant::now());
+    let ix = InterfaceIndex(core::num::NonZeroU32::new(2).unwrap());
+    e.on_network_event(
+        &NetworkEvent::NewLink(
+            ix,
+            "eth0".to_string(),
+            Flags::UP | Flags::RUNNING | Flags::MULTICAST,
+            cotton_netif::OperState::Up,
+        ),
+        &NoSocket,
+        &NoSocket,
+    )
+    .unwrap();
+    e.on_network_event(
+        &NetworkEvent::NewAddr(
+            ix,
+            IpAddr::V4(LOCAL_IP),
+            24,
+            AddressFlags::MULTICAST,
+            AddressOrigin::Dynamic,
+        ),
+        &NoSocket,
+        &NoSocket,
+    )
+    .unwrap();
+    for i in 0..services {
+        e.advertise(
+            format!("uuid:{i:08}-0000-0000-0000-000000000000::urn:test"),
+            Advertisement::new(
+                format!("urn:schemas-example-com:service:Service{i}:1"),
+                "http://127.0.0.1/description.xml",
+            ),
+            &NoSocket,
+        );
+    }
+    e
+}
+
+fn search(search_target: &str) -> String {
+    format!(
+        "M-SEARCH * HTTP/1.1\r
+HOST: 239.255.255.250:1900\r
+MAN: \"ssdp:discover\"\r
+MX: 5\r
+ST: {search_target}\r
+\r\n"
+    )
+}
+
+fn bench_search(c: &mut Criterion) {
+    let searches = [
+        ("type", search("urn:schemas-example-com:service:Service7:1")),
+        (
+            "device",
+            search("uuid:00000007-0000-0000-0000-000000000000"),
+        ),
+        ("all", search("ssdp:all")),
+    ];
+    let mut group = c.benchmark_group("on_data");
+    for services in [10, 100, 1000] {
+        for (name, packet) in &searches {
+            group.bench_with_input(
+                BenchmarkId::new(*name, services),
+                &services,
+                |b, &services| {
+                    let mut e = engine_with(services);
+                    let mut port = 0u16;
+                    b.iter(|| {
+                        // A different searcher each time, so that
+                        // none is ignored as a repeat
+                        port = port.wrapping_add(1);
+                        e.on_data(
+                            packet.as_bytes(),
+                            IpAddr::V4(LOCAL_IP),
+                            SocketAddr::V4(SocketAddrV4::new(
+                                Ipv4Addr::new(192, 168, 100, 2),
+                                port,
+                            )),
+                            Instant::now(),
+                        );
+                    });
+                },
+            );
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_search);
+criterion_main!(benches);
@@ -21,10 +21,7 @@ fn main() -> Result<(), Box<dyn Error>> {
     let uuid = uuid::Uuid::new_v4();
     ssdp.advertise(
         uuid.to_string(),
-        cotton_ssdp::Advertisement {
-            notification_type: "test".to_string(),
-            location: "http://127.0.0.1/test".to_string(),
-        },
+        cotton_ssdp::Advertisement::new("test", "http://127.0.0.1/test"),
     );
 
     let map = RefCell::new(HashMap::new());
@@ -36,6 +33,7 @@ fn main() -> Result<(), Box<dyn Error>> {
                 ref notification_type,
                 ref unique_service_name,
                 ref location,
+                ..
             } = r
             {
                 let mut m = map.borrow_mut();
@@ -18,10 +18,7 @@ async fn main() -> Result<(), Box<dyn Error>> {
 
     ssdp.advertise(
         uuid.to_string(),
-        Advertisement {
-            notification_type: "test".to_string(),
-            location: "http://127.0.0.1/test".to_string(),
-        },
+        Advertisement::new("test", "http://127.0.0.1/test"),
     );
 
     let mut stream = ssdp.subscribe("ssdp:all");
@@ -33,6 +30,7 @@ async fn main() -> Result<(), Box<dyn Error>> {
                         ref notification_type,
                         ref unique_service_name,
                         ref location,
+                        ..
                     } = r
                     {
                         if !map.contains_key(unique_service_name) {
@@ -0,0 +1,424 @@
+#ifndef COTTON_SSDP_H
+#define COTTON_SSDP_H
+
+/* Generated by cbindgen from cotton-ssdp/src/ffi.rs -- do not edit */
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+/**
+ * Success
+ */
+#define COTTON_SSDP_OK 0
+
+/**
+ * A pointer argument was NULL, or a string wasn't valid UTF-8
+ */
+#define COTTON_SSDP_INVALID_ARGUMENT -1
+
+/**
+ * The configured limit on advertisements or subscriptions was reached
+ */
+#define COTTON_SSDP_TOO_MANY -2
+
+/**
+ * A socket callback reported an error
+ */
+#define COTTON_SSDP_SOCKET_ERROR -3
+
+/**
+ * The engine panicked (which is a bug)
+ */
+#define COTTON_SSDP_INTERNAL_ERROR -4
+
+/**
+ * Called from within one of the engine's own callbacks
+ *
+ * The engine isn't re-entrant: while it's calling a socket function
+ * or a subscription callback, none of the `cotton_ssdp_engine_*`
+ * functions may be used on it. Any which are return this (or, for
+ * those which don't return an error code, do nothing), and the
+ * engine is left as it was.
+ */
+#define COTTON_SSDP_REENTERED -5
+
+/**
+ * Which of the two SSDP sockets is meant
+ *
+ * See [`SocketKind`]. C code passes these to
+ * [`cotton_ssdp_engine_on_data()`] as plain `int`s, which are
+ * checked, rather than trusted to be one of the variants.
+ */
+enum cotton_ssdp_socket {
+  /**
+   * The socket bound to an ephemeral port
+   */
+  COTTON_SSDP_SOCKET_EPHEMERAL,
+  /**
+   * The socket bound to port 1900
+   */
+  COTTON_SSDP_SOCKET_PORT1900,
+};
+
+/**
+ * The kinds of notification, see [`Notification`]
+ */
+enum cotton_ssdp_notification_kind {
+  /**
+   * The resource is active
+   */
+  COTTON_SSDP_NOTIFICATION_KIND_ALIVE,
+  /**
+   * The resource is (becoming) inactive
+   */
+  COTTON_SSDP_NOTIFICATION_KIND_BYE_BYE,
+  /**
+   * The resource's advertisement has expired
+   */
+  COTTON_SSDP_NOTIFICATION_KIND_EXPIRED,
+};
+
+/**
+ * An SSDP engine, as seen from C
+ *
+ * Opaque to C code; created with [`cotton_ssdp_engine_new()`] and
+ * destroyed with [`cotton_ssdp_engine_free()`].
+ */
+struct cotton_ssdp_engine;
+
+/**
+ * An IPv4 address, as four octets in network order
+ */
+struct cotton_ssdp_ipv4_addr {
+  /**
+   * The address, most-significant octet first
+   */
+  uint8_t octets[4];
+};
+
+/**
+ * An IPv4 address and UDP port
+ */
+struct cotton_ssdp_socket_addr {
+  /**
+   * IP address
+   */
+  struct cotton_ssdp_ipv4_addr ip;
+  /**
+   * UDP port number, in host byte order
+   */
+  uint16_t port;
+};
+
+/**
+ * Send a UDP datagram from a particular socket and source address
+ *
+ * Returns 0 on success, or an `errno` value on failure. Mustn't call
+ * back into the engine, see [`COTTON_SSDP_REENTERED`].
+ */
+typedef int (*cotton_ssdp_send_fn)(void *context,
+                                   enum cotton_ssdp_socket socket,
+                                   const uint8_t *buffer,
+                                   size_t length,
+                                   struct cotton_ssdp_socket_addr to,
+                                   struct cotton_ssdp_ipv4_addr from);
+
+/**
+ * Join or leave a multicast group on a particular network interface
+ *
+ * Returns 0 on success, or an `errno` value on failure. Mustn't call
+ * back into the engine, see [`COTTON_SSDP_REENTERED`].
+ */
+typedef int (*cotton_ssdp_multicast_fn)(void *context,
+                                        struct cotton_ssdp_ipv4_addr group,
+                                        uint32_t interface);
+
+/**
+ * The networking facilities which the C code provides to the engine
+ *
+ * The C code owns the two UDP sockets: it reads from them, passing
+ * what it receives to [`cotton_ssdp_engine_on_data()`], and the
+ * engine sends on them by calling `send`.
+ */
+struct cotton_ssdp_sockets {
+  /**
+   * Passed unchanged to each of the functions below
+   */
+  void *context;
+  /**
+   * Send a datagram
+   */
+  cotton_ssdp_send_fn send;
+  /**
+   * Join a multicast group (on the port 1900 socket)
+   */
+  cotton_ssdp_multicast_fn join_multicast;
+  /**
+   * Leave a multicast group (on the port 1900 socket)
+   */
+  cotton_ssdp_multicast_fn leave_multicast;
+};
+
+/**
+ * An SSDP notification, as passed to a subscription callback
+ *
+ * The strings are NUL-terminated, and valid only for the duration
+ * of the callback.
+ */
+struct cotton_ssdp_notification {
+  /**
+   * Alive, bye-bye, or expired
+   */
+  enum cotton_ssdp_notification_kind kind;
+  /**
+   * Resource type
+   */
+  const char *notification_type;
+  /**
+   * Unique identifier for this particular resource instance
+   */
+  const char *unique_service_name;
+  /**
+   * URL of the resource; NULL for bye-bye notifications
+   */
+  const char *location;
+};
+
+/**
+ * A subscription callback
+ *
+ * Returns 0 if the notification was handled, or non-zero if not (see
+ * [`Callback`] for what happens then). Mustn't call back into the
+ * engine, see [`COTTON_SSDP_REENTERED`].
+ */
+typedef int (*cotton_ssdp_notification_fn)(void *context,
+                                           const struct cotton_ssdp_notification *notification);
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Create a new SSDP engine
+ *
+ * The engine sends, and joins multicast groups, using the functions
+ * in `sockets`, which is copied. `now_ms` is the current time, see
+ * [`FfiTimebase`].
+ *
+ * Returns NULL if `sockets` is NULL or has no `send` function.
+ *
+ * # Safety
+ *
+ * `sockets` must be NULL or point to a valid set of sockets, whose
+ * functions must remain callable with its `context` until the
+ * engine is freed.
+ */
+struct cotton_ssdp_engine *cotton_ssdp_engine_new(uint32_t random_seed,
+                                                  uint64_t now_ms,
+                                                  const struct cotton_ssdp_sockets *sockets);
+
+/**
+ * Destroy an SSDP engine
+ *
+ * Advertisements are not withdrawn first; to do that, call
+ * [`cotton_ssdp_engine_deadvertise()`] beforehand.
+ *
+ * # Safety
+ *
+ * `engine` must be NULL, or have been returned by
+ * [`cotton_ssdp_engine_new()`] and not yet freed.
+ *
+ * Mustn't be called from within one of the engine's callbacks: if it
+ * is, the engine isn't freed (see [`COTTON_SSDP_REENTERED`]).
+ */
+void cotton_ssdp_engine_free(struct cotton_ssdp_engine *engine);
+
+/**
+ * Tell the engine about a network interface, or a change in its state
+ *
+ * Only interfaces which are up have notifications or searches sent
+ * on them. An interface's addresses are added separately, using
+ * [`cotton_ssdp_engine_add_address()`].
+ *
+ * # Safety
+ *
+ * `engine` must have been returned by [`cotton_ssdp_engine_new()`]
+ * and not yet freed.
+ *
+ * Mustn't be called from within one of the engine's callbacks: if it
+ * is, returns [`COTTON_SSDP_REENTERED`] and does nothing.
+ */
+int cotton_ssdp_engine_add_interface(struct cotton_ssdp_engine *engine,
+                                     uint32_t interface,
+                                     bool up);
+
+/**
+ * Tell the engine that a network interface has gone away
+ *
+ * # Safety
+ *
+ * `engine` must have been returned by [`cotton_ssdp_engine_new()`]
+ * and not yet freed.
+ *
+ * Mustn't be called from within one of the engine's callbacks: if it
+ * is, returns [`COTTON_SSDP_REENTERED`] and does nothing.
+ */
+int cotton_ssdp_engine_remove_interface(struct cotton_ssdp_engine *engine, uint32_t interface);
+
+/**
+ * Tell the engine about a new IP address on a network interface
+ *
+ * # Safety
+ *
+ * `engine` must have been returned by [`cotton_ssdp_engine_new()`]
+ * and not yet freed.
+ *
+ * Mustn't be called from within one of the engine's callbacks: if it
+ * is, returns [`COTTON_SSDP_REENTERED`] and does nothing.
+ */
+int cotton_ssdp_engine_add_address(struct cotton_ssdp_engine *engine,
+                                   uint32_t interface,
+                                   struct cotton_ssdp_ipv4_addr address);
+
+/**
+ * Tell the engine that an IP address has been removed
+ *
+ * # Safety
+ *
+ * `engine` must have been returned by [`cotton_ssdp_engine_new()`]
+ * and not yet freed.
+ *
+ * Mustn't be called from within one of the engine's callbacks: if it
+ * is, returns [`COTTON_SSDP_REENTERED`] and does nothing.
+ */
+int cotton_ssdp_engine_remove_address(struct cotton_ssdp_engine *engine,
+                                      uint32_t interface,
+                                      struct cotton_ssdp_ipv4_addr address);
+
+/**
+ * Pass a received datagram to the engine
+ *
+ * `socket` says which socket it arrived on (one of the values of
+ * [`SsdpSocket`]), `to` the local address it was received on, and
+ * `from` where it came from.
+ *
+ * Returns [`COTTON_SSDP_INVALID_ARGUMENT`] if `socket` isn't one of
+ * the values of [`SsdpSocket`].
+ *
+ * # Safety
+ *
+ * `engine` must have been returned by [`cotton_ssdp_engine_new()`]
+ * and not yet freed; `buffer` must point to `length` readable bytes.
+ *
+ * Mustn't be called from within one of the engine's callbacks: if it
+ * is, returns [`COTTON_SSDP_REENTERED`] and does nothing.
+ */
+int cotton_ssdp_engine_on_data(struct cotton_ssdp_engine *engine,
+                               int socket,
+                               const uint8_t *buffer,
+                               size_t length,
+                               struct cotton_ssdp_ipv4_addr to,
+                               struct cotton_ssdp_socket_addr from,
+                               uint64_t now_ms);
+
+/**
+ * Deal with any expired timeouts
+ *
+ * Should be called once the time returned by
+ * [`cotton_ssdp_engine_poll_timeout()`] has been reached.
+ *
+ * # Safety
+ *
+ * `engine` must have been returned by [`cotton_ssdp_engine_new()`]
+ * and not yet freed.
+ *
+ * Mustn't be called from within one of the engine's callbacks: if it
+ * is, returns [`COTTON_SSDP_REENTERED`] and does nothing.
+ */
+int cotton_ssdp_engine_handle_timeout(struct cotton_ssdp_engine *engine, uint64_t now_ms);
+
+/**
+ * When the engine next needs [`cotton_ssdp_engine_handle_timeout()`]
+ * calling, in the same milliseconds as `now_ms`
+ *
+ * Returns `UINT64_MAX` if `engine` is NULL.
+ *
+ * # Safety
+ *
+ * `engine` must be NULL, or have been returned by
+ * [`cotton_ssdp_engine_new()`] and not yet freed.
+ *
+ * Mustn't be called from within one of the engine's callbacks: if it
+ * is, returns `UINT64_MAX` (see [`COTTON_SSDP_REENTERED`]).
+ */
+uint64_t cotton_ssdp_engine_poll_timeout(const struct cotton_ssdp_engine *engine);
+
+/**
+ * Subscribe to notifications of a particular type
+ *
+ * `callback` is called, with `context`, for each matching
+ * notification received (including responses to the searches which
+ * this sends). Subscriptions last until the engine is freed, or
+ * until the callback fails too many times in a row.
+ *
+ * Returns [`COTTON_SSDP_TOO_MANY`] if the limit on subscriptions has
+ * been reached.
+ *
+ * # Safety
+ *
+ * `engine` must have been returned by [`cotton_ssdp_engine_new()`]
+ * and not yet freed; `notification_type` must be a NUL-terminated
+ * string; `callback` must remain callable with `context` until the
+ * engine is freed.
+ *
+ * Mustn't be called from within one of the engine's callbacks: if it
+ * is, returns [`COTTON_SSDP_REENTERED`] and does nothing.
+ */
+int cotton_ssdp_engine_subscribe(struct cotton_ssdp_engine *engine,
+                                 const char *notification_type,
+                                 cotton_ssdp_notification_fn callback,
+                                 void *context);
+
+/**
+ * Advertise a local resource
+ *
+ * Replaces any existing advertisement with the same unique service
+ * name. The host part of `location` is replaced by the address of
+ * whichever interface each notification is sent from.
+ *
+ * Returns [`COTTON_SSDP_TOO_MANY`] if the limit on advertisements
+ * has been reached.
+ *
+ * # Safety
+ *
+ * `engine` must have been returned by [`cotton_ssdp_engine_new()`]
+ * and not yet freed; the strings must be NUL-terminated.
+ *
+ * Mustn't be called from within one of the engine's callbacks: if it
+ * is, returns [`COTTON_SSDP_REENTERED`] and does nothing.
+ */
+int cotton_ssdp_engine_advertise(struct cotton_ssdp_engine *engine,
+                                 const char *unique_service_name,
+                                 const char *notification_type,
+                                 const char *location);
+
+/**
+ * Withdraw an advertisement, sending bye-bye notifications
+ *
+ * # Safety
+ *
+ * `engine` must have been returned by [`cotton_ssdp_engine_new()`]
+ * and not yet freed; `unique_service_name` must be NUL-terminated.
+ *
+ * Mustn't be called from within one of the engine's callbacks: if it
+ * is, returns [`COTTON_SSDP_REENTERED`] and does nothing.
+ */
+int cotton_ssdp_engine_deadvertise(struct cotton_ssdp_engine *engine,
+                                   const char *unique_service_name);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* COTTON_SSDP_H */
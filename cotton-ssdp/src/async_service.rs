@@ -1,12 +1,22 @@
-use crate::engine::{Callback, Engine};
+use crate::diag::Diagnostics;
+use crate::engine::{
+    Callback, CallbackError, Engine, EngineConfig, HealthEvent,
+    ResponseSource, SocketKind,
+};
 use crate::refresh_timer::StdTimebase;
 use crate::udp;
 use crate::udp::TargetedReceive;
-use crate::{Advertisement, Notification};
-use futures::Stream;
+use crate::validate::{self, HttpClient, Reachability};
+use crate::{
+    Advertisement, DeviceAdvertisement, MatchMode, Notification, Scope,
+};
+use cotton_netif::InterfaceIndex;
+use futures::future::{self, Either};
+use futures::{Stream, StreamExt};
 use rand::RngCore;
+use std::borrow::Cow;
 use std::sync::{Arc, Mutex};
-use std::time::Instant;
+use std::time::{Duration, Instant};
 use tokio::sync::mpsc;
 use tokio_stream::wrappers::ReceiverStream;
 
@@ -15,8 +25,13 @@ struct AsyncCallback {
 }
 
 impl Callback for AsyncCallback {
-    fn on_notification(&self, n: &Notification) {
-        let _ = self.channel.try_send(n.clone());
+    fn on_notification(&self, n: &Notification) -> Result<(), CallbackError> {
+        // If the stream is merely full, the notification is dropped;
+        // if it's gone altogether, the subscription is no longer wanted
+        match self.channel.try_send(n.clone()) {
+            Err(mpsc::error::TrySendError::Closed(_)) => Err(CallbackError),
+            _ => Ok(()),
+        }
     }
 }
 
@@ -30,40 +45,98 @@ type FromStdFn =
 struct Inner {
     engine: Mutex<Engine<AsyncCallback, StdTimebase>>,
     multicast_socket: tokio::net::UdpSocket,
-    search_socket: tokio::net::UdpSocket,
+    /// `None` in single-socket mode
+    search_socket: Option<tokio::net::UdpSocket>,
+    health_channel: Mutex<Option<mpsc::Sender<HealthEvent>>>,
+    started: Instant,
 }
 
 impl Inner {
     fn new(
         engine: Engine<AsyncCallback, StdTimebase>,
+        port: u16,
     ) -> Result<Self, std::io::Error> {
         Self::new_inner(
             engine,
+            port,
             udp::std::setup_socket,
             tokio::net::UdpSocket::from_std,
+            false,
+        )
+    }
+
+    fn new_single_socket(
+        engine: Engine<AsyncCallback, StdTimebase>,
+        port: u16,
+    ) -> Result<Self, std::io::Error> {
+        Self::new_inner(
+            engine,
+            port,
+            udp::std::setup_socket,
+            tokio::net::UdpSocket::from_std,
+            true,
         )
     }
 
     fn new_inner(
         engine: Engine<AsyncCallback, StdTimebase>,
+        port: u16,
         setup_socket: SetupSocketFn,
         from_std: FromStdFn,
+        single_socket: bool,
     ) -> Result<Self, std::io::Error> {
-        let multicast_socket = setup_socket(1900u16)?;
-        let search_socket = setup_socket(0u16)?;
+        let multicast_socket = setup_socket(port)?;
+        let search_socket = if single_socket {
+            None
+        } else {
+            Some(setup_socket(0u16)?)
+        };
 
         // @todo IPv6 https://stackoverflow.com/questions/3062205/setting-the-source-ip-for-a-udp-socket
         Ok(Self {
             engine: Mutex::new(engine),
             multicast_socket: from_std(multicast_socket)?,
-            search_socket: from_std(search_socket)?,
+            search_socket: search_socket.map(from_std).transpose()?,
+            health_channel: Mutex::new(None),
+            started: Instant::now(),
         })
     }
+
+    /// The socket on which searches and notifications are sent
+    fn send_socket(&self) -> &tokio::net::UdpSocket {
+        self.search_socket
+            .as_ref()
+            .unwrap_or(&self.multicast_socket)
+    }
+
+    /// Pass any pending health events to the health channel
+    fn dispatch_health(
+        &self,
+        engine: &mut Engine<AsyncCallback, StdTimebase>,
+    ) {
+        if let Some(channel) = self.health_channel.lock().unwrap().as_ref() {
+            while let Some(event) = engine.poll_health_event() {
+                let _ = channel.try_send(event);
+            }
+        }
+    }
+}
+
+/// Wait until a socket, if there is one, is readable
+async fn readable(
+    socket: Option<&tokio::net::UdpSocket>,
+) -> Result<(), std::io::Error> {
+    match socket {
+        Some(socket) => socket.readable().await,
+        None => std::future::pending().await,
+    }
 }
 
 /// The type of [`Inner::new`]
-type InnerNewFn =
-    fn(Engine<AsyncCallback, StdTimebase>) -> Result<Inner, std::io::Error>;
+type InnerNewFn = fn(
+    Engine<AsyncCallback, StdTimebase>,
+    u16,
+) -> Result<Inner, std::io::Error>;
 
 /** High-level asynchronous SSDP service using tokio.
  *
@@ -75,6 +148,11 @@ pub struct AsyncService {
 }
 
 impl AsyncService {
+    /// How many LOCATION checks
+    /// [`subscribe_validated`](AsyncService::subscribe_validated) runs
+    /// at once
+    pub const MAX_CONCURRENT_CHECKS: usize = 8;
+
     /// Create a new `AsyncService`, including its two UDP sockets
     ///
     /// # Errors
@@ -88,14 +166,85 @@ impl AsyncService {
     /// a bug in cotton-ssdp.
     ///
     pub fn new() -> Result<Self, std::io::Error> {
-        Self::new_inner(Inner::new)
+        Self::with_config(EngineConfig::default())
+    }
+
+    /// Create a new `AsyncService`, with non-default tuning parameters
+    ///
+    /// As [`AsyncService::new`], but the `Engine` is created using
+    /// [`Engine::with_config`], and the multicast socket is bound to
+    /// [`EngineConfig::port`].
+    ///
+    /// # Errors
+    ///
+    /// Can return a `std::io::Error` if any of the underlying socket
+    /// calls fail.
+    ///
+    /// # Panics
+    ///
+    /// Will panic if the internal mutex cannot be locked; that would indicate
+    /// a bug in cotton-ssdp.
+    ///
+    pub fn with_config(config: EngineConfig) -> Result<Self, std::io::Error> {
+        Self::new_inner(Inner::new, config)
     }
 
-    fn new_inner(create: InnerNewFn) -> Result<Self, std::io::Error> {
-        let inner = Arc::new(create(Engine::new(
-            rand::thread_rng().next_u32(),
-            Instant::now(),
-        ))?);
+    /// Create a new `AsyncService` which uses just one UDP socket
+    ///
+    /// The socket, bound to port 1900, is used for searches and
+    /// replies as well as for multicast. This is only suitable if no
+    /// other SSDP implementation is running on the same host, as
+    /// unicast replies to searches might otherwise go to the wrong one.
+    ///
+    /// # Errors
+    ///
+    /// Can return a `std::io::Error` if any of the underlying socket
+    /// calls fail.
+    ///
+    /// # Panics
+    ///
+    /// Will panic if the internal mutex cannot be locked; that would indicate
+    /// a bug in cotton-ssdp.
+    ///
+    pub fn new_single_socket() -> Result<Self, std::io::Error> {
+        Self::single_socket_with_config(EngineConfig::default())
+    }
+
+    /// Create a new `AsyncService` which uses just one UDP socket,
+    /// with non-default tuning parameters
+    ///
+    /// As [`AsyncService::new_single_socket`], but see
+    /// [`AsyncService::with_config`].
+    ///
+    /// # Errors
+    ///
+    /// Can return a `std::io::Error` if any of the underlying socket
+    /// calls fail.
+    ///
+    /// # Panics
+    ///
+    /// Will panic if the internal mutex cannot be locked; that would indicate
+    /// a bug in cotton-ssdp.
+    ///
+    pub fn single_socket_with_config(
+        config: EngineConfig,
+    ) -> Result<Self, std::io::Error> {
+        Self::new_inner(Inner::new_single_socket, config)
+    }
+
+    fn new_inner(
+        create: InnerNewFn,
+        config: EngineConfig,
+    ) -> Result<Self, std::io::Error> {
+        let port = config.port;
+        let inner = Arc::new(create(
+            Engine::with_config(
+                rand::thread_rng().next_u32(),
+                Instant::now(),
+                config,
+            ),
+            port,
+        )?);
         let inner2 = inner.clone();
 
         tokio::spawn(async move {
@@ -105,21 +254,25 @@ impl AsyncService {
                         let mut buf = [0u8; 1500];
                         while let Ok((n, wasto, wasfrom)) =
                             inner.multicast_socket.receive_to(&mut buf) {
-                            inner.engine.lock().unwrap().on_data(
+                            inner.engine.lock().unwrap().on_socket_data(
                                 &buf[0..n],
+                                SocketKind::Port1900,
                                 wasto,
                                 wasfrom,
                                 Instant::now(),
                             );
                         }
                     },
-                    _ = inner.search_socket.readable() => {
+                    _ = readable(inner.search_socket.as_ref()) => {
                         let mut buf = [0u8; 1500];
-                        while let Ok((n, wasto, wasfrom)) =
-                            inner.search_socket.receive_to(&mut buf)
+                        while let Some(Ok((n, wasto, wasfrom))) = inner
+                            .search_socket
+                            .as_ref()
+                            .map(|s| s.receive_to(&mut buf))
                         {
-                            inner.engine.lock().unwrap().on_data(
+                            inner.engine.lock().unwrap().on_socket_data(
                                 &buf[0..n],
+                                SocketKind::Ephemeral,
                                 wasto,
                                 wasfrom,
                                 Instant::now(),
@@ -130,8 +283,13 @@ impl AsyncService {
                         inner.engine.lock().unwrap().poll_timeout()
                             - Instant::now()
                     ) => {
-                        inner.engine.lock().unwrap().handle_timeout(
-                            &inner.search_socket, Instant::now());
+                        let mut engine = inner.engine.lock().unwrap();
+                        engine.handle_timeout_with(
+                            inner.send_socket(),
+                            &inner.multicast_socket,
+                            Instant::now(),
+                        );
+                        inner.dispatch_health(&mut engine);
                     },
                 };
             }
@@ -159,11 +317,14 @@ impl AsyncService {
         &self,
         event: &cotton_netif::NetworkEvent,
     ) -> Result<(), udp::Error> {
-        self.inner.engine.lock().unwrap().on_network_event(
+        let mut engine = self.inner.engine.lock().unwrap();
+        let result = engine.on_network_event(
             event,
             &self.inner.multicast_socket,
-            &self.inner.search_socket,
-        )
+            self.inner.send_socket(),
+        );
+        self.inner.dispatch_health(&mut engine);
+        result
     }
 
     /// Subscribe to SSDP notifications for a resource type.
@@ -178,17 +339,84 @@ impl AsyncService {
         notification_type: A,
     ) -> impl Stream<Item = Notification>
     where
-        A: Into<String>,
+        A: Into<Cow<'static, str>>,
     {
         let (snd, rcv) = mpsc::channel(100);
-        self.inner.engine.lock().unwrap().subscribe(
+        let mut engine = self.inner.engine.lock().unwrap();
+        engine.subscribe(
             notification_type.into(),
             AsyncCallback { channel: snd },
-            &self.inner.search_socket,
+            self.inner.send_socket(),
         );
+        self.inner.dispatch_health(&mut engine);
         ReceiverStream::new(rcv)
     }
 
+    /// Subscribe to SSDP notifications selected by a [`MatchMode`]
+    ///
+    /// Searches are sent for `notification_type` (which can be
+    /// "ssdp:all"), but the stream yields every notification which
+    /// satisfies `match_mode`.
+    ///
+    /// # Panics
+    ///
+    /// Will panic if the internal mutex cannot be locked; that would indicate
+    /// a bug in cotton-ssdp.
+    ///
+    pub fn subscribe_matching<A>(
+        &mut self,
+        notification_type: A,
+        match_mode: MatchMode,
+    ) -> impl Stream<Item = Notification>
+    where
+        A: Into<Cow<'static, str>>,
+    {
+        let (snd, rcv) = mpsc::channel(100);
+        let mut engine = self.inner.engine.lock().unwrap();
+        engine.subscribe_matching(
+            notification_type.into(),
+            match_mode,
+            AsyncCallback { channel: snd },
+            self.inner.send_socket(),
+        );
+        self.inner.dispatch_health(&mut engine);
+        ReceiverStream::new(rcv)
+    }
+
+    /// Subscribe to SSDP notifications, checking their LOCATION URLs
+    ///
+    /// As [`AsyncService::subscribe`], but each ssdp:alive
+    /// notification is passed on only once its LOCATION has been
+    /// checked with [`validate::check`], using `client` and `timeout`;
+    /// the stream yields the result alongside the notification (and
+    /// `None` for ssdp:byebye, which is passed on straight away).
+    ///
+    /// The checks run on Tokio's blocking thread pool, up to
+    /// [`MAX_CONCURRENT_CHECKS`](AsyncService::MAX_CONCURRENT_CHECKS) at once, and each notification is
+    /// yielded as soon as its own check finishes: so one unreachable
+    /// LOCATION doesn't hold up the others, but notifications can
+    /// come out in a different order from that in which they arrived.
+    /// In particular, an ssdp:byebye can overtake the ssdp:alive that
+    /// preceded it, if that alive's check is still running.
+    ///
+    /// # Panics
+    ///
+    /// Will panic if the internal mutex cannot be locked; that would indicate
+    /// a bug in cotton-ssdp.
+    ///
+    pub fn subscribe_validated<A, C>(
+        &mut self,
+        notification_type: A,
+        client: C,
+        timeout: Duration,
+    ) -> impl Stream<Item = (Notification, Option<Reachability>)>
+    where
+        A: Into<Cow<'static, str>>,
+        C: HttpClient + Send + Sync + 'static,
+    {
+        validate_stream(self.subscribe(notification_type), client, timeout)
+    }
+
     /// Announce a new resource
     ///
     /// And start responding to any searches matching it.
@@ -203,13 +431,15 @@ impl AsyncService {
         unique_service_name: USN,
         advertisement: Advertisement,
     ) where
-        USN: Into<String>,
+        USN: Into<Cow<'static, str>>,
     {
-        self.inner.engine.lock().unwrap().advertise(
+        let mut engine = self.inner.engine.lock().unwrap();
+        engine.advertise(
             unique_service_name.into(),
             advertisement,
-            &self.inner.search_socket,
+            self.inner.send_socket(),
         );
+        self.inner.dispatch_health(&mut engine);
     }
 
     /// Announce the disappearance of a resource
@@ -222,14 +452,210 @@ impl AsyncService {
     /// a bug in cotton-ssdp.
     ///
     pub fn deadvertise(&mut self, unique_service_name: &str) {
+        let mut engine = self.inner.engine.lock().unwrap();
+        engine.deadvertise(unique_service_name, self.inner.send_socket());
+        self.inner.dispatch_health(&mut engine);
+    }
+
+    /// Change an existing advertisement, without withdrawing it
+    ///
+    /// For instance, to announce a new LOCATION without the
+    /// ssdp:byebye that `deadvertise` followed by `advertise` would
+    /// send; see [`Engine::update_advertisement`]. Returns `false` if
+    /// there is no advertisement with that unique service name.
+    ///
+    /// # Panics
+    ///
+    /// Will panic if the internal mutex cannot be locked; that would indicate
+    /// a bug in cotton-ssdp.
+    ///
+    pub fn update_advertisement(
+        &mut self,
+        unique_service_name: &str,
+        advertisement: Advertisement,
+    ) -> bool {
+        let mut engine = self.inner.engine.lock().unwrap();
+        let updated = engine.update_advertisement(
+            unique_service_name,
+            advertisement,
+            self.inner.send_socket(),
+        );
+        self.inner.dispatch_health(&mut engine);
+        updated
+    }
+
+    /// Change how far an advertisement's notifications are sent
+    ///
+    /// Advertisements are link-local unless this is called; see
+    /// [`Engine::set_advertisement_scope`]. Returns `false` if there
+    /// is no advertisement with that unique service name.
+    ///
+    /// # Panics
+    ///
+    /// Will panic if the internal mutex cannot be locked; that would indicate
+    /// a bug in cotton-ssdp.
+    ///
+    pub fn set_advertisement_scope(
+        &mut self,
+        unique_service_name: &str,
+        scope: Scope,
+    ) -> bool {
+        let mut engine = self.inner.engine.lock().unwrap();
+        let found = engine.set_advertisement_scope(
+            unique_service_name,
+            scope,
+            self.inner.send_socket(),
+        );
+        self.inner.dispatch_health(&mut engine);
+        found
+    }
+
+    /// Change which socket responses to searches are sent from
+    ///
+    /// By default, they're sent from the search socket (on an ephemeral
+    /// port), or in single-socket mode from the only socket there is.
+    /// See [`ResponseSource`].
+    ///
+    /// # Panics
+    ///
+    /// Will panic if the internal mutex cannot be locked; that would indicate
+    /// a bug in cotton-ssdp.
+    ///
+    pub fn set_response_source(&mut self, source: ResponseSource) {
+        self.inner
+            .engine
+            .lock()
+            .unwrap()
+            .set_response_source(source);
+    }
+
+    /// Respond to searches arriving on an interface without delay
+    ///
+    /// **Not compliant with the UPnP standard**: for deterministic
+    /// timing in tests only. See [`Engine::set_immediate_responses`].
+    ///
+    /// # Panics
+    ///
+    /// Will panic if the internal mutex cannot be locked; that would indicate
+    /// a bug in cotton-ssdp.
+    ///
+    pub fn set_immediate_responses(
+        &mut self,
+        interface: InterfaceIndex,
+        immediate: bool,
+    ) {
         self.inner
             .engine
             .lock()
             .unwrap()
-            .deadvertise(unique_service_name, &self.inner.search_socket);
+            .set_immediate_responses(interface, immediate);
+    }
+
+    /// Announce a whole UPnP device, with all its resources
+    ///
+    /// Each resource is advertised with the USN appropriate to its
+    /// notification type; see [`DeviceAdvertisement`].
+    ///
+    /// # Panics
+    ///
+    /// Will panic if the internal mutex cannot be locked; that would indicate
+    /// a bug in cotton-ssdp.
+    ///
+    pub fn advertise_device(&mut self, device: &DeviceAdvertisement) {
+        let mut engine = self.inner.engine.lock().unwrap();
+        engine.advertise_device(device, self.inner.send_socket());
+        self.inner.dispatch_health(&mut engine);
+    }
+
+    /// Announce the disappearance of a whole UPnP device
+    ///
+    /// # Panics
+    ///
+    /// Will panic if the internal mutex cannot be locked; that would indicate
+    /// a bug in cotton-ssdp.
+    ///
+    pub fn deadvertise_device(&mut self, device: &DeviceAdvertisement) {
+        let mut engine = self.inner.engine.lock().unwrap();
+        engine.deadvertise_device(device, self.inner.send_socket());
+        self.inner.dispatch_health(&mut engine);
+    }
+
+    /// Be told when sending starts (or stops) failing on an interface
+    ///
+    /// The stream yields each [`HealthEvent`] as it occurs (or, for
+    /// any that occurred before this was called, soon afterwards).
+    /// Applications can use this to re-create the `AsyncService`, or
+    /// to alert an operator, if sending fails persistently (three
+    /// times in a row on the same interface). Only one such stream is
+    /// active at once: calling this again replaces it.
+    ///
+    /// # Panics
+    ///
+    /// Will panic if the internal mutex cannot be locked; that would indicate
+    /// a bug in cotton-ssdp.
+    ///
+    pub fn health_events(&mut self) -> impl Stream<Item = HealthEvent> {
+        let (snd, rcv) = mpsc::channel(16);
+        *self.inner.health_channel.lock().unwrap() = Some(snd);
+        let mut engine = self.inner.engine.lock().unwrap();
+        self.inner.dispatch_health(&mut engine);
+        ReceiverStream::new(rcv)
+    }
+
+    /// Report the service's state, for diagnostic purposes
+    ///
+    /// See [`crate::diag`].
+    ///
+    /// # Panics
+    ///
+    /// Will panic if the internal mutex cannot be locked; that would indicate
+    /// a bug in cotton-ssdp.
+    ///
+    #[must_use]
+    pub fn diagnostics(&self) -> Diagnostics {
+        self.inner
+            .engine
+            .lock()
+            .unwrap()
+            .diagnostics(self.inner.started.elapsed())
     }
 }
 
+/// Check the LOCATION of each ssdp:alive in `notifications`
+///
+/// The guts of [`AsyncService::subscribe_validated`], separated out
+/// so that it can be tested without any sockets.
+fn validate_stream<S, C>(
+    notifications: S,
+    client: C,
+    timeout: Duration,
+) -> impl Stream<Item = (Notification, Option<Reachability>)>
+where
+    S: Stream<Item = Notification>,
+    C: HttpClient + Send + Sync + 'static,
+{
+    let client = Arc::new(client);
+    notifications
+        .map(move |notification| {
+            if !matches!(notification, Notification::Alive { .. }) {
+                return Either::Left(future::ready((notification, None)));
+            }
+            let client = client.clone();
+            Either::Right(async move {
+                let reachability = tokio::task::spawn_blocking({
+                    let notification = notification.clone();
+                    move || validate::check(&*client, &notification, timeout)
+                })
+                .await
+                .unwrap_or(Some(Reachability::Unreachable(
+                    std::io::ErrorKind::Other,
+                )));
+                (notification, reachability)
+            })
+        })
+        .buffer_unordered(AsyncService::MAX_CONCURRENT_CHECKS)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -249,7 +675,13 @@ mod tests {
     fn service_passes_on_socket_failure() {
         let engine =
             Engine::<AsyncCallback, StdTimebase>::new(0u32, Instant::now());
-        let e = Inner::new_inner(engine, |_| Err(my_err()), bogus_fromstd);
+        let e = Inner::new_inner(
+            engine,
+            1900,
+            |_| Err(my_err()),
+            bogus_fromstd,
+            false,
+        );
 
         assert!(e.is_err());
     }
@@ -261,6 +693,7 @@ mod tests {
             Engine::<AsyncCallback, StdTimebase>::new(0u32, Instant::now());
         let e = Inner::new_inner(
             engine,
+            1900,
             |p| {
                 if p == 0 {
                     Err(my_err())
@@ -269,6 +702,7 @@ mod tests {
                 }
             },
             bogus_fromstd,
+            false,
         );
 
         assert!(e.is_err());
@@ -281,8 +715,10 @@ mod tests {
             Engine::<AsyncCallback, StdTimebase>::new(0u32, Instant::now());
         let e = Inner::new_inner(
             engine,
+            1900,
             crate::udp::std::setup_socket,
             bogus_fromstd,
+            false,
         );
 
         assert!(e.is_err());
@@ -302,6 +738,7 @@ mod tests {
                 );
                 let e = Inner::new_inner(
                     engine,
+                    1900,
                     crate::udp::std::setup_socket,
                     |s| {
                         if s.local_addr().unwrap().port() == 1900u16 {
@@ -310,12 +747,48 @@ mod tests {
                             Err(my_err())
                         }
                     },
+                    false,
                 );
 
                 assert!(e.is_err());
             });
     }
 
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn single_socket_needs_only_one_socket() {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(async {
+                let engine = Engine::<AsyncCallback, StdTimebase>::new(
+                    0u32,
+                    Instant::now(),
+                );
+                let e = Inner::new_inner(
+                    engine,
+                    1900,
+                    |p| {
+                        if p == 0 {
+                            Err(my_err())
+                        } else {
+                            crate::udp::std::setup_socket(p)
+                        }
+                    },
+                    tokio::net::UdpSocket::from_std,
+                    true,
+                );
+
+                let inner = e.unwrap();
+                assert!(inner.search_socket.is_none());
+                assert_eq!(
+                    inner.send_socket().local_addr().unwrap().port(),
+                    1900
+                );
+            });
+    }
+
     #[test]
     #[cfg_attr(miri, ignore)]
     fn service_passes_on_inner_failure() {
@@ -324,7 +797,10 @@ mod tests {
             .build()
             .unwrap()
             .block_on(async {
-                let e = AsyncService::new_inner(|_| Err(my_err()));
+                let e = AsyncService::new_inner(
+                    |_, _| Err(my_err()),
+                    EngineConfig::default(),
+                );
                 assert!(e.is_err());
             });
     }
@@ -341,4 +817,142 @@ mod tests {
                 assert!(e.is_ok());
             });
     }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn single_socket_service_succeeds() {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(async {
+                let mut s = AsyncService::new_single_socket().unwrap();
+                let _ = s.subscribe("ssdp:all");
+            });
+    }
+
+    /// Answers 200 at once, except for "http://slow/", which waits
+    /// until told to go ahead
+    struct GatedClient {
+        requests: Mutex<Vec<String>>,
+        gate: Mutex<std::sync::mpsc::Receiver<()>>,
+    }
+
+    impl HttpClient for GatedClient {
+        fn request(
+            &self,
+            _: validate::Method,
+            url: &str,
+            _: Duration,
+        ) -> std::io::Result<u16> {
+            self.requests.lock().unwrap().push(url.to_string());
+            if url == "http://slow/" {
+                _ = self.gate.lock().unwrap().recv();
+            }
+            Ok(200)
+        }
+    }
+
+    fn alive(location: &'static str) -> Notification {
+        Notification::Alive {
+            notification_type: "upnp:rootdevice".into(),
+            unique_service_name: location.into(),
+            location: location.into(),
+            boot_id: None,
+        }
+    }
+
+    fn usn(
+        (n, r): (Notification, Option<Reachability>),
+    ) -> (String, Option<Reachability>) {
+        match n {
+            Notification::Alive {
+                unique_service_name,
+                ..
+            }
+            | Notification::ByeBye {
+                unique_service_name,
+                ..
+            }
+            | Notification::Expired {
+                unique_service_name,
+                ..
+            } => (unique_service_name.into_owned(), r),
+        }
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn validation_runs_concurrently() {
+        let (go, gate) = std::sync::mpsc::channel();
+        let client = Arc::new(GatedClient {
+            requests: Mutex::default(),
+            gate: Mutex::new(gate),
+        });
+        let byebye = Notification::ByeBye {
+            notification_type: "upnp:rootdevice".into(),
+            unique_service_name: "http://gone/".into(),
+        };
+
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(async {
+                let mut s = std::pin::pin!(validate_stream(
+                    futures::stream::iter([
+                        alive("http://slow/"),
+                        alive("http://fast/"),
+                        byebye,
+                    ]),
+                    ClientRef(client.clone()),
+                    Duration::from_secs(5),
+                ));
+
+                // Neither the fast check nor the byebye waits for the
+                // slow check
+                let mut early = vec![usn(s.next().await.unwrap())];
+                early.push(usn(s.next().await.unwrap()));
+                early.sort_by(|a, b| a.0.cmp(&b.0));
+                assert_eq!(
+                    early,
+                    vec![
+                        (
+                            "http://fast/".to_string(),
+                            Some(Reachability::Reachable)
+                        ),
+                        ("http://gone/".to_string(), None),
+                    ]
+                );
+
+                go.send(()).unwrap();
+                assert_eq!(
+                    usn(s.next().await.unwrap()),
+                    (
+                        "http://slow/".to_string(),
+                        Some(Reachability::Reachable)
+                    )
+                );
+                assert!(s.next().await.is_none());
+            });
+
+        // The byebye was never checked
+        let mut requests = client.requests.lock().unwrap().clone();
+        requests.sort();
+        assert_eq!(requests, vec!["http://fast/", "http://slow/"]);
+    }
+
+    /// Lets the test keep hold of the client, to look at it afterwards
+    struct ClientRef(Arc<GatedClient>);
+
+    impl HttpClient for ClientRef {
+        fn request(
+            &self,
+            method: validate::Method,
+            url: &str,
+            timeout: Duration,
+        ) -> std::io::Result<u16> {
+            self.0.request(method, url, timeout)
+        }
+    }
 }
@@ -0,0 +1,428 @@
+use crate::Notification;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+use serde::{Deserialize, Serialize};
+
+/// Version number of the serialized format, see [`DeviceCache::to_bytes`]
+const FORMAT_VERSION: u8 = 1;
+
+/// A discovered resource, as recorded in a [`DeviceCache`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CachedDevice {
+    /// Resource type
+    pub notification_type: String,
+
+    /// Unique identifier for this particular resource instance
+    pub unique_service_name: String,
+
+    /// URL of the resource
+    pub location: String,
+
+    /// When the resource should be forgotten unless refreshed, in
+    /// seconds on the cache's clock
+    pub expires: u64,
+}
+
+impl CachedDevice {
+    /// The "alive" notification which this entry records
+    pub fn notification(&self) -> Notification {
+        Notification::Alive {
+            notification_type: self.notification_type.clone().into(),
+            unique_service_name: self.unique_service_name.clone().into(),
+            location: self.location.clone().into(),
+            boot_id: None,
+        }
+    }
+}
+
+/// The serialized form of a [`DeviceCache`]
+#[derive(Serialize, Deserialize)]
+struct Snapshot {
+    version: u8,
+    entries: Vec<CachedDevice>,
+}
+
+/// Errors from [`DeviceCache::to_bytes`] and [`DeviceCache::restore`]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CacheError {
+    /// The data isn't a serialized `DeviceCache`, or is truncated
+    Malformed,
+    /// The data was written by an incompatible version of this crate
+    UnsupportedVersion,
+}
+
+impl core::fmt::Display for CacheError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Malformed => f.write_str("malformed device cache"),
+            Self::UnsupportedVersion => {
+                f.write_str("unsupported device cache version")
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for CacheError {}
+
+/// Remembers discovered resources, and saves them across sleeps
+///
+/// Pass a `DeviceCache` each [`Notification`] from an SSDP
+/// subscription, and it keeps track of which resources are currently
+/// alive, and where. Devices which sleep -- for instance, in Wi-Fi
+/// power-save modes -- can save the cache as a compact binary blob
+/// ([`DeviceCache::to_bytes`], using
+/// [postcard](https://crates.io/crates/postcard)) before sleeping,
+/// and [`restore`](DeviceCache::restore) it on waking, rather than
+/// waiting for every resource to be announced again.
+///
+/// # Time
+///
+/// All times are in seconds, on whatever clock the caller chooses,
+/// as long as it keeps counting while the device is asleep: a
+/// real-time clock, or Unix time, but not (usually) a monotonic
+/// timer which stops during sleep.
+///
+/// Notifications don't report the max-age of the advertisement they
+/// came from, so every resource is given the cache's max-age (by
+/// default 1800s, the same max-age that cotton-ssdp itself
+/// advertises). A resource which is neither refreshed by a further
+/// "alive" notification within that time, nor withdrawn by a
+/// "bye-bye", is dropped by [`DeviceCache::expire`].
+///
+/// When restoring, entries which expired while the device was
+/// asleep are dropped, and entries which claim to last longer than
+/// the max-age from now (as happens if the clock has gone backwards)
+/// are cut back to it.
+#[derive(Debug, Clone)]
+pub struct DeviceCache {
+    max_age: u64,
+    entries: BTreeMap<String, CachedDevice>,
+}
+
+impl Default for DeviceCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DeviceCache {
+    /// Create a new, empty `DeviceCache` with the default max-age
+    pub fn new() -> Self {
+        Self::with_max_age(1800)
+    }
+
+    /// Create a new, empty `DeviceCache` with a specific max-age, in
+    /// seconds
+    pub fn with_max_age(max_age: u64) -> Self {
+        Self {
+            max_age,
+            entries: BTreeMap::new(),
+        }
+    }
+
+    /// Handle an incoming SSDP notification
+    ///
+    /// Alive notifications add or refresh an entry; bye-bye (and
+    /// expired) notifications remove it.
+    pub fn on_notification(&mut self, notification: &Notification, now: u64) {
+        match notification {
+            Notification::Alive {
+                notification_type,
+                unique_service_name,
+                location,
+                ..
+            } => {
+                self.entries.insert(
+                    unique_service_name.clone().into_owned(),
+                    CachedDevice {
+                        notification_type: notification_type
+                            .clone()
+                            .into_owned(),
+                        unique_service_name: unique_service_name
+                            .clone()
+                            .into_owned(),
+                        location: location.clone().into_owned(),
+                        expires: now.saturating_add(self.max_age),
+                    },
+                );
+            }
+            Notification::ByeBye {
+                unique_service_name,
+                ..
+            }
+            | Notification::Expired {
+                unique_service_name,
+                ..
+            } => {
+                self.entries.remove(unique_service_name.as_ref());
+            }
+        }
+    }
+
+    /// Drop any entries which haven't been refreshed in time
+    ///
+    /// Returns the number of entries dropped.
+    pub fn expire(&mut self, now: u64) -> usize {
+        let before = self.entries.len();
+        self.entries.retain(|_, d| d.expires > now);
+        before - self.entries.len()
+    }
+
+    /// Look up a resource by its unique service name
+    pub fn get(&self, unique_service_name: &str) -> Option<&CachedDevice> {
+        self.entries.get(unique_service_name)
+    }
+
+    /// Iterate over all the cached resources, in order of USN
+    pub fn iter(&self) -> impl Iterator<Item = &CachedDevice> {
+        self.entries.values()
+    }
+
+    /// How many resources are cached
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Is the cache empty?
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Serialize the cache to a compact binary blob
+    ///
+    /// The result can be passed to [`DeviceCache::restore`] later,
+    /// even by a later version of the program (the format is
+    /// versioned).
+    ///
+    /// # Errors
+    ///
+    /// Returns [`CacheError::Malformed`] if serialization fails,
+    /// which in practice only happens if memory runs out.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, CacheError> {
+        let snapshot = Snapshot {
+            version: FORMAT_VERSION,
+            entries: self.entries.values().cloned().collect(),
+        };
+        postcard::to_allocvec(&snapshot).map_err(|_| CacheError::Malformed)
+    }
+
+    /// Add entries from a blob written by [`DeviceCache::to_bytes`]
+    ///
+    /// Entries which have expired by `now` are dropped, and those
+    /// expiring later than the max-age from `now` are cut back to
+    /// it. Restored entries replace any existing entries with the
+    /// same USN. Returns the number of entries restored.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`CacheError::UnsupportedVersion`] if the blob came
+    /// from an incompatible version of this crate, or
+    /// [`CacheError::Malformed`] if it can't be read at all. Either
+    /// way, the cache is left unchanged.
+    pub fn restore(
+        &mut self,
+        bytes: &[u8],
+        now: u64,
+    ) -> Result<usize, CacheError> {
+        match bytes.first() {
+            None => return Err(CacheError::Malformed),
+            Some(&FORMAT_VERSION) => {}
+            Some(_) => return Err(CacheError::UnsupportedVersion),
+        }
+        let snapshot: Snapshot =
+            postcard::from_bytes(bytes).map_err(|_| CacheError::Malformed)?;
+        let latest = now.saturating_add(self.max_age);
+        let mut restored = 0;
+        for mut entry in snapshot.entries {
+            if entry.expires <= now {
+                continue;
+            }
+            entry.expires = entry.expires.min(latest);
+            self.entries
+                .insert(entry.unique_service_name.clone(), entry);
+            restored += 1;
+        }
+        Ok(restored)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+
+    fn alive(usn: &str, location: &str) -> Notification {
+        Notification::Alive {
+            notification_type: "upnp:rootdevice".into(),
+            unique_service_name: usn.to_string().into(),
+            location: location.to_string().into(),
+            boot_id: None,
+        }
+    }
+
+    fn byebye(usn: &str) -> Notification {
+        Notification::ByeBye {
+            notification_type: "upnp:rootdevice".into(),
+            unique_service_name: usn.to_string().into(),
+        }
+    }
+
+    #[test]
+    fn alive_adds_entry() {
+        let mut cache = DeviceCache::new();
+        assert!(cache.is_empty());
+        cache.on_notification(&alive("uuid:a", "http://a/"), 100);
+        assert_eq!(cache.len(), 1);
+        let entry = cache.get("uuid:a").unwrap();
+        assert_eq!(entry.location, "http://a/");
+        assert_eq!(entry.expires, 1900);
+    }
+
+    #[test]
+    fn alive_refreshes_entry() {
+        let mut cache = DeviceCache::new();
+        cache.on_notification(&alive("uuid:a", "http://a/"), 100);
+        cache.on_notification(&alive("uuid:a", "http://b/"), 200);
+        assert_eq!(cache.len(), 1);
+        let entry = cache.get("uuid:a").unwrap();
+        assert_eq!(entry.location, "http://b/");
+        assert_eq!(entry.expires, 2000);
+    }
+
+    #[test]
+    fn byebye_removes_entry() {
+        let mut cache = DeviceCache::new();
+        cache.on_notification(&alive("uuid:a", "http://a/"), 100);
+        cache.on_notification(&byebye("uuid:a"), 200);
+        assert!(cache.is_empty());
+        cache.on_notification(&byebye("uuid:b"), 200);
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn expire_drops_stale_entries() {
+        let mut cache = DeviceCache::with_max_age(60);
+        cache.on_notification(&alive("uuid:a", "http://a/"), 100);
+        cache.on_notification(&alive("uuid:b", "http://b/"), 130);
+        assert_eq!(cache.expire(159), 0);
+        assert_eq!(cache.expire(160), 1);
+        assert!(cache.get("uuid:a").is_none());
+        assert!(cache.get("uuid:b").is_some());
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut cache = DeviceCache::new();
+        cache.on_notification(&alive("uuid:a", "http://a/"), 100);
+        cache.on_notification(&alive("uuid:b", "http://b/"), 100);
+        let bytes = cache.to_bytes().unwrap();
+        assert_eq!(bytes[0], FORMAT_VERSION);
+
+        let mut restored = DeviceCache::new();
+        assert_eq!(restored.restore(&bytes, 500), Ok(2));
+        assert_eq!(
+            restored.iter().collect::<Vec<_>>(),
+            cache.iter().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn blob_is_compact() {
+        let mut cache = DeviceCache::new();
+        cache.on_notification(&alive("uuid:a", "http://a/"), 100);
+        let bytes = cache.to_bytes().unwrap();
+        // version, count, three length-prefixed strings, varint expiry
+        assert_eq!(bytes.len(), 1 + 1 + 16 + 7 + 10 + 2);
+    }
+
+    #[test]
+    fn restore_drops_expired() {
+        let mut cache = DeviceCache::with_max_age(60);
+        cache.on_notification(&alive("uuid:a", "http://a/"), 100);
+        cache.on_notification(&alive("uuid:b", "http://b/"), 130);
+        let bytes = cache.to_bytes().unwrap();
+
+        let mut restored = DeviceCache::with_max_age(60);
+        assert_eq!(restored.restore(&bytes, 170), Ok(1));
+        assert!(restored.get("uuid:a").is_none());
+        assert_eq!(restored.get("uuid:b").unwrap().expires, 190);
+    }
+
+    #[test]
+    fn restore_clamps_to_max_age() {
+        let mut cache = DeviceCache::new();
+        cache.on_notification(&alive("uuid:a", "http://a/"), 10_000);
+        let bytes = cache.to_bytes().unwrap();
+
+        // Clock has gone backwards
+        let mut restored = DeviceCache::with_max_age(60);
+        assert_eq!(restored.restore(&bytes, 5_000), Ok(1));
+        assert_eq!(restored.get("uuid:a").unwrap().expires, 5_060);
+    }
+
+    #[test]
+    fn restore_replaces_existing() {
+        let mut cache = DeviceCache::new();
+        cache.on_notification(&alive("uuid:a", "http://new/"), 100);
+        let bytes = cache.to_bytes().unwrap();
+
+        let mut restored = DeviceCache::new();
+        restored.on_notification(&alive("uuid:a", "http://old/"), 0);
+        restored.on_notification(&alive("uuid:b", "http://b/"), 0);
+        assert_eq!(restored.restore(&bytes, 100), Ok(1));
+        assert_eq!(restored.len(), 2);
+        assert_eq!(restored.get("uuid:a").unwrap().location, "http://new/");
+    }
+
+    #[test]
+    fn restore_rejects_bad_data() {
+        let mut cache = DeviceCache::new();
+        cache.on_notification(&alive("uuid:a", "http://a/"), 100);
+        let bytes = cache.to_bytes().unwrap();
+
+        let mut restored = DeviceCache::new();
+        assert_eq!(restored.restore(&[], 100), Err(CacheError::Malformed));
+        assert_eq!(
+            restored.restore(&bytes[..bytes.len() - 1], 100),
+            Err(CacheError::Malformed)
+        );
+        let mut future = bytes.clone();
+        future[0] = FORMAT_VERSION + 1;
+        assert_eq!(
+            restored.restore(&future, 100),
+            Err(CacheError::UnsupportedVersion)
+        );
+        assert!(restored.is_empty());
+    }
+
+    #[test]
+    fn entry_notification() {
+        let mut cache = DeviceCache::new();
+        let n = alive("uuid:a", "http://a/");
+        cache.on_notification(&n, 100);
+        let Notification::Alive {
+            unique_service_name,
+            location,
+            ..
+        } = cache.get("uuid:a").unwrap().notification()
+        else {
+            panic!("alive expected");
+        };
+        assert_eq!(unique_service_name, "uuid:a");
+        assert_eq!(location, "http://a/");
+    }
+
+    #[test]
+    fn error_display() {
+        assert_eq!(
+            CacheError::Malformed.to_string(),
+            "malformed device cache"
+        );
+        assert_eq!(
+            CacheError::UnsupportedVersion.to_string(),
+            "unsupported device cache version"
+        );
+    }
+}
@@ -0,0 +1,145 @@
+use crate::engine::{MemoryUsage, Statistics};
+#[cfg(feature = "advertise")]
+use crate::nt;
+#[cfg(feature = "advertise")]
+use crate::Advertisement;
+#[cfg(not(feature = "std"))]
+use alloc::{format, string::String};
+
+/// The version of cotton-ssdp, as reported in [`Diagnostics`]
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// A snapshot of an SSDP engine's state, for health-checking
+///
+/// Obtained from [`Engine::diagnostics`](crate::engine::Engine::diagnostics)
+/// (or the `diagnostics` method of [`Service`](crate::Service) or
+/// [`AsyncService`](crate::AsyncService)).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Diagnostics {
+    /// The version of cotton-ssdp in use
+    pub version: &'static str,
+    /// How long the engine has been running
+    pub uptime: core::time::Duration,
+    /// What the engine is storing
+    pub memory: MemoryUsage,
+    /// How many packets the engine has sent and received
+    pub statistics: Statistics,
+}
+
+impl Diagnostics {
+    /// Format as a small JSON document, suitable for a status page
+    ///
+    /// An application which advertises diagnostics (see
+    /// [`advertisement`]) can serve this, with content type
+    /// "application/json", at the advertised LOCATION.
+    #[must_use]
+    pub fn to_json(&self) -> String {
+        let m = &self.memory;
+        let s = &self.statistics;
+        format!(
+            concat!(
+                "{{\"version\":\"{}\",\"uptime_secs\":{},",
+                "\"interfaces\":{},\"addresses\":{},",
+                "\"advertisements\":{},\"subscriptions\":{},",
+                "\"queued_responses\":{},\"heap_bytes\":{},",
+                "\"packets_received\":{},\"invalid_packets\":{},",
+                "\"notifications_received\":{},\"searches_received\":{},",
+                "\"responses_received\":{},\"packets_sent\":{},",
+                "\"send_errors\":{}}}"
+            ),
+            self.version,
+            self.uptime.as_secs(),
+            m.interfaces,
+            m.addresses,
+            m.advertisements,
+            m.subscriptions,
+            m.queued_responses,
+            m.total_bytes(),
+            s.packets_received,
+            s.invalid_packets,
+            s.notifications_received,
+            s.searches_received,
+            s.responses_received,
+            s.packets_sent,
+            s.send_errors,
+        )
+    }
+}
+
+/// The diagnostic advertisement for a device, with its USN
+///
+/// Advertising this (with, for instance,
+/// [`Service::advertise`](crate::Service::advertise)) makes the
+/// device findable by searching for [`nt::COTTON_SSDP_DIAG_1`], which
+/// other devices won't answer. The `location` should be the URL of a
+/// status page, such as one serving [`Diagnostics::to_json`]; `uuid`
+/// is the device's UUID, as for [`crate::usn::format`].
+#[cfg(feature = "advertise")]
+#[must_use]
+pub fn advertisement(uuid: &str, location: String) -> (String, Advertisement) {
+    (
+        crate::usn::format(uuid, nt::COTTON_SSDP_DIAG_1.as_str()),
+        Advertisement::new(nt::COTTON_SSDP_DIAG_1.as_str(), location),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "advertise")]
+    use alloc::string::ToString;
+
+    #[test]
+    fn json_includes_everything() {
+        let d = Diagnostics {
+            version: "1.2.3",
+            uptime: core::time::Duration::from_millis(61_500),
+            memory: MemoryUsage {
+                interfaces: 2,
+                addresses: 3,
+                interface_bytes: 100,
+                advertisements: 4,
+                queued_responses: 1,
+                recent_searches: 0,
+                recent_search_bytes: 0,
+                advertisement_bytes: 200,
+                subscriptions: 5,
+                tracked_peers: 0,
+                subscription_bytes: 300,
+                shared_strings: 0,
+                shared_string_bytes: 0,
+            },
+            statistics: Statistics {
+                packets_received: 10,
+                invalid_packets: 1,
+                notifications_received: 6,
+                searches_received: 2,
+                responses_received: 1,
+                packets_sent: 20,
+                send_errors: 3,
+            },
+        };
+        assert_eq!(
+            d.to_json(),
+            concat!(
+                "{\"version\":\"1.2.3\",\"uptime_secs\":61,",
+                "\"interfaces\":2,\"addresses\":3,",
+                "\"advertisements\":4,\"subscriptions\":5,",
+                "\"queued_responses\":1,\"heap_bytes\":600,",
+                "\"packets_received\":10,\"invalid_packets\":1,",
+                "\"notifications_received\":6,\"searches_received\":2,",
+                "\"responses_received\":1,\"packets_sent\":20,",
+                "\"send_errors\":3}"
+            )
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "advertise")]
+    fn advertisement_has_diag_type() {
+        let (usn, a) = advertisement("uuid:37", "http://me/diag".to_string());
+        assert_eq!(usn, "uuid:37::urn:cotton:ssdp-diag:1");
+        assert_eq!(a.notification_type, "urn:cotton:ssdp-diag:1");
+        assert_eq!(a.location, "http://me/diag");
+    }
+}
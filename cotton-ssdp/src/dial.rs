@@ -0,0 +1,243 @@
+#[cfg(not(feature = "std"))]
+use alloc::{format, string::String};
+
+/// The response header in which a DIAL server gives its Application-URL
+///
+/// DIAL servers advertise [`nt::DIAL_1`](crate::nt::DIAL_1) over SSDP
+/// as usual; a client then fetches the advertised LOCATION, and the
+/// HTTP response to that carries this header alongside the device
+/// description. Applications on the server are then found at
+/// [`app_url`]s relative to it.
+pub const APPLICATION_URL_HEADER: &str = "Application-URL";
+
+/// Find the Application-URL in the head of an HTTP response
+///
+/// `response` is the response to a GET of a DIAL server's LOCATION:
+/// at least its status line and headers, and possibly (ignored) some
+/// of its body. Returns `None` unless the status is a success (2xx)
+/// and the header is present; the header name is matched regardless
+/// of case.
+///
+/// ```rust
+/// # use cotton_ssdp::dial::application_url;
+/// let response = b"HTTP/1.1 200 OK\r
+/// Content-Type: text/xml\r
+/// Application-URL: http://192.168.1.5:8008/apps\r
+/// \r
+/// <?xml version=\"1.0\"?>";
+/// assert_eq!(
+///     application_url(response),
+///     Some("http://192.168.1.5:8008/apps")
+/// );
+/// ```
+#[must_use]
+pub fn application_url(response: &[u8]) -> Option<&str> {
+    let end = response
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .unwrap_or(response.len());
+    let head = core::str::from_utf8(&response[..end]).ok()?;
+    let mut lines = head.lines();
+    let mut status = lines.next()?.split_ascii_whitespace();
+    match (status.next(), status.next()) {
+        (Some(version), Some(code))
+            if version.starts_with("HTTP/")
+                && code.len() == 3
+                && code.starts_with('2') => {}
+        _ => return None,
+    }
+    lines
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| {
+            name.trim().eq_ignore_ascii_case(APPLICATION_URL_HEADER)
+        })
+        .map(|(_, value)| value.trim())
+        .filter(|value| !value.is_empty())
+}
+
+/// The headers a DIAL server adds to its device-description response
+///
+/// As well as the Application-URL itself, this exposes it to
+/// cross-origin requests (as DIAL 2.1 requires), so that clients
+/// running in web browsers can read it. Each header is terminated by
+/// CRLF, ready to be written among the server's other response
+/// headers.
+#[must_use]
+pub fn application_url_headers(application_url: &str) -> String {
+    format!(
+        "{APPLICATION_URL_HEADER}: {application_url}\r\n\
+         Access-Control-Expose-Headers: {APPLICATION_URL_HEADER}\r\n"
+    )
+}
+
+/// The URL of one application on a DIAL server
+///
+/// GET this URL for the application's status, or POST to it to
+/// launch the application; `app_name` is a name from the DIAL
+/// registry, such as "YouTube".
+#[must_use]
+pub fn app_url(application_url: &str, app_name: &str) -> String {
+    format!("{}/{app_name}", application_url.trim_end_matches('/'))
+}
+
+/// Fetch a DIAL server's Application-URL, given its LOCATION
+///
+/// Makes a GET request of `location` (which must be an "http:" URL),
+/// using a minimal client like
+/// [`SimpleHttpClient`](crate::validate::SimpleHttpClient), and reads
+/// the response headers. This blocks for up to (roughly) `timeout`.
+///
+/// # Errors
+///
+/// Returns Err if no HTTP response was received, or
+/// [`std::io::ErrorKind::NotFound`] if it didn't include an
+/// Application-URL (or wasn't a success).
+#[cfg(all(feature = "std", feature = "subscribe"))]
+pub fn fetch_application_url(
+    location: &str,
+    timeout: std::time::Duration,
+) -> std::io::Result<String> {
+    use std::io::{ErrorKind, Read, Write};
+
+    let (authority, path) = crate::validate::split_url(location)?;
+    let mut stream = crate::validate::connect(authority, timeout)?;
+    write!(
+        stream,
+        "GET {path} HTTP/1.1\r\nHost: {authority}\r\nUser-Agent: cotton-ssdp\r\nConnection: close\r\n\r\n"
+    )?;
+
+    // Read just the head, which can't reasonably be longer than this
+    let mut buf = [0u8; 4096];
+    let mut len = 0;
+    while len < buf.len() {
+        let n = stream.read(&mut buf[len..])?;
+        if n == 0 {
+            break;
+        }
+        len += n;
+        if buf[..len].windows(4).any(|w| w == b"\r\n\r\n") {
+            break;
+        }
+    }
+    application_url(&buf[..len])
+        .map(String::from)
+        .ok_or_else(|| ErrorKind::NotFound.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn application_url_found() {
+        let r =
+            b"HTTP/1.1 200 OK\r\nAPPLICATION-URL:  http://tv/apps/ \r\n\r\n";
+        assert_eq!(application_url(r), Some("http://tv/apps/"));
+    }
+
+    #[test]
+    fn application_url_without_body() {
+        let r = b"HTTP/1.0 200 OK\r\napplication-url: http://tv/apps";
+        assert_eq!(application_url(r), Some("http://tv/apps"));
+    }
+
+    #[test]
+    fn application_url_absent() {
+        let r = b"HTTP/1.1 200 OK\r\nContent-Type: text/xml\r\n\r\n";
+        assert_eq!(application_url(r), None);
+        let r = b"HTTP/1.1 200 OK\r\nApplication-URL:\r\n\r\n";
+        assert_eq!(application_url(r), None);
+    }
+
+    #[test]
+    fn application_url_in_body_ignored() {
+        let r = b"HTTP/1.1 200 OK\r\n\r\nApplication-URL: http://tv/apps";
+        assert_eq!(application_url(r), None);
+    }
+
+    #[test]
+    fn application_url_needs_success() {
+        let r =
+            b"HTTP/1.1 404 Not Found\r\nApplication-URL: http://tv/\r\n\r\n";
+        assert_eq!(application_url(r), None);
+        let r = b"SSDP/1.1 200 OK\r\nApplication-URL: http://tv/\r\n\r\n";
+        assert_eq!(application_url(r), None);
+        assert_eq!(application_url(b""), None);
+        assert_eq!(application_url(&[0xFF, 0xFE]), None);
+    }
+
+    #[test]
+    fn headers_round_trip() {
+        let headers = application_url_headers("http://10.0.0.2:8008/apps");
+        assert_eq!(
+            headers,
+            "Application-URL: http://10.0.0.2:8008/apps\r\n\
+             Access-Control-Expose-Headers: Application-URL\r\n"
+        );
+        let response = format!("HTTP/1.1 200 OK\r\n{headers}\r\n");
+        assert_eq!(
+            application_url(response.as_bytes()),
+            Some("http://10.0.0.2:8008/apps")
+        );
+    }
+
+    #[test]
+    fn app_urls() {
+        assert_eq!(
+            app_url("http://tv:8008/apps", "YouTube"),
+            "http://tv:8008/apps/YouTube"
+        );
+        assert_eq!(
+            app_url("http://tv:8008/apps/", "Netflix"),
+            "http://tv:8008/apps/Netflix"
+        );
+    }
+
+    #[cfg(all(feature = "std", feature = "subscribe"))]
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn fetch() {
+        use std::io::{ErrorKind, Read, Write};
+        use std::net::TcpListener;
+        use std::time::Duration;
+
+        let serve = |response: &'static str| {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let url =
+                format!("http://{}/dd.xml", listener.local_addr().unwrap());
+            let server = std::thread::spawn(move || {
+                let (mut socket, _) = listener.accept().unwrap();
+                let mut request = Vec::new();
+                let mut buf = [0u8; 512];
+                while !request.ends_with(b"\r\n\r\n") {
+                    let n = socket.read(&mut buf).unwrap();
+                    assert_ne!(n, 0);
+                    request.extend_from_slice(&buf[..n]);
+                }
+                socket.write_all(response.as_bytes()).unwrap();
+                String::from_utf8(request).unwrap()
+            });
+            (url, server)
+        };
+        let timeout = Duration::from_secs(5);
+
+        let (url, server) = serve(
+            "HTTP/1.1 200 OK\r\nApplication-URL: http://tv/apps\r\n\r\n<root/>",
+        );
+        assert_eq!(
+            fetch_application_url(&url, timeout).unwrap(),
+            "http://tv/apps"
+        );
+        assert!(server
+            .join()
+            .unwrap()
+            .starts_with("GET /dd.xml HTTP/1.1\r\n"));
+
+        let (url, server) = serve("HTTP/1.1 200 OK\r\n\r\n<root/>");
+        assert_eq!(
+            fetch_application_url(&url, timeout).unwrap_err().kind(),
+            ErrorKind::NotFound
+        );
+        server.join().unwrap();
+    }
+}
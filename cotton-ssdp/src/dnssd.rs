@@ -0,0 +1,525 @@
+use crate::validate::{split_authority, split_url};
+use crate::Notification;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// TXT key marking a DNS-SD service as having been bridged from SSDP
+///
+/// Its value is always "ssdp".
+pub const BRIDGE_TXT_KEY: &str = "cotton-bridge";
+
+/// The longest DNS-SD instance name (a single DNS label), in bytes
+const MAX_INSTANCE_LEN: usize = 63;
+
+/// The longest single TXT string ("key=value"), in bytes
+const MAX_TXT_LEN: usize = 255;
+
+/// A DNS-SD service instance, as published by a [`Bridge`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DnsSdService {
+    /// Instance name, e.g. "uuid:3a7c…::upnp:rootdevice"
+    pub instance: String,
+
+    /// Service type, e.g. "_ssdp._tcp"
+    pub service_type: String,
+
+    /// Target host for the SRV record: a host name or IP address
+    pub host: String,
+
+    /// Target port for the SRV record
+    pub port: u16,
+
+    /// TXT record, as key/value pairs
+    pub txt: Vec<(String, String)>,
+
+    /// Time-to-live for the records
+    pub ttl: Duration,
+}
+
+impl DnsSdService {
+    /// Look up a key in the TXT record
+    pub fn txt_value(&self, key: &str) -> Option<&str> {
+        self.txt
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Was this service published by a [`Bridge`]?
+    ///
+    /// Anything republishing DNS-SD services over SSDP should skip
+    /// these, to avoid loops.
+    pub fn is_bridged(&self) -> bool {
+        self.txt_value(BRIDGE_TXT_KEY).is_some()
+    }
+}
+
+/// A way of publishing DNS-SD services, typically over mDNS
+pub trait DnsSdPublisher {
+    /// Publish (or update) a service instance
+    ///
+    /// Called again, with the same instance name, if any of the
+    /// details change.
+    fn publish(&mut self, service: &DnsSdService);
+
+    /// Withdraw a previously-published service instance
+    ///
+    /// The records should be sent once more with TTL zero, so that
+    /// caches on other hosts forget them promptly.
+    fn withdraw(&mut self, service: &DnsSdService);
+}
+
+/// Configuration for a [`Bridge`]
+#[derive(Debug, Clone)]
+pub struct BridgeConfig {
+    /// DNS-SD service type under which resources are published
+    ///
+    /// Default "_ssdp._tcp".
+    pub service_type: String,
+
+    /// How long a resource lasts without being refreshed
+    ///
+    /// Used as the DNS-SD TTL. Default 1800 seconds.
+    pub max_age: Duration,
+
+    /// Notifications whose USN starts with any of these are ignored
+    ///
+    /// Default empty.
+    pub ignore_usn_prefixes: Vec<String>,
+}
+
+impl Default for BridgeConfig {
+    fn default() -> Self {
+        Self {
+            service_type: "_ssdp._tcp".to_string(),
+            max_age: Duration::from_secs(1800),
+            ignore_usn_prefixes: Vec::new(),
+        }
+    }
+}
+
+struct Published {
+    service: DnsSdService,
+    expires: Instant,
+}
+
+/// Republishes SSDP resources as DNS-SD services
+///
+/// On networks which have standardised on mDNS/DNS-SD, SSDP-only
+/// devices (media servers, routers, and so on) are invisible to DNS-SD
+/// browsers. Pass a `Bridge` each [`Notification`] from an SSDP
+/// subscription (typically one for "ssdp:all"), and it republishes
+/// each discovered resource as a DNS-SD service instance, with:
+///
+///  * the service type from [`BridgeConfig::service_type`];
+///  * an instance name derived from its unique service name (USN);
+///  * the host and port from its LOCATION URL (which must be
+///    "http:");
+///  * TXT records "nt", "usn", "location" and "path", plus the
+///    [`BRIDGE_TXT_KEY`] marker;
+///  * a TTL equal to the SSDP max-age.
+///
+/// This crate doesn't itself implement mDNS: the actual publishing is
+/// done by an implementation of [`DnsSdPublisher`], which will
+/// typically wrap whichever mDNS responder the application already
+/// uses.
+///
+/// # Loop protection
+///
+/// Two bridges (one in each direction) could otherwise pass the same
+/// resource back and forth indefinitely. Services published by a
+/// `Bridge` carry the [`BRIDGE_TXT_KEY`] TXT record, which anything
+/// bridging DNS-SD *to* SSDP should check
+/// ([`DnsSdService::is_bridged`]) and skip. Conversely,
+/// [`BridgeConfig::ignore_usn_prefixes`] lists USNs which should not
+/// be bridged: for instance, the UUIDs under which this host itself
+/// advertises, whether natively or on behalf of DNS-SD services.
+///
+/// # TTL translation
+///
+/// Notifications don't report the max-age of the advertisement they
+/// came from, so [`BridgeConfig::max_age`] (by default 1800s, the same
+/// max-age that cotton-ssdp itself advertises) is used for every
+/// resource. A resource which is neither refreshed by a further
+/// "alive" notification within that time, nor withdrawn by a
+/// "bye-bye", is withdrawn from DNS-SD by [`Bridge::expire`], which
+/// should be called from time to time.
+pub struct Bridge<P: DnsSdPublisher> {
+    publisher: P,
+    config: BridgeConfig,
+    published: HashMap<String, Published>,
+}
+
+impl<P: DnsSdPublisher> Bridge<P> {
+    /// Create a new `Bridge` with the default configuration
+    pub fn new(publisher: P) -> Self {
+        Self::with_config(publisher, BridgeConfig::default())
+    }
+
+    /// Create a new `Bridge` with a specific configuration
+    pub fn with_config(publisher: P, config: BridgeConfig) -> Self {
+        Self {
+            publisher,
+            config,
+            published: HashMap::new(),
+        }
+    }
+
+    /// Handle an incoming SSDP notification
+    ///
+    /// Alive notifications are published (if new or changed) or
+    /// refreshed; bye-bye (and expired) notifications withdraw any
+    /// previously published service. Notifications with ignored USNs, or whose
+    /// LOCATION isn't an "http:" URL, are skipped.
+    pub fn on_notification(
+        &mut self,
+        notification: &Notification,
+        now: Instant,
+    ) {
+        match notification {
+            Notification::Alive {
+                notification_type,
+                unique_service_name,
+                location,
+                ..
+            } => {
+                if self.is_ignored(unique_service_name) {
+                    return;
+                }
+                let Some(service) = self.translate(
+                    notification_type,
+                    unique_service_name,
+                    location,
+                ) else {
+                    return;
+                };
+                let expires = now + self.config.max_age;
+                match self.published.get_mut(&**unique_service_name) {
+                    Some(p) if p.service == service => {
+                        p.expires = expires;
+                    }
+                    _ => {
+                        self.publisher.publish(&service);
+                        self.published.insert(
+                            unique_service_name.to_string(),
+                            Published { service, expires },
+                        );
+                    }
+                }
+            }
+            Notification::ByeBye {
+                unique_service_name,
+                ..
+            }
+            | Notification::Expired {
+                unique_service_name,
+                ..
+            } => {
+                if let Some(p) = self.published.remove(&**unique_service_name)
+                {
+                    self.withdraw(p.service);
+                }
+            }
+        }
+    }
+
+    /// Withdraw any services which haven't been refreshed in time
+    pub fn expire(&mut self, now: Instant) {
+        let (expired, live) = std::mem::take(&mut self.published)
+            .into_iter()
+            .partition(|(_, p)| p.expires <= now);
+        self.published = live;
+        for (_, p) in expired {
+            self.withdraw(p.service);
+        }
+    }
+
+    /// Withdraw all published services, e.g. before shutting down
+    pub fn withdraw_all(&mut self) {
+        for (_, p) in std::mem::take(&mut self.published) {
+            self.withdraw(p.service);
+        }
+    }
+
+    /// How many services are currently published
+    pub fn published_count(&self) -> usize {
+        self.published.len()
+    }
+
+    /// Access the publisher
+    pub fn publisher(&self) -> &P {
+        &self.publisher
+    }
+
+    fn withdraw(&mut self, mut service: DnsSdService) {
+        service.ttl = Duration::ZERO;
+        self.publisher.withdraw(&service);
+    }
+
+    fn is_ignored(&self, usn: &str) -> bool {
+        self.config
+            .ignore_usn_prefixes
+            .iter()
+            .any(|prefix| usn.starts_with(prefix.as_str()))
+    }
+
+    fn translate(
+        &self,
+        notification_type: &str,
+        unique_service_name: &str,
+        location: &str,
+    ) -> Option<DnsSdService> {
+        let (authority, path) = split_url(location).ok()?;
+        let (host, port) = split_authority(authority).ok()?;
+        let mut txt = Vec::new();
+        for (key, value) in [
+            ("nt", notification_type),
+            ("usn", unique_service_name),
+            ("location", location),
+            ("path", path),
+            (BRIDGE_TXT_KEY, "ssdp"),
+        ] {
+            // A TXT string that won't fit is left out, rather than truncated
+            if key.len() + 1 + value.len() <= MAX_TXT_LEN {
+                txt.push((key.to_string(), value.to_string()));
+            }
+        }
+        Some(DnsSdService {
+            instance: instance_name(unique_service_name),
+            service_type: self.config.service_type.clone(),
+            host: host.to_string(),
+            port,
+            txt,
+            ttl: self.config.max_age,
+        })
+    }
+}
+
+/// Derive a DNS-SD instance name from a USN
+///
+/// Short USNs are used as they are. Longer ones are cut short, and
+/// a hash of the whole USN appended so that resources of the same
+/// device (whose USNs share a long prefix) still get distinct names.
+fn instance_name(usn: &str) -> String {
+    if usn.len() <= MAX_INSTANCE_LEN {
+        return usn.to_string();
+    }
+    // FNV-1a, which unlike std's DefaultHasher is stable across releases
+    let hash = usn.bytes().fold(0x811c_9dc5_u32, |h, b| {
+        (h ^ b as u32).wrapping_mul(0x0100_0193)
+    });
+    let mut end = MAX_INSTANCE_LEN - 9;
+    while !usn.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}-{:08x}", &usn[..end], hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct FakePublisher {
+        published: Vec<DnsSdService>,
+        withdrawn: Vec<DnsSdService>,
+    }
+
+    impl DnsSdPublisher for FakePublisher {
+        fn publish(&mut self, service: &DnsSdService) {
+            self.published.push(service.clone());
+        }
+
+        fn withdraw(&mut self, service: &DnsSdService) {
+            self.withdrawn.push(service.clone());
+        }
+    }
+
+    const USN: &str = "uuid:3a7c0d8e-5b1f-4f4e-9c2a-000000000001";
+
+    fn alive(location: &str) -> Notification {
+        Notification::Alive {
+            notification_type: "upnp:rootdevice".into(),
+            unique_service_name: USN.into(),
+            location: location.to_string().into(),
+            boot_id: None,
+        }
+    }
+
+    fn byebye() -> Notification {
+        Notification::ByeBye {
+            notification_type: "upnp:rootdevice".into(),
+            unique_service_name: USN.into(),
+        }
+    }
+
+    #[test]
+    fn alive_published() {
+        let mut b = Bridge::new(FakePublisher::default());
+        b.on_notification(
+            &alive("http://192.168.1.3:8080/desc.xml"),
+            Instant::now(),
+        );
+        let p = &b.publisher().published;
+        assert_eq!(p.len(), 1);
+        assert_eq!(p[0].instance, USN);
+        assert_eq!(p[0].service_type, "_ssdp._tcp");
+        assert_eq!(p[0].host, "192.168.1.3");
+        assert_eq!(p[0].port, 8080);
+        assert_eq!(p[0].ttl, Duration::from_secs(1800));
+        assert_eq!(p[0].txt_value("nt"), Some("upnp:rootdevice"));
+        assert_eq!(p[0].txt_value("usn"), Some(USN));
+        assert_eq!(p[0].txt_value("path"), Some("/desc.xml"));
+        assert!(p[0].is_bridged());
+        assert_eq!(b.published_count(), 1);
+    }
+
+    #[test]
+    fn refresh_not_republished() {
+        let mut b = Bridge::new(FakePublisher::default());
+        let now = Instant::now();
+        b.on_notification(&alive("http://192.168.1.3/desc.xml"), now);
+        b.on_notification(&alive("http://192.168.1.3/desc.xml"), now);
+        assert_eq!(b.publisher().published.len(), 1);
+        assert_eq!(b.publisher().published[0].port, 80);
+    }
+
+    #[test]
+    fn changed_location_republished() {
+        let mut b = Bridge::new(FakePublisher::default());
+        let now = Instant::now();
+        b.on_notification(&alive("http://192.168.1.3/desc.xml"), now);
+        b.on_notification(&alive("http://192.168.1.4/desc.xml"), now);
+        let p = &b.publisher().published;
+        assert_eq!(p.len(), 2);
+        assert_eq!(p[1].host, "192.168.1.4");
+        assert_eq!(b.published_count(), 1);
+    }
+
+    #[test]
+    fn byebye_withdrawn_with_zero_ttl() {
+        let mut b = Bridge::new(FakePublisher::default());
+        b.on_notification(
+            &alive("http://192.168.1.3/desc.xml"),
+            Instant::now(),
+        );
+        b.on_notification(&byebye(), Instant::now());
+        let w = &b.publisher().withdrawn;
+        assert_eq!(w.len(), 1);
+        assert_eq!(w[0].instance, USN);
+        assert_eq!(w[0].ttl, Duration::ZERO);
+        assert_eq!(b.published_count(), 0);
+    }
+
+    #[test]
+    fn unknown_byebye_ignored() {
+        let mut b = Bridge::new(FakePublisher::default());
+        b.on_notification(&byebye(), Instant::now());
+        assert!(b.publisher().withdrawn.is_empty());
+    }
+
+    #[test]
+    fn expiry_follows_max_age() {
+        let mut b = Bridge::with_config(
+            FakePublisher::default(),
+            BridgeConfig {
+                max_age: Duration::from_secs(60),
+                ..Default::default()
+            },
+        );
+        let now = Instant::now();
+        b.on_notification(&alive("http://192.168.1.3/desc.xml"), now);
+        assert_eq!(b.publisher().published[0].ttl, Duration::from_secs(60));
+
+        // A refresh pushes back the expiry
+        b.on_notification(
+            &alive("http://192.168.1.3/desc.xml"),
+            now + Duration::from_secs(30),
+        );
+        b.expire(now + Duration::from_secs(60));
+        assert!(b.publisher().withdrawn.is_empty());
+
+        b.expire(now + Duration::from_secs(90));
+        assert_eq!(b.publisher().withdrawn.len(), 1);
+        assert_eq!(b.published_count(), 0);
+    }
+
+    #[test]
+    fn withdraw_all() {
+        let mut b = Bridge::new(FakePublisher::default());
+        b.on_notification(
+            &alive("http://192.168.1.3/desc.xml"),
+            Instant::now(),
+        );
+        b.withdraw_all();
+        assert_eq!(b.publisher().withdrawn.len(), 1);
+        assert_eq!(b.published_count(), 0);
+    }
+
+    #[test]
+    fn ignored_usn_not_bridged() {
+        let mut b = Bridge::with_config(
+            FakePublisher::default(),
+            BridgeConfig {
+                ignore_usn_prefixes: vec!["uuid:3a7c0d8e".to_string()],
+                ..Default::default()
+            },
+        );
+        b.on_notification(
+            &alive("http://192.168.1.3/desc.xml"),
+            Instant::now(),
+        );
+        assert!(b.publisher().published.is_empty());
+    }
+
+    #[test]
+    fn non_http_location_not_bridged() {
+        let mut b = Bridge::new(FakePublisher::default());
+        b.on_notification(
+            &alive("https://192.168.1.3/desc.xml"),
+            Instant::now(),
+        );
+        assert!(b.publisher().published.is_empty());
+    }
+
+    #[test]
+    fn ipv6_host() {
+        let mut b = Bridge::new(FakePublisher::default());
+        b.on_notification(&alive("http://[fe80::1]:1900/"), Instant::now());
+        let p = &b.publisher().published;
+        assert_eq!(p[0].host, "fe80::1");
+        assert_eq!(p[0].port, 1900);
+    }
+
+    #[test]
+    fn long_usns_distinct() {
+        let a = instance_name(
+            "uuid:3a7c0d8e-5b1f-4f4e-9c2a-000000000001::urn:schemas-upnp-org:service:ContentDirectory:1",
+        );
+        let b = instance_name(
+            "uuid:3a7c0d8e-5b1f-4f4e-9c2a-000000000001::urn:schemas-upnp-org:service:ConnectionManager:1",
+        );
+        assert!(a.len() <= MAX_INSTANCE_LEN);
+        assert!(b.len() <= MAX_INSTANCE_LEN);
+        assert_ne!(a, b);
+    }
+
+    #[test]
+    fn long_usn_cut_on_char_boundary() {
+        let usn = "é".repeat(40);
+        let name = instance_name(&usn);
+        assert!(name.len() <= MAX_INSTANCE_LEN);
+        assert!(name.starts_with("éé"));
+    }
+
+    #[test]
+    fn oversized_txt_omitted() {
+        let mut b = Bridge::new(FakePublisher::default());
+        let location = format!("http://192.168.1.3/{}", "x".repeat(300));
+        b.on_notification(&alive(&location), Instant::now());
+        let p = &b.publisher().published[0];
+        assert_eq!(p.txt_value("location"), None);
+        assert_eq!(p.txt_value("path"), None);
+        assert_eq!(p.txt_value("usn"), Some(USN));
+    }
+}
@@ -1,3 +1,4 @@
+use alloc::borrow::Cow;
 #[cfg(not(feature = "std"))]
 use alloc::string::String;
 
@@ -15,6 +16,10 @@ use alloc::string::String;
 /// Only notifications of alive (arriving) resources have a Location
 /// field, so this is expressed in the enum.
 ///
+/// A third kind, "Expired", is never sent over the network: the
+/// [`Engine`](crate::engine::Engine) synthesises it when a resource
+/// fails to refresh its advertisement in time.
+///
 /// Neither [`Service`](crate::Service) nor
 /// [`AsyncService`](crate::AsyncService) de-duplicates these
 /// notifications -- in other words, a caller of
@@ -23,38 +28,182 @@ use alloc::string::String;
 /// can be used to distinguish genuinely new resources (e.g., as the
 /// key in a `HashMap`).
 ///
+/// The fields are `Cow<'static, str>`, like those of
+/// [`Advertisement`]; notifications received from the network always
+/// own their strings, but ones constructed locally (in tests, say, or
+/// to replay a cache) needn't.
+///
 #[derive(Debug, Clone)]
 pub enum Notification {
     /// The resource in question is now active (at this location/URL)
     Alive {
         /// Resource type, e.g. "urn:schemas-upnp-org:service:ContentDirectory:1"
-        notification_type: String,
+        notification_type: Cow<'static, str>,
 
         /// Unique identifier for this particular resource instance
-        unique_service_name: String,
+        unique_service_name: Cow<'static, str>,
 
         /// URL of the resource (for UPnP, the device description document)
-        location: String,
+        location: Cow<'static, str>,
+
+        /// The device's UPnP 1.1 BOOTID, if it sent one
+        ///
+        /// This goes up each time the device reboots (or rejoins the
+        /// network), so a change means that anything learned from it
+        /// before, such as event subscriptions, has been lost.
+        boot_id: Option<u32>,
     },
 
     /// The resource in question is (becoming) inactive
     ByeBye {
         /// Resource type
-        notification_type: String,
+        notification_type: Cow<'static, str>,
+
+        /// Unique identifier for this particular resource instance
+        unique_service_name: Cow<'static, str>,
+    },
+
+    /// The resource in question has not been refreshed in time, and
+    /// is presumed to have gone away without saying bye-bye
+    ///
+    /// Alive notifications (and search responses) carry a
+    /// `CACHE-CONTROL: max-age`; if that passes without another one
+    /// arriving for the same unique service name, this is delivered
+    /// in place of the missing bye-bye.
+    Expired {
+        /// Resource type
+        notification_type: Cow<'static, str>,
 
         /// Unique identifier for this particular resource instance
-        unique_service_name: String,
+        unique_service_name: Cow<'static, str>,
     },
 }
 
+/// How a subscription decides which notifications are of interest
+///
+/// Passed to [`Service::subscribe_matching`](crate::Service::subscribe_matching)
+/// (or the equivalent on [`AsyncService`](crate::AsyncService) or
+/// [`Engine`](crate::engine::Engine)); plain `subscribe` always uses
+/// [`MatchMode::ByNotificationType`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum MatchMode {
+    /// Notifications whose type matches the subscribed notification
+    /// type (including later versions of it, see UPnP DA 1.0 s1.2.3)
+    #[default]
+    ByNotificationType,
+
+    /// Notifications whose unique service name (USN) starts with this
+    /// prefix, whatever their type
+    ///
+    /// For instance, "uuid:" followed by a device's UUID will match
+    /// every notification about that particular device, including
+    /// its embedded devices and services.
+    ByUsnPrefix(String),
+
+    /// All notifications whatsoever
+    Any,
+}
+
 /// Outgoing SSDP announcement, passed to
 /// [`Service::advertise`](crate::Service::advertise)
+///
+/// The strings are `Cow<'static, str>`, so that they can be string
+/// literals (`"upnp:rootdevice".into()`) on systems which would rather
+/// not allocate for them, or owned `String`s (`format!(...).into()`)
+/// when they're only known at run-time.
+///
+/// Create one with [`Advertisement::new`], and then set any of the
+/// optional fields that are needed; more optional fields may be added
+/// in future, so `Advertisement` can't be written as a struct literal.
+#[non_exhaustive]
 pub struct Advertisement {
     /// Resource type
-    pub notification_type: String,
+    pub notification_type: Cow<'static, str>,
 
     /// Resource location
-    pub location: String,
+    ///
+    /// Its host part is replaced by the address of the interface the
+    /// notification or response is sent from (unless
+    /// [`EngineConfig::preserve_global_locations`](crate::engine::EngineConfig::preserve_global_locations)
+    /// applies).
+    pub location: Cow<'static, str>,
+
+    /// Resource location for notifications and responses sent over
+    /// IPv6, if different
+    ///
+    /// For services which serve their description on a different port
+    /// or path over IPv6 than over IPv4. Its host part is replaced in
+    /// the same way as `location`'s. If `None`, `location` is used
+    /// whatever the address family.
+    pub location_v6: Option<Cow<'static, str>>,
+
+    /// Resource location over HTTPS, if the resource is also served
+    /// that way
+    ///
+    /// Sent, alongside `location`, in a `SECURELOCATION.UPNP.ORG`
+    /// header (as in UPnP Device Protection); searchers which include
+    /// that header in their M-SEARCH are sent this as the LOCATION
+    /// instead. Its host part is replaced in the same way as
+    /// `location`'s, whatever the address family.
+    pub secure_location: Option<Cow<'static, str>>,
+}
+
+impl Advertisement {
+    /// An advertisement of `notification_type` at `location`
+    ///
+    /// The optional fields all start off as `None`.
+    pub fn new(
+        notification_type: impl Into<Cow<'static, str>>,
+        location: impl Into<Cow<'static, str>>,
+    ) -> Self {
+        Self {
+            notification_type: notification_type.into(),
+            location: location.into(),
+            location_v6: None,
+            secure_location: None,
+        }
+    }
+}
+
+/// How far an advertisement's notifications are sent
+///
+/// SSDP is normally confined to the local link: notifications are
+/// multicast with a time-to-live of 1 (unless
+/// [`EngineConfig::multicast_ttl`](crate::engine::EngineConfig::multicast_ttl)
+/// says otherwise), so routers never forward them.
+/// Networks which deliberately route SSDP between subnets can instead
+/// have particular advertisements sent site-wide; see
+/// [`Engine::set_advertisement_scope`](crate::engine::Engine::set_advertisement_scope).
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    /// The local link only: TTL 1 (or `EngineConfig::multicast_ttl`),
+    /// and on IPv6 the link-local group `FF02::C`
+    #[default]
+    LinkLocal,
+
+    /// The whole site: sent with this multicast TTL, and on IPv6 to
+    /// the site-local group `FF05::C`
+    ///
+    /// UPnP suggests a TTL of 2 or 4; the network's multicast routing
+    /// must in any case be set up to forward SSDP.
+    Site {
+        /// Multicast time-to-live (IPv6 hop limit)
+        ttl: u8,
+    },
+}
+
+impl Scope {
+    /// The multicast time-to-live for this scope
+    ///
+    /// This is the default for [`Scope::LinkLocal`]; see
+    /// [`EngineConfig::multicast_ttl`](crate::engine::EngineConfig::multicast_ttl).
+    #[must_use]
+    pub const fn ttl(&self) -> u8 {
+        match self {
+            Self::LinkLocal => 1,
+            Self::Site { ttl } => *ttl,
+        }
+    }
 }
 
 #[cfg(test)]
@@ -63,27 +212,49 @@ mod tests {
     use alloc::format;
     use alloc::string::ToString;
 
+    #[test]
+    fn scope_ttl() {
+        assert_eq!(Scope::default(), Scope::LinkLocal);
+        assert_eq!(Scope::LinkLocal.ttl(), 1);
+        assert_eq!(Scope::Site { ttl: 4 }.ttl(), 4);
+    }
+
     #[test]
     fn can_debug() {
         let e = format!(
             "{:?}",
             Notification::Alive {
-                notification_type: String::new(),
-                unique_service_name: String::new(),
-                location: String::new(),
+                notification_type: "".into(),
+                unique_service_name: "".into(),
+                location: "".into(),
+                boot_id: None,
             }
         );
-        assert_eq!(e, "Alive { notification_type: \"\", unique_service_name: \"\", location: \"\" }".to_string());
+        assert_eq!(e, "Alive { notification_type: \"\", unique_service_name: \"\", location: \"\", boot_id: None }".to_string());
     }
 
     #[test]
     #[allow(clippy::redundant_clone)]
     fn can_clone() {
         let _ = Notification::Alive {
-            notification_type: String::new(),
-            unique_service_name: String::new(),
-            location: String::new(),
+            notification_type: String::new().into(),
+            unique_service_name: String::new().into(),
+            location: String::new().into(),
+            boot_id: None,
         }
         .clone();
     }
+
+    #[test]
+    fn advertisement_from_literals_borrows() {
+        let mut a = Advertisement::new(
+            "upnp:rootdevice",
+            "http://127.0.0.1/description.xml",
+        );
+        a.secure_location =
+            Some("https://127.0.0.1/description.xml".to_string().into());
+        assert!(matches!(a.notification_type, Cow::Borrowed(_)));
+        assert!(matches!(a.location, Cow::Borrowed(_)));
+        assert!(matches!(a.secure_location, Some(Cow::Owned(_))));
+    }
 }
@@ -0,0 +1,1284 @@
+use crate::engine::{Callback, CallbackError, Engine, SocketKind};
+use crate::refresh_timer::Timebase;
+use crate::udp;
+use crate::{Advertisement, MatchMode, Notification};
+use cotton_netif::InterfaceIndex;
+use no_std_net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::cell::{Cell, UnsafeCell};
+use std::ffi::{c_char, c_int, c_void, CStr, CString};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::time::Duration;
+
+/// Success
+pub const COTTON_SSDP_OK: c_int = 0;
+
+/// A pointer argument was NULL, or a string wasn't valid UTF-8
+pub const COTTON_SSDP_INVALID_ARGUMENT: c_int = -1;
+
+/// The configured limit on advertisements or subscriptions was reached
+pub const COTTON_SSDP_TOO_MANY: c_int = -2;
+
+/// A socket callback reported an error
+pub const COTTON_SSDP_SOCKET_ERROR: c_int = -3;
+
+/// The engine panicked (which is a bug)
+pub const COTTON_SSDP_INTERNAL_ERROR: c_int = -4;
+
+/// Called from within one of the engine's own callbacks
+///
+/// The engine isn't re-entrant: while it's calling a socket function
+/// or a subscription callback, none of the `cotton_ssdp_engine_*`
+/// functions may be used on it. Any which are return this (or, for
+/// those which don't return an error code, do nothing), and the
+/// engine is left as it was.
+pub const COTTON_SSDP_REENTERED: c_int = -5;
+
+/// Time as seen from C: a count of milliseconds since some arbitrary epoch
+///
+/// Only differences between times matter, so any monotonic clock
+/// will do.
+pub struct FfiTimebase();
+
+impl Timebase for FfiTimebase {
+    type Duration = Duration;
+    type Instant = Duration;
+
+    fn saturating_duration_since(
+        later: Duration,
+        earlier: Duration,
+    ) -> Duration {
+        later.saturating_sub(earlier)
+    }
+}
+
+/// An IPv4 address, as four octets in network order
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct SsdpIpv4Addr {
+    /// The address, most-significant octet first
+    pub octets: [u8; 4],
+}
+
+impl From<SsdpIpv4Addr> for Ipv4Addr {
+    fn from(a: SsdpIpv4Addr) -> Self {
+        Ipv4Addr::from(a.octets)
+    }
+}
+
+impl From<Ipv4Addr> for SsdpIpv4Addr {
+    fn from(a: Ipv4Addr) -> Self {
+        Self { octets: a.octets() }
+    }
+}
+
+/// An IPv4 address and UDP port
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct SsdpSocketAddr {
+    /// IP address
+    pub ip: SsdpIpv4Addr,
+    /// UDP port number, in host byte order
+    pub port: u16,
+}
+
+/// Which of the two SSDP sockets is meant
+///
+/// See [`SocketKind`]. C code passes these to
+/// [`cotton_ssdp_engine_on_data()`] as plain `int`s, which are
+/// checked, rather than trusted to be one of the variants.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SsdpSocket {
+    /// The socket bound to an ephemeral port
+    Ephemeral,
+    /// The socket bound to port 1900
+    Port1900,
+}
+
+impl SsdpSocket {
+    /// The socket meant by `socket`, if it's one of the variants
+    fn from_c(socket: c_int) -> Option<Self> {
+        match socket {
+            0 => Some(Self::Ephemeral),
+            1 => Some(Self::Port1900),
+            _ => None,
+        }
+    }
+}
+
+impl From<SsdpSocket> for SocketKind {
+    fn from(s: SsdpSocket) -> Self {
+        match s {
+            SsdpSocket::Ephemeral => SocketKind::Ephemeral,
+            SsdpSocket::Port1900 => SocketKind::Port1900,
+        }
+    }
+}
+
+impl From<SocketKind> for SsdpSocket {
+    fn from(s: SocketKind) -> Self {
+        match s {
+            SocketKind::Ephemeral => SsdpSocket::Ephemeral,
+            SocketKind::Port1900 => SsdpSocket::Port1900,
+        }
+    }
+}
+
+/// Send a UDP datagram from a particular socket and source address
+///
+/// Returns 0 on success, or an `errno` value on failure. Mustn't call
+/// back into the engine, see [`COTTON_SSDP_REENTERED`].
+pub type SsdpSendFn = unsafe extern "C" fn(
+    context: *mut c_void,
+    socket: SsdpSocket,
+    buffer: *const u8,
+    length: usize,
+    to: SsdpSocketAddr,
+    from: SsdpIpv4Addr,
+) -> c_int;
+
+/// Join or leave a multicast group on a particular network interface
+///
+/// Returns 0 on success, or an `errno` value on failure. Mustn't call
+/// back into the engine, see [`COTTON_SSDP_REENTERED`].
+pub type SsdpMulticastFn = unsafe extern "C" fn(
+    context: *mut c_void,
+    group: SsdpIpv4Addr,
+    interface: u32,
+) -> c_int;
+
+/// The networking facilities which the C code provides to the engine
+///
+/// The C code owns the two UDP sockets: it reads from them, passing
+/// what it receives to [`cotton_ssdp_engine_on_data()`], and the
+/// engine sends on them by calling `send`.
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct SsdpSockets {
+    /// Passed unchanged to each of the functions below
+    pub context: *mut c_void,
+    /// Send a datagram
+    pub send: Option<SsdpSendFn>,
+    /// Join a multicast group (on the port 1900 socket)
+    pub join_multicast: Option<SsdpMulticastFn>,
+    /// Leave a multicast group (on the port 1900 socket)
+    pub leave_multicast: Option<SsdpMulticastFn>,
+}
+
+/// The kinds of notification, see [`Notification`]
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SsdpNotificationKind {
+    /// The resource is active
+    Alive,
+    /// The resource is (becoming) inactive
+    ByeBye,
+    /// The resource's advertisement has expired
+    Expired,
+}
+
+/// An SSDP notification, as passed to a subscription callback
+///
+/// The strings are NUL-terminated, and valid only for the duration
+/// of the callback.
+#[repr(C)]
+#[derive(Debug)]
+pub struct SsdpNotification {
+    /// Alive, bye-bye, or expired
+    pub kind: SsdpNotificationKind,
+    /// Resource type
+    pub notification_type: *const c_char,
+    /// Unique identifier for this particular resource instance
+    pub unique_service_name: *const c_char,
+    /// URL of the resource; NULL for bye-bye notifications
+    pub location: *const c_char,
+}
+
+/// A subscription callback
+///
+/// Returns 0 if the notification was handled, or non-zero if not (see
+/// [`Callback`] for what happens then). Mustn't call back into the
+/// engine, see [`COTTON_SSDP_REENTERED`].
+pub type SsdpNotificationFn = unsafe extern "C" fn(
+    context: *mut c_void,
+    notification: *const SsdpNotification,
+) -> c_int;
+
+/// A C function pointer (plus context) acting as a [`Callback`]
+pub struct FfiCallback {
+    function: SsdpNotificationFn,
+    context: *mut c_void,
+}
+
+impl Callback for FfiCallback {
+    fn on_notification(
+        &self,
+        notification: &Notification,
+    ) -> Result<(), CallbackError> {
+        let (kind, nt, usn, location) = match notification {
+            Notification::Alive {
+                notification_type,
+                unique_service_name,
+                location,
+                ..
+            } => (
+                SsdpNotificationKind::Alive,
+                notification_type,
+                unique_service_name,
+                Some(location),
+            ),
+            Notification::ByeBye {
+                notification_type,
+                unique_service_name,
+            } => (
+                SsdpNotificationKind::ByeBye,
+                notification_type,
+                unique_service_name,
+                None,
+            ),
+            Notification::Expired {
+                notification_type,
+                unique_service_name,
+            } => (
+                SsdpNotificationKind::Expired,
+                notification_type,
+                unique_service_name,
+                None,
+            ),
+        };
+
+        // Strings with embedded NULs can't be passed to C; that's the
+        // sender's fault, not the callback's, so isn't an error
+        let Ok(nt) = CString::new(&**nt) else {
+            return Ok(());
+        };
+        let Ok(usn) = CString::new(&**usn) else {
+            return Ok(());
+        };
+        let location = match location.map(|l| CString::new(l.as_ref())) {
+            None => None,
+            Some(Ok(l)) => Some(l),
+            Some(Err(_)) => return Ok(()),
+        };
+
+        let n = SsdpNotification {
+            kind,
+            notification_type: nt.as_ptr(),
+            unique_service_name: usn.as_ptr(),
+            location: location
+                .as_ref()
+                .map_or(core::ptr::null(), |l| l.as_ptr()),
+        };
+        // SAFETY: the C code promised, in cotton_ssdp_engine_subscribe,
+        // that function and context are valid
+        if unsafe { (self.function)(self.context, &n) } == 0 {
+            Ok(())
+        } else {
+            Err(CallbackError)
+        }
+    }
+}
+
+/// One of the C code's sockets, as seen by the engine
+struct FfiSocket<'a> {
+    sockets: &'a SsdpSockets,
+    kind: SsdpSocket,
+}
+
+impl<'a> FfiSocket<'a> {
+    fn new(sockets: &'a SsdpSockets, kind: SsdpSocket) -> Self {
+        Self { sockets, kind }
+    }
+}
+
+fn to_result(
+    rc: c_int,
+    syscall: udp::error::Syscall,
+) -> Result<(), udp::Error> {
+    if rc == 0 {
+        Ok(())
+    } else {
+        Err(udp::Error::Syscall(
+            syscall,
+            std::io::Error::from_raw_os_error(rc),
+        ))
+    }
+}
+
+impl udp::TargetedSend for FfiSocket<'_> {
+    fn send_with<F>(
+        &self,
+        size: usize,
+        to: &SocketAddr,
+        from: &IpAddr,
+        f: F,
+    ) -> Result<(), udp::Error>
+    where
+        F: FnOnce(&mut [u8]) -> usize,
+    {
+        let (SocketAddr::V4(to), IpAddr::V4(from)) = (to, from) else {
+            return Err(udp::Error::Ipv6NotImplemented);
+        };
+        let Some(send) = self.sockets.send else {
+            return Err(udp::Error::NotImplemented);
+        };
+        let mut buffer = vec![0u8; size];
+        let length = f(&mut buffer);
+        let to = SsdpSocketAddr {
+            ip: (*to.ip()).into(),
+            port: to.port(),
+        };
+        // SAFETY: the C code promised, in cotton_ssdp_engine_new, that
+        // the functions and context are valid
+        let rc = unsafe {
+            send(
+                self.sockets.context,
+                self.kind,
+                buffer.as_ptr(),
+                length,
+                to,
+                (*from).into(),
+            )
+        };
+        to_result(rc, udp::error::Syscall::Sendmsg)
+    }
+}
+
+impl udp::Multicast for SsdpSockets {
+    fn join_multicast_group(
+        &self,
+        multicast_address: &IpAddr,
+        interface: InterfaceIndex,
+    ) -> Result<(), udp::Error> {
+        let IpAddr::V4(group) = multicast_address else {
+            return Err(udp::Error::Ipv6NotImplemented);
+        };
+        let Some(join) = self.join_multicast else {
+            return Ok(());
+        };
+        // SAFETY: as for send, above
+        let rc =
+            unsafe { join(self.context, (*group).into(), interface.0.get()) };
+        to_result(rc, udp::error::Syscall::JoinMulticast)
+    }
+
+    fn leave_multicast_group(
+        &self,
+        multicast_address: &IpAddr,
+        interface: InterfaceIndex,
+    ) -> Result<(), udp::Error> {
+        let IpAddr::V4(group) = multicast_address else {
+            return Err(udp::Error::Ipv6NotImplemented);
+        };
+        let Some(leave) = self.leave_multicast else {
+            return Ok(());
+        };
+        // SAFETY: as for send, above
+        let rc =
+            unsafe { leave(self.context, (*group).into(), interface.0.get()) };
+        to_result(rc, udp::error::Syscall::LeaveMulticast)
+    }
+}
+
+/// An SSDP engine, as seen from C
+///
+/// Opaque to C code; created with [`cotton_ssdp_engine_new()`] and
+/// destroyed with [`cotton_ssdp_engine_free()`].
+pub struct SsdpEngine {
+    state: UnsafeCell<EngineState>,
+    /// Whether a call into the engine is in progress
+    busy: Cell<bool>,
+}
+
+/// What's inside an [`SsdpEngine`]
+struct EngineState {
+    engine: Engine<FfiCallback, FfiTimebase>,
+    sockets: SsdpSockets,
+}
+
+impl SsdpEngine {
+    /// Call `f` on the engine's state, unless this is a call from
+    /// within one of its callbacks
+    ///
+    /// Returns `reentered` if it is, or `on_panic` if `f` panics.
+    fn enter_with<R>(
+        &self,
+        reentered: R,
+        on_panic: R,
+        f: impl FnOnce(&mut EngineState) -> R,
+    ) -> R {
+        if self.busy.replace(true) {
+            return reentered;
+        }
+        // SAFETY: busy was false, so there's no other reference to
+        // the state; and busy is true until this one is finished with
+        let state = unsafe { &mut *self.state.get() };
+        let result = guard(on_panic, || f(state));
+        self.busy.set(false);
+        result
+    }
+
+    /// As [`enter_with()`](Self::enter_with), for calls returning
+    /// `COTTON_SSDP_*` codes
+    fn enter(&self, f: impl FnOnce(&mut EngineState) -> c_int) -> c_int {
+        self.enter_with(COTTON_SSDP_REENTERED, COTTON_SSDP_INTERNAL_ERROR, f)
+    }
+}
+
+/// Don't let panics unwind into C
+fn guard<R>(on_panic: R, f: impl FnOnce() -> R) -> R {
+    catch_unwind(AssertUnwindSafe(f)).unwrap_or(on_panic)
+}
+
+fn millis(ms: u64) -> Duration {
+    Duration::from_millis(ms)
+}
+
+/// # Safety
+///
+/// `s` must be NULL or a valid NUL-terminated string.
+unsafe fn to_str<'a>(s: *const c_char) -> Option<&'a str> {
+    if s.is_null() {
+        None
+    } else {
+        CStr::from_ptr(s).to_str().ok()
+    }
+}
+
+/// Create a new SSDP engine
+///
+/// The engine sends, and joins multicast groups, using the functions
+/// in `sockets`, which is copied. `now_ms` is the current time, see
+/// [`FfiTimebase`].
+///
+/// Returns NULL if `sockets` is NULL or has no `send` function.
+///
+/// # Safety
+///
+/// `sockets` must be NULL or point to a valid set of sockets, whose
+/// functions must remain callable with its `context` until the
+/// engine is freed.
+#[no_mangle]
+pub unsafe extern "C" fn cotton_ssdp_engine_new(
+    random_seed: u32,
+    now_ms: u64,
+    sockets: *const SsdpSockets,
+) -> *mut SsdpEngine {
+    let Some(sockets) = sockets.as_ref() else {
+        return core::ptr::null_mut();
+    };
+    if sockets.send.is_none() {
+        return core::ptr::null_mut();
+    }
+    guard(core::ptr::null_mut(), || {
+        Box::into_raw(Box::new(SsdpEngine {
+            state: UnsafeCell::new(EngineState {
+                engine: Engine::new(random_seed, millis(now_ms)),
+                sockets: *sockets,
+            }),
+            busy: Cell::new(false),
+        }))
+    })
+}
+
+/// Destroy an SSDP engine
+///
+/// Advertisements are not withdrawn first; to do that, call
+/// [`cotton_ssdp_engine_deadvertise()`] beforehand.
+///
+/// # Safety
+///
+/// `engine` must be NULL, or have been returned by
+/// [`cotton_ssdp_engine_new()`] and not yet freed.
+///
+/// Mustn't be called from within one of the engine's callbacks: if it
+/// is, the engine isn't freed (see [`COTTON_SSDP_REENTERED`]).
+#[no_mangle]
+pub unsafe extern "C" fn cotton_ssdp_engine_free(engine: *mut SsdpEngine) {
+    if !engine.is_null() && !(*engine).busy.get() {
+        drop(Box::from_raw(engine));
+    }
+}
+
+/// Tell the engine about a network interface, or a change in its state
+///
+/// Only interfaces which are up have notifications or searches sent
+/// on them. An interface's addresses are added separately, using
+/// [`cotton_ssdp_engine_add_address()`].
+///
+/// # Safety
+///
+/// `engine` must have been returned by [`cotton_ssdp_engine_new()`]
+/// and not yet freed.
+///
+/// Mustn't be called from within one of the engine's callbacks: if it
+/// is, returns [`COTTON_SSDP_REENTERED`] and does nothing.
+#[no_mangle]
+pub unsafe extern "C" fn cotton_ssdp_engine_add_interface(
+    engine: *mut SsdpEngine,
+    interface: u32,
+    up: bool,
+) -> c_int {
+    let (Some(e), Some(ix)) =
+        (engine.as_ref(), core::num::NonZeroU32::new(interface))
+    else {
+        return COTTON_SSDP_INVALID_ARGUMENT;
+    };
+    let mut flags = cotton_netif::Flags::MULTICAST;
+    if up {
+        flags |= cotton_netif::Flags::UP | cotton_netif::Flags::RUNNING;
+    }
+    e.enter(|e| {
+        let search = FfiSocket::new(&e.sockets, SsdpSocket::Ephemeral);
+        match e.engine.on_new_link_event(
+            &InterfaceIndex(ix),
+            &flags,
+            &cotton_netif::OperState::from_flags(flags),
+            &e.sockets,
+            &search,
+        ) {
+            Ok(()) => COTTON_SSDP_OK,
+            Err(_) => COTTON_SSDP_SOCKET_ERROR,
+        }
+    })
+}
+
+/// Tell the engine that a network interface has gone away
+///
+/// # Safety
+///
+/// `engine` must have been returned by [`cotton_ssdp_engine_new()`]
+/// and not yet freed.
+///
+/// Mustn't be called from within one of the engine's callbacks: if it
+/// is, returns [`COTTON_SSDP_REENTERED`] and does nothing.
+#[no_mangle]
+pub unsafe extern "C" fn cotton_ssdp_engine_remove_interface(
+    engine: *mut SsdpEngine,
+    interface: u32,
+) -> c_int {
+    let (Some(e), Some(ix)) =
+        (engine.as_ref(), core::num::NonZeroU32::new(interface))
+    else {
+        return COTTON_SSDP_INVALID_ARGUMENT;
+    };
+    e.enter(|e| {
+        match e.engine.on_del_link_event(&InterfaceIndex(ix), &e.sockets) {
+            Ok(()) => COTTON_SSDP_OK,
+            Err(_) => COTTON_SSDP_SOCKET_ERROR,
+        }
+    })
+}
+
+/// Tell the engine about a new IP address on a network interface
+///
+/// # Safety
+///
+/// `engine` must have been returned by [`cotton_ssdp_engine_new()`]
+/// and not yet freed.
+///
+/// Mustn't be called from within one of the engine's callbacks: if it
+/// is, returns [`COTTON_SSDP_REENTERED`] and does nothing.
+#[no_mangle]
+pub unsafe extern "C" fn cotton_ssdp_engine_add_address(
+    engine: *mut SsdpEngine,
+    interface: u32,
+    address: SsdpIpv4Addr,
+) -> c_int {
+    let (Some(e), Some(ix)) =
+        (engine.as_ref(), core::num::NonZeroU32::new(interface))
+    else {
+        return COTTON_SSDP_INVALID_ARGUMENT;
+    };
+    e.enter(|e| {
+        let search = FfiSocket::new(&e.sockets, SsdpSocket::Ephemeral);
+        e.engine.on_new_addr_event(
+            &InterfaceIndex(ix),
+            &IpAddr::V4(address.into()),
+            &search,
+        );
+        COTTON_SSDP_OK
+    })
+}
+
+/// Tell the engine that an IP address has been removed
+///
+/// # Safety
+///
+/// `engine` must have been returned by [`cotton_ssdp_engine_new()`]
+/// and not yet freed.
+///
+/// Mustn't be called from within one of the engine's callbacks: if it
+/// is, returns [`COTTON_SSDP_REENTERED`] and does nothing.
+#[no_mangle]
+pub unsafe extern "C" fn cotton_ssdp_engine_remove_address(
+    engine: *mut SsdpEngine,
+    interface: u32,
+    address: SsdpIpv4Addr,
+) -> c_int {
+    let (Some(e), Some(ix)) =
+        (engine.as_ref(), core::num::NonZeroU32::new(interface))
+    else {
+        return COTTON_SSDP_INVALID_ARGUMENT;
+    };
+    e.enter(|e| {
+        e.engine.on_del_addr_event(
+            &InterfaceIndex(ix),
+            &IpAddr::V4(address.into()),
+        );
+        COTTON_SSDP_OK
+    })
+}
+
+/// Pass a received datagram to the engine
+///
+/// `socket` says which socket it arrived on (one of the values of
+/// [`SsdpSocket`]), `to` the local address it was received on, and
+/// `from` where it came from.
+///
+/// Returns [`COTTON_SSDP_INVALID_ARGUMENT`] if `socket` isn't one of
+/// the values of [`SsdpSocket`].
+///
+/// # Safety
+///
+/// `engine` must have been returned by [`cotton_ssdp_engine_new()`]
+/// and not yet freed; `buffer` must point to `length` readable bytes.
+///
+/// Mustn't be called from within one of the engine's callbacks: if it
+/// is, returns [`COTTON_SSDP_REENTERED`] and does nothing.
+#[no_mangle]
+pub unsafe extern "C" fn cotton_ssdp_engine_on_data(
+    engine: *mut SsdpEngine,
+    socket: c_int,
+    buffer: *const u8,
+    length: usize,
+    to: SsdpIpv4Addr,
+    from: SsdpSocketAddr,
+    now_ms: u64,
+) -> c_int {
+    let (Some(e), Some(socket)) =
+        (engine.as_ref(), SsdpSocket::from_c(socket))
+    else {
+        return COTTON_SSDP_INVALID_ARGUMENT;
+    };
+    if buffer.is_null() {
+        return COTTON_SSDP_INVALID_ARGUMENT;
+    }
+    let buf = core::slice::from_raw_parts(buffer, length);
+    e.enter(|e| {
+        e.engine.on_socket_data(
+            buf,
+            socket.into(),
+            IpAddr::V4(to.into()),
+            SocketAddr::V4(SocketAddrV4::new(from.ip.into(), from.port)),
+            millis(now_ms),
+        );
+        COTTON_SSDP_OK
+    })
+}
+
+/// Deal with any expired timeouts
+///
+/// Should be called once the time returned by
+/// [`cotton_ssdp_engine_poll_timeout()`] has been reached.
+///
+/// # Safety
+///
+/// `engine` must have been returned by [`cotton_ssdp_engine_new()`]
+/// and not yet freed.
+///
+/// Mustn't be called from within one of the engine's callbacks: if it
+/// is, returns [`COTTON_SSDP_REENTERED`] and does nothing.
+#[no_mangle]
+pub unsafe extern "C" fn cotton_ssdp_engine_handle_timeout(
+    engine: *mut SsdpEngine,
+    now_ms: u64,
+) -> c_int {
+    let Some(e) = engine.as_ref() else {
+        return COTTON_SSDP_INVALID_ARGUMENT;
+    };
+    e.enter(|e| {
+        e.engine.handle_timeout_with(
+            &FfiSocket::new(&e.sockets, SsdpSocket::Ephemeral),
+            &FfiSocket::new(&e.sockets, SsdpSocket::Port1900),
+            millis(now_ms),
+        );
+        COTTON_SSDP_OK
+    })
+}
+
+/// When the engine next needs [`cotton_ssdp_engine_handle_timeout()`]
+/// calling, in the same milliseconds as `now_ms`
+///
+/// Returns `UINT64_MAX` if `engine` is NULL.
+///
+/// # Safety
+///
+/// `engine` must be NULL, or have been returned by
+/// [`cotton_ssdp_engine_new()`] and not yet freed.
+///
+/// Mustn't be called from within one of the engine's callbacks: if it
+/// is, returns `UINT64_MAX` (see [`COTTON_SSDP_REENTERED`]).
+#[no_mangle]
+pub unsafe extern "C" fn cotton_ssdp_engine_poll_timeout(
+    engine: *const SsdpEngine,
+) -> u64 {
+    let Some(e) = engine.as_ref() else {
+        return u64::MAX;
+    };
+    e.enter_with(u64::MAX, u64::MAX, |e| {
+        e.engine
+            .poll_timeout()
+            .as_millis()
+            .try_into()
+            .unwrap_or(u64::MAX)
+    })
+}
+
+/// Subscribe to notifications of a particular type
+///
+/// `callback` is called, with `context`, for each matching
+/// notification received (including responses to the searches which
+/// this sends). Subscriptions last until the engine is freed, or
+/// until the callback fails too many times in a row.
+///
+/// Returns [`COTTON_SSDP_TOO_MANY`] if the limit on subscriptions has
+/// been reached.
+///
+/// # Safety
+///
+/// `engine` must have been returned by [`cotton_ssdp_engine_new()`]
+/// and not yet freed; `notification_type` must be a NUL-terminated
+/// string; `callback` must remain callable with `context` until the
+/// engine is freed.
+///
+/// Mustn't be called from within one of the engine's callbacks: if it
+/// is, returns [`COTTON_SSDP_REENTERED`] and does nothing.
+#[no_mangle]
+pub unsafe extern "C" fn cotton_ssdp_engine_subscribe(
+    engine: *mut SsdpEngine,
+    notification_type: *const c_char,
+    callback: Option<SsdpNotificationFn>,
+    context: *mut c_void,
+) -> c_int {
+    let (Some(e), Some(nt), Some(function)) =
+        (engine.as_ref(), to_str(notification_type), callback)
+    else {
+        return COTTON_SSDP_INVALID_ARGUMENT;
+    };
+    e.enter(|e| {
+        let search = FfiSocket::new(&e.sockets, SsdpSocket::Ephemeral);
+        match e.engine.try_subscribe_matching(
+            nt.to_string(),
+            MatchMode::ByNotificationType,
+            FfiCallback { function, context },
+            &search,
+        ) {
+            Ok(()) => COTTON_SSDP_OK,
+            Err(_) => COTTON_SSDP_TOO_MANY,
+        }
+    })
+}
+
+/// Advertise a local resource
+///
+/// Replaces any existing advertisement with the same unique service
+/// name. The host part of `location` is replaced by the address of
+/// whichever interface each notification is sent from.
+///
+/// Returns [`COTTON_SSDP_TOO_MANY`] if the limit on advertisements
+/// has been reached.
+///
+/// # Safety
+///
+/// `engine` must have been returned by [`cotton_ssdp_engine_new()`]
+/// and not yet freed; the strings must be NUL-terminated.
+///
+/// Mustn't be called from within one of the engine's callbacks: if it
+/// is, returns [`COTTON_SSDP_REENTERED`] and does nothing.
+#[no_mangle]
+pub unsafe extern "C" fn cotton_ssdp_engine_advertise(
+    engine: *mut SsdpEngine,
+    unique_service_name: *const c_char,
+    notification_type: *const c_char,
+    location: *const c_char,
+) -> c_int {
+    let (Some(e), Some(usn), Some(nt), Some(location)) = (
+        engine.as_ref(),
+        to_str(unique_service_name),
+        to_str(notification_type),
+        to_str(location),
+    ) else {
+        return COTTON_SSDP_INVALID_ARGUMENT;
+    };
+    e.enter(|e| {
+        let socket = FfiSocket::new(&e.sockets, SsdpSocket::Ephemeral);
+        match e.engine.try_advertise(
+            usn.to_string(),
+            Advertisement::new(nt.to_string(), location.to_string()),
+            &socket,
+        ) {
+            Ok(()) => COTTON_SSDP_OK,
+            Err(_) => COTTON_SSDP_TOO_MANY,
+        }
+    })
+}
+
+/// Withdraw an advertisement, sending bye-bye notifications
+///
+/// # Safety
+///
+/// `engine` must have been returned by [`cotton_ssdp_engine_new()`]
+/// and not yet freed; `unique_service_name` must be NUL-terminated.
+///
+/// Mustn't be called from within one of the engine's callbacks: if it
+/// is, returns [`COTTON_SSDP_REENTERED`] and does nothing.
+#[no_mangle]
+pub unsafe extern "C" fn cotton_ssdp_engine_deadvertise(
+    engine: *mut SsdpEngine,
+    unique_service_name: *const c_char,
+) -> c_int {
+    let (Some(e), Some(usn)) = (engine.as_ref(), to_str(unique_service_name))
+    else {
+        return COTTON_SSDP_INVALID_ARGUMENT;
+    };
+    e.enter(|e| {
+        let socket = FfiSocket::new(&e.sockets, SsdpSocket::Ephemeral);
+        e.engine.deadvertise(usn, &socket);
+        COTTON_SSDP_OK
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    #[derive(Default)]
+    struct Fake {
+        sends: RefCell<Vec<(SsdpSocket, String, SsdpSocketAddr)>>,
+        joins: RefCell<Vec<u32>>,
+        leaves: RefCell<Vec<u32>>,
+        send_error: c_int,
+        notifications: RefCell<Vec<(SsdpNotificationKind, String)>>,
+    }
+
+    unsafe extern "C" fn fake_send(
+        context: *mut c_void,
+        socket: SsdpSocket,
+        buffer: *const u8,
+        length: usize,
+        to: SsdpSocketAddr,
+        _from: SsdpIpv4Addr,
+    ) -> c_int {
+        let f = &*(context as *const Fake);
+        let data = core::slice::from_raw_parts(buffer, length);
+        f.sends.borrow_mut().push((
+            socket,
+            String::from_utf8_lossy(data).into_owned(),
+            to,
+        ));
+        f.send_error
+    }
+
+    unsafe extern "C" fn fake_join(
+        context: *mut c_void,
+        _group: SsdpIpv4Addr,
+        interface: u32,
+    ) -> c_int {
+        let f = &*(context as *const Fake);
+        f.joins.borrow_mut().push(interface);
+        0
+    }
+
+    unsafe extern "C" fn fake_leave(
+        context: *mut c_void,
+        _group: SsdpIpv4Addr,
+        interface: u32,
+    ) -> c_int {
+        let f = &*(context as *const Fake);
+        f.leaves.borrow_mut().push(interface);
+        0
+    }
+
+    unsafe extern "C" fn fake_callback(
+        context: *mut c_void,
+        notification: *const SsdpNotification,
+    ) -> c_int {
+        let f = &*(context as *const Fake);
+        let n = &*notification;
+        let usn = CStr::from_ptr(n.unique_service_name);
+        f.notifications
+            .borrow_mut()
+            .push((n.kind, usn.to_str().unwrap().to_string()));
+        0
+    }
+
+    const LOCAL: SsdpIpv4Addr = SsdpIpv4Addr {
+        octets: [192, 168, 100, 1],
+    };
+
+    const PEER: SsdpSocketAddr = SsdpSocketAddr {
+        ip: SsdpIpv4Addr {
+            octets: [192, 168, 100, 60],
+        },
+        port: 12345,
+    };
+
+    fn new_engine(fake: &Fake) -> *mut SsdpEngine {
+        let sockets = SsdpSockets {
+            context: fake as *const Fake as *mut c_void,
+            send: Some(fake_send),
+            join_multicast: Some(fake_join),
+            leave_multicast: Some(fake_leave),
+        };
+        let e = unsafe { cotton_ssdp_engine_new(1, 0, &sockets) };
+        assert!(!e.is_null());
+        assert_eq!(
+            unsafe { cotton_ssdp_engine_add_interface(e, 4, true) },
+            COTTON_SSDP_OK
+        );
+        assert_eq!(
+            unsafe { cotton_ssdp_engine_add_address(e, 4, LOCAL) },
+            COTTON_SSDP_OK
+        );
+        e
+    }
+
+    #[test]
+    fn new_requires_send() {
+        let sockets = SsdpSockets {
+            context: core::ptr::null_mut(),
+            send: None,
+            join_multicast: None,
+            leave_multicast: None,
+        };
+        assert!(unsafe { cotton_ssdp_engine_new(1, 0, &sockets) }.is_null());
+        assert!(unsafe { cotton_ssdp_engine_new(1, 0, core::ptr::null()) }
+            .is_null());
+    }
+
+    #[test]
+    fn null_engine_rejected() {
+        let e = core::ptr::null_mut();
+        unsafe {
+            assert_eq!(
+                cotton_ssdp_engine_add_interface(e, 1, true),
+                COTTON_SSDP_INVALID_ARGUMENT
+            );
+            assert_eq!(
+                cotton_ssdp_engine_handle_timeout(e, 0),
+                COTTON_SSDP_INVALID_ARGUMENT
+            );
+            assert_eq!(cotton_ssdp_engine_poll_timeout(e), u64::MAX);
+            assert_eq!(
+                cotton_ssdp_engine_deadvertise(e, c"uuid:x".as_ptr()),
+                COTTON_SSDP_INVALID_ARGUMENT
+            );
+            cotton_ssdp_engine_free(e);
+        }
+    }
+
+    #[test]
+    fn interfaces_join_and_leave() {
+        let fake = Fake::default();
+        let e = new_engine(&fake);
+        assert_eq!(*fake.joins.borrow(), vec![4]);
+        unsafe {
+            assert_eq!(
+                cotton_ssdp_engine_add_interface(e, 0, true),
+                COTTON_SSDP_INVALID_ARGUMENT
+            );
+            assert_eq!(
+                cotton_ssdp_engine_remove_address(e, 4, LOCAL),
+                COTTON_SSDP_OK
+            );
+            assert_eq!(
+                cotton_ssdp_engine_remove_interface(e, 4),
+                COTTON_SSDP_OK
+            );
+            cotton_ssdp_engine_free(e);
+        }
+        assert_eq!(*fake.leaves.borrow(), vec![4]);
+    }
+
+    #[test]
+    fn advertise_sends_notify() {
+        let fake = Fake::default();
+        let e = new_engine(&fake);
+        unsafe {
+            assert_eq!(
+                cotton_ssdp_engine_advertise(
+                    e,
+                    c"uuid:137".as_ptr(),
+                    c"upnp:rootdevice".as_ptr(),
+                    c"http://127.0.0.1/description.xml".as_ptr(),
+                ),
+                COTTON_SSDP_OK
+            );
+        }
+        {
+            let sends = fake.sends.borrow();
+            let (socket, data, to) = sends.last().unwrap();
+            assert_eq!(*socket, SsdpSocket::Ephemeral);
+            assert!(data.starts_with("NOTIFY"));
+            assert!(data.contains("http://192.168.100.1/description.xml"));
+            assert_eq!(to.ip.octets, [239, 255, 255, 250]);
+            assert_eq!(to.port, 1900);
+        }
+        fake.sends.borrow_mut().clear();
+        unsafe {
+            assert_eq!(
+                cotton_ssdp_engine_deadvertise(e, c"uuid:137".as_ptr()),
+                COTTON_SSDP_OK
+            );
+            cotton_ssdp_engine_free(e);
+        }
+        assert!(fake.sends.borrow()[0].1.contains("ssdp:byebye"));
+    }
+
+    #[test]
+    fn advertise_rejects_null() {
+        let fake = Fake::default();
+        let e = new_engine(&fake);
+        unsafe {
+            assert_eq!(
+                cotton_ssdp_engine_advertise(
+                    e,
+                    c"uuid:137".as_ptr(),
+                    core::ptr::null(),
+                    c"http://127.0.0.1/description.xml".as_ptr(),
+                ),
+                COTTON_SSDP_INVALID_ARGUMENT
+            );
+            cotton_ssdp_engine_free(e);
+        }
+    }
+
+    #[test]
+    fn search_is_answered_after_timeout() {
+        let fake = Fake::default();
+        let e = new_engine(&fake);
+        let search = b"M-SEARCH * HTTP/1.1\r\n\
+HOST: 239.255.255.250:1900\r\n\
+MAN: \"ssdp:discover\"\r\n\
+MX: 1\r\n\
+ST: upnp:rootdevice\r\n\r\n";
+        unsafe {
+            cotton_ssdp_engine_advertise(
+                e,
+                c"uuid:137".as_ptr(),
+                c"upnp:rootdevice".as_ptr(),
+                c"http://127.0.0.1/description.xml".as_ptr(),
+            );
+            fake.sends.borrow_mut().clear();
+            assert_eq!(
+                cotton_ssdp_engine_on_data(
+                    e,
+                    SsdpSocket::Port1900 as c_int,
+                    search.as_ptr(),
+                    search.len(),
+                    LOCAL,
+                    PEER,
+                    10,
+                ),
+                COTTON_SSDP_OK
+            );
+            // Responses are delayed by up to MX seconds
+            assert!(cotton_ssdp_engine_poll_timeout(e) <= 1010);
+            cotton_ssdp_engine_handle_timeout(e, 1010);
+            cotton_ssdp_engine_free(e);
+        }
+        let sends = fake.sends.borrow();
+        let response = sends
+            .iter()
+            .find(|(_, data, _)| data.starts_with("HTTP/1.1 200 OK"))
+            .unwrap();
+        assert_eq!(response.2, PEER);
+    }
+
+    #[test]
+    fn subscriber_is_called() {
+        let fake = Fake::default();
+        let e = new_engine(&fake);
+        let notify = b"NOTIFY * HTTP/1.1\r\n\
+HOST: 239.255.255.250:1900\r\n\
+CACHE-CONTROL: max-age=1800\r\n\
+LOCATION: http://192.168.100.60/description.xml\r\n\
+NT: upnp:rootdevice\r\n\
+NTS: ssdp:alive\r\n\
+USN: uuid:999::upnp:rootdevice\r\n\r\n";
+        unsafe {
+            assert_eq!(
+                cotton_ssdp_engine_subscribe(
+                    e,
+                    c"upnp:rootdevice".as_ptr(),
+                    Some(fake_callback),
+                    &fake as *const Fake as *mut c_void,
+                ),
+                COTTON_SSDP_OK
+            );
+            assert!(fake
+                .sends
+                .borrow()
+                .iter()
+                .any(|(_, data, _)| data.starts_with("M-SEARCH")));
+            cotton_ssdp_engine_on_data(
+                e,
+                SsdpSocket::Port1900 as c_int,
+                notify.as_ptr(),
+                notify.len(),
+                LOCAL,
+                PEER,
+                10,
+            );
+            cotton_ssdp_engine_free(e);
+        }
+        assert_eq!(
+            *fake.notifications.borrow(),
+            vec![(
+                SsdpNotificationKind::Alive,
+                "uuid:999::upnp:rootdevice".to_string()
+            )]
+        );
+    }
+
+    struct Reentrant {
+        engine: Cell<*mut SsdpEngine>,
+        results: RefCell<Vec<c_int>>,
+    }
+
+    unsafe extern "C" fn reentrant_callback(
+        context: *mut c_void,
+        _notification: *const SsdpNotification,
+    ) -> c_int {
+        let r = &*(context as *const Reentrant);
+        let e = r.engine.get();
+        r.results
+            .borrow_mut()
+            .push(cotton_ssdp_engine_handle_timeout(e, 20));
+        r.results
+            .borrow_mut()
+            .push(cotton_ssdp_engine_deadvertise(e, c"uuid:137".as_ptr()));
+        assert_eq!(cotton_ssdp_engine_poll_timeout(e), u64::MAX);
+        cotton_ssdp_engine_free(e);
+        0
+    }
+
+    #[test]
+    fn callbacks_cannot_reenter() {
+        let fake = Fake::default();
+        let e = new_engine(&fake);
+        let r = Reentrant {
+            engine: Cell::new(e),
+            results: RefCell::default(),
+        };
+        let notify = b"NOTIFY * HTTP/1.1\r\n\
+HOST: 239.255.255.250:1900\r\n\
+CACHE-CONTROL: max-age=1800\r\n\
+LOCATION: http://192.168.100.60/description.xml\r\n\
+NT: upnp:rootdevice\r\n\
+NTS: ssdp:alive\r\n\
+USN: uuid:999::upnp:rootdevice\r\n\r\n";
+        unsafe {
+            cotton_ssdp_engine_subscribe(
+                e,
+                c"upnp:rootdevice".as_ptr(),
+                Some(reentrant_callback),
+                &r as *const Reentrant as *mut c_void,
+            );
+            assert_eq!(
+                cotton_ssdp_engine_on_data(
+                    e,
+                    SsdpSocket::Port1900 as c_int,
+                    notify.as_ptr(),
+                    notify.len(),
+                    LOCAL,
+                    PEER,
+                    10,
+                ),
+                COTTON_SSDP_OK
+            );
+            assert_eq!(
+                *r.results.borrow(),
+                vec![COTTON_SSDP_REENTERED, COTTON_SSDP_REENTERED]
+            );
+
+            // Still there, and usable again
+            assert_eq!(
+                cotton_ssdp_engine_handle_timeout(e, 20),
+                COTTON_SSDP_OK
+            );
+            assert_ne!(cotton_ssdp_engine_poll_timeout(e), u64::MAX);
+            cotton_ssdp_engine_free(e);
+        }
+    }
+
+    #[test]
+    fn on_data_rejects_unknown_socket() {
+        let fake = Fake::default();
+        let e = new_engine(&fake);
+        let data = b"NOTIFY * HTTP/1.1\r\n\r\n";
+        unsafe {
+            assert_eq!(
+                cotton_ssdp_engine_on_data(
+                    e,
+                    2,
+                    data.as_ptr(),
+                    data.len(),
+                    LOCAL,
+                    PEER,
+                    10,
+                ),
+                COTTON_SSDP_INVALID_ARGUMENT
+            );
+            assert_eq!(
+                cotton_ssdp_engine_on_data(
+                    e,
+                    -1,
+                    data.as_ptr(),
+                    data.len(),
+                    LOCAL,
+                    PEER,
+                    10,
+                ),
+                COTTON_SSDP_INVALID_ARGUMENT
+            );
+            cotton_ssdp_engine_free(e);
+        }
+    }
+
+    #[test]
+    fn send_errors_are_errno() {
+        let fake = Fake {
+            send_error: 5,
+            ..Default::default()
+        };
+        let sockets = SsdpSockets {
+            context: &fake as *const Fake as *mut c_void,
+            send: Some(fake_send),
+            join_multicast: None,
+            leave_multicast: None,
+        };
+        let socket = FfiSocket {
+            sockets: &sockets,
+            kind: SsdpSocket::Port1900,
+        };
+        let r = udp::TargetedSend::send_with(
+            &socket,
+            4,
+            &SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 1900)),
+            &IpAddr::V4(Ipv4Addr::LOCALHOST),
+            |b| {
+                b.copy_from_slice(b"abcd");
+                4
+            },
+        );
+        let Err(udp::Error::Syscall(udp::error::Syscall::Sendmsg, e)) = r
+        else {
+            panic!("expected error");
+        };
+        assert_eq!(e.raw_os_error(), Some(5));
+        assert_eq!(fake.sends.borrow()[0].1, "abcd");
+    }
+}
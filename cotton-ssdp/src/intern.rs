@@ -0,0 +1,102 @@
+use alloc::collections::BTreeSet;
+
+/// A reference-counted, immutable string
+///
+/// Atomically reference-counted where the target has atomics, so
+/// that an [`crate::engine::Engine`] can still be sent between
+/// threads; plain `Rc` otherwise (e.g. on Cortex-M0).
+#[cfg(target_has_atomic = "ptr")]
+pub type SharedStr = alloc::sync::Arc<str>;
+
+/// A reference-counted, immutable string
+#[cfg(not(target_has_atomic = "ptr"))]
+pub type SharedStr = alloc::rc::Rc<str>;
+
+#[cfg(target_has_atomic = "ptr")]
+#[cfg_attr(not(feature = "advertise"), allow(dead_code))]
+fn strong_count(s: &SharedStr) -> usize {
+    alloc::sync::Arc::strong_count(s)
+}
+
+#[cfg(not(target_has_atomic = "ptr"))]
+#[cfg_attr(not(feature = "advertise"), allow(dead_code))]
+fn strong_count(s: &SharedStr) -> usize {
+    alloc::rc::Rc::strong_count(s)
+}
+
+/// A set of shared strings, so that equal strings are stored only once
+///
+/// With many advertisements, the same notification types and
+/// locations turn up again and again (a whole UPnP device shares one
+/// LOCATION; every response to an "ssdp:all" search carries the same
+/// search type). Handing out clones of one [`SharedStr`] instead
+/// means each distinct string costs heap only once.
+///
+/// Strings are not freed as soon as their last user goes away: call
+/// [`Interner::purge`] from time to time to drop the ones that only
+/// the `Interner` itself still holds.
+#[derive(Default)]
+pub struct Interner {
+    strings: BTreeSet<SharedStr>,
+}
+
+impl Interner {
+    /// Obtain the shared copy of `s`, creating it if necessary
+    pub fn intern(&mut self, s: &str) -> SharedStr {
+        if let Some(shared) = self.strings.get(s) {
+            return shared.clone();
+        }
+        let shared = SharedStr::from(s);
+        self.strings.insert(shared.clone());
+        shared
+    }
+
+    /// Forget any strings which are no longer used elsewhere
+    #[cfg_attr(not(feature = "advertise"), allow(dead_code))]
+    pub fn purge(&mut self) {
+        self.strings.retain(|s| strong_count(s) > 1);
+    }
+
+    /// How many distinct strings are stored
+    pub fn len(&self) -> usize {
+        self.strings.len()
+    }
+
+    /// Heap bytes used by the strings themselves
+    pub fn bytes(&self) -> usize {
+        self.strings.iter().map(|s| s.len()).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn equal_strings_shared() {
+        let mut i = Interner::default();
+        let a = i.intern("upnp:rootdevice");
+        let b = i.intern("upnp:rootdevice");
+        let c = i.intern("ssdp:all");
+        assert!(SharedStr::ptr_eq(&a, &b));
+        assert!(!SharedStr::ptr_eq(&a, &c));
+        assert_eq!(&*b, "upnp:rootdevice");
+        assert_eq!(i.len(), 2);
+        assert_eq!(i.bytes(), "upnp:rootdevice".len() + "ssdp:all".len());
+    }
+
+    #[test]
+    fn purge_drops_unused() {
+        let mut i = Interner::default();
+        let a = i.intern("upnp:rootdevice");
+        let b = i.intern("ssdp:all");
+        drop(b);
+        i.purge();
+        assert_eq!(i.len(), 1);
+        assert!(SharedStr::ptr_eq(&a, &i.intern("upnp:rootdevice")));
+        drop(a);
+        i.purge();
+        assert_eq!(i.len(), 0);
+        assert_eq!(i.bytes(), 0);
+    }
+}
@@ -37,6 +37,15 @@
 //! [`Service`] using the lower-level facilities in
 //! [`engine::Engine`].
 //!
+//! On embedded systems using [Embassy](https://embassy.dev), the
+//! `embassy` feature provides the `Timebase` and UDP-trait
+//! implementations that [`engine::Engine`] needs.
+//!
+//! Existing C programs can embed [`engine::Engine`] using the `ffi`
+//! feature, which provides C bindings (with a header in
+//! `include/cotton_ssdp.h`) and can be built as a static library
+//! using `scripts/do-ssdp-ffi`.
+//!
 //! Example code is available both for asynchronous Tokio use:
 //! [ssdp-search](https://github.com/pdh11/cotton/blob/main/cotton-ssdp/examples/ssdp-search.rs)
 //! (on Github) and reactor-style MIO use:
@@ -45,13 +54,14 @@
 //!
 //! Todo:
 //!  - [x] Make mio/tokio features
-//!  - [ ] Make advertise/subscribe features
-//!  - [ ] `Cow<'static>` for input strings?
+//!  - [x] Make advertise/subscribe features
+//!  - [x] `Cow<'static>` for input strings?
 //!  - [ ] Hasher instead of `thread_rng`; hash over network interfaces sb unique
 //!  - [ ] Vary phase 1,2,3 timings but keep phase 0 timings on round numbers (needs _absolute_ wall time)
 //!  - [x] Monotonic time instead of `Instant::now` (lifetime?) *Solved differently*
 //!  - [x] `smoltcp`/`no_std`, see <https://github.com/rust-lang/rust/pull/104265>
-//!  - [ ] IPv6, see UPnP DA appendix A
+//!  - [x] IPv6 in `Engine`, see UPnP DA appendix A
+//!  - [ ] IPv6 in the `std`, `mio` and `tokio` UDP layers
 //!
 
 #![cfg_attr(not(feature = "std"), no_std)]
@@ -68,20 +78,51 @@ extern crate alloc;
 #[cfg(feature = "async")]
 mod async_service;
 
+/// Remembering discovered resources, including across sleeps
+#[cfg(feature = "cache")]
+pub mod cache;
+
+/// Diagnostic reports, and advertising them for health-checking
+pub mod diag;
+
+/// DIAL, the "Discovery and Launch" protocol of smart TVs and cast devices
+pub mod dial;
+
+/// Republishing discovered SSDP resources as DNS-SD services
+#[cfg(all(feature = "std", feature = "subscribe"))]
+pub mod dnssd;
+
 /// Low-level SSDP API used inside [`Service`] and [`AsyncService`]
 pub mod engine;
 
 /// Inbound and outbound SSDP events, high-level
 pub mod event;
 
+/// C bindings for [`engine::Engine`], for embedding in C firmware
+#[cfg(feature = "ffi")]
+pub mod ffi;
+
+#[cfg(any(feature = "advertise", feature = "subscribe"))]
+mod intern;
+
 mod message;
 
+/// Well-known notification types, and matching them by version
+pub mod nt;
+
 #[cfg(feature = "sync")]
 mod service;
 
 /// Traits used to abstract over various UDP socket implementations
 pub mod udp;
 
+/// Unique service names (USNs), and advertising whole UPnP devices
+pub mod usn;
+
+/// Checking that LOCATION URLs in notifications are reachable
+#[cfg(all(feature = "std", feature = "subscribe"))]
+pub mod validate;
+
 /// Common code for triggering refreshes of [`Service`] and [`AsyncService`]
 pub mod refresh_timer;
 
@@ -92,4 +133,8 @@ pub use async_service::AsyncService;
 pub use service::Service;
 
 pub use event::Advertisement;
+pub use event::MatchMode;
 pub use event::Notification;
+pub use event::Scope;
+pub use nt::NotificationType;
+pub use usn::DeviceAdvertisement;
@@ -2,14 +2,35 @@ extern crate alloc;
 use alloc::collections::BTreeMap;
 #[cfg(not(feature = "std"))]
 use alloc::string::String;
+#[cfg(any(feature = "advertise", feature = "subscribe"))]
 use core::fmt::Write;
+#[cfg(any(feature = "advertise", feature = "subscribe"))]
+use no_std_net::SocketAddrV4;
 
+// Every message type is parsed, even those the enabled features ignore
+#[cfg_attr(
+    not(all(feature = "advertise", feature = "subscribe")),
+    allow(dead_code)
+)]
 #[derive(Debug)]
 pub enum Message {
     NotifyAlive {
         notification_type: String,
         unique_service_name: String,
         location: String,
+        /// How long the notification remains valid, in seconds, if
+        /// the sender said (see [`parse_max_age`])
+        max_age: Option<u32>,
+        /// The sender's [`BOOTID_HEADER`], if it's UPnP 1.1
+        boot_id: Option<u32>,
+    },
+    /// An ssdp:update: the sender's BOOTID is about to change to
+    /// `next_boot_id` (UPnP DA 1.1 s1.2.4)
+    NotifyUpdate {
+        notification_type: String,
+        unique_service_name: String,
+        location: String,
+        next_boot_id: u32,
     },
     NotifyByeBye {
         notification_type: String,
@@ -18,11 +39,21 @@ pub enum Message {
     Search {
         search_target: String,
         maximum_wait_sec: u8,
+        /// Whether the searcher can use HTTPS locations, see
+        /// [`SECURE_LOCATION_HEADER`]
+        secure: bool,
+        /// Whether the search had `MAN: "ssdp:discover"`, as UPnP
+        /// requires (but not all searchers send)
+        discover: bool,
     },
     Response {
         search_target: String,
         unique_service_name: String,
         location: String,
+        /// As for [`Message::NotifyAlive`]
+        max_age: Option<u32>,
+        /// As for [`Message::NotifyAlive`]
+        boot_id: Option<u32>,
     },
 }
 
@@ -33,6 +64,91 @@ pub enum Error {
     UnexpectedEof,
 }
 
+/// The header carrying an HTTPS location for a resource
+///
+/// This is the header that UPnP Device Protection adds to
+/// notifications and search responses. A searcher which includes it
+/// (with any value) in an M-SEARCH is taken to be able to use HTTPS,
+/// and is sent the HTTPS location as the LOCATION itself.
+pub const SECURE_LOCATION_HEADER: &str = "SECURELOCATION.UPNP.ORG";
+
+/// The UPnP 1.1 header which changes each time a device reboots
+///
+/// UPnP DA 1.1 s1.2.2: a non-negative 31-bit number, which increases
+/// whenever the device (re)joins the network, and stays the same
+/// otherwise.
+pub const BOOTID_HEADER: &str = "BOOTID.UPNP.ORG";
+
+/// The UPnP 1.1 header which changes when a device's description does
+#[cfg(feature = "advertise")]
+pub const CONFIGID_HEADER: &str = "CONFIGID.UPNP.ORG";
+
+/// The UPnP 1.1 header giving the BOOTID a device is about to switch
+/// to, in ssdp:update notifications
+pub const NEXTBOOTID_HEADER: &str = "NEXTBOOTID.UPNP.ORG";
+
+/// The UPnP 1.1 boot and configuration IDs which a device sends
+#[cfg(feature = "advertise")]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct BootInfo {
+    /// See [`BOOTID_HEADER`]
+    pub boot_id: u32,
+    /// See [`CONFIGID_HEADER`]
+    pub config_id: u32,
+}
+
+/// Which optional parts of the standards outgoing messages follow,
+/// how long they may be, and where they're sent
+#[cfg(feature = "advertise")]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct MessageStyle {
+    /// Exactly the headers UPnP DA requires, see
+    /// [`EngineConfig::strict_compliance`](crate::engine::EngineConfig::strict_compliance)
+    pub strict: bool,
+    /// UPnP 1.1 boot information, if any
+    pub boot: Option<BootInfo>,
+    /// The longest message to send, see
+    /// [`EngineConfig::max_packet_size`](crate::engine::EngineConfig::max_packet_size)
+    pub max_size: usize,
+    /// The IPv4 multicast group and port, see
+    /// [`EngineConfig::multicast_group`](crate::engine::EngineConfig::multicast_group)
+    pub host: SocketAddrV4,
+    /// The multicast TTL for link-local notifications, see
+    /// [`EngineConfig::multicast_ttl`](crate::engine::EngineConfig::multicast_ttl)
+    pub ttl: u8,
+}
+
+#[cfg(feature = "advertise")]
+impl Default for MessageStyle {
+    fn default() -> Self {
+        Self {
+            strict: false,
+            boot: None,
+            max_size: crate::engine::DEFAULT_MAX_PACKET_SIZE,
+            host: SocketAddrV4::new(
+                crate::engine::IPV4_GROUP,
+                crate::engine::SSDP_PORT,
+            ),
+            ttl: 1,
+        }
+    }
+}
+
+/// Extract the max-age from the value of a CACHE-CONTROL header
+///
+/// Other directives, such as "no-cache", are ignored; so is a
+/// max-age which isn't a (decimal) number of seconds.
+pub fn parse_max_age(cache_control: &str) -> Option<u32> {
+    cache_control.split(',').find_map(|directive| {
+        let (name, value) = directive.split_once('=')?;
+        if name.trim().eq_ignore_ascii_case("max-age") {
+            value.trim().trim_matches('"').parse().ok()
+        } else {
+            None
+        }
+    })
+}
+
 pub fn parse(buf: &[u8]) -> Result<Message, Error> {
     let packet = core::str::from_utf8(buf).map_err(|_| Error::InvalidData)?;
 
@@ -46,6 +162,8 @@ pub fn parse(buf: &[u8]) -> Result<Message, Error> {
             map.insert(key.to_ascii_uppercase(), value.trim());
         }
     }
+    let max_age = map.get("CACHE-CONTROL").and_then(|v| parse_max_age(v));
+    let boot_id = map.get(BOOTID_HEADER).and_then(|v| v.parse().ok());
     match prefix {
         "NOTIFY * HTTP/1.1" => {
             if let Some(&nts) = map.get("NTS") {
@@ -60,6 +178,24 @@ pub fn parse(buf: &[u8]) -> Result<Message, Error> {
                                 notification_type: String::from(*nt),
                                 unique_service_name: String::from(*usn),
                                 location: String::from(*loc),
+                                max_age,
+                                boot_id,
+                            });
+                        }
+                    }
+                    "ssdp:update" => {
+                        if let (Some(nt), Some(usn), Some(loc), Some(next)) = (
+                            map.get("NT"),
+                            map.get("USN"),
+                            map.get("LOCATION"),
+                            map.get(NEXTBOOTID_HEADER)
+                                .and_then(|v| v.parse().ok()),
+                        ) {
+                            return Ok(Message::NotifyUpdate {
+                                notification_type: String::from(*nt),
+                                unique_service_name: String::from(*usn),
+                                location: String::from(*loc),
+                                next_boot_id: next,
                             });
                         }
                     }
@@ -85,6 +221,8 @@ pub fn parse(buf: &[u8]) -> Result<Message, Error> {
                     search_target: String::from(*st),
                     unique_service_name: String::from(*usn),
                     location: String::from(*loc),
+                    max_age,
+                    boot_id,
                 });
             }
         }
@@ -94,6 +232,10 @@ pub fn parse(buf: &[u8]) -> Result<Message, Error> {
                     return Ok(Message::Search {
                         search_target: String::from(*st),
                         maximum_wait_sec: mxn,
+                        secure: map.contains_key(SECURE_LOCATION_HEADER),
+                        discover: map.get("MAN").is_some_and(|man| {
+                            man.trim_matches('"') == "ssdp:discover"
+                        }),
                     });
                 }
             }
@@ -104,11 +246,19 @@ pub fn parse(buf: &[u8]) -> Result<Message, Error> {
 }
 
 /// A replacement for Cursor that works in `no_std`
+///
+/// Like C's `snprintf`, it keeps counting once the buffer is full, so
+/// that the `build_` functions below can return the length the whole
+/// message needs. If that's more than the buffer, the buffer holds
+/// only an incomplete message; building into an empty buffer measures
+/// a message without writing any of it.
+#[cfg(any(feature = "advertise", feature = "subscribe"))]
 struct MessageCursor<'a> {
     buf: &'a mut [u8],
     offset: usize,
 }
 
+#[cfg(any(feature = "advertise", feature = "subscribe"))]
 impl<'a> MessageCursor<'a> {
     pub fn new(buf: &'a mut [u8]) -> MessageCursor<'a> {
         MessageCursor { buf, offset: 0 }
@@ -119,26 +269,35 @@ impl<'a> MessageCursor<'a> {
     }
 }
 
+#[cfg(any(feature = "advertise", feature = "subscribe"))]
 impl core::fmt::Write for MessageCursor<'_> {
     fn write_str(&mut self, s: &str) -> core::fmt::Result {
-        let n = s.len();
-        if n + self.offset > self.buf.len() {
-            return core::fmt::Result::Err(core::fmt::Error);
+        let end = self.offset + s.len();
+        if let Some(dest) = self.buf.get_mut(self.offset..end) {
+            dest.clone_from_slice(s.as_bytes());
         }
-        let b = s.as_bytes();
-        self.buf[self.offset..self.offset + n].clone_from_slice(b);
-        self.offset += n;
+        self.offset = end;
         core::fmt::Result::Ok(())
     }
 }
 
+/// Build a search (M-SEARCH) for `search_type`, to be sent to `host`
+///
+/// Like the other `build_` functions, this returns the length of the
+/// whole message, which is more than `buf.len()` if it didn't fit
+/// (see [`MessageCursor`]).
+#[cfg(feature = "subscribe")]
 #[allow(clippy::cast_possible_truncation)]
-pub fn build_search(buf: &mut [u8], search_type: &str) -> usize {
+pub fn build_search(
+    buf: &mut [u8],
+    search_type: &str,
+    host: SocketAddrV4,
+) -> usize {
     let mut cursor = MessageCursor::new(buf);
     let _ = write!(
         cursor,
         "M-SEARCH * HTTP/1.1\r
-HOST: 239.255.255.250:1900\r
+HOST: {host}\r
 MAN: \"ssdp:discover\"\r
 MX: 5\r
 ST: {search_type}\r
@@ -147,79 +306,173 @@ ST: {search_type}\r
     cursor.position()
 }
 
+/// Build a response to a search
+///
+/// If `style.strict`, the response includes the (empty) EXT header
+/// which UPnP DA 1.0 s1.3.3 requires, but which no known control
+/// point checks for. If there's `style.boot` information, the
+/// response is a UPnP 1.1 one.
+#[cfg(feature = "advertise")]
 #[allow(clippy::cast_possible_truncation)]
 pub fn build_response(
     buf: &mut [u8],
     search_target: &str,
     unique_service_name: &str,
     location: &str,
+    secure_location: Option<&str>,
+    style: MessageStyle,
 ) -> usize {
     let mut cursor = MessageCursor::new(buf);
     let _ = write!(
         cursor,
         "HTTP/1.1 200 OK\r
-CACHE-CONTROL: max-age=1800\r
-ST: {search_target}\r
+CACHE-CONTROL: max-age=1800\r\n"
+    );
+    if style.strict {
+        let _ = write!(cursor, "EXT:\r\n");
+    }
+    let _ = write!(
+        cursor,
+        "ST: {search_target}\r
 USN: {unique_service_name}\r
-LOCATION: {location}\r
-SERVER: none/0 UPnP/1.0 {}/{}\r
-\r\n",
+LOCATION: {location}\r\n"
+    );
+    write_secure_location(&mut cursor, secure_location);
+    write_boot_info(&mut cursor, style.boot);
+    write_server(&mut cursor, style.boot);
+    let _ = write!(cursor, "\r\n");
+    cursor.position()
+}
+
+#[cfg(feature = "advertise")]
+fn write_secure_location(
+    cursor: &mut MessageCursor,
+    secure_location: Option<&str>,
+) {
+    if let Some(url) = secure_location {
+        let _ = write!(cursor, "{SECURE_LOCATION_HEADER}: {url}\r\n");
+    }
+}
+
+/// Write the BOOTID and CONFIGID headers, if there are any
+#[cfg(feature = "advertise")]
+fn write_boot_info(cursor: &mut MessageCursor, boot: Option<BootInfo>) {
+    if let Some(boot) = boot {
+        let _ = write!(
+            cursor,
+            "{BOOTID_HEADER}: {}\r\n{CONFIGID_HEADER}: {}\r\n",
+            boot.boot_id, boot.config_id
+        );
+    }
+}
+
+/// Write the SERVER header: UPnP 1.1 if there's `boot` information
+#[cfg(feature = "advertise")]
+fn write_server(cursor: &mut MessageCursor, boot: Option<BootInfo>) {
+    let _ = write!(
+        cursor,
+        "SERVER: none/0 UPnP/{} {}/{}\r\n",
+        if boot.is_some() { "1.1" } else { "1.0" },
         env!("CARGO_PKG_NAME"),
         env!("CARGO_PKG_VERSION"),
     );
-    cursor.position()
 }
 
+/// Build an ssdp:alive notification
+///
+/// If there's `style.boot` information, the notification is a UPnP
+/// 1.1 one.
+#[cfg(feature = "advertise")]
 #[allow(clippy::cast_possible_truncation)]
 pub fn build_notify(
     buf: &mut [u8],
     notification_type: &str,
     unique_service_name: &str,
     location: &str,
+    secure_location: Option<&str>,
+    style: MessageStyle,
 ) -> usize {
     let mut cursor = MessageCursor::new(buf);
     let _ = write!(
         cursor,
         "NOTIFY * HTTP/1.1\r
-HOST: 239.255.255.250:1900\r
+HOST: {}\r
 CACHE-CONTROL: max-age=1800\r
-LOCATION: {}\r
-NT: {}\r
+LOCATION: {location}\r\n",
+        style.host
+    );
+    write_secure_location(&mut cursor, secure_location);
+    let _ = write!(
+        cursor,
+        "NT: {notification_type}\r
 NTS: ssdp:alive\r
-USN: {}\r
-SERVER: none/0 UPnP/1.0 {}/{}\r
-\r\n",
-        location,
-        notification_type,
-        unique_service_name,
-        env!("CARGO_PKG_NAME"),
-        env!("CARGO_PKG_VERSION"),
+USN: {unique_service_name}\r\n"
     );
+    write_boot_info(&mut cursor, style.boot);
+    write_server(&mut cursor, style.boot);
+    let _ = write!(cursor, "\r\n");
     cursor.position()
 }
 
+/// Build an ssdp:update notification, announcing a change of BOOTID
+///
+/// UPnP DA 1.1 s1.2.4: sent, with the old `boot` information, before
+/// the device starts using `next_boot_id`.
+#[cfg(feature = "advertise")]
 #[allow(clippy::cast_possible_truncation)]
-pub fn build_byebye(
+pub fn build_update(
     buf: &mut [u8],
     notification_type: &str,
     unique_service_name: &str,
+    location: &str,
+    boot: BootInfo,
+    next_boot_id: u32,
+    host: SocketAddrV4,
 ) -> usize {
     let mut cursor = MessageCursor::new(buf);
     let _ = write!(
         cursor,
         "NOTIFY * HTTP/1.1\r
-HOST: 239.255.255.250:1900\r
-CACHE-CONTROL: max-age=1800\r
-NT: {}\r
+HOST: {host}\r
+LOCATION: {location}\r
+NT: {notification_type}\r
+NTS: ssdp:update\r
+USN: {unique_service_name}\r\n"
+    );
+    write_boot_info(&mut cursor, Some(boot));
+    let _ = write!(cursor, "{NEXTBOOTID_HEADER}: {next_boot_id}\r\n\r\n");
+    cursor.position()
+}
+
+/// Build an ssdp:byebye notification
+///
+/// If `style.strict`, it has exactly the headers listed in UPnP DA
+/// 1.1 s1.2.3, leaving out the CACHE-CONTROL and SERVER which (for
+/// symmetry with ssdp:alive) are otherwise sent.
+#[cfg(feature = "advertise")]
+#[allow(clippy::cast_possible_truncation)]
+pub fn build_byebye(
+    buf: &mut [u8],
+    notification_type: &str,
+    unique_service_name: &str,
+    style: MessageStyle,
+) -> usize {
+    let mut cursor = MessageCursor::new(buf);
+    let _ = write!(cursor, "NOTIFY * HTTP/1.1\r\nHOST: {}\r\n", style.host);
+    if !style.strict {
+        let _ = write!(cursor, "CACHE-CONTROL: max-age=1800\r\n");
+    }
+    let _ = write!(
+        cursor,
+        "NT: {notification_type}\r
 NTS: ssdp:byebye\r
-USN: {}\r
-SERVER: none/0 UPnP/1.0 {}/{}\r
-\r\n",
-        notification_type,
-        unique_service_name,
-        env!("CARGO_PKG_NAME"),
-        env!("CARGO_PKG_VERSION"),
+USN: {unique_service_name}\r\n"
     );
+    write_boot_info(&mut cursor, style.boot);
+    if !style.strict {
+        write_server(&mut cursor, style.boot);
+    }
+    let _ = write!(cursor, "\r\n");
     cursor.position()
 }
 
@@ -237,9 +490,22 @@ mod tests {
                 notification_type: String::new(),
                 unique_service_name: String::new(),
                 location: String::new(),
+                max_age: Some(1800),
+                boot_id: Some(3),
             }
         );
-        assert_eq!(e, "NotifyAlive { notification_type: \"\", unique_service_name: \"\", location: \"\" }".to_string());
+        assert_eq!(e, "NotifyAlive { notification_type: \"\", unique_service_name: \"\", location: \"\", max_age: Some(1800), boot_id: Some(3) }".to_string());
+
+        let e = format!(
+            "{:?}",
+            Message::NotifyUpdate {
+                notification_type: String::new(),
+                unique_service_name: String::new(),
+                location: String::new(),
+                next_boot_id: 4,
+            }
+        );
+        assert_eq!(e, "NotifyUpdate { notification_type: \"\", unique_service_name: \"\", location: \"\", next_boot_id: 4 }".to_string());
 
         let e = format!(
             "{:?}",
@@ -255,11 +521,13 @@ mod tests {
             Message::Search {
                 search_target: String::new(),
                 maximum_wait_sec: 3,
+                secure: false,
+                discover: true,
             }
         );
         assert_eq!(
             e,
-            "Search { search_target: \"\", maximum_wait_sec: 3 }".to_string()
+            "Search { search_target: \"\", maximum_wait_sec: 3, secure: false, discover: true }".to_string()
         );
 
         let e = format!(
@@ -268,9 +536,11 @@ mod tests {
                 search_target: String::new(),
                 unique_service_name: String::new(),
                 location: String::new(),
+                max_age: None,
+                boot_id: None,
             }
         );
-        assert_eq!(e, "Response { search_target: \"\", unique_service_name: \"\", location: \"\" }".to_string());
+        assert_eq!(e, "Response { search_target: \"\", unique_service_name: \"\", location: \"\", max_age: None, boot_id: None }".to_string());
     }
 
     #[test]
@@ -310,12 +580,51 @@ Location: http://foo\r\n\
         );
         assert!(r.is_ok());
         assert!(matches!(r.unwrap(),
-                         Message::NotifyAlive {notification_type, unique_service_name, location}
+                         Message::NotifyAlive {notification_type, unique_service_name, location, ..}
                          if notification_type == "fnord"
                          && unique_service_name == "prod37"
                          && location == "http://foo"));
     }
 
+    #[test]
+    fn accepts_max_age() {
+        let r = parse(
+            b"NOTIFY * HTTP/1.1\r\n\
+NTS: ssdp:alive\r\n\
+NT: fnord\r\n\
+USN: prod37\r\n\
+Cache-Control: max-age = 300\r\n\
+Location: http://foo\r\n\
+\r\n",
+        );
+        assert!(matches!(r.unwrap(),
+                         Message::NotifyAlive { max_age, .. }
+                         if max_age == Some(300)));
+
+        let r = parse(
+            b"NOTIFY * HTTP/1.1\r\n\
+NTS: ssdp:alive\r\n\
+NT: fnord\r\n\
+USN: prod37\r\n\
+Location: http://foo\r\n\
+\r\n",
+        );
+        assert!(matches!(r.unwrap(),
+                         Message::NotifyAlive { max_age, .. }
+                         if max_age.is_none()));
+    }
+
+    #[test]
+    fn max_age_directives() {
+        assert_eq!(parse_max_age("max-age=1800"), Some(1800));
+        assert_eq!(parse_max_age("MAX-AGE=60"), Some(60));
+        assert_eq!(parse_max_age("no-cache, max-age=\"90\""), Some(90));
+        assert_eq!(parse_max_age("no-cache"), None);
+        assert_eq!(parse_max_age("max-age=forever"), None);
+        assert_eq!(parse_max_age("max-age=-5"), None);
+        assert_eq!(parse_max_age(""), None);
+    }
+
     #[test]
     fn rejects_notify_bad_nts() {
         let r = parse(
@@ -430,7 +739,7 @@ Location: http://foo\r\n\
         );
         assert!(r.is_ok());
         assert!(matches!(r.unwrap(),
-                         Message::Response { search_target, unique_service_name, location }
+                         Message::Response { search_target, unique_service_name, location, .. }
                          if search_target == "fnord"
                          && unique_service_name == "prod37"
                          && location == "http://foo"));
@@ -476,7 +785,7 @@ Location\r\n\
         let r = parse(b"M-SEARCH * HTTP/1.1\r\nST: foo\r\nMX: 5\r\n\r\n");
         assert!(r.is_ok());
         assert!(matches!(r.unwrap(),
-                         Message::Search { search_target, maximum_wait_sec }
+                         Message::Search { search_target, maximum_wait_sec, .. }
                          if search_target == "foo"
                          && maximum_wait_sec == 5));
     }
@@ -499,11 +808,12 @@ Location\r\n\
         assert!(r.is_err());
     }
 
+    #[cfg(feature = "subscribe")]
     #[test]
     fn builds_search() {
         let mut buf = [0u8; 512];
 
-        let n = build_search(&mut buf, "upnp::rootdevice");
+        let n = build_search(&mut buf, "upnp::rootdevice", HOST);
 
         let expected = b"M-SEARCH * HTTP/1.1\r
 HOST: 239.255.255.250:1900\r
@@ -515,6 +825,7 @@ ST: upnp::rootdevice\r
         assert!(expected[0..n] == buf[0..n]);
     }
 
+    #[cfg(feature = "advertise")]
     #[test]
     fn builds_response() {
         let mut buf = [0u8; 512];
@@ -524,6 +835,8 @@ ST: upnp::rootdevice\r
             "upnp::rootdevice",
             "uuid:37",
             "http://me",
+            None,
+            MessageStyle::default(),
         );
         let expected = format!(
             "HTTP/1.1 200 OK\r
@@ -540,12 +853,19 @@ SERVER: none/0 UPnP/1.0 {}/{}\r
         assert!(expected.as_bytes()[0..n] == buf[0..n]);
     }
 
+    #[cfg(feature = "advertise")]
     #[test]
     fn builds_notify() {
         let mut buf = [0u8; 512];
 
-        let n =
-            build_notify(&mut buf, "upnp::rootdevice", "uuid:37", "http://me");
+        let n = build_notify(
+            &mut buf,
+            "upnp::rootdevice",
+            "uuid:37",
+            "http://me",
+            None,
+            MessageStyle::default(),
+        );
         let expected = format!(
             "NOTIFY * HTTP/1.1\r
 HOST: 239.255.255.250:1900\r
@@ -563,17 +883,139 @@ SERVER: none/0 UPnP/1.0 {}/{}\r
         assert!(expected.as_bytes()[0..n] == buf[0..n]);
     }
 
+    #[cfg(feature = "advertise")]
+    #[test]
+    fn builds_response_with_secure_location() {
+        let mut buf = [0u8; 512];
+
+        let n = build_response(
+            &mut buf,
+            "upnp::rootdevice",
+            "uuid:37",
+            "http://me",
+            Some("https://me"),
+            MessageStyle::default(),
+        );
+        let expected = format!(
+            "HTTP/1.1 200 OK\r
+CACHE-CONTROL: max-age=1800\r
+ST: upnp::rootdevice\r
+USN: uuid:37\r
+LOCATION: http://me\r
+SECURELOCATION.UPNP.ORG: https://me\r
+SERVER: none/0 UPnP/1.0 {}/{}\r
+\r\n",
+            env!("CARGO_PKG_NAME"),
+            env!("CARGO_PKG_VERSION"),
+        );
+        assert_eq!(expected.as_bytes(), &buf[0..n]);
+    }
+
+    #[cfg(feature = "advertise")]
+    #[test]
+    fn builds_notify_with_secure_location() {
+        let mut buf = [0u8; 512];
+
+        let n = build_notify(
+            &mut buf,
+            "upnp::rootdevice",
+            "uuid:37",
+            "http://me",
+            Some("https://me"),
+            MessageStyle::default(),
+        );
+        let expected = format!(
+            "NOTIFY * HTTP/1.1\r
+HOST: 239.255.255.250:1900\r
+CACHE-CONTROL: max-age=1800\r
+LOCATION: http://me\r
+SECURELOCATION.UPNP.ORG: https://me\r
+NT: upnp::rootdevice\r
+NTS: ssdp:alive\r
+USN: uuid:37\r
+SERVER: none/0 UPnP/1.0 {}/{}\r
+\r\n",
+            env!("CARGO_PKG_NAME"),
+            env!("CARGO_PKG_VERSION"),
+        );
+        assert_eq!(expected.as_bytes(), &buf[0..n]);
+    }
+
+    #[test]
+    fn search_with_secure_location() {
+        let msg = parse(
+            b"M-SEARCH * HTTP/1.1\r
+HOST: 239.255.255.250:1900\r
+MAN: \"ssdp:discover\"\r
+MX: 3\r
+ST: ssdp:all\r
+securelocation.upnp.org: 1\r
+\r\n",
+        )
+        .unwrap();
+        assert!(matches!(msg, Message::Search { secure: true, .. }));
+    }
+
+    #[test]
+    fn search_discover() {
+        let msg = parse(
+            b"M-SEARCH * HTTP/1.1\r
+HOST: 239.255.255.250:1900\r
+MAN: \"ssdp:discover\"\r
+MX: 3\r
+ST: ssdp:all\r
+\r\n",
+        )
+        .unwrap();
+        assert!(matches!(msg, Message::Search { discover: true, .. }));
+
+        let msg = parse(
+            b"M-SEARCH * HTTP/1.1\r
+HOST: 239.255.255.250:1900\r
+MX: 3\r
+ST: ssdp:all\r
+\r\n",
+        )
+        .unwrap();
+        assert!(matches!(
+            msg,
+            Message::Search {
+                discover: false,
+                ..
+            }
+        ));
+
+        let msg = parse(
+            b"M-SEARCH * HTTP/1.1\r
+HOST: 239.255.255.250:1900\r
+MAN: \"ssdp:update\"\r
+MX: 3\r
+ST: ssdp:all\r
+\r\n",
+        )
+        .unwrap();
+        assert!(matches!(
+            msg,
+            Message::Search {
+                discover: false,
+                ..
+            }
+        ));
+    }
+
+    #[cfg(feature = "subscribe")]
     #[test]
     fn search_round_trip() {
         let mut buf = [0u8; 512];
-        let n = build_search(&mut buf, "upnp::rootdevice");
+        let n = build_search(&mut buf, "upnp::rootdevice", HOST);
         let msg = parse(&buf[0..n]).unwrap();
         assert!(matches!(msg,
-                         Message::Search { search_target, maximum_wait_sec }
+                         Message::Search { search_target, maximum_wait_sec, .. }
                          if search_target == "upnp::rootdevice"
                          && maximum_wait_sec == 5));
     }
 
+    #[cfg(feature = "advertise")]
     #[test]
     fn response_round_trip() {
         let mut buf = [0u8; 512];
@@ -582,15 +1024,20 @@ SERVER: none/0 UPnP/1.0 {}/{}\r
             "upnp::rootdevice",
             "uuid:xyz",
             "https://you",
+            None,
+            MessageStyle::default(),
         );
         let msg = parse(&buf[0..n]).unwrap();
         assert!(matches!(msg,
-                         Message::Response { search_target, unique_service_name, location }
+                         Message::Response { search_target, unique_service_name, location, max_age, boot_id }
                          if search_target == "upnp::rootdevice"
                          && unique_service_name == "uuid:xyz"
-                         && location == "https://you"));
+                         && location == "https://you"
+                         && max_age == Some(1800)
+                         && boot_id.is_none()));
     }
 
+    #[cfg(feature = "advertise")]
     #[test]
     fn notify_round_trip() {
         let mut buf = [0u8; 512];
@@ -599,19 +1046,280 @@ SERVER: none/0 UPnP/1.0 {}/{}\r
             "upnp::rootdevice",
             "uuid:xyz",
             "https://you",
+            None,
+            MessageStyle::default(),
         );
         let msg = parse(&buf[0..n]).unwrap();
         assert!(matches!(msg,
-                         Message::NotifyAlive { notification_type, unique_service_name, location }
+                         Message::NotifyAlive { notification_type, unique_service_name, location, max_age, boot_id }
                          if notification_type == "upnp::rootdevice"
                          && unique_service_name == "uuid:xyz"
-                         && location == "https://you"));
+                         && location == "https://you"
+                         && max_age == Some(1800)
+                         && boot_id.is_none()));
+    }
+
+    #[cfg(any(feature = "advertise", feature = "subscribe"))]
+    const HOST: SocketAddrV4 =
+        SocketAddrV4::new(crate::engine::IPV4_GROUP, crate::engine::SSDP_PORT);
+
+    #[cfg(feature = "advertise")]
+    const BOOT: MessageStyle = MessageStyle {
+        strict: false,
+        boot: Some(BootInfo {
+            boot_id: 7,
+            config_id: 123,
+        }),
+        max_size: 512,
+        host: HOST,
+        ttl: 1,
+    };
+
+    #[cfg(feature = "advertise")]
+    #[test]
+    fn builds_upnp11_notify() {
+        let mut buf = [0u8; 512];
+
+        let n = build_notify(
+            &mut buf,
+            "upnp:rootdevice",
+            "uuid:37",
+            "http://me",
+            None,
+            BOOT,
+        );
+        let expected = format!(
+            "NOTIFY * HTTP/1.1\r
+HOST: 239.255.255.250:1900\r
+CACHE-CONTROL: max-age=1800\r
+LOCATION: http://me\r
+NT: upnp:rootdevice\r
+NTS: ssdp:alive\r
+USN: uuid:37\r
+BOOTID.UPNP.ORG: 7\r
+CONFIGID.UPNP.ORG: 123\r
+SERVER: none/0 UPnP/1.1 {}/{}\r
+\r\n",
+            env!("CARGO_PKG_NAME"),
+            env!("CARGO_PKG_VERSION"),
+        );
+        assert_eq!(expected.as_bytes(), &buf[0..n]);
     }
 
+    #[cfg(feature = "advertise")]
+    #[test]
+    fn builds_upnp11_byebye() {
+        let mut buf = [0u8; 512];
+
+        let n = build_byebye(
+            &mut buf,
+            "upnp:rootdevice",
+            "uuid:37",
+            MessageStyle {
+                strict: true,
+                ..BOOT
+            },
+        );
+        let expected = b"NOTIFY * HTTP/1.1\r
+HOST: 239.255.255.250:1900\r
+NT: upnp:rootdevice\r
+NTS: ssdp:byebye\r
+USN: uuid:37\r
+BOOTID.UPNP.ORG: 7\r
+CONFIGID.UPNP.ORG: 123\r
+\r\n";
+        assert_eq!(expected, &buf[0..n]);
+    }
+
+    #[cfg(feature = "advertise")]
+    #[test]
+    fn builds_update() {
+        let mut buf = [0u8; 512];
+
+        let n = build_update(
+            &mut buf,
+            "upnp:rootdevice",
+            "uuid:37",
+            "http://me",
+            BOOT.boot.unwrap(),
+            8,
+            HOST,
+        );
+        let expected = b"NOTIFY * HTTP/1.1\r
+HOST: 239.255.255.250:1900\r
+LOCATION: http://me\r
+NT: upnp:rootdevice\r
+NTS: ssdp:update\r
+USN: uuid:37\r
+BOOTID.UPNP.ORG: 7\r
+CONFIGID.UPNP.ORG: 123\r
+NEXTBOOTID.UPNP.ORG: 8\r
+\r\n";
+        assert_eq!(expected, &buf[0..n]);
+    }
+
+    #[cfg(feature = "advertise")]
+    #[test]
+    fn boot_id_round_trip() {
+        let mut buf = [0u8; 512];
+        let n = build_notify(&mut buf, "a", "uuid:b", "http://c", None, BOOT);
+        let msg = parse(&buf[0..n]).unwrap();
+        assert!(matches!(
+            msg,
+            Message::NotifyAlive {
+                boot_id: Some(7),
+                ..
+            }
+        ));
+
+        let n =
+            build_response(&mut buf, "a", "uuid:b", "http://c", None, BOOT);
+        let msg = parse(&buf[0..n]).unwrap();
+        assert!(matches!(
+            msg,
+            Message::Response {
+                boot_id: Some(7),
+                ..
+            }
+        ));
+
+        let n = build_update(
+            &mut buf,
+            "a",
+            "uuid:b",
+            "http://c",
+            BOOT.boot.unwrap(),
+            8,
+            HOST,
+        );
+        let msg = parse(&buf[0..n]).unwrap();
+        assert!(matches!(msg,
+                         Message::NotifyUpdate { notification_type, unique_service_name, location, next_boot_id }
+                         if notification_type == "a"
+                         && unique_service_name == "uuid:b"
+                         && location == "http://c"
+                         && next_boot_id == 8));
+    }
+
+    #[test]
+    fn rejects_bad_boot_ids() {
+        let msg = parse(
+            b"NOTIFY * HTTP/1.1\r
+HOST: 239.255.255.250:1900\r
+LOCATION: http://me\r
+NT: upnp:rootdevice\r
+NTS: ssdp:alive\r
+USN: uuid:37\r
+BOOTID.UPNP.ORG: fnord\r
+\r\n",
+        )
+        .unwrap();
+        assert!(matches!(msg, Message::NotifyAlive { boot_id: None, .. }));
+
+        // An update without NEXTBOOTID is no use to anyone
+        assert!(parse(
+            b"NOTIFY * HTTP/1.1\r
+HOST: 239.255.255.250:1900\r
+LOCATION: http://me\r
+NT: upnp:rootdevice\r
+NTS: ssdp:update\r
+USN: uuid:37\r
+BOOTID.UPNP.ORG: 7\r
+\r\n",
+        )
+        .is_err());
+    }
+
+    #[cfg(feature = "advertise")]
+    #[test]
+    fn builds_strict_response() {
+        let mut buf = [0u8; 512];
+
+        let n = build_response(
+            &mut buf,
+            "upnp::rootdevice",
+            "uuid:37",
+            "http://me",
+            None,
+            MessageStyle {
+                strict: true,
+                ..Default::default()
+            },
+        );
+        let expected = format!(
+            "HTTP/1.1 200 OK\r
+CACHE-CONTROL: max-age=1800\r
+EXT:\r
+ST: upnp::rootdevice\r
+USN: uuid:37\r
+LOCATION: http://me\r
+SERVER: none/0 UPnP/1.0 {}/{}\r
+\r\n",
+            env!("CARGO_PKG_NAME"),
+            env!("CARGO_PKG_VERSION"),
+        );
+        assert_eq!(expected.as_bytes(), &buf[0..n]);
+    }
+
+    #[cfg(feature = "advertise")]
+    #[test]
+    fn builds_byebye() {
+        let mut buf = [0u8; 512];
+
+        let n = build_byebye(
+            &mut buf,
+            "upnp:rootdevice",
+            "uuid:37",
+            MessageStyle::default(),
+        );
+        let expected = format!(
+            "NOTIFY * HTTP/1.1\r
+HOST: 239.255.255.250:1900\r
+CACHE-CONTROL: max-age=1800\r
+NT: upnp:rootdevice\r
+NTS: ssdp:byebye\r
+USN: uuid:37\r
+SERVER: none/0 UPnP/1.0 {}/{}\r
+\r\n",
+            env!("CARGO_PKG_NAME"),
+            env!("CARGO_PKG_VERSION"),
+        );
+        assert_eq!(expected.as_bytes(), &buf[0..n]);
+    }
+
+    #[cfg(feature = "advertise")]
+    #[test]
+    fn builds_strict_byebye() {
+        let mut buf = [0u8; 512];
+
+        let n = build_byebye(
+            &mut buf,
+            "upnp:rootdevice",
+            "uuid:37",
+            MessageStyle {
+                strict: true,
+                ..Default::default()
+            },
+        );
+        let expected = b"NOTIFY * HTTP/1.1\r
+HOST: 239.255.255.250:1900\r
+NT: upnp:rootdevice\r
+NTS: ssdp:byebye\r
+USN: uuid:37\r
+\r\n";
+        assert_eq!(expected, &buf[0..n]);
+    }
+
+    #[cfg(feature = "advertise")]
     #[test]
     fn byebye_round_trip() {
         let mut buf = [0u8; 512];
-        let n = build_byebye(&mut buf, "upnp::rootdevice", "uuid:xyz");
+        let n = build_byebye(
+            &mut buf,
+            "upnp::rootdevice",
+            "uuid:xyz",
+            MessageStyle::default(),
+        );
         let msg = parse(&buf[0..n]).unwrap();
         assert!(matches!(msg,
                          Message::NotifyByeBye { notification_type, unique_service_name }
@@ -626,10 +1334,74 @@ SERVER: none/0 UPnP/1.0 {}/{}\r
         assert_eq!(e, "InvalidData".to_string());
     }
 
+    #[cfg(feature = "advertise")]
     #[test]
     fn overflow() {
+        let mut full = [0u8; 512];
+        let n = build_response(
+            &mut full,
+            "foo",
+            "bar",
+            "wurdle",
+            None,
+            MessageStyle::default(),
+        );
+
         let mut buf = [0u8; 6];
-        let e = build_response(&mut buf, "foo", "bar", "wurdle");
-        assert!(e <= 6);
+        let e = build_response(
+            &mut buf,
+            "foo",
+            "bar",
+            "wurdle",
+            None,
+            MessageStyle::default(),
+        );
+        assert_eq!(e, n);
+        assert!(e > buf.len());
+    }
+
+    #[cfg(feature = "advertise")]
+    #[test]
+    fn host_header_follows_style() {
+        let mut buf = [0u8; 512];
+        let style = MessageStyle {
+            host: SocketAddrV4::new(
+                no_std_net::Ipv4Addr::new(239, 255, 255, 251),
+                1901,
+            ),
+            ..Default::default()
+        };
+        let n = build_notify(&mut buf, "a", "uuid:b", "http://c", None, style);
+        let msg = core::str::from_utf8(&buf[0..n]).unwrap();
+        assert!(msg.contains("\r\nHOST: 239.255.255.251:1901\r\n"));
+
+        let n = build_byebye(&mut buf, "a", "uuid:b", style);
+        let msg = core::str::from_utf8(&buf[0..n]).unwrap();
+        assert!(msg.contains("\r\nHOST: 239.255.255.251:1901\r\n"));
+    }
+
+    #[cfg(feature = "advertise")]
+    #[test]
+    fn measure_without_writing() {
+        let mut full = [0u8; 512];
+        let n = build_notify(
+            &mut full,
+            "foo",
+            "bar",
+            "wurdle",
+            Some("https://wurdle"),
+            MessageStyle::default(),
+        );
+        assert_eq!(
+            build_notify(
+                &mut [],
+                "foo",
+                "bar",
+                "wurdle",
+                Some("https://wurdle"),
+                MessageStyle::default(),
+            ),
+            n
+        );
     }
 }
@@ -0,0 +1,223 @@
+#[cfg(not(feature = "std"))]
+use alloc::string::String;
+
+/// An SSDP notification type (or search target)
+///
+/// Notification types are usually URNs, such as
+/// "urn:schemas-upnp-org:service:ContentDirectory:1", whose last
+/// component is a version number. A search for one version of a type
+/// is satisfied by resources offering that version or any later one
+/// (UPnP DA 1.0 s1.2.3), and [`NotificationType::matches`] implements
+/// that rule -- the same one that [`Engine`](crate::engine::Engine)
+/// uses when deciding which subscribers to notify.
+///
+/// Well-known types are available as constants in this module, and
+/// can be passed anywhere a string notification type is expected:
+///
+/// ```rust
+/// # use cotton_ssdp::nt;
+/// let s: String = nt::MEDIA_SERVER_1.into();
+/// assert_eq!(s, "urn:schemas-upnp-org:device:MediaServer:1");
+/// assert!(nt::CONTENT_DIRECTORY_1.matches(nt::CONTENT_DIRECTORY_2.as_str()));
+/// ```
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct NotificationType<'a>(&'a str);
+
+impl<'a> NotificationType<'a> {
+    /// Wrap a string as a `NotificationType`
+    #[must_use]
+    pub const fn new(s: &'a str) -> Self {
+        Self(s)
+    }
+
+    /// The notification type as a string
+    #[must_use]
+    pub const fn as_str(&self) -> &'a str {
+        self.0
+    }
+
+    fn split(&self) -> Option<(&'a str, usize)> {
+        let (base, version) = self.0.rsplit_once(':')?;
+        Some((base, version.parse::<usize>().ok()?))
+    }
+
+    /// The type without its version number
+    ///
+    /// For instance, "urn:schemas-upnp-org:device:MediaServer" for
+    /// "urn:schemas-upnp-org:device:MediaServer:1". Types without a
+    /// version number, such as "upnp:rootdevice", are returned whole.
+    #[must_use]
+    pub fn base(&self) -> &'a str {
+        self.split().map_or(self.0, |(base, _)| base)
+    }
+
+    /// The version number, if the type has one
+    #[must_use]
+    pub fn version(&self) -> Option<usize> {
+        self.split().map(|(_, version)| version)
+    }
+
+    /// Does a resource of type `candidate` satisfy a search for this type?
+    ///
+    /// True if this type is "ssdp:all", or if the types are equal, or
+    /// if they differ only in that `candidate` has a later version
+    /// number.
+    #[must_use]
+    pub fn matches(&self, candidate: &str) -> bool {
+        if self.0 == ALL.0 || self.0 == candidate {
+            return true;
+        }
+        match (self.split(), NotificationType(candidate).split()) {
+            (Some((sbase, sversion)), Some((cbase, cversion))) => {
+                sbase == cbase && cversion >= sversion
+            }
+            _ => false,
+        }
+    }
+
+    /// Does a resource of this type satisfy a search for `search`?
+    ///
+    /// The converse of [`NotificationType::matches`].
+    #[must_use]
+    pub fn satisfies(&self, search: &str) -> bool {
+        NotificationType(search).matches(self.0)
+    }
+}
+
+impl AsRef<str> for NotificationType<'_> {
+    fn as_ref(&self) -> &str {
+        self.0
+    }
+}
+
+impl core::fmt::Display for NotificationType<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(self.0)
+    }
+}
+
+impl From<NotificationType<'_>> for String {
+    fn from(nt: NotificationType<'_>) -> Self {
+        Self::from(nt.0)
+    }
+}
+
+/// Search target matching every resource
+pub const ALL: NotificationType<'static> = NotificationType("ssdp:all");
+
+/// Every UPnP root device
+pub const ROOT_DEVICE: NotificationType<'static> =
+    NotificationType("upnp:rootdevice");
+
+/// UPnP AV media server, version 1
+pub const MEDIA_SERVER_1: NotificationType<'static> =
+    NotificationType("urn:schemas-upnp-org:device:MediaServer:1");
+
+/// UPnP AV media renderer, version 1
+pub const MEDIA_RENDERER_1: NotificationType<'static> =
+    NotificationType("urn:schemas-upnp-org:device:MediaRenderer:1");
+
+/// UPnP Internet gateway device (router), version 1
+pub const INTERNET_GATEWAY_DEVICE_1: NotificationType<'static> =
+    NotificationType("urn:schemas-upnp-org:device:InternetGatewayDevice:1");
+
+/// UPnP Internet gateway device (router), version 2
+pub const INTERNET_GATEWAY_DEVICE_2: NotificationType<'static> =
+    NotificationType("urn:schemas-upnp-org:device:InternetGatewayDevice:2");
+
+/// UPnP AV content directory service, version 1
+pub const CONTENT_DIRECTORY_1: NotificationType<'static> =
+    NotificationType("urn:schemas-upnp-org:service:ContentDirectory:1");
+
+/// UPnP AV content directory service, version 2
+pub const CONTENT_DIRECTORY_2: NotificationType<'static> =
+    NotificationType("urn:schemas-upnp-org:service:ContentDirectory:2");
+
+/// UPnP AV content directory service, version 3
+pub const CONTENT_DIRECTORY_3: NotificationType<'static> =
+    NotificationType("urn:schemas-upnp-org:service:ContentDirectory:3");
+
+/// UPnP AV content directory service, version 4
+pub const CONTENT_DIRECTORY_4: NotificationType<'static> =
+    NotificationType("urn:schemas-upnp-org:service:ContentDirectory:4");
+
+/// DIAL ("Discovery and Launch") server, version 1, see [`crate::dial`]
+pub const DIAL_1: NotificationType<'static> =
+    NotificationType("urn:dial-multiscreen-org:service:dial:1");
+
+/// cotton-ssdp's own diagnostic advertisement, see [`crate::diag`]
+pub const COTTON_SSDP_DIAG_1: NotificationType<'static> =
+    NotificationType("urn:cotton:ssdp-diag:1");
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::format;
+    use alloc::string::ToString;
+
+    #[test]
+    fn base_and_version() {
+        assert_eq!(
+            MEDIA_SERVER_1.base(),
+            "urn:schemas-upnp-org:device:MediaServer"
+        );
+        assert_eq!(MEDIA_SERVER_1.version(), Some(1));
+        assert_eq!(CONTENT_DIRECTORY_4.version(), Some(4));
+        assert_eq!(ROOT_DEVICE.base(), "upnp:rootdevice");
+        assert_eq!(ROOT_DEVICE.version(), None);
+        assert_eq!(NotificationType::new("fnord").base(), "fnord");
+        assert_eq!(NotificationType::new("fnord").version(), None);
+    }
+
+    #[test]
+    fn all_matches_everything() {
+        assert!(ALL.matches(ROOT_DEVICE.as_str()));
+        assert!(ALL.matches("fnord"));
+        assert!(!ROOT_DEVICE.matches(ALL.as_str()));
+    }
+
+    #[test]
+    fn later_versions_match() {
+        assert!(CONTENT_DIRECTORY_1.matches(CONTENT_DIRECTORY_1.as_str()));
+        assert!(CONTENT_DIRECTORY_1.matches(CONTENT_DIRECTORY_3.as_str()));
+        assert!(!CONTENT_DIRECTORY_3.matches(CONTENT_DIRECTORY_1.as_str()));
+        assert!(INTERNET_GATEWAY_DEVICE_1
+            .matches(INTERNET_GATEWAY_DEVICE_2.as_str()));
+        assert!(!MEDIA_SERVER_1.matches(MEDIA_RENDERER_1.as_str()));
+        assert!(!MEDIA_SERVER_1.matches(CONTENT_DIRECTORY_1.as_str()));
+    }
+
+    #[test]
+    fn noncanonical_forms_dont_match() {
+        let unversioned = NotificationType::new(
+            "urn:schemas-upnp-org:service:ContentDirectory",
+        );
+        let bad_version = NotificationType::new(
+            "urn:schemas-upnp-org:service:ContentDirectory:X",
+        );
+        assert!(!unversioned.matches(CONTENT_DIRECTORY_1.as_str()));
+        assert!(!CONTENT_DIRECTORY_1.matches(unversioned.as_str()));
+        assert!(!bad_version.matches(CONTENT_DIRECTORY_1.as_str()));
+        assert!(!CONTENT_DIRECTORY_1.matches(bad_version.as_str()));
+    }
+
+    #[test]
+    fn satisfies() {
+        assert!(CONTENT_DIRECTORY_2.satisfies(CONTENT_DIRECTORY_1.as_str()));
+        assert!(!CONTENT_DIRECTORY_1.satisfies(CONTENT_DIRECTORY_2.as_str()));
+        assert!(ROOT_DEVICE.satisfies("ssdp:all"));
+    }
+
+    #[test]
+    fn conversions() {
+        let s: String = ROOT_DEVICE.into();
+        assert_eq!(s, "upnp:rootdevice");
+        assert_eq!(ROOT_DEVICE.to_string(), "upnp:rootdevice");
+        assert_eq!(
+            format!("{ROOT_DEVICE:?}"),
+            "NotificationType(\"upnp:rootdevice\")"
+        );
+        assert_eq!(ROOT_DEVICE.as_ref(), "upnp:rootdevice");
+        assert_eq!(NotificationType::new("upnp:rootdevice"), ROOT_DEVICE);
+    }
+}
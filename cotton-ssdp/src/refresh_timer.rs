@@ -14,6 +14,16 @@ pub trait Timebase {
 
     /// Representing a moment in time, see `std::time::Instant`
     type Instant: AddAssign<Self::Duration> + Ord + Copy + Debug;
+
+    /// The time from `earlier` until `later`, or zero if `later` is
+    /// in fact earlier
+    ///
+    /// This is `core::time::Duration` whatever the timebase, so that
+    /// callers can convert it to whatever their timers need.
+    fn saturating_duration_since(
+        later: Self::Instant,
+        earlier: Self::Instant,
+    ) -> core::time::Duration;
 }
 
 /// Implementing the `Timebase` abstraction in terms of smoltcp types
@@ -24,6 +34,61 @@ pub struct SmoltcpTimebase();
 impl Timebase for SmoltcpTimebase {
     type Duration = smoltcp::time::Duration;
     type Instant = smoltcp::time::Instant;
+
+    fn saturating_duration_since(
+        later: Self::Instant,
+        earlier: Self::Instant,
+    ) -> core::time::Duration {
+        if later > earlier {
+            (later - earlier).into()
+        } else {
+            core::time::Duration::ZERO
+        }
+    }
+}
+
+/// Implementing the `Timebase` abstraction in terms of Embassy types
+///
+/// Embassy's `Instant` can be used directly, but its `Duration` isn't
+/// `From<core::time::Duration>`, so is wrapped in [`EmbassyDuration`].
+#[cfg(feature = "embassy")]
+pub struct EmbassyTimebase();
+
+/// A newtype making `embassy_time::Duration` usable in a `Timebase`
+#[cfg(feature = "embassy")]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(transparent)]
+pub struct EmbassyDuration(pub embassy_time::Duration);
+
+#[cfg(feature = "embassy")]
+impl From<core::time::Duration> for EmbassyDuration {
+    fn from(d: core::time::Duration) -> Self {
+        Self(embassy_time::Duration::from_micros(
+            d.as_micros().try_into().unwrap_or(u64::MAX),
+        ))
+    }
+}
+
+#[cfg(feature = "embassy")]
+impl AddAssign<EmbassyDuration> for embassy_time::Instant {
+    fn add_assign(&mut self, d: EmbassyDuration) {
+        *self += d.0;
+    }
+}
+
+#[cfg(feature = "embassy")]
+impl Timebase for EmbassyTimebase {
+    type Duration = EmbassyDuration;
+    type Instant = embassy_time::Instant;
+
+    fn saturating_duration_since(
+        later: Self::Instant,
+        earlier: Self::Instant,
+    ) -> core::time::Duration {
+        core::time::Duration::from_micros(
+            later.saturating_duration_since(earlier).as_micros(),
+        )
+    }
 }
 
 /// Implementing the `Timebase` abstraction in terms of standard types
@@ -34,6 +99,13 @@ pub struct StdTimebase();
 impl Timebase for StdTimebase {
     type Duration = std::time::Duration;
     type Instant = std::time::Instant;
+
+    fn saturating_duration_since(
+        later: Self::Instant,
+        earlier: Self::Instant,
+    ) -> core::time::Duration {
+        later.saturating_duration_since(earlier)
+    }
 }
 
 /// Encapsulating the SSDP retransmit process
@@ -42,10 +114,18 @@ impl Timebase for StdTimebase {
 /// notification messages. The interval between salvos is randomised to
 /// help avoid network congestion.
 ///
+/// The first salvo can also be held back by a random delay (see
+/// [`RefreshTimer::with_initial_delay`]), so that a roomful of devices
+/// all powered-up at once -- say, after a power cut -- don't all
+/// announce themselves at once.
+///
 pub struct RefreshTimer<T: Timebase> {
     random_seed: u32,
     next_salvo: T::Instant,
     phase: u8,
+    quiet: bool,
+    /// Whether any salvo has been sent since creation (or reset)
+    started: bool,
 }
 
 impl<T: Timebase> RefreshTimer<T> {
@@ -57,13 +137,68 @@ impl<T: Timebase> RefreshTimer<T> {
             random_seed,
             next_salvo: now,
             phase: 0u8,
+            quiet: false,
+            started: false,
         }
     }
 
+    /// Create a new [`RefreshTimer`] whose first salvo is delayed
+    ///
+    /// The delay is chosen (using the random seed) to lie between
+    /// zero and `max_delay`; so, if the seeds differ between devices
+    /// -- as they do if derived from a per-device unique ID using
+    /// cotton-unique -- so do the delays. Until the first salvo is
+    /// due, [`RefreshTimer::is_quiet`] returns true.
+    #[must_use]
+    pub fn with_initial_delay(
+        random_seed: u32,
+        now: T::Instant,
+        max_delay: core::time::Duration,
+    ) -> Self {
+        let max_delay_ms = max_delay.as_millis().min(u32::MAX as u128) as u32;
+        if max_delay_ms == 0 {
+            return Self::new(random_seed, now);
+        }
+        // Scrambled, so as not to correlate with the salvo offsets
+        let delay_ms = random_seed.wrapping_mul(0x9E37_79B9) % max_delay_ms;
+        let mut next_salvo = now;
+        next_salvo +=
+            core::time::Duration::from_millis(delay_ms as u64).into();
+        Self {
+            random_seed,
+            next_salvo,
+            phase: 0u8,
+            quiet: true,
+            started: false,
+        }
+    }
+
+    /// Is the first salvo still being held back?
+    ///
+    /// While this is true, announcements should only be made by
+    /// the salvo itself, not as soon as (for instance) a new
+    /// advertisement is added.
+    #[must_use]
+    pub fn is_quiet(&self) -> bool {
+        self.quiet
+    }
+
     /// Reset the refresh timer (e.g. if network has gone away and come back)
     pub fn reset(&mut self, now: T::Instant) {
         self.next_salvo = now;
         self.phase = 0;
+        self.quiet = false;
+        self.started = false;
+    }
+
+    /// Is a burst of salvos due, or under way?
+    ///
+    /// True from creation (or reset) until the last salvo of the first
+    /// burst, and thereafter from the first salvo of each burst until
+    /// its last; false during the long waits between bursts.
+    #[must_use]
+    pub fn in_burst(&self) -> bool {
+        !self.started || self.phase != 0
     }
 
     /// Obtain the desired delay before the next refresh is needed
@@ -81,6 +216,8 @@ impl<T: Timebase> RefreshTimer<T> {
         if now < self.next_salvo {
             return;
         }
+        self.quiet = false;
+        self.started = true;
         // random offset 0-2550ms
         let random_offset =
             ((self.random_seed >> (self.phase * 8)) & 255) * 10;
@@ -105,6 +242,36 @@ mod tests {
         assert_eq!(f.next_refresh(), now);
     }
 
+    #[test]
+    fn std_duration_since_saturates() {
+        let now = Instant::now();
+        let later = now + Duration::from_millis(1500);
+        assert_eq!(
+            StdTimebase::saturating_duration_since(later, now),
+            Duration::from_millis(1500)
+        );
+        assert_eq!(
+            StdTimebase::saturating_duration_since(now, later),
+            Duration::ZERO
+        );
+    }
+
+    #[cfg(feature = "smoltcp")]
+    #[test]
+    fn smoltcp_duration_since_saturates() {
+        use smoltcp::time::Instant;
+        let now = Instant::from_millis(1000);
+        let later = Instant::from_millis(2500);
+        assert_eq!(
+            SmoltcpTimebase::saturating_duration_since(later, now),
+            Duration::from_millis(1500)
+        );
+        assert_eq!(
+            SmoltcpTimebase::saturating_duration_since(now, later),
+            Duration::ZERO
+        );
+    }
+
     #[test]
     fn retransmit_sets_timeouts() {
         let mut now = Instant::now();
@@ -140,6 +307,27 @@ mod tests {
         assert!(t == t2);
     }
 
+    #[test]
+    fn bursts() {
+        let mut now = Instant::now();
+        let mut f = RefreshTimer::<StdTimebase>::new(0, now);
+        assert!(f.in_burst());
+
+        for _ in 0..3 {
+            f.update_refresh(now);
+            assert!(f.in_burst());
+            now = f.next_refresh();
+        }
+        f.update_refresh(now);
+        assert!(!f.in_burst());
+
+        f.update_refresh(f.next_refresh());
+        assert!(f.in_burst());
+
+        f.reset(now);
+        assert!(f.in_burst());
+    }
+
     #[test]
     fn reset() {
         let now = Instant::now();
@@ -149,4 +337,78 @@ mod tests {
         f.reset(now);
         assert_eq!(f.next_refresh(), now);
     }
+
+    #[test]
+    fn initial_delay() {
+        let now = Instant::now();
+        let mut f = RefreshTimer::<StdTimebase>::with_initial_delay(
+            12345,
+            now,
+            Duration::from_secs(10),
+        );
+        let t = f.next_refresh() - now;
+        assert!(t > Duration::ZERO && t < Duration::from_secs(10));
+        assert!(f.is_quiet());
+
+        f.update_refresh(now);
+        assert!(f.is_quiet());
+        assert_eq!(f.next_refresh() - now, t);
+
+        f.update_refresh(now + t);
+        assert!(!f.is_quiet());
+        assert!(f.next_refresh() > now + t);
+    }
+
+    #[test]
+    fn initial_delay_varies_with_seed() {
+        let now = Instant::now();
+        let delays = (0..16u32)
+            .map(|seed| {
+                RefreshTimer::<StdTimebase>::with_initial_delay(
+                    seed,
+                    now,
+                    Duration::from_secs(60),
+                )
+                .next_refresh()
+                    - now
+            })
+            .collect::<std::collections::BTreeSet<_>>();
+        assert!(delays.len() > 8);
+    }
+
+    #[test]
+    fn zero_initial_delay() {
+        let now = Instant::now();
+        let f = RefreshTimer::<StdTimebase>::with_initial_delay(
+            12345,
+            now,
+            Duration::ZERO,
+        );
+        assert_eq!(f.next_refresh(), now);
+        assert!(!f.is_quiet());
+    }
+
+    #[test]
+    fn reset_ends_initial_delay() {
+        let now = Instant::now();
+        let mut f = RefreshTimer::<StdTimebase>::with_initial_delay(
+            12345,
+            now,
+            Duration::from_secs(10),
+        );
+        f.reset(now);
+        assert!(!f.is_quiet());
+        assert_eq!(f.next_refresh(), now);
+    }
+
+    #[test]
+    #[cfg(feature = "embassy")]
+    fn embassy_duration() {
+        let d = EmbassyDuration::from(Duration::from_millis(1500));
+        assert_eq!(d.0, embassy_time::Duration::from_millis(1500));
+
+        let mut i = embassy_time::Instant::from_millis(1000);
+        i += d;
+        assert_eq!(i, embassy_time::Instant::from_millis(2500));
+    }
 }
@@ -1,18 +1,170 @@
-use crate::engine::{Callback, Engine};
+use crate::diag::Diagnostics;
+use crate::engine::{
+    Callback, CallbackError, Engine, EngineConfig, HealthEvent,
+    ResponseSource, SocketKind,
+};
 use crate::refresh_timer::StdTimebase;
 use crate::udp;
 use crate::udp::TargetedReceive;
-use crate::{Advertisement, Notification};
+use crate::{
+    Advertisement, DeviceAdvertisement, MatchMode, Notification, Scope,
+};
+use cotton_netif::InterfaceIndex;
+use no_std_net::{IpAddr, SocketAddr};
 use rand::RngCore;
-use std::time::Instant;
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::panic::AssertUnwindSafe;
+use std::time::{Duration, Instant};
 
 struct SyncCallback {
     callback: Box<dyn Fn(&Notification)>,
+    catch_panics: bool,
 }
 
 impl Callback for SyncCallback {
-    fn on_notification(&self, r: &Notification) {
-        (self.callback)(r);
+    fn on_notification(&self, r: &Notification) -> Result<(), CallbackError> {
+        if self.catch_panics {
+            std::panic::catch_unwind(AssertUnwindSafe(|| (self.callback)(r)))
+                .map_err(|_| CallbackError)
+        } else {
+            (self.callback)(r);
+            Ok(())
+        }
+    }
+}
+
+/// The most packets which [`PacketLogger`] logs in any one second
+///
+/// Searches from many hosts at once (a "search storm") can provoke
+/// hundreds of packets; only the first few each second are worth
+/// logging.
+const MAX_PACKETS_LOGGED_PER_SECOND: u32 = 20;
+
+/// Rate-limited logging of SSDP packet summaries
+struct PacketLogger {
+    enabled: bool,
+    window_start: Instant,
+    logged: u32,
+    suppressed: u32,
+}
+
+impl PacketLogger {
+    const fn new(now: Instant) -> Self {
+        Self {
+            enabled: false,
+            window_start: now,
+            logged: 0,
+            suppressed: 0,
+        }
+    }
+
+    /// Decide whether a packet seen at time `now` should be logged
+    ///
+    /// At the start of each one-second window, reports (and resets)
+    /// how many packets the previous windows suppressed.
+    fn should_log(&mut self, now: Instant) -> bool {
+        if !self.enabled {
+            return false;
+        }
+        if now.duration_since(self.window_start) >= Duration::from_secs(1) {
+            if self.suppressed > 0 {
+                log::info!(
+                    "ssdp: {} packets not logged (rate limit)",
+                    self.suppressed
+                );
+            }
+            self.window_start = now;
+            self.logged = 0;
+            self.suppressed = 0;
+        }
+        if self.logged < MAX_PACKETS_LOGGED_PER_SECOND {
+            self.logged += 1;
+            true
+        } else {
+            self.suppressed += 1;
+            false
+        }
+    }
+
+    fn log_incoming(&mut self, buf: &[u8], wasfrom: &SocketAddr) {
+        if self.should_log(Instant::now()) {
+            log::info!("ssdp: <- {wasfrom} {}", summarise(buf));
+        }
+    }
+
+    fn log_outgoing(&mut self, buf: &[u8], to: &SocketAddr) {
+        if self.should_log(Instant::now()) {
+            log::info!("ssdp: -> {to} {}", summarise(buf));
+        }
+    }
+}
+
+/// Summarise an SSDP packet: its first line and its identifying headers
+fn summarise(buf: &[u8]) -> String {
+    let packet = String::from_utf8_lossy(buf);
+    let mut lines = packet.lines();
+    let mut summary = lines.next().unwrap_or_default().to_string();
+    for line in lines {
+        if let Some((key, value)) = line.split_once(':') {
+            let key = key.trim().to_ascii_uppercase();
+            if matches!(key.as_str(), "NT" | "NTS" | "ST" | "USN") {
+                summary.push_str(&format!(" {key}={}", value.trim()));
+            }
+        }
+    }
+    summary
+}
+
+/// A socket which logs, via a [`PacketLogger`], what is sent on it
+struct LoggingSocket<'a> {
+    socket: &'a mio::net::UdpSocket,
+    logger: &'a RefCell<PacketLogger>,
+}
+
+impl<'a> LoggingSocket<'a> {
+    const fn new(
+        socket: &'a mio::net::UdpSocket,
+        logger: &'a RefCell<PacketLogger>,
+    ) -> Self {
+        Self { socket, logger }
+    }
+}
+
+impl udp::TargetedSend for LoggingSocket<'_> {
+    fn send_with<F>(
+        &self,
+        size: usize,
+        to: &SocketAddr,
+        from: &IpAddr,
+        f: F,
+    ) -> Result<(), udp::Error>
+    where
+        F: FnOnce(&mut [u8]) -> usize,
+    {
+        self.socket.send_with(size, to, from, |b| {
+            let n = f(b);
+            self.logger.borrow_mut().log_outgoing(&b[0..n], to);
+            n
+        })
+    }
+
+    fn send_with_ttl<F>(
+        &self,
+        size: usize,
+        to: &SocketAddr,
+        from: &IpAddr,
+        ttl: u8,
+        f: F,
+    ) -> Result<(), udp::Error>
+    where
+        F: FnOnce(&mut [u8]) -> usize,
+    {
+        self.socket.send_with_ttl(size, to, from, ttl, |b| {
+            let n = f(b);
+            self.logger.borrow_mut().log_outgoing(&b[0..n], to);
+            n
+        })
     }
 }
 
@@ -29,14 +181,19 @@ An alternative mechanism, [`crate::AsyncService`], integrates directly
 with [`tokio`] instead of [`mio`].
 
 The implementation requires _two_ UDP sockets: one bound to the
-well-known SSDP port number (1900) which subscribes to the multicast
-group, and a second bound to a random port for sending unicast
+well-known SSDP port number (1900, unless [`Service::with_config`]
+says otherwise) which subscribes to the multicast group, and a second bound to a random port for sending unicast
 searches and receiving unicast replies. (It would be possible to get
 by with a single socket if cotton-ssdp knew it was the _only_ SSDP
 implementation running on that IP address -- but if there might be
 other implementations running, it needs its own search socket in order
 not to steal other applications' packets.)
 
+If you _do_ know that -- on a constrained system, say, or to keep
+firewall rules simple -- [`Service::new_single_socket`] creates a
+`Service` which does everything, searches included, using just the
+one socket on port 1900, and which needs just the one MIO token.
+
 For that reason, _two_ MIO tokens are required; these should be passed
 to [`Service::new`], which takes care of registering them with the MIO
 poller. Likewise, the main polling loop can indicate readiness on
@@ -73,6 +230,7 @@ network, from all network interfaces, and stores unique ones in a
                 ref notification_type,
                 ref unique_service_name,
                 ref location,
+                ..
             } = r {
                 if !m.contains_key(unique_service_name) {
                     m.insert(unique_service_name.clone(), r.clone());
@@ -105,10 +263,7 @@ this simpler example is not in itself compliant with that document.)
 # #[cfg(not(miri))]
     ssdp.advertise(
         uuid.to_string(),
-        cotton_ssdp::Advertisement {
-            notification_type: "test".to_string(),
-            location: "http://127.0.0.1:3333/test".to_string(),
-        },
+        cotton_ssdp::Advertisement::new("test", "http://127.0.0.1:3333/test"),
     );
 ```
 
@@ -162,9 +317,17 @@ this:
 pub struct Service {
     engine: Engine<SyncCallback, StdTimebase>,
     multicast_socket: mio::net::UdpSocket,
-    search_socket: mio::net::UdpSocket,
+    /// `None` in single-socket mode
+    search_socket: Option<mio::net::UdpSocket>,
+    packet_logger: RefCell<PacketLogger>,
+    health_callback: Option<HealthCallback>,
+    catch_callback_panics: bool,
+    started: Instant,
 }
 
+/// The type of the argument to [`Service::set_health_callback`]
+type HealthCallback = Box<dyn Fn(&HealthEvent)>;
+
 /// The type of [`udp::std::setup_socket`]
 type SocketFn = fn(u16) -> Result<std::net::UdpSocket, std::io::Error>;
 
@@ -178,17 +341,23 @@ type RegisterFn = fn(
 impl Service {
     fn new_inner(
         registry: &mio::Registry,
-        tokens: (mio::Token, mio::Token),
+        tokens: (mio::Token, Option<mio::Token>),
         socket: SocketFn,
         register: RegisterFn,
         interfaces: Vec<cotton_netif::NetworkEvent>,
+        config: EngineConfig,
     ) -> Result<Self, std::io::Error> {
         let mut multicast_socket =
-            mio::net::UdpSocket::from_std(socket(1900u16)?);
-        let mut search_socket = mio::net::UdpSocket::from_std(socket(0u16)?); // ephemeral port
-        let mut engine = Engine::<SyncCallback, StdTimebase>::new(
+            mio::net::UdpSocket::from_std(socket(config.port)?);
+        let mut search_socket = match tokens.1 {
+            // ephemeral port
+            Some(_) => Some(mio::net::UdpSocket::from_std(socket(0u16)?)),
+            None => None,
+        };
+        let mut engine = Engine::<SyncCallback, StdTimebase>::with_config(
             rand::thread_rng().next_u32(),
             Instant::now(),
+            config,
         );
 
         for netif in interfaces {
@@ -197,17 +366,25 @@ impl Service {
             _ = engine.on_network_event(
                 &netif,
                 &multicast_socket,
-                &search_socket,
+                Self::send_socket(&search_socket, &multicast_socket),
             );
         }
 
         register(registry, &mut multicast_socket, tokens.0)?;
-        register(registry, &mut search_socket, tokens.1)?;
+        if let (Some(search_socket), Some(token)) =
+            (search_socket.as_mut(), tokens.1)
+        {
+            register(registry, search_socket, token)?;
+        }
 
         Ok(Self {
             engine,
             multicast_socket,
             search_socket,
+            packet_logger: RefCell::new(PacketLogger::new(Instant::now())),
+            health_callback: None,
+            catch_callback_panics: false,
+            started: Instant::now(),
         })
     }
 
@@ -223,16 +400,95 @@ impl Service {
     pub fn new(
         registry: &mio::Registry,
         tokens: (mio::Token, mio::Token),
+    ) -> Result<Self, std::io::Error> {
+        Self::with_config(registry, tokens, EngineConfig::default())
+    }
+
+    /// Create a new `Service`, with non-default tuning parameters
+    ///
+    /// As [`Service::new`], but the `Engine` is created using
+    /// [`Engine::with_config`], and the multicast socket is bound to
+    /// [`EngineConfig::port`].
+    ///
+    /// # Errors
+    ///
+    /// Can return a `std::io::Error` if any of the underlying socket
+    /// calls fail.
+    ///
+    pub fn with_config(
+        registry: &mio::Registry,
+        tokens: (mio::Token, mio::Token),
+        config: EngineConfig,
+    ) -> Result<Self, std::io::Error> {
+        Self::new_inner(
+            registry,
+            (tokens.0, Some(tokens.1)),
+            udp::std::setup_socket,
+            |r, s, t| r.register(s, t, mio::Interest::READABLE),
+            cotton_netif::get_interfaces()?.collect(),
+            config,
+        )
+    }
+
+    /// Create a new `Service` which uses just one UDP socket
+    ///
+    /// The socket, bound to port 1900, is used for searches and
+    /// replies as well as for multicast, and is registered with the
+    /// [`mio::Registry`] using `token`; when it is readable, call
+    /// [`Service::multicast_ready`]. This is only suitable if no other
+    /// SSDP implementation is running on the same host, as unicast
+    /// replies to searches might otherwise go to the wrong one.
+    ///
+    /// # Errors
+    ///
+    /// Can return a `std::io::Error` if any of the underlying socket
+    /// calls fail.
+    ///
+    pub fn new_single_socket(
+        registry: &mio::Registry,
+        token: mio::Token,
+    ) -> Result<Self, std::io::Error> {
+        Self::single_socket_with_config(
+            registry,
+            token,
+            EngineConfig::default(),
+        )
+    }
+
+    /// Create a new `Service` which uses just one UDP socket, with
+    /// non-default tuning parameters
+    ///
+    /// As [`Service::new_single_socket`], but see
+    /// [`Service::with_config`].
+    ///
+    /// # Errors
+    ///
+    /// Can return a `std::io::Error` if any of the underlying socket
+    /// calls fail.
+    ///
+    pub fn single_socket_with_config(
+        registry: &mio::Registry,
+        token: mio::Token,
+        config: EngineConfig,
     ) -> Result<Self, std::io::Error> {
         Self::new_inner(
             registry,
-            tokens,
+            (token, None),
             udp::std::setup_socket,
             |r, s, t| r.register(s, t, mio::Interest::READABLE),
             cotton_netif::get_interfaces()?.collect(),
+            config,
         )
     }
 
+    /// The socket on which searches and notifications are sent
+    fn send_socket<'a>(
+        search_socket: &'a Option<mio::net::UdpSocket>,
+        multicast_socket: &'a mio::net::UdpSocket,
+    ) -> &'a mio::net::UdpSocket {
+        search_socket.as_ref().unwrap_or(multicast_socket)
+    }
+
     /// Subscribe to notifications about a particular service type
     ///
     /// Or subscribe to "ssdp:all" for notifications about *all* service
@@ -244,13 +500,129 @@ impl Service {
         notification_type: A,
         callback: Box<dyn Fn(&Notification)>,
     ) where
-        A: Into<String>,
+        A: Into<Cow<'static, str>>,
     {
         self.engine.subscribe(
             notification_type.into(),
-            SyncCallback { callback },
-            &self.search_socket,
+            SyncCallback {
+                callback,
+                catch_panics: self.catch_callback_panics,
+            },
+            &LoggingSocket::new(
+                Self::send_socket(&self.search_socket, &self.multicast_socket),
+                &self.packet_logger,
+            ),
+        );
+        self.dispatch_health();
+    }
+
+    /// Subscribe to notifications selected by a particular [`MatchMode`]
+    ///
+    /// Searches are sent for `notification_type` (which can be
+    /// "ssdp:all"), but the callback is called for every notification
+    /// which satisfies `match_mode`: for instance, every notification
+    /// whose USN names a particular device UUID.
+    ///
+    /// This call also sends fresh search messages.
+    pub fn subscribe_matching<A>(
+        &mut self,
+        notification_type: A,
+        match_mode: MatchMode,
+        callback: Box<dyn Fn(&Notification)>,
+    ) where
+        A: Into<Cow<'static, str>>,
+    {
+        self.engine.subscribe_matching(
+            notification_type.into(),
+            match_mode,
+            SyncCallback {
+                callback,
+                catch_panics: self.catch_callback_panics,
+            },
+            &LoggingSocket::new(
+                Self::send_socket(&self.search_socket, &self.multicast_socket),
+                &self.packet_logger,
+            ),
         );
+        self.dispatch_health();
+    }
+
+    /// Turn logging of SSDP packets on or off
+    ///
+    /// When on, a one-line summary of each packet sent or received
+    /// is logged (at `Info` level) using the [`log`] crate. To avoid
+    /// flooding the log during search storms, only the first few
+    /// packets in each second are logged, followed by a count of
+    /// those which weren't. Logging is off by default.
+    pub fn set_packet_logging(&mut self, enabled: bool) {
+        self.packet_logger.get_mut().enabled = enabled;
+    }
+
+    /// Be told when sending starts (or stops) failing on an interface
+    ///
+    /// The callback is called with each [`HealthEvent`] as it occurs
+    /// -- or, for any that occurred before it was set, on the next
+    /// call to a `Service` method which sends packets. Applications
+    /// can use this to re-create their sockets, or to alert an
+    /// operator, if sending fails persistently (three times in a row
+    /// on the same interface).
+    pub fn set_health_callback(&mut self, callback: HealthCallback) {
+        self.health_callback = Some(callback);
+    }
+
+    /// Turn isolation of panics in subscription callbacks on or off
+    ///
+    /// Normally, a panic in a callback passed to
+    /// [`Service::subscribe`] unwinds out of whichever `Service` method
+    /// received the notification, and so usually out of the
+    /// application's event loop. When this is on, such panics are
+    /// instead caught, and count as failures of the callback: after
+    /// three in a row, the subscription is cancelled, and a
+    /// [`HealthEvent::SubscriptionCancelled`] is passed to the health
+    /// callback (see [`Service::set_health_callback`]).
+    ///
+    /// This affects subscriptions made after it's called. It's off by
+    /// default. It has no effect if panics abort rather than unwind.
+    pub fn set_catch_callback_panics(&mut self, enabled: bool) {
+        self.catch_callback_panics = enabled;
+    }
+
+    /// Change which socket responses to searches are sent from
+    ///
+    /// By default, they're sent from the search socket (on an ephemeral
+    /// port), or in single-socket mode from the only socket there is.
+    /// See [`ResponseSource`].
+    pub fn set_response_source(&mut self, source: ResponseSource) {
+        self.engine.set_response_source(source);
+    }
+
+    /// Respond to searches arriving on an interface without delay
+    ///
+    /// **Not compliant with the UPnP standard**: for deterministic
+    /// timing in tests only. See [`Engine::set_immediate_responses`].
+    pub fn set_immediate_responses(
+        &mut self,
+        interface: InterfaceIndex,
+        immediate: bool,
+    ) {
+        self.engine.set_immediate_responses(interface, immediate);
+    }
+
+    /// Report the service's state, for diagnostic purposes
+    ///
+    /// See [`crate::diag`].
+    #[must_use]
+    pub fn diagnostics(&self) -> Diagnostics {
+        self.engine.diagnostics(self.started.elapsed())
+    }
+
+    /// Pass any pending health events to the health callback
+    fn dispatch_health(&mut self) {
+        if let Some(callback) = &self.health_callback {
+            while let Some(event) = self.engine.poll_health_event() {
+                callback(&event);
+            }
+        }
     }
 
     /// Advertise a local resource on the network
@@ -259,13 +631,17 @@ impl Service {
         unique_service_name: USN,
         advertisement: Advertisement,
     ) where
-        USN: Into<String>,
+        USN: Into<Cow<'static, str>>,
     {
         self.engine.advertise(
             unique_service_name.into(),
             advertisement,
-            &self.search_socket,
+            &LoggingSocket::new(
+                Self::send_socket(&self.search_socket, &self.multicast_socket),
+                &self.packet_logger,
+            ),
         );
+        self.dispatch_health();
     }
 
     /// Withdraw an advertisement for a local resource
@@ -274,41 +650,149 @@ impl Service {
     /// cleanly.
     ///
     pub fn deadvertise(&mut self, unique_service_name: &str) {
-        self.engine
-            .deadvertise(unique_service_name, &self.search_socket);
+        self.engine.deadvertise(
+            unique_service_name,
+            &LoggingSocket::new(
+                Self::send_socket(&self.search_socket, &self.multicast_socket),
+                &self.packet_logger,
+            ),
+        );
+        self.dispatch_health();
+    }
+
+    /// Change an existing advertisement, without withdrawing it
+    ///
+    /// For instance, to announce a new LOCATION without the
+    /// ssdp:byebye that `deadvertise` followed by `advertise` would
+    /// send; see [`Engine::update_advertisement`]. Returns `false` if
+    /// there is no advertisement with that unique service name.
+    pub fn update_advertisement(
+        &mut self,
+        unique_service_name: &str,
+        advertisement: Advertisement,
+    ) -> bool {
+        let updated = self.engine.update_advertisement(
+            unique_service_name,
+            advertisement,
+            &LoggingSocket::new(
+                Self::send_socket(&self.search_socket, &self.multicast_socket),
+                &self.packet_logger,
+            ),
+        );
+        self.dispatch_health();
+        updated
+    }
+
+    /// Change how far an advertisement's notifications are sent
+    ///
+    /// Advertisements are link-local unless this is called; see
+    /// [`Engine::set_advertisement_scope`]. Returns `false` if there
+    /// is no advertisement with that unique service name.
+    pub fn set_advertisement_scope(
+        &mut self,
+        unique_service_name: &str,
+        scope: Scope,
+    ) -> bool {
+        let found = self.engine.set_advertisement_scope(
+            unique_service_name,
+            scope,
+            &LoggingSocket::new(
+                Self::send_socket(&self.search_socket, &self.multicast_socket),
+                &self.packet_logger,
+            ),
+        );
+        self.dispatch_health();
+        found
+    }
+
+    /// Advertise a whole UPnP device, with all its resources
+    ///
+    /// Each resource is advertised with the USN appropriate to its
+    /// notification type; see [`DeviceAdvertisement`].
+    pub fn advertise_device(&mut self, device: &DeviceAdvertisement) {
+        self.engine.advertise_device(
+            device,
+            &LoggingSocket::new(
+                Self::send_socket(&self.search_socket, &self.multicast_socket),
+                &self.packet_logger,
+            ),
+        );
+        self.dispatch_health();
+    }
+
+    /// Withdraw all the advertisements for a whole UPnP device
+    pub fn deadvertise_device(&mut self, device: &DeviceAdvertisement) {
+        self.engine.deadvertise_device(
+            device,
+            &LoggingSocket::new(
+                Self::send_socket(&self.search_socket, &self.multicast_socket),
+                &self.packet_logger,
+            ),
+        );
+        self.dispatch_health();
     }
 
     /// Handler to be called when multicast socket is readable
+    ///
+    /// In single-socket mode, this socket receives everything.
     pub fn multicast_ready(&mut self) {
         let mut buf = [0u8; 1500];
         while let Ok((n, wasto, wasfrom)) =
             self.multicast_socket.receive_to(&mut buf)
         {
-            self.engine
-                .on_data(&buf[0..n], wasto, wasfrom, Instant::now());
+            self.packet_logger
+                .borrow_mut()
+                .log_incoming(&buf[0..n], &wasfrom);
+            self.engine.on_socket_data(
+                &buf[0..n],
+                SocketKind::Port1900,
+                wasto,
+                wasfrom,
+                Instant::now(),
+            );
         }
     }
 
     /// Handler to be called when search socket is readable
+    ///
+    /// In single-socket mode, there is no search socket, and this
+    /// does nothing.
     pub fn search_ready(&mut self) {
+        let Some(search_socket) = &self.search_socket else {
+            return;
+        };
         let mut buf = [0u8; 1500];
-        while let Ok((n, wasto, wasfrom)) =
-            self.search_socket.receive_to(&mut buf)
+        while let Ok((n, wasto, wasfrom)) = search_socket.receive_to(&mut buf)
         {
-            self.engine
-                .on_data(&buf[0..n], wasto, wasfrom, Instant::now());
+            self.packet_logger
+                .borrow_mut()
+                .log_incoming(&buf[0..n], &wasfrom);
+            self.engine.on_socket_data(
+                &buf[0..n],
+                SocketKind::Ephemeral,
+                wasto,
+                wasfrom,
+                Instant::now(),
+            );
         }
     }
 
     /// Time before next wakeup
     pub fn next_wakeup(&self) -> std::time::Duration {
-        self.engine.poll_timeout() - Instant::now()
+        self.engine.next_wake_in(Instant::now())
     }
 
     /// Handler to be called when wakeup timer elapses
     pub fn wakeup(&mut self) {
-        self.engine
-            .handle_timeout(&self.search_socket, Instant::now());
+        self.engine.handle_timeout_with(
+            &LoggingSocket::new(
+                Self::send_socket(&self.search_socket, &self.multicast_socket),
+                &self.packet_logger,
+            ),
+            &LoggingSocket::new(&self.multicast_socket, &self.packet_logger),
+            Instant::now(),
+        );
+        self.dispatch_health();
     }
 }
 
@@ -348,10 +832,11 @@ mod tests {
 
         let e = Service::new_inner(
             poll.registry(),
-            (SSDP_TOKEN1, SSDP_TOKEN2),
+            (SSDP_TOKEN1, Some(SSDP_TOKEN2)),
             |_| Err(std::io::Error::new(std::io::ErrorKind::Other, "TEST")),
             bogus_register,
             cotton_netif::get_interfaces().unwrap().collect(),
+            EngineConfig::default(),
         );
 
         assert!(e.is_err());
@@ -366,7 +851,7 @@ mod tests {
 
         let e = Service::new_inner(
             poll.registry(),
-            (SSDP_TOKEN1, SSDP_TOKEN2),
+            (SSDP_TOKEN1, Some(SSDP_TOKEN2)),
             |p| {
                 if p == 0 {
                     Err(std::io::Error::new(std::io::ErrorKind::Other, "TEST"))
@@ -376,11 +861,50 @@ mod tests {
             },
             bogus_register,
             cotton_netif::get_interfaces().unwrap().collect(),
+            EngineConfig::default(),
         );
 
         assert!(e.is_err());
     }
 
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn instantiate_single_socket() {
+        const SSDP_TOKEN: mio::Token = mio::Token(37);
+        let poll = mio::Poll::new().unwrap();
+
+        let mut s =
+            Service::new_single_socket(poll.registry(), SSDP_TOKEN).unwrap();
+        assert!(s.search_socket.is_none());
+        s.subscribe("ssdp:all", Box::new(|_| {}));
+        s.search_ready();
+        s.multicast_ready();
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn single_socket_needs_only_one_socket() {
+        const SSDP_TOKEN: mio::Token = mio::Token(37);
+        let poll = mio::Poll::new().unwrap();
+
+        let e = Service::new_inner(
+            poll.registry(),
+            (SSDP_TOKEN, None),
+            |p| {
+                if p == 0 {
+                    Err(my_err())
+                } else {
+                    udp::std::setup_socket(p)
+                }
+            },
+            |r, s, t| r.register(s, t, mio::Interest::READABLE),
+            Vec::default(),
+            EngineConfig::default(),
+        );
+
+        assert!(e.is_ok());
+    }
+
     #[test]
     #[cfg_attr(miri, ignore)]
     fn service_ok_with_no_netifs() {
@@ -390,10 +914,11 @@ mod tests {
 
         let e = Service::new_inner(
             poll.registry(),
-            (SSDP_TOKEN1, SSDP_TOKEN2),
+            (SSDP_TOKEN1, Some(SSDP_TOKEN2)),
             udp::std::setup_socket,
             |r, s, t| r.register(s, t, mio::Interest::READABLE),
             Vec::default(),
+            EngineConfig::default(),
         );
 
         assert!(e.is_ok());
@@ -408,10 +933,11 @@ mod tests {
 
         let e = Service::new_inner(
             poll.registry(),
-            (SSDP_TOKEN1, SSDP_TOKEN2),
+            (SSDP_TOKEN1, Some(SSDP_TOKEN2)),
             udp::std::setup_socket,
             bogus_register,
             cotton_netif::get_interfaces().unwrap().collect(),
+            EngineConfig::default(),
         );
 
         assert!(e.is_err());
@@ -426,7 +952,7 @@ mod tests {
 
         let e = Service::new_inner(
             poll.registry(),
-            (SSDP_TOKEN1, SSDP_TOKEN2),
+            (SSDP_TOKEN1, Some(SSDP_TOKEN2)),
             udp::std::setup_socket,
             |_, _, t| {
                 if t == SSDP_TOKEN1 {
@@ -436,8 +962,130 @@ mod tests {
                 }
             },
             cotton_netif::get_interfaces().unwrap().collect(),
+            EngineConfig::default(),
         );
 
         assert!(e.is_err());
     }
+
+    #[test]
+    fn packet_logger_off_by_default() {
+        let now = Instant::now();
+        let mut p = PacketLogger::new(now);
+        assert!(!p.should_log(now));
+    }
+
+    #[test]
+    fn packet_logger_rate_limits() {
+        let now = Instant::now();
+        let mut p = PacketLogger::new(now);
+        p.enabled = true;
+        for _ in 0..MAX_PACKETS_LOGGED_PER_SECOND {
+            assert!(p.should_log(now));
+        }
+        assert!(!p.should_log(now + Duration::from_millis(500)));
+        assert!(!p.should_log(now + Duration::from_millis(999)));
+        assert_eq!(p.suppressed, 2);
+
+        // New window
+        assert!(p.should_log(now + Duration::from_secs(1)));
+        assert_eq!(p.suppressed, 0);
+        assert_eq!(p.logged, 1);
+    }
+
+    #[test]
+    fn packet_logger_can_be_turned_off() {
+        let now = Instant::now();
+        let mut p = PacketLogger::new(now);
+        p.enabled = true;
+        assert!(p.should_log(now));
+        p.enabled = false;
+        assert!(!p.should_log(now));
+        assert_eq!(p.logged, 1);
+    }
+
+    #[test]
+    fn summarise_notify() {
+        let s = summarise(
+            b"NOTIFY * HTTP/1.1\r\n\
+HOST: 239.255.255.250:1900\r\n\
+CACHE-CONTROL: max-age=1800\r\n\
+LOCATION: http://127.0.0.1/\r\n\
+nt: upnp:rootdevice\r\n\
+NTS: ssdp:alive\r\n\
+USN: uuid:xyz::upnp:rootdevice\r\n\
+\r\n",
+        );
+        assert_eq!(
+            s,
+            "NOTIFY * HTTP/1.1 NT=upnp:rootdevice NTS=ssdp:alive USN=uuid:xyz::upnp:rootdevice"
+        );
+    }
+
+    #[test]
+    fn summarise_garbage() {
+        assert_eq!(summarise(b""), "");
+        assert_eq!(summarise(b"\xff\xfe"), "\u{fffd}\u{fffd}");
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn set_packet_logging() {
+        const SSDP_TOKEN1: mio::Token = mio::Token(37);
+        const SSDP_TOKEN2: mio::Token = mio::Token(94);
+        let poll = mio::Poll::new().unwrap();
+
+        let mut s = Service::new_inner(
+            poll.registry(),
+            (SSDP_TOKEN1, Some(SSDP_TOKEN2)),
+            udp::std::setup_socket,
+            |r, s, t| r.register(s, t, mio::Interest::READABLE),
+            Vec::default(),
+            EngineConfig::default(),
+        )
+        .unwrap();
+        assert!(!s.packet_logger.borrow().enabled);
+        s.set_packet_logging(true);
+        assert!(s.packet_logger.borrow().enabled);
+        s.subscribe("ssdp:all", Box::new(|_| {}));
+        s.set_packet_logging(false);
+        assert!(!s.packet_logger.borrow().enabled);
+    }
+
+    fn alive() -> Notification {
+        Notification::Alive {
+            notification_type: "upnp:rootdevice".into(),
+            unique_service_name: "uuid:1::upnp:rootdevice".into(),
+            location: "http://127.0.0.1/".into(),
+            boot_id: None,
+        }
+    }
+
+    #[test]
+    fn sync_callback_catches_panics() {
+        let c = SyncCallback {
+            callback: Box::new(|_| panic!("callback failed")),
+            catch_panics: true,
+        };
+        assert_eq!(c.on_notification(&alive()), Err(CallbackError));
+    }
+
+    #[test]
+    #[should_panic(expected = "callback failed")]
+    fn sync_callback_propagates_panics_by_default() {
+        let c = SyncCallback {
+            callback: Box::new(|_| panic!("callback failed")),
+            catch_panics: false,
+        };
+        let _ = c.on_notification(&alive());
+    }
+
+    #[test]
+    fn sync_callback_succeeds() {
+        let c = SyncCallback {
+            callback: Box::new(|_| {}),
+            catch_panics: true,
+        };
+        assert_eq!(c.on_notification(&alive()), Ok(()));
+    }
 }
@@ -13,17 +13,21 @@ pub trait TargetedSend {
     /// addresses.
     ///
     /// For how this works see
-    /// <https://man7.org/linux/man-pages/man7/ip.7.html>
-    ///
-    /// This facility probably only works on Linux.
+    /// <https://man7.org/linux/man-pages/man7/ip.7.html> (`IP_PKTINFO`)
+    /// or, on FreeBSD, DragonFly and OpenBSD, `IP_SENDSRCADDR` in
+    /// ip(4).
     ///
     /// The interface is agnostic about IPv4/IPv6, but the current
     /// implementation is IPv4-only.
     ///
+    /// The datagram is written by `f` into a buffer of `size` bytes,
+    /// and is as long as `f` returns.
+    ///
     /// # Errors
     ///
-    /// Returns `Err` if the underlying sendmsg call fails, or
-    /// (currently) if IPv6 is attempted.
+    /// Returns `Err` if the underlying sendmsg call fails,
+    /// [`Error::MessageTooLong`] if the implementation can't send
+    /// `size` bytes, or (currently) if IPv6 is attempted.
     ///
     fn send_with<F>(
         &self,
@@ -34,6 +38,38 @@ pub trait TargetedSend {
     ) -> Result<(), Error>
     where
         F: FnOnce(&mut [u8]) -> usize;
+
+    /// Send a UDP datagram from a specific source IP, with a multicast TTL
+    ///
+    /// As [`TargetedSend::send_with`], but if `to` is a multicast
+    /// address, the datagram is sent with time-to-live (IPv6 hop limit)
+    /// `ttl` instead of the default of 1, so that it can be forwarded
+    /// by multicast routers. For unicast destinations `ttl` is ignored.
+    ///
+    /// The default implementation can only send with a TTL of 1.
+    ///
+    /// # Errors
+    ///
+    /// As [`TargetedSend::send_with`]; and, in the default
+    /// implementation, [`Error::NotImplemented`] if `ttl` isn't 1.
+    ///
+    fn send_with_ttl<F>(
+        &self,
+        size: usize,
+        to: &SocketAddr,
+        from: &IpAddr,
+        ttl: u8,
+        f: F,
+    ) -> Result<(), Error>
+    where
+        F: FnOnce(&mut [u8]) -> usize,
+    {
+        if ttl == 1 || !to.ip().is_multicast() {
+            self.send_with(size, to, from, f)
+        } else {
+            Err(Error::NotImplemented)
+        }
+    }
 }
 
 /// Receiving UDP datagrams, recording which IP we received it on
@@ -44,10 +80,15 @@ pub trait TargetedReceive {
     /// the case of broadcast packets); it's the IP from which the
     /// peer would be expecting a reply to originate.
     ///
-    /// The socket must have its `Ipv4PacketInfo` option enabled,
-    /// using some equivalent of
-    /// `nix::sys::socket::setsockopt`(`s.as_raw_fd`(),
-    /// `nix::sys::socket::sockopt::Ipv4PacketInfo`, &true)?;
+    /// The socket must have destination-address reporting enabled
+    /// (`IP_PKTINFO` or `IP_RECVDSTADDR`, depending on platform),
+    /// for instance using `std::receive_destination_address`.
+    ///
+    /// Where the platform reports only the datagram's destination
+    /// address, and not the local address it was received on, replies
+    /// to multicast datagrams can't be routed by source address: the
+    /// returned IP is then `0.0.0.0`, and sending from that address
+    /// leaves the choice to the kernel.
     ///
     /// The interface is agnostic about IPv4/IPv6, but the current
     /// implementation is IPv4-only.
@@ -92,6 +133,10 @@ pub trait Multicast {
 }
 
 /// Utilities common to all implementations using `std::net` underneath
+///
+/// These are Unix-only, as they use the "nix" crate for the
+/// `IP_PKTINFO` (or `IP_RECVDSTADDR`) control messages; Windows, which
+/// would need `WSARecvMsg` instead, isn't supported.
 #[cfg(any(feature = "sync", feature = "async"))]
 pub mod std;
 
@@ -107,4 +152,8 @@ pub mod tokio;
 #[cfg(feature = "smoltcp")]
 pub mod smoltcp;
 
+/// Trait implementations for Embassy network stacks and sockets
+#[cfg(feature = "embassy")]
+pub mod embassy;
+
 pub use error::{Error, Syscall};
@@ -0,0 +1,127 @@
+use super::smoltcp::{GenericIpAddress, GenericSocketAddr};
+use super::{Error, Syscall};
+use core::future::Future;
+use core::pin::pin;
+use core::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+use cotton_netif::InterfaceIndex;
+use embassy_net::driver::Driver;
+use embassy_net::udp::UdpSocket;
+use embassy_net::Stack;
+use no_std_net::{IpAddr, SocketAddr};
+
+/// The largest datagram `WrappedSocket` can send
+///
+/// Embassy-net 0.4 has no `send_to_with`, so outgoing datagrams are
+/// assembled in a buffer of this size on the stack.
+const MAX_DATAGRAM_SIZE: usize = 1500;
+
+/// Poll a future once, without waiting for it if it isn't ready
+///
+/// The UDP traits are synchronous, but the Embassy operations they
+/// wrap are async (even if they only rarely have to wait).
+fn poll_once<F: Future>(f: F) -> Poll<F::Output> {
+    const VTABLE: RawWakerVTable = RawWakerVTable::new(
+        |_| RawWaker::new(core::ptr::null(), &VTABLE),
+        |_| {},
+        |_| {},
+        |_| {},
+    );
+    // SAFETY: the vtable functions do nothing, so are trivially sound
+    let waker =
+        unsafe { Waker::from_raw(RawWaker::new(core::ptr::null(), &VTABLE)) };
+    let mut cx = Context::from_waker(&waker);
+    pin!(f).poll(&mut cx)
+}
+
+/// Wrap an Embassy network `Stack` so it can be used by cotton-ssdp
+///
+/// Embassy-net only supports a single network interface, so the
+/// interface index passed to the `Multicast` methods is ignored.
+pub struct WrappedStack<'a, D: Driver> {
+    stack: &'a Stack<D>,
+}
+
+impl<'a, D: Driver> WrappedStack<'a, D> {
+    /// Create a new `WrappedStack`
+    pub const fn new(stack: &'a Stack<D>) -> Self {
+        Self { stack }
+    }
+}
+
+impl<D: Driver> super::Multicast for WrappedStack<'_, D> {
+    fn join_multicast_group(
+        &self,
+        multicast_address: &IpAddr,
+        _interface: InterfaceIndex,
+    ) -> Result<(), Error> {
+        let ip: embassy_net::IpAddress =
+            GenericIpAddress::from(*multicast_address).into();
+
+        // Joining doesn't actually wait for anything, so completes on
+        // its first poll
+        match poll_once(self.stack.join_multicast_group(ip)) {
+            Poll::Ready(Ok(_)) => Ok(()),
+            Poll::Ready(Err(e)) => {
+                Err(Error::SmoltcpMulticast(Syscall::JoinMulticast, e))
+            }
+            Poll::Pending => Err(Error::WouldBlock),
+        }
+    }
+
+    fn leave_multicast_group(
+        &self,
+        multicast_address: &IpAddr,
+        _interface: InterfaceIndex,
+    ) -> Result<(), Error> {
+        let ip: embassy_net::IpAddress =
+            GenericIpAddress::from(*multicast_address).into();
+
+        match poll_once(self.stack.leave_multicast_group(ip)) {
+            Poll::Ready(Ok(_)) => Ok(()),
+            Poll::Ready(Err(e)) => {
+                Err(Error::SmoltcpMulticast(Syscall::LeaveMulticast, e))
+            }
+            Poll::Pending => Err(Error::WouldBlock),
+        }
+    }
+}
+
+/// Wrap an Embassy `UdpSocket` so it can be used by cotton-ssdp
+pub struct WrappedSocket<'a, 'b> {
+    socket: &'a UdpSocket<'b>,
+}
+
+impl<'a, 'b> WrappedSocket<'a, 'b> {
+    /// Create a new `WrappedSocket`
+    pub const fn new(socket: &'a UdpSocket<'b>) -> Self {
+        Self { socket }
+    }
+}
+
+impl super::TargetedSend for WrappedSocket<'_, '_> {
+    fn send_with<F>(
+        &self,
+        size: usize,
+        to: &SocketAddr,
+        _from: &IpAddr,
+        f: F,
+    ) -> Result<(), Error>
+    where
+        F: FnOnce(&mut [u8]) -> usize,
+    {
+        let mut buf = [0u8; MAX_DATAGRAM_SIZE];
+        let Some(buf) = buf.get_mut(0..size) else {
+            return Err(Error::MessageTooLong);
+        };
+        let size = f(buf);
+        let ep: embassy_net::IpEndpoint = GenericSocketAddr::from(*to).into();
+
+        // Sending only waits if the socket's transmit buffer is full.
+        // Blocking here would stop the network stack from emptying it,
+        // so instead report that the datagram was dropped.
+        match poll_once(self.socket.send_to(&buf[0..size], ep)) {
+            Poll::Ready(r) => r.map_err(Error::EmbassyUdpSend),
+            Poll::Pending => Err(Error::WouldBlock),
+        }
+    }
+}
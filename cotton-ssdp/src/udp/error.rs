@@ -22,6 +22,14 @@ pub enum Error {
     Ipv6NotImplemented,
     /// Something else not implemented
     NotImplemented,
+    /// The operation couldn't complete without blocking
+    WouldBlock,
+    /// The datagram was too long to send
+    ///
+    /// Either it was longer than
+    /// [`EngineConfig::max_packet_size`](crate::engine::EngineConfig::max_packet_size),
+    /// or longer than the socket implementation can send.
+    MessageTooLong,
 
     /// A system call returned an error
     #[cfg(feature = "std")]
@@ -34,6 +42,10 @@ pub enum Error {
     /// A smoltcp send call returned an error
     #[cfg(feature = "smoltcp")]
     SmoltcpUdpSend(::smoltcp::socket::udp::SendError),
+
+    /// An Embassy send call returned an error
+    #[cfg(feature = "embassy")]
+    EmbassyUdpSend(::embassy_net::udp::SendError),
 }
 
 impl ::core::fmt::Display for Error {
@@ -42,6 +54,8 @@ impl ::core::fmt::Display for Error {
             Self::NoPacketInfo => f.write_str("recvmsg: no pktinfo returned"),
             Self::Ipv6NotImplemented => f.write_str("IPv6 not implemented"),
             Self::NotImplemented => f.write_str("not implemented"),
+            Self::WouldBlock => f.write_str("operation would block"),
+            Self::MessageTooLong => f.write_str("message too long"),
 
             #[cfg(feature = "std")]
             Self::Syscall(s, _) => write!(f, "error from syscall {s:?}"),
@@ -55,6 +69,11 @@ impl ::core::fmt::Display for Error {
             Self::SmoltcpUdpSend(e) => {
                 write!(f, "error from smoltcp UDP send: {e:?}")
             }
+
+            #[cfg(feature = "embassy")]
+            Self::EmbassyUdpSend(e) => {
+                write!(f, "error from Embassy UDP send: {e:?}")
+            }
         }
     }
 }
@@ -133,6 +152,34 @@ mod tests {
         assert_eq!(e, "NotImplemented".to_string());
     }
 
+    #[test]
+    fn display_would_block_error() {
+        let e = super::Error::WouldBlock;
+        let m = format!("{e}");
+        assert_eq!(m, "operation would block".to_string());
+    }
+
+    #[test]
+    fn debug_would_block_error() {
+        let e = super::Error::WouldBlock;
+        let e = format!("{e:?}");
+        assert_eq!(e, "WouldBlock".to_string());
+    }
+
+    #[test]
+    fn display_message_too_long_error() {
+        let e = super::Error::MessageTooLong;
+        let m = format!("{e}");
+        assert_eq!(m, "message too long".to_string());
+    }
+
+    #[test]
+    fn debug_message_too_long_error() {
+        let e = super::Error::MessageTooLong;
+        let e = format!("{e:?}");
+        assert_eq!(e, "MessageTooLong".to_string());
+    }
+
     #[test]
     #[cfg(feature = "std")]
     fn display_syscall_error() {
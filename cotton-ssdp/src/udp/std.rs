@@ -2,7 +2,19 @@ use super::{Error, Syscall};
 use cotton_netif::InterfaceIndex;
 use nix::cmsg_space;
 use nix::sys::socket::setsockopt;
+#[cfg(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "netbsd",
+    target_vendor = "apple"
+))]
 use nix::sys::socket::sockopt::Ipv4PacketInfo;
+#[cfg(any(
+    target_os = "freebsd",
+    target_os = "dragonfly",
+    target_os = "openbsd"
+))]
+use nix::sys::socket::sockopt::Ipv4RecvDstAddr;
 use nix::sys::socket::ControlMessage;
 use nix::sys::socket::ControlMessageOwned;
 use nix::sys::socket::MsgFlags;
@@ -10,6 +22,7 @@ use nix::sys::socket::SockaddrStorage;
 use std::io::IoSlice;
 use std::io::IoSliceMut;
 use std::net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::os::fd::AsFd;
 use std::os::unix::io::AsRawFd;
 use std::os::unix::prelude::RawFd;
 
@@ -54,10 +67,65 @@ pub(crate) fn setup_socket(
         socket2::Socket::set_nonblocking,
         socket2::Socket::set_reuse_address,
         |s, a| s.bind(&socket2::SockAddr::from(a)),
-        |s, b| setsockopt(s, Ipv4PacketInfo, &b),
+        receive_destination_address,
     )
 }
 
+/// Ask for each received datagram's destination address to be reported
+///
+/// This must be enabled on any socket used with
+/// [`super::TargetedReceive`]. Linux, Android, NetBSD and Apple
+/// platforms use `IP_PKTINFO`; FreeBSD, DragonFly and OpenBSD use
+/// `IP_RECVDSTADDR`.
+///
+/// Windows isn't supported: there, the destination address is
+/// reported only via `WSARecvMsg`, which this module doesn't use (it
+/// is built on the Unix-only "nix" crate).
+///
+/// # Errors
+///
+/// Passes on any error from the underlying `setsockopt` call.
+///
+#[cfg(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "netbsd",
+    target_vendor = "apple"
+))]
+pub fn receive_destination_address<F: AsFd>(
+    socket: &F,
+    enable: bool,
+) -> Result<(), nix::errno::Errno> {
+    setsockopt(socket, Ipv4PacketInfo, &enable)
+}
+
+/// Ask for each received datagram's destination address to be reported
+///
+/// This must be enabled on any socket used with
+/// [`super::TargetedReceive`]. Linux, Android, NetBSD and Apple
+/// platforms use `IP_PKTINFO`; FreeBSD, DragonFly and OpenBSD use
+/// `IP_RECVDSTADDR`.
+///
+/// Windows isn't supported: there, the destination address is
+/// reported only via `WSARecvMsg`, which this module doesn't use (it
+/// is built on the Unix-only "nix" crate).
+///
+/// # Errors
+///
+/// Passes on any error from the underlying `setsockopt` call.
+///
+#[cfg(any(
+    target_os = "freebsd",
+    target_os = "dragonfly",
+    target_os = "openbsd"
+))]
+pub fn receive_destination_address<F: AsFd>(
+    socket: &F,
+    enable: bool,
+) -> Result<(), nix::errno::Errno> {
+    setsockopt(socket, Ipv4RecvDstAddr, &enable)
+}
+
 #[allow(clippy::cast_possible_truncation)] // socklen_t
 #[allow(clippy::cast_possible_wrap)] // ifindex
 fn ipv4_multicast_operation(
@@ -94,6 +162,7 @@ fn ipv4_multicast_operation(
             }
             Ok(())
         }
+        // Not yet supported, so `Engine` ignores IPv6 addresses
         IpAddr::V6(_) => Err(std::io::ErrorKind::Unsupported.into()),
     }
 }
@@ -128,34 +197,142 @@ impl<T: AsRawFd> super::Multicast for T {
     }
 }
 
+/// Send, asking for the source address to be `from`
+///
+/// If `from` is unspecified (0.0.0.0), no source address is requested
+/// and the kernel chooses one as usual.
+#[cfg(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "netbsd",
+    target_vendor = "apple"
+))]
+fn sendmsg_from(
+    fd: RawFd,
+    iov: &[IoSlice],
+    dest: &SockaddrStorage,
+    from: &Ipv4Addr,
+) -> nix::Result<usize> {
+    let pi = libc::in_pktinfo {
+        ipi_ifindex: 0,
+        ipi_addr: libc::in_addr { s_addr: 0 },
+        ipi_spec_dst: libc::in_addr {
+            s_addr: u32::to_be((*from).into()),
+        },
+    };
+    let cmsg = [ControlMessage::Ipv4PacketInfo(&pi)];
+    let cmsgs: &[ControlMessage] =
+        if from.is_unspecified() { &[] } else { &cmsg };
+    nix::sys::socket::sendmsg(fd, iov, cmsgs, MsgFlags::empty(), Some(dest))
+}
+
+/// Send, asking for the source address to be `from`
+///
+/// If `from` is unspecified (0.0.0.0), no source address is requested
+/// and the kernel chooses one as usual.
+#[cfg(any(
+    target_os = "freebsd",
+    target_os = "dragonfly",
+    target_os = "openbsd"
+))]
+fn sendmsg_from(
+    fd: RawFd,
+    iov: &[IoSlice],
+    dest: &SockaddrStorage,
+    from: &Ipv4Addr,
+) -> nix::Result<usize> {
+    let addr = libc::in_addr {
+        s_addr: u32::to_be((*from).into()),
+    };
+    let cmsg = [ControlMessage::Ipv4SendSrcAddr(&addr)];
+    let cmsgs: &[ControlMessage] =
+        if from.is_unspecified() { &[] } else { &cmsg };
+    nix::sys::socket::sendmsg(fd, iov, cmsgs, MsgFlags::empty(), Some(dest))
+}
+
+/// Choose the interface that multicast datagrams leave by
+///
+/// Without this, the kernel picks the interface for a multicast
+/// destination by looking up the route to it -- which, on a
+/// multi-homed host, is usually the same interface whatever source
+/// address was requested. Linux happens to choose by source address
+/// if one is given in `IP_PKTINFO`, but the BSDs don't, so it's set
+/// explicitly here (`IP_MULTICAST_IF`, see ip(7) or ip(4)) before
+/// every multicast send. An unspecified address (0.0.0.0) reverts to
+/// the kernel's default choice.
+#[allow(clippy::cast_possible_truncation)] // socklen_t
+fn set_multicast_interface(
+    fd: RawFd,
+    from: &Ipv4Addr,
+) -> Result<(), std::io::Error> {
+    let addr = libc::in_addr {
+        s_addr: u32::to_be((*from).into()),
+    };
+    unsafe {
+        let ret = libc::setsockopt(
+            fd,
+            libc::IPPROTO_IP,
+            libc::IP_MULTICAST_IF,
+            std::ptr::addr_of!(addr).cast::<libc::c_void>(),
+            std::mem::size_of_val(&addr) as libc::socklen_t,
+        );
+        if ret != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// Choose how many hops multicast datagrams may be forwarded
+///
+/// Like the interface, this is set before every multicast send
+/// (`IP_MULTICAST_TTL`), so that one send with a larger TTL doesn't
+/// affect those that follow. The option is a `u_char`, which is what
+/// the BSDs insist on, and Linux also accepts.
+#[allow(clippy::cast_possible_truncation)] // socklen_t
+fn set_multicast_ttl(fd: RawFd, ttl: u8) -> Result<(), std::io::Error> {
+    unsafe {
+        let ret = libc::setsockopt(
+            fd,
+            libc::IPPROTO_IP,
+            libc::IP_MULTICAST_TTL,
+            std::ptr::addr_of!(ttl).cast::<libc::c_void>(),
+            std::mem::size_of_val(&ttl) as libc::socklen_t,
+        );
+        if ret != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
 pub(crate) fn send_from<T: AsRawFd>(
     socket: &T,
     buffer: &[u8],
     to: &SocketAddr,
     from: &IpAddr,
+) -> Result<(), std::io::Error> {
+    send_from_with_ttl(socket, buffer, to, from, 1)
+}
+
+pub(crate) fn send_from_with_ttl<T: AsRawFd>(
+    socket: &T,
+    buffer: &[u8],
+    to: &SocketAddr,
+    from: &IpAddr,
+    ttl: u8,
 ) -> Result<(), std::io::Error> {
     if let IpAddr::V4(from) = from {
+        if to.ip().is_multicast() {
+            set_multicast_interface(socket.as_raw_fd(), from)?;
+            set_multicast_ttl(socket.as_raw_fd(), ttl)?;
+        }
         let iov = [IoSlice::new(buffer)];
-        let pi = libc::in_pktinfo {
-            ipi_ifindex: 0,
-            ipi_addr: libc::in_addr { s_addr: 0 },
-            ipi_spec_dst: libc::in_addr {
-                s_addr: u32::to_be((*from).into()),
-            },
-        };
-
-        let cmsg = ControlMessage::Ipv4PacketInfo(&pi);
         let dest = match to {
             SocketAddr::V4(ipv4) => SockaddrStorage::from(*ipv4),
             SocketAddr::V6(ipv6) => SockaddrStorage::from(*ipv6),
         };
-        let r = nix::sys::socket::sendmsg(
-            socket.as_raw_fd(),
-            &iov,
-            &[cmsg],
-            MsgFlags::empty(),
-            Some(&dest),
-        );
+        let r = sendmsg_from(socket.as_raw_fd(), &iov, &dest, from);
         if let Err(e) = r {
             println!("sendmsg {e:?}");
             return Err(e.into());
@@ -163,14 +340,74 @@ pub(crate) fn send_from<T: AsRawFd>(
         // println!("sendmsg to {:?} OK", to);
         Ok(())
     } else {
-        Err(std::io::Error::new(std::io::ErrorKind::Other, "IPv6 NYI"))
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "IPv6 not supported yet",
+        ))
+    }
+}
+
+/// Extract the local address a datagram arrived on, from its control data
+///
+/// On Linux, `IP_PKTINFO` reports both the header destination address
+/// (`ipi_addr`) and the local address that a reply should come from
+/// (`ipi_spec_dst`), which differ for multicast and broadcast
+/// datagrams. Other platforms only report the header destination, so
+/// in the multicast/broadcast case there's no usable local address,
+/// and `Some(UNSPECIFIED)` is returned -- leaving the `Engine` to
+/// answer from its only address, if it has just the one, or not at all.
+///
+/// Returns `None` if none of the control messages carry an address.
+fn destination_address<I: Iterator<Item = ControlMessageOwned>>(
+    cmsgs: I,
+) -> Option<Ipv4Addr> {
+    for cmsg in cmsgs {
+        let addr = match cmsg {
+            #[cfg(any(
+                target_os = "linux",
+                target_os = "android",
+                target_os = "netbsd",
+                target_vendor = "apple"
+            ))]
+            ControlMessageOwned::Ipv4PacketInfo(pi) => {
+                if pi.ipi_spec_dst.s_addr != 0 {
+                    pi.ipi_spec_dst
+                } else {
+                    pi.ipi_addr
+                }
+            }
+            #[cfg(any(
+                target_os = "freebsd",
+                target_os = "dragonfly",
+                target_os = "openbsd"
+            ))]
+            ControlMessageOwned::Ipv4RecvDstAddr(addr) => addr,
+            _ => continue,
+        };
+        let addr = Ipv4Addr::from(u32::from_be(addr.s_addr));
+        if addr.is_multicast() || addr.is_broadcast() {
+            return Some(Ipv4Addr::UNSPECIFIED);
+        }
+        return Some(addr);
     }
+    None
 }
 
 fn receive_using_recvmsg(
     fd: RawFd,
     buffer: &mut [u8],
 ) -> Result<(usize, IpAddr, Option<SockaddrStorage>), std::io::Error> {
+    #[cfg(any(
+        target_os = "freebsd",
+        target_os = "dragonfly",
+        target_os = "openbsd"
+    ))]
+    let mut cmsgspace = cmsg_space!(libc::in_addr);
+    #[cfg(not(any(
+        target_os = "freebsd",
+        target_os = "dragonfly",
+        target_os = "openbsd"
+    )))]
     let mut cmsgspace = cmsg_space!(libc::in_pktinfo);
     let mut iov = [IoSliceMut::new(buffer)];
     let r = nix::sys::socket::recvmsg::<SockaddrStorage>(
@@ -179,12 +416,10 @@ fn receive_using_recvmsg(
         Some(&mut cmsgspace),
         MsgFlags::empty(),
     )?;
-    let Some(ControlMessageOwned::Ipv4PacketInfo(pi)) = r.cmsgs()?.next()
-    else {
+    let Some(rxon) = destination_address(r.cmsgs()?) else {
         println!("receive: no pktinfo");
         return Err(std::io::ErrorKind::InvalidData.into());
     };
-    let rxon = Ipv4Addr::from(u32::from_be(pi.ipi_spec_dst.s_addr));
     Ok((r.bytes, IpAddr::V4(rxon), r.address))
 }
 
@@ -234,9 +469,7 @@ pub(crate) fn receive_to<T: AsRawFd>(
 #[cfg(test)]
 mod tests {
     use super::*;
-    use nix::sys::socket::setsockopt;
     use nix::sys::socket::sockopt::Ipv4OrigDstAddr;
-    use nix::sys::socket::sockopt::Ipv4PacketInfo;
     use std::net::Ipv6Addr;
     use std::net::SocketAddrV6;
     use std::os::unix::io::FromRawFd;
@@ -369,7 +602,13 @@ mod tests {
 
     fn local_ipv4() -> Option<Ipv4Addr> {
         cotton_netif::get_interfaces().unwrap().find_map(|e| {
-            if let cotton_netif::NetworkEvent::NewAddr(_, IpAddr::V4(a), _) = e
+            if let cotton_netif::NetworkEvent::NewAddr(
+                _,
+                IpAddr::V4(a),
+                _,
+                _,
+                _,
+            ) = e
             {
                 if a == Ipv4Addr::LOCALHOST {
                     None
@@ -390,7 +629,7 @@ mod tests {
         let tx_port = tx.local_addr().unwrap().port();
         let rx = std::net::UdpSocket::bind("0.0.0.0:0").unwrap();
         rx.set_nonblocking(true).unwrap();
-        setsockopt(&rx, Ipv4PacketInfo, &true).unwrap();
+        receive_destination_address(&rx, true).unwrap();
         let rx_port = rx.local_addr().unwrap().port();
         assert!(send_from(
             &tx,
@@ -416,7 +655,7 @@ mod tests {
         let tx_port = tx.local_addr().unwrap().port();
         let rx = std::net::UdpSocket::bind("0.0.0.0:0").unwrap();
         rx.set_nonblocking(true).unwrap();
-        setsockopt(&rx, Ipv4PacketInfo, &true).unwrap();
+        receive_destination_address(&rx, true).unwrap();
         let rx_port = rx.local_addr().unwrap().port();
         assert!(send_from(
             &tx,
@@ -442,7 +681,7 @@ mod tests {
         let tx_port = tx.local_addr().unwrap().port();
         let rx = std::net::UdpSocket::bind("0.0.0.0:0").unwrap();
         rx.set_nonblocking(true).unwrap();
-        setsockopt(&rx, Ipv4PacketInfo, &true).unwrap();
+        receive_destination_address(&rx, true).unwrap();
         let rx_port = rx.local_addr().unwrap().port();
         assert!(send_from(
             &tx,
@@ -468,7 +707,7 @@ mod tests {
         let tx_port = tx.local_addr().unwrap().port();
         let rx = std::net::UdpSocket::bind("0.0.0.0:0").unwrap();
         rx.set_nonblocking(true).unwrap();
-        setsockopt(&rx, Ipv4PacketInfo, &true).unwrap();
+        receive_destination_address(&rx, true).unwrap();
         let rx_port = rx.local_addr().unwrap().port();
         assert!(send_from(
             &tx,
@@ -485,6 +724,32 @@ mod tests {
         assert!(wasfrom == SocketAddr::new(ipv4, tx_port));
     }
 
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn multicast_from_foreign_address_fails() {
+        // Not an address of any local interface, so IP_MULTICAST_IF
+        // can't be set
+        let tx = std::net::UdpSocket::bind("0.0.0.0:0").unwrap();
+        let e = send_from(
+            &tx,
+            b"foo",
+            &"239.255.255.250:1900".parse().unwrap(),
+            &IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)),
+        )
+        .unwrap_err();
+        assert_eq!(e.raw_os_error(), Some(libc::EADDRNOTAVAIL));
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn multicast_ttl_set() {
+        let tx = std::net::UdpSocket::bind("0.0.0.0:0").unwrap();
+        set_multicast_ttl(tx.as_raw_fd(), 4).unwrap();
+        assert_eq!(tx.multicast_ttl_v4().unwrap(), 4);
+        set_multicast_ttl(tx.as_raw_fd(), 1).unwrap();
+        assert_eq!(tx.multicast_ttl_v4().unwrap(), 1);
+    }
+
     #[test]
     #[cfg_attr(miri, ignore)]
     fn ipv6_source_fails() {
@@ -533,7 +798,7 @@ mod tests {
         let rx = std::net::UdpSocket::bind("0.0.0.0:0").unwrap();
         rx.set_nonblocking(true).unwrap();
         // But! we forget to do the setsockopt:
-        //setsockopt(&rx, Ipv4PacketInfo, &true).unwrap();
+        //receive_destination_address(&rx, true).unwrap();
         let rx_port = rx.local_addr().unwrap().port();
         assert!(send_from(
             &tx,
@@ -560,7 +825,7 @@ mod tests {
         let rx = std::net::UdpSocket::bind("0.0.0.0:0").unwrap();
         rx.set_nonblocking(true).unwrap();
         // But! with both sockopts set our buffer would overflow -- nix errors
-        setsockopt(&rx, Ipv4PacketInfo, &true).unwrap();
+        receive_destination_address(&rx, true).unwrap();
         setsockopt(&rx, Ipv4OrigDstAddr, &true).unwrap();
         let rx_port = rx.local_addr().unwrap().port();
         assert!(send_from(
@@ -583,7 +848,7 @@ mod tests {
         //let tx_port = tx.local_addr().unwrap().port();
         let rx = std::net::UdpSocket::bind("::0:0").unwrap();
         rx.set_nonblocking(true).unwrap();
-        //setsockopt(&rx, Ipv4PacketInfo, &true).unwrap();
+        //receive_destination_address(&rx, true).unwrap();
         let rx_port = rx.local_addr().unwrap().port();
         tx.send_to(b"foo", SocketAddr::new(localhost, rx_port))
             .unwrap();
@@ -642,4 +907,159 @@ mod tests {
                 .is_err()
         );
     }
+
+    fn in_addr(a: Ipv4Addr) -> libc::in_addr {
+        libc::in_addr {
+            s_addr: u32::to_be(a.into()),
+        }
+    }
+
+    #[cfg(any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "netbsd",
+        target_vendor = "apple"
+    ))]
+    fn pktinfo(spec_dst: Ipv4Addr, addr: Ipv4Addr) -> ControlMessageOwned {
+        ControlMessageOwned::Ipv4PacketInfo(libc::in_pktinfo {
+            ipi_ifindex: 2,
+            ipi_spec_dst: in_addr(spec_dst),
+            ipi_addr: in_addr(addr),
+        })
+    }
+
+    #[test]
+    #[cfg(any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "netbsd",
+        target_vendor = "apple"
+    ))]
+    fn pktinfo_prefers_spec_dst() {
+        let cmsgs = vec![pktinfo(
+            Ipv4Addr::new(192, 168, 1, 3),
+            Ipv4Addr::new(239, 255, 255, 250),
+        )];
+        assert_eq!(
+            destination_address(cmsgs.into_iter()),
+            Some(Ipv4Addr::new(192, 168, 1, 3))
+        );
+    }
+
+    #[test]
+    #[cfg(any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "netbsd",
+        target_vendor = "apple"
+    ))]
+    fn pktinfo_without_spec_dst_uses_header_address() {
+        // As seen on macOS
+        let cmsgs = vec![pktinfo(
+            Ipv4Addr::UNSPECIFIED,
+            Ipv4Addr::new(192, 168, 1, 3),
+        )];
+        assert_eq!(
+            destination_address(cmsgs.into_iter()),
+            Some(Ipv4Addr::new(192, 168, 1, 3))
+        );
+    }
+
+    #[test]
+    #[cfg(any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "netbsd",
+        target_vendor = "apple"
+    ))]
+    fn pktinfo_multicast_only_is_unspecified() {
+        let cmsgs = vec![pktinfo(
+            Ipv4Addr::UNSPECIFIED,
+            Ipv4Addr::new(239, 255, 255, 250),
+        )];
+        assert_eq!(
+            destination_address(cmsgs.into_iter()),
+            Some(Ipv4Addr::UNSPECIFIED)
+        );
+    }
+
+    #[test]
+    #[cfg(any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "netbsd",
+        target_vendor = "apple"
+    ))]
+    fn unrelated_cmsgs_skipped() {
+        let cmsgs = vec![
+            ControlMessageOwned::ScmRights(Vec::new()),
+            pktinfo(Ipv4Addr::new(10, 0, 0, 1), Ipv4Addr::new(10, 0, 0, 1)),
+        ];
+        assert_eq!(
+            destination_address(cmsgs.into_iter()),
+            Some(Ipv4Addr::new(10, 0, 0, 1))
+        );
+    }
+
+    #[test]
+    #[cfg(any(
+        target_os = "freebsd",
+        target_os = "dragonfly",
+        target_os = "openbsd"
+    ))]
+    fn recvdstaddr_parsed() {
+        let cmsgs = vec![ControlMessageOwned::Ipv4RecvDstAddr(in_addr(
+            Ipv4Addr::new(192, 168, 1, 3),
+        ))];
+        assert_eq!(
+            destination_address(cmsgs.into_iter()),
+            Some(Ipv4Addr::new(192, 168, 1, 3))
+        );
+    }
+
+    #[test]
+    #[cfg(any(
+        target_os = "freebsd",
+        target_os = "dragonfly",
+        target_os = "openbsd"
+    ))]
+    fn recvdstaddr_broadcast_is_unspecified() {
+        let cmsgs = vec![ControlMessageOwned::Ipv4RecvDstAddr(in_addr(
+            Ipv4Addr::BROADCAST,
+        ))];
+        assert_eq!(
+            destination_address(cmsgs.into_iter()),
+            Some(Ipv4Addr::UNSPECIFIED)
+        );
+    }
+
+    #[test]
+    fn no_destination_cmsg() {
+        let cmsgs = vec![ControlMessageOwned::ScmRights(Vec::new())];
+        assert_eq!(destination_address(cmsgs.into_iter()), None);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn unspecified_source_lets_kernel_choose() {
+        let localhost = IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1));
+        let tx = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let tx_port = tx.local_addr().unwrap().port();
+        let rx = std::net::UdpSocket::bind("0.0.0.0:0").unwrap();
+        rx.set_nonblocking(true).unwrap();
+        receive_destination_address(&rx, true).unwrap();
+        let rx_port = rx.local_addr().unwrap().port();
+        assert!(send_from(
+            &tx,
+            b"foo",
+            &SocketAddr::new(localhost, rx_port),
+            &IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        )
+        .is_ok());
+        let mut buf = [0u8; 1500];
+        let (n, wasto, wasfrom) = receive_to(&rx, &mut buf).unwrap();
+        assert!(n == 3);
+        assert!(wasto == localhost);
+        assert!(wasfrom == SocketAddr::new(localhost, tx_port));
+    }
 }
@@ -19,6 +19,31 @@ impl super::TargetedSend for tokio::net::UdpSocket {
         })
         .map_err(|e| Error::Syscall(Syscall::Sendmsg, e))
     }
+
+    fn send_with_ttl<F>(
+        &self,
+        size: usize,
+        to: &SocketAddr,
+        from: &IpAddr,
+        ttl: u8,
+        f: F,
+    ) -> Result<(), Error>
+    where
+        F: FnOnce(&mut [u8]) -> usize,
+    {
+        let mut buffer = vec![0u8; size];
+        let actual_size = f(&mut buffer);
+        self.try_io(tokio::io::Interest::WRITABLE, || {
+            super::std::send_from_with_ttl(
+                self,
+                &buffer[0..actual_size],
+                to,
+                from,
+                ttl,
+            )
+        })
+        .map_err(|e| Error::Syscall(Syscall::Sendmsg, e))
+    }
 }
 
 impl super::TargetedReceive for tokio::net::UdpSocket {
@@ -35,11 +60,10 @@ impl super::TargetedReceive for tokio::net::UdpSocket {
 
 #[cfg(test)]
 mod tests {
+    use super::super::std::receive_destination_address;
     use super::super::{Multicast, TargetedReceive, TargetedSend};
     use super::*;
     use cotton_netif::InterfaceIndex;
-    use nix::sys::socket::setsockopt;
-    use nix::sys::socket::sockopt::Ipv4PacketInfo;
     use std::net::Ipv4Addr;
 
     fn make_index(i: u32) -> InterfaceIndex {
@@ -55,7 +79,7 @@ mod tests {
         let tx_port = tx.local_addr().unwrap().port();
         let rx = std::net::UdpSocket::bind("0.0.0.0:0").unwrap();
         rx.set_nonblocking(true).unwrap();
-        setsockopt(&rx, Ipv4PacketInfo, &true).unwrap();
+        receive_destination_address(&rx, true).unwrap();
         let rx_port = rx.local_addr().unwrap().port();
 
         tokio::runtime::Builder::new_current_thread()
@@ -0,0 +1,409 @@
+#[cfg(not(feature = "std"))]
+use alloc::{format, string::String, vec::Vec};
+
+use crate::nt;
+use crate::Advertisement;
+
+/// Form the unique service name (USN) for a resource of a device
+///
+/// Follows UPnP DA 1.1 s1.1.2: the USN for the notification type
+/// "uuid:X" is just "uuid:X"; for any other type -- such as
+/// "upnp:rootdevice", or a device or service type URN -- it's
+/// "uuid:X::" followed by the type. The `uuid` may be given with or
+/// without its "uuid:" prefix.
+///
+/// ```rust
+/// # use cotton_ssdp::usn;
+/// assert_eq!(
+///     usn::format("2fac1234-31f8-11b4-a222-08002b34c003", "upnp:rootdevice"),
+///     "uuid:2fac1234-31f8-11b4-a222-08002b34c003::upnp:rootdevice"
+/// );
+/// ```
+#[must_use]
+pub fn format(uuid: &str, notification_type: &str) -> String {
+    let uuid = uuid.strip_prefix("uuid:").unwrap_or(uuid);
+    if notification_type.strip_prefix("uuid:") == Some(uuid) {
+        format!("uuid:{uuid}")
+    } else {
+        format!("uuid:{uuid}::{notification_type}")
+    }
+}
+
+/// Split a unique service name (USN) into its device UUID and type
+///
+/// The inverse of [`format()`]: returns the UUID (without its "uuid:"
+/// prefix), and the notification type if there is one. Returns
+/// `None` if the USN doesn't start with "uuid:", as happens with
+/// non-UPnP users of SSDP.
+#[must_use]
+pub fn parse(usn: &str) -> Option<(&str, Option<&str>)> {
+    let rest = usn.strip_prefix("uuid:")?;
+    Some(match rest.split_once("::") {
+        Some((uuid, notification_type)) => (uuid, Some(notification_type)),
+        None => (rest, None),
+    })
+}
+
+/// Whether a unique service name (USN) is in the form UPnP requires
+///
+/// That is, whether it's exactly what [`format()`] would produce for
+/// `notification_type`, with a UUID in the 8-4-4-4-12 hex-digit form
+/// of RFC 4122. The UPnP Certification Test Tool checks this; in
+/// practice control points don't care, and plenty of devices use
+/// UUIDs that aren't really UUIDs.
+///
+/// ```rust
+/// # use cotton_ssdp::usn;
+/// assert!(usn::is_compliant(
+///     "uuid:2fac1234-31f8-11b4-a222-08002b34c003::upnp:rootdevice",
+///     "upnp:rootdevice"
+/// ));
+/// assert!(!usn::is_compliant("uuid:37::upnp:rootdevice", "upnp:rootdevice"));
+/// ```
+#[must_use]
+pub fn is_compliant(usn: &str, notification_type: &str) -> bool {
+    let Some((uuid, _)) = parse(usn) else {
+        return false;
+    };
+    is_uuid(uuid) && usn == format(uuid, notification_type)
+}
+
+fn is_uuid(uuid: &str) -> bool {
+    let mut groups = uuid.split('-');
+    [8, 4, 4, 4, 12].iter().all(|len| {
+        groups.next().is_some_and(|g| {
+            g.len() == *len && g.bytes().all(|b| b.is_ascii_hexdigit())
+        })
+    }) && groups.next().is_none()
+}
+
+/// An embedded device, advertised as part of a [`DeviceAdvertisement`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EmbeddedDevice {
+    /// The device's UUID (with or without "uuid:" prefix)
+    pub uuid: String,
+
+    /// Device type, e.g. "urn:schemas-upnp-org:device:MediaServer:1"
+    pub device_type: String,
+
+    /// Service types, e.g. "urn:schemas-upnp-org:service:ContentDirectory:1"
+    pub services: Vec<String>,
+}
+
+/// A whole UPnP root device, to be advertised as a group
+///
+/// UPnP devices announce themselves with several SSDP notifications
+/// each, all with the same location (the URL of the root device's
+/// description document), but each with a different notification
+/// type and unique service name (UPnP DA 1.1 s1.1.2):
+///
+///  - "upnp:rootdevice", with USN "uuid:X::upnp:rootdevice";
+///  - "uuid:X", with USN "uuid:X";
+///  - the device type, with USN "uuid:X::" followed by the type;
+///
+/// then two for each embedded device (all but the first of the
+/// above, with the embedded device's own UUID) and one for each
+/// distinct service type of each device. Passing a
+/// `DeviceAdvertisement` to
+/// [`Service::advertise_device`](crate::Service::advertise_device) (or
+/// the equivalent on [`AsyncService`](crate::AsyncService) or
+/// [`Engine`](crate::engine::Engine)) sends all of those, and
+/// responds to searches for any of them with the right USN.
+///
+/// A device with no `device_type` is advertised, and found, only as
+/// "upnp:rootdevice" and by its UUID; that suits simple devices which
+/// don't follow the rest of UPnP.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DeviceAdvertisement {
+    /// The root device's UUID (with or without "uuid:" prefix)
+    pub uuid: String,
+
+    /// Device type, e.g. "urn:schemas-upnp-org:device:MediaServer:1"
+    pub device_type: Option<String>,
+
+    /// Service types of the root device
+    pub services: Vec<String>,
+
+    /// Embedded devices, at any depth
+    pub embedded_devices: Vec<EmbeddedDevice>,
+
+    /// URL of the root device's description document
+    pub location: String,
+
+    /// URL of the description document over IPv6, if different
+    ///
+    /// See [`Advertisement::location_v6`].
+    pub location_v6: Option<String>,
+
+    /// HTTPS URL of the description document, if it's served that way
+    ///
+    /// See [`Advertisement::secure_location`].
+    pub secure_location: Option<String>,
+}
+
+impl DeviceAdvertisement {
+    /// The individual advertisements for this device, with their USNs
+    ///
+    /// In the order listed in UPnP DA 1.1 s1.1.2, and with duplicate
+    /// service types (on the same device) removed.
+    #[must_use]
+    pub fn advertisements(&self) -> Vec<(String, Advertisement)> {
+        let mut result = Vec::new();
+        let mut push = |uuid: &str, notification_type: &str| {
+            let usn = format(uuid, notification_type);
+            if !result.iter().any(|(u, _)| *u == usn) {
+                result.push((
+                    usn,
+                    Advertisement {
+                        notification_type: String::from(notification_type)
+                            .into(),
+                        location: self.location.clone().into(),
+                        location_v6: self.location_v6.clone().map(Into::into),
+                        secure_location: self
+                            .secure_location
+                            .clone()
+                            .map(Into::into),
+                    },
+                ));
+            }
+        };
+
+        let root_uuid = self.uuid.strip_prefix("uuid:").unwrap_or(&self.uuid);
+        push(root_uuid, nt::ROOT_DEVICE.as_str());
+        push(root_uuid, &format!("uuid:{root_uuid}"));
+        if let Some(device_type) = &self.device_type {
+            push(root_uuid, device_type);
+        }
+        for device in &self.embedded_devices {
+            let uuid =
+                device.uuid.strip_prefix("uuid:").unwrap_or(&device.uuid);
+            push(uuid, &format!("uuid:{uuid}"));
+            push(uuid, &device.device_type);
+        }
+        for service in &self.services {
+            push(root_uuid, service);
+        }
+        for device in &self.embedded_devices {
+            for service in &device.services {
+                push(&device.uuid, service);
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::format;
+    use alloc::string::ToString;
+    use alloc::vec;
+
+    const UUID: &str = "2fac1234-31f8-11b4-a222-08002b34c003";
+
+    #[test]
+    fn formats_da_examples() {
+        // The USN forms in UPnP DA 1.1 s1.1.2
+        assert_eq!(
+            format(UUID, "upnp:rootdevice"),
+            "uuid:2fac1234-31f8-11b4-a222-08002b34c003::upnp:rootdevice"
+        );
+        assert_eq!(
+            format(UUID, "uuid:2fac1234-31f8-11b4-a222-08002b34c003"),
+            "uuid:2fac1234-31f8-11b4-a222-08002b34c003"
+        );
+        assert_eq!(
+            format(UUID, "urn:schemas-upnp-org:device:MediaServer:1"),
+            "uuid:2fac1234-31f8-11b4-a222-08002b34c003::urn:schemas-upnp-org:device:MediaServer:1"
+        );
+        assert_eq!(
+            format(UUID, "urn:schemas-upnp-org:service:ContentDirectory:1"),
+            "uuid:2fac1234-31f8-11b4-a222-08002b34c003::urn:schemas-upnp-org:service:ContentDirectory:1"
+        );
+        assert_eq!(
+            format(UUID, "urn:domain-name:device:deviceType:3"),
+            "uuid:2fac1234-31f8-11b4-a222-08002b34c003::urn:domain-name:device:deviceType:3"
+        );
+    }
+
+    #[test]
+    fn format_accepts_prefixed_uuid() {
+        assert_eq!(
+            format("uuid:37", "upnp:rootdevice"),
+            "uuid:37::upnp:rootdevice"
+        );
+        assert_eq!(format("uuid:37", "uuid:37"), "uuid:37");
+    }
+
+    #[test]
+    fn other_uuid_is_a_type() {
+        assert_eq!(format("37", "uuid:38"), "uuid:37::uuid:38");
+    }
+
+    #[test]
+    fn parses() {
+        assert_eq!(parse("uuid:37"), Some(("37", None)));
+        assert_eq!(
+            parse("uuid:37::upnp:rootdevice"),
+            Some(("37", Some("upnp:rootdevice")))
+        );
+        assert_eq!(
+            parse("uuid:37::urn:schemas-upnp-org:device:MediaServer:1"),
+            Some(("37", Some("urn:schemas-upnp-org:device:MediaServer:1")))
+        );
+        assert_eq!(parse("prod37"), None);
+    }
+
+    #[test]
+    fn parse_inverts_format() {
+        for nt in ["upnp:rootdevice", "urn:schemas-upnp-org:device:Foo:2"] {
+            assert_eq!(parse(&format(UUID, nt)), Some((UUID, Some(nt))));
+        }
+        assert_eq!(
+            parse(&format(UUID, &format!("uuid:{UUID}"))),
+            Some((UUID, None))
+        );
+    }
+
+    #[test]
+    fn compliance() {
+        assert!(is_compliant(
+            &format(UUID, "upnp:rootdevice"),
+            "upnp:rootdevice"
+        ));
+        assert!(is_compliant(
+            &format!("uuid:{UUID}"),
+            &format!("uuid:{UUID}")
+        ));
+        assert!(is_compliant(
+            &format(UUID, "urn:schemas-upnp-org:device:MediaServer:1"),
+            "urn:schemas-upnp-org:device:MediaServer:1"
+        ));
+        assert!(is_compliant(
+            "uuid:2FAC1234-31F8-11B4-A222-08002B34C003",
+            "uuid:2FAC1234-31F8-11B4-A222-08002B34C003"
+        ));
+
+        // Wrong type, or no type
+        assert!(!is_compliant(&format(UUID, "upnp:rootdevice"), "ssdp:all"));
+        assert!(!is_compliant(&format!("uuid:{UUID}"), "upnp:rootdevice"));
+        // Not a UUID
+        assert!(!is_compliant("uuid:37::upnp:rootdevice", "upnp:rootdevice"));
+        assert!(!is_compliant(
+            "uuid:2fac1234-31f8-11b4-a222-08002b34c00g::upnp:rootdevice",
+            "upnp:rootdevice"
+        ));
+        assert!(!is_compliant(
+            "uuid:2fac1234-31f8-11b4-a222-08002b34c003-1::upnp:rootdevice",
+            "upnp:rootdevice"
+        ));
+        assert!(!is_compliant(
+            "uuid:2fac123431f811b4a22208002b34c003::upnp:rootdevice",
+            "upnp:rootdevice"
+        ));
+        // Not a UPnP USN at all
+        assert!(!is_compliant("prod37", "upnp:rootdevice"));
+    }
+
+    fn usns(d: &DeviceAdvertisement) -> Vec<(String, String)> {
+        d.advertisements()
+            .into_iter()
+            .map(|(usn, a)| {
+                assert_eq!(a.location, d.location);
+                assert_eq!(a.location_v6.as_deref(), d.location_v6.as_deref());
+                assert_eq!(
+                    a.secure_location.as_deref(),
+                    d.secure_location.as_deref()
+                );
+                (a.notification_type.into_owned(), usn)
+            })
+            .collect()
+    }
+
+    fn pairs(v: &[(&str, &str)]) -> Vec<(String, String)> {
+        v.iter()
+            .map(|(a, b)| (a.to_string(), b.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn root_device_only() {
+        let d = DeviceAdvertisement {
+            uuid: "uuid:37".to_string(),
+            location: "http://me/".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            usns(&d),
+            pairs(&[
+                ("upnp:rootdevice", "uuid:37::upnp:rootdevice"),
+                ("uuid:37", "uuid:37"),
+            ])
+        );
+    }
+
+    #[test]
+    fn device_v6_location() {
+        let d = DeviceAdvertisement {
+            uuid: "uuid:37".to_string(),
+            location: "http://me/".to_string(),
+            location_v6: Some("http://me:8086/".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(usns(&d).len(), 2);
+    }
+
+    #[test]
+    fn whole_device() {
+        // A media server with an embedded device, as in UPnP DA 1.1
+        // s1.1.2: 3+2d+k messages, where d is the number of embedded
+        // devices and k the number of distinct service types
+        let d = DeviceAdvertisement {
+            uuid: "37".to_string(),
+            device_type: Some(
+                "urn:schemas-upnp-org:device:MediaServer:1".to_string(),
+            ),
+            services: vec![
+                "urn:schemas-upnp-org:service:ContentDirectory:1".to_string(),
+                "urn:schemas-upnp-org:service:ConnectionManager:1".to_string(),
+                "urn:schemas-upnp-org:service:ContentDirectory:1".to_string(),
+            ],
+            embedded_devices: vec![EmbeddedDevice {
+                uuid: "uuid:38".to_string(),
+                device_type: "urn:example-com:device:Widget:2".to_string(),
+                services: vec!["urn:example-com:service:Frob:1".to_string()],
+            }],
+            location: "http://me/".to_string(),
+            secure_location: Some("https://me/".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            usns(&d),
+            pairs(&[
+                ("upnp:rootdevice", "uuid:37::upnp:rootdevice"),
+                ("uuid:37", "uuid:37"),
+                (
+                    "urn:schemas-upnp-org:device:MediaServer:1",
+                    "uuid:37::urn:schemas-upnp-org:device:MediaServer:1"
+                ),
+                ("uuid:38", "uuid:38"),
+                (
+                    "urn:example-com:device:Widget:2",
+                    "uuid:38::urn:example-com:device:Widget:2"
+                ),
+                (
+                    "urn:schemas-upnp-org:service:ContentDirectory:1",
+                    "uuid:37::urn:schemas-upnp-org:service:ContentDirectory:1"
+                ),
+                (
+                    "urn:schemas-upnp-org:service:ConnectionManager:1",
+                    "uuid:37::urn:schemas-upnp-org:service:ConnectionManager:1"
+                ),
+                (
+                    "urn:example-com:service:Frob:1",
+                    "uuid:38::urn:example-com:service:Frob:1"
+                ),
+            ])
+        );
+    }
+}
@@ -0,0 +1,455 @@
+use crate::Notification;
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+/// Whether a resource's LOCATION URL answered an HTTP request
+///
+/// Obtained from [`check_location`], or alongside each notification
+/// from [`AsyncService::subscribe_validated`](crate::AsyncService::subscribe_validated).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reachability {
+    /// The server answered with a success (2xx) status
+    Reachable,
+
+    /// The server answered, but with this (unsuccessful) status code
+    HttpStatus(u16),
+
+    /// There was no usable answer: the connection was refused or
+    /// timed out, or the URL couldn't be used (for instance, because
+    /// the client doesn't support its scheme)
+    Unreachable(io::ErrorKind),
+}
+
+/// An HTTP request method, as used by [`HttpClient`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Method {
+    /// Fetch only the headers
+    Head,
+    /// Fetch the whole resource (for servers which don't implement HEAD)
+    Get,
+}
+
+/// A way of making HTTP requests, for checking LOCATION URLs
+///
+/// [`SimpleHttpClient`] is a minimal implementation with no
+/// dependencies; applications which already use an HTTP client crate
+/// (which might support HTTPS, or proxies) can implement this trait
+/// for it instead.
+pub trait HttpClient {
+    /// Make a request of `url`, returning the HTTP status code
+    ///
+    /// Any response body can be ignored. The whole request should take
+    /// no longer than (roughly) `timeout`.
+    ///
+    /// # Errors
+    ///
+    /// Returns Err if no HTTP response was received.
+    fn request(
+        &self,
+        method: Method,
+        url: &str,
+        timeout: Duration,
+    ) -> io::Result<u16>;
+}
+
+/// A minimal HTTP/1.1 client, using `std::net::TcpStream`
+///
+/// Only "http:" URLs are supported; anything else is reported as
+/// [`io::ErrorKind::Unsupported`]. Only the status line of the response
+/// is read.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SimpleHttpClient;
+
+/// Split an "http:" URL into its authority ("host:port") and path
+pub(crate) fn split_url(url: &str) -> io::Result<(&str, &str)> {
+    let rest = url
+        .get(..7)
+        .filter(|scheme| scheme.eq_ignore_ascii_case("http://"))
+        .map(|_| &url[7..])
+        .ok_or(io::ErrorKind::Unsupported)?;
+    let end = rest.find(['/', '?', '#']).unwrap_or(rest.len());
+    let authority = &rest[..end];
+    let authority = authority
+        .rsplit_once('@')
+        .map_or(authority, |(_userinfo, hostport)| hostport);
+    let path = match &rest[end..] {
+        "" => "/",
+        p if p.starts_with('/') => p,
+        _ => "/", // just a query or fragment
+    };
+    let path = path.split('#').next().unwrap_or(path);
+    if authority.is_empty() {
+        return Err(io::ErrorKind::InvalidInput.into());
+    }
+    Ok((authority, path))
+}
+
+/// Split an authority into host and port (defaulting to 80)
+pub(crate) fn split_authority(authority: &str) -> io::Result<(&str, u16)> {
+    let (host, port) = if let Some(rest) = authority.strip_prefix('[') {
+        // Bracketed IPv6 literal
+        let (host, rest) =
+            rest.split_once(']').ok_or(io::ErrorKind::InvalidInput)?;
+        (host, rest.strip_prefix(':'))
+    } else {
+        match authority.rsplit_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (authority, None),
+        }
+    };
+    let port = match port {
+        None | Some("") => 80,
+        Some(p) => p.parse().map_err(|_| io::ErrorKind::InvalidInput)?,
+    };
+    Ok((host, port))
+}
+
+/// Connect to an authority ("host:port"), trying each of its addresses
+///
+/// The stream's read and write timeouts are set to `timeout`.
+pub(crate) fn connect(
+    authority: &str,
+    timeout: Duration,
+) -> io::Result<TcpStream> {
+    let (host, port) = split_authority(authority)?;
+    let mut last_error = io::Error::from(io::ErrorKind::NotFound);
+    for addr in (host, port).to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(stream) => {
+                stream.set_read_timeout(Some(timeout))?;
+                stream.set_write_timeout(Some(timeout))?;
+                return Ok(stream);
+            }
+            Err(e) => last_error = e,
+        }
+    }
+    Err(last_error)
+}
+
+/// Parse the status code from an HTTP status line
+pub(crate) fn parse_status(line: &[u8]) -> io::Result<u16> {
+    let line =
+        core::str::from_utf8(line).map_err(|_| io::ErrorKind::InvalidData)?;
+    let mut words = line.split_ascii_whitespace();
+    match (words.next(), words.next()) {
+        (Some(version), Some(code))
+            if version.starts_with("HTTP/") && code.len() == 3 =>
+        {
+            code.parse().map_err(|_| io::ErrorKind::InvalidData.into())
+        }
+        _ => Err(io::ErrorKind::InvalidData.into()),
+    }
+}
+
+impl HttpClient for SimpleHttpClient {
+    fn request(
+        &self,
+        method: Method,
+        url: &str,
+        timeout: Duration,
+    ) -> io::Result<u16> {
+        let (authority, path) = split_url(url)?;
+        let mut stream = connect(authority, timeout)?;
+
+        let method = match method {
+            Method::Head => "HEAD",
+            Method::Get => "GET",
+        };
+        write!(
+            stream,
+            "{method} {path} HTTP/1.1\r\nHost: {authority}\r\nUser-Agent: cotton-ssdp\r\nConnection: close\r\n\r\n"
+        )?;
+
+        // Read just the status line
+        let mut buf = [0u8; 256];
+        let mut len = 0;
+        while len < buf.len() {
+            let n = stream.read(&mut buf[len..])?;
+            if n == 0 {
+                break;
+            }
+            len += n;
+            if let Some(eol) = buf[..len].iter().position(|b| *b == b'\n') {
+                return parse_status(&buf[..eol]);
+            }
+        }
+        Err(io::ErrorKind::InvalidData.into())
+    }
+}
+
+/// Check whether a LOCATION URL answers an HTTP request
+///
+/// A HEAD request is tried first; if the server says it doesn't
+/// implement HEAD (status 405 or 501), a GET request is tried
+/// instead.
+pub fn check_location<C: HttpClient + ?Sized>(
+    client: &C,
+    location: &str,
+    timeout: Duration,
+) -> Reachability {
+    let result = match client.request(Method::Head, location, timeout) {
+        Ok(405 | 501) => client.request(Method::Get, location, timeout),
+        result => result,
+    };
+    match result {
+        Ok(200..=299) => Reachability::Reachable,
+        Ok(status) => Reachability::HttpStatus(status),
+        Err(e) => Reachability::Unreachable(e.kind()),
+    }
+}
+
+/// Check whether the LOCATION of an incoming notification is reachable
+///
+/// Returns `None` for [`Notification::ByeBye`] and
+/// [`Notification::Expired`], which have no LOCATION.
+///
+/// This blocks for up to (roughly) twice `timeout`, so in a
+/// [`Service`](crate::Service) callback it holds up the handling of
+/// other SSDP traffic meanwhile;
+/// [`AsyncService::subscribe_validated`](crate::AsyncService::subscribe_validated)
+/// avoids that.
+pub fn check<C: HttpClient + ?Sized>(
+    client: &C,
+    notification: &Notification,
+    timeout: Duration,
+) -> Option<Reachability> {
+    match notification {
+        Notification::Alive { location, .. } => {
+            Some(check_location(client, location, timeout))
+        }
+        Notification::ByeBye { .. } | Notification::Expired { .. } => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::net::TcpListener;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// Answers with each of `statuses` in turn, recording the methods
+    #[derive(Default)]
+    struct FakeClient {
+        statuses: RefCell<Vec<io::Result<u16>>>,
+        methods: RefCell<Vec<Method>>,
+    }
+
+    impl FakeClient {
+        fn new(statuses: Vec<io::Result<u16>>) -> Self {
+            Self {
+                statuses: RefCell::new(statuses),
+                methods: RefCell::default(),
+            }
+        }
+    }
+
+    impl HttpClient for FakeClient {
+        fn request(
+            &self,
+            method: Method,
+            url: &str,
+            timeout: Duration,
+        ) -> io::Result<u16> {
+            assert_eq!(url, "http://192.168.1.3:8080/desc.xml");
+            assert_eq!(timeout, TIMEOUT);
+            self.methods.borrow_mut().push(method);
+            self.statuses.borrow_mut().remove(0)
+        }
+    }
+
+    fn check_with(
+        statuses: Vec<io::Result<u16>>,
+    ) -> (Reachability, Vec<Method>) {
+        let client = FakeClient::new(statuses);
+        let r = check_location(
+            &client,
+            "http://192.168.1.3:8080/desc.xml",
+            TIMEOUT,
+        );
+        (r, client.methods.into_inner())
+    }
+
+    #[test]
+    fn head_success_is_reachable() {
+        assert_eq!(
+            check_with(vec![Ok(200)]),
+            (Reachability::Reachable, vec![Method::Head])
+        );
+    }
+
+    #[test]
+    fn head_not_allowed_falls_back_to_get() {
+        assert_eq!(
+            check_with(vec![Ok(405), Ok(204)]),
+            (Reachability::Reachable, vec![Method::Head, Method::Get])
+        );
+        assert_eq!(
+            check_with(vec![Ok(501), Ok(404)]),
+            (
+                Reachability::HttpStatus(404),
+                vec![Method::Head, Method::Get]
+            )
+        );
+    }
+
+    #[test]
+    fn error_status_reported() {
+        assert_eq!(
+            check_with(vec![Ok(404)]),
+            (Reachability::HttpStatus(404), vec![Method::Head])
+        );
+    }
+
+    #[test]
+    fn no_answer_is_unreachable() {
+        assert_eq!(
+            check_with(vec![Err(io::ErrorKind::TimedOut.into())]),
+            (
+                Reachability::Unreachable(io::ErrorKind::TimedOut),
+                vec![Method::Head]
+            )
+        );
+    }
+
+    #[test]
+    fn byebye_not_checked() {
+        let client = FakeClient::default();
+        let n = Notification::ByeBye {
+            notification_type: "upnp:rootdevice".into(),
+            unique_service_name: "uuid:37".into(),
+        };
+        assert_eq!(check(&client, &n, TIMEOUT), None);
+        assert!(client.methods.borrow().is_empty());
+    }
+
+    #[test]
+    fn alive_checked() {
+        let client = FakeClient::new(vec![Ok(200)]);
+        let n = Notification::Alive {
+            notification_type: "upnp:rootdevice".into(),
+            unique_service_name: "uuid:37".into(),
+            location: "http://192.168.1.3:8080/desc.xml".into(),
+            boot_id: None,
+        };
+        assert_eq!(check(&client, &n, TIMEOUT), Some(Reachability::Reachable));
+    }
+
+    #[test]
+    fn urls_split() {
+        assert_eq!(
+            split_url("http://192.168.1.3:8080/desc.xml").unwrap(),
+            ("192.168.1.3:8080", "/desc.xml")
+        );
+        assert_eq!(split_url("HTTP://host").unwrap(), ("host", "/"));
+        assert_eq!(
+            split_url("http://user:pw@host:81/a?b=c#d").unwrap(),
+            ("host:81", "/a?b=c")
+        );
+        assert_eq!(split_url("http://host?x").unwrap(), ("host", "/"));
+        assert_eq!(
+            split_url("http://[fe80::1]:8080/x").unwrap(),
+            ("[fe80::1]:8080", "/x")
+        );
+    }
+
+    #[test]
+    fn unsupported_urls_rejected() {
+        for url in ["https://host/", "ftp://host/", "http://", "http:/x", ""] {
+            assert!(split_url(url).is_err(), "{url}");
+        }
+        assert_eq!(
+            check_location(&SimpleHttpClient, "https://host/", TIMEOUT),
+            Reachability::Unreachable(io::ErrorKind::Unsupported)
+        );
+    }
+
+    #[test]
+    fn authorities_split() {
+        assert_eq!(split_authority("host").unwrap(), ("host", 80));
+        assert_eq!(split_authority("host:").unwrap(), ("host", 80));
+        assert_eq!(split_authority("host:8080").unwrap(), ("host", 8080));
+        assert_eq!(split_authority("[::1]").unwrap(), ("::1", 80));
+        assert_eq!(split_authority("[::1]:81").unwrap(), ("::1", 81));
+        assert!(split_authority("host:http").is_err());
+        assert!(split_authority("[::1").is_err());
+    }
+
+    #[test]
+    fn status_lines_parsed() {
+        assert_eq!(parse_status(b"HTTP/1.1 200 OK\r").unwrap(), 200);
+        assert_eq!(parse_status(b"HTTP/1.0 404 Not Found").unwrap(), 404);
+        assert!(parse_status(b"SSH-2.0-OpenSSH").is_err());
+        assert!(parse_status(b"HTTP/1.1 2000 OK").is_err());
+        assert!(parse_status(b"HTTP/1.1 abc OK").is_err());
+    }
+
+    /// Serve one connection with a canned response, returning the request
+    fn serve_once(
+        response: &'static str,
+    ) -> (String, std::thread::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!(
+            "http://{}/description.xml",
+            listener.local_addr().unwrap()
+        );
+        let server = std::thread::spawn(move || {
+            let (mut socket, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0u8; 512];
+            while !request.ends_with(b"\r\n\r\n") {
+                let n = socket.read(&mut buf).unwrap();
+                assert_ne!(n, 0);
+                request.extend_from_slice(&buf[..n]);
+            }
+            socket.write_all(response.as_bytes()).unwrap();
+            String::from_utf8(request).unwrap()
+        });
+        (url, server)
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn simple_client_sends_head() {
+        let (url, server) =
+            serve_once("HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n");
+        assert_eq!(
+            SimpleHttpClient
+                .request(Method::Head, &url, TIMEOUT)
+                .unwrap(),
+            200
+        );
+        let request = server.join().unwrap();
+        assert!(request.starts_with("HEAD /description.xml HTTP/1.1\r\n"));
+        assert!(request.contains("\r\nHost: 127.0.0.1:"));
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn simple_client_sends_get() {
+        let (url, server) = serve_once("HTTP/1.0 404 Not Found\r\n\r\n");
+        assert_eq!(
+            SimpleHttpClient
+                .request(Method::Get, &url, TIMEOUT)
+                .unwrap(),
+            404
+        );
+        assert!(server.join().unwrap().starts_with("GET /description.xml "));
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn simple_client_refused() {
+        // Find a port with nothing listening on it
+        let url = {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            format!("http://{}/", listener.local_addr().unwrap())
+        };
+        assert_eq!(
+            check_location(&SimpleHttpClient, &url, TIMEOUT),
+            Reachability::Unreachable(io::ErrorKind::ConnectionRefused)
+        );
+    }
+}
@@ -18,18 +18,18 @@ async fn services_communicate() {
 
     ssdp1.advertise(
         "uuid:999",
-        Advertisement {
-            notification_type: "upnp::Directory:3".to_string(),
-            location: "http://127.0.0.1/description.xml".to_string(),
-        },
+        Advertisement::new(
+            "upnp::Directory:3",
+            "http://127.0.0.1/description.xml",
+        ),
     );
 
     ssdp1.advertise(
         "uuid:998",
-        Advertisement {
-            notification_type: "upnp::root_device".to_string(),
-            location: "http://127.0.0.1/description.xml".to_string(),
-        },
+        Advertisement::new(
+            "upnp::root_device",
+            "http://127.0.0.1/description.xml",
+        ),
     );
 
     let mut stage: u32 = 0;
@@ -41,6 +41,7 @@ async fn services_communicate() {
                 ref notification_type,
                 ref unique_service_name,
                 location: _,
+                ..
             } if notification_type == "upnp::Directory:3"
                 && unique_service_name == "uuid:999"
                 && stage == 0 =>
@@ -0,0 +1,184 @@
+//! Checking that multicasts leave by the right interface
+//!
+//! These tests need a host with two multicast-capable interfaces; to
+//! get one reproducibly, each test runs in a thread of its own, in a
+//! new network namespace containing two veth pairs, "a0"/"b0" and
+//! "a1"/"b1". The multicast route points at a0, so that a multicast
+//! which ignores the requested source address leaves by the wrong
+//! interface. Creating the namespace needs CAP_SYS_ADMIN (and the
+//! `ip` tool): where that's not available, the tests are skipped.
+#![cfg(target_os = "linux")]
+
+use cotton_ssdp::udp::TargetedSend;
+use cotton_ssdp::{Advertisement, Service};
+use nix::sys::socket::{setsockopt, sockopt};
+use std::ffi::OsString;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+const A0: Ipv4Addr = Ipv4Addr::new(10, 99, 0, 1);
+const A1: Ipv4Addr = Ipv4Addr::new(10, 99, 1, 1);
+const SSDP_GROUP: Ipv4Addr = Ipv4Addr::new(239, 255, 255, 250);
+
+fn ip(args: &str) -> bool {
+    Command::new("ip")
+        .args(args.split(' '))
+        .status()
+        .is_ok_and(|s| s.success())
+}
+
+/// Run `f` in a new network namespace with two interfaces
+///
+/// Returns without running `f` if the namespace can't be set up.
+fn in_two_interface_netns<F: FnOnce() + Send + 'static>(f: F) {
+    let t = std::thread::spawn(move || {
+        // Only this thread (and its children) see the new namespace
+        if unsafe { libc::unshare(libc::CLONE_NEWNET) } != 0 {
+            eprintln!(
+                "skipping: can't create netns: {}",
+                std::io::Error::last_os_error()
+            );
+            return;
+        }
+        for cmd in [
+            "link set lo up",
+            "link add a0 type veth peer name b0",
+            "link add a1 type veth peer name b1",
+            "addr add 10.99.0.1/24 dev a0",
+            "addr add 10.99.1.1/24 dev a1",
+            "link set a0 up",
+            "link set b0 up",
+            "link set a1 up",
+            "link set b1 up",
+            "route add 224.0.0.0/4 dev a0",
+        ] {
+            if !ip(cmd) {
+                eprintln!("skipping: \"ip {cmd}\" failed");
+                return;
+            }
+        }
+        f();
+    });
+    if let Err(e) = t.join() {
+        std::panic::resume_unwind(e);
+    }
+}
+
+/// A socket receiving multicasts that arrive (or are looped back) on
+/// one interface only
+fn receiver(device: &str, port: u16) -> UdpSocket {
+    let s = socket2::Socket::new(
+        socket2::Domain::IPV4,
+        socket2::Type::DGRAM,
+        None,
+    )
+    .unwrap();
+    s.set_reuse_address(true).unwrap();
+    setsockopt(&s, sockopt::BindToDevice, &OsString::from(device)).unwrap();
+    s.bind(&SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), port).into())
+        .unwrap();
+    let s: UdpSocket = s.into();
+    let index = nix::net::if_::if_nametoindex(device).unwrap();
+    cotton_ssdp::udp::Multicast::join_multicast_group(
+        &s,
+        &IpAddr::V4(SSDP_GROUP),
+        cotton_netif::InterfaceIndex(
+            core::num::NonZeroU32::new(index).unwrap(),
+        ),
+    )
+    .unwrap();
+    s.set_read_timeout(Some(Duration::from_millis(100)))
+        .unwrap();
+    s
+}
+
+/// The sources of all datagrams received so far
+fn sources(s: &UdpSocket) -> Vec<IpAddr> {
+    let mut result = Vec::new();
+    let mut buf = [0u8; 1500];
+    while let Ok((_, from)) = s.recv_from(&mut buf) {
+        result.push(from.ip());
+    }
+    result
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn multicast_leaves_by_source_interface() {
+    in_two_interface_netns(|| {
+        let rx0 = receiver("a0", 5000);
+        let rx1 = receiver("a1", 5000);
+        let tx = UdpSocket::bind("0.0.0.0:0").unwrap();
+        tx.set_nonblocking(true).unwrap();
+        let tx = mio::net::UdpSocket::from_std(tx);
+        let to = SocketAddr::new(SSDP_GROUP.into(), 5000);
+        let send = |from: Ipv4Addr| {
+            tx.send_with(512, &to, &from.into(), |b| {
+                b[0..3].copy_from_slice(b"foo");
+                3
+            })
+            .unwrap();
+        };
+
+        send(A1);
+        assert_eq!(sources(&rx1), [IpAddr::V4(A1)]);
+        assert!(sources(&rx0).is_empty());
+
+        send(A0);
+        assert_eq!(sources(&rx0), [IpAddr::V4(A0)]);
+        assert!(sources(&rx1).is_empty());
+
+        // Unspecified source reverts to following the route
+        send(A1);
+        send(Ipv4Addr::UNSPECIFIED);
+        assert_eq!(sources(&rx1), [IpAddr::V4(A1)]);
+        assert_eq!(sources(&rx0), [IpAddr::V4(A0)]);
+    });
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn service_notifies_on_each_interface() {
+    in_two_interface_netns(|| {
+        let rx0 = receiver("a0", 1900);
+        let rx1 = receiver("a1", 1900);
+        let mut poll = mio::Poll::new().unwrap();
+        let mut ssdp =
+            Service::new(poll.registry(), (mio::Token(1), mio::Token(2)))
+                .unwrap();
+        ssdp.advertise(
+            "uuid:999",
+            Advertisement::new(
+                "upnp::Fnord:3",
+                "http://127.0.0.1/description.xml",
+            ),
+        );
+
+        let mut seen0 = Vec::new();
+        let mut seen1 = Vec::new();
+        let deadline = Instant::now() + Duration::from_secs(10);
+        let mut events = mio::Events::with_capacity(1024);
+        while (seen0.is_empty() || seen1.is_empty())
+            && Instant::now() < deadline
+        {
+            poll.poll(
+                &mut events,
+                Some(ssdp.next_wakeup().min(Duration::from_millis(100))),
+            )
+            .unwrap();
+            ssdp.wakeup();
+            for _ in &events {
+                ssdp.multicast_ready();
+                ssdp.search_ready();
+            }
+            seen0.extend(sources(&rx0));
+            seen1.extend(sources(&rx1));
+        }
+
+        assert!(!seen0.is_empty());
+        assert!(!seen1.is_empty());
+        assert!(seen0.iter().all(|ip| *ip == IpAddr::V4(A0)));
+        assert!(seen1.iter().all(|ip| *ip == IpAddr::V4(A1)));
+    });
+}
@@ -1,3 +1,4 @@
+use cotton_ssdp::engine::EngineConfig;
 use cotton_ssdp::{Advertisement, Notification, Service};
 use serial_test::*;
 use std::cell::RefCell;
@@ -23,10 +24,10 @@ fn services_can_communicate_notify() {
 
     ssdp1.advertise(
         "uuid:999",
-        Advertisement {
-            notification_type: "upnp::Fnord:3".to_string(),
-            location: "http://127.0.0.1/description.xml".to_string(),
-        },
+        Advertisement::new(
+            "upnp::Fnord:3",
+            "http://127.0.0.1/description.xml",
+        ),
     );
 
     let seen = Rc::new(RefCell::new(Vec::new()));
@@ -85,10 +86,10 @@ fn services_can_communicate_search() {
 
     ssdp1.advertise(
         "uuid:999",
-        Advertisement {
-            notification_type: "upnp::Directory:3".to_string(),
-            location: "http://127.0.0.1/description.xml".to_string(),
-        },
+        Advertisement::new(
+            "upnp::Directory:3",
+            "http://127.0.0.1/description.xml",
+        ),
     );
 
     let seen = Rc::new(RefCell::new(Vec::new()));
@@ -145,10 +146,10 @@ fn services_can_deadvertise() {
 
     ssdp1.advertise(
         "uuid:998",
-        Advertisement {
-            notification_type: "upnp::Directory:4".to_string(),
-            location: "http://127.0.0.1/description.xml".to_string(),
-        },
+        Advertisement::new(
+            "upnp::Directory:4",
+            "http://127.0.0.1/description.xml",
+        ),
     );
 
     let seen = Rc::new(RefCell::new(Vec::new()));
@@ -189,3 +190,73 @@ fn services_can_deadvertise() {
         }
     }
 }
+
+#[test]
+#[serial(ssdp)]
+#[cfg_attr(miri, ignore)]
+#[cfg(not(any(target_arch = "powerpc", target_arch = "powerpc64")))]
+fn services_can_communicate_on_other_port_and_group() {
+    const SSDP_TOKEN1: mio::Token = mio::Token(1);
+    const SSDP_TOKEN2: mio::Token = mio::Token(2);
+    const SSDP_TOKEN3: mio::Token = mio::Token(3);
+    const SSDP_TOKEN4: mio::Token = mio::Token(4);
+    let config = EngineConfig {
+        multicast_group: no_std_net::Ipv4Addr::new(239, 255, 255, 253),
+        port: 19001,
+        ..Default::default()
+    };
+    let mut poll = mio::Poll::new().unwrap();
+    let mut ssdp1 = Service::with_config(
+        poll.registry(),
+        (SSDP_TOKEN1, SSDP_TOKEN2),
+        config.clone(),
+    )
+    .unwrap();
+    let mut ssdp2 = Service::with_config(
+        poll.registry(),
+        (SSDP_TOKEN3, SSDP_TOKEN4),
+        config,
+    )
+    .unwrap();
+
+    ssdp1.advertise(
+        "uuid:997",
+        Advertisement::new(
+            "upnp::Fnord:4",
+            "http://127.0.0.1/description.xml",
+        ),
+    );
+
+    let seen = Rc::new(RefCell::new(Vec::new()));
+    let seen2 = seen.clone();
+
+    ssdp2.subscribe(
+        "upnp::Fnord:4",
+        Box::new(move |r| {
+            seen2.borrow_mut().push(r.clone());
+        }),
+    );
+
+    let mut events = mio::Events::with_capacity(1024);
+    while !seen.borrow().iter().any(|r| {
+        matches!(r,
+                 Notification::Alive { notification_type, unique_service_name, .. } if
+                 notification_type == "upnp::Fnord:4"
+                 && unique_service_name == "uuid:997"
+        )
+    }) {
+        poll.poll(&mut events,
+                  Some(ssdp1.next_wakeup().min(ssdp2.next_wakeup())))
+            .unwrap();
+
+        ssdp1.wakeup();
+        ssdp2.wakeup();
+
+        for _ in &events {
+            ssdp1.multicast_ready();
+            ssdp1.search_ready();
+            ssdp2.multicast_ready();
+            ssdp2.search_ready();
+        }
+    }
+}
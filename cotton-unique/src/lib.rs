@@ -31,6 +31,11 @@
 //! This does not *guarantee* uniqueness, but if the hash function is
 //! doing its job, the odds of a collision involve a factor of 2^-64 --
 //! or in other words are highly unlikely.
+//!
+//! The hash function is part of the contract: an [`Algorithm`], once
+//! released, always derives the same identifiers from the same chip ID
+//! and salt, so that firmware updates don't change a device's MAC
+//! address or UUIDs.
 #![no_std]
 #![warn(missing_docs)]
 #![warn(rustdoc::missing_crate_level_docs)]
@@ -40,9 +45,37 @@ pub mod unique_id {
 
     use core::hash::Hasher;
 
+    /// The hash function used to derive identifiers from a unique ID
+    ///
+    /// Identifiers such as MAC addresses and UUIDs must not change when
+    /// a device's firmware is updated, so each algorithm is frozen
+    /// once it's released: the same unique ID and salt always give the
+    /// same identifier, in every version of this crate. (The tests
+    /// include golden values to make sure of it.) Improvements, if
+    /// any, will appear as new variants, never as changes to existing
+    /// ones; see [`Migration`] for moving a device from one to another.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+    #[non_exhaustive]
+    pub enum Algorithm {
+        /// SipHash-2-4, keyed by the unique ID, over the salt
+        ///
+        /// The algorithm used by [`UniqueId::new`], and the only one
+        /// in cotton-unique 0.1 and 0.2.
+        #[default]
+        SipHash24V1,
+
+        /// SipHash-1-3, keyed by the unique ID, over the salt
+        ///
+        /// Faster than SipHash-2-4, but gives entirely different
+        /// identifiers.
+        SipHash13V1,
+    }
+
     /// An object from which unique identifers can be obtained
+    #[derive(Clone)]
     pub struct UniqueId {
         id: [u64; 2],
+        algorithm: Algorithm,
     }
 
     impl UniqueId {
@@ -50,6 +83,9 @@ pub mod unique_id {
         ///
         /// The `unique_bytes` can be a raw unique chip ID, as they are hashed
         /// and salted before any client code sees them.
+        ///
+        /// Identifiers are derived using [`Algorithm::SipHash24V1`];
+        /// use [`UniqueId::with_algorithm`] to choose another.
         pub fn new(unique_bytes: &[u8; 16]) -> Self {
             Self {
                 id: [
@@ -58,6 +94,42 @@ pub mod unique_id {
                         unique_bytes[8..16].try_into().unwrap(),
                     ),
                 ],
+                algorithm: Algorithm::SipHash24V1,
+            }
+        }
+
+        /// Derive identifiers using a specific algorithm
+        ///
+        /// For instance,
+        /// `UniqueId::new(&bytes).with_algorithm(Algorithm::SipHash24V1)`
+        /// makes explicit the choice which [`UniqueId::new`] makes
+        /// implicitly.
+        #[must_use]
+        pub fn with_algorithm(self, algorithm: Algorithm) -> Self {
+            Self { algorithm, ..self }
+        }
+
+        /// The algorithm used to derive identifiers
+        pub fn algorithm(&self) -> Algorithm {
+            self.algorithm
+        }
+
+        fn hash(&self, write: impl Fn(&mut dyn Hasher)) -> u64 {
+            match self.algorithm {
+                Algorithm::SipHash24V1 => {
+                    let mut h = siphasher::sip::SipHasher24::new_with_keys(
+                        self.id[0], self.id[1],
+                    );
+                    write(&mut h);
+                    h.finish()
+                }
+                Algorithm::SipHash13V1 => {
+                    let mut h = siphasher::sip::SipHasher13::new_with_keys(
+                        self.id[0], self.id[1],
+                    );
+                    write(&mut h);
+                    h.finish()
+                }
             }
         }
 
@@ -67,11 +139,7 @@ pub mod unique_id {
         /// identifier is needed; i.e., identifiers for different purposes must
         /// have different salts.
         pub fn id(&self, salt: &[u8]) -> u64 {
-            let mut h = siphasher::sip::SipHasher::new_with_keys(
-                self.id[0], self.id[1],
-            );
-            h.write(salt);
-            h.finish()
+            self.hash(|h| h.write(salt))
         }
 
         /// Return a (statistically) unique identifier for a specific purpose
@@ -80,12 +148,10 @@ pub mod unique_id {
         /// and a u32. This is intended to be helpful when creating identifiers
         /// larger than u64; see the implementation of `uuid()` for an example.
         pub fn id2(&self, salt: &[u8], salt2: u32) -> u64 {
-            let mut h = siphasher::sip::SipHasher::new_with_keys(
-                self.id[0], self.id[1],
-            );
-            h.write(salt);
-            h.write_u32(salt2.to_le());
-            h.finish()
+            self.hash(|h| {
+                h.write(salt);
+                h.write_u32(salt2.to_le());
+            })
         }
     }
 
@@ -116,10 +182,93 @@ pub mod unique_id {
         bytes[8..16].copy_from_slice(&u2);
         uuid::Uuid::new_v8(bytes)
     }
+
+    /// An identifier as derived by both the old and new algorithms
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Transition<T> {
+        /// The identifier under the algorithm being migrated away from
+        pub old: T,
+
+        /// The identifier under the algorithm being migrated to
+        pub new: T,
+    }
+
+    impl<T: PartialEq> Transition<T> {
+        /// Does the identifier differ between the two algorithms?
+        pub fn changed(&self) -> bool {
+            self.old != self.new
+        }
+
+        /// Is `value` this identifier, under either algorithm?
+        ///
+        /// Useful for recognising, for instance, stored references to
+        /// a device's old UUID.
+        pub fn matches(&self, value: &T) -> bool {
+            self.old == *value || self.new == *value
+        }
+    }
+
+    /// Deriving identifiers under two algorithms at once
+    ///
+    /// When a product moves to a new [`Algorithm`], its devices' MAC
+    /// addresses and UUIDs change; a `Migration` computes both the old
+    /// and new versions, so that firmware can (for a while) answer to
+    /// both, or tell peers about the change.
+    #[derive(Clone)]
+    pub struct Migration {
+        from: UniqueId,
+        to: UniqueId,
+    }
+
+    impl Migration {
+        /// Migrate from `unique`'s current algorithm to `to`
+        pub fn new(unique: &UniqueId, to: Algorithm) -> Self {
+            Self {
+                from: unique.clone(),
+                to: unique.clone().with_algorithm(to),
+            }
+        }
+
+        /// The unique ID, using the old algorithm
+        pub fn from(&self) -> &UniqueId {
+            &self.from
+        }
+
+        /// The unique ID, using the new algorithm
+        pub fn to(&self) -> &UniqueId {
+            &self.to
+        }
+
+        /// Both versions of [`UniqueId::id`]
+        pub fn id(&self, salt: &[u8]) -> Transition<u64> {
+            Transition {
+                old: self.from.id(salt),
+                new: self.to.id(salt),
+            }
+        }
+
+        /// Both versions of [`mac_address`]
+        pub fn mac_address(&self, salt: &[u8]) -> Transition<[u8; 6]> {
+            Transition {
+                old: mac_address(&self.from, salt),
+                new: mac_address(&self.to, salt),
+            }
+        }
+
+        /// Both versions of [`uuid()`]
+        pub fn uuid(&self, salt: &[u8]) -> Transition<uuid::Uuid> {
+            Transition {
+                old: uuid(&self.from, salt),
+                new: uuid(&self.to, salt),
+            }
+        }
+    }
 }
 
 #[doc(inline)]
-pub use unique_id::{mac_address, uuid, UniqueId};
+pub use unique_id::{
+    mac_address, uuid, Algorithm, Migration, Transition, UniqueId,
+};
 
 #[cfg(feature = "stm32")]
 /// Obtaining a UniqueId on STM32 platforms
@@ -200,4 +349,81 @@ mod tests {
             alloc::format!("{}", uuid(&unique, b"upnp-media-renderer:0"));
         assert_eq!("2505b7b1-dfa3-8c2d-8f02-9e3409457472", uuid);
     }
+
+    // Golden values: these must never change, or devices in the field
+    // would get new MAC addresses and UUIDs on a firmware update.
+
+    const RAW_ID: [u8; 16] =
+        [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15];
+
+    #[test]
+    fn test_default_algorithm() {
+        let unique = UniqueId::new(&RAW_ID);
+        assert_eq!(unique.algorithm(), Algorithm::SipHash24V1);
+        assert_eq!(Algorithm::default(), Algorithm::SipHash24V1);
+    }
+
+    #[test]
+    fn test_golden_siphash24v1() {
+        let unique =
+            UniqueId::new(&RAW_ID).with_algorithm(Algorithm::SipHash24V1);
+        assert_eq!(3781934145656120962u64, unique.id(b"test-vector"));
+        assert_eq!(8438658973711308931u64, unique.id2(b"test-vector", 37));
+        assert_eq!(
+            [0x16, 0x6C, 0x31, 0x3A, 0x8F, 0x92],
+            mac_address(&unique, b"eth0")
+        );
+        assert_eq!(
+            "acd5408c-70e4-8119-8f18-11a33684634d",
+            alloc::format!("{}", uuid(&unique, b"upnp"))
+        );
+    }
+
+    #[test]
+    fn test_golden_siphash13v1() {
+        let unique =
+            UniqueId::new(&RAW_ID).with_algorithm(Algorithm::SipHash13V1);
+        assert_eq!(5113894989895299592u64, unique.id(b"test-vector"));
+        assert_eq!(6540645878837869009u64, unique.id2(b"test-vector", 37));
+        assert_eq!(
+            [0x8A, 0xD3, 0x3D, 0x1A, 0x58, 0xF8],
+            mac_address(&unique, b"eth0")
+        );
+        assert_eq!(
+            "61d83220-8d2f-8d83-97e1-d5ed63f77b61",
+            alloc::format!("{}", uuid(&unique, b"upnp"))
+        );
+    }
+
+    #[test]
+    fn test_migration() {
+        let unique = UniqueId::new(&RAW_ID);
+        let m = Migration::new(&unique, Algorithm::SipHash13V1);
+        assert_eq!(m.from().algorithm(), Algorithm::SipHash24V1);
+        assert_eq!(m.to().algorithm(), Algorithm::SipHash13V1);
+
+        let mac = m.mac_address(b"eth0");
+        assert_eq!(mac.old, mac_address(&unique, b"eth0"));
+        assert_eq!(mac.new, [0x8A, 0xD3, 0x3D, 0x1A, 0x58, 0xF8]);
+        assert!(mac.changed());
+        assert!(mac.matches(&mac.old));
+        assert!(mac.matches(&mac.new));
+        assert!(!mac.matches(&[0u8; 6]));
+
+        let id = m.id(b"test-vector");
+        assert_eq!(id.old, 3781934145656120962u64);
+        assert_eq!(id.new, 5113894989895299592u64);
+
+        let u = m.uuid(b"upnp");
+        assert_eq!(u.old, uuid(&unique, b"upnp"));
+        assert_ne!(u.old, u.new);
+    }
+
+    #[test]
+    fn test_null_migration() {
+        let unique = UniqueId::new(&RAW_ID);
+        let m = Migration::new(&unique, Algorithm::SipHash24V1);
+        assert!(!m.uuid(b"upnp").changed());
+        assert!(!m.mac_address(b"eth0").changed());
+    }
 }
@@ -0,0 +1,13 @@
+use std::error::Error;
+
+/// Print a mass-storage trace log (as collected by a `TraceLog`, or
+/// by firmware encoding `trace::Record`s) in readable form
+fn main() -> Result<(), Box<dyn Error>> {
+    let Some(path) = std::env::args().nth(1) else {
+        eprintln!("usage: msc-trace-print <log-file>");
+        std::process::exit(1);
+    };
+    let log = std::fs::read(path)?;
+    print!("{}", cotton_usb_host_msc::trace::pretty_print(&log)?);
+    Ok(())
+}
@@ -1,4 +1,5 @@
 #![cfg_attr(not(feature = "std"), no_std)]
 mod debug;
 pub mod mass_storage;
+pub mod trace;
 pub use mass_storage::{IdentifyMassStorage, MassStorage};
@@ -1,4 +1,7 @@
 use super::debug;
+#[cfg(feature = "trace")]
+use super::trace::TraceSink;
+use super::trace::{Direction, Record};
 use cotton_scsi::scsi_transport::DataPhase;
 use cotton_scsi::{Error, ScsiTransport};
 use cotton_usb_host::device::identify::IdentifyFromDescriptors;
@@ -10,17 +13,19 @@ use cotton_usb_host::wire::{
     ConfigurationDescriptor, DescriptorVisitor, InterfaceDescriptor,
 };
 
-pub struct MassStorage<'a, HC: HostController> {
-    bus: &'a UsbBus<HC>,
+pub struct MassStorage<'a, HC: HostController, const D: usize = 512> {
+    bus: &'a UsbBus<HC, D>,
     //device: UsbDevice,
     bulk_in: BulkIn,
     bulk_out: BulkOut,
     tag: u32,
+    #[cfg(feature = "trace")]
+    trace: Option<&'a dyn TraceSink>,
 }
 
-impl<'a, HC: HostController> MassStorage<'a, HC> {
+impl<'a, HC: HostController, const D: usize> MassStorage<'a, HC, D> {
     pub fn new(
-        bus: &'a UsbBus<HC>,
+        bus: &'a UsbBus<HC, D>,
         mut device: UsbDevice,
     ) -> Result<Self, UsbError> {
         let in_ep = device.in_endpoints().iter().next().unwrap_or_default();
@@ -33,8 +38,32 @@ impl<'a, HC: HostController> MassStorage<'a, HC> {
             bulk_in,
             bulk_out,
             tag: 1,
+            #[cfg(feature = "trace")]
+            trace: None,
         })
     }
+
+    /// Report each phase of every subsequent command to `sink`
+    #[cfg(feature = "trace")]
+    #[must_use]
+    pub fn with_trace(mut self, sink: &'a dyn TraceSink) -> Self {
+        self.trace = Some(sink);
+        self
+    }
+
+    #[cfg(feature = "trace")]
+    fn trace(&self, record: impl FnOnce() -> Record) {
+        if let Some(sink) = self.trace {
+            sink.record(&record());
+        }
+    }
+
+    #[cfg(not(feature = "trace"))]
+    fn trace(&self, _record: impl FnOnce() -> Record) {}
+}
+
+fn transferred(r: &Result<usize, UsbError>) -> Result<u32, UsbError> {
+    r.map(|n| n as u32)
 }
 
 #[derive(Default)]
@@ -105,7 +134,9 @@ impl CommandBlockWrapper {
     }
 }
 
-impl<HC: HostController> ScsiTransport for MassStorage<'_, HC> {
+impl<HC: HostController, const D: usize> ScsiTransport
+    for MassStorage<'_, HC, D>
+{
     type Error = UsbError;
 
     async fn command(
@@ -127,22 +158,26 @@ impl<HC: HostController> ScsiTransport for MassStorage<'_, HC> {
             DataPhase::In(_) => 0x80,
             _ => 0,
         };
+        let direction = match data {
+            DataPhase::In(_) => Some(Direction::In),
+            DataPhase::Out(_) => Some(Direction::Out),
+            DataPhase::None => None,
+        };
         let cbw = CommandBlockWrapper::new(self.tag, len as u32, flags, cmd);
         // NB the CommandBlockWrapper struct has no padding as
         // defined, but it's one byte too long (an actual, on-the-wire
         // command block wrapper is 31 bytes). So we only send a
         // partial slice of it.
-        if self
+        let cbw = &bytemuck::bytes_of(&cbw)[0..31];
+        let sent = self
             .bus
-            .bulk_out_transfer(
-                &self.bulk_out,
-                &bytemuck::bytes_of(&cbw)[0..31],
-                TransferType::FixedSize,
-            )
-            .await
-            .map_err(Error::Transport)?
-            < 31
-        {
+            .bulk_out_transfer(&self.bulk_out, cbw, TransferType::FixedSize)
+            .await;
+        self.trace(|| Record::Command {
+            cbw: cbw.try_into().unwrap(),
+            result: transferred(&sent),
+        });
+        if sent.map_err(Error::Transport)? < 31 {
             return Err(Error::ProtocolError);
         }
         //debug::println!("bot {:?}", rc);
@@ -180,6 +215,13 @@ impl<HC: HostController> ScsiTransport for MassStorage<'_, HC> {
             }
             DataPhase::None => Ok(0),
         };
+        if let Some(direction) = direction {
+            self.trace(|| Record::Data {
+                direction,
+                requested: len as u32,
+                result: transferred(&response),
+            });
+        }
         let response = if response == Err(UsbError::Stall) {
             debug::println!("msc bulk stall");
             self.bus
@@ -196,8 +238,12 @@ impl<HC: HostController> ScsiTransport for MassStorage<'_, HC> {
         let sz = self
             .bus
             .bulk_in_transfer(&self.bulk_in, &mut csw, TransferType::FixedSize)
-            .await
-            .map_err(Error::Transport)?;
+            .await;
+        self.trace(|| Record::Status {
+            csw,
+            result: transferred(&sz),
+        });
+        let sz = sz.map_err(Error::Transport)?;
         if sz < 13 {
             debug::println!("Bad CSW {}/13", sz);
             return Err(Error::ProtocolError);
@@ -217,6 +263,13 @@ impl<HC: HostController> ScsiTransport for MassStorage<'_, HC> {
             _ => Err(Error::ProtocolError),
         }
     }
+
+    fn max_transfer_size(&self) -> usize {
+        // Bulk-only transport itself allows transfers up to 4GB, but
+        // host controllers (e.g. RP2040) count bulk transfer lengths
+        // in 16 bits.
+        32768
+    }
 }
 
 #[cfg(all(test, feature = "std"))]
@@ -837,3 +837,146 @@ fn test_dont_identify_mass_storage() {
     cotton_usb_host::wire::parse_descriptors(ELLA, &mut ims);
     assert_eq!(ims.identify(), None);
 }
+
+#[test]
+fn test_max_transfer_size() {
+    do_test(
+        |_| {},
+        |f| {
+            assert!(f.m.max_transfer_size() <= u16::MAX as usize);
+        },
+    );
+}
+
+/// Set up the mock to behave as the device did in a trace log
+///
+/// Data-in phases return only the logged length (the data itself
+/// isn't logged), and status phases return the logged CSW.
+#[cfg(feature = "trace")]
+fn replay(hc: &mut MockHostControllerInner, log: &[u8]) {
+    use crate::trace::{Reader, Record};
+    use std::collections::VecDeque;
+    use std::sync::Mutex;
+
+    let mut outs = VecDeque::new();
+    let mut ins = VecDeque::new();
+    for r in Reader::new(log).unwrap() {
+        match r.unwrap() {
+            Record::Command { result, .. }
+            | Record::Data {
+                direction: Direction::Out,
+                result,
+                ..
+            } => outs.push_back(result),
+            Record::Data { result, .. } => ins.push_back((None, result)),
+            Record::Status { csw, result } => {
+                ins.push_back((Some(csw), result))
+            }
+        }
+    }
+    let outs = Mutex::new(outs);
+    let ins = Mutex::new(ins);
+    hc.expect_bulk_out_transfer()
+        .returning(move |_, _, _, _, _, _| {
+            let r = outs.lock().unwrap().pop_front().unwrap();
+            Box::pin(future::ready(r.map(|n| n as usize)))
+        });
+    hc.expect_bulk_in_transfer()
+        .returning(move |_, _, _, d, _, _| {
+            let (csw, r) = ins.lock().unwrap().pop_front().unwrap();
+            if let (Some(csw), Ok(n)) = (csw, r) {
+                let n = (n as usize).min(d.len());
+                d[..n].copy_from_slice(&csw[..n]);
+            }
+            Box::pin(future::ready(r.map(|n| n as usize)))
+        });
+}
+
+#[cfg(feature = "trace")]
+fn traced_read<SetupFn: FnMut(&mut MockHostControllerInner)>(
+    mut setup: SetupFn,
+) -> (Result<usize, MockError>, Vec<u8>) {
+    let w = Waker::from(Arc::new(NoOpWaker));
+    let mut c = core::task::Context::from_waker(&w);
+    let mut hc = MockHostController::default();
+    setup(&mut hc.inner);
+    let bus = UsbBus::new(hc);
+    // SAFETY: we don't use this with a non-mock bus
+    let device = unsafe { create_test_device(2, 2) };
+    let log = crate::trace::TraceLog::default();
+    let mut m = MassStorage::new(&bus, device).unwrap().with_trace(&log);
+    let mut buf = [0u8; 512];
+    let result = {
+        let fut =
+            pin!(m.command(&[0x28, 0, 0, 0, 0, 0], DataPhase::In(&mut buf)));
+        fut.poll(&mut c).to_option().unwrap()
+    };
+    (result, log.to_bytes())
+}
+
+#[cfg(feature = "trace")]
+#[test]
+fn test_trace_command() {
+    let (result, log) = traced_read(|hc| {
+        hc.expect_bulk_out_transfer()
+            .times(1)
+            .returning(bulk_out_ok::<31>);
+        hc.expect_bulk_in_transfer()
+            .times(2)
+            .returning(bulk_in_ok_with(status_ok));
+    });
+    assert_eq!(result, Ok(512));
+    let text = crate::trace::pretty_print(&log).unwrap();
+    assert_eq!(
+        text,
+        "   0: CBW tag 3 length 512 in lun 0 cb 28 00 00 00 00 00\n   \
+         1: DATA in 512/512\n   \
+         2: CSW tag 0 residue 0 status 0\n"
+    );
+}
+
+#[cfg(feature = "trace")]
+#[test]
+fn test_trace_failures() {
+    let (result, log) = traced_read(|hc| {
+        hc.expect_bulk_out_transfer()
+            .times(1)
+            .returning(bulk_out_ok::<31>);
+        hc.expect_bulk_in_transfer()
+            .times(1)
+            .returning(bulk_in_stalls);
+        hc.expect_bulk_in_transfer()
+            .times(1)
+            .returning(bulk_in_ok_with(|_| 5));
+        hc.expect_control_transfer()
+            .returning(control_transfer_ok::<0>);
+    });
+    assert_eq!(result, Err(Error::ProtocolError));
+    let text = crate::trace::pretty_print(&log).unwrap();
+    assert!(text.contains("DATA in 0/512 (stall)"));
+    assert!(text.contains("CSW tag 0 residue 0 status 0 (short)"));
+}
+
+#[cfg(feature = "trace")]
+#[test]
+fn test_trace_replay() {
+    let (result, log) = traced_read(|hc| {
+        hc.expect_bulk_out_transfer()
+            .times(1)
+            .returning(bulk_out_ok::<31>);
+        hc.expect_bulk_in_transfer()
+            .times(1)
+            .returning(bulk_in_ok_with(|_| 100));
+        hc.expect_bulk_in_transfer()
+            .times(1)
+            .returning(bulk_in_ok_with(|d| {
+                d[12] = 1;
+                d.len()
+            }));
+    });
+    assert_eq!(result, Err(Error::CommandFailed));
+
+    let (result2, log2) = traced_read(|hc| replay(hc, &log));
+    assert_eq!(result2, result);
+    assert_eq!(log2, log);
+}
@@ -0,0 +1,217 @@
+use super::*;
+use std::cell::RefCell;
+
+fn cbw() -> [u8; 31] {
+    let mut cbw = [0u8; 31];
+    cbw[0..4].copy_from_slice(b"USBC");
+    cbw[4..8].copy_from_slice(&3u32.to_le_bytes());
+    cbw[8..12].copy_from_slice(&512u32.to_le_bytes());
+    cbw[12] = 0x80;
+    cbw[14] = 10;
+    cbw[15..25].copy_from_slice(&[0x28, 0, 0, 0, 0, 7, 0, 0, 1, 0]);
+    cbw
+}
+
+fn csw(status: u8) -> [u8; 13] {
+    let mut csw = [0u8; 13];
+    csw[0..4].copy_from_slice(b"USBS");
+    csw[4..8].copy_from_slice(&3u32.to_le_bytes());
+    csw[8..12].copy_from_slice(&12u32.to_le_bytes());
+    csw[12] = status;
+    csw
+}
+
+fn sample() -> [Record; 4] {
+    [
+        Record::Command {
+            cbw: cbw(),
+            result: Ok(31),
+        },
+        Record::Data {
+            direction: Direction::In,
+            requested: 512,
+            result: Ok(500),
+        },
+        Record::Data {
+            direction: Direction::Out,
+            requested: 512,
+            result: Err(UsbError::Stall),
+        },
+        Record::Status {
+            csw: csw(1),
+            result: Ok(13),
+        },
+    ]
+}
+
+fn log_of(records: &[Record]) -> Vec<u8> {
+    let log = TraceLog::default();
+    for r in records {
+        log.record(r);
+    }
+    log.to_bytes()
+}
+
+#[test]
+fn encode_lengths() {
+    let mut buf = [0u8; MAX_RECORD_LEN];
+    let [c, d, _, s] = sample();
+    assert_eq!(c.encode(&mut buf).len(), 38);
+    assert_eq!(buf[0..2], [1, 36]);
+    assert_eq!(d.encode(&mut buf), [2, 10, 1, 0, 2, 0, 0, 0, 0xF4, 1, 0, 0]);
+    assert_eq!(s.encode(&mut buf).len(), 20);
+}
+
+#[test]
+fn round_trip() {
+    let records = sample();
+    let log = log_of(&records);
+    assert_eq!(log[0..8], HEADER);
+    let decoded = Reader::new(&log)
+        .unwrap()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(decoded, records);
+}
+
+#[test]
+fn all_errors_round_trip() {
+    for code in 1..=12 {
+        let e = error_from_code(code).unwrap();
+        assert_eq!(error_code(e), code);
+        let r = Record::Status {
+            csw: [0; 13],
+            result: Err(e),
+        };
+        let log = log_of(&[r]);
+        assert_eq!(Reader::new(&log).unwrap().next(), Some(Ok(r)));
+    }
+    assert_eq!(error_from_code(0), None);
+    assert_eq!(error_from_code(13), None);
+}
+
+#[test]
+fn empty_log() {
+    assert_eq!(Reader::new(&HEADER).unwrap().next(), None);
+}
+
+#[test]
+fn bad_header() {
+    assert!(Reader::new(b"").is_err());
+    assert!(Reader::new(b"CMSC").is_err());
+    assert!(Reader::new(b"CMSC\x02\0\0\0").is_err());
+    assert_eq!(
+        Reader::new(b"PCAP\x01\0\0\0").err(),
+        Some(FormatError::BadHeader)
+    );
+}
+
+#[test]
+fn truncated() {
+    let log = log_of(&sample());
+    for cut in [9, 20, log.len() - 1] {
+        let results = Reader::new(&log[..cut]).unwrap().collect::<Vec<_>>();
+        assert_eq!(results.last(), Some(&Err(FormatError::Truncated)));
+    }
+}
+
+#[test]
+fn unknown_kind_skipped() {
+    let mut log = HEADER.to_vec();
+    log.extend_from_slice(&[99, 3, 1, 2, 3]);
+    log.extend_from_slice(&log_of(&sample()[1..2])[8..]);
+    let decoded = Reader::new(&log).unwrap().collect::<Vec<_>>();
+    assert_eq!(decoded, vec![Ok(sample()[1])]);
+}
+
+#[test]
+fn bad_length() {
+    let mut log = HEADER.to_vec();
+    log.extend_from_slice(&[2, 3, 1, 2, 3]);
+    assert_eq!(
+        Reader::new(&log).unwrap().next(),
+        Some(Err(FormatError::BadLength))
+    );
+}
+
+#[test]
+fn bad_direction() {
+    let mut log = log_of(&sample()[1..2]);
+    log[10] = 3;
+    assert_eq!(
+        Reader::new(&log).unwrap().next(),
+        Some(Err(FormatError::BadValue))
+    );
+}
+
+#[test]
+fn bad_result() {
+    let mut log = log_of(&sample()[1..2]);
+    log[15] = 200;
+    let mut reader = Reader::new(&log).unwrap();
+    assert_eq!(reader.next(), Some(Err(FormatError::BadValue)));
+    assert_eq!(reader.next(), None);
+}
+
+#[test]
+fn display() {
+    let [c, d, o, s] = sample();
+    assert_eq!(
+        format!("{c}"),
+        "CBW tag 3 length 512 in lun 0 cb 28 00 00 00 00 07 00 00 01 00"
+    );
+    assert_eq!(format!("{d}"), "DATA in 500/512");
+    assert_eq!(format!("{o}"), "DATA out 0/512 (stall)");
+    assert_eq!(format!("{s}"), "CSW tag 3 residue 12 status 1");
+}
+
+#[test]
+fn display_short_and_failed() {
+    let c = Record::Command {
+        cbw: cbw(),
+        result: Ok(1),
+    };
+    assert!(format!("{c}").ends_with(" (short)"));
+    let s = Record::Status {
+        csw: [0; 13],
+        result: Err(UsbError::Timeout),
+    };
+    assert_eq!(format!("{s}"), "CSW tag 0 residue 0 status 0 (timeout)");
+}
+
+#[test]
+fn format_error_display() {
+    assert_eq!(
+        format!("{}", FormatError::Truncated),
+        "log truncated".to_string()
+    );
+}
+
+#[test]
+fn pretty() {
+    let text = pretty_print(&log_of(&sample())).unwrap();
+    let lines = text.lines().collect::<Vec<_>>();
+    assert_eq!(lines.len(), 4);
+    assert_eq!(lines[1], "   1: DATA in 500/512");
+    assert_eq!(lines[3], "   3: CSW tag 3 residue 12 status 1");
+}
+
+#[test]
+fn pretty_fails() {
+    assert_eq!(pretty_print(b"nonsense"), Err(FormatError::BadHeader));
+    let log = log_of(&sample());
+    assert_eq!(
+        pretty_print(&log[..log.len() - 1]),
+        Err(FormatError::Truncated)
+    );
+}
+
+#[test]
+fn closure_sink() {
+    let seen = RefCell::new(Vec::new());
+    let sink = |r: &Record| seen.borrow_mut().push(*r);
+    for r in sample() {
+        sink.record(&r);
+    }
+    assert_eq!(seen.into_inner(), sample());
+}
@@ -0,0 +1,437 @@
+//! Recording the Bulk-Only Transport transactions with a device
+//!
+//! Mass-storage devices vary a great deal in how faithfully they
+//! follow the specification, and working out why a particular one
+//! misbehaves usually means knowing exactly which commands it was
+//! sent, how much data moved, and what status it reported. A
+//! [`TraceSink`] attached to a
+//! [`MassStorage`](crate::mass_storage::MassStorage) (with the `trace`
+//! cargo feature) is told about each phase of every command as a
+//! [`Record`].
+//!
+//! Records can be encoded into a compact binary log, which is cheap
+//! enough to collect on a microcontroller, and which can be attached
+//! to a bug report. On the host, [`Reader`] decodes such a log, and
+//! (with the `std` feature) `pretty_print` makes it readable.
+//!
+//! # Log format
+//!
+//! Like a pcap file, a log is a fixed header followed by a sequence of
+//! self-delimiting records. All integers are little-endian.
+//!
+//! The header is the four bytes `CMSC`, a version byte (currently 1),
+//! and three reserved zero bytes: see [`HEADER`].
+//!
+//! Each record is a kind byte, a length byte, and then that many bytes
+//! of payload:
+//!
+//! | Kind | Record | Payload |
+//! |------|--------|---------|
+//! | 1 | [`Record::Command`] | the 31-byte CBW, then a result |
+//! | 2 | [`Record::Data`] | direction (1=in, 2=out), requested length (u32), then a result |
+//! | 3 | [`Record::Status`] | the 13-byte CSW (zero-padded if short), then a result |
+//!
+//! A result is five bytes: a code (0 for success, otherwise which
+//! [`UsbError`]) and then, on success, the number of bytes actually
+//! transferred (u32). Records of unknown kinds are skipped by
+//! [`Reader`], so that later versions can add new ones.
+use core::fmt;
+use cotton_usb_host::host_controller::UsbError;
+
+/// The header at the start of every binary log
+pub const HEADER: [u8; 8] = *b"CMSC\x01\0\0\0";
+
+/// The most bytes that [`Record::encode`] produces
+pub const MAX_RECORD_LEN: usize = 2 + CBW_LEN + RESULT_LEN;
+
+const CBW_LEN: usize = 31;
+const CSW_LEN: usize = 13;
+const RESULT_LEN: usize = 5;
+
+const KIND_COMMAND: u8 = 1;
+const KIND_DATA: u8 = 2;
+const KIND_STATUS: u8 = 3;
+
+/// Which way a data phase went
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Direction {
+    /// From device to host
+    In,
+    /// From host to device
+    Out,
+}
+
+/// One phase of a Bulk-Only Transport command
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum Record {
+    /// A Command Block Wrapper was sent
+    Command {
+        /// The CBW exactly as sent
+        cbw: [u8; 31],
+        /// How many bytes of it were transferred
+        result: Result<u32, UsbError>,
+    },
+
+    /// Data was transferred
+    Data {
+        /// Which way the data went
+        direction: Direction,
+        /// How many bytes the command asked for
+        requested: u32,
+        /// How many bytes actually moved
+        result: Result<u32, UsbError>,
+    },
+
+    /// A Command Status Wrapper was received
+    Status {
+        /// The CSW as received (zero-padded if the device sent fewer
+        /// than 13 bytes)
+        csw: [u8; 13],
+        /// How many bytes of it were received
+        result: Result<u32, UsbError>,
+    },
+}
+
+impl Record {
+    /// Encode this record, in the log format, into `buf`
+    ///
+    /// Returns the encoded bytes (a prefix of `buf`).
+    pub fn encode<'a>(&self, buf: &'a mut [u8; MAX_RECORD_LEN]) -> &'a [u8] {
+        let (kind, result, len) = match self {
+            Record::Command { cbw, result } => {
+                buf[2..2 + CBW_LEN].copy_from_slice(cbw);
+                (KIND_COMMAND, result, CBW_LEN)
+            }
+            Record::Data {
+                direction,
+                requested,
+                result,
+            } => {
+                buf[2] = match direction {
+                    Direction::In => 1,
+                    Direction::Out => 2,
+                };
+                buf[3..7].copy_from_slice(&requested.to_le_bytes());
+                (KIND_DATA, result, 5)
+            }
+            Record::Status { csw, result } => {
+                buf[2..2 + CSW_LEN].copy_from_slice(csw);
+                (KIND_STATUS, result, CSW_LEN)
+            }
+        };
+        let r = &mut buf[2 + len..2 + len + RESULT_LEN];
+        match result {
+            Ok(n) => {
+                r[0] = 0;
+                r[1..].copy_from_slice(&n.to_le_bytes());
+            }
+            Err(e) => {
+                r[0] = error_code(*e);
+                r[1..].fill(0);
+            }
+        }
+        buf[0] = kind;
+        buf[1] = (len + RESULT_LEN) as u8;
+        &buf[..2 + len + RESULT_LEN]
+    }
+
+    /// Decode one record's payload, given its kind
+    ///
+    /// Returns `Ok(None)` for kinds this version doesn't know about.
+    fn decode(kind: u8, payload: &[u8]) -> Result<Option<Self>, FormatError> {
+        let fixed = match kind {
+            KIND_COMMAND => CBW_LEN,
+            KIND_DATA => 5,
+            KIND_STATUS => CSW_LEN,
+            _ => return Ok(None),
+        };
+        if payload.len() != fixed + RESULT_LEN {
+            return Err(FormatError::BadLength);
+        }
+        let result = decode_result(&payload[fixed..])?;
+        Ok(Some(match kind {
+            KIND_COMMAND => Record::Command {
+                cbw: payload[..CBW_LEN].try_into().unwrap(),
+                result,
+            },
+            KIND_DATA => Record::Data {
+                direction: match payload[0] {
+                    1 => Direction::In,
+                    2 => Direction::Out,
+                    _ => return Err(FormatError::BadValue),
+                },
+                requested: u32::from_le_bytes(
+                    payload[1..5].try_into().unwrap(),
+                ),
+                result,
+            },
+            _ => Record::Status {
+                csw: payload[..CSW_LEN].try_into().unwrap(),
+                result,
+            },
+        }))
+    }
+}
+
+fn decode_result(r: &[u8]) -> Result<Result<u32, UsbError>, FormatError> {
+    if r[0] == 0 {
+        Ok(Ok(u32::from_le_bytes(r[1..5].try_into().unwrap())))
+    } else {
+        error_from_code(r[0]).map(Err).ok_or(FormatError::BadValue)
+    }
+}
+
+fn error_code(e: UsbError) -> u8 {
+    match e {
+        UsbError::Stall => 1,
+        UsbError::Timeout => 2,
+        UsbError::Overflow => 3,
+        UsbError::BitStuffError => 4,
+        UsbError::CrcError => 5,
+        UsbError::DataSeqError => 6,
+        UsbError::BufferTooSmall => 7,
+        UsbError::AllPipesInUse => 8,
+        UsbError::ProtocolError => 9,
+        UsbError::TooManyDevices => 10,
+        UsbError::NoSuchEndpoint => 11,
+        UsbError::NoSuchDevice => 12,
+        _ => 255,
+    }
+}
+
+fn error_from_code(code: u8) -> Option<UsbError> {
+    Some(match code {
+        1 => UsbError::Stall,
+        2 => UsbError::Timeout,
+        3 => UsbError::Overflow,
+        4 => UsbError::BitStuffError,
+        5 => UsbError::CrcError,
+        6 => UsbError::DataSeqError,
+        7 => UsbError::BufferTooSmall,
+        8 => UsbError::AllPipesInUse,
+        9 => UsbError::ProtocolError,
+        10 => UsbError::TooManyDevices,
+        11 => UsbError::NoSuchEndpoint,
+        12 => UsbError::NoSuchDevice,
+        _ => return None,
+    })
+}
+
+fn error_name(e: UsbError) -> &'static str {
+    match e {
+        UsbError::Stall => "stall",
+        UsbError::Timeout => "timeout",
+        UsbError::Overflow => "overflow",
+        UsbError::BitStuffError => "bit-stuff error",
+        UsbError::CrcError => "CRC error",
+        UsbError::DataSeqError => "data sequence error",
+        UsbError::BufferTooSmall => "buffer too small",
+        UsbError::AllPipesInUse => "all pipes in use",
+        UsbError::ProtocolError => "protocol error",
+        UsbError::TooManyDevices => "too many devices",
+        UsbError::NoSuchEndpoint => "no such endpoint",
+        UsbError::NoSuchDevice => "no such device",
+        _ => "unknown error",
+    }
+}
+
+fn le32(b: &[u8]) -> u32 {
+    u32::from_le_bytes(b[0..4].try_into().unwrap())
+}
+
+impl fmt::Display for Record {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let result = match self {
+            Record::Command { cbw, result } => {
+                let cb_len = (cbw[14] as usize).min(16);
+                write!(
+                    f,
+                    "CBW tag {} length {} {} lun {} cb",
+                    le32(&cbw[4..]),
+                    le32(&cbw[8..]),
+                    if cbw[12] & 0x80 != 0 { "in" } else { "out" },
+                    cbw[13],
+                )?;
+                for b in &cbw[15..15 + cb_len] {
+                    write!(f, " {:02x}", b)?;
+                }
+                result.map(|n| (n as usize) < CBW_LEN)
+            }
+            Record::Data {
+                direction,
+                requested,
+                result,
+            } => {
+                write!(
+                    f,
+                    "DATA {} {}/{}",
+                    match direction {
+                        Direction::In => "in",
+                        Direction::Out => "out",
+                    },
+                    result.unwrap_or(0),
+                    requested
+                )?;
+                result.map(|_| false)
+            }
+            Record::Status { csw, result } => {
+                write!(
+                    f,
+                    "CSW tag {} residue {} status {}",
+                    le32(&csw[4..]),
+                    le32(&csw[8..]),
+                    csw[12],
+                )?;
+                result.map(|n| (n as usize) < CSW_LEN)
+            }
+        };
+        match result {
+            Ok(false) => Ok(()),
+            Ok(true) => write!(f, " (short)"),
+            Err(e) => write!(f, " ({})", error_name(e)),
+        }
+    }
+}
+
+/// Something told about each phase of each mass-storage command
+///
+/// Any function or closure taking a `&Record` is a `TraceSink`; with
+/// the `std` feature, so is [`TraceLog`].
+pub trait TraceSink {
+    /// Note that a phase of a command has completed
+    fn record(&self, record: &Record);
+}
+
+impl<F: Fn(&Record)> TraceSink for F {
+    fn record(&self, record: &Record) {
+        self(record)
+    }
+}
+
+/// The ways in which a binary log can be malformed
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FormatError {
+    /// The log doesn't start with [`HEADER`] (or is a later version)
+    BadHeader,
+    /// The log ends part-way through a record
+    Truncated,
+    /// A record has the wrong length for its kind
+    BadLength,
+    /// A record contains an impossible value
+    BadValue,
+}
+
+impl fmt::Display for FormatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            FormatError::BadHeader => "not a mass-storage trace log",
+            FormatError::Truncated => "log truncated",
+            FormatError::BadLength => "record has bad length",
+            FormatError::BadValue => "record has bad value",
+        })
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for FormatError {}
+
+/// Decoding the records in a binary log
+///
+/// An iterator over the records; after the first error, it stops.
+pub struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    /// Start reading a binary log, which must begin with [`HEADER`]
+    pub fn new(log: &'a [u8]) -> Result<Self, FormatError> {
+        match log.strip_prefix(&HEADER[..]) {
+            Some(rest) => Ok(Self { bytes: rest }),
+            None => Err(FormatError::BadHeader),
+        }
+    }
+}
+
+impl Iterator for Reader<'_> {
+    type Item = Result<Record, FormatError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let [kind, len, rest @ ..] = self.bytes else {
+                if self.bytes.is_empty() {
+                    return None;
+                }
+                self.bytes = &[];
+                return Some(Err(FormatError::Truncated));
+            };
+            let len = *len as usize;
+            if rest.len() < len {
+                self.bytes = &[];
+                return Some(Err(FormatError::Truncated));
+            }
+            let (payload, rest) = rest.split_at(len);
+            self.bytes = rest;
+            match Record::decode(*kind, payload) {
+                Ok(Some(r)) => return Some(Ok(r)),
+                Ok(None) => continue,
+                Err(e) => {
+                    self.bytes = &[];
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
+
+/// A [`TraceSink`] which accumulates a binary log in memory
+#[cfg(feature = "std")]
+pub struct TraceLog {
+    bytes: core::cell::RefCell<Vec<u8>>,
+}
+
+#[cfg(feature = "std")]
+impl Default for TraceLog {
+    fn default() -> Self {
+        Self {
+            bytes: core::cell::RefCell::new(HEADER.to_vec()),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl TraceLog {
+    /// The binary log so far, including the header
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.bytes.borrow().clone()
+    }
+}
+
+#[cfg(feature = "std")]
+impl TraceSink for TraceLog {
+    fn record(&self, record: &Record) {
+        let mut buf = [0u8; MAX_RECORD_LEN];
+        self.bytes
+            .borrow_mut()
+            .extend_from_slice(record.encode(&mut buf));
+    }
+}
+
+/// Render a binary log as text, one numbered record per line
+#[cfg(feature = "std")]
+pub fn pretty_print(log: &[u8]) -> Result<String, FormatError> {
+    use std::fmt::Write;
+
+    let mut s = String::new();
+    for (i, r) in Reader::new(log)?.enumerate() {
+        let _ = writeln!(s, "{:4}: {}", i, r?);
+    }
+    Ok(s)
+}
+
+#[cfg(all(test, feature = "std"))]
+#[path = "tests/trace.rs"]
+mod tests;
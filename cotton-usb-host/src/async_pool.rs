@@ -1,5 +1,5 @@
 use crate::bitset::BitSet;
-use core::cell::{Cell, RefCell};
+use crate::cell::{self, Cell, RefCell};
 use core::future::Future;
 use core::pin::Pin;
 use core::task::{Context, Poll, Waker};
@@ -38,10 +38,38 @@ use std::fmt::{self, Display};
 ///
 /// For a larger example, see how the RP2040 USB host-controller driver
 /// shares out its USB endpoints.
+///
+/// # Priority
+/// Allocations made with [`Pool::alloc_with_priority()`] belong to
+/// one of the [`Priority`] classes. While any caller of a higher
+/// class is waiting for a resource, callers of lower classes can't
+/// obtain one, even if one is idle -- so a steady stream of
+/// low-priority users can't starve high-priority ones.
 pub struct Pool {
     total: u8,
     allocated: Cell<BitSet>,
-    waker: RefCell<Option<Waker>>,
+    waiting: Cell<[u8; Priority::COUNT]>,
+    wakers: [RefCell<Option<Waker>>; Priority::COUNT],
+}
+
+/// The priority class of an allocation from a [`Pool`]
+///
+/// See [`Pool::alloc_with_priority()`].
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    /// Gives way to all other allocations (e.g., bulk transfers)
+    Low = 0,
+    /// The priority of [`Pool::alloc()`] and [`Pool::try_alloc()`]
+    #[default]
+    Normal = 1,
+    /// Takes precedence over all other allocations (e.g., control
+    /// transfers, including those used for enumeration)
+    High = 2,
+}
+
+impl Priority {
+    const COUNT: usize = 3;
 }
 
 /// Representing ownership of one of the resources in a [`Pool`]
@@ -77,19 +105,106 @@ impl Drop for Pooled<'_> {
     }
 }
 
-struct PoolFuture<'a> {
+/// Keeping track of whether a future is waiting on a [`Pool`]
+///
+/// Callers of lower priority give way to this one for as long as it
+/// is waiting, so it mustn't *stay* waiting once it has completed or
+/// been cancelled (dropped).
+struct Waiting<'a> {
     pool: &'a Pool,
+    priority: Priority,
+    waiting: bool,
+}
+
+impl<'a> Waiting<'a> {
+    const fn new(pool: &'a Pool, priority: Priority) -> Self {
+        Self {
+            pool,
+            priority,
+            waiting: false,
+        }
+    }
+
+    fn register(&self, cx: &Context<'_>) {
+        self.pool.wakers[self.priority as usize]
+            .replace(Some(cx.waker().clone()));
+    }
+
+    fn start(&mut self) {
+        if !self.waiting {
+            self.waiting = true;
+            cell::modify(&self.pool.waiting, |waiting| {
+                waiting[self.priority as usize] += 1;
+            });
+        }
+    }
+
+    fn stop(&mut self) {
+        if self.waiting {
+            self.waiting = false;
+            let last = cell::modify(&self.pool.waiting, |waiting| {
+                waiting[self.priority as usize] -= 1;
+                waiting[self.priority as usize] == 0
+            });
+            if last {
+                // Nobody's left to wake at our priority; but
+                // lower-priority callers may have been giving way to us
+                self.pool.wakers[self.priority as usize].take();
+                self.pool.wake(..self.priority as usize);
+            }
+        }
+    }
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+struct PoolFuture<'a> {
+    waiting: Waiting<'a>,
 }
 
 impl<'a> Future for PoolFuture<'a> {
     type Output = Pooled<'a>;
 
-    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        self.pool.waker.replace(Some(cx.waker().clone()));
+    fn poll(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Self::Output> {
+        let pool = self.waiting.pool;
+        self.waiting.register(cx);
 
-        if let Some(n) = self.pool.alloc_internal() {
-            Poll::Ready(Pooled { n, pool: self.pool })
+        if let Some(n) = pool.alloc_internal(self.waiting.priority) {
+            self.waiting.stop();
+            Poll::Ready(Pooled { n, pool })
         } else {
+            self.waiting.start();
+            Poll::Pending
+        }
+    }
+}
+
+struct PoolFutureN<'a, const N: usize> {
+    waiting: Waiting<'a>,
+}
+
+impl<'a, const N: usize> Future for PoolFutureN<'a, N> {
+    type Output = [Pooled<'a>; N];
+
+    fn poll(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Self::Output> {
+        let pool = self.waiting.pool;
+        self.waiting.register(cx);
+
+        if let Some(ns) = pool.alloc_n_internal::<N>(self.waiting.priority) {
+            self.waiting.stop();
+            Poll::Ready(ns.map(|n| Pooled { n, pool }))
+        } else {
+            self.waiting.start();
             Poll::Pending
         }
     }
@@ -108,30 +223,71 @@ impl Pool {
         Self {
             total,
             allocated: Cell::new(BitSet::new()),
-            waker: RefCell::new(None),
+            waiting: Cell::new([0; Priority::COUNT]),
+            wakers: [
+                RefCell::new(None),
+                RefCell::new(None),
+                RefCell::new(None),
+            ],
         }
     }
 
-    fn alloc_internal(&self) -> Option<u8> {
-        let mut bits = self.allocated.get();
-        let n = bits.set_any()?;
-        if n >= self.total {
-            None
-        } else {
-            self.allocated.replace(bits);
-            Some(n)
+    /// Whether callers of this priority must give way to waiting ones
+    fn must_give_way(&self, priority: Priority) -> bool {
+        self.waiting.get()[priority as usize + 1..]
+            .iter()
+            .any(|n| *n > 0)
+    }
+
+    fn wake(&self, priorities: core::ops::RangeTo<usize>) {
+        for waker in &self.wakers[priorities] {
+            if let Some(w) = waker.take() {
+                w.wake();
+            }
         }
     }
 
-    fn dealloc_internal(&self, n: u8) {
-        let mut bits = self.allocated.get();
-        debug_assert!(bits.contains(n));
-        bits.clear(n);
-        self.allocated.replace(bits);
+    fn alloc_internal(&self, priority: Priority) -> Option<u8> {
+        if self.must_give_way(priority) {
+            return None;
+        }
+        cell::modify(&self.allocated, |allocated| {
+            let mut bits = *allocated;
+            let n = bits.set_any()?;
+            if n >= self.total {
+                None
+            } else {
+                *allocated = bits;
+                Some(n)
+            }
+        })
+    }
 
-        if let Some(w) = self.waker.take() {
-            w.wake();
+    fn alloc_n_internal<const N: usize>(
+        &self,
+        priority: Priority,
+    ) -> Option<[u8; N]> {
+        if self.must_give_way(priority) {
+            return None;
         }
+        cell::modify(&self.allocated, |allocated| {
+            let mut bits = *allocated;
+            let mut ns = [0; N];
+            for n in &mut ns {
+                *n = bits.set_any().filter(|n| *n < self.total)?;
+            }
+            *allocated = bits;
+            Some(ns)
+        })
+    }
+
+    fn dealloc_internal(&self, n: u8) {
+        cell::modify(&self.allocated, |bits| {
+            debug_assert!(bits.contains(n));
+            bits.clear(n);
+        });
+
+        self.wake(..Priority::COUNT);
     }
 
     /// Obtain one of the resources
@@ -149,9 +305,26 @@ impl Pool {
     /// other potential resource users.
     ///
     /// # See also
-    /// [`Pool::try_alloc()`] for a synchronous version
+    /// [`Pool::try_alloc()`] for a synchronous version, and
+    /// [`Pool::alloc_with_priority()`] for one that can jump (or
+    /// give way to) other callers
     pub async fn alloc(&self) -> Pooled {
-        let fut = PoolFuture { pool: self };
+        self.alloc_with_priority(Priority::Normal).await
+    }
+
+    /// Obtain one of the resources, as a caller of a certain priority
+    ///
+    /// Like [`Pool::alloc()`], except that while any caller of higher
+    /// priority is waiting, this one won't be given a resource even
+    /// if one is idle; and while this one is waiting, callers of
+    /// lower priority won't be either.
+    ///
+    /// # See also
+    /// [`Pool::try_alloc_with_priority()`] for a synchronous version
+    pub async fn alloc_with_priority(&self, priority: Priority) -> Pooled<'_> {
+        let fut = PoolFuture {
+            waiting: Waiting::new(self, priority),
+        };
         fut.await
     }
 
@@ -163,11 +336,78 @@ impl Pool {
     /// # See also
     /// [`Pool::alloc()`] for an asynchronous version
     pub fn try_alloc(&self) -> Option<Pooled> {
+        self.try_alloc_with_priority(Priority::Normal)
+    }
+
+    /// Obtain a resource if one is immediately available to a caller
+    /// of a certain priority
+    ///
+    /// Returns `None` if none of the resources is idle, or if any
+    /// caller of higher priority is waiting in
+    /// [`Pool::alloc_with_priority()`].
+    ///
+    /// # See also
+    /// [`Pool::alloc_with_priority()`] for an asynchronous version
+    pub fn try_alloc_with_priority(
+        &self,
+        priority: Priority,
+    ) -> Option<Pooled<'_>> {
         Some(Pooled {
-            n: self.alloc_internal()?,
+            n: self.alloc_internal(priority)?,
             pool: self,
         })
     }
+
+    /// Obtain N of the resources, all at once
+    ///
+    /// Like [`Pool::alloc()`], but waits until N resources are idle
+    /// at the same time, and then takes them all together. A caller
+    /// needing several resources that instead called `alloc()`
+    /// several times could, while holding some of them, end up
+    /// waiting forever for another caller who is in turn waiting for
+    /// those.
+    ///
+    /// Nothing is allocated until the returned future completes, so
+    /// dropping it beforehand (cancelling it) leaves the pool
+    /// untouched. It never completes if N is larger than the total
+    /// number of resources in the pool.
+    ///
+    /// # See also
+    /// [`Pool::try_alloc_n()`] for a synchronous version
+    pub async fn alloc_n<const N: usize>(&self) -> [Pooled<'_>; N] {
+        let fut = PoolFutureN::<N> {
+            waiting: Waiting::new(self, Priority::Normal),
+        };
+        fut.await
+    }
+
+    /// Obtain N resources if that many are immediately available
+    ///
+    /// Returns `Some` if at least N of the resources are currently
+    /// idle (unused). Otherwise, returns `None` -- and allocates none
+    /// of them.
+    ///
+    /// # See also
+    /// [`Pool::alloc_n()`] for an asynchronous version
+    pub fn try_alloc_n<const N: usize>(&self) -> Option<[Pooled<'_>; N]> {
+        Some(
+            self.alloc_n_internal::<N>(Priority::Normal)?
+                .map(|n| Pooled { n, pool: self }),
+        )
+    }
+
+    /// The total number of resources in the pool, whether in use or not
+    pub const fn total(&self) -> u8 {
+        self.total
+    }
+
+    /// The number of resources currently idle (unused)
+    ///
+    /// This is only a snapshot: by the time the caller acts on it,
+    /// other users of the pool may have changed it.
+    pub fn available(&self) -> u8 {
+        self.total - self.allocated.get().0.count_ones() as u8
+    }
 }
 
 #[cfg(all(test, feature = "std"))]
@@ -1,10 +1,12 @@
-/// A compact representation of a set of integers, 0-31 inclusive
+/// A compact representation of a set of integers, 0-127 inclusive
+///
+/// This is large enough to hold any set of USB device addresses.
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 #[cfg_attr(feature = "std", derive(Debug))]
 #[derive(Clone, Copy, PartialEq, Eq, Default)]
 pub struct BitSet(
     /// A bitfield, with a 1 in bit N signifying that N is present in the BitSet
-    pub u32,
+    pub u128,
 );
 
 impl BitSet {
@@ -23,23 +25,23 @@ impl BitSet {
 
     /// Add n to the set
     pub fn set(&mut self, n: u8) {
-        assert!(n < 32);
+        assert!(n < 128);
         self.0 |= 1 << n;
     }
 
     /// Remove n from the set, if present
     pub fn clear(&mut self, n: u8) {
-        assert!(n < 32);
+        assert!(n < 128);
         self.0 &= !(1 << n);
     }
 
     /// Add to the set the smallest integer not already present
     ///
-    /// And return it. Or if the set is "full" (integers 0-31 are all
+    /// And return it. Or if the set is "full" (integers 0-127 are all
     /// present), return None.
     pub fn set_any(&mut self) -> Option<u8> {
         let next = self.0.trailing_ones() as u8;
-        if next >= 32 {
+        if next >= 128 {
             None
         } else {
             self.set(next);
@@ -49,15 +51,15 @@ impl BitSet {
 
     /// Is n present in the set?
     pub fn contains(&self, n: u8) -> bool {
-        assert!(n < 32);
+        assert!(n < 128);
         (self.0 & (1 << n)) != 0
     }
 }
 
-struct BitIterator(u32);
+struct BitIterator(u128);
 
 impl BitIterator {
-    pub const fn new(n: u32) -> Self {
+    pub const fn new(n: u128) -> Self {
         Self(n)
     }
 }
@@ -0,0 +1,138 @@
+#[cfg(not(feature = "send-futures"))]
+pub use core::cell::{Cell, RefCell};
+
+#[cfg(feature = "send-futures")]
+pub use sync::{Cell, RefCell};
+
+/// Modify the value in a cell, all in one go
+///
+/// Unlike `cell.set(f(cell.get()))`, this can't lose an update made
+/// in between by another thread.
+#[cfg(not(feature = "send-futures"))]
+pub(crate) fn modify<T: Copy, R>(
+    cell: &Cell<T>,
+    f: impl FnOnce(&mut T) -> R,
+) -> R {
+    let mut value = cell.get();
+    let result = f(&mut value);
+    cell.set(value);
+    result
+}
+
+/// Modify the value in a cell, all in one go
+///
+/// Unlike `cell.set(f(cell.get()))`, this can't lose an update made
+/// in between by another thread.
+#[cfg(feature = "send-futures")]
+pub(crate) fn modify<T: Copy, R>(
+    cell: &Cell<T>,
+    f: impl FnOnce(&mut T) -> R,
+) -> R {
+    f(&mut cell.lock())
+}
+
+#[cfg(feature = "send-futures")]
+mod sync {
+    use std::sync::{Mutex, MutexGuard};
+
+    fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+        // A panic elsewhere doesn't leave any of our data inconsistent
+        mutex.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// A thread-safe replacement for `core::cell::Cell`
+    #[derive(Default)]
+    pub struct Cell<T>(Mutex<T>);
+
+    impl<T> Cell<T> {
+        /// Create a new `Cell` containing `value`
+        pub const fn new(value: T) -> Self {
+            Self(Mutex::new(value))
+        }
+
+        /// Set the contained value
+        pub fn set(&self, value: T) {
+            *self.lock() = value;
+        }
+
+        /// Replace the contained value, returning the old one
+        pub fn replace(&self, value: T) -> T {
+            core::mem::replace(&mut self.lock(), value)
+        }
+
+        pub(crate) fn lock(&self) -> MutexGuard<'_, T> {
+            lock(&self.0)
+        }
+    }
+
+    impl<T: Copy> Cell<T> {
+        /// Return a copy of the contained value
+        pub fn get(&self) -> T {
+            *self.lock()
+        }
+    }
+
+    impl<T: Copy + core::fmt::Debug> core::fmt::Debug for Cell<T> {
+        fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+            f.debug_struct("Cell").field("value", &self.get()).finish()
+        }
+    }
+
+    #[cfg(feature = "defmt")]
+    impl<T: Copy + defmt::Format> defmt::Format for Cell<T> {
+        fn format(&self, f: defmt::Formatter<'_>) {
+            defmt::write!(f, "Cell {{ value: {} }}", self.get())
+        }
+    }
+
+    impl<T: Copy + PartialEq> PartialEq for Cell<T> {
+        fn eq(&self, other: &Self) -> bool {
+            self.get() == other.get()
+        }
+    }
+
+    impl<T: Copy + Eq> Eq for Cell<T> {}
+
+    /// A thread-safe replacement for `core::cell::RefCell`
+    ///
+    /// Unlike `core::cell::RefCell`, even a shared borrow excludes all
+    /// other borrows.
+    #[derive(Default)]
+    pub struct RefCell<T>(Mutex<T>);
+
+    impl<T> RefCell<T> {
+        /// Create a new `RefCell` containing `value`
+        pub const fn new(value: T) -> Self {
+            Self(Mutex::new(value))
+        }
+
+        /// Borrow the contained value
+        pub fn borrow(&self) -> MutexGuard<'_, T> {
+            lock(&self.0)
+        }
+
+        /// Mutably borrow the contained value
+        pub fn borrow_mut(&self) -> MutexGuard<'_, T> {
+            lock(&self.0)
+        }
+
+        /// Replace the contained value, returning the old one
+        pub fn replace(&self, value: T) -> T {
+            core::mem::replace(&mut self.borrow_mut(), value)
+        }
+    }
+
+    #[cfg(feature = "defmt")]
+    impl<T: defmt::Format> defmt::Format for RefCell<T> {
+        fn format(&self, f: defmt::Formatter<'_>) {
+            defmt::write!(f, "RefCell {{ value: {} }}", *self.borrow())
+        }
+    }
+
+    impl<T: Default> RefCell<T> {
+        /// Take the contained value, leaving `Default::default()`
+        pub fn take(&self) -> T {
+            core::mem::take(&mut self.borrow_mut())
+        }
+    }
+}
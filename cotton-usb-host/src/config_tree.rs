@@ -0,0 +1,202 @@
+use crate::wire::{
+    ConfigurationDescriptor, DescriptorVisitor, EndpointDescriptor,
+    InterfaceDescriptor, INTERFACE_ASSOCIATION_DESCRIPTOR,
+};
+use alloc::vec::Vec;
+
+/// A device's configuration descriptors, parsed into a tree
+///
+/// Can be obtained from
+/// [`UsbBus::get_configuration_tree()`](crate::usb_bus::UsbBus::get_configuration_tree),
+/// or by passing a `ConfigurationTree::default()` as the
+/// [`DescriptorVisitor`] to
+/// [`parse_descriptors()`](crate::wire::parse_descriptors).
+///
+/// Descriptors that aren't configuration, interface, or endpoint
+/// descriptors -- class-specific or vendor-specific ones, such as HID
+/// or audio descriptors -- are attached, as raw bytes, to whichever of
+/// those they follow: as USB 2.0 section 9.5 lays out. The exception
+/// is interface association descriptors, which come *before* the
+/// interfaces they describe, and so are attached to the configuration.
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Default, Clone)]
+pub struct ConfigurationTree {
+    /// The configurations, in the order they were found
+    pub configurations: Vec<Configuration>,
+
+    /// Where the next "other" descriptor is to be attached
+    cursor: Cursor,
+}
+
+/// The most recent node added to a [`ConfigurationTree`]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Default, Clone, Copy)]
+enum Cursor {
+    #[default]
+    None,
+    Configuration,
+    /// Indexes into `interfaces`, and into that interface's `alternates`
+    AlternateSetting(usize, usize),
+    Endpoint(usize, usize),
+}
+
+/// One configuration, with all its interfaces
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Clone)]
+pub struct Configuration {
+    /// The configuration descriptor itself
+    pub descriptor: ConfigurationDescriptor,
+
+    /// The interfaces, in order of first appearance
+    pub interfaces: Vec<Interface>,
+
+    /// Other descriptors, each as raw bytes, belonging to the
+    /// configuration as a whole
+    pub extra: Vec<Vec<u8>>,
+}
+
+/// One interface, with all its alternate settings
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Clone)]
+pub struct Interface {
+    /// The interface number (`bInterfaceNumber`)
+    pub number: u8,
+
+    /// The alternate settings; there is always at least one
+    pub alternates: Vec<AlternateSetting>,
+}
+
+/// One alternate setting of an interface, with its endpoints
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Clone)]
+pub struct AlternateSetting {
+    /// The interface descriptor for this alternate setting
+    pub descriptor: InterfaceDescriptor,
+
+    /// The endpoints used in this alternate setting
+    pub endpoints: Vec<Endpoint>,
+
+    /// Other descriptors (for instance, a HID descriptor), each as raw
+    /// bytes, belonging to this alternate setting
+    pub extra: Vec<Vec<u8>>,
+}
+
+/// One endpoint
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Clone)]
+pub struct Endpoint {
+    /// The endpoint descriptor itself
+    pub descriptor: EndpointDescriptor,
+
+    /// Other descriptors (for instance, class-specific endpoint
+    /// descriptors), each as raw bytes, belonging to this endpoint
+    pub extra: Vec<Vec<u8>>,
+}
+
+impl Configuration {
+    /// The interface with this interface number, if there is one
+    pub fn interface(&self, number: u8) -> Option<&Interface> {
+        self.interfaces.iter().find(|i| i.number == number)
+    }
+}
+
+impl Interface {
+    /// The alternate setting with this number, if there is one
+    pub fn alternate(&self, setting: u8) -> Option<&AlternateSetting> {
+        self.alternates
+            .iter()
+            .find(|a| a.descriptor.bAlternateSetting == setting)
+    }
+}
+
+impl ConfigurationTree {
+    fn current(&mut self) -> Option<&mut Configuration> {
+        self.configurations.last_mut()
+    }
+}
+
+impl DescriptorVisitor for ConfigurationTree {
+    fn on_configuration(&mut self, c: &ConfigurationDescriptor) {
+        self.configurations.push(Configuration {
+            descriptor: *c,
+            interfaces: Vec::new(),
+            extra: Vec::new(),
+        });
+        self.cursor = Cursor::Configuration;
+    }
+
+    fn on_interface(&mut self, i: &InterfaceDescriptor) {
+        let Some(c) = self.current() else {
+            return;
+        };
+        let alternate = AlternateSetting {
+            descriptor: *i,
+            endpoints: Vec::new(),
+            extra: Vec::new(),
+        };
+        let index = match c
+            .interfaces
+            .iter()
+            .position(|x| x.number == i.bInterfaceNumber)
+        {
+            Some(index) => {
+                c.interfaces[index].alternates.push(alternate);
+                index
+            }
+            None => {
+                c.interfaces.push(Interface {
+                    number: i.bInterfaceNumber,
+                    alternates: alloc::vec![alternate],
+                });
+                c.interfaces.len() - 1
+            }
+        };
+        let alternate = c.interfaces[index].alternates.len() - 1;
+        self.cursor = Cursor::AlternateSetting(index, alternate);
+    }
+
+    fn on_endpoint(&mut self, e: &EndpointDescriptor) {
+        let (Cursor::AlternateSetting(i, a) | Cursor::Endpoint(i, a)) =
+            self.cursor
+        else {
+            return;
+        };
+        if let Some(c) = self.current() {
+            c.interfaces[i].alternates[a].endpoints.push(Endpoint {
+                descriptor: *e,
+                extra: Vec::new(),
+            });
+            self.cursor = Cursor::Endpoint(i, a);
+        }
+    }
+
+    fn on_other(&mut self, d: &[u8]) {
+        let cursor = if d[1] == INTERFACE_ASSOCIATION_DESCRIPTOR {
+            Cursor::Configuration
+        } else {
+            self.cursor
+        };
+        let Some(c) = self.current() else {
+            return;
+        };
+        let extra = match cursor {
+            Cursor::None => return,
+            Cursor::Configuration => &mut c.extra,
+            Cursor::AlternateSetting(i, a) => {
+                &mut c.interfaces[i].alternates[a].extra
+            }
+            Cursor::Endpoint(i, a) => {
+                let alternate = &mut c.interfaces[i].alternates[a];
+                match alternate.endpoints.last_mut() {
+                    Some(e) => &mut e.extra,
+                    None => &mut alternate.extra,
+                }
+            }
+        };
+        extra.push(d.to_vec());
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+#[path = "tests/config_tree.rs"]
+mod tests;
@@ -0,0 +1,133 @@
+use core::future::Future;
+
+/// A way of waiting for a given number of milliseconds
+///
+/// The USB host stack needs to wait at various points -- for instance,
+/// while a port is being reset, or before retrying enumeration -- but
+/// doesn't itself know how timers work on any particular executor. So
+/// it asks a `DelayProvider` instead.
+///
+/// Any function or closure taking a number of milliseconds, and
+/// returning a Future that completes after that long, is a
+/// `DelayProvider`. Ready-made implementations are also available:
+///
+///  - `EmbassyDelay`, with the `embassy-time` feature;
+///  - `rtic_delay_provider!`, with the `rtic` feature, for RTIC
+///    monotonics;
+///  - [`BusyWait`], which doesn't need a timer at all.
+///
+pub trait DelayProvider {
+    /// Return a Future that completes after (at least) `ms` milliseconds
+    fn delay_ms(&self, ms: usize) -> impl Future<Output = ()>;
+}
+
+impl<F, D> DelayProvider for F
+where
+    F: Fn(usize) -> D,
+    D: Future<Output = ()>,
+{
+    fn delay_ms(&self, ms: usize) -> impl Future<Output = ()> {
+        self(ms)
+    }
+}
+
+/// A [`DelayProvider`] using the Embassy timer
+///
+/// ```no_run
+/// # use cotton_usb_host::host_controller::HostController;
+/// # use cotton_usb_host::delay::EmbassyDelay;
+/// # use cotton_usb_host::usb_bus::{HubState, UsbBus};
+/// # use core::pin::pin;
+/// # async fn foo<D: HostController>(driver: D) -> () {
+/// let hub_state = HubState::default();
+/// let bus = UsbBus::new(driver);
+/// let mut device_stream = pin!(bus.device_events(&hub_state, EmbassyDelay));
+/// # }
+/// ```
+#[cfg(feature = "embassy-time")]
+#[derive(Copy, Clone, Default)]
+pub struct EmbassyDelay;
+
+#[cfg(feature = "embassy-time")]
+impl DelayProvider for EmbassyDelay {
+    fn delay_ms(&self, ms: usize) -> impl Future<Output = ()> {
+        embassy_time::Timer::after_millis(ms as u64)
+    }
+}
+
+/// Declare a [`DelayProvider`] using an RTIC monotonic
+///
+/// RTIC monotonics are types declared in the application (usually by
+/// a macro from the `rtic-monotonics` crate), so the corresponding
+/// `DelayProvider` is declared by a macro too. The first argument is
+/// the name (and, optionally, visibility) of the new unit struct
+/// type, the second is the type of the monotonic. That type must
+/// implement `rtic_monotonics::Monotonic` (from `rtic-monotonics`
+/// 2.x) with a `fugit` duration type, either 32-bit or 64-bit:
+///
+/// ```ignore
+/// rtic_monotonics::rp2040_timer_monotonic!(Mono);
+/// cotton_usb_host::rtic_delay_provider!(MonoDelay, Mono);
+///
+/// let mut device_stream = pin!(bus.device_events(&hub_state, MonoDelay));
+/// ```
+///
+/// Delays longer than `u32::MAX` milliseconds are clamped to that.
+#[cfg(feature = "rtic")]
+#[macro_export]
+macro_rules! rtic_delay_provider {
+    ($vis:vis $name:ident, $mono:ty) => {
+        #[derive(Copy, Clone, Default)]
+        $vis struct $name;
+
+        impl $crate::delay::DelayProvider for $name {
+            fn delay_ms(
+                &self,
+                ms: usize,
+            ) -> impl core::future::Future<Output = ()> {
+                use $crate::__rtic_monotonics::Monotonic;
+                let ms = core::cmp::min(ms, u32::MAX as usize);
+                <$mono as Monotonic>::delay(
+                    <$mono as Monotonic>::Duration::millis(ms as _),
+                )
+            }
+        }
+    };
+}
+
+/// A [`DelayProvider`] which spins the CPU, for use where no timer is
+/// available
+///
+/// The delay is only as accurate as the calibration: the number of
+/// iterations of [`core::hint::spin_loop()`] taking one millisecond,
+/// which depends on the CPU and its clock speed. The spinning happens
+/// when the returned Future is first polled, and nothing else on the
+/// same executor can run meanwhile.
+#[derive(Copy, Clone)]
+pub struct BusyWait {
+    spins_per_ms: u32,
+}
+
+impl BusyWait {
+    /// Create a `BusyWait` calibrated to `spins_per_ms` iterations of
+    /// [`core::hint::spin_loop()`] per millisecond
+    #[must_use]
+    pub const fn new(spins_per_ms: u32) -> Self {
+        Self { spins_per_ms }
+    }
+}
+
+impl DelayProvider for BusyWait {
+    fn delay_ms(&self, ms: usize) -> impl Future<Output = ()> {
+        let spins = (ms as u64).saturating_mul(self.spins_per_ms as u64);
+        async move {
+            for _ in 0..spins {
+                core::hint::spin_loop();
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+#[path = "tests/delay.rs"]
+mod tests;
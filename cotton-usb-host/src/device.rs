@@ -1,2 +1,17 @@
 /// Identifying which driver to use for a particular USB device
 pub mod identify;
+
+/// A driver for USB Device Firmware Upgrade (DFU) class devices
+pub mod dfu;
+
+/// A generic driver for register-based vendor-specific devices
+pub mod vendor;
+
+/// An example driver for XInput (Xbox 360-compatible) game controllers
+pub mod xinput;
+
+/// A driver for boot-protocol keyboards, with keymaps and key repeat
+pub mod keyboard;
+
+/// A driver for CDC-NCM USB network adapters, such as tethered phones
+pub mod cdc_ncm;
@@ -0,0 +1,906 @@
+use crate::device::identify::IdentifyFromDescriptors;
+use crate::host_controller::{
+    DataPhase, HostController, TransferType, UsbError,
+};
+use crate::usb_bus::{BulkIn, BulkOut, UsbBus, UsbDevice};
+use crate::wire::{
+    ConfigurationDescriptor, DescriptorVisitor, EndpointDescriptor,
+    InterfaceDescriptor, SetupPacket, CLASS_REQUEST, DEVICE_TO_HOST,
+    GET_DESCRIPTOR, HOST_TO_DEVICE, RECIPIENT_DEVICE, RECIPIENT_INTERFACE,
+    SET_INTERFACE, STANDARD_REQUEST, STRING_DESCRIPTOR,
+};
+use core::ops::Range;
+
+/// Interface class code of CDC communications interfaces
+pub const CDC_COMMUNICATIONS_CLASSCODE: u8 = 0x02;
+
+/// Interface subclass code of NCM communications interfaces
+pub const NCM_SUBCLASS: u8 = 0x0D;
+
+/// Interface class code of CDC data interfaces
+pub const CDC_DATA_CLASSCODE: u8 = 0x0A;
+
+/// Descriptor type of class-specific interface descriptors
+const CS_INTERFACE: u8 = 0x24;
+
+/// Functional descriptor subtypes (CDC 1.2 table 13)
+const UNION_FUNCTIONAL: u8 = 0x06;
+const ETHERNET_FUNCTIONAL: u8 = 0x0F;
+const NCM_FUNCTIONAL: u8 = 0x1A;
+
+/// Class request: read the device's NTB parameters (NCM 1.0 section 6.2.1)
+pub const GET_NTB_PARAMETERS: u8 = 0x80;
+
+/// Class request: limit the size of NTBs sent to the host (NCM 1.0 6.2.7)
+pub const SET_NTB_INPUT_SIZE: u8 = 0x86;
+
+/// `bmNetworkCapabilities` bit: SET_NTB_INPUT_SIZE takes 8 bytes, not 4
+const NTB_INPUT_SIZE_8_BYTE: u8 = 0x20;
+
+/// Signature of the 16-bit NTB header, "NCMH"
+pub const NTH16_SIGNATURE: [u8; 4] = *b"NCMH";
+
+/// Signature of 16-bit datagram pointer tables without CRCs, "NCM0"
+pub const NDP16_SIGNATURE: [u8; 4] = *b"NCM0";
+
+const NTH16_LENGTH: usize = 12;
+
+/// The most datagrams batched into any one transmitted NTB
+pub const MAX_DATAGRAMS_PER_NTB: usize = 16;
+
+/// The most chained NDPs followed in any one received NTB
+///
+/// Devices in practice send just one; this only stops a malformed
+/// chain which loops back on itself from being followed forever.
+const MAX_NDPS_PER_NTB: u8 = 8;
+
+/// Largest Ethernet frame, if the device doesn't say (NCM 1.0 5.2.1)
+const DEFAULT_MAX_SEGMENT_SIZE: u16 = 1514;
+
+fn le16(buf: &[u8], at: usize) -> usize {
+    u16::from_le_bytes([buf[at], buf[at + 1]]) as usize
+}
+
+fn put_le16(buf: &mut [u8], at: usize, value: usize) {
+    buf[at..at + 2].copy_from_slice(&(value as u16).to_le_bytes());
+}
+
+fn ndp16_length(datagrams: usize) -> usize {
+    // Header, then one entry per datagram, then a zero terminator
+    8 + 4 * (datagrams + 1)
+}
+
+/// The device's NTB parameters (NCM 1.0 table 6-3)
+///
+/// The `Default` value is the smallest set that any NCM device must
+/// support, used until the real parameters have been read.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct NtbParameters {
+    /// Bit 0: 16-bit NTBs supported; bit 1: 32-bit NTBs supported
+    pub ntb_formats_supported: u16,
+    /// Largest NTB the device might send to the host
+    pub ntb_in_max_size: u32,
+    /// Largest NTB the device can receive from the host
+    pub ntb_out_max_size: u32,
+    /// Outgoing datagrams must start at an offset which, modulo this...
+    pub ndp_out_divisor: u16,
+    /// ...is equal to this
+    pub ndp_out_payload_remainder: u16,
+    /// Outgoing NDPs must start at a multiple of this
+    pub ndp_out_alignment: u16,
+    /// Most datagrams the device accepts in one NTB (0 means no limit)
+    pub ntb_out_max_datagrams: u16,
+}
+
+impl Default for NtbParameters {
+    fn default() -> Self {
+        Self {
+            ntb_formats_supported: 1,
+            ntb_in_max_size: 2048,
+            ntb_out_max_size: 2048,
+            ndp_out_divisor: 4,
+            ndp_out_payload_remainder: 0,
+            ndp_out_alignment: 4,
+            ntb_out_max_datagrams: 0,
+        }
+    }
+}
+
+impl NtbParameters {
+    /// Decode the response to GET_NTB_PARAMETERS
+    pub fn parse(buf: &[u8]) -> Option<Self> {
+        if buf.len() < 28 {
+            return None;
+        }
+        let word = |i: usize| u16::from_le_bytes([buf[i], buf[i + 1]]);
+        let dword = |i: usize| {
+            u32::from_le_bytes([buf[i], buf[i + 1], buf[i + 2], buf[i + 3]])
+        };
+        Some(Self {
+            ntb_formats_supported: word(2),
+            ntb_in_max_size: dword(4),
+            ntb_out_max_size: dword(16),
+            ndp_out_divisor: word(20),
+            ndp_out_payload_remainder: word(22),
+            ndp_out_alignment: word(24),
+            ntb_out_max_datagrams: word(26),
+        })
+    }
+}
+
+/// Reasons why a received NTB can't be unpacked
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum NtbError {
+    /// The NTB doesn't start with a valid NTH16, or is shorter than
+    /// that header says
+    BadHeader,
+    /// The NTH16 doesn't point to a valid NDP16
+    BadNdp,
+}
+
+/// Is there a valid NDP16 at offset `at` in this NTB?
+fn is_ndp16(ntb: &[u8], at: usize) -> bool {
+    if at < NTH16_LENGTH || at % 4 != 0 || at + 8 > ntb.len() {
+        return false;
+    }
+    let len = le16(ntb, at + 4);
+    ntb[at..at + 4] == NDP16_SIGNATURE
+        && len >= ndp16_length(1)
+        && len % 4 == 0
+        && at + len <= ntb.len()
+}
+
+/// Position of a walk through the datagrams of an NTB
+///
+/// This holds offsets rather than borrowing the NTB, so that a driver
+/// can keep hold of it between calls.
+#[derive(Default)]
+struct NtbCursor {
+    ndp: Option<usize>,
+    entry: usize,
+    ndps_left: u8,
+}
+
+impl NtbCursor {
+    fn next(&mut self, ntb: &[u8]) -> Option<Range<usize>> {
+        loop {
+            let ndp = self.ndp?;
+            let entry = ndp + 8 + 4 * self.entry;
+            if entry + 4 <= ndp + le16(ntb, ndp + 4) {
+                let index = le16(ntb, entry);
+                let len = le16(ntb, entry + 2);
+                if index != 0 && len != 0 {
+                    self.entry += 1;
+                    // Skip, rather than trust, pointers outside the NTB
+                    if index >= NTH16_LENGTH && index + len <= ntb.len() {
+                        return Some(index..(index + len));
+                    }
+                    continue;
+                }
+            }
+            let next = le16(ntb, ndp + 6);
+            self.entry = 0;
+            self.ndp = if self.ndps_left > 0 && is_ndp16(ntb, next) {
+                self.ndps_left -= 1;
+                Some(next)
+            } else {
+                None
+            };
+        }
+    }
+}
+
+/// The datagrams (Ethernet frames) in a received NTB
+///
+/// Returned by [`parse_ntb()`]. Datagram pointers which lie outside
+/// the NTB are skipped.
+pub struct NtbDatagrams<'a> {
+    ntb: &'a [u8],
+    cursor: NtbCursor,
+}
+
+impl<'a> Iterator for NtbDatagrams<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.cursor.next(self.ntb).map(|r| &self.ntb[r])
+    }
+}
+
+/// Unpack a received 16-bit NTB (NCM 1.0 section 3)
+///
+/// Checks the NTH16 and the first NDP16; any further NDP16s chained
+/// from the first are followed while they're valid.
+pub fn parse_ntb(ntb: &[u8]) -> Result<NtbDatagrams<'_>, NtbError> {
+    if ntb.len() < NTH16_LENGTH
+        || ntb[0..4] != NTH16_SIGNATURE
+        || le16(ntb, 4) != NTH16_LENGTH
+    {
+        return Err(NtbError::BadHeader);
+    }
+    let block_length = le16(ntb, 8);
+    if block_length < NTH16_LENGTH || block_length > ntb.len() {
+        return Err(NtbError::BadHeader);
+    }
+    let ntb = &ntb[0..block_length];
+    let ndp = le16(ntb, 10);
+    if !is_ndp16(ntb, ndp) {
+        return Err(NtbError::BadNdp);
+    }
+    Ok(NtbDatagrams {
+        ntb,
+        cursor: NtbCursor {
+            ndp: Some(ndp),
+            entry: 0,
+            ndps_left: MAX_NDPS_PER_NTB - 1,
+        },
+    })
+}
+
+/// Batches outgoing datagrams into a 16-bit NTB
+///
+/// The layout is the one Linux's NCM host driver uses: the NTH16, then
+/// a single NDP16 with room reserved for the most datagrams the NTB
+/// can hold, then the datagrams themselves, each placed at the offset
+/// the device asked for in its [`NtbParameters`].
+pub struct NtbBuilder<'a> {
+    buf: &'a mut [u8],
+    capacity: usize,
+    sequence: u16,
+    max_datagrams: usize,
+    divisor: usize,
+    remainder: usize,
+    ndp_index: usize,
+    count: usize,
+    end: usize,
+}
+
+impl<'a> NtbBuilder<'a> {
+    /// Create a builder which assembles NTBs in `buf`
+    ///
+    /// NTBs are limited to the smaller of the size of `buf` and the
+    /// device's `ntb_out_max_size`.
+    pub fn new(buf: &'a mut [u8], params: &NtbParameters) -> Self {
+        let mut b = Self {
+            buf,
+            capacity: 0,
+            sequence: 0,
+            max_datagrams: 0,
+            divisor: 1,
+            remainder: 0,
+            ndp_index: 0,
+            count: 0,
+            end: 0,
+        };
+        b.set_parameters(params);
+        b
+    }
+
+    /// Adopt new NTB parameters, discarding any datagrams so far
+    pub fn set_parameters(&mut self, params: &NtbParameters) {
+        self.capacity = self
+            .buf
+            .len()
+            .min(params.ntb_out_max_size as usize)
+            .min(u16::MAX as usize);
+        self.max_datagrams = match params.ntb_out_max_datagrams as usize {
+            0 => MAX_DATAGRAMS_PER_NTB,
+            n => n.min(MAX_DATAGRAMS_PER_NTB),
+        };
+        self.divisor = (params.ndp_out_divisor as usize).max(1);
+        self.remainder =
+            params.ndp_out_payload_remainder as usize % self.divisor;
+        let alignment = (params.ndp_out_alignment as usize).max(4);
+        self.ndp_index = NTH16_LENGTH.div_ceil(alignment) * alignment;
+        self.count = 0;
+        self.end = self.ndp_index + ndp16_length(self.max_datagrams);
+    }
+
+    /// Where the next datagram would start
+    fn next_offset(&self) -> usize {
+        let offset = self.end - self.end % self.divisor + self.remainder;
+        if offset < self.end {
+            offset + self.divisor
+        } else {
+            offset
+        }
+    }
+
+    /// Would a datagram of `len` bytes fit in this NTB?
+    pub fn has_room(&self, len: usize) -> bool {
+        self.count < self.max_datagrams
+            && self.next_offset() + len <= self.capacity
+    }
+
+    /// Add a datagram of `len` bytes, returning the space to fill in
+    ///
+    /// Returns `None` if it doesn't fit: send the NTB so far, then
+    /// [`clear()`](Self::clear) it, and try again.
+    pub fn push(&mut self, len: usize) -> Option<&mut [u8]> {
+        if !self.has_room(len) {
+            return None;
+        }
+        let start = self.next_offset();
+        self.buf[self.end..start].fill(0);
+        let entry = self.ndp_index + 8 + 4 * self.count;
+        put_le16(self.buf, entry, start);
+        put_le16(self.buf, entry + 2, len);
+        self.count += 1;
+        self.end = start + len;
+        Some(&mut self.buf[start..self.end])
+    }
+
+    /// Space after the datagrams so far, which isn't part of the NTB
+    ///
+    /// Somewhere to put a datagram which is then to be dropped.
+    #[cfg(feature = "smoltcp")]
+    fn scratch(&mut self, len: usize) -> Option<&mut [u8]> {
+        self.buf.get_mut(self.end..self.end + len)
+    }
+
+    /// The number of datagrams in this NTB so far
+    pub fn datagrams(&self) -> usize {
+        self.count
+    }
+
+    /// Are there no datagrams in this NTB yet?
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Fill in the headers, returning the finished NTB
+    ///
+    /// The NTB is empty (zero bytes long) if no datagrams were added.
+    pub fn finish(&mut self) -> &[u8] {
+        if self.count == 0 {
+            return &[];
+        }
+        let ndp = self.ndp_index;
+        let ndp_length = ndp16_length(self.count);
+        self.buf[0..4].copy_from_slice(&NTH16_SIGNATURE);
+        put_le16(self.buf, 4, NTH16_LENGTH);
+        put_le16(self.buf, 6, self.sequence as usize);
+        put_le16(self.buf, 8, self.end);
+        put_le16(self.buf, 10, ndp);
+        self.buf[NTH16_LENGTH..ndp].fill(0);
+        self.buf[ndp..ndp + 4].copy_from_slice(&NDP16_SIGNATURE);
+        put_le16(self.buf, ndp + 4, ndp_length);
+        put_le16(self.buf, ndp + 6, 0);
+        // Terminator, and the rest of the reserved space
+        let reserved = ndp16_length(self.max_datagrams);
+        self.buf[ndp + ndp_length - 4..ndp + reserved].fill(0);
+        &self.buf[0..self.end]
+    }
+
+    /// Discard the datagrams, ready to start the next NTB
+    pub fn clear(&mut self) {
+        if self.count > 0 {
+            self.sequence = self.sequence.wrapping_add(1);
+        }
+        self.count = 0;
+        self.end = self.ndp_index + ndp16_length(self.max_datagrams);
+    }
+}
+
+/// Recognises CDC-NCM network adapters, see [`IdentifyFromDescriptors`]
+///
+/// An NCM function is a communications interface (class 2, subclass
+/// 0x0D) and a data interface (class 0x0A), whose bulk endpoints are
+/// in its alternate setting 1; the data interface is the one named by
+/// the communications interface's Union descriptor if it has one,
+/// otherwise the next one.
+#[derive(Default)]
+pub struct IdentifyCdcNcm {
+    current_configuration: Option<u8>,
+    ncm_configuration: Option<u8>,
+    in_control_interface: bool,
+    in_data_interface: bool,
+    control_interface: Option<u8>,
+    data_interface: Option<u8>,
+    data_alternate_setting: u8,
+    mac_address_string: u8,
+    max_segment_size: u16,
+    network_capabilities: u8,
+    bulk_in: Option<u8>,
+    bulk_out: Option<u8>,
+}
+
+impl IdentifyCdcNcm {
+    /// The interface number of the communications interface
+    pub fn control_interface(&self) -> Option<u8> {
+        self.control_interface
+    }
+
+    /// The interface number of the data interface
+    pub fn data_interface(&self) -> Option<u8> {
+        self.data_interface
+    }
+
+    /// The data interface's alternate setting with the bulk endpoints
+    pub fn data_alternate_setting(&self) -> u8 {
+        self.data_alternate_setting
+    }
+
+    /// Index of the string descriptor holding the MAC address (0 if none)
+    pub fn mac_address_string(&self) -> u8 {
+        self.mac_address_string
+    }
+
+    /// Largest Ethernet frame the device handles, including its header
+    pub fn max_segment_size(&self) -> u16 {
+        if self.max_segment_size == 0 {
+            DEFAULT_MAX_SEGMENT_SIZE
+        } else {
+            self.max_segment_size
+        }
+    }
+
+    /// The number of the data interface's bulk IN endpoint
+    pub fn bulk_in_endpoint(&self) -> Option<u8> {
+        self.bulk_in
+    }
+
+    /// The number of the data interface's bulk OUT endpoint
+    pub fn bulk_out_endpoint(&self) -> Option<u8> {
+        self.bulk_out
+    }
+}
+
+impl DescriptorVisitor for IdentifyCdcNcm {
+    fn on_configuration(&mut self, c: &ConfigurationDescriptor) {
+        self.current_configuration = Some(c.bConfigurationValue);
+        self.in_control_interface = false;
+        self.in_data_interface = false;
+    }
+
+    fn on_interface(&mut self, i: &InterfaceDescriptor) {
+        self.in_control_interface = false;
+        self.in_data_interface = false;
+        if self.ncm_configuration.is_some() {
+            return;
+        }
+        if i.bInterfaceClass == CDC_COMMUNICATIONS_CLASSCODE
+            && i.bInterfaceSubClass == NCM_SUBCLASS
+        {
+            self.in_control_interface = true;
+            self.control_interface = Some(i.bInterfaceNumber);
+            self.data_interface = None;
+            self.mac_address_string = 0;
+            self.max_segment_size = 0;
+            self.network_capabilities = 0;
+        } else if i.bInterfaceClass == CDC_DATA_CLASSCODE
+            && self.control_interface.is_some()
+            && self
+                .data_interface
+                .map_or(true, |d| d == i.bInterfaceNumber)
+        {
+            self.in_data_interface = true;
+            self.data_interface = Some(i.bInterfaceNumber);
+            self.data_alternate_setting = i.bAlternateSetting;
+            self.bulk_in = None;
+            self.bulk_out = None;
+        }
+    }
+
+    fn on_endpoint(&mut self, e: &EndpointDescriptor) {
+        if !self.in_data_interface || (e.bmAttributes & 3) != 2 {
+            return;
+        }
+        if (e.bEndpointAddress & 0x80) != 0 {
+            self.bulk_in.get_or_insert(e.bEndpointAddress & 15);
+        } else {
+            self.bulk_out.get_or_insert(e.bEndpointAddress & 15);
+        }
+        if self.bulk_in.is_some() && self.bulk_out.is_some() {
+            self.ncm_configuration = self.current_configuration;
+            self.in_data_interface = false;
+        }
+    }
+
+    fn on_other(&mut self, d: &[u8]) {
+        if !self.in_control_interface || d.len() < 3 || d[1] != CS_INTERFACE {
+            return;
+        }
+        match d[2] {
+            UNION_FUNCTIONAL if d.len() >= 5 => {
+                self.data_interface = Some(d[4]);
+            }
+            ETHERNET_FUNCTIONAL if d.len() >= 13 => {
+                self.mac_address_string = d[3];
+                self.max_segment_size = u16::from_le_bytes([d[8], d[9]]);
+            }
+            NCM_FUNCTIONAL if d.len() >= 6 => {
+                self.network_capabilities = d[5];
+            }
+            _ => {}
+        }
+    }
+}
+
+impl IdentifyFromDescriptors for IdentifyCdcNcm {
+    fn identify(&self) -> Option<u8> {
+        self.ncm_configuration
+    }
+}
+
+/// Decode a MAC address string descriptor ("0211223344AA", UTF-16LE)
+fn parse_mac_address(d: &[u8]) -> Option<[u8; 6]> {
+    if d.len() < 26 || d[1] != STRING_DESCRIPTOR {
+        return None;
+    }
+    let mut mac = [0u8; 6];
+    for i in 0..12 {
+        if d[3 + 2 * i] != 0 {
+            return None;
+        }
+        let nybble = (d[2 + 2 * i] as char).to_digit(16)? as u8;
+        mac[i / 2] = (mac[i / 2] << 4) | nybble;
+    }
+    Some(mac)
+}
+
+/// A driver for CDC-NCM (Network Control Model) USB network adapters
+///
+/// NCM is the protocol spoken by tethered phones, by LTE modems, and
+/// by Linux's USB network gadget. Unlike CDC-ECM, where each Ethernet
+/// frame is a USB transfer of its own, NCM packs several frames
+/// ("datagrams") into each transfer (an "NTB", NCM Transfer Block),
+/// which makes much better use of the bus.
+///
+/// Received NTBs are unpacked in place in the receive buffer, and
+/// transmitted datagrams are batched in the transmit buffer until
+/// [`flush()`](Self::flush) is called; with the `smoltcp` feature,
+/// the driver is a `smoltcp::phy::Device` over those buffers. Both
+/// buffers should be at least 2048 bytes long, the smallest NTB size
+/// which all NCM devices support.
+pub struct CdcNcm<'a, HC: HostController, const D: usize = 512> {
+    bus: &'a UsbBus<HC, D>,
+    device: UsbDevice,
+    control_interface: u8,
+    data_interface: u8,
+    data_alternate_setting: u8,
+    mac_address_string: u8,
+    max_segment_size: u16,
+    network_capabilities: u8,
+    bulk_in: BulkIn,
+    bulk_out: BulkOut,
+    mac_address: Option<[u8; 6]>,
+    rx: &'a mut [u8],
+    rx_size: usize,
+    rx_length: usize,
+    rx_cursor: NtbCursor,
+    tx: NtbBuilder<'a>,
+}
+
+impl<'a, HC: HostController, const D: usize> CdcNcm<'a, HC, D> {
+    /// Create a driver for a configured CDC-NCM device
+    ///
+    /// The interfaces and endpoints are those found by
+    /// [`IdentifyCdcNcm`]; returns `UsbError::NoSuchEndpoint` if it
+    /// didn't find them.
+    pub fn new(
+        bus: &'a UsbBus<HC, D>,
+        mut device: UsbDevice,
+        identify: &IdentifyCdcNcm,
+        rx_buffer: &'a mut [u8],
+        tx_buffer: &'a mut [u8],
+    ) -> Result<Self, UsbError> {
+        let (Some(control), Some(data), Some(bulk_in), Some(bulk_out)) = (
+            identify.control_interface(),
+            identify.data_interface(),
+            identify.bulk_in_endpoint(),
+            identify.bulk_out_endpoint(),
+        ) else {
+            return Err(UsbError::NoSuchEndpoint);
+        };
+        let bulk_in = device.open_in_endpoint(bulk_in)?;
+        let bulk_out = device.open_out_endpoint(bulk_out)?;
+        let params = NtbParameters::default();
+        let rx_size = rx_buffer.len().min(params.ntb_in_max_size as usize);
+        Ok(Self {
+            bus,
+            device,
+            control_interface: control,
+            data_interface: data,
+            data_alternate_setting: identify.data_alternate_setting(),
+            mac_address_string: identify.mac_address_string(),
+            max_segment_size: identify.max_segment_size(),
+            network_capabilities: identify.network_capabilities,
+            bulk_in,
+            bulk_out,
+            mac_address: None,
+            rx: rx_buffer,
+            rx_size,
+            rx_length: 0,
+            rx_cursor: NtbCursor::default(),
+            tx: NtbBuilder::new(tx_buffer, &params),
+        })
+    }
+
+    /// The underlying device
+    pub fn device(&self) -> &UsbDevice {
+        &self.device
+    }
+
+    /// The device's MAC address, if [`init()`](Self::init) could read it
+    ///
+    /// This is the address of the far end of the link (the phone, say):
+    /// the host's own interface needs a different one.
+    pub fn mac_address(&self) -> Option<[u8; 6]> {
+        self.mac_address
+    }
+
+    /// Largest Ethernet frame the device handles, including its header
+    pub fn max_segment_size(&self) -> u16 {
+        self.max_segment_size
+    }
+
+    /// Prepare the device for use
+    ///
+    /// Reads the device's NTB parameters (and asks it not to send NTBs
+    /// bigger than the receive buffer), reads its MAC address, and
+    /// then selects the data interface's alternate setting, which
+    /// starts the flow of datagrams.
+    pub async fn init(&mut self) -> Result<(), UsbError> {
+        let mut buf = [0u8; 28];
+        let n = self
+            .bus
+            .control_transfer(
+                &self.device,
+                SetupPacket {
+                    bmRequestType: DEVICE_TO_HOST
+                        | CLASS_REQUEST
+                        | RECIPIENT_INTERFACE,
+                    bRequest: GET_NTB_PARAMETERS,
+                    wValue: 0,
+                    wIndex: self.control_interface as u16,
+                    wLength: buf.len() as u16,
+                },
+                DataPhase::In(&mut buf),
+            )
+            .await?;
+        let params =
+            NtbParameters::parse(&buf[0..n]).ok_or(UsbError::ProtocolError)?;
+        if (params.ntb_formats_supported & 1) == 0 {
+            return Err(UsbError::ProtocolError);
+        }
+
+        self.rx_size = self.rx.len().min(params.ntb_in_max_size as usize);
+        if self.rx_size < params.ntb_in_max_size as usize {
+            // The 8-byte form also sets wNtbInMaxDatagrams (0, no limit)
+            let mut size = [0u8; 8];
+            size[0..4].copy_from_slice(&(self.rx_size as u32).to_le_bytes());
+            let len =
+                if (self.network_capabilities & NTB_INPUT_SIZE_8_BYTE) != 0 {
+                    8
+                } else {
+                    4
+                };
+            self.bus
+                .control_transfer(
+                    &self.device,
+                    SetupPacket {
+                        bmRequestType: HOST_TO_DEVICE
+                            | CLASS_REQUEST
+                            | RECIPIENT_INTERFACE,
+                        bRequest: SET_NTB_INPUT_SIZE,
+                        wValue: 0,
+                        wIndex: self.control_interface as u16,
+                        wLength: len as u16,
+                    },
+                    DataPhase::Out(&size[0..len]),
+                )
+                .await?;
+        }
+        self.tx.set_parameters(&params);
+
+        if self.mac_address_string != 0 {
+            let mut buf = [0u8; 26];
+            let n = self
+                .bus
+                .control_transfer(
+                    &self.device,
+                    SetupPacket {
+                        bmRequestType: DEVICE_TO_HOST
+                            | STANDARD_REQUEST
+                            | RECIPIENT_DEVICE,
+                        bRequest: GET_DESCRIPTOR,
+                        wValue: ((STRING_DESCRIPTOR as u16) << 8)
+                            | self.mac_address_string as u16,
+                        wIndex: 0x409, // US English
+                        wLength: buf.len() as u16,
+                    },
+                    DataPhase::In(&mut buf),
+                )
+                .await?;
+            self.mac_address = parse_mac_address(&buf[0..n]);
+        }
+
+        self.bus
+            .control_transfer(
+                &self.device,
+                SetupPacket {
+                    bmRequestType: HOST_TO_DEVICE
+                        | STANDARD_REQUEST
+                        | RECIPIENT_INTERFACE,
+                    bRequest: SET_INTERFACE,
+                    wValue: self.data_alternate_setting as u16,
+                    wIndex: self.data_interface as u16,
+                    wLength: 0,
+                },
+                DataPhase::None,
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Wait for the next NTB from the device
+    ///
+    /// Any datagrams not yet collected from the previous NTB are
+    /// discarded. Returns `UsbError::ProtocolError` if the NTB can't
+    /// be unpacked.
+    pub async fn read_ntb(&mut self) -> Result<(), UsbError> {
+        self.rx_cursor = NtbCursor::default();
+        self.rx_length = 0;
+        let n = self
+            .bus
+            .bulk_in_transfer(
+                &self.bulk_in,
+                &mut self.rx[0..self.rx_size],
+                TransferType::VariableSize,
+            )
+            .await?;
+        let datagrams =
+            parse_ntb(&self.rx[0..n]).map_err(|_| UsbError::ProtocolError)?;
+        self.rx_length = datagrams.ntb.len();
+        self.rx_cursor = datagrams.cursor;
+        Ok(())
+    }
+
+    /// The next datagram (Ethernet frame) from the latest NTB
+    ///
+    /// Returns `None` once they've all been collected: call
+    /// [`read_ntb()`](Self::read_ntb) for more.
+    pub fn next_datagram(&mut self) -> Option<&mut [u8]> {
+        let r = self.rx_cursor.next(&self.rx[0..self.rx_length])?;
+        Some(&mut self.rx[r])
+    }
+
+    /// Queue a datagram (Ethernet frame) for the next NTB
+    ///
+    /// Returns false if the NTB is full: call [`flush()`](Self::flush),
+    /// then try again.
+    pub fn queue_datagram(&mut self, datagram: &[u8]) -> bool {
+        match self.tx.push(datagram.len()) {
+            Some(buf) => {
+                buf.copy_from_slice(datagram);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Send any queued datagrams to the device, as one NTB
+    pub async fn flush(&mut self) -> Result<(), UsbError> {
+        if self.tx.is_empty() {
+            return Ok(());
+        }
+        let ntb = self.tx.finish();
+        let result = self
+            .bus
+            .bulk_out_transfer(&self.bulk_out, ntb, TransferType::VariableSize)
+            .await;
+        self.tx.clear();
+        result.map(|_| ())
+    }
+}
+
+/// Using CDC-NCM devices with smoltcp
+///
+/// Datagrams are received from the NTB most recently read by
+/// [`CdcNcm::read_ntb()`], and transmitted ones are batched until
+/// [`CdcNcm::flush()`]; so a typical event loop reads an NTB (or
+/// times out), polls the smoltcp interface, then flushes.
+#[cfg(feature = "smoltcp")]
+mod phy {
+    use super::{CdcNcm, NtbBuilder};
+    use crate::host_controller::HostController;
+
+    /// Passes a received datagram to smoltcp
+    pub struct NcmRxToken<'a> {
+        datagram: &'a mut [u8],
+    }
+
+    /// Adds a datagram from smoltcp to the NTB being built
+    pub struct NcmTxToken<'a, 'b> {
+        ntb: &'a mut NtbBuilder<'b>,
+    }
+
+    impl<'b, HC: HostController, const D: usize> smoltcp::phy::Device
+        for CdcNcm<'b, HC, D>
+    {
+        type RxToken<'token>
+            = NcmRxToken<'token>
+        where
+            Self: 'token;
+        type TxToken<'token>
+            = NcmTxToken<'token, 'b>
+        where
+            Self: 'token;
+
+        fn receive(
+            &mut self,
+            _timestamp: smoltcp::time::Instant,
+        ) -> Option<(Self::RxToken<'_>, Self::TxToken<'_>)> {
+            // smoltcp may reply straight away (to an ARP request, say)
+            if !self.tx.has_room(self.max_segment_size as usize) {
+                return None;
+            }
+            let r = self.rx_cursor.next(&self.rx[0..self.rx_length])?;
+            Some((
+                NcmRxToken {
+                    datagram: &mut self.rx[r],
+                },
+                NcmTxToken { ntb: &mut self.tx },
+            ))
+        }
+
+        fn transmit(
+            &mut self,
+            _timestamp: smoltcp::time::Instant,
+        ) -> Option<Self::TxToken<'_>> {
+            if self.tx.has_room(self.max_segment_size as usize) {
+                Some(NcmTxToken { ntb: &mut self.tx })
+            } else {
+                None
+            }
+        }
+
+        fn capabilities(&self) -> smoltcp::phy::DeviceCapabilities {
+            let mut caps = smoltcp::phy::DeviceCapabilities::default();
+            caps.max_transmission_unit = self.max_segment_size as usize;
+            caps.medium = smoltcp::phy::Medium::Ethernet;
+            caps
+        }
+    }
+
+    impl smoltcp::phy::RxToken for NcmRxToken<'_> {
+        fn consume<R, F>(self, f: F) -> R
+        where
+            F: FnOnce(&mut [u8]) -> R,
+        {
+            f(self.datagram)
+        }
+    }
+
+    impl smoltcp::phy::TxToken for NcmTxToken<'_, '_> {
+        fn consume<R, F>(self, len: usize, f: F) -> R
+        where
+            F: FnOnce(&mut [u8]) -> R,
+        {
+            // Tokens are only handed out when there's room for a frame
+            // of the MTU, which smoltcp never exceeds. If it ever did,
+            // the frame is built in any spare buffer after the NTB, and
+            // dropped -- unless it's too big even for that.
+            if self.ntb.has_room(len) {
+                return f(self.ntb.push(len).unwrap());
+            }
+            debug_assert!(false, "{len}-byte frame doesn't fit in NTB");
+            f(self
+                .ntb
+                .scratch(len)
+                .expect("smoltcp frame larger than the whole NTB buffer"))
+        }
+    }
+}
+
+#[cfg(feature = "smoltcp")]
+pub use phy::*;
+
+#[cfg(all(test, feature = "std"))]
+#[path = "../tests/cdc_ncm.rs"]
+mod tests;
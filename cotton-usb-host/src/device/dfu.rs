@@ -0,0 +1,601 @@
+use crate::delay::DelayProvider;
+use crate::device::identify::IdentifyFromDescriptors;
+use crate::host_controller::{DataPhase, HostController, UsbError};
+use crate::usb_bus::{UsbBus, UsbDevice};
+use crate::wire::{
+    ConfigurationDescriptor, DescriptorVisitor, InterfaceDescriptor,
+    SetupPacket, CLASS_REQUEST, DEVICE_TO_HOST, HOST_TO_DEVICE,
+    RECIPIENT_INTERFACE,
+};
+use core::pin::pin;
+use futures::{Stream, StreamExt};
+
+/// Interface class code for application-specific interfaces (DFU 1.1 s4.2.1)
+pub const APPLICATION_SPECIFIC_CLASSCODE: u8 = 0xFE;
+
+/// Interface subclass code for DFU (DFU 1.1 s4.2.1)
+pub const DFU_SUBCLASS: u8 = 1;
+
+/// Interface protocol code for a device in run-time mode (DFU 1.1 s4.2.1)
+pub const RUNTIME_PROTOCOL: u8 = 1;
+
+/// Interface protocol code for a device in DFU mode (DFU 1.1 s4.2.3)
+pub const DFU_MODE_PROTOCOL: u8 = 2;
+
+/// Descriptor type of the DFU functional descriptor (DFU 1.1 s4.1.3)
+pub const DFU_FUNCTIONAL_DESCRIPTOR: u8 = 0x21;
+
+// DFU class-specific requests (DFU 1.1 table 3.2)
+
+/// Ask a run-time mode device to enter DFU mode (DFU 1.1 s5.1)
+pub const DFU_DETACH: u8 = 0;
+
+/// Send a block of firmware (DFU 1.1 s6.1.1)
+pub const DFU_DNLOAD: u8 = 1;
+
+/// Read back a block of firmware (DFU 1.1 s6.2.1)
+pub const DFU_UPLOAD: u8 = 2;
+
+/// Request status (DFU 1.1 s6.1.2)
+pub const DFU_GETSTATUS: u8 = 3;
+
+/// Leave the dfuERROR state (DFU 1.1 s6.1.3)
+pub const DFU_CLRSTATUS: u8 = 4;
+
+/// Request state, without side-effects (DFU 1.1 s6.1.5)
+pub const DFU_GETSTATE: u8 = 5;
+
+/// Return to the dfuIDLE state (DFU 1.1 s6.1.4)
+pub const DFU_ABORT: u8 = 6;
+
+/// The states of the DFU state machine (DFU 1.1 s6.1.2 and figure A.1)
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone, PartialEq, Eq)]
+#[allow(missing_docs)]
+pub enum DfuState {
+    AppIdle = 0,
+    AppDetach = 1,
+    Idle = 2,
+    DownloadSync = 3,
+    DownloadBusy = 4,
+    DownloadIdle = 5,
+    ManifestSync = 6,
+    Manifest = 7,
+    ManifestWaitReset = 8,
+    UploadIdle = 9,
+    Error = 10,
+}
+
+impl DfuState {
+    fn from_u8(b: u8) -> Option<Self> {
+        Some(match b {
+            0 => Self::AppIdle,
+            1 => Self::AppDetach,
+            2 => Self::Idle,
+            3 => Self::DownloadSync,
+            4 => Self::DownloadBusy,
+            5 => Self::DownloadIdle,
+            6 => Self::ManifestSync,
+            7 => Self::Manifest,
+            8 => Self::ManifestWaitReset,
+            9 => Self::UploadIdle,
+            10 => Self::Error,
+            _ => return None,
+        })
+    }
+}
+
+/// Status codes reported by DFU_GETSTATUS (DFU 1.1 s6.1.2)
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum DfuStatusCode {
+    /// No error
+    Ok = 0,
+    /// File is not targeted for use by this device
+    Target = 1,
+    /// File is for this device but fails a vendor-specific check
+    File = 2,
+    /// Device is unable to write memory
+    Write = 3,
+    /// Memory erase function failed
+    Erase = 4,
+    /// Memory erase check failed
+    CheckErased = 5,
+    /// Program memory function failed
+    Prog = 6,
+    /// Programmed memory failed verification
+    Verify = 7,
+    /// Cannot program memory due to received address out of range
+    Address = 8,
+    /// Received zero-length DNLOAD, but the device thinks it's not done
+    NotDone = 9,
+    /// The device's firmware is corrupt, so it can't leave DFU mode
+    Firmware = 10,
+    /// A vendor-specific error (see `iString`)
+    Vendor = 11,
+    /// Device detected an unexpected USB reset
+    UsbReset = 12,
+    /// Device detected an unexpected power-on reset
+    PowerOnReset = 13,
+    /// Something went wrong, but the device doesn't know what
+    Unknown = 14,
+    /// Device stalled an unexpected request
+    StalledPacket = 15,
+}
+
+impl DfuStatusCode {
+    fn from_u8(b: u8) -> Option<Self> {
+        Some(match b {
+            0 => Self::Ok,
+            1 => Self::Target,
+            2 => Self::File,
+            3 => Self::Write,
+            4 => Self::Erase,
+            5 => Self::CheckErased,
+            6 => Self::Prog,
+            7 => Self::Verify,
+            8 => Self::Address,
+            9 => Self::NotDone,
+            10 => Self::Firmware,
+            11 => Self::Vendor,
+            12 => Self::UsbReset,
+            13 => Self::PowerOnReset,
+            14 => Self::Unknown,
+            15 => Self::StalledPacket,
+            _ => return None,
+        })
+    }
+}
+
+/// The response to DFU_GETSTATUS (DFU 1.1 s6.1.2)
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct DfuStatus {
+    /// The result of the most recent request
+    pub status: DfuStatusCode,
+    /// How long to wait before the next DFU_GETSTATUS
+    pub poll_timeout_ms: u32,
+    /// The state the device is now in
+    pub state: DfuState,
+    /// Index of a string descriptor describing the status, or zero
+    pub string_index: u8,
+}
+
+/// The DFU functional descriptor (DFU 1.1 s4.1.3 and table 4.2)
+///
+/// Found among the configuration descriptors, following the DFU
+/// interface descriptor. Fields are unpacked from the little-endian
+/// wire format.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct DfuFunctionalDescriptor {
+    /// DFU attributes (`bmAttributes`), see the accessor methods
+    pub attributes: u8,
+    /// How long the device waits for a reset after DFU_DETACH
+    pub detach_timeout_ms: u16,
+    /// Maximum number of bytes per DFU_DNLOAD or DFU_UPLOAD
+    pub transfer_size: u16,
+    /// DFU specification release number in BCD, e.g. 0x0110
+    ///
+    /// DFU 1.0 devices have a shorter descriptor without this field,
+    /// and are reported as 0x0100.
+    pub dfu_version: u16,
+}
+
+impl DfuFunctionalDescriptor {
+    /// Unpack a functional descriptor from its wire format
+    ///
+    /// Returns `None` if `d` isn't a DFU functional descriptor.
+    pub fn parse(d: &[u8]) -> Option<Self> {
+        if d.len() < 7 || d[0] < 7 || d[1] != DFU_FUNCTIONAL_DESCRIPTOR {
+            return None;
+        }
+        let dfu_version = if d.len() >= 9 && d[0] >= 9 {
+            u16::from_le_bytes([d[7], d[8]])
+        } else {
+            0x0100
+        };
+        Some(Self {
+            attributes: d[2],
+            detach_timeout_ms: u16::from_le_bytes([d[3], d[4]]),
+            transfer_size: u16::from_le_bytes([d[5], d[6]]),
+            dfu_version,
+        })
+    }
+
+    /// Does the device accept firmware downloads? (`bitCanDnload`)
+    pub fn can_download(&self) -> bool {
+        (self.attributes & 1) != 0
+    }
+
+    /// Can firmware be read back from the device? (`bitCanUpload`)
+    pub fn can_upload(&self) -> bool {
+        (self.attributes & 2) != 0
+    }
+
+    /// Does the device still talk to the host after manifestation?
+    /// (`bitManifestationTolerant`)
+    pub fn manifestation_tolerant(&self) -> bool {
+        (self.attributes & 4) != 0
+    }
+
+    /// Does the device detach itself after DFU_DETACH? (`bitWillDetach`)
+    ///
+    /// If not, the host must reset it to get it into DFU mode.
+    pub fn will_detach(&self) -> bool {
+        (self.attributes & 8) != 0
+    }
+}
+
+/// Errors from DFU operations
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum DfuError {
+    /// A USB transfer failed
+    Usb(UsbError),
+    /// The device reported an error, and is now in dfuERROR
+    Status(DfuStatusCode),
+    /// The device was in a state that doesn't allow the operation
+    ///
+    /// For instance, it's still in run-time mode (`AppIdle`) and
+    /// needs to be detached and reset first.
+    UnexpectedState(DfuState),
+    /// The device doesn't support downloads (`bitCanDnload` is clear)
+    DownloadNotSupported,
+}
+
+impl From<UsbError> for DfuError {
+    fn from(e: UsbError) -> Self {
+        Self::Usb(e)
+    }
+}
+
+/// Recognises devices with a DFU interface, see [`IdentifyFromDescriptors`]
+///
+/// Both run-time mode (DFU interface alongside the device's usual
+/// ones) and DFU mode are recognised; see [`IdentifyDfu::is_dfu_mode()`].
+#[derive(Default)]
+pub struct IdentifyDfu {
+    current_configuration: Option<u8>,
+    dfu_configuration: Option<u8>,
+    interface: u8,
+    protocol: u8,
+    in_dfu_interface: bool,
+    functional: Option<DfuFunctionalDescriptor>,
+}
+
+impl IdentifyDfu {
+    /// The interface number of the DFU interface
+    pub fn interface(&self) -> u8 {
+        self.interface
+    }
+
+    /// Is the device in DFU mode (rather than run-time mode)?
+    pub fn is_dfu_mode(&self) -> bool {
+        self.protocol == DFU_MODE_PROTOCOL
+    }
+
+    /// The DFU functional descriptor, if one was found
+    pub fn functional_descriptor(&self) -> Option<DfuFunctionalDescriptor> {
+        self.functional
+    }
+}
+
+impl DescriptorVisitor for IdentifyDfu {
+    fn on_configuration(&mut self, c: &ConfigurationDescriptor) {
+        self.current_configuration = Some(c.bConfigurationValue);
+        self.in_dfu_interface = false;
+    }
+
+    fn on_interface(&mut self, i: &InterfaceDescriptor) {
+        self.in_dfu_interface = self.dfu_configuration.is_none()
+            && i.bInterfaceClass == APPLICATION_SPECIFIC_CLASSCODE
+            && i.bInterfaceSubClass == DFU_SUBCLASS;
+        if self.in_dfu_interface {
+            self.interface = i.bInterfaceNumber;
+            self.protocol = i.bInterfaceProtocol;
+        }
+    }
+
+    fn on_other(&mut self, d: &[u8]) {
+        if self.in_dfu_interface {
+            if let Some(f) = DfuFunctionalDescriptor::parse(d) {
+                self.functional = Some(f);
+                self.dfu_configuration = self.current_configuration;
+                self.in_dfu_interface = false;
+            }
+        }
+    }
+}
+
+impl IdentifyFromDescriptors for IdentifyDfu {
+    fn identify(&self) -> Option<u8> {
+        self.dfu_configuration
+    }
+}
+
+/// A USB Device Firmware Upgrade (DFU 1.1) class driver
+///
+/// Takes a configured device with a DFU interface -- as identified by
+/// [`IdentifyDfu`] -- and downloads new firmware to it.
+///
+/// A device in run-time mode must first be sent [`Dfu::detach()`];
+/// it then re-enumerates (perhaps only after being reset, see
+/// [`DfuFunctionalDescriptor::will_detach()`]) in DFU mode, when it
+/// can be passed to a new `Dfu` for [`Dfu::flash_firmware()`].
+pub struct Dfu<'a, HC: HostController, const D: usize = 512> {
+    bus: &'a UsbBus<HC, D>,
+    device: UsbDevice,
+    interface: u8,
+    functional: DfuFunctionalDescriptor,
+}
+
+impl<'a, HC: HostController, const D: usize> Dfu<'a, HC, D> {
+    /// Create a DFU driver for a particular interface of a device
+    ///
+    /// The interface number and functional descriptor are available
+    /// from [`IdentifyDfu`].
+    pub fn new(
+        bus: &'a UsbBus<HC, D>,
+        device: UsbDevice,
+        interface: u8,
+        functional: DfuFunctionalDescriptor,
+    ) -> Self {
+        Self {
+            bus,
+            device,
+            interface,
+            functional,
+        }
+    }
+
+    /// The device's DFU functional descriptor
+    pub fn functional_descriptor(&self) -> &DfuFunctionalDescriptor {
+        &self.functional
+    }
+
+    async fn request(
+        &self,
+        direction: u8,
+        request: u8,
+        value: u16,
+        data: DataPhase<'_>,
+    ) -> Result<usize, UsbError> {
+        let length = data.len() as u16;
+        self.bus
+            .control_transfer(
+                &self.device,
+                SetupPacket {
+                    bmRequestType: direction
+                        | CLASS_REQUEST
+                        | RECIPIENT_INTERFACE,
+                    bRequest: request,
+                    wValue: value,
+                    wIndex: self.interface as u16,
+                    wLength: length,
+                },
+                data,
+            )
+            .await
+    }
+
+    /// Ask a run-time mode device to switch to DFU mode
+    ///
+    /// If the device doesn't detach itself (see
+    /// [`DfuFunctionalDescriptor::will_detach()`]), the caller must
+    /// reset it within the detach timeout.
+    pub async fn detach(&self) -> Result<(), DfuError> {
+        self.request(
+            HOST_TO_DEVICE,
+            DFU_DETACH,
+            self.functional.detach_timeout_ms,
+            DataPhase::None,
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Read the device's status (DFU_GETSTATUS)
+    ///
+    /// Note that, in some states, this moves the device on to the
+    /// next state.
+    pub async fn get_status(&self) -> Result<DfuStatus, DfuError> {
+        let mut buf = [0u8; 6];
+        let n = self
+            .request(DEVICE_TO_HOST, DFU_GETSTATUS, 0, DataPhase::In(&mut buf))
+            .await?;
+        if n < 6 {
+            return Err(UsbError::ProtocolError.into());
+        }
+        match (DfuStatusCode::from_u8(buf[0]), DfuState::from_u8(buf[4])) {
+            (Some(status), Some(state)) => Ok(DfuStatus {
+                status,
+                poll_timeout_ms: u32::from_le_bytes([
+                    buf[1], buf[2], buf[3], 0,
+                ]),
+                state,
+                string_index: buf[5],
+            }),
+            _ => Err(UsbError::ProtocolError.into()),
+        }
+    }
+
+    /// Read the device's state, without side-effects (DFU_GETSTATE)
+    pub async fn get_state(&self) -> Result<DfuState, DfuError> {
+        let mut buf = [0u8; 1];
+        let n = self
+            .request(DEVICE_TO_HOST, DFU_GETSTATE, 0, DataPhase::In(&mut buf))
+            .await?;
+        match DfuState::from_u8(buf[0]) {
+            Some(state) if n == 1 => Ok(state),
+            _ => Err(UsbError::ProtocolError.into()),
+        }
+    }
+
+    /// Move the device from dfuERROR to dfuIDLE (DFU_CLRSTATUS)
+    pub async fn clear_status(&self) -> Result<(), DfuError> {
+        self.request(HOST_TO_DEVICE, DFU_CLRSTATUS, 0, DataPhase::None)
+            .await?;
+        Ok(())
+    }
+
+    /// Abandon any download or upload, returning to dfuIDLE (DFU_ABORT)
+    pub async fn abort(&self) -> Result<(), DfuError> {
+        self.request(HOST_TO_DEVICE, DFU_ABORT, 0, DataPhase::None)
+            .await?;
+        Ok(())
+    }
+
+    /// Send one block of firmware (DFU_DNLOAD)
+    ///
+    /// The block must be no larger than the transfer size in the
+    /// functional descriptor. A zero-length block marks the end of
+    /// the firmware. Most callers will want [`Dfu::flash_firmware()`]
+    /// instead.
+    pub async fn download_block(
+        &self,
+        block_number: u16,
+        data: &[u8],
+    ) -> Result<(), DfuError> {
+        self.request(
+            HOST_TO_DEVICE,
+            DFU_DNLOAD,
+            block_number,
+            DataPhase::Out(data),
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Poll status until the device leaves any of the `waiting` states
+    async fn wait_while<P: DelayProvider>(
+        &self,
+        waiting: &[DfuState],
+        delay: &P,
+    ) -> Result<DfuState, DfuError> {
+        loop {
+            let status = self.get_status().await?;
+            if status.status != DfuStatusCode::Ok {
+                return Err(DfuError::Status(status.status));
+            }
+            if !waiting.contains(&status.state) {
+                return Ok(status.state);
+            }
+            delay.delay_ms(status.poll_timeout_ms as usize).await;
+        }
+    }
+
+    /// Get a DFU-mode device into dfuIDLE, from wherever it's got to
+    async fn make_idle(&self) -> Result<(), DfuError> {
+        let status = self.get_status().await?;
+        match status.state {
+            DfuState::Idle => return Ok(()),
+            DfuState::Error => self.clear_status().await?,
+            DfuState::DownloadSync
+            | DfuState::DownloadIdle
+            | DfuState::ManifestSync
+            | DfuState::UploadIdle => self.abort().await?,
+            s => return Err(DfuError::UnexpectedState(s)),
+        }
+        match self.get_state().await? {
+            DfuState::Idle => Ok(()),
+            s => Err(DfuError::UnexpectedState(s)),
+        }
+    }
+
+    /// Download a complete firmware image to a DFU-mode device
+    ///
+    /// The image is taken from the `firmware` stream, in chunks of
+    /// any size, and sent in blocks of the transfer size from the
+    /// functional descriptor (or of the size of `buffer`, if that's
+    /// smaller). After each block, and after the final zero-length
+    /// block, the device's status is polled, waiting (using
+    /// `delay`) as long as the device asks between polls.
+    ///
+    /// Returns, with the number of bytes downloaded, once the device
+    /// has manifested the new firmware. Unless the device is
+    /// manifestation-tolerant, it must then be reset before it
+    /// starts running the new firmware.
+    pub async fn flash_firmware<S, B, P>(
+        &self,
+        firmware: S,
+        buffer: &mut [u8],
+        delay: P,
+    ) -> Result<usize, DfuError>
+    where
+        S: Stream<Item = B>,
+        B: AsRef<[u8]>,
+        P: DelayProvider,
+    {
+        if !self.functional.can_download() {
+            return Err(DfuError::DownloadNotSupported);
+        }
+        let block_size =
+            buffer.len().min(self.functional.transfer_size as usize);
+        if block_size == 0 {
+            return Err(UsbError::BufferTooSmall.into());
+        }
+
+        self.make_idle().await?;
+
+        let mut firmware = pin!(firmware);
+        let mut block_number = 0u16;
+        let mut filled = 0;
+        let mut total = 0;
+        loop {
+            let chunk = firmware.next().await;
+            let mut bytes = chunk.as_ref().map_or(&[][..], |c| c.as_ref());
+            while !bytes.is_empty() || (chunk.is_none() && filled > 0) {
+                let n = (block_size - filled).min(bytes.len());
+                buffer[filled..(filled + n)].copy_from_slice(&bytes[..n]);
+                filled += n;
+                bytes = &bytes[n..];
+                if filled == block_size || chunk.is_none() {
+                    self.download_block(block_number, &buffer[..filled])
+                        .await?;
+                    match self
+                        .wait_while(
+                            &[DfuState::DownloadSync, DfuState::DownloadBusy],
+                            &delay,
+                        )
+                        .await?
+                    {
+                        DfuState::DownloadIdle => {}
+                        s => return Err(DfuError::UnexpectedState(s)),
+                    }
+                    block_number = block_number.wrapping_add(1);
+                    total += filled;
+                    filled = 0;
+                }
+            }
+            if chunk.is_none() {
+                break;
+            }
+        }
+
+        // A zero-length download starts manifestation (DFU 1.1 s7.2)
+        self.download_block(block_number, &[]).await?;
+        let waiting: &[DfuState] = if self.functional.manifestation_tolerant()
+        {
+            &[DfuState::ManifestSync, DfuState::Manifest]
+        } else {
+            // A non-tolerant device may not answer in dfuMANIFEST
+            &[DfuState::ManifestSync]
+        };
+        match self.wait_while(waiting, &delay).await? {
+            DfuState::Idle
+            | DfuState::Manifest
+            | DfuState::ManifestWaitReset => Ok(total),
+            s => Err(DfuError::UnexpectedState(s)),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+#[path = "../tests/dfu.rs"]
+mod tests;
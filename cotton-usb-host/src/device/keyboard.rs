@@ -0,0 +1,620 @@
+use crate::delay::DelayProvider;
+use crate::device::identify::IdentifyFromDescriptors;
+use crate::host_controller::{DataPhase, HostController, UsbError};
+use crate::usb_bus::{UsbBus, UsbDevice};
+use crate::wire::{
+    ConfigurationDescriptor, DescriptorVisitor, EndpointDescriptor,
+    InterfaceDescriptor, SetupPacket, CLASS_REQUEST, HOST_TO_DEVICE,
+    RECIPIENT_INTERFACE,
+};
+use futures::{Stream, StreamExt};
+
+/// Interface class code of HID (Human Interface Device) interfaces
+pub const HID_CLASSCODE: u8 = 0x03;
+
+/// Interface subclass code of HID interfaces supporting the boot protocol
+pub const BOOT_INTERFACE_SUBCLASS: u8 = 0x01;
+
+/// Interface protocol code of boot-protocol keyboards
+pub const KEYBOARD_PROTOCOL: u8 = 0x01;
+
+// HID class requests (HID 1.11 section 7.2)
+const SET_REPORT: u8 = 0x09;
+const SET_IDLE: u8 = 0x0A;
+const SET_PROTOCOL: u8 = 0x0B;
+
+// Report type of output reports, in the high byte of SET_REPORT's wValue
+const OUTPUT_REPORT: u16 = 0x0200;
+
+// Usage ID which fills a report when too many keys are pressed at once
+const ERROR_ROLL_OVER: u8 = 0x01;
+
+const CAPS_LOCK_KEY: u8 = 0x39;
+const SCROLL_LOCK_KEY: u8 = 0x47;
+const NUM_LOCK_KEY: u8 = 0x53;
+const NON_US_BACKSLASH_KEY: u8 = 0x64;
+
+/// The modifier keys reported in [`BootReport::modifiers`], as bit masks
+pub mod modifiers {
+    /// Left Control
+    pub const LEFT_CTRL: u8 = 0x01;
+    /// Left Shift
+    pub const LEFT_SHIFT: u8 = 0x02;
+    /// Left Alt
+    pub const LEFT_ALT: u8 = 0x04;
+    /// Left GUI (Windows, Command, or Meta)
+    pub const LEFT_GUI: u8 = 0x08;
+    /// Right Control
+    pub const RIGHT_CTRL: u8 = 0x10;
+    /// Right Shift
+    pub const RIGHT_SHIFT: u8 = 0x20;
+    /// Right Alt (AltGr)
+    pub const RIGHT_ALT: u8 = 0x40;
+    /// Right GUI (Windows, Command, or Meta)
+    pub const RIGHT_GUI: u8 = 0x80;
+    /// Either Control key
+    pub const CTRL: u8 = LEFT_CTRL | RIGHT_CTRL;
+    /// Either Shift key
+    pub const SHIFT: u8 = LEFT_SHIFT | RIGHT_SHIFT;
+}
+
+/// The keyboard LEDs set by [`BootKeyboard::set_leds()`], as bit masks
+pub mod leds {
+    /// Num Lock
+    pub const NUM_LOCK: u8 = 0x01;
+    /// Caps Lock
+    pub const CAPS_LOCK: u8 = 0x02;
+    /// Scroll Lock
+    pub const SCROLL_LOCK: u8 = 0x04;
+}
+
+/// A boot-protocol keyboard input report (HID 1.11 appendix B.1)
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone, Default, PartialEq, Eq)]
+pub struct BootReport {
+    /// Modifier keys currently pressed, see [`modifiers`]
+    pub modifiers: u8,
+    /// Usage IDs of (up to six) other keys currently pressed; unused
+    /// entries are zero
+    pub keys: [u8; 6],
+}
+
+impl BootReport {
+    /// Decode an input report
+    ///
+    /// Returns `None` if the report is too short, or if it's a
+    /// "phantom" report, which keyboards send when more keys are
+    /// pressed than they can report.
+    pub fn parse(report: &[u8]) -> Option<Self> {
+        if report.len() < 8 || report[2] == ERROR_ROLL_OVER {
+            return None;
+        }
+        let mut keys = [0u8; 6];
+        keys.copy_from_slice(&report[2..8]);
+        Some(Self {
+            modifiers: report[0],
+            keys,
+        })
+    }
+
+    /// Is the key with this usage ID pressed?
+    ///
+    /// Modifier keys don't appear in [`BootReport::keys`], so must be
+    /// checked for in [`BootReport::modifiers`] instead.
+    pub fn pressed(&self, usage: u8) -> bool {
+        usage != 0 && self.keys.contains(&usage)
+    }
+}
+
+/// A keyboard layout, for translating usage IDs into characters
+///
+/// Layouts other than the built-in [`Keymap::US`] and [`Keymap::UK`]
+/// can be made by filling in the tables: `unshifted` and `shifted`
+/// hold the characters produced by each of the 53 keys with usage IDs
+/// from 0x04 ("a") to 0x38 ("/"), in usage-ID order, without and with
+/// Shift pressed.
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct Keymap {
+    /// Characters produced by usage IDs 0x04 to 0x38 without Shift
+    pub unshifted: &'static str,
+    /// Characters produced by usage IDs 0x04 to 0x38 with Shift
+    pub shifted: &'static str,
+    /// Characters produced by the extra key (usage ID 0x64) next to
+    /// the left Shift key on ISO keyboards, without and with Shift
+    pub non_us_backslash: (char, char),
+}
+
+impl Keymap {
+    /// The United States layout
+    pub const US: Keymap = Keymap {
+        unshifted: "abcdefghijklmnopqrstuvwxyz1234567890\n\x1b\x08\t \
+                    -=[]\\\\;'`,./",
+        shifted: "ABCDEFGHIJKLMNOPQRSTUVWXYZ!@#$%^&*()\n\x1b\x08\t \
+                  _+{}||:\"~<>?",
+        non_us_backslash: ('\\', '|'),
+    };
+
+    /// The United Kingdom layout
+    pub const UK: Keymap = Keymap {
+        unshifted: "abcdefghijklmnopqrstuvwxyz1234567890\n\x1b\x08\t \
+                    -=[]\\#;'`,./",
+        shifted: "ABCDEFGHIJKLMNOPQRSTUVWXYZ!\"£$%^&*()\n\x1b\x08\t \
+                  _+{}|~:@¬<>?",
+        non_us_backslash: ('\\', '|'),
+    };
+
+    /// The character, if any, produced by a key
+    ///
+    /// Caps Lock affects only letters; Num Lock selects between
+    /// digits and (non-character) cursor keys on the numeric keypad.
+    /// With Control pressed, letters produce ASCII control characters
+    /// and other keys produce nothing. Alt and GUI are ignored, so
+    /// applications wanting to treat them as shortcuts should check
+    /// [`KeyEvent::modifiers`].
+    pub fn character(
+        &self,
+        usage: u8,
+        modifiers: u8,
+        leds: u8,
+    ) -> Option<char> {
+        let shift = (modifiers & modifiers::SHIFT) != 0;
+        let ctrl = (modifiers & modifiers::CTRL) != 0;
+        let c = match usage {
+            0x04..=0x1D => {
+                let caps = (leds & leds::CAPS_LOCK) != 0;
+                let c = self.lookup(usage, shift != caps)?;
+                if ctrl {
+                    return c
+                        .is_ascii_alphabetic()
+                        .then(|| char::from((c as u8) & 0x1F));
+                }
+                c
+            }
+            0x1E..=0x38 => self.lookup(usage, shift)?,
+            NON_US_BACKSLASH_KEY => {
+                if shift {
+                    self.non_us_backslash.1
+                } else {
+                    self.non_us_backslash.0
+                }
+            }
+            0x54..=0x58 => char::from(b"/*-+\n"[(usage - 0x54) as usize]),
+            0x59..=0x63 if (leds & leds::NUM_LOCK) != 0 => {
+                char::from(b"1234567890."[(usage - 0x59) as usize])
+            }
+            _ => return None,
+        };
+        if ctrl {
+            None
+        } else {
+            Some(c)
+        }
+    }
+
+    fn lookup(&self, usage: u8, shift: bool) -> Option<char> {
+        let table = if shift { self.shifted } else { self.unshifted };
+        table.chars().nth((usage - 0x04) as usize)
+    }
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Self::US
+    }
+}
+
+/// Key-repeat timing, see [`KeyboardState::new()`]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct KeyRepeat {
+    /// How long a key must be held down before it starts repeating
+    pub delay_ms: u32,
+    /// The interval between repeats, once started
+    pub interval_ms: u32,
+}
+
+impl Default for KeyRepeat {
+    fn default() -> Self {
+        Self {
+            delay_ms: 500,
+            interval_ms: 33,
+        }
+    }
+}
+
+/// What happened to a key, see [`KeyEvent`]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum KeyEventKind {
+    /// The key was pressed
+    Press,
+    /// The key has been held down long enough to repeat
+    Repeat,
+    /// The key was released
+    Release,
+}
+
+/// A key press, repeat, or release, with its translation
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct KeyEvent {
+    /// The key's usage ID (HID usage tables, section 10)
+    pub usage: u8,
+    /// What happened
+    pub kind: KeyEventKind,
+    /// Modifier keys pressed at the time, see [`modifiers`]
+    pub modifiers: u8,
+    /// The character produced, if any (always `None` for releases)
+    pub character: Option<char>,
+}
+
+const MAX_EVENTS: usize = 12;
+
+/// The events arising from one report or timer tick
+///
+/// A report can release six keys and press six others, so there are at
+/// most twelve.
+#[derive(Default)]
+pub struct KeyEvents {
+    events: [Option<KeyEvent>; MAX_EVENTS],
+    len: usize,
+    next: usize,
+}
+
+impl KeyEvents {
+    fn push(&mut self, event: KeyEvent) {
+        if self.len < MAX_EVENTS {
+            self.events[self.len] = Some(event);
+            self.len += 1;
+        }
+    }
+}
+
+impl Iterator for KeyEvents {
+    type Item = KeyEvent;
+
+    fn next(&mut self) -> Option<KeyEvent> {
+        if self.next < self.len {
+            self.next += 1;
+            self.events[self.next - 1].take()
+        } else {
+            None
+        }
+    }
+}
+
+/// Turns a keyboard's reports into key events
+///
+/// This tracks which keys are held down, the state of the lock keys
+/// (and so of their LEDs), and which key, if any, is repeating. It
+/// doesn't itself do any I/O, so can be used with reports obtained by
+/// other means than [`BootKeyboard`]; [`BootKeyboard::events()`] does
+/// the I/O for it.
+pub struct KeyboardState {
+    keymap: Keymap,
+    repeat: Option<KeyRepeat>,
+    report: BootReport,
+    leds: u8,
+    repeating: Option<u8>,
+    held_ms: u32,
+    repeated: bool,
+}
+
+impl KeyboardState {
+    /// Create a new `KeyboardState` with the given layout
+    ///
+    /// Pass `None` for `repeat` to disable key repeat altogether.
+    pub fn new(keymap: Keymap, repeat: Option<KeyRepeat>) -> Self {
+        Self {
+            keymap,
+            repeat,
+            report: BootReport::default(),
+            leds: 0,
+            repeating: None,
+            held_ms: 0,
+            repeated: false,
+        }
+    }
+
+    /// Change the layout
+    pub fn set_keymap(&mut self, keymap: Keymap) {
+        self.keymap = keymap;
+    }
+
+    /// Modifier keys currently pressed, see [`modifiers`]
+    pub fn modifiers(&self) -> u8 {
+        self.report.modifiers
+    }
+
+    /// Lock keys currently in effect (and so LEDs lit), see [`leds`]
+    pub fn leds(&self) -> u8 {
+        self.leds
+    }
+
+    /// Override the lock-key state, for instance to start with Num
+    /// Lock on
+    pub fn set_leds(&mut self, leds: u8) {
+        self.leds = leds;
+    }
+
+    /// The usage ID of the key currently repeating (or waiting to)
+    pub fn repeating(&self) -> Option<u8> {
+        self.repeating
+    }
+
+    fn event(&self, usage: u8, kind: KeyEventKind) -> KeyEvent {
+        let modifiers = self.report.modifiers;
+        KeyEvent {
+            usage,
+            kind,
+            modifiers,
+            character: match kind {
+                KeyEventKind::Release => None,
+                _ => self.keymap.character(usage, modifiers, self.leds),
+            },
+        }
+    }
+
+    /// Process a new report, returning the resulting events
+    ///
+    /// Releases are reported before presses. Pressing a lock key
+    /// toggles the corresponding bit of [`KeyboardState::leds()`].
+    pub fn update(&mut self, report: &BootReport) -> KeyEvents {
+        let previous = core::mem::replace(&mut self.report, *report);
+        let mut events = KeyEvents::default();
+        for usage in previous.keys {
+            if usage > ERROR_ROLL_OVER && !report.pressed(usage) {
+                events.push(self.event(usage, KeyEventKind::Release));
+                if self.repeating == Some(usage) {
+                    self.repeating = None;
+                }
+            }
+        }
+        for usage in report.keys {
+            if usage > ERROR_ROLL_OVER && !previous.pressed(usage) {
+                let lock = match usage {
+                    CAPS_LOCK_KEY => leds::CAPS_LOCK,
+                    NUM_LOCK_KEY => leds::NUM_LOCK,
+                    SCROLL_LOCK_KEY => leds::SCROLL_LOCK,
+                    _ => 0,
+                };
+                self.leds ^= lock;
+                events.push(self.event(usage, KeyEventKind::Press));
+                if lock == 0 && self.repeat.is_some() {
+                    self.repeating = Some(usage);
+                    self.held_ms = 0;
+                    self.repeated = false;
+                }
+            }
+        }
+        events
+    }
+
+    /// Note the passage of time, returning any resulting repeat
+    ///
+    /// Call this regularly (at least as often as
+    /// [`KeyRepeat::interval_ms`]) with the time since the last call.
+    pub fn tick(&mut self, elapsed_ms: u32) -> KeyEvents {
+        let mut events = KeyEvents::default();
+        if let (Some(usage), Some(repeat)) = (self.repeating, self.repeat) {
+            self.held_ms = self.held_ms.saturating_add(elapsed_ms);
+            let threshold = if self.repeated {
+                repeat.interval_ms
+            } else {
+                repeat.delay_ms
+            };
+            if self.held_ms >= threshold {
+                self.held_ms -= threshold;
+                self.repeated = true;
+                events.push(self.event(usage, KeyEventKind::Repeat));
+            }
+        }
+        events
+    }
+}
+
+/// Recognises boot-protocol keyboards, see [`IdentifyFromDescriptors`]
+#[derive(Default)]
+pub struct IdentifyKeyboard {
+    current_configuration: Option<u8>,
+    keyboard_configuration: Option<u8>,
+    in_keyboard_interface: bool,
+    interface: u8,
+    in_endpoint: Option<EndpointDescriptor>,
+}
+
+impl IdentifyKeyboard {
+    /// The interface number of the keyboard interface
+    pub fn interface(&self) -> u8 {
+        self.interface
+    }
+
+    /// The keyboard interface's interrupt IN endpoint
+    pub fn in_endpoint(&self) -> Option<&EndpointDescriptor> {
+        self.in_endpoint.as_ref()
+    }
+}
+
+impl DescriptorVisitor for IdentifyKeyboard {
+    fn on_configuration(&mut self, c: &ConfigurationDescriptor) {
+        self.current_configuration = Some(c.bConfigurationValue);
+        self.in_keyboard_interface = false;
+    }
+
+    fn on_interface(&mut self, i: &InterfaceDescriptor) {
+        self.in_keyboard_interface = self.keyboard_configuration.is_none()
+            && i.bInterfaceClass == HID_CLASSCODE
+            && i.bInterfaceSubClass == BOOT_INTERFACE_SUBCLASS
+            && i.bInterfaceProtocol == KEYBOARD_PROTOCOL;
+        if self.in_keyboard_interface {
+            self.interface = i.bInterfaceNumber;
+            self.in_endpoint = None;
+        }
+    }
+
+    fn on_endpoint(&mut self, e: &EndpointDescriptor) {
+        if self.in_keyboard_interface
+            && (e.bmAttributes & 3) == 3
+            && (e.bEndpointAddress & 0x80) != 0
+        {
+            self.in_endpoint = Some(*e);
+            self.keyboard_configuration = self.current_configuration;
+            self.in_keyboard_interface = false;
+        }
+    }
+}
+
+impl IdentifyFromDescriptors for IdentifyKeyboard {
+    fn identify(&self) -> Option<u8> {
+        self.keyboard_configuration
+    }
+}
+
+/// A driver for USB keyboards, using the HID boot protocol
+///
+/// Almost all USB keyboards support the boot protocol, a fixed report
+/// format intended for use by PC BIOSes; that saves parsing HID report
+/// descriptors. Raw reports are available from
+/// [`BootKeyboard::reports()`], but most applications will want
+/// [`BootKeyboard::events()`], which translates them into characters
+/// and handles the lock-key LEDs and key repeat.
+pub struct BootKeyboard<'a, HC: HostController, const D: usize = 512> {
+    bus: &'a UsbBus<HC, D>,
+    device: UsbDevice,
+    interface: u8,
+    in_endpoint: EndpointDescriptor,
+}
+
+impl<'a, HC: HostController, const D: usize> BootKeyboard<'a, HC, D> {
+    /// Create a driver for a configured keyboard
+    ///
+    /// The interface and endpoint are those found by
+    /// [`IdentifyKeyboard`]; returns `UsbError::NoSuchEndpoint` if it
+    /// didn't find them.
+    pub fn new(
+        bus: &'a UsbBus<HC, D>,
+        device: UsbDevice,
+        identify: &IdentifyKeyboard,
+    ) -> Result<Self, UsbError> {
+        let Some(in_endpoint) = identify.in_endpoint() else {
+            return Err(UsbError::NoSuchEndpoint);
+        };
+        Ok(Self {
+            bus,
+            device,
+            interface: identify.interface(),
+            in_endpoint: *in_endpoint,
+        })
+    }
+
+    /// The underlying device
+    pub fn device(&self) -> &UsbDevice {
+        &self.device
+    }
+
+    async fn request(
+        &self,
+        request: u8,
+        value: u16,
+        data: &[u8],
+    ) -> Result<(), UsbError> {
+        self.bus
+            .control_transfer(
+                &self.device,
+                SetupPacket {
+                    bmRequestType: HOST_TO_DEVICE
+                        | CLASS_REQUEST
+                        | RECIPIENT_INTERFACE,
+                    bRequest: request,
+                    wValue: value,
+                    wIndex: self.interface as u16,
+                    wLength: data.len() as u16,
+                },
+                if data.is_empty() {
+                    DataPhase::None
+                } else {
+                    DataPhase::Out(data)
+                },
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Prepare the keyboard for use
+    ///
+    /// This selects the boot protocol (keyboards start in the report
+    /// protocol, which needn't use the boot report format) and asks
+    /// for reports only when something changes.
+    pub async fn init(&self) -> Result<(), UsbError> {
+        self.request(SET_PROTOCOL, 0, &[]).await?;
+        self.request(SET_IDLE, 0, &[]).await
+    }
+
+    /// Light the keyboard's LEDs, see [`leds`]
+    pub async fn set_leds(&self, leds: u8) -> Result<(), UsbError> {
+        self.request(SET_REPORT, OUTPUT_REPORT, &[leds]).await
+    }
+
+    /// The keyboard's reports, each time a key is pressed or released
+    pub fn reports(&self) -> impl Stream<Item = BootReport> + '_ {
+        let e = &self.in_endpoint;
+        self.bus
+            .interrupt_endpoint_in(
+                self.device.address(),
+                e.bEndpointAddress & 15,
+                e.max_packet_size(),
+                e.bInterval,
+            )
+            .filter_map(|packet| {
+                core::future::ready(BootReport::parse(
+                    &packet.data[0..packet.size as usize],
+                ))
+            })
+    }
+
+    /// Key events, translated into characters by `state`
+    ///
+    /// When a lock key changes the state of the LEDs, they're updated
+    /// on the keyboard (errors doing so are ignored, as not all
+    /// keyboards have LEDs). Key repeat is timed using `delay`, in
+    /// steps of the repeat interval; the stream doesn't finish while
+    /// repeat is enabled.
+    pub fn events<'b>(
+        &'b self,
+        mut state: KeyboardState,
+        delay: impl DelayProvider + 'b,
+    ) -> impl Stream<Item = KeyEvent> + 'b {
+        let interval_ms = state.repeat.map(|r| r.interval_ms.max(1));
+        // `None` items are timer ticks
+        let ticks = futures::stream::unfold(delay, move |delay| async move {
+            delay.delay_ms(interval_ms? as usize).await;
+            Some((None, delay))
+        });
+        futures::stream::select(self.reports().map(Some), ticks)
+            .map(move |report| match report {
+                Some(report) => {
+                    let leds = state.leds();
+                    let events = state.update(&report);
+                    let changed = (state.leds() != leds).then(|| state.leds());
+                    (events, changed)
+                }
+                None => (state.tick(interval_ms.unwrap_or(0)), None),
+            })
+            .then(move |(events, leds)| async move {
+                if let Some(leds) = leds {
+                    let _ = self.set_leds(leds).await;
+                }
+                futures::stream::iter(events)
+            })
+            .flatten()
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+#[path = "../tests/keyboard.rs"]
+mod tests;
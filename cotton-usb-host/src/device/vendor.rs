@@ -0,0 +1,202 @@
+use crate::host_controller::{DataPhase, HostController, UsbError};
+use crate::usb_bus::{UsbBus, UsbDevice};
+use crate::wire::{
+    SetupPacket, DEVICE_TO_HOST, HOST_TO_DEVICE, RECIPIENT_DEVICE,
+    VENDOR_REQUEST,
+};
+
+/// The largest register that [`VendorDevice`] can read or write
+///
+/// This is the largest control-endpoint packet size on full-speed
+/// devices, and the size of the buffer kept on the stack.
+pub const MAX_REGISTER_SIZE: usize = 64;
+
+/// The byte order in which a device transfers multi-byte registers
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum Endian {
+    /// Least-significant byte first (as used by USB itself)
+    Little,
+    /// Most-significant byte first
+    Big,
+}
+
+/// A type which can be read from, or written to, a device register
+///
+/// Implemented for the primitive integer types, and for byte arrays
+/// (for which byte order makes no difference).
+pub trait Register: Sized {
+    /// The size of the register on the wire, in bytes
+    const SIZE: usize;
+
+    /// Decode a register value from exactly `SIZE` bytes
+    fn from_bytes(bytes: &[u8], endian: Endian) -> Self;
+
+    /// Encode a register value into exactly `SIZE` bytes
+    fn to_bytes(&self, bytes: &mut [u8], endian: Endian);
+}
+
+macro_rules! integer_register {
+    ($($t:ty),*) => {
+        $(
+            impl Register for $t {
+                const SIZE: usize = core::mem::size_of::<$t>();
+
+                fn from_bytes(bytes: &[u8], endian: Endian) -> Self {
+                    let mut b = [0u8; core::mem::size_of::<$t>()];
+                    b.copy_from_slice(bytes);
+                    match endian {
+                        Endian::Little => <$t>::from_le_bytes(b),
+                        Endian::Big => <$t>::from_be_bytes(b),
+                    }
+                }
+
+                fn to_bytes(&self, bytes: &mut [u8], endian: Endian) {
+                    bytes.copy_from_slice(&match endian {
+                        Endian::Little => self.to_le_bytes(),
+                        Endian::Big => self.to_be_bytes(),
+                    });
+                }
+            }
+        )*
+    };
+}
+
+integer_register!(u8, u16, u32, u64, i8, i16, i32, i64);
+
+impl<const N: usize> Register for [u8; N] {
+    const SIZE: usize = N;
+
+    fn from_bytes(bytes: &[u8], _endian: Endian) -> Self {
+        let mut b = [0u8; N];
+        b.copy_from_slice(bytes);
+        b
+    }
+
+    fn to_bytes(&self, bytes: &mut [u8], _endian: Endian) {
+        bytes.copy_from_slice(self);
+    }
+}
+
+/// A device whose vendor-specific control requests read and write
+/// registers
+///
+/// Many simple vendor-specific devices use control requests as
+/// register accesses: `bRequest` says which operation, `wIndex` says
+/// which register (or sometimes `bRequest` says both), and the data
+/// phase is the register's value. `VendorDevice` does the encoding,
+/// decoding, and length-checking; for instance, the MAC address of
+/// an ASIX AX88772 Ethernet adaptor is a six-byte register read by
+/// request 0x13:
+///
+/// ```no_run
+/// # use cotton_usb_host::host_controller::{HostController, UsbError};
+/// # use cotton_usb_host::usb_bus::{UsbBus, UsbDevice};
+/// # use cotton_usb_host::device::vendor::{Endian, VendorDevice};
+/// # async fn foo<HC: HostController>(bus: &UsbBus<HC>, device: UsbDevice)
+/// #     -> Result<(), UsbError> {
+/// let ax88772 = VendorDevice::new(bus, device, Endian::Little);
+/// let mac = ax88772.read_reg::<[u8; 6]>(0x13, 0).await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct VendorDevice<'a, HC: HostController, const D: usize = 512> {
+    bus: &'a UsbBus<HC, D>,
+    device: UsbDevice,
+    endian: Endian,
+}
+
+impl<'a, HC: HostController, const D: usize> VendorDevice<'a, HC, D> {
+    /// Create a vendor-device driver for a device with registers in
+    /// a particular byte order
+    pub fn new(
+        bus: &'a UsbBus<HC, D>,
+        device: UsbDevice,
+        endian: Endian,
+    ) -> Self {
+        Self {
+            bus,
+            device,
+            endian,
+        }
+    }
+
+    /// The underlying device
+    pub fn device(&self) -> &UsbDevice {
+        &self.device
+    }
+
+    /// Read a register, using a device-to-host vendor request
+    ///
+    /// Returns `UsbError::BufferTooSmall` if `T` is larger than
+    /// [`MAX_REGISTER_SIZE`], and `UsbError::ProtocolError` if the
+    /// device returns fewer bytes than the size of `T`.
+    pub async fn read_reg<T: Register>(
+        &self,
+        request: u8,
+        index: u16,
+    ) -> Result<T, UsbError> {
+        if T::SIZE > MAX_REGISTER_SIZE {
+            return Err(UsbError::BufferTooSmall);
+        }
+        let mut buf = [0u8; MAX_REGISTER_SIZE];
+        let n = self
+            .bus
+            .control_transfer(
+                &self.device,
+                SetupPacket {
+                    bmRequestType: DEVICE_TO_HOST
+                        | VENDOR_REQUEST
+                        | RECIPIENT_DEVICE,
+                    bRequest: request,
+                    wValue: 0,
+                    wIndex: index,
+                    wLength: T::SIZE as u16,
+                },
+                DataPhase::In(&mut buf[0..T::SIZE]),
+            )
+            .await?;
+        if n != T::SIZE {
+            return Err(UsbError::ProtocolError);
+        }
+        Ok(T::from_bytes(&buf[0..T::SIZE], self.endian))
+    }
+
+    /// Write a register, using a host-to-device vendor request
+    ///
+    /// Returns `UsbError::BufferTooSmall` if `T` is larger than
+    /// [`MAX_REGISTER_SIZE`].
+    pub async fn write_reg<T: Register>(
+        &self,
+        request: u8,
+        index: u16,
+        value: &T,
+    ) -> Result<(), UsbError> {
+        if T::SIZE > MAX_REGISTER_SIZE {
+            return Err(UsbError::BufferTooSmall);
+        }
+        let mut buf = [0u8; MAX_REGISTER_SIZE];
+        value.to_bytes(&mut buf[0..T::SIZE], self.endian);
+        self.bus
+            .control_transfer(
+                &self.device,
+                SetupPacket {
+                    bmRequestType: HOST_TO_DEVICE
+                        | VENDOR_REQUEST
+                        | RECIPIENT_DEVICE,
+                    bRequest: request,
+                    wValue: 0,
+                    wIndex: index,
+                    wLength: T::SIZE as u16,
+                },
+                DataPhase::Out(&buf[0..T::SIZE]),
+            )
+            .await?;
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+#[path = "../tests/vendor.rs"]
+mod tests;
@@ -0,0 +1,334 @@
+use crate::device::identify::IdentifyFromDescriptors;
+use crate::host_controller::{
+    DataPhase, HostController, TransferType, UsbError,
+};
+use crate::usb_bus::{BulkOut, UsbBus, UsbDevice};
+use crate::wire::{
+    ConfigurationDescriptor, DescriptorVisitor, EndpointDescriptor,
+    InterfaceDescriptor, SetupPacket, DEVICE_TO_HOST, RECIPIENT_INTERFACE,
+    VENDOR_REQUEST,
+};
+use futures::{Stream, StreamExt};
+
+/// Interface class code of XInput controllers (vendor-specific)
+pub const VENDOR_SPECIFIC_CLASSCODE: u8 = 0xFF;
+
+/// Interface subclass code of XInput controllers
+pub const XINPUT_SUBCLASS: u8 = 0x5D;
+
+/// Interface protocol code of an XInput controller's gamepad interface
+///
+/// Wired Xbox 360 controllers have further interfaces, with other
+/// protocol codes, for the headset port and for security; only the
+/// gamepad one is driven here.
+pub const XINPUT_GAMEPAD_PROTOCOL: u8 = 0x01;
+
+/// Message type of input reports (on the interrupt IN endpoint)
+pub const INPUT_REPORT: u8 = 0x00;
+
+/// Message type of rumble commands (on the interrupt OUT endpoint)
+pub const RUMBLE_COMMAND: u8 = 0x00;
+
+/// Message type of LED commands (on the interrupt OUT endpoint)
+pub const LED_COMMAND: u8 = 0x01;
+
+/// The buttons reported in [`GamepadState::buttons`], as bit masks
+pub mod buttons {
+    /// Directional pad, up
+    pub const DPAD_UP: u16 = 0x0001;
+    /// Directional pad, down
+    pub const DPAD_DOWN: u16 = 0x0002;
+    /// Directional pad, left
+    pub const DPAD_LEFT: u16 = 0x0004;
+    /// Directional pad, right
+    pub const DPAD_RIGHT: u16 = 0x0008;
+    /// Start
+    pub const START: u16 = 0x0010;
+    /// Back
+    pub const BACK: u16 = 0x0020;
+    /// Left stick, pressed in
+    pub const LEFT_THUMB: u16 = 0x0040;
+    /// Right stick, pressed in
+    pub const RIGHT_THUMB: u16 = 0x0080;
+    /// Left shoulder ("bumper")
+    pub const LEFT_SHOULDER: u16 = 0x0100;
+    /// Right shoulder ("bumper")
+    pub const RIGHT_SHOULDER: u16 = 0x0200;
+    /// The central "Guide" button
+    pub const GUIDE: u16 = 0x0400;
+    /// A
+    pub const A: u16 = 0x1000;
+    /// B
+    pub const B: u16 = 0x2000;
+    /// X
+    pub const X: u16 = 0x4000;
+    /// Y
+    pub const Y: u16 = 0x8000;
+}
+
+/// The state of an XInput controller, as decoded from an input report
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone, Default, PartialEq, Eq)]
+pub struct GamepadState {
+    /// Buttons currently pressed, see [`buttons`]
+    pub buttons: u16,
+    /// Left trigger, 0 (released) to 255 (fully pressed)
+    pub left_trigger: u8,
+    /// Right trigger, 0 (released) to 255 (fully pressed)
+    pub right_trigger: u8,
+    /// Left stick position (x, y); positive is right and up
+    pub left_stick: (i16, i16),
+    /// Right stick position (x, y); positive is right and up
+    pub right_stick: (i16, i16),
+}
+
+impl GamepadState {
+    /// Decode an input report
+    ///
+    /// Returns `None` if the packet isn't an input report: controllers
+    /// also send other messages on the same endpoint, such as which
+    /// LED pattern is showing.
+    pub fn parse(report: &[u8]) -> Option<Self> {
+        if report.len() < 14 || report[0] != INPUT_REPORT || report[1] < 14 {
+            return None;
+        }
+        let word = |i: usize| [report[i], report[i + 1]];
+        Some(Self {
+            buttons: u16::from_le_bytes(word(2)),
+            left_trigger: report[4],
+            right_trigger: report[5],
+            left_stick: (
+                i16::from_le_bytes(word(6)),
+                i16::from_le_bytes(word(8)),
+            ),
+            right_stick: (
+                i16::from_le_bytes(word(10)),
+                i16::from_le_bytes(word(12)),
+            ),
+        })
+    }
+
+    /// Are all of the buttons in `mask` pressed?
+    pub fn pressed(&self, mask: u16) -> bool {
+        (self.buttons & mask) == mask
+    }
+}
+
+/// Patterns for the ring of four LEDs around the Guide button
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone, PartialEq, Eq)]
+#[allow(missing_docs)]
+pub enum LedPattern {
+    Off = 0,
+    AllBlinking = 1,
+    Player1Flash = 2,
+    Player2Flash = 3,
+    Player3Flash = 4,
+    Player4Flash = 5,
+    Player1 = 6,
+    Player2 = 7,
+    Player3 = 8,
+    Player4 = 9,
+    Rotating = 10,
+}
+
+/// Recognises XInput controllers, see [`IdentifyFromDescriptors`]
+#[derive(Default)]
+pub struct IdentifyXInput {
+    current_configuration: Option<u8>,
+    xinput_configuration: Option<u8>,
+    in_xinput_interface: bool,
+    interface: u8,
+    in_endpoint: Option<EndpointDescriptor>,
+    out_endpoint: Option<u8>,
+}
+
+impl IdentifyXInput {
+    /// The interface number of the gamepad interface
+    pub fn interface(&self) -> u8 {
+        self.interface
+    }
+
+    /// The gamepad interface's interrupt IN endpoint
+    pub fn in_endpoint(&self) -> Option<&EndpointDescriptor> {
+        self.in_endpoint.as_ref()
+    }
+
+    /// The number of the gamepad interface's interrupt OUT endpoint
+    pub fn out_endpoint(&self) -> Option<u8> {
+        self.out_endpoint
+    }
+}
+
+impl DescriptorVisitor for IdentifyXInput {
+    fn on_configuration(&mut self, c: &ConfigurationDescriptor) {
+        self.current_configuration = Some(c.bConfigurationValue);
+        self.in_xinput_interface = false;
+    }
+
+    fn on_interface(&mut self, i: &InterfaceDescriptor) {
+        self.in_xinput_interface = self.xinput_configuration.is_none()
+            && i.bInterfaceClass == VENDOR_SPECIFIC_CLASSCODE
+            && i.bInterfaceSubClass == XINPUT_SUBCLASS
+            && i.bInterfaceProtocol == XINPUT_GAMEPAD_PROTOCOL;
+        if self.in_xinput_interface {
+            self.interface = i.bInterfaceNumber;
+            self.in_endpoint = None;
+            self.out_endpoint = None;
+        }
+    }
+
+    fn on_endpoint(&mut self, e: &EndpointDescriptor) {
+        if !self.in_xinput_interface || (e.bmAttributes & 3) != 3 {
+            return;
+        }
+        if (e.bEndpointAddress & 0x80) != 0 {
+            if self.in_endpoint.is_none() {
+                self.in_endpoint = Some(*e);
+            }
+        } else if self.out_endpoint.is_none() {
+            self.out_endpoint = Some(e.bEndpointAddress & 15);
+        }
+        if self.in_endpoint.is_some() && self.out_endpoint.is_some() {
+            self.xinput_configuration = self.current_configuration;
+            self.in_xinput_interface = false;
+        }
+    }
+}
+
+impl IdentifyFromDescriptors for IdentifyXInput {
+    fn identify(&self) -> Option<u8> {
+        self.xinput_configuration
+    }
+}
+
+/// An example driver for XInput-compatible game controllers
+///
+/// XInput is the protocol of wired Xbox 360 controllers, and of the
+/// many third-party controllers which imitate them. It's vendor
+/// specific (there's no published standard), so this driver is
+/// written from the behaviour of real controllers: input reports
+/// arrive, whenever anything changes, on an interrupt IN endpoint;
+/// rumble and LED commands are sent on an interrupt OUT endpoint.
+///
+/// Interrupt OUT transfers are sent using
+/// [`UsbBus::bulk_out_transfer()`]: each command is a single short
+/// packet, which is the same on the wire either way.
+pub struct XInput<'a, HC: HostController, const D: usize = 512> {
+    bus: &'a UsbBus<HC, D>,
+    device: UsbDevice,
+    interface: u8,
+    in_endpoint: EndpointDescriptor,
+    out_endpoint: BulkOut,
+}
+
+impl<'a, HC: HostController, const D: usize> XInput<'a, HC, D> {
+    /// Create a driver for a configured XInput controller
+    ///
+    /// The interface and endpoints are those found by
+    /// [`IdentifyXInput`]; returns `UsbError::NoSuchEndpoint` if it
+    /// didn't find them.
+    pub fn new(
+        bus: &'a UsbBus<HC, D>,
+        mut device: UsbDevice,
+        identify: &IdentifyXInput,
+    ) -> Result<Self, UsbError> {
+        let (Some(in_endpoint), Some(out)) =
+            (identify.in_endpoint(), identify.out_endpoint())
+        else {
+            return Err(UsbError::NoSuchEndpoint);
+        };
+        let out_endpoint = device.open_out_endpoint(out)?;
+        Ok(Self {
+            bus,
+            device,
+            interface: identify.interface(),
+            in_endpoint: *in_endpoint,
+            out_endpoint,
+        })
+    }
+
+    /// The underlying device
+    pub fn device(&self) -> &UsbDevice {
+        &self.device
+    }
+
+    /// Prepare the controller for use
+    ///
+    /// Some third-party controllers send no input reports until the
+    /// host has made this vendor-specific request (which genuine
+    /// controllers answer with a serial number); this then lights the
+    /// player-1 LED, as an Xbox would, to show that the controller is
+    /// in use.
+    pub async fn init(&self) -> Result<(), UsbError> {
+        let mut buf = [0u8; 20];
+        self.bus
+            .control_transfer(
+                &self.device,
+                SetupPacket {
+                    bmRequestType: DEVICE_TO_HOST
+                        | VENDOR_REQUEST
+                        | RECIPIENT_INTERFACE,
+                    bRequest: 1,
+                    wValue: 0x100,
+                    wIndex: self.interface as u16,
+                    wLength: buf.len() as u16,
+                },
+                DataPhase::In(&mut buf),
+            )
+            .await?;
+        self.set_led(LedPattern::Player1).await
+    }
+
+    /// The controller's state, each time it changes
+    pub fn states(&self) -> impl Stream<Item = GamepadState> + '_ {
+        let e = &self.in_endpoint;
+        self.bus
+            .interrupt_endpoint_in(
+                self.device.address(),
+                e.bEndpointAddress & 15,
+                e.max_packet_size(),
+                e.bInterval,
+            )
+            .filter_map(|packet| {
+                core::future::ready(GamepadState::parse(
+                    &packet.data[0..packet.size as usize],
+                ))
+            })
+    }
+
+    async fn send(&self, command: &[u8]) -> Result<(), UsbError> {
+        self.bus
+            .bulk_out_transfer(
+                &self.out_endpoint,
+                command,
+                TransferType::FixedSize,
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Set the strength of the two rumble motors (0 is off)
+    ///
+    /// The left ("large") motor has a heavier weight, and gives a
+    /// lower-pitched rumble than the right ("small") one.
+    pub async fn set_rumble(
+        &self,
+        large: u8,
+        small: u8,
+    ) -> Result<(), UsbError> {
+        self.send(&[RUMBLE_COMMAND, 8, 0, large, small, 0, 0, 0])
+            .await
+    }
+
+    /// Set the pattern shown by the LEDs around the Guide button
+    pub async fn set_led(&self, pattern: LedPattern) -> Result<(), UsbError> {
+        self.send(&[LED_COMMAND, 3, pattern as u8]).await
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+#[path = "../tests/xinput.rs"]
+mod tests;
@@ -1,11 +1,13 @@
-use crate::async_pool::Pool;
+use crate::async_pool::{Pool, Priority};
+use crate::cell::Cell;
 use crate::debug;
 use crate::host_controller::{
-    DataPhase, DeviceStatus, HostController, InterruptPacket, TransferType,
+    gather, scatter, DataPhase, DeviceStatus, HostController, InterruptPacket,
+    InterruptPacketView, StatisticsTable, TransferStatistics, TransferType,
     UsbError, UsbSpeed,
 };
+use crate::role::RoleManager;
 use crate::wire::{Direction, EndpointType, SetupPacket};
-use core::cell::Cell;
 use core::future::Future;
 use core::pin::Pin;
 use core::task::{Context, Poll};
@@ -85,17 +87,43 @@ impl Default for UsbShared {
 }
 
 /// Data that isn't shared with the IRQ handler, but must be 'static anyway
-pub struct UsbStatics {
+///
+/// The two const parameters size it at compile time:
+///
+///  - `PIPES`: how many interrupt pipes (see
+///    [`HostController::alloc_interrupt_pipe()`]) can be open at
+///    once, at most 15 (the hardware's limit). Each hub, and each
+///    device such as a keyboard or mouse, needs one.
+///  - `DEVICES`: how many USB addresses transfer statistics are kept
+///    for, see [`StatisticsTable`].
+///
+/// The defaults (15 and 128) allow for anything the hardware can do.
+/// A keyboard-only host, for instance, might use
+/// `UsbStatics::<2, 4>::sized()`.
+pub struct UsbStatics<const PIPES: usize = 15, const DEVICES: usize = 128> {
     bulk_pipes: Pool,
     control_pipes: Pool,
+    statistics: StatisticsTable<DEVICES>,
 }
 
 impl UsbStatics {
     /// Crate a new `UsbStatics` (nb, is const, unlike `default()`)
     pub const fn new() -> Self {
+        Self::sized()
+    }
+}
+
+impl<const PIPES: usize, const DEVICES: usize> UsbStatics<PIPES, DEVICES> {
+    /// Create a new `UsbStatics` of the sizes given by its type
+    ///
+    /// # Panics
+    /// Will panic (at compile time, if used in a `static`) if `PIPES`>15.
+    pub const fn sized() -> Self {
+        assert!(PIPES <= 15);
         Self {
-            bulk_pipes: Pool::new(15),
+            bulk_pipes: Pool::new(PIPES as u8),
             control_pipes: Pool::new(1),
+            statistics: StatisticsTable::sized(),
         }
     }
 }
@@ -286,58 +314,110 @@ impl Pipe {
     }
 }
 
+/// Hand one half of a double-buffered endpoint back to the hardware
+///
+/// Each half of EP_BUFFER_CONTROL belongs to one of the two buffers,
+/// and the hardware may update the other half at any moment, so each
+/// half is written with a 16-bit store of its own rather than by a
+/// read-modify-write of the whole register. Buffer 0 always expects
+/// DATA0, and buffer 1 DATA1, as the hardware alternates between them.
+fn arm_buffer(
+    reg: &pac::usbctrl_dpram::EP_BUFFER_CONTROL,
+    buffer: u8,
+    length: u16,
+) {
+    // Bits of each half: LENGTH 0-9, AVAILABLE 10, PID 13, LAST 14, FULL 15
+    let bits = (length & 0x3FF) | ((buffer as u16 & 1) << 13) | (1 << 14);
+    let half = unsafe { (reg.as_ptr() as *mut u16).add(buffer as usize) };
+    unsafe { half.write_volatile(bits) };
+
+    // The AVAILABLE bit must be set separately, some cycles later
+    // (RP2040 datasheet s4.1.2.5.1)
+    cortex_m::asm::delay(12);
+
+    unsafe { half.write_volatile(bits | (1 << 10)) };
+}
+
+/// The HOST_POLL_INTERVAL field for an interrupt endpoint
+///
+/// The hardware counts SOFs, polling each endpoint every
+/// HOST_POLL_INTERVAL + 1 frames; so, with one frame per millisecond,
+/// each pipe is polled at its own `bInterval` (RP2040 datasheet
+/// s4.1.2.7, and USB 2.0 s5.7.4).
+const fn poll_interval(interval_ms: u8) -> u16 {
+    if interval_ms == 0 {
+        0
+    } else {
+        interval_ms as u16 - 1
+    }
+}
+
 /// Implementation of `HostController::InterruptPipe` for RP2040
-pub struct Rp2040InterruptPipe {
+///
+/// Each interrupt pipe is double-buffered, so that the hardware can
+/// carry on polling the device while one packet is waiting to be
+/// collected. If both buffers fill up before they are collected, the
+/// next packet is marked with [`InterruptPacket::overrun`] (and the
+/// overrun counted in the device's [`TransferStatistics`]).
+pub struct Rp2040InterruptPipe<const DEVICES: usize = 128> {
     shared: &'static UsbShared,
+    statistics: &'static StatisticsTable<DEVICES>,
     pipe: Pipe,
     max_packet_size: u16,
-    data_toggle: Cell<bool>,
+    next_buffer: Cell<u8>,
 }
 
-impl Rp2040InterruptPipe {
+impl<const DEVICES: usize> Rp2040InterruptPipe<DEVICES> {
     fn set_waker(&self, waker: &core::task::Waker) {
         self.shared.pipe_wakers[self.pipe.which() as usize].register(waker);
     }
 
-    fn poll(&self) -> Option<InterruptPacket> {
+    /// Call `f` on the next packet, if any, while it's still in DPRAM
+    fn poll_in_place<R>(
+        &self,
+        f: impl FnOnce(InterruptPacketView<'_>) -> R,
+    ) -> Option<R> {
         let dpram = unsafe { pac::USBCTRL_DPRAM::steal() };
         let regs = unsafe { pac::USBCTRL_REGS::steal() };
         let which = self.pipe.which();
         let bc = dpram.ep_buffer_control((which * 2) as usize).read();
-        if bc.full_0().bit() {
+        let buffer = self.next_buffer.get();
+        let (full, length, other_full) = if buffer == 0 {
+            (bc.full_0().bit(), bc.length_0().bits(), bc.full_1().bit())
+        } else {
+            (bc.full_1().bit(), bc.length_1().bits(), bc.full_0().bit())
+        };
+        if full {
             let addr_endp = regs.host_addr_endp((which - 1) as usize).read();
-            let mut result = InterruptPacket {
-                address: addr_endp.address().bits() as u8,
-                endpoint: addr_endp.endpoint().bits() as u8,
-                size: core::cmp::min(bc.length_0().bits(), 64) as u8,
-                ..Default::default()
-            };
-            unsafe {
-                core::ptr::copy_nonoverlapping(
-                    (0x5010_0200 + (which as u32) * 128) as *const u8,
-                    &mut result.data[0] as *mut u8,
-                    result.size as usize,
+            let address = addr_endp.address().bits();
+            let size = core::cmp::min(length, 64) as usize;
+            // The buffer isn't handed back to the hardware until after
+            // `f` returns, so can't change underneath it
+            let data = unsafe {
+                core::slice::from_raw_parts(
+                    (0x5010_0200 + (which as u32) * 128 + (buffer as u32) * 64)
+                        as *const u8,
+                    size,
                 )
             };
-            self.data_toggle.set(!self.data_toggle.get());
-            dpram
-                .ep_buffer_control((which * 2) as usize)
-                .write(|w| unsafe {
-                    w.full_0()
-                        .clear_bit()
-                        .pid_0()
-                        .bit(self.data_toggle.get())
-                        .length_0()
-                        .bits(self.max_packet_size)
-                        .last_0()
-                        .set_bit()
-                });
-
-            cortex_m::asm::delay(12);
-
-            dpram
-                .ep_buffer_control((which * 2) as usize)
-                .modify(|_, w| w.available_0().set_bit());
+            // If the other buffer is full too, the hardware has had
+            // nowhere to put packets, and has stopped polling the device
+            let overrun = other_full;
+            if overrun {
+                self.statistics.record_overrun(address);
+            }
+            let result = f(InterruptPacketView {
+                address,
+                endpoint: addr_endp.endpoint().bits() as u8,
+                data,
+                overrun,
+            });
+            arm_buffer(
+                dpram.ep_buffer_control((which * 2) as usize),
+                buffer,
+                self.max_packet_size,
+            );
+            self.next_buffer.set(buffer ^ 1);
             defmt::println!(
                 "IE ready inte {:x} iec {:x} ecr {:x} epbc {:x}",
                 regs.inte().read().bits(),
@@ -364,9 +444,32 @@ impl Rp2040InterruptPipe {
             None
         }
     }
+
+    fn poll(&self) -> Option<InterruptPacket> {
+        self.poll_in_place(|p| InterruptPacket::from(p))
+    }
+
+    /// Poll for the next packet, parsing it without copying it
+    ///
+    /// This is an alternative to the [`Stream`] implementation
+    /// (which copies each packet into an [`InterruptPacket`]) for
+    /// drivers on hot paths: `f` is called on the packet while it is
+    /// still in the USB controller's own memory.
+    pub fn poll_next_with<R>(
+        &self,
+        cx: &mut Context<'_>,
+        f: impl FnOnce(InterruptPacketView<'_>) -> R,
+    ) -> Poll<R> {
+        self.set_waker(cx.waker());
+
+        match self.poll_in_place(f) {
+            Some(r) => Poll::Ready(r),
+            None => Poll::Pending,
+        }
+    }
 }
 
-impl Stream for Rp2040InterruptPipe {
+impl<const DEVICES: usize> Stream for Rp2040InterruptPipe<DEVICES> {
     type Item = InterruptPacket;
 
     fn poll_next(
@@ -503,14 +606,14 @@ struct OutPacketiser<'a> {
     offset: usize,
     packet_size: usize,
     need_zero_size_packet: bool,
-    buf: &'a [u8],
+    buf: &'a [&'a [u8]],
 }
 
 impl<'a> OutPacketiser<'a> {
     fn new(
         size: u16,
         packet_size: u16,
-        buf: &'a [u8],
+        buf: &'a [&'a [u8]],
         initial_pid: bool,
         zlp: ZeroLengthPacket,
     ) -> Self {
@@ -564,13 +667,13 @@ impl Packetiser for OutPacketiser<'_> {
                             is_last
                         );
                         if this_packet > 0 {
-                            unsafe {
-                                core::ptr::copy_nonoverlapping(
-                                    &self.buf[self.offset] as *const u8,
+                            let dest = unsafe {
+                                core::slice::from_raw_parts_mut(
                                     (0x5010_0000 + 0x180) as *mut u8,
                                     this_packet,
-                                );
-                            }
+                                )
+                            };
+                            gather(self.buf, self.offset, dest);
                         }
                         reg.modify(|_, w| {
                             w.full_0().set_bit();
@@ -601,13 +704,13 @@ impl Packetiser for OutPacketiser<'_> {
                             self.remain,
                             is_last
                         );
-                        unsafe {
-                            core::ptr::copy_nonoverlapping(
-                                &self.buf[self.offset] as *const u8,
+                        let dest = unsafe {
+                            core::slice::from_raw_parts_mut(
                                 (0x5010_0000 + 0x1C0) as *mut u8,
                                 this_packet,
-                            );
-                        }
+                            )
+                        };
+                        gather(self.buf, self.offset, dest);
                         reg.modify(|_, w| {
                             w.full_1().set_bit();
                             w.pid_1().bit(!self.initial_pid);
@@ -636,16 +739,16 @@ trait Depacketiser {
     fn retire(&mut self, reg: &pac::usbctrl_dpram::EP_BUFFER_CONTROL) -> bool;
 }
 
-struct InDepacketiser<'a> {
+struct InDepacketiser<'a, 'b> {
     next_retire: u8,
     packet_parity: bool,
     remain: usize,
     offset: usize,
-    buf: &'a mut [u8],
+    buf: &'a mut [&'b mut [u8]],
 }
 
-impl<'a> InDepacketiser<'a> {
-    fn new(size: u16, buf: &'a mut [u8]) -> Self {
+impl<'a, 'b> InDepacketiser<'a, 'b> {
+    fn new(size: u16, buf: &'a mut [&'b mut [u8]]) -> Self {
         Self {
             next_retire: 0,
             packet_parity: false,
@@ -660,7 +763,7 @@ impl<'a> InDepacketiser<'a> {
     }
 }
 
-impl Depacketiser for InDepacketiser<'_> {
+impl Depacketiser for InDepacketiser<'_, '_> {
     fn retire(&mut self, reg: &pac::usbctrl_dpram::EP_BUFFER_CONTROL) -> bool {
         let val = reg.read();
         match self.next_retire {
@@ -677,13 +780,13 @@ impl Depacketiser for InDepacketiser<'_> {
                         val.length_0().bits() as usize,
                     );
                     if this_packet > 0 {
-                        unsafe {
-                            core::ptr::copy_nonoverlapping(
+                        let src = unsafe {
+                            core::slice::from_raw_parts(
                                 (0x5010_0000 + 0x180) as *const u8,
-                                &mut self.buf[self.offset] as *mut u8,
                                 this_packet,
-                            );
-                        }
+                            )
+                        };
+                        scatter(self.buf, self.offset, src);
                     }
 
                     self.remain -= this_packet;
@@ -705,13 +808,13 @@ impl Depacketiser for InDepacketiser<'_> {
                         val.length_1().bits() as usize,
                     );
                     if this_packet > 0 {
-                        unsafe {
-                            core::ptr::copy_nonoverlapping(
+                        let src = unsafe {
+                            core::slice::from_raw_parts(
                                 (0x5010_0000 + 0x1C0) as *const u8,
-                                &mut self.buf[self.offset] as *mut u8,
                                 this_packet,
-                            );
-                        }
+                            )
+                        };
+                        scatter(self.buf, self.offset, src);
                     }
 
                     self.remain -= this_packet;
@@ -765,14 +868,21 @@ impl Depacketiser for OutDepacketiser {
 }
 
 /// Implementation of HostController for RP2040
-pub struct Rp2040HostController {
+///
+/// The const parameters are those of the [`UsbStatics`] it uses.
+pub struct Rp2040HostController<
+    const PIPES: usize = 15,
+    const DEVICES: usize = 128,
+> {
     shared: &'static UsbShared,
-    statics: &'static UsbStatics,
+    statics: &'static UsbStatics<PIPES, DEVICES>,
     regs: pac::USBCTRL_REGS,
     dpram: pac::USBCTRL_DPRAM,
 }
 
-impl Rp2040HostController {
+impl<const PIPES: usize, const DEVICES: usize>
+    Rp2040HostController<PIPES, DEVICES>
+{
     /// Create a new RP2040HostController
     ///
     /// You'll need a rp2040::UsbShared, a rp2040::UsbStatics, and the
@@ -785,11 +895,21 @@ impl Rp2040HostController {
         regs: pac::USBCTRL_REGS,
         dpram: pac::USBCTRL_DPRAM,
         shared: &'static UsbShared,
-        statics: &'static UsbStatics,
+        statics: &'static UsbStatics<PIPES, DEVICES>,
     ) -> Self {
         resets.reset().modify(|_, w| w.usbctrl().set_bit());
         resets.reset().modify(|_, w| w.usbctrl().clear_bit());
 
+        Self::start(regs, dpram, shared, statics)
+    }
+
+    /// Set up the (freshly-reset) hardware as a host controller
+    fn start(
+        regs: pac::USBCTRL_REGS,
+        dpram: pac::USBCTRL_DPRAM,
+        shared: &'static UsbShared,
+        statics: &'static UsbStatics<PIPES, DEVICES>,
+    ) -> Self {
         regs.usb_muxing().modify(|_, w| {
             w.to_phy().set_bit();
             w.softcon().set_bit()
@@ -825,20 +945,51 @@ impl Rp2040HostController {
         }
     }
 
-    async fn alloc_pipe(&self, endpoint_type: EndpointType) -> Pipe {
-        if endpoint_type == EndpointType::Control {
-            Pipe::new(self.statics.control_pipes.alloc().await, 0)
-        } else {
-            Pipe::new(self.statics.bulk_pipes.alloc().await, 1)
+    /// Stop acting as a host controller, and give back the hardware
+    ///
+    /// The USB interrupt is masked, but the hardware is otherwise left
+    /// as-is; see [`Rp2040RoleManager`] for handing it on to a device
+    /// stack.
+    pub fn release(self) -> (pac::USBCTRL_REGS, pac::USBCTRL_DPRAM) {
+        pac::NVIC::mask(pac::Interrupt::USBCTRL_IRQ);
+        self.regs.inte().write(|w| unsafe { w.bits(0) });
+        self.regs.sie_ctrl().write(|w| unsafe { w.bits(0) });
+        self.regs
+            .main_ctrl()
+            .modify(|_, w| w.controller_en().clear_bit());
+        (self.regs, self.dpram)
+    }
+
+    /// Which pipe pool serves this endpoint type, and at what priority
+    ///
+    /// Control and bulk transfers both use EPX, so they share its
+    /// pool; control transfers (which include all those made while
+    /// enumerating a newly-attached device) take precedence over
+    /// bulk ones, so that heavy bulk traffic (such as to a mass-storage
+    /// device) can't starve them.
+    fn pipe_pool(
+        &self,
+        endpoint_type: EndpointType,
+    ) -> (&'static Pool, u8, Priority) {
+        match endpoint_type {
+            EndpointType::Control => {
+                (&self.statics.control_pipes, 0, Priority::High)
+            }
+            EndpointType::Bulk => {
+                (&self.statics.control_pipes, 0, Priority::Low)
+            }
+            _ => (&self.statics.bulk_pipes, 1, Priority::Normal),
         }
     }
 
+    async fn alloc_pipe(&self, endpoint_type: EndpointType) -> Pipe {
+        let (pool, offset, priority) = self.pipe_pool(endpoint_type);
+        Pipe::new(pool.alloc_with_priority(priority).await, offset)
+    }
+
     fn try_alloc_pipe(&self, endpoint_type: EndpointType) -> Option<Pipe> {
-        if endpoint_type == EndpointType::Control {
-            Some(Pipe::new(self.statics.control_pipes.try_alloc()?, 0))
-        } else {
-            Some(Pipe::new(self.statics.bulk_pipes.try_alloc()?, 1))
-        }
+        let (pool, offset, priority) = self.pipe_pool(endpoint_type);
+        Some(Pipe::new(pool.try_alloc_with_priority(priority)?, offset))
     }
 
     async fn send_setup(
@@ -1176,9 +1327,9 @@ impl Rp2040HostController {
         address: u8,
         packet_size: u8,
         size: usize,
-        buf: &mut [u8],
+        buf: &mut [&mut [u8]],
     ) -> Result<usize, UsbError> {
-        if buf.len() < size {
+        if buf.iter().map(|b| b.len()).sum::<usize>() < size {
             return Err(UsbError::BufferTooSmall);
         }
         let mut packetiser = InPacketiser::new(
@@ -1208,9 +1359,10 @@ impl Rp2040HostController {
         address: u8,
         packet_size: u8,
         size: usize,
-        buf: &[u8],
+        buf: &[&[u8]],
     ) -> Result<usize, UsbError> {
-        if buf.len() < size {
+        let total = buf.iter().map(|b| b.len()).sum::<usize>();
+        if total < size {
             return Err(UsbError::BufferTooSmall);
         }
         let mut packetiser = OutPacketiser::new(
@@ -1233,7 +1385,7 @@ impl Rp2040HostController {
         )
         .await?;
 
-        Ok(buf.len())
+        Ok(total)
     }
 
     fn interrupt_pipe(
@@ -1243,7 +1395,7 @@ impl Rp2040HostController {
         endpoint: u8,
         max_packet_size: u16,
         interval_ms: u8,
-    ) -> Rp2040InterruptPipe {
+    ) -> Rp2040InterruptPipe<DEVICES> {
         let n = pipe.which();
         let regs = unsafe { pac::USBCTRL_REGS::steal() };
         let dpram = unsafe { pac::USBCTRL_DPRAM::steal() };
@@ -1256,8 +1408,13 @@ impl Rp2040HostController {
                 .clear_bit() // IN
         });
 
+        // Two 64-byte buffers, in the 128 bytes of DPRAM for this pipe
+        let max_packet_size = core::cmp::min(max_packet_size, 64);
+
         dpram.ep_control((n * 2 - 2) as usize).write(|w| unsafe {
             w.enable()
+                .set_bit()
+                .double_buffered()
                 .set_bit()
                 .interrupt_per_buff()
                 .set_bit()
@@ -1266,31 +1423,29 @@ impl Rp2040HostController {
                 .buffer_address()
                 .bits(0x200 + (n as u16) * 128)
                 .host_poll_interval()
-                .bits(core::cmp::min(interval_ms as u16, 9))
-        });
-
-        dpram.ep_buffer_control((n * 2) as usize).write(|w| unsafe {
-            w.full_0()
-                .clear_bit()
-                .length_0()
-                .bits(max_packet_size)
-                .pid_0()
-                .clear_bit()
-                .last_0()
-                .set_bit()
+                .bits(poll_interval(interval_ms))
         });
 
-        cortex_m::asm::delay(12);
-
         dpram
             .ep_buffer_control((n * 2) as usize)
-            .modify(|_, w| w.available_0().set_bit());
+            .write(|w| unsafe { w.bits(0) });
+        arm_buffer(
+            dpram.ep_buffer_control((n * 2) as usize),
+            0,
+            max_packet_size,
+        );
+        arm_buffer(
+            dpram.ep_buffer_control((n * 2) as usize),
+            1,
+            max_packet_size,
+        );
 
         Rp2040InterruptPipe {
             shared: self.shared,
+            statistics: &self.statics.statistics,
             pipe,
             max_packet_size,
-            data_toggle: Cell::new(false),
+            next_buffer: Cell::new(0),
         }
     }
 
@@ -1401,24 +1556,11 @@ impl Rp2040HostController {
         Ok(size)
     }
      */
-}
 
-impl HostController for Rp2040HostController {
-    type InterruptPipe = Rp2040InterruptPipe;
-    type DeviceDetect = Rp2040DeviceDetect;
-
-    fn device_detect(&self) -> Self::DeviceDetect {
-        Rp2040DeviceDetect::new(&self.shared.device_waker)
-    }
+    // The transfers themselves; the HostController methods wrap these
+    // in order to keep statistics.
 
-    fn reset_root_port(&self, rst: bool) {
-        if rst {
-            self.regs.sie_ctrl().modify(|_, w| w.reset_bus().set_bit());
-        }
-        // SIE_CTRL.RESET_BUS clears itself when done
-    }
-
-    async fn control_transfer<'a>(
+    async fn unrecorded_control_transfer<'a>(
         &self,
         address: u8,
         packet_size: u8,
@@ -1435,7 +1577,20 @@ impl HostController for Rp2040HostController {
                         address,
                         packet_size,
                         setup.wLength as usize,
-                        buf,
+                        &mut [buf],
+                    )
+                    .await?;
+                self.control_transfer_out(address, packet_size, 0, &[])
+                    .await?;
+                Ok(sz)
+            }
+            DataPhase::InVectored(bufs) => {
+                let sz = self
+                    .control_transfer_in(
+                        address,
+                        packet_size,
+                        setup.wLength as usize,
+                        bufs,
                     )
                     .await?;
                 self.control_transfer_out(address, packet_size, 0, &[])
@@ -1448,7 +1603,20 @@ impl HostController for Rp2040HostController {
                         address,
                         packet_size,
                         setup.wLength as usize,
-                        buf,
+                        &[buf],
+                    )
+                    .await?;
+                self.control_transfer_in(address, packet_size, 0, &mut [])
+                    .await?;
+                Ok(sz)
+            }
+            DataPhase::OutVectored(bufs) => {
+                let sz = self
+                    .control_transfer_out(
+                        address,
+                        packet_size,
+                        setup.wLength as usize,
+                        bufs,
                     )
                     .await?;
                 self.control_transfer_in(address, packet_size, 0, &mut [])
@@ -1462,7 +1630,7 @@ impl HostController for Rp2040HostController {
         }
     }
 
-    async fn bulk_in_transfer(
+    async fn unrecorded_bulk_in_transfer(
         &self,
         address: u8,
         endpoint: u8,
@@ -1471,7 +1639,7 @@ impl HostController for Rp2040HostController {
         transfer_type: TransferType,
         data_toggle: &Cell<bool>,
     ) -> Result<usize, UsbError> {
-        let _pipe = self.alloc_pipe(EndpointType::Control).await;
+        let _pipe = self.alloc_pipe(EndpointType::Bulk).await;
         /*
         debug::println!("bulk in {} on pipe {} parity {}",
                         data.len(),
@@ -1488,7 +1656,8 @@ impl HostController for Rp2040HostController {
             },
         );
         let length = data.len() as u16;
-        let mut depacketiser = InDepacketiser::new(length, data);
+        let mut buf = [data];
+        let mut depacketiser = InDepacketiser::new(length, &mut buf);
 
         self.control_transfer_inner(
             address,
@@ -1516,7 +1685,7 @@ impl HostController for Rp2040HostController {
         Ok(depacketiser.total())
     }
 
-    async fn bulk_out_transfer(
+    async fn unrecorded_bulk_out_transfer(
         &self,
         address: u8,
         endpoint: u8,
@@ -1525,7 +1694,7 @@ impl HostController for Rp2040HostController {
         transfer_type: TransferType,
         data_toggle: &Cell<bool>,
     ) -> Result<usize, UsbError> {
-        let _pipe = self.alloc_pipe(EndpointType::Control).await;
+        let _pipe = self.alloc_pipe(EndpointType::Bulk).await;
         /*
         debug::println!(
             "bulk out {} on pipe {} parity {}", data.len(),
@@ -1533,10 +1702,11 @@ impl HostController for Rp2040HostController {
             data_toggle.get()
         );
         */
+        let buf = [data];
         let mut packetiser = OutPacketiser::new(
             data.len() as u16,
             packet_size as u16,
-            data,
+            &buf,
             data_toggle.get(),
             match transfer_type {
                 TransferType::FixedSize => ZeroLengthPacket::Never,
@@ -1567,6 +1737,89 @@ impl HostController for Rp2040HostController {
         */
         Ok(data.len())
     }
+}
+
+impl<const PIPES: usize, const DEVICES: usize> HostController
+    for Rp2040HostController<PIPES, DEVICES>
+{
+    type InterruptPipe = Rp2040InterruptPipe<DEVICES>;
+    type DeviceDetect = Rp2040DeviceDetect;
+
+    fn device_detect(&self) -> Self::DeviceDetect {
+        Rp2040DeviceDetect::new(&self.shared.device_waker)
+    }
+
+    fn reset_root_port(&self, rst: bool) {
+        if rst {
+            self.regs.sie_ctrl().modify(|_, w| w.reset_bus().set_bit());
+        }
+        // SIE_CTRL.RESET_BUS clears itself when done
+    }
+
+    async fn control_transfer<'a>(
+        &self,
+        address: u8,
+        packet_size: u8,
+        setup: SetupPacket,
+        data_phase: DataPhase<'a>,
+    ) -> Result<usize, UsbError> {
+        let result = self
+            .unrecorded_control_transfer(
+                address,
+                packet_size,
+                setup,
+                data_phase,
+            )
+            .await;
+        self.statics.statistics.record(address, &result);
+        result
+    }
+
+    async fn bulk_in_transfer(
+        &self,
+        address: u8,
+        endpoint: u8,
+        packet_size: u16,
+        data: &mut [u8],
+        transfer_type: TransferType,
+        data_toggle: &Cell<bool>,
+    ) -> Result<usize, UsbError> {
+        let result = self
+            .unrecorded_bulk_in_transfer(
+                address,
+                endpoint,
+                packet_size,
+                data,
+                transfer_type,
+                data_toggle,
+            )
+            .await;
+        self.statics.statistics.record(address, &result);
+        result
+    }
+
+    async fn bulk_out_transfer(
+        &self,
+        address: u8,
+        endpoint: u8,
+        packet_size: u16,
+        data: &[u8],
+        transfer_type: TransferType,
+        data_toggle: &Cell<bool>,
+    ) -> Result<usize, UsbError> {
+        let result = self
+            .unrecorded_bulk_out_transfer(
+                address,
+                endpoint,
+                packet_size,
+                data,
+                transfer_type,
+                data_toggle,
+            )
+            .await;
+        self.statics.statistics.record(address, &result);
+        result
+    }
 
     // The trait defines this with "-> impl Future"-style syntax, but the one
     // is just sugar for the other according to Clippy.
@@ -1576,7 +1829,7 @@ impl HostController for Rp2040HostController {
         endpoint: u8,
         max_packet_size: u16,
         interval_ms: u8,
-    ) -> Rp2040InterruptPipe {
+    ) -> Rp2040InterruptPipe<DEVICES> {
         let pipe = self.alloc_pipe(EndpointType::Interrupt).await;
         debug::println!("interrupt_endpoint on pipe {}", pipe.which());
         self.interrupt_pipe(
@@ -1608,4 +1861,87 @@ impl HostController for Rp2040HostController {
             Err(UsbError::TooManyDevices)
         }
     }
+
+    fn statistics(&self, address: u8) -> TransferStatistics {
+        self.statics.statistics.get(address)
+    }
+
+    fn reset_statistics(&self, address: u8) {
+        self.statics.statistics.reset(address);
+    }
+}
+
+/// Reset the whole USB controller block
+///
+/// Only used while the USB hardware is owned by whoever is resetting
+/// it, and only touches the USBCTRL bit of RESETS, in a critical
+/// section so that the read-modify-write can't race with any other.
+fn reset_usbctrl() {
+    critical_section::with(|_| {
+        // SAFETY: see above
+        let resets = unsafe { pac::RESETS::steal() };
+        resets.reset().modify(|_, w| w.usbctrl().set_bit());
+        resets.reset().modify(|_, w| w.usbctrl().clear_bit());
+        while resets.reset_done().read().usbctrl().bit_is_clear() {}
+    });
+}
+
+/// Implementation of [`RoleManager`] for RP2040
+///
+/// In the device role, the hardware is represented by the USB
+/// register blocks from the PAC, just as the HAL's (or any other)
+/// device stack expects to be given them. Each switch resets the whole
+/// USB controller, so neither stack sees any leftover state from the
+/// other.
+///
+/// The same `UsbShared` and `UsbStatics` are reused by each new
+/// [`Rp2040HostController`]; the USB interrupt handler should carry on
+/// calling [`UsbShared::on_irq()`] only while in the host role.
+pub struct Rp2040RoleManager<
+    const PIPES: usize = 15,
+    const DEVICES: usize = 128,
+> {
+    shared: &'static UsbShared,
+    statics: &'static UsbStatics<PIPES, DEVICES>,
+}
+
+impl<const PIPES: usize, const DEVICES: usize>
+    Rp2040RoleManager<PIPES, DEVICES>
+{
+    /// Create a new Rp2040RoleManager
+    ///
+    /// The `shared` and `statics` should be the same ones as were
+    /// passed to [`Rp2040HostController::new()`], if the hardware
+    /// started out in the host role.
+    pub const fn new(
+        shared: &'static UsbShared,
+        statics: &'static UsbStatics<PIPES, DEVICES>,
+    ) -> Self {
+        Self { shared, statics }
+    }
+}
+
+impl<const PIPES: usize, const DEVICES: usize> RoleManager
+    for Rp2040RoleManager<PIPES, DEVICES>
+{
+    type HostController = Rp2040HostController<PIPES, DEVICES>;
+    type DeviceHardware = (pac::USBCTRL_REGS, pac::USBCTRL_DPRAM);
+
+    fn host_to_device(
+        &mut self,
+        host: Self::HostController,
+    ) -> Self::DeviceHardware {
+        let hardware = host.release();
+        reset_usbctrl();
+        hardware
+    }
+
+    fn device_to_host(
+        &mut self,
+        (regs, dpram): Self::DeviceHardware,
+    ) -> Self::HostController {
+        pac::NVIC::mask(pac::Interrupt::USBCTRL_IRQ);
+        reset_usbctrl();
+        Rp2040HostController::start(regs, dpram, self.shared, self.statics)
+    }
 }
@@ -1,5 +1,6 @@
+use crate::cell::Cell;
 use crate::wire::SetupPacket;
-use core::cell::Cell;
+use core::cell::RefCell;
 use core::ops::Deref;
 use futures::Stream;
 
@@ -53,6 +54,8 @@ pub enum UsbError {
     TooManyDevices,
     /// [`UsbDevice::open_in_endpoint()`](crate::usb_bus::UsbDevice::open_in_endpoint) was called with a bogus endpoint number
     NoSuchEndpoint,
+    /// [`UsbBus::reset_device()`](crate::usb_bus::UsbBus::reset_device) was called for a device which is no longer present, or is a hub
+    NoSuchDevice,
 }
 
 /// Connection speed for a USB device
@@ -98,17 +101,24 @@ pub enum DataPhase<'a> {
     Out(&'a [u8]),
     /// No data phase in control transaction
     None,
+    /// IN transaction, filling each of several buffers in turn
+    ///
+    /// This lets, for instance, a descriptor header and its body be
+    /// received directly into separate places.
+    InVectored(&'a mut [&'a mut [u8]]),
+    /// OUT transaction, sending each of several buffers in turn
+    OutVectored(&'a [&'a [u8]]),
 }
 
 impl DataPhase<'_> {
-    /// Is this DataPhase an IN variant?
+    /// Is this DataPhase an IN variant (vectored or not)?
     pub fn is_in(&self) -> bool {
-        matches!(self, DataPhase::In(_))
+        matches!(self, DataPhase::In(_) | DataPhase::InVectored(_))
     }
 
-    /// Is this DataPhase an OUT variant?
+    /// Is this DataPhase an OUT variant (vectored or not)?
     pub fn is_out(&self) -> bool {
-        matches!(self, DataPhase::Out(_))
+        matches!(self, DataPhase::Out(_) | DataPhase::OutVectored(_))
     }
 
     /// Is this DataPhase a no-data variant?
@@ -116,13 +126,109 @@ impl DataPhase<'_> {
         matches!(self, DataPhase::None)
     }
 
-    /// If this DataPhase is an IN variant, call the supplied function
-    /// on the received data
+    /// If this DataPhase is a (non-vectored) IN variant, call the
+    /// supplied function on the received data
     pub fn in_with<F: FnOnce(&mut [u8])>(&mut self, f: F) {
         if let Self::In(x) = self {
             f(x)
         }
     }
+
+    /// Total size of the buffer or buffers, in bytes
+    pub fn len(&self) -> usize {
+        match self {
+            Self::In(x) => x.len(),
+            Self::Out(x) => x.len(),
+            Self::None => 0,
+            Self::InVectored(v) => v.iter().map(|x| x.len()).sum(),
+            Self::OutVectored(v) => v.iter().map(|x| x.len()).sum(),
+        }
+    }
+
+    /// Is the total size of the buffer or buffers zero?
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// If this DataPhase is an IN variant, copy received data into it
+    ///
+    /// The data is placed `offset` bytes into the buffer (or into the
+    /// concatenation of the buffers, if vectored). Returns the number
+    /// of bytes copied, which is less than `data.len()` if the buffer
+    /// is too small, and zero if this isn't an IN variant.
+    pub fn scatter_from(&mut self, offset: usize, data: &[u8]) -> usize {
+        match self {
+            Self::In(x) => scatter(&mut [&mut **x], offset, data),
+            Self::InVectored(v) => scatter(v, offset, data),
+            _ => 0,
+        }
+    }
+
+    /// If this DataPhase is an OUT variant, copy data out of it
+    ///
+    /// The data is taken from `offset` bytes into the buffer (or into
+    /// the concatenation of the buffers, if vectored). Returns the
+    /// number of bytes copied, which is less than `buf.len()` if the
+    /// data runs out, and zero if this isn't an OUT variant.
+    pub fn gather_into(&self, offset: usize, buf: &mut [u8]) -> usize {
+        match self {
+            Self::Out(x) => gather(&[x], offset, buf),
+            Self::OutVectored(v) => gather(v, offset, buf),
+            _ => 0,
+        }
+    }
+}
+
+/// Copy `data` into a list of buffers, starting `offset` bytes in
+///
+/// Returns the number of bytes copied.
+pub(crate) fn scatter(
+    buffers: &mut [&mut [u8]],
+    mut offset: usize,
+    mut data: &[u8],
+) -> usize {
+    let mut copied = 0;
+    for b in buffers.iter_mut() {
+        if data.is_empty() {
+            break;
+        }
+        if offset >= b.len() {
+            offset -= b.len();
+            continue;
+        }
+        let n = core::cmp::min(b.len() - offset, data.len());
+        b[offset..(offset + n)].copy_from_slice(&data[0..n]);
+        data = &data[n..];
+        copied += n;
+        offset = 0;
+    }
+    copied
+}
+
+/// Copy from a list of buffers, starting `offset` bytes in, into `buf`
+///
+/// Returns the number of bytes copied.
+pub(crate) fn gather(
+    buffers: &[&[u8]],
+    mut offset: usize,
+    mut buf: &mut [u8],
+) -> usize {
+    let mut copied = 0;
+    for b in buffers {
+        if buf.is_empty() {
+            break;
+        }
+        if offset >= b.len() {
+            offset -= b.len();
+            continue;
+        }
+        let n = core::cmp::min(b.len() - offset, buf.len());
+        buf[0..n].copy_from_slice(&b[offset..(offset + n)]);
+        buf = &mut buf[n..];
+        copied += n;
+        offset = 0;
+    }
+    copied
 }
 
 /// Is this a fixed-size transfer or variable-size transfer?
@@ -143,6 +249,9 @@ pub enum TransferType {
 }
 
 /// A packet as received on an interrupt IN endpoint
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(PartialEq, Eq)]
 pub struct InterruptPacket {
     /// USB address (1-127) of device from which packet was received
     pub address: u8,
@@ -152,6 +261,13 @@ pub struct InterruptPacket {
     pub size: u8,
     /// Packet contents
     pub data: [u8; 64],
+    /// Data may have been lost before this packet
+    ///
+    /// Set if the host controller's buffers for this endpoint had all
+    /// filled up before the packet was collected, so that the device
+    /// could not be polled as often as it asked to be: any reports it
+    /// had in the meantime were dropped (or merged) at the device end.
+    pub overrun: bool,
 }
 
 impl Default for InterruptPacket {
@@ -171,6 +287,19 @@ impl InterruptPacket {
             endpoint: 0,
             size: 0,
             data: [0u8; 64],
+            overrun: false,
+        }
+    }
+}
+
+impl InterruptPacket {
+    /// Borrow this packet as an [`InterruptPacketView`]
+    pub fn view(&self) -> InterruptPacketView<'_> {
+        InterruptPacketView {
+            address: self.address,
+            endpoint: self.endpoint,
+            data: self,
+            overrun: self.overrun,
         }
     }
 }
@@ -183,6 +312,217 @@ impl Deref for InterruptPacket {
     }
 }
 
+/// A packet received on an interrupt IN endpoint, borrowed in place
+///
+/// Drivers which parse interrupt packets can take one of these
+/// instead of an [`InterruptPacket`], so that they work, without
+/// further copying, both on owned packets (via
+/// [`InterruptPacket::view`]) and on packets still in the host
+/// controller's own buffers.
+#[derive(Copy, Clone)]
+pub struct InterruptPacketView<'a> {
+    /// USB address (1-127) of device from which packet was received
+    pub address: u8,
+    /// Endpoint number on which packet was received
+    pub endpoint: u8,
+    /// Packet contents
+    pub data: &'a [u8],
+    /// Data may have been lost before this packet, see
+    /// [`InterruptPacket::overrun`]
+    pub overrun: bool,
+}
+
+impl Deref for InterruptPacketView<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        self.data
+    }
+}
+
+impl From<InterruptPacketView<'_>> for InterruptPacket {
+    /// Copy a borrowed packet; any data beyond 64 bytes is discarded
+    fn from(view: InterruptPacketView<'_>) -> Self {
+        let size = core::cmp::min(view.data.len(), 64);
+        let mut packet = InterruptPacket {
+            address: view.address,
+            endpoint: view.endpoint,
+            size: size as u8,
+            overrun: view.overrun,
+            ..Default::default()
+        };
+        packet.data[0..size].copy_from_slice(&view.data[0..size]);
+        packet
+    }
+}
+
+/// Counts of transfers to/from a USB device, and of how they failed
+///
+/// Kept per device by each [`HostController`], and obtainable via
+/// [`UsbBus::statistics()`](crate::usb_bus::UsbBus::statistics). CRC
+/// and bit-stuffing errors are detected by the host-controller hardware
+/// and usually indicate poor signal quality (bad cables, long cables,
+/// electrical noise); data-sequence errors and overflows usually
+/// indicate a software problem instead.
+///
+/// The counters are 16-bit; when the transfer count reaches its maximum,
+/// all the counters are halved, so the ratios between them reflect
+/// recent traffic rather than all traffic since the device appeared.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Default, Copy, Clone, PartialEq, Eq)]
+pub struct TransferStatistics {
+    /// Total number of transfers attempted (successful or not)
+    pub transfers: u16,
+    /// Transfers which failed with [`UsbError::CrcError`]
+    pub crc_errors: u16,
+    /// Transfers which failed with [`UsbError::BitStuffError`]
+    pub bit_stuff_errors: u16,
+    /// Transfers which failed with [`UsbError::DataSeqError`]
+    pub data_seq_errors: u16,
+    /// Transfers which failed with [`UsbError::Timeout`]
+    pub timeouts: u16,
+    /// Transfers which failed with [`UsbError::Overflow`]
+    pub overflows: u16,
+    /// Interrupt packets which arrived with [`InterruptPacket::overrun`]
+    /// set, i.e. after earlier data had (probably) been lost
+    pub overruns: u16,
+}
+
+impl TransferStatistics {
+    /// Create a new, empty, set of statistics
+    pub const fn new() -> Self {
+        Self {
+            transfers: 0,
+            crc_errors: 0,
+            bit_stuff_errors: 0,
+            data_seq_errors: 0,
+            timeouts: 0,
+            overflows: 0,
+            overruns: 0,
+        }
+    }
+
+    /// Count a transfer, and its error (if any)
+    pub fn record(&mut self, result: &Result<usize, UsbError>) {
+        if self.transfers == u16::MAX {
+            self.transfers /= 2;
+            self.crc_errors /= 2;
+            self.bit_stuff_errors /= 2;
+            self.data_seq_errors /= 2;
+            self.timeouts /= 2;
+            self.overflows /= 2;
+            self.overruns /= 2;
+        }
+        self.transfers += 1;
+        let counter = match result {
+            Err(UsbError::CrcError) => &mut self.crc_errors,
+            Err(UsbError::BitStuffError) => &mut self.bit_stuff_errors,
+            Err(UsbError::DataSeqError) => &mut self.data_seq_errors,
+            Err(UsbError::Timeout) => &mut self.timeouts,
+            Err(UsbError::Overflow) => &mut self.overflows,
+            _ => return,
+        };
+        *counter = counter.saturating_add(1);
+    }
+
+    /// Count an interrupt packet which arrived after an overrun
+    pub fn record_overrun(&mut self) {
+        self.overruns = self.overruns.saturating_add(1);
+    }
+
+    /// The number of transfers which failed due to (probable) poor signal
+    /// quality, i.e. CRC errors plus bit-stuffing errors
+    pub fn signal_errors(&self) -> u32 {
+        self.crc_errors as u32 + self.bit_stuff_errors as u32
+    }
+}
+
+/// Per-device [`TransferStatistics`] for all possible USB addresses
+///
+/// This is a helper for implementors of [`HostController`]: a
+/// controller can record the result of each transfer here, and return
+/// the results from [`HostController::statistics()`].
+///
+/// The const parameter `N` is how many USB addresses (starting from
+/// zero) statistics are kept for; transfers to devices at higher
+/// addresses aren't counted, and their statistics always read as
+/// empty. The default, 128, covers every possible address; systems
+/// with fewer devices (and so, as addresses are allocated from the
+/// bottom up, lower addresses) can save RAM with a smaller table.
+pub struct StatisticsTable<const N: usize = 128> {
+    devices: critical_section::Mutex<RefCell<[TransferStatistics; N]>>,
+}
+
+impl StatisticsTable {
+    /// Create a new `StatisticsTable` (nb, is const, unlike `default()`)
+    pub const fn new() -> Self {
+        Self::sized()
+    }
+}
+
+impl<const N: usize> StatisticsTable<N> {
+    /// Create a new `StatisticsTable` with as many entries as given
+    /// by its type
+    pub const fn sized() -> Self {
+        Self {
+            devices: critical_section::Mutex::new(RefCell::new(
+                [TransferStatistics::new(); N],
+            )),
+        }
+    }
+
+    /// Count a transfer to/from the device at `address`
+    pub fn record(&self, address: u8, result: &Result<usize, UsbError>) {
+        critical_section::with(|cs| {
+            if let Some(s) =
+                self.devices.borrow_ref_mut(cs).get_mut(address as usize)
+            {
+                s.record(result);
+            }
+        });
+    }
+
+    /// Count an interrupt-endpoint overrun on the device at `address`
+    pub fn record_overrun(&self, address: u8) {
+        critical_section::with(|cs| {
+            if let Some(s) =
+                self.devices.borrow_ref_mut(cs).get_mut(address as usize)
+            {
+                s.record_overrun();
+            }
+        });
+    }
+
+    /// The statistics so far for the device at `address`
+    pub fn get(&self, address: u8) -> TransferStatistics {
+        critical_section::with(|cs| {
+            self.devices
+                .borrow_ref(cs)
+                .get(address as usize)
+                .copied()
+                .unwrap_or_default()
+        })
+    }
+
+    /// Forget the statistics for `address` (e.g., it has been reassigned)
+    pub fn reset(&self, address: u8) {
+        critical_section::with(|cs| {
+            if let Some(s) =
+                self.devices.borrow_ref_mut(cs).get_mut(address as usize)
+            {
+                *s = TransferStatistics::new();
+            }
+        });
+    }
+}
+
+impl Default for StatisticsTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 /// Encapsulating a particular USB hardware host controller
 ///
 /// This trait can be implemented for different USB hardware (e.g.,
@@ -263,6 +603,11 @@ pub trait HostController {
     /// If no interrupt-capable pipes are available when the function is
     /// called, it awaits for one to become available.
     ///
+    /// The endpoint should be polled every `interval_ms` frames
+    /// (typically [`crate::wire::EndpointDescriptor::interval_ms()`]),
+    /// each pipe on its own schedule, so that slowly-polled devices
+    /// don't use more of the bus -- or more power -- than they need.
+    ///
     /// The returned object implements a stream of [`InterruptPacket`] events.
     fn alloc_interrupt_pipe(
         &self,
@@ -288,6 +633,19 @@ pub trait HostController {
         max_packet_size: u16,
         interval_ms: u8,
     ) -> Result<Self::InterruptPipe, UsbError>;
+
+    /// Return the transfer statistics for the device at `address`
+    ///
+    /// Host controllers which don't keep statistics can leave this
+    /// defaulted, which always returns empty statistics.
+    fn statistics(&self, _address: u8) -> TransferStatistics {
+        TransferStatistics::default()
+    }
+
+    /// Forget the transfer statistics for the device at `address`
+    ///
+    /// Called when an address is given to a newly-connected device.
+    fn reset_statistics(&self, _address: u8) {}
 }
 
 #[cfg(all(test, feature = "std"))]
@@ -6,13 +6,33 @@
 #![cfg_attr(docsrs, feature(doc_cfg_hide))]
 #![cfg_attr(docsrs, doc(cfg_hide(doc)))]
 
+#[cfg(feature = "alloc")]
+extern crate alloc;
+
 /// Encapsulates waiting for any one of N resources to become available
 pub mod async_pool;
 
-/// A compact representation of a set of 32 booleans
+/// A compact representation of a set of 128 booleans
 pub mod bitset;
+
+/// Interior mutability, made thread-safe by the `send-futures` feature
+///
+/// In the default configuration, these are just the types from
+/// `core::cell`, so cost nothing. With the `send-futures` feature, they
+/// are instead wrappers around `std::sync::Mutex` offering the same
+/// (small) subset of the `core::cell` API; that makes them `Sync`, so
+/// that references to a [`UsbBus`](crate::usb_bus::UsbBus) -- and hence
+/// its futures -- can be sent between threads.
+pub mod cell;
+
+/// Configuration descriptors parsed into an owned tree (needs `alloc`)
+#[cfg(feature = "alloc")]
+pub mod config_tree;
 mod debug;
 
+/// Waiting for a number of milliseconds, on various executors
+pub mod delay;
+
 /// Example device-drivers for USB devices
 pub mod device;
 
@@ -22,6 +42,9 @@ pub mod host;
 /// Abstraction over host-controller drivers
 pub mod host_controller;
 
+/// Handing USB hardware over between host and device stacks
+pub mod role;
+
 /// Encapsulating the layout of a USB bus
 pub mod topology;
 
@@ -29,8 +52,18 @@ pub mod topology;
 pub mod usb_bus;
 
 /// Data representations straight from the USB standards
+///
+/// These are role-neutral: a host encodes SETUP packets and decodes
+/// descriptors, and a device decodes SETUP packets and encodes
+/// descriptors, using the same types (see [`role`]).
 pub mod wire;
 
 /// A mock host-controller driver, for writing unit tests
 #[cfg(feature = "std")]
 pub mod mocks;
+
+// Used by rtic_delay_provider!, whose expansion can't rely on the
+// application having rtic-monotonics in scope under that name
+#[cfg(feature = "rtic")]
+#[doc(hidden)]
+pub use rtic_monotonics as __rtic_monotonics;
@@ -1,12 +1,12 @@
+use crate::cell::Cell;
 use crate::host_controller::{
-    DataPhase, DeviceStatus, HostController, InterruptPacket, TransferType,
-    UsbError,
+    DataPhase, DeviceStatus, HostController, InterruptPacket, StatisticsTable,
+    TransferStatistics, TransferType, UsbError,
 };
 use crate::wire::SetupPacket;
 use futures::Future;
 use futures::Stream;
 use mockall::mock;
-use std::cell::Cell;
 use std::pin::Pin;
 use std::task::{Context, Poll};
 
@@ -100,18 +100,24 @@ mock! {
 /// Because the lifetimes got icky, the actual Mockall mock is kept as an
 /// inner struct inside this one. So expectations should typically be set
 /// on `mock_controller.inner`, not `mock_controller` itself. All methods
-/// on MockHostController itself just forward straight to the inner struct.
+/// on MockHostController itself just forward straight to the inner struct
+/// (except that transfer results are also recorded in `statistics`, as a
+/// real host controller would).
 pub struct MockHostController {
     /// Mock HostController, for testing purposes
     ///
     /// See src/tests/usb_bus.rs for widespread use of this facility.
     pub inner: MockHostControllerInner,
+
+    /// Per-device transfer statistics
+    pub statistics: StatisticsTable,
 }
 
 impl Default for MockHostController {
     fn default() -> Self {
         Self {
             inner: MockHostControllerInner::new(),
+            statistics: StatisticsTable::new(),
         }
     }
 }
@@ -135,8 +141,17 @@ impl HostController for MockHostController {
         setup: SetupPacket,
         data_phase: DataPhase<'_>,
     ) -> impl core::future::Future<Output = Result<usize, UsbError>> {
-        self.inner
-            .control_transfer(address, packet_size, setup, data_phase)
+        let f = self.inner.control_transfer(
+            address,
+            packet_size,
+            setup,
+            data_phase,
+        );
+        async move {
+            let result = f.await;
+            self.statistics.record(address, &result);
+            result
+        }
     }
 
     fn bulk_in_transfer(
@@ -148,14 +163,19 @@ impl HostController for MockHostController {
         transfer_type: TransferType,
         data_toggle: &Cell<bool>,
     ) -> impl core::future::Future<Output = Result<usize, UsbError>> {
-        self.inner.bulk_in_transfer(
+        let f = self.inner.bulk_in_transfer(
             address,
             endpoint,
             packet_size,
             data,
             transfer_type,
             data_toggle,
-        )
+        );
+        async move {
+            let result = f.await;
+            self.statistics.record(address, &result);
+            result
+        }
     }
 
     fn bulk_out_transfer(
@@ -167,14 +187,19 @@ impl HostController for MockHostController {
         transfer_type: TransferType,
         data_toggle: &Cell<bool>,
     ) -> impl core::future::Future<Output = Result<usize, UsbError>> {
-        self.inner.bulk_out_transfer(
+        let f = self.inner.bulk_out_transfer(
             address,
             endpoint,
             packet_size,
             data,
             transfer_type,
             data_toggle,
-        )
+        );
+        async move {
+            let result = f.await;
+            self.statistics.record(address, &result);
+            result
+        }
     }
 
     fn alloc_interrupt_pipe(
@@ -206,4 +231,12 @@ impl HostController for MockHostController {
             interval_ms,
         )
     }
+
+    fn statistics(&self, address: u8) -> TransferStatistics {
+        self.statistics.get(address)
+    }
+
+    fn reset_statistics(&self, address: u8) {
+        self.statistics.reset(address);
+    }
 }
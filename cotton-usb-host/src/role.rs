@@ -0,0 +1,49 @@
+use crate::host_controller::HostController;
+
+/// Switching dual-role USB hardware between host and device stacks
+///
+/// Some USB hardware (e.g. RP2040, or any USB OTG controller) can act
+/// as either a host or a device, but not both at once. This trait can
+/// be implemented for such hardware to tear down the
+/// [`HostController`] and hand the hardware over to a device stack
+/// (such as [usb-device](https://crates.io/crates/usb-device)), or to
+/// take the hardware back from a device stack and start a
+/// `HostController` on it again.
+///
+/// Ownership of the hardware passes back and forth through these
+/// calls, so it's never in use by both stacks at once. To switch from
+/// host to device, the [`UsbBus`](crate::usb_bus::UsbBus) must be given
+/// up (see
+/// [`UsbBus::into_host_controller()`](crate::usb_bus::UsbBus::into_host_controller)),
+/// and any interrupt pipes or other host futures dropped, first.
+/// Likewise the device stack must release its hold on the hardware
+/// before switching back.
+///
+/// Deciding *when* to switch -- from the state of an ID pin, say, or
+/// at the user's request -- is up to the application.
+pub trait RoleManager {
+    /// The host-controller driver used in the host role
+    type HostController: HostController;
+
+    /// What a device stack needs in order to drive the hardware
+    type DeviceHardware;
+
+    /// Stop acting as a host, and hand the hardware to a device stack
+    ///
+    /// Any devices attached are disconnected. The hardware is left
+    /// reset, as if the device stack were its first user.
+    fn host_to_device(
+        &mut self,
+        host: Self::HostController,
+    ) -> Self::DeviceHardware;
+
+    /// Take the hardware back from a device stack, and act as a host
+    ///
+    /// The device is disconnected from its host. The returned
+    /// `HostController` starts afresh, as if newly-created; a new
+    /// [`UsbBus`](crate::usb_bus::UsbBus) will need creating around it.
+    fn device_to_host(
+        &mut self,
+        device: Self::DeviceHardware,
+    ) -> Self::HostController;
+}
@@ -70,3 +70,213 @@ fn dealloc_wakes_waker() {
     let r = pf.poll(&mut c);
     assert!(r.is_ready());
 }
+
+#[test]
+fn statistics() {
+    let p = Pool::new(3);
+    assert_eq!(p.total(), 3);
+    assert_eq!(p.available(), 3);
+    {
+        let _p1 = p.try_alloc().unwrap();
+        assert_eq!(p.available(), 2);
+        let _p2 = p.try_alloc().unwrap();
+        assert_eq!(p.available(), 1);
+    }
+    assert_eq!(p.total(), 3);
+    assert_eq!(p.available(), 3);
+}
+
+#[test]
+fn try_alloc_n() {
+    let p = Pool::new(3);
+    {
+        let [a, b] = p.try_alloc_n::<2>().unwrap();
+        assert_eq!(a.which(), 0);
+        assert_eq!(b.which(), 1);
+        assert_eq!(p.available(), 1);
+    }
+    assert_eq!(p.available(), 3);
+}
+
+#[test]
+fn try_alloc_n_is_all_or_nothing() {
+    let p = Pool::new(3);
+    let _p1 = p.try_alloc().unwrap();
+    let _p2 = p.try_alloc().unwrap();
+    assert!(p.try_alloc_n::<2>().is_none());
+    // The one remaining resource wasn't taken by the failed attempt
+    assert_eq!(p.available(), 1);
+    assert!(p.try_alloc().is_some());
+}
+
+#[test]
+fn try_alloc_n_too_many() {
+    let p = Pool::new(2);
+    assert!(p.try_alloc_n::<3>().is_none());
+    assert_eq!(p.available(), 2);
+}
+
+#[test]
+fn alloc_n_waits_for_all() {
+    let p = Pool::new(3);
+    let mut w = MockTestWaker::new();
+    w.expect_wake().return_const(());
+
+    let w = Waker::from(Arc::new(w));
+    let mut c = core::task::Context::from_waker(&w);
+
+    let p1 = p.try_alloc().unwrap();
+    let p2 = p.try_alloc().unwrap();
+    let mut pf = pin!(p.alloc_n::<2>());
+    let r = pf.as_mut().poll(&mut c);
+    assert!(r.is_pending());
+
+    // While waiting, the future holds nothing, so there's no
+    // deadlock with other users
+    assert_eq!(p.available(), 1);
+    let p3 = p.try_alloc().unwrap();
+    drop(p1);
+    let r = pf.as_mut().poll(&mut c);
+    assert!(r.is_pending());
+
+    drop(p2);
+    let Poll::Ready([a, b]) = pf.poll(&mut c) else {
+        panic!("alloc_n should be ready");
+    };
+    assert_eq!(a.which(), 0);
+    assert_eq!(b.which(), 1);
+    assert_eq!(p.available(), 0);
+    drop(p3);
+}
+
+#[test]
+fn alloc_n_cancelled() {
+    let p = Pool::new(2);
+    let mut w = MockTestWaker::new();
+    // The cancelled future's waker may still be woken (spuriously)
+    w.expect_wake().return_const(());
+
+    let w = Waker::from(Arc::new(w));
+    let mut c = core::task::Context::from_waker(&w);
+
+    let p1 = p.try_alloc().unwrap();
+    {
+        let mut pf = pin!(p.alloc_n::<2>());
+        let r = pf.as_mut().poll(&mut c);
+        assert!(r.is_pending());
+        // Future dropped (cancelled) here
+    }
+    assert_eq!(p.available(), 1);
+    drop(p1);
+    assert_eq!(p.available(), 2);
+    assert!(p.try_alloc_n::<2>().is_some());
+}
+
+#[test]
+fn waiting_high_priority_excludes_lower() {
+    let p = Pool::new(1);
+    let mut w = MockTestWaker::new();
+    w.expect_wake().return_const(());
+
+    let w = Waker::from(Arc::new(w));
+    let mut c = core::task::Context::from_waker(&w);
+
+    let p1 = p.try_alloc_with_priority(Priority::Low).unwrap();
+    let mut pf = pin!(p.alloc_with_priority(Priority::High));
+    let r = pf.as_mut().poll(&mut c);
+    assert!(r.is_pending());
+
+    drop(p1);
+    assert_eq!(p.available(), 1);
+    assert!(p.try_alloc_with_priority(Priority::Low).is_none());
+    assert!(p.try_alloc().is_none());
+    assert!(p.try_alloc_n::<1>().is_none());
+
+    let Poll::Ready(pp) = pf.poll(&mut c) else {
+        panic!("high-priority alloc should be ready");
+    };
+    assert_eq!(pp.which(), 0);
+    drop(pp);
+
+    // Once the high-priority caller is served, others get a look in
+    assert!(p.try_alloc_with_priority(Priority::Low).is_some());
+}
+
+#[test]
+fn waiting_low_priority_excludes_nobody() {
+    let p = Pool::new(2);
+    let mut w = MockTestWaker::new();
+    w.expect_wake().return_const(());
+
+    let w = Waker::from(Arc::new(w));
+    let mut c = core::task::Context::from_waker(&w);
+
+    let p1 = p.try_alloc().unwrap();
+    let p2 = p.try_alloc().unwrap();
+    let mut pf = pin!(p.alloc_with_priority(Priority::Low));
+    let r = pf.as_mut().poll(&mut c);
+    assert!(r.is_pending());
+
+    drop(p1);
+    let p3 = p.try_alloc_with_priority(Priority::High).unwrap();
+    let r = pf.as_mut().poll(&mut c);
+    assert!(r.is_pending());
+
+    drop(p2);
+    let r = pf.poll(&mut c);
+    assert!(r.is_ready());
+    drop(p3);
+}
+
+#[test]
+fn equal_priority_not_excluded() {
+    let p = Pool::new(2);
+    let mut w = MockTestWaker::new();
+    w.expect_wake().return_const(());
+
+    let w = Waker::from(Arc::new(w));
+    let mut c = core::task::Context::from_waker(&w);
+
+    let [p1, p2] = p.try_alloc_n::<2>().unwrap();
+    let mut pf = pin!(p.alloc_with_priority(Priority::High));
+    let r = pf.as_mut().poll(&mut c);
+    assert!(r.is_pending());
+
+    drop(p1);
+    assert!(p.try_alloc_with_priority(Priority::High).is_some());
+    drop(p2);
+}
+
+#[test]
+fn cancelled_high_priority_wakes_lower() {
+    let p = Pool::new(1);
+    let mut w = MockTestWaker::new();
+    // Once when the resource is freed, once when the high-priority
+    // waiter is cancelled
+    w.expect_wake().times(2).return_const(());
+    let w = Waker::from(Arc::new(w));
+    let mut c = core::task::Context::from_waker(&w);
+
+    let mut w2 = MockTestWaker::new();
+    w2.expect_wake().times(1).return_const(());
+    let w2 = Waker::from(Arc::new(w2));
+    let mut c2 = core::task::Context::from_waker(&w2);
+
+    let p1 = p.try_alloc().unwrap();
+    let mut low = pin!(p.alloc_with_priority(Priority::Low));
+    {
+        let mut high = pin!(p.alloc_with_priority(Priority::High));
+        let r = high.as_mut().poll(&mut c2);
+        assert!(r.is_pending());
+        let r = low.as_mut().poll(&mut c);
+        assert!(r.is_pending());
+
+        drop(p1);
+        let r = low.as_mut().poll(&mut c);
+        assert!(r.is_pending());
+        // Future dropped (cancelled) here
+    }
+
+    let r = low.poll(&mut c);
+    assert!(r.is_ready());
+}
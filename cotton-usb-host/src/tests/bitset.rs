@@ -46,15 +46,28 @@ fn set_any() {
 #[test]
 fn set_any_final() {
     let mut bs = BitSet::new();
-    bs.0 = 0x7FFF_FFFF;
+    bs.0 = u128::MAX >> 1;
     let n = bs.set_any();
-    assert_eq!(n, Some(31));
+    assert_eq!(n, Some(127));
 }
 
 #[test]
 fn set_any_fail() {
     let mut bs = BitSet::new();
-    bs.0 = 0xFFFF_FFFF;
+    bs.0 = u128::MAX;
     let n = bs.set_any();
     assert_eq!(n, None);
 }
+
+#[test]
+fn high_addresses() {
+    let mut bs = BitSet::new();
+    bs.set(127);
+    bs.set(64);
+    assert!(bs.contains(127));
+    assert!(bs.contains(64));
+    assert!(!bs.contains(31));
+    assert_eq!(bs.iter().collect::<Vec<_>>(), vec![64, 127]);
+    bs.clear(127);
+    assert_eq!(bs.0, 1 << 64);
+}
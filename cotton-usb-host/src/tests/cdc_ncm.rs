@@ -0,0 +1,726 @@
+use super::*;
+use crate::mocks::MockHostController;
+use crate::usb_bus::create_test_device;
+use crate::wire::parse_descriptors;
+use core::future::Future;
+use futures::future;
+use std::pin::pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Wake, Waker};
+
+struct NoOpWaker;
+
+impl Wake for NoOpWaker {
+    fn wake(self: Arc<Self>) {}
+}
+
+fn run<F: Future>(fut: F) -> F::Output {
+    let w = Waker::from(Arc::new(NoOpWaker));
+    let mut c = Context::from_waker(&w);
+    let Poll::Ready(r) = pin!(fut).poll(&mut c) else {
+        panic!("future pended");
+    };
+    r
+}
+
+// An NCM function as Linux's USB network gadget presents it
+const NCM_CONFIG: &[u8] = &[
+    9, 2, 94, 0, 2, 1, 0, 192, 50, // configuration
+    8, 11, 0, 2, 2, 13, 0, 0, // interface association
+    9, 4, 0, 0, 1, 2, 13, 0, 0, // communications interface
+    5, 36, 0, 16, 1, // CDC header
+    5, 36, 6, 0, 1, // union: data interface is 1
+    13, 36, 15, 4, 0, 0, 0, 0, 234, 5, 0, 0,
+    0, // Ethernet, MAC in string 4
+    6, 36, 26, 0, 1, 0, // NCM
+    7, 5, 129, 3, 16, 0, 9, // interrupt IN (notifications)
+    9, 4, 1, 0, 0, 10, 0, 1, 0, // data interface, no endpoints
+    9, 4, 1, 1, 2, 10, 0, 1, 0, // data interface, alternate setting 1
+    7, 5, 130, 2, 64, 0, 0, // bulk IN
+    7, 5, 1, 2, 64, 0, 0, // bulk OUT
+];
+
+// Response to GET_NTB_PARAMETERS: 16K NTBs each way, at most two
+// datagrams per NTB sent to the device
+const NTB_PARAMETERS: [u8; 28] = [
+    28, 0, 1, 0, 0, 64, 0, 0, 4, 0, 0, 0, 4, 0, 0, 0, 0, 64, 0, 0, 4, 0, 0, 0,
+    4, 0, 2, 0,
+];
+
+// The MAC address string descriptor, "0A0B0C0D0E0F"
+const MAC_STRING: [u8; 26] = [
+    26, 3, 48, 0, 65, 0, 48, 0, 66, 0, 48, 0, 67, 0, 48, 0, 68, 0, 48, 0, 69,
+    0, 48, 0, 70, 0,
+];
+
+// An ARP request from the device (192.168.42.129), for 192.168.42.1
+const ARP_REQUEST: [u8; 42] = [
+    0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x02, 0x11, 0x22, 0x33, 0x44, 0x55,
+    0x08, 0x06, 0x00, 0x01, 0x08, 0x00, 0x06, 0x04, 0x00, 0x01, 0x02, 0x11,
+    0x22, 0x33, 0x44, 0x55, 0xc0, 0xa8, 0x2a, 0x81, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0xc0, 0xa8, 0x2a, 0x01,
+];
+
+// An ARP reply to the device, padded to Ethernet's minimum length
+const ARP_REPLY: [u8; 60] = [
+    0x02, 0x11, 0x22, 0x33, 0x44, 0x55, 0x0a, 0x0b, 0x0c, 0x0d, 0x0e, 0x0f,
+    0x08, 0x06, 0x00, 0x01, 0x08, 0x00, 0x06, 0x04, 0x00, 0x02, 0x0a, 0x0b,
+    0x0c, 0x0d, 0x0e, 0x0f, 0xc0, 0xa8, 0x2a, 0x01, 0x02, 0x11, 0x22, 0x33,
+    0x44, 0x55, 0xc0, 0xa8, 0x2a, 0x81, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+// Both frames, in an NTB with its NDP straight after the NTH; this is
+// also what NtbBuilder makes of them, if limited to two datagrams
+const NTB_NDP_FIRST: [u8; 136] = [
+    0x4e, 0x43, 0x4d, 0x48, 0x0c, 0x00, 0x00, 0x00, 0x88, 0x00, 0x0c, 0x00,
+    0x4e, 0x43, 0x4d, 0x30, 0x14, 0x00, 0x00, 0x00, 0x20, 0x00, 0x2a, 0x00,
+    0x4c, 0x00, 0x3c, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0xff, 0xff, 0xff,
+    0xff, 0xff, 0x02, 0x11, 0x22, 0x33, 0x44, 0x55, 0x08, 0x06, 0x00, 0x01,
+    0x08, 0x00, 0x06, 0x04, 0x00, 0x01, 0x02, 0x11, 0x22, 0x33, 0x44, 0x55,
+    0xc0, 0xa8, 0x2a, 0x81, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xc0, 0xa8,
+    0x2a, 0x01, 0x00, 0x00, 0x02, 0x11, 0x22, 0x33, 0x44, 0x55, 0x0a, 0x0b,
+    0x0c, 0x0d, 0x0e, 0x0f, 0x08, 0x06, 0x00, 0x01, 0x08, 0x00, 0x06, 0x04,
+    0x00, 0x02, 0x0a, 0x0b, 0x0c, 0x0d, 0x0e, 0x0f, 0xc0, 0xa8, 0x2a, 0x01,
+    0x02, 0x11, 0x22, 0x33, 0x44, 0x55, 0xc0, 0xa8, 0x2a, 0x81, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00,
+];
+
+// Both frames, in an NTB with its NDP after the datagrams
+const NTB_NDP_LAST: [u8; 136] = [
+    0x4e, 0x43, 0x4d, 0x48, 0x0c, 0x00, 0x07, 0x00, 0x88, 0x00, 0x74, 0x00,
+    0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x02, 0x11, 0x22, 0x33, 0x44, 0x55,
+    0x08, 0x06, 0x00, 0x01, 0x08, 0x00, 0x06, 0x04, 0x00, 0x01, 0x02, 0x11,
+    0x22, 0x33, 0x44, 0x55, 0xc0, 0xa8, 0x2a, 0x81, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0xc0, 0xa8, 0x2a, 0x01, 0x00, 0x00, 0x02, 0x11, 0x22, 0x33,
+    0x44, 0x55, 0x0a, 0x0b, 0x0c, 0x0d, 0x0e, 0x0f, 0x08, 0x06, 0x00, 0x01,
+    0x08, 0x00, 0x06, 0x04, 0x00, 0x02, 0x0a, 0x0b, 0x0c, 0x0d, 0x0e, 0x0f,
+    0xc0, 0xa8, 0x2a, 0x01, 0x02, 0x11, 0x22, 0x33, 0x44, 0x55, 0xc0, 0xa8,
+    0x2a, 0x81, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x4e, 0x43, 0x4d, 0x30,
+    0x14, 0x00, 0x00, 0x00, 0x0c, 0x00, 0x2a, 0x00, 0x38, 0x00, 0x3c, 0x00,
+    0x00, 0x00, 0x00, 0x00,
+];
+
+// Both frames, in an NTB with two chained NDPs, one frame each
+const NTB_CHAINED: [u8; 148] = [
+    0x4e, 0x43, 0x4d, 0x48, 0x0c, 0x00, 0x34, 0x12, 0x94, 0x00, 0x0c, 0x00,
+    0x4e, 0x43, 0x4d, 0x30, 0x10, 0x00, 0x1c, 0x00, 0x2c, 0x00, 0x2a, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x4e, 0x43, 0x4d, 0x30, 0x10, 0x00, 0x00, 0x00,
+    0x58, 0x00, 0x3c, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0xff, 0xff, 0xff,
+    0xff, 0xff, 0x02, 0x11, 0x22, 0x33, 0x44, 0x55, 0x08, 0x06, 0x00, 0x01,
+    0x08, 0x00, 0x06, 0x04, 0x00, 0x01, 0x02, 0x11, 0x22, 0x33, 0x44, 0x55,
+    0xc0, 0xa8, 0x2a, 0x81, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xc0, 0xa8,
+    0x2a, 0x01, 0x00, 0x00, 0x02, 0x11, 0x22, 0x33, 0x44, 0x55, 0x0a, 0x0b,
+    0x0c, 0x0d, 0x0e, 0x0f, 0x08, 0x06, 0x00, 0x01, 0x08, 0x00, 0x06, 0x04,
+    0x00, 0x02, 0x0a, 0x0b, 0x0c, 0x0d, 0x0e, 0x0f, 0xc0, 0xa8, 0x2a, 0x01,
+    0x02, 0x11, 0x22, 0x33, 0x44, 0x55, 0xc0, 0xa8, 0x2a, 0x81, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00,
+];
+
+fn identify() -> IdentifyCdcNcm {
+    let mut id = IdentifyCdcNcm::default();
+    parse_descriptors(NCM_CONFIG, &mut id);
+    id
+}
+
+fn datagrams(ntb: &[u8]) -> Vec<Vec<u8>> {
+    parse_ntb(ntb).unwrap().map(|d| d.to_vec()).collect()
+}
+
+fn both_frames() -> Vec<Vec<u8>> {
+    vec![ARP_REQUEST.to_vec(), ARP_REPLY.to_vec()]
+}
+
+#[test]
+fn identify_ncm() {
+    let id = identify();
+    assert_eq!(id.identify(), Some(1));
+    assert_eq!(id.control_interface(), Some(0));
+    assert_eq!(id.data_interface(), Some(1));
+    assert_eq!(id.data_alternate_setting(), 1);
+    assert_eq!(id.mac_address_string(), 4);
+    assert_eq!(id.max_segment_size(), 1514);
+    assert_eq!(id.bulk_in_endpoint(), Some(2));
+    assert_eq!(id.bulk_out_endpoint(), Some(1));
+}
+
+#[test]
+fn identify_needs_bulk_endpoints() {
+    let mut id = IdentifyCdcNcm::default();
+    parse_descriptors(&NCM_CONFIG[0..87], &mut id);
+    assert_eq!(id.identify(), None);
+    assert_eq!(id.bulk_in_endpoint(), Some(2));
+    assert_eq!(id.bulk_out_endpoint(), None);
+}
+
+#[test]
+fn identify_ignores_ecm() {
+    let mut config = NCM_CONFIG.to_vec();
+    config[23] = 6; // communications interface is now ECM
+    let mut id = IdentifyCdcNcm::default();
+    parse_descriptors(&config, &mut id);
+    assert_eq!(id.identify(), None);
+}
+
+#[test]
+fn identify_follows_union() {
+    let mut config = NCM_CONFIG.to_vec();
+    config[35] = 2; // union names some other data interface
+    let mut id = IdentifyCdcNcm::default();
+    parse_descriptors(&config, &mut id);
+    assert_eq!(id.identify(), None);
+}
+
+#[test]
+fn identify_without_functional_descriptors() {
+    let mut config = NCM_CONFIG[0..26].to_vec();
+    config.extend_from_slice(&NCM_CONFIG[55..]);
+    config[2] = config.len() as u8;
+    let mut id = IdentifyCdcNcm::default();
+    parse_descriptors(&config, &mut id);
+    assert_eq!(id.identify(), Some(1));
+    assert_eq!(id.data_interface(), Some(1));
+    assert_eq!(id.mac_address_string(), 0);
+    assert_eq!(id.max_segment_size(), 1514);
+}
+
+#[test]
+fn parse_parameters() {
+    let p = NtbParameters::parse(&NTB_PARAMETERS).unwrap();
+    assert_eq!(p.ntb_formats_supported, 1);
+    assert_eq!(p.ntb_in_max_size, 16384);
+    assert_eq!(p.ntb_out_max_size, 16384);
+    assert_eq!(p.ndp_out_divisor, 4);
+    assert_eq!(p.ndp_out_payload_remainder, 0);
+    assert_eq!(p.ndp_out_alignment, 4);
+    assert_eq!(p.ntb_out_max_datagrams, 2);
+    assert_eq!(NtbParameters::parse(&NTB_PARAMETERS[0..27]), None);
+}
+
+#[test]
+fn parse_ndp_first() {
+    assert_eq!(datagrams(&NTB_NDP_FIRST), both_frames());
+}
+
+#[test]
+fn parse_ndp_last() {
+    assert_eq!(datagrams(&NTB_NDP_LAST), both_frames());
+}
+
+#[test]
+fn parse_chained() {
+    assert_eq!(datagrams(&NTB_CHAINED), both_frames());
+}
+
+#[test]
+fn parse_ignores_trailing_bytes() {
+    let mut ntb = NTB_NDP_LAST.to_vec();
+    ntb.extend_from_slice(&[0xFF; 7]);
+    assert_eq!(datagrams(&ntb), both_frames());
+}
+
+#[test]
+fn parse_rejects_bad_header() {
+    assert_eq!(
+        parse_ntb(&NTB_NDP_FIRST[0..11]).err(),
+        Some(NtbError::BadHeader)
+    );
+    // Truncated: shorter than wBlockLength
+    assert_eq!(
+        parse_ntb(&NTB_NDP_FIRST[0..135]).err(),
+        Some(NtbError::BadHeader)
+    );
+    let mut ntb = NTB_NDP_FIRST;
+    ntb[3] = b'X';
+    assert_eq!(parse_ntb(&ntb).err(), Some(NtbError::BadHeader));
+    let mut ntb = NTB_NDP_FIRST;
+    ntb[4] = 16; // wHeaderLength
+    assert_eq!(parse_ntb(&ntb).err(), Some(NtbError::BadHeader));
+}
+
+#[test]
+fn parse_rejects_bad_ndp() {
+    let mut ntb = NTB_NDP_FIRST;
+    ntb[10] = 14; // misaligned
+    assert_eq!(parse_ntb(&ntb).err(), Some(NtbError::BadNdp));
+    let mut ntb = NTB_NDP_FIRST;
+    ntb[15] = b'1'; // with CRCs, which aren't supported
+    assert_eq!(parse_ntb(&ntb).err(), Some(NtbError::BadNdp));
+    let mut ntb = NTB_NDP_LAST;
+    ntb[120] = 24; // wLength runs off the end
+    assert_eq!(parse_ntb(&ntb).err(), Some(NtbError::BadNdp));
+}
+
+#[test]
+fn parse_skips_bad_pointers() {
+    let mut ntb = NTB_NDP_FIRST;
+    ntb[22] = 200; // first datagram now runs off the end
+    assert_eq!(datagrams(&ntb), vec![ARP_REPLY.to_vec()]);
+}
+
+#[test]
+fn parse_stops_at_terminator() {
+    let mut ntb = NTB_NDP_FIRST;
+    ntb[24] = 0; // second entry is now a terminator
+    ntb[25] = 0;
+    assert_eq!(datagrams(&ntb), vec![ARP_REQUEST.to_vec()]);
+}
+
+#[test]
+fn parse_ignores_bad_chain() {
+    let mut ntb = NTB_CHAINED;
+    ntb[28] = 0; // second NDP's signature
+    assert_eq!(datagrams(&ntb), vec![ARP_REQUEST.to_vec()]);
+}
+
+#[test]
+fn parse_limits_looping_chain() {
+    let mut ntb = NTB_CHAINED;
+    ntb[34] = 28; // second NDP chains to itself
+    assert_eq!(parse_ntb(&ntb).unwrap().count(), 8);
+}
+
+fn parameters() -> NtbParameters {
+    NtbParameters::parse(&NTB_PARAMETERS).unwrap()
+}
+
+#[test]
+fn build_matches_example() {
+    let mut buf = [0xAAu8; 2048];
+    let mut b = NtbBuilder::new(&mut buf, &parameters());
+    assert!(b.is_empty());
+    assert!(b.has_room(1514));
+    b.push(ARP_REQUEST.len())
+        .unwrap()
+        .copy_from_slice(&ARP_REQUEST);
+    b.push(ARP_REPLY.len()).unwrap().copy_from_slice(&ARP_REPLY);
+    assert_eq!(b.datagrams(), 2);
+    assert!(!b.has_room(1));
+    assert_eq!(b.push(1), None);
+    assert_eq!(b.finish(), &NTB_NDP_FIRST);
+}
+
+#[test]
+fn build_empty() {
+    let mut buf = [0u8; 2048];
+    let mut b = NtbBuilder::new(&mut buf, &parameters());
+    assert_eq!(b.finish(), &[]);
+    b.clear();
+    b.push(1).unwrap()[0] = 1;
+    assert_eq!(&b.finish()[6..8], &[0, 0]); // still sequence 0
+}
+
+#[test]
+fn build_sequence() {
+    let mut buf = [0u8; 2048];
+    let mut b = NtbBuilder::new(&mut buf, &parameters());
+    b.push(1).unwrap()[0] = 1;
+    assert_eq!(&b.finish()[6..8], &[0, 0]);
+    b.clear();
+    assert!(b.is_empty());
+    b.push(1).unwrap()[0] = 2;
+    let ntb = b.finish();
+    assert_eq!(&ntb[6..8], &[1, 0]);
+    assert_eq!(datagrams(ntb), vec![vec![2]]);
+}
+
+#[test]
+fn build_with_payload_remainder() {
+    let params = NtbParameters {
+        ndp_out_divisor: 8,
+        ndp_out_payload_remainder: 2,
+        ndp_out_alignment: 16,
+        ..Default::default()
+    };
+    let mut buf = [0u8; 2048];
+    let mut b = NtbBuilder::new(&mut buf, &params);
+    b.push(ARP_REQUEST.len())
+        .unwrap()
+        .copy_from_slice(&ARP_REQUEST);
+    b.push(ARP_REPLY.len()).unwrap().copy_from_slice(&ARP_REPLY);
+    let ntb = b.finish();
+    assert_eq!(ntb[10], 16); // wNdpIndex
+    for entry in [24, 28] {
+        assert_eq!(ntb[entry] % 8, 2);
+    }
+    assert_eq!(datagrams(ntb), both_frames());
+}
+
+#[test]
+fn build_limited_by_buffer() {
+    let mut buf = [0u8; 200];
+    let mut b = NtbBuilder::new(&mut buf, &NtbParameters::default());
+    assert!(b.has_room(112));
+    assert!(!b.has_room(113));
+    assert_eq!(b.push(113), None);
+    assert!(b.push(112).is_some());
+    assert_eq!(b.finish().len(), 200);
+}
+
+#[test]
+fn build_limited_by_datagram_count() {
+    let mut buf = [0u8; 2048];
+    let mut b = NtbBuilder::new(&mut buf, &NtbParameters::default());
+    for _ in 0..MAX_DATAGRAMS_PER_NTB {
+        assert!(b.push(10).is_some());
+    }
+    assert_eq!(b.push(10), None);
+    assert_eq!(datagrams(b.finish()).len(), MAX_DATAGRAMS_PER_NTB);
+}
+
+#[test]
+fn mac_address() {
+    assert_eq!(
+        parse_mac_address(&MAC_STRING),
+        Some([0x0A, 0x0B, 0x0C, 0x0D, 0x0E, 0x0F])
+    );
+    assert_eq!(parse_mac_address(&MAC_STRING[0..24]), None);
+    let mut s = MAC_STRING;
+    s[2] = b'G';
+    assert_eq!(parse_mac_address(&s), None);
+}
+
+// Control transfers made, with their OUT data if any
+type Requests = Arc<Mutex<Vec<(SetupPacket, Vec<u8>)>>>;
+
+// Answers the control transfers made by init(), recording them
+fn expect_init(hc: &mut MockHostController, requests: &Requests) {
+    let requests = requests.clone();
+    hc.inner
+        .expect_control_transfer()
+        .returning(move |a, _, s, mut d| {
+            assert_eq!(a, 255);
+            let mut out = Vec::new();
+            if let DataPhase::Out(data) = d {
+                out.extend_from_slice(data);
+            }
+            requests.lock().unwrap().push((s, out));
+            let reply: &[u8] = match s.bRequest {
+                GET_NTB_PARAMETERS => &NTB_PARAMETERS,
+                GET_DESCRIPTOR => &MAC_STRING,
+                _ => &[],
+            };
+            d.in_with(|b| b[0..reply.len()].copy_from_slice(reply));
+            Box::pin(future::ready(Ok(reply.len())))
+        });
+}
+
+fn expect_sends(hc: &mut MockHostController, sent: &Arc<Mutex<Vec<Vec<u8>>>>) {
+    let sent = sent.clone();
+    hc.inner
+        .expect_bulk_out_transfer()
+        .withf(|a, e, _, _, t, _| {
+            *a == 255 && *e == 1 && *t == TransferType::VariableSize
+        })
+        .returning(move |_, _, _, data, _, _| {
+            sent.lock().unwrap().push(data.to_vec());
+            Box::pin(future::ready(Ok(data.len())))
+        });
+}
+
+fn expect_receive(hc: &mut MockHostController, ntb: &'static [u8]) {
+    hc.inner
+        .expect_bulk_in_transfer()
+        .withf(|a, e, _, d, t, _| {
+            *a == 255
+                && *e == 2
+                && d.len() == 2048
+                && *t == TransferType::VariableSize
+        })
+        .returning(move |_, _, _, d, _, _| {
+            d[0..ntb.len()].copy_from_slice(ntb);
+            Box::pin(future::ready(Ok(ntb.len())))
+        });
+}
+
+#[test]
+fn new_needs_endpoints() {
+    let bus = UsbBus::new(MockHostController::default());
+    let device = unsafe { create_test_device(0, 0) };
+    let mut rx = [0u8; 2048];
+    let mut tx = [0u8; 2048];
+    let r = CdcNcm::new(
+        &bus,
+        device,
+        &IdentifyCdcNcm::default(),
+        &mut rx,
+        &mut tx,
+    );
+    assert_eq!(r.err(), Some(UsbError::NoSuchEndpoint));
+}
+
+#[test]
+fn init() {
+    let requests = Arc::new(Mutex::new(Vec::new()));
+    let mut hc = MockHostController::default();
+    expect_init(&mut hc, &requests);
+    let bus = UsbBus::new(hc);
+    let device = unsafe { create_test_device(4, 2) };
+    let mut rx = [0u8; 2048];
+    let mut tx = [0u8; 2048];
+    let mut ncm =
+        CdcNcm::new(&bus, device, &identify(), &mut rx, &mut tx).unwrap();
+
+    assert_eq!(ncm.mac_address(), None);
+    assert_eq!(run(ncm.init()), Ok(()));
+    assert_eq!(ncm.mac_address(), Some([10, 11, 12, 13, 14, 15]));
+    assert_eq!(ncm.max_segment_size(), 1514);
+
+    let requests = requests.lock().unwrap();
+    let summary = requests
+        .iter()
+        .map(|(s, out)| {
+            (
+                s.bmRequestType,
+                s.bRequest,
+                s.wValue,
+                s.wIndex,
+                s.wLength,
+                out.clone(),
+            )
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        summary,
+        [
+            (0xA1, 0x80, 0, 0, 28, vec![]),
+            (0x21, 0x86, 0, 0, 4, vec![0, 8, 0, 0]),
+            (0x80, 6, 0x304, 0x409, 26, vec![]),
+            (0x01, 11, 1, 1, 0, vec![]),
+        ]
+    );
+}
+
+#[test]
+fn init_large_buffer() {
+    let requests = Arc::new(Mutex::new(Vec::new()));
+    let mut hc = MockHostController::default();
+    expect_init(&mut hc, &requests);
+    let bus = UsbBus::new(hc);
+    let device = unsafe { create_test_device(4, 2) };
+    let mut rx = vec![0u8; 20000];
+    let mut tx = [0u8; 2048];
+    let mut ncm =
+        CdcNcm::new(&bus, device, &identify(), &mut rx, &mut tx).unwrap();
+
+    assert_eq!(run(ncm.init()), Ok(()));
+    // No need for SET_NTB_INPUT_SIZE
+    let requests = requests.lock().unwrap();
+    assert_eq!(requests.len(), 3);
+    assert!(requests.iter().all(|(s, _)| s.bRequest != 0x86));
+}
+
+#[test]
+fn init_fails() {
+    let mut hc = MockHostController::default();
+    hc.inner
+        .expect_control_transfer()
+        .returning(|_, _, _, _| Box::pin(future::ready(Err(UsbError::Stall))));
+    let bus = UsbBus::new(hc);
+    let device = unsafe { create_test_device(4, 2) };
+    let mut rx = [0u8; 2048];
+    let mut tx = [0u8; 2048];
+    let mut ncm =
+        CdcNcm::new(&bus, device, &identify(), &mut rx, &mut tx).unwrap();
+
+    assert_eq!(run(ncm.init()), Err(UsbError::Stall));
+}
+
+#[test]
+fn init_bad_parameters() {
+    let mut hc = MockHostController::default();
+    hc.inner
+        .expect_control_transfer()
+        .returning(|_, _, _, mut d| {
+            d.in_with(|b| b[0..10].copy_from_slice(&NTB_PARAMETERS[0..10]));
+            Box::pin(future::ready(Ok(10)))
+        });
+    let bus = UsbBus::new(hc);
+    let device = unsafe { create_test_device(4, 2) };
+    let mut rx = [0u8; 2048];
+    let mut tx = [0u8; 2048];
+    let mut ncm =
+        CdcNcm::new(&bus, device, &identify(), &mut rx, &mut tx).unwrap();
+
+    assert_eq!(run(ncm.init()), Err(UsbError::ProtocolError));
+}
+
+#[test]
+fn receive() {
+    let mut hc = MockHostController::default();
+    expect_receive(&mut hc, &NTB_NDP_LAST);
+    let bus = UsbBus::new(hc);
+    let device = unsafe { create_test_device(4, 2) };
+    let mut rx = [0u8; 2048];
+    let mut tx = [0u8; 2048];
+    let mut ncm =
+        CdcNcm::new(&bus, device, &identify(), &mut rx, &mut tx).unwrap();
+
+    assert_eq!(ncm.next_datagram(), None);
+    assert_eq!(run(ncm.read_ntb()), Ok(()));
+    assert_eq!(ncm.next_datagram().unwrap(), &ARP_REQUEST);
+    assert_eq!(ncm.next_datagram().unwrap(), &ARP_REPLY);
+    assert_eq!(ncm.next_datagram(), None);
+}
+
+#[test]
+fn receive_bad_ntb() {
+    let mut hc = MockHostController::default();
+    expect_receive(&mut hc, &ARP_REQUEST);
+    let bus = UsbBus::new(hc);
+    let device = unsafe { create_test_device(4, 2) };
+    let mut rx = [0u8; 2048];
+    let mut tx = [0u8; 2048];
+    let mut ncm =
+        CdcNcm::new(&bus, device, &identify(), &mut rx, &mut tx).unwrap();
+
+    assert_eq!(run(ncm.read_ntb()), Err(UsbError::ProtocolError));
+    assert_eq!(ncm.next_datagram(), None);
+}
+
+#[test]
+fn transmit() {
+    let sent = Arc::new(Mutex::new(Vec::new()));
+    let requests = Arc::new(Mutex::new(Vec::new()));
+    let mut hc = MockHostController::default();
+    expect_init(&mut hc, &requests);
+    expect_sends(&mut hc, &sent);
+    let bus = UsbBus::new(hc);
+    let device = unsafe { create_test_device(4, 2) };
+    let mut rx = [0u8; 2048];
+    let mut tx = [0u8; 2048];
+    let mut ncm =
+        CdcNcm::new(&bus, device, &identify(), &mut rx, &mut tx).unwrap();
+    assert_eq!(run(ncm.init()), Ok(()));
+
+    assert_eq!(run(ncm.flush()), Ok(()));
+    assert!(sent.lock().unwrap().is_empty());
+
+    assert!(ncm.queue_datagram(&ARP_REQUEST));
+    assert!(ncm.queue_datagram(&ARP_REPLY));
+    assert!(!ncm.queue_datagram(&ARP_REPLY));
+    assert_eq!(run(ncm.flush()), Ok(()));
+    assert!(ncm.queue_datagram(&ARP_REPLY));
+    assert_eq!(run(ncm.flush()), Ok(()));
+
+    let sent = sent.lock().unwrap();
+    assert_eq!(sent.len(), 2);
+    assert_eq!(sent[0], NTB_NDP_FIRST);
+    assert_eq!(&sent[1][6..8], &[1, 0]);
+    assert_eq!(datagrams(&sent[1]), vec![ARP_REPLY.to_vec()]);
+}
+
+#[test]
+fn transmit_fails() {
+    let mut hc = MockHostController::default();
+    hc.inner
+        .expect_bulk_out_transfer()
+        .returning(|_, _, _, _, _, _| {
+            Box::pin(future::ready(Err(UsbError::Timeout)))
+        });
+    let bus = UsbBus::new(hc);
+    let device = unsafe { create_test_device(4, 2) };
+    let mut rx = [0u8; 2048];
+    let mut tx = [0u8; 2048];
+    let mut ncm =
+        CdcNcm::new(&bus, device, &identify(), &mut rx, &mut tx).unwrap();
+
+    assert!(ncm.queue_datagram(&ARP_REQUEST));
+    assert_eq!(run(ncm.flush()), Err(UsbError::Timeout));
+    // The NTB is dropped, not retried
+    assert_eq!(run(ncm.flush()), Ok(()));
+}
+
+#[cfg(feature = "smoltcp")]
+mod smoltcp_device {
+    use super::*;
+    use smoltcp::phy::{Device, Medium, RxToken, TxToken};
+    use smoltcp::time::Instant;
+
+    #[test]
+    fn capabilities() {
+        let bus = UsbBus::new(MockHostController::default());
+        let device = unsafe { create_test_device(4, 2) };
+        let mut rx = [0u8; 2048];
+        let mut tx = [0u8; 2048];
+        let ncm =
+            CdcNcm::new(&bus, device, &identify(), &mut rx, &mut tx).unwrap();
+
+        let caps = ncm.capabilities();
+        assert_eq!(caps.max_transmission_unit, 1514);
+        assert_eq!(caps.medium, Medium::Ethernet);
+    }
+
+    #[test]
+    fn receive_and_transmit() {
+        let now = Instant::from_millis(0);
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let mut hc = MockHostController::default();
+        expect_init(&mut hc, &requests);
+        expect_sends(&mut hc, &sent);
+        expect_receive(&mut hc, &NTB_NDP_LAST);
+        let bus = UsbBus::new(hc);
+        let device = unsafe { create_test_device(4, 2) };
+        let mut rx = [0u8; 2048];
+        let mut tx = [0u8; 2048];
+        let mut ncm =
+            CdcNcm::new(&bus, device, &identify(), &mut rx, &mut tx).unwrap();
+        assert_eq!(run(ncm.init()), Ok(()));
+
+        assert!(ncm.receive(now).is_none());
+        assert_eq!(run(ncm.read_ntb()), Ok(()));
+
+        let tx = ncm.transmit(now).unwrap();
+        tx.consume(ARP_REQUEST.len(), |b| b.copy_from_slice(&ARP_REQUEST));
+
+        let (rx, tx) = ncm.receive(now).unwrap();
+        assert_eq!(rx.consume(|b| b.to_vec()), ARP_REQUEST);
+        tx.consume(ARP_REPLY.len(), |b| b.copy_from_slice(&ARP_REPLY));
+
+        // The NTB is full (the device takes two datagrams at a time), so
+        // nothing more can happen until it's sent
+        assert!(ncm.transmit(now).is_none());
+        assert!(ncm.receive(now).is_none());
+
+        assert_eq!(run(ncm.flush()), Ok(()));
+        assert_eq!(*sent.lock().unwrap(), [NTB_NDP_FIRST.to_vec()]);
+
+        let (rx, _) = ncm.receive(now).unwrap();
+        assert_eq!(rx.consume(|b| b.to_vec()), ARP_REPLY);
+        assert!(ncm.receive(now).is_none());
+        assert!(ncm.transmit(now).is_some());
+    }
+
+    #[test]
+    #[cfg_attr(debug_assertions, should_panic(expected = "doesn't fit"))]
+    fn oversized_frame_dropped() {
+        let now = Instant::from_millis(0);
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let mut hc = MockHostController::default();
+        expect_init(&mut hc, &requests);
+        let bus = UsbBus::new(hc);
+        let device = unsafe { create_test_device(4, 2) };
+        let mut rx = [0u8; 2048];
+        // Bigger than the device's 16K NTBs, so there's somewhere to
+        // put a frame which doesn't fit
+        let mut tx = vec![0u8; 20000];
+        let mut ncm =
+            CdcNcm::new(&bus, device, &identify(), &mut rx, &mut tx).unwrap();
+        assert_eq!(run(ncm.init()), Ok(()));
+
+        // Bigger than the MTU, which smoltcp should never ask for
+        let tx = ncm.transmit(now).unwrap();
+        let len = tx.consume(17000, |b| {
+            b.fill(0xAA);
+            b.len()
+        });
+        assert_eq!(len, 17000);
+
+        // ...so it isn't sent
+        assert_eq!(run(ncm.flush()), Ok(()));
+    }
+}
@@ -0,0 +1,130 @@
+use super::*;
+use crate::wire::parse_descriptors;
+
+#[rustfmt::skip]
+const COMPOSITE: &[u8] = &[
+    // Configuration 1, two interfaces
+    9, 2, 71, 0, 2, 1, 0, 0x80, 50,
+    // Interface association: interfaces 0-1, audio
+    8, 11, 0, 2, 1, 0, 0, 0,
+    // Interface 0 alt 0: audio control, one endpoint
+    9, 4, 0, 0, 1, 1, 1, 0, 0,
+    // Class-specific AC header
+    9, 36, 1, 0, 1, 9, 0, 1, 1,
+    // Endpoint 0x81 interrupt
+    7, 5, 0x81, 3, 8, 0, 8,
+    // Class-specific endpoint
+    7, 37, 1, 0, 0, 0, 0,
+    // Interface 1 alt 0: audio streaming, zero-bandwidth
+    9, 4, 1, 0, 0, 1, 2, 0, 0,
+    // Interface 1 alt 1: one endpoint
+    9, 4, 1, 1, 1, 1, 2, 0, 0,
+    // Endpoint 0x02 isochronous
+    7, 5, 0x02, 1, 0, 1, 1,
+];
+
+fn tree(bytes: &[u8]) -> ConfigurationTree {
+    let mut t = ConfigurationTree::default();
+    parse_descriptors(bytes, &mut t);
+    t
+}
+
+#[test]
+fn empty() {
+    let t = tree(&[]);
+    assert!(t.configurations.is_empty());
+}
+
+#[test]
+fn composite() {
+    let t = tree(COMPOSITE);
+    assert_eq!(t.configurations.len(), 1);
+    let c = &t.configurations[0];
+    assert_eq!(c.descriptor.bConfigurationValue, 1);
+    assert_eq!(c.interfaces.len(), 2);
+
+    let i0 = c.interface(0).unwrap();
+    assert_eq!(i0.alternates.len(), 1);
+    let a = i0.alternate(0).unwrap();
+    assert_eq!(a.descriptor.bInterfaceClass, 1);
+    assert_eq!(a.endpoints.len(), 1);
+    assert_eq!(a.endpoints[0].descriptor.bEndpointAddress, 0x81);
+
+    let i1 = c.interface(1).unwrap();
+    assert_eq!(i1.alternates.len(), 2);
+    assert!(i1.alternate(0).unwrap().endpoints.is_empty());
+    let a = i1.alternate(1).unwrap();
+    assert_eq!(a.endpoints.len(), 1);
+    assert_eq!(a.endpoints[0].descriptor.bEndpointAddress, 0x02);
+
+    assert!(c.interface(2).is_none());
+    assert!(i1.alternate(2).is_none());
+}
+
+#[test]
+fn extra_descriptors_attached() {
+    let t = tree(COMPOSITE);
+    let c = &t.configurations[0];
+
+    // The interface association goes with the configuration
+    assert_eq!(c.extra, vec![vec![8, 11, 0, 2, 1, 0, 0, 0]]);
+
+    let a = c.interface(0).unwrap().alternate(0).unwrap();
+    assert_eq!(a.extra, vec![vec![9, 36, 1, 0, 1, 9, 0, 1, 1]]);
+    assert_eq!(a.endpoints[0].extra, vec![vec![7, 37, 1, 0, 0, 0, 0]]);
+
+    let a = c.interface(1).unwrap().alternate(1).unwrap();
+    assert!(a.extra.is_empty());
+    assert!(a.endpoints[0].extra.is_empty());
+}
+
+#[test]
+fn association_after_endpoint_goes_to_configuration() {
+    let mut bytes = COMPOSITE.to_vec();
+    bytes.extend_from_slice(&[8, 11, 2, 1, 3, 0, 0, 0]);
+    let t = tree(&bytes);
+    let c = &t.configurations[0];
+    assert_eq!(c.extra.len(), 2);
+    let a = c.interface(1).unwrap().alternate(1).unwrap();
+    assert!(a.endpoints[0].extra.is_empty());
+}
+
+#[test]
+fn descriptors_before_configuration_ignored() {
+    let mut bytes = vec![
+        9, 4, 0, 0, 1, 1, 1, 0, 0, // interface
+        7, 5, 0x81, 3, 8, 0, 8, // endpoint
+        4, 36, 1, 0, // other
+    ];
+    bytes.extend_from_slice(COMPOSITE);
+    let t = tree(&bytes);
+    assert_eq!(t.configurations.len(), 1);
+    assert_eq!(t.configurations[0].interfaces.len(), 2);
+    assert_eq!(t.configurations[0].extra.len(), 1);
+}
+
+#[test]
+fn endpoint_without_interface_ignored() {
+    let t = tree(&[9, 2, 16, 0, 0, 1, 0, 0x80, 50, 7, 5, 0x81, 3, 8, 0, 8]);
+    assert_eq!(t.configurations.len(), 1);
+    assert!(t.configurations[0].interfaces.is_empty());
+}
+
+#[test]
+fn two_configurations() {
+    let mut bytes = COMPOSITE.to_vec();
+    bytes.extend_from_slice(&[9, 2, 18, 0, 1, 2, 0, 0x80, 50]);
+    bytes.extend_from_slice(&[9, 4, 0, 0, 0, 255, 0, 0, 0]);
+    let t = tree(&bytes);
+    assert_eq!(t.configurations.len(), 2);
+    assert_eq!(t.configurations[1].descriptor.bConfigurationValue, 2);
+    assert_eq!(t.configurations[1].interfaces.len(), 1);
+    assert_eq!(t.configurations[0].interfaces.len(), 2);
+}
+
+#[test]
+fn can_clone_and_debug() {
+    let t = tree(COMPOSITE);
+    let u = t.clone();
+    assert_eq!(format!("{:?}", t), format!("{:?}", u));
+}
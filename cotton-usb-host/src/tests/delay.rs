@@ -0,0 +1,131 @@
+use super::*;
+use futures::future;
+use std::cell::RefCell;
+use std::pin::pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake, Waker};
+
+struct NoOpWaker;
+
+impl Wake for NoOpWaker {
+    fn wake(self: Arc<Self>) {}
+}
+
+fn run<F: Future>(fut: F) -> F::Output {
+    let w = Waker::from(Arc::new(NoOpWaker));
+    let mut c = Context::from_waker(&w);
+    let Poll::Ready(r) = pin!(fut).poll(&mut c) else {
+        panic!("future pended");
+    };
+    r
+}
+
+fn wait_twice<P: DelayProvider>(delay: &P) {
+    run(delay.delay_ms(50));
+    run(delay.delay_ms(10));
+}
+
+fn no_delay(_ms: usize) -> impl Future<Output = ()> {
+    future::ready(())
+}
+
+#[test]
+fn function_is_provider() {
+    wait_twice(&no_delay);
+}
+
+#[test]
+fn closure_is_provider() {
+    let requests = RefCell::new(Vec::new());
+    wait_twice(&|ms| {
+        requests.borrow_mut().push(ms);
+        future::ready(())
+    });
+    assert_eq!(*requests.borrow(), [50, 10]);
+}
+
+#[test]
+fn busy_wait() {
+    wait_twice(&BusyWait::new(100));
+}
+
+#[test]
+fn busy_wait_is_lazy() {
+    // Creating the future mustn't spin (it'd take forever)
+    let delay = BusyWait::new(u32::MAX);
+    drop(delay.delay_ms(usize::MAX));
+}
+
+#[cfg(feature = "rtic")]
+mod rtic {
+    use super::*;
+    use rtic_monotonics::fugit;
+    use rtic_monotonics::{Monotonic, TimeoutError};
+
+    thread_local! {
+        static DELAYS: RefCell<Vec<u64>> = const { RefCell::new(Vec::new()) };
+    }
+
+    // Monotonics never actually wait: they just note the requested
+    // delays, in milliseconds
+    macro_rules! fake_monotonic {
+        ($name:ident, $int:ty, $hz:literal) => {
+            struct $name;
+
+            impl Monotonic for $name {
+                type Instant = fugit::Instant<$int, 1, $hz>;
+                type Duration = fugit::Duration<$int, 1, $hz>;
+
+                fn now() -> Self::Instant {
+                    Self::Instant::from_ticks(0)
+                }
+
+                async fn delay(duration: Self::Duration) {
+                    DELAYS.with(|v| {
+                        v.borrow_mut().push(duration.to_millis() as u64)
+                    });
+                }
+
+                async fn delay_until(_instant: Self::Instant) {}
+
+                async fn timeout_at<F: Future>(
+                    _instant: Self::Instant,
+                    future: F,
+                ) -> Result<F::Output, TimeoutError> {
+                    Ok(future.await)
+                }
+
+                async fn timeout_after<F: Future>(
+                    _duration: Self::Duration,
+                    future: F,
+                ) -> Result<F::Output, TimeoutError> {
+                    Ok(future.await)
+                }
+            }
+        };
+    }
+
+    fake_monotonic!(Mono32, u32, 1_000);
+    fake_monotonic!(Mono64, u64, 1_000_000);
+
+    crate::rtic_delay_provider!(Delay32, Mono32);
+    crate::rtic_delay_provider!(pub(crate) Delay64, Mono64);
+
+    #[test]
+    fn rtic_provider_32bit() {
+        wait_twice(&Delay32);
+        DELAYS.with(|v| assert_eq!(*v.borrow(), [50, 10]));
+    }
+
+    #[test]
+    fn rtic_provider_64bit() {
+        wait_twice(&Delay64);
+        DELAYS.with(|v| assert_eq!(*v.borrow(), [50, 10]));
+    }
+
+    #[test]
+    fn rtic_provider_clamps() {
+        run(Delay32.delay_ms(usize::MAX));
+        DELAYS.with(|v| assert_eq!(*v.borrow(), [u32::MAX as u64]));
+    }
+}
@@ -0,0 +1,543 @@
+use super::*;
+use crate::mocks::MockHostController;
+use crate::usb_bus::create_test_device;
+use crate::wire::parse_descriptors;
+use core::future::Future;
+use futures::{future, stream};
+use std::cell::RefCell;
+use std::pin::pin;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Wake, Waker};
+
+struct NoOpWaker;
+
+impl Wake for NoOpWaker {
+    fn wake(self: Arc<Self>) {}
+}
+
+fn run<F: Future>(fut: F) -> F::Output {
+    let w = Waker::from(Arc::new(NoOpWaker));
+    let mut c = Context::from_waker(&w);
+    let Poll::Ready(r) = pin!(fut).poll(&mut c) else {
+        panic!("future pended");
+    };
+    r
+}
+
+fn no_delay(_ms: usize) -> impl Future<Output = ()> {
+    future::ready(())
+}
+
+// A vendor-specific interface, plus a DFU run-time interface (as
+// interface 1) whose functional descriptor follows it
+const RUNTIME_CONFIG: &[u8] = &[
+    9, 2, 43, 0, 2, 1, 0, 128, 250, // configuration
+    9, 4, 0, 0, 1, 255, 0, 3, 0, // vendor-specific interface
+    7, 5, 133, 3, 8, 0, 8, // its endpoint
+    9, 4, 1, 0, 0, 254, 1, 1, 0, // DFU interface
+    9, 33, 13, 200, 0, 0, 4, 16, 1, // DFU functional descriptor
+];
+
+const FUNCTIONAL: DfuFunctionalDescriptor = DfuFunctionalDescriptor {
+    attributes: 0b0101, // CanDnload, ManifestationTolerant
+    detach_timeout_ms: 200,
+    transfer_size: 16,
+    dfu_version: 0x0110,
+};
+
+/// A pretend DFU-mode device
+#[derive(Default)]
+struct FakeDfu {
+    state: u8,
+    status: u8,
+    /// How many times to report dfuDNBUSY before each dfuDNLOAD-IDLE
+    busy_polls: usize,
+    busy_remaining: usize,
+    /// Block number whose download fails with errPROG
+    fail_block: Option<u16>,
+    non_tolerant: bool,
+    blocks: Vec<(u16, Vec<u8>)>,
+    requests: Vec<(u8, u16, u16)>,
+}
+
+impl FakeDfu {
+    fn new() -> Self {
+        Self {
+            state: DfuState::Idle as u8,
+            ..Default::default()
+        }
+    }
+
+    fn handle(
+        &mut self,
+        setup: SetupPacket,
+        mut data: DataPhase,
+    ) -> Result<usize, UsbError> {
+        assert_eq!(setup.bmRequestType & 0x7F, 0x21);
+        assert_eq!(setup.wIndex, 0);
+        self.requests
+            .push((setup.bRequest, setup.wValue, setup.wLength));
+        match setup.bRequest {
+            DFU_DNLOAD => {
+                let DataPhase::Out(bytes) = data else {
+                    panic!("DNLOAD without data phase");
+                };
+                if bytes.is_empty() {
+                    self.state = DfuState::ManifestSync as u8;
+                } else {
+                    self.blocks.push((setup.wValue, bytes.to_vec()));
+                    self.state = DfuState::DownloadSync as u8;
+                    self.busy_remaining = self.busy_polls;
+                    if self.fail_block == Some(setup.wValue) {
+                        self.status = DfuStatusCode::Prog as u8;
+                        self.state = DfuState::Error as u8;
+                    }
+                }
+                Ok(bytes.len())
+            }
+            DFU_GETSTATUS => {
+                const SYNC: u8 = DfuState::DownloadSync as u8;
+                const BUSY: u8 = DfuState::DownloadBusy as u8;
+                const MSYNC: u8 = DfuState::ManifestSync as u8;
+                const MANIFEST: u8 = DfuState::Manifest as u8;
+                let mut timeout = 0u32;
+                self.state = match self.state {
+                    SYNC | BUSY if self.busy_remaining > 0 => {
+                        self.busy_remaining -= 1;
+                        timeout = 0x10203;
+                        BUSY
+                    }
+                    SYNC | BUSY => DfuState::DownloadIdle as u8,
+                    MSYNC => {
+                        timeout = 5;
+                        MANIFEST
+                    }
+                    MANIFEST if self.non_tolerant => {
+                        panic!("polled non-tolerant device in dfuMANIFEST")
+                    }
+                    MANIFEST => DfuState::Idle as u8,
+                    s => s,
+                };
+                let t = timeout.to_le_bytes();
+                data.in_with(|b| {
+                    b.copy_from_slice(&[
+                        self.status,
+                        t[0],
+                        t[1],
+                        t[2],
+                        self.state,
+                        0,
+                    ])
+                });
+                Ok(6)
+            }
+            DFU_GETSTATE => {
+                data.in_with(|b| b[0] = self.state);
+                Ok(1)
+            }
+            DFU_CLRSTATUS | DFU_ABORT => {
+                self.status = 0;
+                self.state = DfuState::Idle as u8;
+                Ok(0)
+            }
+            DFU_DETACH => {
+                self.state = DfuState::AppDetach as u8;
+                Ok(0)
+            }
+            _ => Err(UsbError::Stall),
+        }
+    }
+}
+
+fn with_fake<F: FnOnce(&Dfu<MockHostController>)>(
+    fake: &Arc<Mutex<FakeDfu>>,
+    functional: DfuFunctionalDescriptor,
+    test: F,
+) {
+    let mut hc = MockHostController::default();
+    let f = fake.clone();
+    hc.inner
+        .expect_control_transfer()
+        .returning(move |_, _, setup, data| {
+            let r = f.lock().unwrap().handle(setup, data);
+            Box::pin(future::ready(r))
+        });
+    let bus = UsbBus::new(hc);
+    let device = unsafe { create_test_device(0, 0) };
+    let dfu = Dfu::new(&bus, device, 0, functional);
+    test(&dfu);
+}
+
+#[test]
+fn identify_runtime() {
+    let mut id = IdentifyDfu::default();
+    parse_descriptors(RUNTIME_CONFIG, &mut id);
+    assert_eq!(id.identify(), Some(1));
+    assert_eq!(id.interface(), 1);
+    assert!(!id.is_dfu_mode());
+    let f = id.functional_descriptor().unwrap();
+    assert_eq!(f.transfer_size, 1024);
+    assert_eq!(f.detach_timeout_ms, 200);
+    assert_eq!(f.dfu_version, 0x0110);
+    assert!(f.can_download());
+    assert!(!f.can_upload());
+    assert!(f.manifestation_tolerant());
+    assert!(f.will_detach());
+}
+
+#[test]
+fn identify_not_dfu() {
+    let mut id = IdentifyDfu::default();
+    parse_descriptors(&RUNTIME_CONFIG[0..25], &mut id);
+    assert_eq!(id.identify(), None);
+    assert_eq!(id.functional_descriptor(), None);
+}
+
+#[test]
+fn identify_needs_functional_descriptor() {
+    let mut id = IdentifyDfu::default();
+    parse_descriptors(&RUNTIME_CONFIG[0..34], &mut id);
+    assert_eq!(id.identify(), None);
+}
+
+#[test]
+fn identify_dfu_mode() {
+    let mut config = RUNTIME_CONFIG.to_vec();
+    config[32] = DFU_MODE_PROTOCOL;
+    let mut id = IdentifyDfu::default();
+    parse_descriptors(&config, &mut id);
+    assert_eq!(id.identify(), Some(1));
+    assert!(id.is_dfu_mode());
+}
+
+#[test]
+fn parse_dfu_1_0_descriptor() {
+    let f = DfuFunctionalDescriptor::parse(&[7, 33, 3, 0, 1, 64, 0]).unwrap();
+    assert_eq!(f.dfu_version, 0x0100);
+    assert_eq!(f.detach_timeout_ms, 256);
+    assert_eq!(f.transfer_size, 64);
+    assert!(f.can_upload());
+    assert!(!f.will_detach());
+}
+
+#[test]
+fn parse_rejects_other_descriptors() {
+    assert!(DfuFunctionalDescriptor::parse(&[7, 34, 3, 0, 1, 64, 0]).is_none());
+    assert!(DfuFunctionalDescriptor::parse(&[6, 33, 3, 0, 1, 64]).is_none());
+}
+
+#[test]
+fn detach() {
+    let fake = Arc::new(Mutex::new(FakeDfu::new()));
+    with_fake(&fake, FUNCTIONAL, |dfu| {
+        assert_eq!(run(dfu.detach()), Ok(()));
+    });
+    assert_eq!(fake.lock().unwrap().requests, [(DFU_DETACH, 200, 0)]);
+}
+
+#[test]
+fn get_status() {
+    let fake = Arc::new(Mutex::new(FakeDfu::new()));
+    fake.lock().unwrap().state = DfuState::DownloadSync as u8;
+    fake.lock().unwrap().busy_polls = 1;
+    fake.lock().unwrap().busy_remaining = 1;
+    with_fake(&fake, FUNCTIONAL, |dfu| {
+        assert_eq!(
+            run(dfu.get_status()),
+            Ok(DfuStatus {
+                status: DfuStatusCode::Ok,
+                poll_timeout_ms: 0x10203,
+                state: DfuState::DownloadBusy,
+                string_index: 0,
+            })
+        );
+        assert_eq!(run(dfu.get_state()), Ok(DfuState::DownloadBusy));
+    });
+}
+
+#[test]
+fn get_status_short_response() {
+    let mut hc = MockHostController::default();
+    hc.inner
+        .expect_control_transfer()
+        .returning(|_, _, _, _| Box::pin(future::ready(Ok(5))));
+    let bus = UsbBus::new(hc);
+    let device = unsafe { create_test_device(0, 0) };
+    let dfu = Dfu::new(&bus, device, 0, FUNCTIONAL);
+    assert_eq!(
+        run(dfu.get_status()),
+        Err(DfuError::Usb(UsbError::ProtocolError))
+    );
+}
+
+#[test]
+fn get_status_bogus_state() {
+    let mut hc = MockHostController::default();
+    hc.inner
+        .expect_control_transfer()
+        .returning(|_, _, _, mut d| {
+            d.in_with(|b| b.copy_from_slice(&[0, 0, 0, 0, 11, 0]));
+            Box::pin(future::ready(Ok(6)))
+        });
+    let bus = UsbBus::new(hc);
+    let device = unsafe { create_test_device(0, 0) };
+    let dfu = Dfu::new(&bus, device, 0, FUNCTIONAL);
+    assert_eq!(
+        run(dfu.get_status()),
+        Err(DfuError::Usb(UsbError::ProtocolError))
+    );
+}
+
+#[test]
+fn flash_firmware() {
+    let fake = Arc::new(Mutex::new(FakeDfu::new()));
+    let image: Vec<u8> = (0..40).collect();
+    with_fake(&fake, FUNCTIONAL, |dfu| {
+        // Chunks which don't line up with blocks
+        let chunks = [&image[0..10], &image[10..11], &image[11..40]];
+        let mut buffer = [0u8; 64];
+        assert_eq!(
+            run(dfu.flash_firmware(
+                stream::iter(chunks),
+                &mut buffer,
+                no_delay
+            )),
+            Ok(40)
+        );
+    });
+    let fake = fake.lock().unwrap();
+    assert_eq!(
+        fake.blocks,
+        [
+            (0, image[0..16].to_vec()),
+            (1, image[16..32].to_vec()),
+            (2, image[32..40].to_vec())
+        ]
+    );
+    assert_eq!(fake.requests.last(), Some(&(DFU_GETSTATUS, 0, 6)));
+    assert!(fake.requests.contains(&(DFU_DNLOAD, 3, 0)));
+    assert_eq!(fake.state, DfuState::Idle as u8);
+}
+
+#[test]
+fn flash_firmware_small_buffer() {
+    let fake = Arc::new(Mutex::new(FakeDfu::new()));
+    with_fake(&fake, FUNCTIONAL, |dfu| {
+        let mut buffer = [0u8; 10];
+        assert_eq!(
+            run(dfu.flash_firmware(
+                stream::iter([[7u8; 25]]),
+                &mut buffer,
+                no_delay
+            )),
+            Ok(25)
+        );
+    });
+    let sizes = fake
+        .lock()
+        .unwrap()
+        .blocks
+        .iter()
+        .map(|(_, b)| b.len())
+        .collect::<Vec<_>>();
+    assert_eq!(sizes, [10, 10, 5]);
+}
+
+#[test]
+fn flash_firmware_empty_buffer() {
+    let fake = Arc::new(Mutex::new(FakeDfu::new()));
+    with_fake(&fake, FUNCTIONAL, |dfu| {
+        assert_eq!(
+            run(dfu.flash_firmware(
+                stream::iter([[7u8; 25]]),
+                &mut [],
+                no_delay
+            )),
+            Err(DfuError::Usb(UsbError::BufferTooSmall))
+        );
+    });
+    assert!(fake.lock().unwrap().requests.is_empty());
+}
+
+#[test]
+fn flash_firmware_waits_while_busy() {
+    let fake = Arc::new(Mutex::new(FakeDfu::new()));
+    fake.lock().unwrap().busy_polls = 2;
+    let delays = Rc::new(RefCell::new(Vec::new()));
+    let d2 = delays.clone();
+    with_fake(&fake, FUNCTIONAL, |dfu| {
+        let mut buffer = [0u8; 16];
+        assert_eq!(
+            run(dfu.flash_firmware(
+                stream::iter([[1u8; 16]]),
+                &mut buffer,
+                move |ms| {
+                    d2.borrow_mut().push(ms);
+                    future::ready(())
+                }
+            )),
+            Ok(16)
+        );
+    });
+    // Two busy polls for the block, then one wait in dfuMANIFEST
+    assert_eq!(*delays.borrow(), [0x10203, 0x10203, 5]);
+}
+
+#[test]
+fn flash_firmware_device_error() {
+    let fake = Arc::new(Mutex::new(FakeDfu::new()));
+    fake.lock().unwrap().fail_block = Some(1);
+    with_fake(&fake, FUNCTIONAL, |dfu| {
+        let mut buffer = [0u8; 16];
+        assert_eq!(
+            run(dfu.flash_firmware(
+                stream::iter([[1u8; 40]]),
+                &mut buffer,
+                no_delay
+            )),
+            Err(DfuError::Status(DfuStatusCode::Prog))
+        );
+    });
+    assert_eq!(fake.lock().unwrap().blocks.len(), 2);
+}
+
+#[test]
+fn flash_firmware_clears_error() {
+    let fake = Arc::new(Mutex::new(FakeDfu::new()));
+    fake.lock().unwrap().state = DfuState::Error as u8;
+    fake.lock().unwrap().status = DfuStatusCode::Unknown as u8;
+    with_fake(&fake, FUNCTIONAL, |dfu| {
+        let mut buffer = [0u8; 16];
+        assert_eq!(
+            run(dfu.flash_firmware(
+                stream::iter([[1u8; 4]]),
+                &mut buffer,
+                no_delay
+            )),
+            Ok(4)
+        );
+    });
+    let fake = fake.lock().unwrap();
+    assert_eq!(fake.requests[0], (DFU_GETSTATUS, 0, 6));
+    assert_eq!(fake.requests[1], (DFU_CLRSTATUS, 0, 0));
+    assert_eq!(fake.requests[2], (DFU_GETSTATE, 0, 1));
+}
+
+#[test]
+fn flash_firmware_aborts_previous_download() {
+    let fake = Arc::new(Mutex::new(FakeDfu::new()));
+    fake.lock().unwrap().state = DfuState::DownloadIdle as u8;
+    with_fake(&fake, FUNCTIONAL, |dfu| {
+        let mut buffer = [0u8; 16];
+        assert_eq!(
+            run(dfu.flash_firmware(
+                stream::iter([[1u8; 4]]),
+                &mut buffer,
+                no_delay
+            )),
+            Ok(4)
+        );
+    });
+    assert_eq!(fake.lock().unwrap().requests[1], (DFU_ABORT, 0, 0));
+}
+
+#[test]
+fn flash_firmware_needs_dfu_mode() {
+    let fake = Arc::new(Mutex::new(FakeDfu::new()));
+    fake.lock().unwrap().state = DfuState::AppIdle as u8;
+    with_fake(&fake, FUNCTIONAL, |dfu| {
+        let mut buffer = [0u8; 16];
+        assert_eq!(
+            run(dfu.flash_firmware(
+                stream::iter([[1u8; 4]]),
+                &mut buffer,
+                no_delay
+            )),
+            Err(DfuError::UnexpectedState(DfuState::AppIdle))
+        );
+    });
+    assert!(fake.lock().unwrap().blocks.is_empty());
+}
+
+#[test]
+fn flash_firmware_non_tolerant() {
+    let fake = Arc::new(Mutex::new(FakeDfu::new()));
+    fake.lock().unwrap().non_tolerant = true;
+    let functional = DfuFunctionalDescriptor {
+        attributes: 1,
+        ..FUNCTIONAL
+    };
+    with_fake(&fake, functional, |dfu| {
+        let mut buffer = [0u8; 16];
+        assert_eq!(
+            run(dfu.flash_firmware(
+                stream::iter([[1u8; 4]]),
+                &mut buffer,
+                no_delay
+            )),
+            Ok(4)
+        );
+    });
+    assert_eq!(fake.lock().unwrap().state, DfuState::Manifest as u8);
+}
+
+#[test]
+fn flash_firmware_not_supported() {
+    let fake = Arc::new(Mutex::new(FakeDfu::new()));
+    let functional = DfuFunctionalDescriptor {
+        attributes: 2,
+        ..FUNCTIONAL
+    };
+    with_fake(&fake, functional, |dfu| {
+        let mut buffer = [0u8; 16];
+        assert_eq!(
+            run(dfu.flash_firmware(
+                stream::iter([[1u8; 4]]),
+                &mut buffer,
+                no_delay
+            )),
+            Err(DfuError::DownloadNotSupported)
+        );
+    });
+    assert!(fake.lock().unwrap().requests.is_empty());
+}
+
+#[test]
+fn flash_firmware_usb_error() {
+    let mut hc = MockHostController::default();
+    hc.inner
+        .expect_control_transfer()
+        .returning(|_, _, _, _| Box::pin(future::ready(Err(UsbError::Stall))));
+    let bus = UsbBus::new(hc);
+    let device = unsafe { create_test_device(0, 0) };
+    let dfu = Dfu::new(&bus, device, 0, FUNCTIONAL);
+    let mut buffer = [0u8; 16];
+    assert_eq!(
+        run(dfu.flash_firmware(
+            stream::iter([[1u8; 4]]),
+            &mut buffer,
+            no_delay
+        )),
+        Err(DfuError::Usb(UsbError::Stall))
+    );
+}
+
+#[test]
+fn empty_firmware() {
+    let fake = Arc::new(Mutex::new(FakeDfu::new()));
+    with_fake(&fake, FUNCTIONAL, |dfu| {
+        let mut buffer = [0u8; 16];
+        assert_eq!(
+            run(dfu.flash_firmware(
+                stream::iter(Vec::<Vec<u8>>::new()),
+                &mut buffer,
+                no_delay
+            )),
+            Ok(0)
+        );
+    });
+    let fake = fake.lock().unwrap();
+    assert!(fake.blocks.is_empty());
+    assert!(fake.requests.contains(&(DFU_DNLOAD, 0, 0)));
+}
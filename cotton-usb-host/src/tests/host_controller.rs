@@ -21,6 +21,61 @@ fn packet_deref() {
     assert_eq!((&p)[9], 1);
 }
 
+#[test]
+fn packet_view() {
+    let mut p = InterruptPacket::new();
+    p.address = 3;
+    p.endpoint = 1;
+    p.size = 2;
+    p.data[1] = 7;
+    let v = p.view();
+    assert_eq!(v.address, 3);
+    assert_eq!(v.endpoint, 1);
+    assert_eq!(v.len(), 2);
+    assert_eq!(v[1], 7);
+}
+
+#[test]
+fn packet_from_view() {
+    let data = [1u8, 2, 3];
+    let p = InterruptPacket::from(InterruptPacketView {
+        address: 5,
+        endpoint: 2,
+        data: &data,
+        overrun: false,
+    });
+    assert_eq!(p.address, 5);
+    assert_eq!(p.endpoint, 2);
+    assert_eq!(&*p, &data);
+    assert!(!p.overrun);
+}
+
+#[test]
+fn overrun_survives_view_and_copy() {
+    let data = [1u8, 2, 3];
+    let p = InterruptPacket::from(InterruptPacketView {
+        address: 5,
+        endpoint: 2,
+        data: &data,
+        overrun: true,
+    });
+    assert!(p.overrun);
+    assert!(p.view().overrun);
+    assert!(!InterruptPacket::new().overrun);
+}
+
+#[test]
+fn packet_from_oversize_view() {
+    let data = [1u8; 100];
+    let p = InterruptPacket::from(InterruptPacketView {
+        address: 5,
+        endpoint: 2,
+        data: &data,
+        overrun: false,
+    });
+    assert_eq!(p.size, 64);
+}
+
 fn add_one(b: &mut [u8]) {
     b[0] += 1;
 }
@@ -47,3 +102,184 @@ fn dataphase_accessors() {
     d1.in_with(add_one);
     assert_eq!(b[0], 2); // not IN, nothing added
 }
+
+#[test]
+fn dataphase_vectored_accessors() {
+    let mut b1 = [1u8; 2];
+    let mut b2 = [1u8; 3];
+    let mut v = [&mut b1[..], &mut b2[..]];
+    let mut d1 = DataPhase::InVectored(&mut v);
+    assert!(d1.is_in());
+    assert!(!d1.is_out());
+    assert!(!d1.is_none());
+    assert_eq!(d1.len(), 5);
+    d1.in_with(add_one);
+    assert_eq!(b1, [1, 1]); // not plain IN, nothing added
+
+    let v = [&b2[..], &b2[..]];
+    let d1 = DataPhase::OutVectored(&v);
+    assert!(!d1.is_in());
+    assert!(d1.is_out());
+    assert!(!d1.is_none());
+    assert_eq!(d1.len(), 6);
+    assert!(!d1.is_empty());
+}
+
+#[test]
+fn dataphase_len() {
+    let mut b = [0u8; 4];
+    assert_eq!(DataPhase::In(&mut b).len(), 4);
+    assert_eq!(DataPhase::Out(&b).len(), 4);
+    assert_eq!(DataPhase::None.len(), 0);
+    assert!(DataPhase::None.is_empty());
+    assert!(DataPhase::OutVectored(&[]).is_empty());
+}
+
+#[test]
+fn dataphase_scatter() {
+    let mut b = [0u8; 4];
+    let mut d = DataPhase::In(&mut b);
+    assert_eq!(d.scatter_from(1, &[1, 2]), 2);
+    assert_eq!(d.scatter_from(3, &[3, 4]), 1);
+    assert_eq!(d.scatter_from(5, &[5]), 0);
+    assert_eq!(b, [0, 1, 2, 3]);
+}
+
+#[test]
+fn dataphase_scatter_vectored() {
+    let mut b1 = [0u8; 2];
+    let mut b2 = [0u8; 0];
+    let mut b3 = [0u8; 3];
+    let mut v = [&mut b1[..], &mut b2[..], &mut b3[..]];
+    let mut d = DataPhase::InVectored(&mut v);
+    assert_eq!(d.scatter_from(1, &[1, 2, 3]), 3);
+    assert_eq!(d.scatter_from(4, &[4, 5, 6]), 1);
+    assert_eq!(b1, [0, 1]);
+    assert_eq!(b3, [2, 3, 4]);
+}
+
+#[test]
+fn dataphase_scatter_not_in() {
+    let b = [0u8; 4];
+    assert_eq!(DataPhase::Out(&b).scatter_from(0, &[1]), 0);
+    assert_eq!(DataPhase::None.scatter_from(0, &[1]), 0);
+}
+
+#[test]
+fn dataphase_gather() {
+    let b = [1u8, 2, 3, 4];
+    let d = DataPhase::Out(&b);
+    let mut out = [0u8; 3];
+    assert_eq!(d.gather_into(2, &mut out), 2);
+    assert_eq!(out, [3, 4, 0]);
+    assert_eq!(d.gather_into(4, &mut out), 0);
+}
+
+#[test]
+fn dataphase_gather_vectored() {
+    let v = [&[1u8, 2][..], &[][..], &[3u8, 4, 5][..]];
+    let d = DataPhase::OutVectored(&v);
+    let mut out = [0u8; 3];
+    assert_eq!(d.gather_into(1, &mut out), 3);
+    assert_eq!(out, [2, 3, 4]);
+    assert_eq!(d.gather_into(3, &mut out), 2);
+    assert_eq!(out, [4, 5, 4]);
+}
+
+#[test]
+fn dataphase_gather_not_out() {
+    let mut b = [0u8; 4];
+    let mut out = [0u8; 3];
+    assert_eq!(DataPhase::In(&mut b).gather_into(0, &mut out), 0);
+    assert_eq!(DataPhase::None.gather_into(0, &mut out), 0);
+}
+
+#[test]
+fn statistics_record() {
+    let mut s = TransferStatistics::new();
+    s.record(&Ok(8));
+    s.record(&Err(UsbError::CrcError));
+    s.record(&Err(UsbError::BitStuffError));
+    s.record(&Err(UsbError::DataSeqError));
+    s.record(&Err(UsbError::Timeout));
+    s.record(&Err(UsbError::Overflow));
+    s.record(&Err(UsbError::Stall));
+    assert_eq!(
+        s,
+        TransferStatistics {
+            transfers: 7,
+            crc_errors: 1,
+            bit_stuff_errors: 1,
+            data_seq_errors: 1,
+            timeouts: 1,
+            overflows: 1,
+            overruns: 0,
+        }
+    );
+    assert_eq!(s.signal_errors(), 2);
+}
+
+#[test]
+fn statistics_record_overrun() {
+    let mut s = TransferStatistics::new();
+    s.record(&Ok(8));
+    s.record_overrun();
+    assert_eq!(s.transfers, 1);
+    assert_eq!(s.overruns, 1);
+    s.overruns = u16::MAX;
+    s.record_overrun();
+    assert_eq!(s.overruns, u16::MAX);
+}
+
+#[test]
+fn statistics_decay() {
+    let mut s = TransferStatistics {
+        transfers: u16::MAX,
+        crc_errors: 100,
+        bit_stuff_errors: 51,
+        overruns: 10,
+        ..Default::default()
+    };
+    s.record(&Err(UsbError::CrcError));
+    assert_eq!(s.transfers, u16::MAX / 2 + 1);
+    assert_eq!(s.crc_errors, 51);
+    assert_eq!(s.bit_stuff_errors, 25);
+    assert_eq!(s.overruns, 5);
+}
+
+#[test]
+fn statistics_table() {
+    let t = StatisticsTable::default();
+    t.record(3, &Err(UsbError::CrcError));
+    t.record(3, &Ok(0));
+    t.record(4, &Ok(0));
+    t.record_overrun(4);
+    assert_eq!(t.get(4).overruns, 1);
+    assert_eq!(t.get(3).transfers, 2);
+    assert_eq!(t.get(3).crc_errors, 1);
+    assert_eq!(t.get(4).transfers, 1);
+    t.reset(3);
+    assert_eq!(t.get(3), TransferStatistics::default());
+    assert_eq!(t.get(4).transfers, 1);
+}
+
+#[test]
+fn statistics_table_bogus_address() {
+    let t = StatisticsTable::new();
+    t.record(200, &Err(UsbError::CrcError));
+    t.record_overrun(200);
+    t.reset(200);
+    assert_eq!(t.get(200), TransferStatistics::default());
+}
+
+#[test]
+fn small_statistics_table() {
+    let t = StatisticsTable::<4>::sized();
+    t.record(3, &Err(UsbError::CrcError));
+    assert_eq!(t.get(3).crc_errors, 1);
+    // Beyond the end of the table: not counted
+    t.record(4, &Err(UsbError::CrcError));
+    t.record_overrun(4);
+    t.reset(4);
+    assert_eq!(t.get(4), TransferStatistics::default());
+}
@@ -0,0 +1,492 @@
+use super::*;
+use crate::host_controller::InterruptPacket;
+use crate::mocks::{MockHostController, MockInterruptPipe};
+use crate::usb_bus::create_test_device;
+use crate::wire::parse_descriptors;
+use core::future::Future;
+use futures::future;
+use std::pin::pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Wake, Waker};
+
+struct NoOpWaker;
+
+impl Wake for NoOpWaker {
+    fn wake(self: Arc<Self>) {}
+}
+
+fn run<F: Future>(fut: F) -> F::Output {
+    let w = Waker::from(Arc::new(NoOpWaker));
+    let mut c = Context::from_waker(&w);
+    let Poll::Ready(r) = pin!(fut).poll(&mut c) else {
+        panic!("future pended");
+    };
+    r
+}
+
+// A typical keyboard with media keys: the boot keyboard, and a
+// (non-boot) consumer-control interface
+const KEYBOARD_CONFIG: &[u8] = &[
+    9, 2, 59, 0, 2, 1, 0, 160, 50, // configuration
+    9, 4, 0, 0, 1, 3, 1, 1, 0, // keyboard interface
+    9, 33, 17, 1, 0, 1, 34, 65, 0, // HID
+    7, 5, 129, 3, 8, 0, 10, // interrupt IN
+    9, 4, 1, 0, 1, 3, 0, 0, 0, // consumer-control interface
+    9, 33, 17, 1, 0, 1, 34, 50, 0, // HID
+    7, 5, 130, 3, 4, 0, 10, // interrupt IN
+];
+
+const REPORT_IDLE: [u8; 8] = [0; 8];
+const REPORT_A: [u8; 8] = [0, 0, 4, 0, 0, 0, 0, 0];
+const REPORT_SHIFT_A_B: [u8; 8] = [2, 0, 4, 5, 0, 0, 0, 0];
+const REPORT_CAPS_LOCK: [u8; 8] = [0, 0, 0x39, 0, 0, 0, 0, 0];
+const REPORT_ROLLOVER: [u8; 8] = [0, 0, 1, 1, 1, 1, 1, 1];
+
+fn identify() -> IdentifyKeyboard {
+    let mut id = IdentifyKeyboard::default();
+    parse_descriptors(KEYBOARD_CONFIG, &mut id);
+    id
+}
+
+fn packet(data: &[u8]) -> InterruptPacket {
+    let mut p = InterruptPacket {
+        address: 255,
+        endpoint: 1,
+        size: data.len() as u8,
+        ..Default::default()
+    };
+    p.data[0..data.len()].copy_from_slice(data);
+    p
+}
+
+fn report(modifiers: u8, keys: &[u8]) -> BootReport {
+    let mut r = BootReport {
+        modifiers,
+        ..Default::default()
+    };
+    r.keys[0..keys.len()].copy_from_slice(keys);
+    r
+}
+
+fn press(usage: u8, modifiers: u8, character: Option<char>) -> KeyEvent {
+    KeyEvent {
+        usage,
+        kind: KeyEventKind::Press,
+        modifiers,
+        character,
+    }
+}
+
+fn release(usage: u8, modifiers: u8) -> KeyEvent {
+    KeyEvent {
+        usage,
+        kind: KeyEventKind::Release,
+        modifiers,
+        character: None,
+    }
+}
+
+fn repeat(usage: u8, character: Option<char>) -> KeyEvent {
+    KeyEvent {
+        usage,
+        kind: KeyEventKind::Repeat,
+        modifiers: 0,
+        character,
+    }
+}
+
+#[test]
+fn identify_keyboard() {
+    let id = identify();
+    assert_eq!(id.identify(), Some(1));
+    assert_eq!(id.interface(), 0);
+    let e = id.in_endpoint().unwrap();
+    assert_eq!(e.bEndpointAddress, 0x81);
+    assert_eq!(e.max_packet_size(), 8);
+    assert_eq!(e.bInterval, 10);
+}
+
+#[test]
+fn identify_ignores_non_boot_interfaces() {
+    let mut config = KEYBOARD_CONFIG.to_vec();
+    config[16] = 2; // keyboard interface now claims to be a mouse
+    let mut id = IdentifyKeyboard::default();
+    parse_descriptors(&config, &mut id);
+    assert_eq!(id.identify(), None);
+    assert!(id.in_endpoint().is_none());
+}
+
+#[test]
+fn parse_reports() {
+    assert_eq!(BootReport::parse(&REPORT_IDLE), Some(BootReport::default()));
+    let r = BootReport::parse(&REPORT_SHIFT_A_B).unwrap();
+    assert_eq!(r, report(modifiers::LEFT_SHIFT, &[4, 5]));
+    assert!(r.pressed(4));
+    assert!(r.pressed(5));
+    assert!(!r.pressed(6));
+    assert!(!r.pressed(0));
+    assert_eq!(BootReport::parse(&REPORT_A[0..7]), None);
+    assert_eq!(BootReport::parse(&REPORT_ROLLOVER), None);
+}
+
+#[test]
+fn keymap_tables_complete() {
+    for keymap in [Keymap::US, Keymap::UK] {
+        assert_eq!(keymap.unshifted.chars().count(), 53);
+        assert_eq!(keymap.shifted.chars().count(), 53);
+    }
+}
+
+#[test]
+fn keymap_us() {
+    let k = Keymap::US;
+    assert_eq!(k.character(0x04, 0, 0), Some('a'));
+    assert_eq!(k.character(0x04, modifiers::RIGHT_SHIFT, 0), Some('A'));
+    assert_eq!(k.character(0x1F, modifiers::LEFT_SHIFT, 0), Some('@'));
+    assert_eq!(k.character(0x20, modifiers::LEFT_SHIFT, 0), Some('#'));
+    assert_eq!(k.character(0x28, 0, 0), Some('\n'));
+    assert_eq!(k.character(0x2C, 0, 0), Some(' '));
+    assert_eq!(k.character(0x34, modifiers::LEFT_SHIFT, 0), Some('"'));
+    assert_eq!(k.character(0x35, modifiers::LEFT_SHIFT, 0), Some('~'));
+    assert_eq!(k.character(0x38, modifiers::LEFT_SHIFT, 0), Some('?'));
+    assert_eq!(k.character(0x3A, 0, 0), None); // F1
+}
+
+#[test]
+fn keymap_uk() {
+    let k = Keymap::UK;
+    assert_eq!(k.character(0x04, 0, 0), Some('a'));
+    assert_eq!(k.character(0x1F, modifiers::LEFT_SHIFT, 0), Some('"'));
+    assert_eq!(k.character(0x20, modifiers::LEFT_SHIFT, 0), Some('£'));
+    assert_eq!(k.character(0x32, 0, 0), Some('#'));
+    assert_eq!(k.character(0x32, modifiers::LEFT_SHIFT, 0), Some('~'));
+    assert_eq!(k.character(0x34, modifiers::LEFT_SHIFT, 0), Some('@'));
+    assert_eq!(k.character(0x35, modifiers::LEFT_SHIFT, 0), Some('¬'));
+    assert_eq!(k.character(0x64, 0, 0), Some('\\'));
+    assert_eq!(k.character(0x64, modifiers::LEFT_SHIFT, 0), Some('|'));
+}
+
+#[test]
+fn keymap_caps_lock_affects_only_letters() {
+    let k = Keymap::US;
+    let caps = leds::CAPS_LOCK;
+    assert_eq!(k.character(0x04, 0, caps), Some('A'));
+    assert_eq!(k.character(0x04, modifiers::LEFT_SHIFT, caps), Some('a'));
+    assert_eq!(k.character(0x1E, 0, caps), Some('1'));
+}
+
+#[test]
+fn keymap_control() {
+    let k = Keymap::US;
+    assert_eq!(k.character(0x06, modifiers::LEFT_CTRL, 0), Some('\x03'));
+    assert_eq!(
+        k.character(0x06, modifiers::RIGHT_CTRL | modifiers::LEFT_SHIFT, 0),
+        Some('\x03')
+    );
+    assert_eq!(k.character(0x1E, modifiers::LEFT_CTRL, 0), None);
+    // Alt doesn't affect translation
+    assert_eq!(k.character(0x06, modifiers::LEFT_ALT, 0), Some('c'));
+}
+
+#[test]
+fn keymap_keypad_follows_num_lock() {
+    let k = Keymap::UK;
+    assert_eq!(k.character(0x59, 0, leds::NUM_LOCK), Some('1'));
+    assert_eq!(k.character(0x62, 0, leds::NUM_LOCK), Some('0'));
+    assert_eq!(k.character(0x63, 0, leds::NUM_LOCK), Some('.'));
+    assert_eq!(k.character(0x59, 0, 0), None);
+    assert_eq!(k.character(0x55, 0, 0), Some('*'));
+    assert_eq!(k.character(0x58, 0, 0), Some('\n'));
+}
+
+#[test]
+fn state_presses_and_releases() {
+    let mut s = KeyboardState::new(Keymap::US, None);
+    let ev: Vec<_> = s.update(&report(0, &[4])).collect();
+    assert_eq!(ev, [press(4, 0, Some('a'))]);
+
+    let ev: Vec<_> = s.update(&report(modifiers::LEFT_SHIFT, &[5])).collect();
+    assert_eq!(
+        ev,
+        [
+            release(4, modifiers::LEFT_SHIFT),
+            press(5, modifiers::LEFT_SHIFT, Some('B'))
+        ]
+    );
+    assert_eq!(s.modifiers(), modifiers::LEFT_SHIFT);
+
+    // Modifier changes alone produce no events
+    assert_eq!(s.update(&report(0, &[5])).count(), 0);
+    assert_eq!(s.modifiers(), 0);
+
+    let ev: Vec<_> = s.update(&report(0, &[])).collect();
+    assert_eq!(ev, [release(5, 0)]);
+}
+
+#[test]
+fn state_lock_keys_toggle_leds() {
+    let mut s = KeyboardState::new(Keymap::US, None);
+    let ev: Vec<_> = s.update(&report(0, &[0x39])).collect();
+    assert_eq!(ev, [press(0x39, 0, None)]);
+    assert_eq!(s.leds(), leds::CAPS_LOCK);
+    s.update(&report(0, &[]));
+    assert_eq!(s.leds(), leds::CAPS_LOCK);
+
+    let ev: Vec<_> = s.update(&report(0, &[4])).collect();
+    assert_eq!(ev, [press(4, 0, Some('A'))]);
+
+    s.update(&report(0, &[4, 0x53, 0x47]));
+    assert_eq!(
+        s.leds(),
+        leds::CAPS_LOCK | leds::NUM_LOCK | leds::SCROLL_LOCK
+    );
+    s.update(&report(0, &[]));
+    s.update(&report(0, &[0x39]));
+    assert_eq!(s.leds(), leds::NUM_LOCK | leds::SCROLL_LOCK);
+}
+
+#[test]
+fn state_repeats() {
+    let mut s = KeyboardState::new(
+        Keymap::US,
+        Some(KeyRepeat {
+            delay_ms: 500,
+            interval_ms: 30,
+        }),
+    );
+    assert_eq!(s.tick(1000).count(), 0);
+    s.update(&report(0, &[4]));
+    assert_eq!(s.repeating(), Some(4));
+    assert_eq!(s.tick(490).count(), 0);
+    let ev: Vec<_> = s.tick(20).collect();
+    assert_eq!(ev, [repeat(4, Some('a'))]);
+    assert_eq!(s.tick(10).count(), 0);
+    let ev: Vec<_> = s.tick(20).collect();
+    assert_eq!(ev, [repeat(4, Some('a'))]);
+
+    // The most recently pressed key repeats; releasing another
+    // doesn't stop it
+    s.update(&report(0, &[4, 5]));
+    assert_eq!(s.repeating(), Some(5));
+    s.update(&report(0, &[5]));
+    assert_eq!(s.repeating(), Some(5));
+    assert_eq!(s.tick(30).count(), 0);
+    let ev: Vec<_> = s.tick(470).collect();
+    assert_eq!(ev, [repeat(5, Some('b'))]);
+
+    s.update(&report(0, &[]));
+    assert_eq!(s.repeating(), None);
+    assert_eq!(s.tick(1000).count(), 0);
+}
+
+#[test]
+fn state_lock_keys_dont_repeat() {
+    let mut s = KeyboardState::new(Keymap::US, Some(KeyRepeat::default()));
+    s.update(&report(0, &[0x39]));
+    assert_eq!(s.repeating(), None);
+    assert_eq!(s.tick(1000).count(), 0);
+}
+
+#[test]
+fn state_without_repeat() {
+    let mut s = KeyboardState::new(Keymap::US, None);
+    s.update(&report(0, &[4]));
+    assert_eq!(s.repeating(), None);
+    assert_eq!(s.tick(1000).count(), 0);
+}
+
+#[test]
+fn new_needs_endpoint() {
+    let bus = UsbBus::new(MockHostController::default());
+    let device = unsafe { create_test_device(0, 0) };
+    let r = BootKeyboard::new(&bus, device, &IdentifyKeyboard::default());
+    assert_eq!(r.err(), Some(UsbError::NoSuchEndpoint));
+}
+
+#[test]
+fn init() {
+    let mut hc = MockHostController::default();
+    hc.inner
+        .expect_control_transfer()
+        .withf(|a, _, s, d| {
+            *a == 255
+                && s.bmRequestType == 0x21
+                && s.bRequest == 0x0B
+                && s.wValue == 0
+                && s.wIndex == 0
+                && s.wLength == 0
+                && matches!(d, DataPhase::None)
+        })
+        .times(1)
+        .returning(|_, _, _, _| Box::pin(future::ready(Ok(0))));
+    hc.inner
+        .expect_control_transfer()
+        .withf(|a, _, s, d| {
+            *a == 255
+                && s.bmRequestType == 0x21
+                && s.bRequest == 0x0A
+                && s.wValue == 0
+                && s.wLength == 0
+                && matches!(d, DataPhase::None)
+        })
+        .times(1)
+        .returning(|_, _, _, _| Box::pin(future::ready(Ok(0))));
+    let bus = UsbBus::new(hc);
+    let device = unsafe { create_test_device(2, 2) };
+    let k = BootKeyboard::new(&bus, device, &identify()).unwrap();
+
+    assert_eq!(run(k.init()), Ok(()));
+}
+
+#[test]
+fn init_fails() {
+    let mut hc = MockHostController::default();
+    hc.inner
+        .expect_control_transfer()
+        .returning(|_, _, _, _| Box::pin(future::ready(Err(UsbError::Stall))));
+    let bus = UsbBus::new(hc);
+    let device = unsafe { create_test_device(2, 2) };
+    let k = BootKeyboard::new(&bus, device, &identify()).unwrap();
+
+    assert_eq!(run(k.init()), Err(UsbError::Stall));
+}
+
+fn expect_set_leds(hc: &mut MockHostController, sent: &Arc<Mutex<Vec<u8>>>) {
+    let sent = sent.clone();
+    hc.inner
+        .expect_control_transfer()
+        .withf(|a, _, s, _| {
+            *a == 255
+                && s.bmRequestType == 0x21
+                && s.bRequest == 0x09
+                && s.wValue == 0x200
+                && s.wIndex == 0
+                && s.wLength == 1
+        })
+        .returning(move |_, _, _, d| {
+            let DataPhase::Out(data) = d else {
+                panic!("SET_REPORT not OUT");
+            };
+            sent.lock().unwrap().extend_from_slice(data);
+            Box::pin(future::ready(Ok(1)))
+        });
+}
+
+#[test]
+fn set_leds() {
+    let sent = Arc::new(Mutex::new(Vec::new()));
+    let mut hc = MockHostController::default();
+    expect_set_leds(&mut hc, &sent);
+    let bus = UsbBus::new(hc);
+    let device = unsafe { create_test_device(2, 2) };
+    let k = BootKeyboard::new(&bus, device, &identify()).unwrap();
+
+    assert_eq!(run(k.set_leds(leds::CAPS_LOCK | leds::NUM_LOCK)), Ok(()));
+    assert_eq!(*sent.lock().unwrap(), [3]);
+}
+
+fn expect_reports(hc: &mut MockHostController, reports: &[&[u8]]) {
+    let mut reports: Vec<_> =
+        reports.iter().rev().map(|r| packet(r)).collect();
+    hc.inner
+        .expect_alloc_interrupt_pipe()
+        .withf(|a, e, m, i| *a == 255 && *e == 1 && *m == 8 && *i == 10)
+        .return_once(move |_, _, _, _| {
+            Box::pin(future::ready({
+                let mut ip = MockInterruptPipe::new();
+                ip.expect_poll_next()
+                    .returning(move |_| Poll::Ready(reports.pop()));
+                ip
+            }))
+        });
+}
+
+#[test]
+fn reports() {
+    let w = Waker::from(Arc::new(NoOpWaker));
+    let mut c = Context::from_waker(&w);
+
+    let mut hc = MockHostController::default();
+    expect_reports(&mut hc, &[&REPORT_ROLLOVER, &REPORT_SHIFT_A_B]);
+    let bus = UsbBus::new(hc);
+    let device = unsafe { create_test_device(2, 2) };
+    let k = BootKeyboard::new(&bus, device, &identify()).unwrap();
+
+    let mut s = pin!(k.reports());
+    assert_eq!(
+        s.as_mut().poll_next(&mut c),
+        Poll::Ready(BootReport::parse(&REPORT_SHIFT_A_B))
+    );
+    assert_eq!(s.as_mut().poll_next(&mut c), Poll::Ready(None));
+}
+
+#[test]
+fn events() {
+    let sent = Arc::new(Mutex::new(Vec::new()));
+    let mut hc = MockHostController::default();
+    expect_reports(
+        &mut hc,
+        &[&REPORT_CAPS_LOCK, &REPORT_IDLE, &REPORT_A, &REPORT_IDLE],
+    );
+    expect_set_leds(&mut hc, &sent);
+    let bus = UsbBus::new(hc);
+    let device = unsafe { create_test_device(2, 2) };
+    let k = BootKeyboard::new(&bus, device, &identify()).unwrap();
+
+    let state = KeyboardState::new(Keymap::UK, None);
+    let delay = |_| future::ready(());
+    let events: Vec<_> = run(k.events(state, delay).collect());
+    assert_eq!(
+        events,
+        [
+            press(0x39, 0, None),
+            release(0x39, 0),
+            press(4, 0, Some('A')),
+            release(4, 0),
+        ]
+    );
+    assert_eq!(*sent.lock().unwrap(), [leds::CAPS_LOCK]);
+}
+
+#[test]
+fn events_repeat() {
+    let w = Waker::from(Arc::new(NoOpWaker));
+    let mut c = Context::from_waker(&w);
+
+    let mut hc = MockHostController::default();
+    expect_reports(&mut hc, &[&REPORT_A]);
+    let bus = UsbBus::new(hc);
+    let device = unsafe { create_test_device(2, 2) };
+    let k = BootKeyboard::new(&bus, device, &identify()).unwrap();
+
+    let state = KeyboardState::new(
+        Keymap::US,
+        Some(KeyRepeat {
+            delay_ms: 100,
+            interval_ms: 50,
+        }),
+    );
+    let delays = Arc::new(Mutex::new(Vec::new()));
+    let d2 = delays.clone();
+    let delay = move |ms| {
+        d2.lock().unwrap().push(ms);
+        future::ready(())
+    };
+    let mut s = pin!(k.events(state, delay));
+    let mut events = Vec::new();
+    while events.len() < 3 {
+        let Poll::Ready(Some(e)) = s.as_mut().poll_next(&mut c) else {
+            panic!("stream ended");
+        };
+        events.push(e);
+    }
+    assert_eq!(
+        events,
+        [
+            press(4, 0, Some('a')),
+            repeat(4, Some('a')),
+            repeat(4, Some('a'))
+        ]
+    );
+    assert!(delays.lock().unwrap().iter().all(|ms| *ms == 50));
+}
@@ -12,11 +12,11 @@ fn create() {
 fn one_device() {
     let mut bus = Topology::new();
     let d = bus.device_connect(0, 1, false);
-    assert_eq!(d, Some(31));
-    assert!(bus.is_present(31));
-    assert!(!bus.is_present(30));
+    assert_eq!(d, Some(127));
+    assert!(bus.is_present(127));
+    assert!(!bus.is_present(126));
     let e = format!("{:?}", bus);
-    assert_eq!(e, "0:(31)");
+    assert_eq!(e, "0:(127)");
 }
 
 #[test]
@@ -25,7 +25,7 @@ fn one_hub() {
     let d = bus.device_connect(0, 1, true);
     assert_eq!(d, Some(1));
     assert!(bus.is_present(1));
-    assert!(!bus.is_present(31));
+    assert!(!bus.is_present(127));
     let e = format!("{:?}", bus);
     assert_eq!(e, "0:(1)");
 }
@@ -36,22 +36,22 @@ fn child_device() {
     let d = bus.device_connect(0, 1, true).unwrap();
     assert_eq!(d, 1);
     let dd = bus.device_connect(1, 2, false).unwrap();
-    assert_eq!(dd, 31);
+    assert_eq!(dd, 127);
     assert!(bus.is_present(1));
-    assert!(!bus.is_present(30));
+    assert!(!bus.is_present(126));
     let e = format!("{:?}", bus);
-    assert_eq!(e, "0:(1:(31))");
+    assert_eq!(e, "0:(1:(127))");
 }
 
 #[test]
 fn one_device_disconnect() {
     let mut bus = Topology::new();
     let d = bus.device_connect(0, 1, false);
-    assert_eq!(d, Some(31));
-    assert!(bus.is_present(31));
-    assert!(!bus.is_present(30));
+    assert_eq!(d, Some(127));
+    assert!(bus.is_present(127));
+    assert!(!bus.is_present(126));
     let m = bus.device_disconnect(0, 1);
-    assert_eq!(m.0, 0x8000_0000);
+    assert_eq!(m.0, 1 << 127);
     let e = format!("{:?}", bus);
     assert_eq!(e, "0");
 }
@@ -62,12 +62,12 @@ fn child_device_disconnect() {
     let d = bus.device_connect(0, 1, true).unwrap();
     assert_eq!(d, 1);
     let dd = bus.device_connect(1, 2, false).unwrap();
-    assert_eq!(dd, 31);
+    assert_eq!(dd, 127);
     assert!(bus.is_present(1));
 
     // the child device disappears but the hub is still there
     let m = bus.device_disconnect(1, 2);
-    assert_eq!(m.0, 0x8000_0000);
+    assert_eq!(m.0, 1 << 127);
     let e = format!("{:?}", bus);
     assert_eq!(e, "0:(1)");
 }
@@ -78,11 +78,11 @@ fn child_device_root_disconnect() {
     let d = bus.device_connect(0, 1, true).unwrap();
     assert_eq!(d, 1);
     let dd = bus.device_connect(1, 2, false).unwrap();
-    assert_eq!(dd, 31);
+    assert_eq!(dd, 127);
 
     // the hub disappears, so its child device does too
     let m = bus.device_disconnect(0, 1);
-    assert_eq!(m.0, 0x8000_0002);
+    assert_eq!(m.0, (1 << 127) | 2);
     let e = format!("{:?}", bus);
     assert_eq!(e, "0");
 }
@@ -116,7 +116,7 @@ fn too_many_hubs() {
 }
 
 #[test]
-fn too_many_devices() {
+fn many_devices() {
     let mut bus = Topology::new();
     let mut devices = 0;
     bus.device_connect(0, 15, true);
@@ -130,11 +130,48 @@ fn too_many_devices() {
         }
         devices += 1;
     }
-    assert_eq!(devices, 28); // plus the three hubs, is 31
-    assert_eq!(format!("{:?}", bus), "0:(1:(6 10 14 18 22 26 30) 2:(5 9 13 17 21 25 29) 3:(4 8 12 16 20 24 28) 7 11 15 19 23 27 31)"
+    // Runs out of ports (4 * 15) before running out of addresses; root
+    // ports 13-15 are the hubs themselves
+    assert_eq!(devices, 60);
+    assert_eq!(format!("{:?}", bus), "0:(1:(73 76 79 82 86 90 94 98 102 106 110 114 118 122 126) 2:(72 75 78 81 85 89 93 97 101 105 109 113 117 121 125) 3:(71 74 77 80 84 88 92 96 100 104 108 112 116 120 124) 83 87 91 95 99 103 107 111 115 119 123 127)"
         );
 }
 
+#[test]
+fn too_many_devices() {
+    let mut bus = Topology::new();
+    let mut hubs = 0;
+    while bus.device_connect(0, hubs + 1, true).is_some() {
+        hubs += 1;
+    }
+    assert_eq!(hubs, 15);
+
+    // Fill every port of every hub until addresses run out
+    let mut devices = 0;
+    'outer: for hub in 1..16 {
+        for port in 1..16 {
+            match bus.device_connect(hub, port, false) {
+                Some(address) => {
+                    assert!(address > 15 && address < 128);
+                    devices += 1;
+                }
+                None => break 'outer,
+            }
+        }
+    }
+    assert_eq!(devices, 127 - 15);
+    assert!(bus.is_present(127));
+    assert!(bus.is_present(16));
+    assert!(bus.device_connect(15, 15, false).is_none());
+
+    // Disconnecting a hub releases all the devices downstream of it
+    let m = bus.device_disconnect(0, 1);
+    assert_eq!(m.0.count_ones(), 16);
+    assert!(m.contains(1));
+    assert!(m.contains(127));
+    assert!(bus.device_connect(15, 15, false).is_some());
+}
+
 #[test]
 fn ludicrous_input_rejected() {
     let mut bus = Topology::new();
@@ -142,3 +179,143 @@ fn ludicrous_input_rejected() {
     assert!(bus.device_connect(100, 100, true).is_none());
     assert_eq!(bus.device_disconnect(100, 100).0, 0);
 }
+
+#[test]
+fn restore() {
+    let mut bus = Topology::new();
+    assert!(bus.device_restore(3, 0, 1));
+    assert!(bus.device_restore(100, 3, 2));
+    assert_eq!(bus.parent(100), Some((3, 2)));
+    let e = format!("{:?}", bus);
+    assert_eq!(e, "0:(3:(100))");
+
+    // Restoring the same thing again changes nothing
+    assert!(bus.device_restore(100, 3, 2));
+    // A later connect on the same port gets the restored address
+    assert_eq!(bus.device_connect(3, 2, false), Some(100));
+}
+
+#[test]
+fn restore_conflict_rejected() {
+    let mut bus = Topology::new();
+    assert!(bus.device_restore(3, 0, 1));
+    assert!(!bus.device_restore(3, 0, 2));
+    assert_eq!(bus.parent(3), Some((0, 1)));
+}
+
+#[test]
+fn restore_ludicrous_input_rejected() {
+    let mut bus = Topology::new();
+    assert!(!bus.device_restore(0, 0, 1));
+    assert!(!bus.device_restore(200, 0, 1));
+    assert!(!bus.device_restore(5, 100, 1));
+    assert!(!bus.device_restore(5, 1, 100));
+    assert_eq!(format!("{:?}", bus), "0");
+}
+
+#[test]
+fn hub_power() {
+    let mut bus = Topology::new();
+    let d = bus.device_connect(0, 1, true).unwrap();
+    assert_eq!(bus.hub_power(d), None);
+    bus.set_hub_power(d, HubPower::new(100, 50));
+    let p = bus.hub_power(d).unwrap();
+    assert_eq!(p.power_on_to_power_good_ms(), 100);
+    assert_eq!(p.controller_current_ma(), 50);
+    let dd = bus.device_connect(1, 2, false).unwrap();
+    assert_eq!(dd, 127);
+    let e = format!("{:?}", bus);
+    assert_eq!(e, "0:(1[100ms 50mA]:(127))");
+}
+
+#[test]
+fn hub_power_rounding() {
+    let p = HubPower::new(5, 0);
+    assert_eq!(p.power_on_to_power_good_ms(), 6);
+    let p = HubPower::new(1000, 0);
+    assert_eq!(p.power_on_to_power_good_ms(), 510);
+}
+
+#[test]
+fn hub_power_forgotten_on_disconnect() {
+    let mut bus = Topology::new();
+    let d = bus.device_connect(0, 1, true).unwrap();
+    let dd = bus.device_connect(d, 1, true).unwrap();
+    bus.set_hub_power(d, HubPower::new(100, 50));
+    bus.set_hub_power(dd, HubPower::new(20, 100));
+    bus.device_disconnect(0, 1);
+    assert_eq!(bus.hub_power(d), None);
+    assert_eq!(bus.hub_power(dd), None);
+}
+
+#[test]
+fn hub_power_ludicrous_hub() {
+    let mut bus = Topology::new();
+    bus.set_hub_power(200, HubPower::new(100, 50));
+    assert_eq!(bus.hub_power(200), None);
+}
+
+#[test]
+fn parent() {
+    let mut bus = Topology::new();
+    let d = bus.device_connect(0, 1, true).unwrap();
+    let dd = bus.device_connect(d, 3, false).unwrap();
+    assert_eq!(bus.parent(d), Some((0, 1)));
+    assert_eq!(bus.parent(dd), Some((d, 3)));
+    assert_eq!(bus.parent(5), None);
+    assert_eq!(bus.parent(200), None);
+    bus.device_disconnect(0, 1);
+    assert_eq!(bus.parent(dd), None);
+}
+
+#[test]
+fn port_path_root() {
+    let bus = Topology::new();
+    let p = bus.port_path(0, 1);
+    assert_eq!(p, PortPath::root());
+    assert_eq!(p.ports(), &[1]);
+    assert_eq!(p.port(), 1);
+    assert_eq!(p.hub_address(), 0);
+    assert_eq!(format!("{}", p), "1");
+}
+
+#[test]
+fn port_path_nested() {
+    let mut bus = Topology::new();
+    let h1 = bus.device_connect(0, 1, true).unwrap();
+    let h2 = bus.device_connect(h1, 4, true).unwrap();
+    let p = bus.port_path(h2, 2);
+    assert_eq!(p.ports(), &[1, 4, 2]);
+    assert_eq!(p.port(), 2);
+    assert_eq!(p.hub_address(), h2);
+    assert_eq!(format!("{}", p), "1.4.2");
+}
+
+#[test]
+fn port_path_deepest() {
+    let mut bus = Topology::new();
+    let mut hub = 0;
+    let mut port = 1;
+    for _ in 0..5 {
+        hub = bus.device_connect(hub, port, true).unwrap();
+        port = 2;
+    }
+    let p = bus.port_path(hub, 3);
+    assert_eq!(p.ports(), &[1, 2, 2, 2, 2, 3]);
+    assert_eq!(p.hub_address(), hub);
+    assert_eq!(format!("{}", p), "1.2.2.2.2.3");
+}
+
+#[test]
+fn port_path_too_deep() {
+    let mut bus = Topology::new();
+    let mut hub = 0;
+    let mut port = 1;
+    for _ in 0..10 {
+        hub = bus.device_connect(hub, port, true).unwrap();
+        port = 2;
+    }
+    let p = bus.port_path(hub, 3);
+    assert_eq!(p.ports().len(), 6);
+    assert_eq!(p.port(), 3);
+}
@@ -7,6 +7,8 @@ use crate::wire::{
     EndpointDescriptor, InterfaceDescriptor, ENDPOINT_DESCRIPTOR,
     INTERFACE_DESCRIPTOR, RECIPIENT_ENDPOINT, VENDOR_REQUEST,
 };
+#[cfg(feature = "hubs")]
+use crate::wire::{CLASS_REQUEST, HUB_DESCRIPTOR, RECIPIENT_OTHER};
 use futures::{future, Future};
 use std::pin::{pin, Pin};
 use std::sync::Arc;
@@ -315,8 +317,14 @@ fn control_transfer_ok_with<F: FnMut(&mut [u8]) -> usize>(
     DataPhase,
 ) -> Pin<Box<dyn Future<Output = Result<usize, UsbError>>>> {
     move |_, _, _, mut d| {
+        // Like a real device, send only as much as was asked for
         let mut n = 0;
-        d.in_with(|bytes| n = f(bytes));
+        d.in_with(|bytes| {
+            let mut all = [0u8; 1024];
+            all[0..bytes.len()].copy_from_slice(bytes);
+            n = f(&mut all).min(bytes.len());
+            bytes[0..n].copy_from_slice(&all[0..n]);
+        });
         Box::pin(future::ready(Ok(n)))
     }
 }
@@ -363,6 +371,7 @@ fn bulk_out_ok<const N: usize>(
 
 trait ExtraExpectations {
     fn expect_multi_interrupt_pipe_ignored(&mut self);
+    #[cfg(feature = "hubs")]
     fn expect_add_to_multi_interrupt_pipe(&mut self);
 
     /// Expect a call to get_basic_configuration (for a certain address),
@@ -377,14 +386,17 @@ trait ExtraExpectations {
     /// configuration number) which does a control transfer.
     fn expect_set_configuration<const ADDR: u8, const VALUE: u16>(&mut self);
 
+    #[cfg(feature = "hubs")]
     /// Expect a control transfer to read the hub descriptor from a certain
     /// address.
     fn expect_get_hub_descriptor<const ADDR: u8>(&mut self);
 
+    #[cfg(feature = "hubs")]
     /// Expect a control transfer (for a certain address and port
     /// number) to set a port power feature.
     fn expect_set_port_power<const ADDR: u8, const PORT: u8>(&mut self);
 
+    #[cfg(feature = "hubs")]
     /// Expect a get-port-status command for a specific port, returning a
     /// specific state and changeset.
     fn expect_get_port_status<
@@ -395,10 +407,12 @@ trait ExtraExpectations {
         &mut self,
     );
 
+    #[cfg(feature = "hubs")]
     /// Expect a set-port-feature command for a specific port, enabling a
     /// specific feature.
     fn expect_set_port_feature<const PORT: u8, const FEATURE: u16>(&mut self);
 
+    #[cfg(feature = "hubs")]
     /// Expect a clear-port-feature command for a specific port, clearing a
     /// specific feature.
     fn expect_clear_port_feature<const PORT: u8, const FEATURE: u16>(
@@ -408,7 +422,9 @@ trait ExtraExpectations {
     fn expect_get_device_descriptor_prefix(&mut self);
     fn expect_get_device_descriptor(&mut self);
     fn expect_set_address<const ADDR: u8>(&mut self);
+    #[cfg(feature = "hubs")]
     fn expect_get_device_descriptor_prefix_hub(&mut self);
+    #[cfg(feature = "hubs")]
     fn expect_get_device_descriptor_hub(&mut self);
     fn expect_clear_endpoint_feature<const EP: u8, const FEATURE: u16>(
         &mut self,
@@ -416,6 +432,7 @@ trait ExtraExpectations {
 }
 
 impl ExtraExpectations for MockHostControllerInner {
+    #[cfg(feature = "hubs")]
     fn expect_add_to_multi_interrupt_pipe(&mut self) {
         self.expect_try_alloc_interrupt_pipe()
             .returning(|_, _, _, _| Ok(MockInterruptPipe::new()));
@@ -427,14 +444,14 @@ impl ExtraExpectations for MockHostControllerInner {
 
     fn expect_get_configuration<const ADDR: u8>(&mut self) {
         self.expect_control_transfer()
-            .times(1)
+            .times(2)
             .withf(is_get_configuration_descriptor::<ADDR>)
             .returning(control_transfer_ok_with(example_config_descriptor));
     }
 
     fn expect_get_double_configuration<const ADDR: u8>(&mut self) {
         self.expect_control_transfer()
-            .times(1)
+            .times(2)
             .withf(is_get_configuration_descriptor::<ADDR>)
             .returning(control_transfer_ok_with(double_config_descriptor));
     }
@@ -446,6 +463,7 @@ impl ExtraExpectations for MockHostControllerInner {
             .returning(control_transfer_ok::<0>);
     }
 
+    #[cfg(feature = "hubs")]
     fn expect_get_hub_descriptor<const ADDR: u8>(&mut self) {
         self.expect_control_transfer()
             .times(1)
@@ -453,6 +471,7 @@ impl ExtraExpectations for MockHostControllerInner {
             .returning(control_transfer_ok_with(hub_descriptor));
     }
 
+    #[cfg(feature = "hubs")]
     fn expect_set_port_power<const ADDR: u8, const PORT: u8>(&mut self) {
         self.expect_control_transfer()
             .times(1)
@@ -460,6 +479,7 @@ impl ExtraExpectations for MockHostControllerInner {
             .returning(control_transfer_ok::<0>);
     }
 
+    #[cfg(feature = "hubs")]
     fn expect_get_port_status<
         const PORT: u8,
         const STATE: u16,
@@ -475,6 +495,7 @@ impl ExtraExpectations for MockHostControllerInner {
             ));
     }
 
+    #[cfg(feature = "hubs")]
     fn expect_set_port_feature<const PORT: u8, const FEATURE: u16>(&mut self) {
         self.expect_control_transfer()
             .times(1)
@@ -482,6 +503,7 @@ impl ExtraExpectations for MockHostControllerInner {
             .returning(control_transfer_ok::<0>);
     }
 
+    #[cfg(feature = "hubs")]
     fn expect_clear_port_feature<const PORT: u8, const FEATURE: u16>(
         &mut self,
     ) {
@@ -508,12 +530,14 @@ impl ExtraExpectations for MockHostControllerInner {
             .withf(is_set_address::<ADDR>)
             .returning(control_transfer_ok::<0>);
     }
+    #[cfg(feature = "hubs")]
     fn expect_get_device_descriptor_prefix_hub(&mut self) {
         self.expect_control_transfer()
             .times(1)
             .withf(is_get_device_descriptor::<8>)
             .returning(control_transfer_ok_with(device_descriptor_prefix_hub));
     }
+    #[cfg(feature = "hubs")]
     fn expect_get_device_descriptor_hub(&mut self) {
         self.expect_control_transfer()
             .times(1)
@@ -530,8 +554,15 @@ impl ExtraExpectations for MockHostControllerInner {
     }
 }
 
+/// For tests of what happens when enumeration requests fail
+const NO_CONTROL_RETRIES: ControlRetryPolicy = ControlRetryPolicy {
+    attempts: 1,
+    retry_delay_ms: 0,
+};
+
 struct Fixture<'a> {
     c: &'a mut core::task::Context<'a>,
+    #[cfg(feature = "hubs")]
     hub_state: HubState<MockHostController>,
     bus: UsbBus<MockHostController>,
 }
@@ -552,6 +583,7 @@ fn do_test<
 
     let f = Fixture {
         c: &mut c,
+        #[cfg(feature = "hubs")]
         hub_state: HubState::default(),
         bus: UsbBus::new(hc),
     };
@@ -566,6 +598,16 @@ fn new_bus() {
     let _bus = UsbBus::new(hc);
 }
 
+#[test]
+fn bus_gives_back_host_controller() {
+    let mut hc = MockHostController::default();
+    hc.inner.expect_multi_interrupt_pipe_ignored();
+    let bus = UsbBus::new(hc);
+    let mut hc = bus.into_host_controller();
+    hc.inner.checkpoint();
+    let _bus = UsbBus::new(hc);
+}
+
 #[test]
 fn configure() {
     do_test(
@@ -694,6 +736,47 @@ fn get_basic_configuration() {
     );
 }
 
+#[test]
+fn get_configuration_tree() {
+    do_test(
+        |hc| {
+            hc.expect_multi_interrupt_pipe_ignored();
+            hc.expect_get_configuration::<5>();
+        },
+        |f| {
+            let r = pin!(f.bus.get_configuration_tree(&UNCONFIGURED_DEVICE));
+            let rr = r.poll(f.c);
+            let tree = unwrap_poll(rr).unwrap().unwrap();
+            assert_eq!(tree.configurations.len(), 1);
+            let i = tree.configurations[0].interface(1).unwrap();
+            let endpoints = &i.alternate(0).unwrap().endpoints;
+            assert_eq!(endpoints.len(), 2);
+            assert_eq!(endpoints[1].descriptor.bEndpointAddress, 0x82);
+        },
+    );
+}
+
+#[test]
+fn get_configuration_tree_bad_descriptors() {
+    let w = Waker::from(Arc::new(NoOpWaker));
+    let mut c = core::task::Context::from_waker(&w);
+
+    let mut hc = MockHostController::default();
+    hc.inner.expect_multi_interrupt_pipe_ignored();
+
+    hc.inner
+        .expect_control_transfer()
+        .times(1)
+        .withf(is_get_configuration_descriptor::<5>)
+        .returning(control_transfer_ok::<25>);
+
+    let bus = UsbBus::new(hc);
+
+    let r = pin!(bus.get_configuration_tree(&UNCONFIGURED_DEVICE));
+    let rr = r.poll(&mut c);
+    assert!(matches!(rr, Poll::Ready(Err(UsbError::ProtocolError))));
+}
+
 #[test]
 fn get_basic_configuration_bad_descriptors() {
     let w = Waker::from(Arc::new(NoOpWaker));
@@ -725,7 +808,7 @@ fn get_basic_configuration_bad_configuration_value() {
 
     hc.inner
         .expect_control_transfer()
-        .times(1)
+        .times(2)
         .withf(is_get_configuration_descriptor::<5>)
         .returning(control_transfer_ok_with(|bytes| {
             example_config_descriptor(bytes);
@@ -784,6 +867,137 @@ fn get_basic_configuration_fails() {
     assert_eq!(rr, Poll::Ready(Err(UsbError::Timeout)));
 }
 
+#[test]
+fn get_configuration_large() {
+    do_test(
+        |hc| {
+            hc.expect_multi_interrupt_pipe_ignored();
+            hc.expect_control_transfer()
+                .times(2)
+                .withf(|a, p, s, d| {
+                    is_get_configuration_descriptor::<5>(a, p, s, d)
+                        && (s.wLength == 9 || s.wLength as usize == ELLA.len())
+                })
+                .returning(control_transfer_ok_with(|bytes| {
+                    bytes[0..ELLA.len()].copy_from_slice(ELLA);
+                    ELLA.len()
+                }));
+        },
+        |f| {
+            let r = pin!(f.bus.get_basic_configuration(&UNCONFIGURED_DEVICE));
+            let bc = unwrap_poll(r.poll(f.c)).unwrap().unwrap();
+            // The last endpoint is at offset >400
+            assert_eq!(bc.in_endpoints, 0b111000);
+            assert_eq!(bc.out_endpoints, 0b1100000100);
+        },
+    );
+}
+
+#[test]
+fn get_configuration_is_cached() {
+    do_test(
+        |hc| {
+            hc.expect_multi_interrupt_pipe_ignored();
+            hc.expect_get_configuration::<5>(); // only once
+        },
+        |f| {
+            let r = pin!(f.bus.get_basic_configuration(&UNCONFIGURED_DEVICE));
+            let bc1 = unwrap_poll(r.poll(f.c)).unwrap().unwrap();
+            let r = pin!(f.bus.get_basic_configuration(&UNCONFIGURED_DEVICE));
+            let bc2 = unwrap_poll(r.poll(f.c)).unwrap().unwrap();
+            assert_eq!(bc1, bc2);
+        },
+    );
+}
+
+#[test]
+fn get_configuration_failure_not_cached() {
+    do_test(
+        |hc| {
+            hc.expect_multi_interrupt_pipe_ignored();
+            hc.expect_control_transfer()
+                .times(1)
+                .withf(is_get_configuration_descriptor::<5>)
+                .returning(control_transfer_timeout);
+            hc.expect_get_configuration::<5>();
+        },
+        |f| {
+            let r = pin!(f.bus.get_basic_configuration(&UNCONFIGURED_DEVICE));
+            let rr = unwrap_poll(r.poll(f.c)).unwrap();
+            assert_eq!(rr, Err(UsbError::Timeout));
+            let r = pin!(f.bus.get_basic_configuration(&UNCONFIGURED_DEVICE));
+            let rr = unwrap_poll(r.poll(f.c)).unwrap();
+            assert!(rr.is_ok());
+        },
+    );
+}
+
+#[test]
+fn get_configuration_sized() {
+    let w = Waker::from(Arc::new(NoOpWaker));
+    let mut c = core::task::Context::from_waker(&w);
+    let mut hc = MockHostController::default();
+    hc.inner.expect_multi_interrupt_pipe_ignored();
+    hc.inner
+        .expect_control_transfer()
+        .times(2)
+        .withf(|a, p, s, d| {
+            is_get_configuration_descriptor::<5>(a, p, s, d)
+                && (s.wLength == 9 || s.wLength == 32)
+        })
+        .returning(control_transfer_ok_with(example_config_descriptor));
+    let bus = UsbBus::<MockHostController, 64>::sized(hc);
+
+    let r = pin!(bus.get_basic_configuration(&UNCONFIGURED_DEVICE));
+    let bc = unwrap_poll(r.poll(&mut c)).unwrap().unwrap();
+    assert_eq!(bc.in_endpoints, 0b100);
+}
+
+#[test]
+fn get_configuration_overflow() {
+    let w = Waker::from(Arc::new(NoOpWaker));
+    let mut c = core::task::Context::from_waker(&w);
+    let mut hc = MockHostController::default();
+    hc.inner.expect_multi_interrupt_pipe_ignored();
+    // Only the header is read: the 32-byte set doesn't fit
+    hc.inner
+        .expect_control_transfer()
+        .times(2)
+        .withf(|a, p, s, d| {
+            is_get_configuration_descriptor::<5>(a, p, s, d) && s.wLength == 9
+        })
+        .returning(control_transfer_ok_with(example_config_descriptor));
+    let bus = UsbBus::<MockHostController, 16>::sized(hc);
+
+    let r = pin!(bus.get_basic_configuration(&UNCONFIGURED_DEVICE));
+    let rr = unwrap_poll(r.poll(&mut c)).unwrap();
+    assert_eq!(rr, Err(UsbError::Overflow));
+
+    // Nor is anything cached
+    let r = pin!(bus.get_basic_configuration(&UNCONFIGURED_DEVICE));
+    let rr = unwrap_poll(r.poll(&mut c)).unwrap();
+    assert_eq!(rr, Err(UsbError::Overflow));
+}
+
+#[test]
+fn configure_invalidates_cache() {
+    do_test(
+        |hc| {
+            hc.expect_multi_interrupt_pipe_ignored();
+            hc.expect_get_configuration::<5>();
+            hc.expect_set_configuration::<5, 1>();
+        },
+        |f| {
+            let r = pin!(f.bus.get_basic_configuration(&UNCONFIGURED_DEVICE));
+            unwrap_poll(r.poll(f.c)).unwrap().unwrap();
+            let r = pin!(f.bus.configure(unconfigured_device(), 1));
+            let rr = unwrap_poll(r.poll(f.c)).unwrap();
+            assert!(rr.is_ok());
+            assert!(f.bus.descriptor_cache.borrow().get(5).is_none());
+        },
+    );
+}
+
 fn is_set_address<const N: u8>(
     a: &u8,
     p: &u8,
@@ -819,6 +1033,27 @@ fn set_address() {
     assert!(rr == Poll::Ready(Ok(unconfigured_device())));
 }
 
+#[test]
+fn set_address_invalidates_cache() {
+    do_test(
+        |hc| {
+            hc.expect_multi_interrupt_pipe_ignored();
+            hc.expect_get_configuration::<5>();
+            hc.expect_set_address::<5>();
+        },
+        |f| {
+            let r = pin!(f.bus.get_basic_configuration(&UNCONFIGURED_DEVICE));
+            unwrap_poll(r.poll(f.c)).unwrap().unwrap();
+            assert!(f.bus.descriptor_cache.borrow().get(5).is_some());
+
+            // A new device is given the same address
+            let r = pin!(f.bus.set_address(unaddressed_device(), 5));
+            unwrap_poll(r.poll(f.c)).unwrap().unwrap();
+            assert!(f.bus.descriptor_cache.borrow().get(5).is_none());
+        },
+    );
+}
+
 #[test]
 fn set_address_pends() {
     do_test(
@@ -834,7 +1069,7 @@ fn set_address_pends() {
             assert!(poll.is_pending());
             let poll = fut.as_mut().poll(&mut f.c);
             assert!(poll.is_pending());
-        }
+        },
     );
 }
 
@@ -957,13 +1192,52 @@ fn new_device() {
 
     let bus = UsbBus::new(hc);
 
-    let r = pin!(bus.new_device(UsbSpeed::Full12));
+    let r = pin!(bus.new_device(UsbSpeed::Full12, &no_delay));
     let rr = r.poll(&mut c);
     let (_device, di) = unwrap_poll(rr).unwrap().unwrap();
     assert_eq!(di.vid, 0x1234);
     assert_eq!(di.pid, 0x5678);
 }
 
+fn versioned_device_descriptor(bytes: &mut [u8]) -> usize {
+    device_descriptor(bytes);
+    bytes[2] = 0x10; // bcdUSB 2.1
+    bytes[3] = 0x02;
+    bytes[12] = 0x23; // bcdDevice 1.23
+    bytes[13] = 0x01;
+    bytes[16] = 3; // iSerialNumber
+    18
+}
+
+#[test]
+fn new_device_versions() {
+    let w = Waker::from(Arc::new(NoOpWaker));
+    let mut c = core::task::Context::from_waker(&w);
+
+    let mut hc = MockHostController::default();
+
+    hc.inner
+        .expect_control_transfer()
+        .times(1)
+        .withf(is_get_device_descriptor::<8>)
+        .returning(control_transfer_ok_with(device_descriptor_prefix));
+
+    hc.inner
+        .expect_control_transfer()
+        .times(1)
+        .withf(is_get_device_descriptor::<18>)
+        .returning(control_transfer_ok_with(versioned_device_descriptor));
+
+    let bus = UsbBus::new(hc);
+
+    let r = pin!(bus.new_device(UsbSpeed::Full12, &no_delay));
+    let rr = r.poll(&mut c);
+    let (_device, di) = unwrap_poll(rr).unwrap().unwrap();
+    assert_eq!(di.usb_version, 0x0210);
+    assert_eq!(di.device_release, 0x0123);
+    assert_eq!(di.serial_number_index, 3);
+}
+
 #[test]
 fn new_device_first_call_errors() {
     let w = Waker::from(Arc::new(NoOpWaker));
@@ -980,9 +1254,10 @@ fn new_device_first_call_errors() {
 
     // No second call!
 
-    let bus = UsbBus::new(hc);
+    let mut bus = UsbBus::new(hc);
+    bus.set_control_retry_policy(NO_CONTROL_RETRIES);
 
-    let r = pin!(bus.new_device(UsbSpeed::Full12));
+    let r = pin!(bus.new_device(UsbSpeed::Full12, &no_delay));
     let rr = r.poll(&mut c);
     let rc = unwrap_poll(rr).unwrap();
     assert_eq!(rc.unwrap_err(), UsbError::Timeout);
@@ -1004,9 +1279,10 @@ fn new_device_first_call_short() {
 
     // No second call!
 
-    let bus = UsbBus::new(hc);
+    let mut bus = UsbBus::new(hc);
+    bus.set_control_retry_policy(NO_CONTROL_RETRIES);
 
-    let r = pin!(bus.new_device(UsbSpeed::Full12));
+    let r = pin!(bus.new_device(UsbSpeed::Full12, &no_delay));
     let rr = r.poll(&mut c);
     let rc = unwrap_poll(rr).unwrap();
     assert_eq!(rc.unwrap_err(), UsbError::ProtocolError);
@@ -1033,9 +1309,10 @@ fn new_device_second_call_errors() {
         .withf(is_get_device_descriptor::<18>)
         .returning(control_transfer_timeout);
 
-    let bus = UsbBus::new(hc);
+    let mut bus = UsbBus::new(hc);
+    bus.set_control_retry_policy(NO_CONTROL_RETRIES);
 
-    let r = pin!(bus.new_device(UsbSpeed::Full12));
+    let r = pin!(bus.new_device(UsbSpeed::Full12, &no_delay));
     let rr = r.poll(&mut c);
     let rc = unwrap_poll(rr).unwrap();
     assert_eq!(rc.unwrap_err(), UsbError::Timeout);
@@ -1064,7 +1341,7 @@ fn new_device_second_call_pends() {
 
     let bus = UsbBus::new(hc);
 
-    let mut r = pin!(bus.new_device(UsbSpeed::Full12));
+    let mut r = pin!(bus.new_device(UsbSpeed::Full12, &no_delay));
     let rr = r.as_mut().poll(&mut c);
     assert!(rr.is_pending());
     let rr = r.as_mut().poll(&mut c);
@@ -1092,89 +1369,412 @@ fn new_device_second_call_short() {
         .withf(is_get_device_descriptor::<18>)
         .returning(control_transfer_ok::<17>);
 
-    let bus = UsbBus::new(hc);
+    let mut bus = UsbBus::new(hc);
+    bus.set_control_retry_policy(NO_CONTROL_RETRIES);
 
-    let r = pin!(bus.new_device(UsbSpeed::Full12));
+    let r = pin!(bus.new_device(UsbSpeed::Full12, &no_delay));
     let rr = r.poll(&mut c);
     let rc = unwrap_poll(rr).unwrap();
     assert_eq!(rc.unwrap_err(), UsbError::ProtocolError);
 }
 
-fn is_get_hub_descriptor<const ADDR: u8>(
-    a: &u8,
-    p: &u8,
-    s: &SetupPacket,
-    d: &DataPhase,
-) -> bool {
-    *a == ADDR
-        && *p == 8
-        && s.bmRequestType == DEVICE_TO_HOST | CLASS_REQUEST
-        && s.bRequest == GET_DESCRIPTOR
-        && s.wValue == 0x2900
-        && s.wIndex == 0
-        && s.wLength >= 9
-        && d.is_in()
+fn control_transfer_all_pipes_in_use(
+    _: u8,
+    _: u8,
+    _: SetupPacket,
+    _: DataPhase,
+) -> Pin<Box<dyn Future<Output = Result<usize, UsbError>>>> {
+    Box::pin(future::ready(Err(UsbError::AllPipesInUse)))
 }
 
-fn hub_descriptor(bytes: &mut [u8]) -> usize {
-    bytes[0] = 9;
-    bytes[1] = HUB_DESCRIPTOR;
-    bytes[2] = 2; // 2-port hub
-    9
-}
+#[test]
+fn new_device_first_call_retried() {
+    let w = Waker::from(Arc::new(NoOpWaker));
+    let mut c = core::task::Context::from_waker(&w);
 
-fn giant_hub_descriptor(bytes: &mut [u8]) -> usize {
-    bytes[0] = 9;
-    bytes[1] = HUB_DESCRIPTOR;
-    bytes[2] = 15; // 15-port hub
-    11 // NB bigger than normal
-}
+    let mut hc = MockHostController::default();
+    let mut seq = mockall::Sequence::new();
+    hc.inner
+        .expect_control_transfer()
+        .times(2)
+        .in_sequence(&mut seq)
+        .withf(is_get_device_descriptor::<8>)
+        .returning(control_transfer_timeout);
+    hc.inner
+        .expect_control_transfer()
+        .times(1)
+        .in_sequence(&mut seq)
+        .withf(is_get_device_descriptor::<8>)
+        .returning(control_transfer_ok_with(device_descriptor_prefix));
+    hc.inner
+        .expect_control_transfer()
+        .times(1)
+        .in_sequence(&mut seq)
+        .withf(is_get_device_descriptor::<18>)
+        .returning(control_transfer_ok_with(device_descriptor));
 
-fn is_set_port_power<const ADDR: u8, const N: u8>(
-    a: &u8,
-    p: &u8,
-    s: &SetupPacket,
-    d: &DataPhase,
-) -> bool {
-    *a == ADDR
-        && *p == 8
-        && s.bmRequestType == HOST_TO_DEVICE | CLASS_REQUEST | RECIPIENT_OTHER
-        && s.bRequest == SET_FEATURE
-        && s.wValue == PORT_POWER
-        && s.wIndex == N.into()
-        && s.wLength == 0
-        && d.is_none()
-}
+    let bus = UsbBus::new(hc);
+    let delays = std::cell::RefCell::new(Vec::new());
+    let delay = |ms| {
+        delays.borrow_mut().push(ms);
+        future::ready(())
+    };
 
-#[test]
-fn new_hub() {
-    do_test(
-        |hc| {
-            hc.expect_add_to_multi_interrupt_pipe();
-            hc.expect_get_configuration::<5>();
-            hc.expect_set_configuration::<5, 1>();
-            hc.expect_get_configuration::<5>();
-            hc.expect_get_hub_descriptor::<5>();
-            hc.expect_set_port_power::<5, 1>();
-            hc.expect_set_port_power::<5, 2>();
-        },
-        |f| {
-            let r = pin!(f.bus.new_hub(&f.hub_state, unconfigured_device()));
-            let rr = r.poll(f.c);
-            let rc = unwrap_poll(rr).unwrap();
-            assert!(rc.is_ok());
-        },
-    );
+    let r = pin!(bus.new_device(UsbSpeed::Full12, &delay));
+    let (_device, di) = unwrap_poll(r.poll(&mut c)).unwrap().unwrap();
+    assert_eq!(di.vid, 0x1234);
+    assert_eq!(*delays.borrow(), [10, 10]);
 }
 
 #[test]
-fn new_hub_giant() {
-    do_test(
+fn new_device_second_call_retried() {
+    let w = Waker::from(Arc::new(NoOpWaker));
+    let mut c = core::task::Context::from_waker(&w);
+
+    let mut hc = MockHostController::default();
+    let mut seq = mockall::Sequence::new();
+    hc.inner
+        .expect_control_transfer()
+        .times(1)
+        .in_sequence(&mut seq)
+        .withf(is_get_device_descriptor::<8>)
+        .returning(control_transfer_ok_with(device_descriptor_prefix));
+    hc.inner
+        .expect_control_transfer()
+        .times(1)
+        .in_sequence(&mut seq)
+        .withf(is_get_device_descriptor::<18>)
+        .returning(control_transfer_ok::<17>);
+    hc.inner
+        .expect_control_transfer()
+        .times(1)
+        .in_sequence(&mut seq)
+        .withf(is_get_device_descriptor::<18>)
+        .returning(control_transfer_ok_with(device_descriptor));
+
+    let mut bus = UsbBus::new(hc);
+    bus.set_control_retry_policy(ControlRetryPolicy {
+        attempts: 2,
+        retry_delay_ms: 25,
+    });
+    let delays = std::cell::RefCell::new(Vec::new());
+    let delay = |ms| {
+        delays.borrow_mut().push(ms);
+        future::ready(())
+    };
+
+    let r = pin!(bus.new_device(UsbSpeed::Full12, &delay));
+    let (_device, di) = unwrap_poll(r.poll(&mut c)).unwrap().unwrap();
+    assert_eq!(di.pid, 0x5678);
+    assert_eq!(*delays.borrow(), [25]);
+}
+
+#[test]
+fn new_device_control_retries_exhausted() {
+    let w = Waker::from(Arc::new(NoOpWaker));
+    let mut c = core::task::Context::from_waker(&w);
+
+    let mut hc = MockHostController::default();
+    hc.inner
+        .expect_control_transfer()
+        .times(3)
+        .withf(is_get_device_descriptor::<8>)
+        .returning(control_transfer_timeout);
+
+    let bus = UsbBus::new(hc);
+
+    let r = pin!(bus.new_device(UsbSpeed::Full12, &no_delay));
+    let rc = unwrap_poll(r.poll(&mut c)).unwrap();
+    assert_eq!(rc.unwrap_err(), UsbError::Timeout);
+}
+
+#[test]
+fn new_device_not_retried_without_resources() {
+    let w = Waker::from(Arc::new(NoOpWaker));
+    let mut c = core::task::Context::from_waker(&w);
+
+    let mut hc = MockHostController::default();
+    hc.inner
+        .expect_control_transfer()
+        .times(1)
+        .withf(is_get_device_descriptor::<8>)
+        .returning(control_transfer_all_pipes_in_use);
+
+    let bus = UsbBus::new(hc);
+
+    let r = pin!(bus.new_device(UsbSpeed::Full12, &no_delay));
+    let rc = unwrap_poll(r.poll(&mut c)).unwrap();
+    assert_eq!(rc.unwrap_err(), UsbError::AllPipesInUse);
+}
+
+#[test]
+fn new_device_retry_pends_in_delay() {
+    let w = Waker::from(Arc::new(NoOpWaker));
+    let mut c = core::task::Context::from_waker(&w);
+
+    let mut hc = MockHostController::default();
+    hc.inner
+        .expect_control_transfer()
+        .times(1)
+        .withf(is_get_device_descriptor::<8>)
+        .returning(control_transfer_timeout);
+
+    let bus = UsbBus::new(hc);
+
+    let mut r = pin!(bus.new_device(UsbSpeed::Full12, &long_delay));
+    assert!(r.as_mut().poll(&mut c).is_pending());
+    assert!(r.as_mut().poll(&mut c).is_pending());
+}
+
+#[test]
+fn control_retry_policy_default() {
+    assert_eq!(
+        ControlRetryPolicy::default(),
+        ControlRetryPolicy {
+            attempts: 3,
+            retry_delay_ms: 10
+        }
+    );
+}
+
+#[cfg(feature = "hubs")]
+fn is_get_hub_descriptor<const ADDR: u8>(
+    a: &u8,
+    p: &u8,
+    s: &SetupPacket,
+    d: &DataPhase,
+) -> bool {
+    *a == ADDR
+        && *p == 8
+        && s.bmRequestType == DEVICE_TO_HOST | CLASS_REQUEST
+        && s.bRequest == GET_DESCRIPTOR
+        && s.wValue == 0x2900
+        && s.wIndex == 0
+        && s.wLength >= 9
+        && d.is_in()
+}
+
+#[cfg(feature = "hubs")]
+fn hub_descriptor(bytes: &mut [u8]) -> usize {
+    bytes[0] = 9;
+    bytes[1] = HUB_DESCRIPTOR;
+    bytes[2] = 2; // 2-port hub
+    9
+}
+
+#[cfg(feature = "hubs")]
+fn giant_hub_descriptor(bytes: &mut [u8]) -> usize {
+    bytes[0] = 9;
+    bytes[1] = HUB_DESCRIPTOR;
+    bytes[2] = 15; // 15-port hub
+    11 // NB bigger than normal
+}
+
+#[cfg(feature = "hubs")]
+fn powered_hub_descriptor(bytes: &mut [u8]) -> usize {
+    bytes[0] = 9;
+    bytes[1] = HUB_DESCRIPTOR;
+    bytes[2] = 2; // 2-port hub
+    bytes[5] = 50; // 100ms power-on to power-good
+    bytes[6] = 100; // 100mA
+    9
+}
+
+#[cfg(feature = "hubs")]
+fn is_set_port_power<const ADDR: u8, const N: u8>(
+    a: &u8,
+    p: &u8,
+    s: &SetupPacket,
+    d: &DataPhase,
+) -> bool {
+    *a == ADDR
+        && *p == 8
+        && s.bmRequestType == HOST_TO_DEVICE | CLASS_REQUEST | RECIPIENT_OTHER
+        && s.bRequest == SET_FEATURE
+        && s.wValue == 8 // PORT_POWER
+        && s.wIndex == N.into()
+        && s.wLength == 0
+        && d.is_none()
+}
+
+#[cfg(feature = "hubs")]
+#[test]
+fn new_hub() {
+    do_test(
         |hc| {
             hc.expect_add_to_multi_interrupt_pipe();
             hc.expect_get_configuration::<5>();
             hc.expect_set_configuration::<5, 1>();
+            hc.expect_get_hub_descriptor::<5>();
+            hc.expect_set_port_power::<5, 1>();
+            hc.expect_set_port_power::<5, 2>();
+        },
+        |f| {
+            let r = pin!(f.bus.new_hub(
+                &f.hub_state,
+                unconfigured_device(),
+                no_delay
+            ));
+            let rr = r.poll(f.c);
+            let rc = unwrap_poll(rr).unwrap();
+            assert!(rc.is_ok());
+        },
+    );
+}
+
+#[cfg(feature = "hubs")]
+#[test]
+fn new_hub_uses_endpoint_descriptor() {
+    do_test(
+        |hc| {
+            // Example descriptor has 64-byte packets, bInterval 0
+            hc.expect_try_alloc_interrupt_pipe()
+                .times(1)
+                .withf(|a, e, s, i| *a == 5 && *e == 2 && *s == 64 && *i == 1)
+                .returning(|_, _, _, _| Ok(MockInterruptPipe::new()));
+            hc.expect_get_configuration::<5>();
+            hc.expect_set_configuration::<5, 1>();
+            hc.expect_get_hub_descriptor::<5>();
+            hc.expect_set_port_power::<5, 1>();
+            hc.expect_set_port_power::<5, 2>();
+        },
+        |f| {
+            let r = pin!(f.bus.new_hub(
+                &f.hub_state,
+                unconfigured_device(),
+                no_delay
+            ));
+            let rr = r.poll(f.c);
+            let rc = unwrap_poll(rr).unwrap();
+            assert!(rc.is_ok());
+        },
+    );
+}
+
+/// A hub-like configuration with no endpoints at all
+#[cfg(feature = "hubs")]
+fn no_endpoint_config_descriptor(buf: &mut [u8]) -> usize {
+    let c = ConfigurationDescriptor {
+        bLength: core::mem::size_of::<ConfigurationDescriptor>() as u8,
+        bDescriptorType: CONFIGURATION_DESCRIPTOR,
+        wTotalLength: 18u16.to_le_bytes(),
+        bNumInterfaces: 1,
+        bConfigurationValue: 1,
+        iConfiguration: 0,
+        bmAttributes: 0,
+        bMaxPower: 0,
+    };
+    buf[0..9].copy_from_slice(bytemuck::bytes_of(&c));
+
+    let i = InterfaceDescriptor {
+        bLength: core::mem::size_of::<InterfaceDescriptor>() as u8,
+        bDescriptorType: INTERFACE_DESCRIPTOR,
+        bInterfaceNumber: 0,
+        bAlternateSetting: 0,
+        bNumEndpoints: 0,
+        bInterfaceClass: HUB_CLASSCODE,
+        bInterfaceSubClass: 0,
+        bInterfaceProtocol: 0,
+        iInterface: 0,
+    };
+    buf[9..18].copy_from_slice(bytemuck::bytes_of(&i));
+    18
+}
+
+#[cfg(feature = "hubs")]
+#[test]
+fn new_hub_without_status_endpoint() {
+    do_test(
+        |hc| {
+            hc.expect_multi_interrupt_pipe_ignored();
+            hc.expect_control_transfer()
+                .times(2)
+                .withf(is_get_configuration_descriptor::<5>)
+                .returning(control_transfer_ok_with(
+                    no_endpoint_config_descriptor,
+                ));
+        },
+        |f| {
+            let r = pin!(f.bus.new_hub(
+                &f.hub_state,
+                unconfigured_device(),
+                no_delay
+            ));
+            let rr = r.poll(f.c);
+            let rc = unwrap_poll(rr).unwrap();
+            assert_eq!(rc.unwrap_err(), UsbError::ProtocolError);
+        },
+    );
+}
+
+#[cfg(feature = "hubs")]
+#[test]
+fn new_hub_records_power() {
+    do_test(
+        |hc| {
+            hc.expect_add_to_multi_interrupt_pipe();
+            hc.expect_get_configuration::<5>();
+            hc.expect_set_configuration::<5, 1>();
+            hc.expect_control_transfer()
+                .times(1)
+                .withf(is_get_hub_descriptor::<5>)
+                .returning(control_transfer_ok_with(powered_hub_descriptor));
+            hc.expect_set_port_power::<5, 1>();
+            hc.expect_set_port_power::<5, 2>();
+        },
+        |f| {
+            let r = pin!(f.bus.new_hub(
+                &f.hub_state,
+                unconfigured_device(),
+                short_delay
+            ));
+            let rr = r.poll(f.c);
+            let rc = unwrap_poll(rr).unwrap();
+            assert!(rc.is_ok());
+            let power = f.hub_state.topology().hub_power(5).unwrap();
+            assert_eq!(power.power_on_to_power_good_ms(), 100);
+            assert_eq!(power.controller_current_ma(), 100);
+        },
+    );
+}
+
+#[cfg(feature = "hubs")]
+#[test]
+fn new_hub_waits_for_power_good() {
+    do_test(
+        |hc| {
+            hc.expect_add_to_multi_interrupt_pipe();
             hc.expect_get_configuration::<5>();
+            hc.expect_set_configuration::<5, 1>();
+            hc.expect_control_transfer()
+                .times(1)
+                .withf(is_get_hub_descriptor::<5>)
+                .returning(control_transfer_ok_with(powered_hub_descriptor));
+            hc.expect_set_port_power::<5, 1>();
+            hc.expect_set_port_power::<5, 2>();
+        },
+        |f| {
+            let mut r = pin!(f.bus.new_hub(
+                &f.hub_state,
+                unconfigured_device(),
+                long_delay
+            ));
+            let rr = r.as_mut().poll(f.c);
+            assert_eq!(rr, Poll::Pending);
+            let rr = r.as_mut().poll(f.c);
+            assert_eq!(rr, Poll::Pending);
+        },
+    );
+}
+
+#[cfg(feature = "hubs")]
+#[test]
+fn new_hub_giant() {
+    do_test(
+        |hc| {
+            hc.expect_add_to_multi_interrupt_pipe();
+            hc.expect_get_configuration::<5>();
+            hc.expect_set_configuration::<5, 1>();
 
             // Get hub descriptor
             hc.expect_control_transfer()
@@ -1188,7 +1788,11 @@ fn new_hub_giant() {
                 .returning(control_transfer_ok::<0>);
         },
         |f| {
-            let r = pin!(f.bus.new_hub(&f.hub_state, unconfigured_device()));
+            let r = pin!(f.bus.new_hub(
+                &f.hub_state,
+                unconfigured_device(),
+                no_delay
+            ));
             let rr = r.poll(f.c);
             let rc = unwrap_poll(rr).unwrap();
             assert!(rc.is_ok());
@@ -1196,6 +1800,7 @@ fn new_hub_giant() {
     );
 }
 
+#[cfg(feature = "hubs")]
 #[test]
 fn new_hub_get_configuration_fails() {
     do_test(
@@ -1207,7 +1812,11 @@ fn new_hub_get_configuration_fails() {
                 .returning(control_transfer_timeout);
         },
         |f| {
-            let r = pin!(f.bus.new_hub(&f.hub_state, unconfigured_device()));
+            let r = pin!(f.bus.new_hub(
+                &f.hub_state,
+                unconfigured_device(),
+                no_delay
+            ));
             let rr = r.poll(f.c);
             let rc = unwrap_poll(rr).unwrap();
             assert_eq!(rc, Err(UsbError::Timeout));
@@ -1215,6 +1824,7 @@ fn new_hub_get_configuration_fails() {
     );
 }
 
+#[cfg(feature = "hubs")]
 #[test]
 fn new_hub_configure_fails() {
     do_test(
@@ -1228,7 +1838,11 @@ fn new_hub_configure_fails() {
                 .returning(control_transfer_timeout);
         },
         |f| {
-            let r = pin!(f.bus.new_hub(&f.hub_state, unconfigured_device()));
+            let r = pin!(f.bus.new_hub(
+                &f.hub_state,
+                unconfigured_device(),
+                no_delay
+            ));
             let rr = r.poll(f.c);
             let rc = unwrap_poll(rr).unwrap();
             assert_eq!(rc, Err(UsbError::Timeout));
@@ -1236,6 +1850,7 @@ fn new_hub_configure_fails() {
     );
 }
 
+#[cfg(feature = "hubs")]
 #[test]
 fn new_hub_configure_pends() {
     do_test(
@@ -1249,8 +1864,11 @@ fn new_hub_configure_pends() {
                 .returning(control_transfer_pending);
         },
         |f| {
-            let mut r =
-                pin!(f.bus.new_hub(&f.hub_state, unconfigured_device()));
+            let mut r = pin!(f.bus.new_hub(
+                &f.hub_state,
+                unconfigured_device(),
+                no_delay
+            ));
             let rr = r.as_mut().poll(f.c);
             assert_eq!(rr, Poll::Pending);
             let rr = r.as_mut().poll(f.c);
@@ -1259,6 +1877,7 @@ fn new_hub_configure_pends() {
     );
 }
 
+#[cfg(feature = "hubs")]
 #[test]
 fn new_hub_try_add_fails() {
     do_test(
@@ -1267,10 +1886,13 @@ fn new_hub_try_add_fails() {
                 .returning(|_, _, _, _| Err(UsbError::TooManyDevices));
             hc.expect_get_configuration::<5>();
             hc.expect_set_configuration::<5, 1>();
-            hc.expect_get_configuration::<5>();
         },
         |f| {
-            let r = pin!(f.bus.new_hub(&f.hub_state, unconfigured_device()));
+            let r = pin!(f.bus.new_hub(
+                &f.hub_state,
+                unconfigured_device(),
+                no_delay
+            ));
             let rr = r.poll(f.c);
             let rc = unwrap_poll(rr).unwrap();
             assert_eq!(rc, Err(UsbError::TooManyDevices));
@@ -1278,14 +1900,30 @@ fn new_hub_try_add_fails() {
     );
 }
 
+#[cfg(feature = "hubs")]
 #[test]
-fn new_hub_get_descriptor_fails() {
-    do_test(
+fn new_hub_beyond_sized_limit() {
+    let w = Waker::from(Arc::new(NoOpWaker));
+    let mut c = core::task::Context::from_waker(&w);
+    let mut hc = MockHostController::default();
+    hc.inner.expect_try_alloc_interrupt_pipe().times(0);
+    hc.inner.expect_get_configuration::<5>();
+    hc.inner.expect_set_configuration::<5, 1>();
+    let bus = UsbBus::new(hc);
+    let hub_state = HubState::<MockHostController, 0, 1>::sized();
+
+    let r = pin!(bus.new_hub(&hub_state, unconfigured_device(), no_delay));
+    assert_eq!(r.poll(&mut c), Poll::Ready(Err(UsbError::TooManyDevices)));
+}
+
+#[cfg(feature = "hubs")]
+#[test]
+fn new_hub_get_descriptor_fails() {
+    do_test(
         |hc| {
             hc.expect_add_to_multi_interrupt_pipe();
             hc.expect_get_configuration::<5>();
             hc.expect_set_configuration::<5, 1>();
-            hc.expect_get_configuration::<5>();
 
             // Get hub descriptor
             hc.expect_control_transfer()
@@ -1294,7 +1932,11 @@ fn new_hub_get_descriptor_fails() {
                 .returning(control_transfer_timeout);
         },
         |f| {
-            let r = pin!(f.bus.new_hub(&f.hub_state, unconfigured_device()));
+            let r = pin!(f.bus.new_hub(
+                &f.hub_state,
+                unconfigured_device(),
+                no_delay
+            ));
             let rr = r.poll(f.c);
             let rc = unwrap_poll(rr).unwrap();
             assert_eq!(rc, Err(UsbError::Timeout));
@@ -1302,6 +1944,7 @@ fn new_hub_get_descriptor_fails() {
     );
 }
 
+#[cfg(feature = "hubs")]
 #[test]
 fn new_hub_get_descriptor_short() {
     do_test(
@@ -1309,7 +1952,6 @@ fn new_hub_get_descriptor_short() {
             hc.expect_add_to_multi_interrupt_pipe();
             hc.expect_get_configuration::<5>();
             hc.expect_set_configuration::<5, 1>();
-            hc.expect_get_configuration::<5>();
 
             // Get hub descriptor
             hc.expect_control_transfer()
@@ -1318,7 +1960,11 @@ fn new_hub_get_descriptor_short() {
                 .returning(control_transfer_ok::<8>);
         },
         |f| {
-            let r = pin!(f.bus.new_hub(&f.hub_state, unconfigured_device()));
+            let r = pin!(f.bus.new_hub(
+                &f.hub_state,
+                unconfigured_device(),
+                no_delay
+            ));
             let rr = r.poll(f.c);
             let rc = unwrap_poll(rr).unwrap();
             assert_eq!(rc, Err(UsbError::ProtocolError));
@@ -1326,6 +1972,7 @@ fn new_hub_get_descriptor_short() {
     );
 }
 
+#[cfg(feature = "hubs")]
 #[test]
 fn new_hub_get_descriptor_pends() {
     do_test(
@@ -1333,7 +1980,6 @@ fn new_hub_get_descriptor_pends() {
             hc.expect_add_to_multi_interrupt_pipe();
             hc.expect_get_configuration::<5>();
             hc.expect_set_configuration::<5, 1>();
-            hc.expect_get_configuration::<5>();
 
             // Get hub descriptor
             hc.expect_control_transfer()
@@ -1342,8 +1988,11 @@ fn new_hub_get_descriptor_pends() {
                 .returning(control_transfer_pending);
         },
         |f| {
-            let mut r =
-                pin!(f.bus.new_hub(&f.hub_state, unconfigured_device()));
+            let mut r = pin!(f.bus.new_hub(
+                &f.hub_state,
+                unconfigured_device(),
+                no_delay
+            ));
             let rr = r.as_mut().poll(f.c);
             assert_eq!(rr, Poll::Pending);
             let rr = r.as_mut().poll(f.c);
@@ -1352,6 +2001,7 @@ fn new_hub_get_descriptor_pends() {
     );
 }
 
+#[cfg(feature = "hubs")]
 #[test]
 fn new_hub_set_port_power_fails() {
     do_test(
@@ -1359,7 +2009,6 @@ fn new_hub_set_port_power_fails() {
             hc.expect_add_to_multi_interrupt_pipe();
             hc.expect_get_configuration::<5>();
             hc.expect_set_configuration::<5, 1>();
-            hc.expect_get_configuration::<5>();
             hc.expect_get_hub_descriptor::<5>();
 
             // Set port power
@@ -1369,7 +2018,11 @@ fn new_hub_set_port_power_fails() {
                 .returning(control_transfer_timeout);
         },
         |f| {
-            let r = pin!(f.bus.new_hub(&f.hub_state, unconfigured_device()));
+            let r = pin!(f.bus.new_hub(
+                &f.hub_state,
+                unconfigured_device(),
+                no_delay
+            ));
             let rr = r.poll(f.c);
             let rc = unwrap_poll(rr).unwrap();
             assert_eq!(rc, Err(UsbError::Timeout));
@@ -1377,6 +2030,7 @@ fn new_hub_set_port_power_fails() {
     );
 }
 
+#[cfg(feature = "hubs")]
 #[test]
 fn new_hub_set_port_power_pends() {
     do_test(
@@ -1384,7 +2038,6 @@ fn new_hub_set_port_power_pends() {
             hc.expect_add_to_multi_interrupt_pipe();
             hc.expect_get_configuration::<5>();
             hc.expect_set_configuration::<5, 1>();
-            hc.expect_get_configuration::<5>();
             hc.expect_get_hub_descriptor::<5>();
 
             // Set port power
@@ -1394,8 +2047,11 @@ fn new_hub_set_port_power_pends() {
                 .returning(control_transfer_pending);
         },
         |f| {
-            let mut r =
-                pin!(f.bus.new_hub(&f.hub_state, unconfigured_device()));
+            let mut r = pin!(f.bus.new_hub(
+                &f.hub_state,
+                unconfigured_device(),
+                no_delay
+            ));
             let rr = r.as_mut().poll(f.c);
             assert_eq!(rr, Poll::Pending);
             let rr = r.as_mut().poll(f.c);
@@ -1404,6 +2060,7 @@ fn new_hub_set_port_power_pends() {
     );
 }
 
+#[cfg(feature = "hubs")]
 #[test]
 fn handle_hub_packet_empty() {
     do_test(
@@ -1413,8 +2070,11 @@ fn handle_hub_packet_empty() {
         |f| {
             let mut p = InterruptPacket::new();
             p.size = 1;
-            let fut =
-                pin!(f.bus.handle_hub_packet(&f.hub_state, &p, no_delay));
+            let fut = pin!(f.bus.handle_hub_packet(
+                &f.hub_state,
+                p.view(),
+                no_delay
+            ));
 
             let poll = fut.poll(f.c);
             let result = unwrap_poll(poll).unwrap();
@@ -1423,6 +2083,7 @@ fn handle_hub_packet_empty() {
     );
 }
 
+#[cfg(feature = "hubs")]
 fn is_get_port_status<const N: u8>(
     a: &u8,
     p: &u8,
@@ -1439,6 +2100,7 @@ fn is_get_port_status<const N: u8>(
         && d.is_in()
 }
 
+#[cfg(feature = "hubs")]
 fn port_status<const STATE: u16, const CHANGES: u16>(
     bytes: &mut [u8],
 ) -> usize {
@@ -1447,6 +2109,7 @@ fn port_status<const STATE: u16, const CHANGES: u16>(
     4
 }
 
+#[cfg(feature = "hubs")]
 fn is_clear_port_feature<const PORT: u8, const FEATURE: u16>(
     a: &u8,
     p: &u8,
@@ -1463,6 +2126,7 @@ fn is_clear_port_feature<const PORT: u8, const FEATURE: u16>(
         && d.is_none()
 }
 
+#[cfg(feature = "hubs")]
 fn is_set_port_feature<const PORT: u8, const FEATURE: u16>(
     a: &u8,
     p: &u8,
@@ -1479,6 +2143,7 @@ fn is_set_port_feature<const PORT: u8, const FEATURE: u16>(
         && d.is_none()
 }
 
+#[cfg(feature = "hubs")]
 #[test]
 fn handle_hub_packet_connection() {
     do_test(
@@ -1490,7 +2155,7 @@ fn handle_hub_packet_connection() {
             hc.expect_get_port_status::<1, 3, 0>(); // ENABLED
             hc.expect_get_device_descriptor_prefix();
             hc.expect_get_device_descriptor();
-            hc.expect_set_address::<31>();
+            hc.expect_set_address::<127>();
             // The new device is NOT a hub so we're now done
         },
         |f| {
@@ -1498,15 +2163,18 @@ fn handle_hub_packet_connection() {
             p.address = 5;
             p.size = 1;
             p.data[0] = 0b10; // bit 1 set => port 1 needs attention
-            let fut =
-                pin!(f.bus.handle_hub_packet(&f.hub_state, &p, no_delay));
+            let fut = pin!(f.bus.handle_hub_packet(
+                &f.hub_state,
+                p.view(),
+                no_delay
+            ));
             let poll = fut.poll(f.c);
             let result = unwrap_poll(poll).unwrap();
             assert_eq!(
                 result,
                 Ok(DeviceEvent::Connect(
                     UnconfiguredDevice {
-                        usb_address: 31,
+                        usb_address: 127,
                         usb_speed: UsbSpeed::Full12,
                         packet_size_ep0: 8
                     },
@@ -1514,14 +2182,65 @@ fn handle_hub_packet_connection() {
                         vid: 0x1234,
                         pid: 0x5678,
                         class: 0,
-                        subclass: 0
-                    }
+                        subclass: 0,
+                        usb_version: 0,
+                        device_release: 0,
+                        serial_number_index: 0
+                    },
+                    f.hub_state.topology().port_path(5, 1)
                 ))
             );
         },
     );
 }
 
+#[cfg(feature = "hubs")]
+#[test]
+fn handle_hub_packet_connection_port_path() {
+    do_test(
+        |hc| {
+            hc.expect_multi_interrupt_pipe_ignored();
+            hc.expect_get_port_status::<1, 1, 1>(); // CONNECTION, C_PORT_CONNECTION
+            hc.expect_clear_port_feature::<1, 16>(); // C_PORT_CONNECTION
+            hc.expect_set_port_feature::<1, 4>(); // PORT_RESET
+            hc.expect_get_port_status::<1, 3, 0>(); // ENABLED
+            hc.expect_get_device_descriptor_prefix();
+            hc.expect_get_device_descriptor();
+            hc.expect_set_address::<127>();
+        },
+        |f| {
+            {
+                // Set up topology so that hub 5 is on port 4 of hub 1
+                let mut b = f.hub_state.topology.borrow_mut();
+                b.device_connect(0, 1, true); // 1
+                b.device_connect(1, 1, true); // 2
+                b.device_connect(1, 2, true); // 3
+                b.device_connect(1, 3, true); // 4
+                b.device_connect(1, 4, true); // 5
+            }
+
+            let mut p = InterruptPacket::new();
+            p.address = 5;
+            p.size = 1;
+            p.data[0] = 0b10; // bit 1 set => port 1 needs attention
+            let fut = pin!(f.bus.handle_hub_packet(
+                &f.hub_state,
+                p.view(),
+                no_delay
+            ));
+            let poll = fut.poll(f.c);
+            let result = unwrap_poll(poll).unwrap();
+            let Ok(DeviceEvent::Connect(_, _, path)) = result else {
+                panic!("Connect expected");
+            };
+            assert_eq!(path.ports(), &[1, 4, 1]);
+            assert_eq!(path.hub_address(), 5);
+            assert_eq!(path.port(), 1);
+        },
+    );
+}
+
+#[cfg(feature = "hubs")]
 #[test]
 fn handle_hub_packet_no_changes() {
     do_test(
@@ -1535,8 +2254,11 @@ fn handle_hub_packet_no_changes() {
             p.size = 2;
             p.data[0] = 0;
             p.data[1] = 1; // bit 8 set => port 8 needs attention
-            let fut =
-                pin!(f.bus.handle_hub_packet(&f.hub_state, &p, no_delay));
+            let fut = pin!(f.bus.handle_hub_packet(
+                &f.hub_state,
+                p.view(),
+                no_delay
+            ));
 
             let poll = fut.poll(f.c);
             let result = unwrap_poll(poll).unwrap();
@@ -1545,6 +2267,7 @@ fn handle_hub_packet_no_changes() {
     );
 }
 
+#[cfg(feature = "hubs")]
 #[test]
 fn handle_hub_packet_crazy_changes() {
     do_test(
@@ -1558,8 +2281,11 @@ fn handle_hub_packet_crazy_changes() {
             p.size = 2;
             p.data[0] = 0;
             p.data[1] = 1; // bit 8 set => port 8 needs attention
-            let fut =
-                pin!(f.bus.handle_hub_packet(&f.hub_state, &p, no_delay));
+            let fut = pin!(f.bus.handle_hub_packet(
+                &f.hub_state,
+                p.view(),
+                no_delay
+            ));
             let poll = fut.poll(f.c);
             let result = unwrap_poll(poll).unwrap();
             assert_eq!(result, Ok(DeviceEvent::None));
@@ -1567,6 +2293,7 @@ fn handle_hub_packet_crazy_changes() {
     );
 }
 
+#[cfg(feature = "hubs")]
 #[test]
 fn handle_hub_packet_connection_status_fails() {
     do_test(
@@ -1584,16 +2311,26 @@ fn handle_hub_packet_connection_status_fails() {
             p.address = 5;
             p.size = 1;
             p.data[0] = 0b10; // bit 1 set => port 1 needs attention
-            let fut =
-                pin!(f.bus.handle_hub_packet(&f.hub_state, &p, no_delay));
+            let fut = pin!(f.bus.handle_hub_packet(
+                &f.hub_state,
+                p.view(),
+                no_delay
+            ));
 
             let poll = fut.poll(f.c);
             let result = unwrap_poll(poll).unwrap();
-            assert_eq!(result, Err(UsbError::Timeout));
+            assert_eq!(
+                result,
+                Ok(DeviceEvent::EnumerationError(
+                    f.hub_state.topology().port_path(5, 1),
+                    UsbError::Timeout
+                ))
+            );
         },
     );
 }
 
+#[cfg(feature = "hubs")]
 #[test]
 fn handle_hub_packet_connection_status_pends() {
     do_test(
@@ -1611,8 +2348,11 @@ fn handle_hub_packet_connection_status_pends() {
             p.address = 5;
             p.size = 1;
             p.data[0] = 0b10; // bit 1 set => port 1 needs attention
-            let mut fut =
-                pin!(f.bus.handle_hub_packet(&f.hub_state, &p, no_delay));
+            let mut fut = pin!(f.bus.handle_hub_packet(
+                &f.hub_state,
+                p.view(),
+                no_delay
+            ));
 
             let poll = fut.as_mut().poll(f.c);
             assert!(poll.is_pending());
@@ -1622,6 +2362,7 @@ fn handle_hub_packet_connection_status_pends() {
     );
 }
 
+#[cfg(feature = "hubs")]
 #[test]
 fn handle_hub_packet_connection_clear_fails() {
     do_test(
@@ -1639,16 +2380,26 @@ fn handle_hub_packet_connection_clear_fails() {
             p.address = 5;
             p.size = 1;
             p.data[0] = 0b10; // bit 1 set => port 1 needs attention
-            let fut =
-                pin!(f.bus.handle_hub_packet(&f.hub_state, &p, no_delay));
+            let fut = pin!(f.bus.handle_hub_packet(
+                &f.hub_state,
+                p.view(),
+                no_delay
+            ));
 
             let poll = fut.poll(f.c);
             let result = unwrap_poll(poll).unwrap();
-            assert_eq!(result, Err(UsbError::Timeout));
+            assert_eq!(
+                result,
+                Ok(DeviceEvent::EnumerationError(
+                    f.hub_state.topology().port_path(5, 1),
+                    UsbError::Timeout
+                ))
+            );
         },
     );
 }
 
+#[cfg(feature = "hubs")]
 #[test]
 fn handle_hub_packet_connection_clear_pends() {
     do_test(
@@ -1666,8 +2417,11 @@ fn handle_hub_packet_connection_clear_pends() {
             p.address = 5;
             p.size = 1;
             p.data[0] = 0b10; // bit 1 set => port 1 needs attention
-            let mut fut =
-                pin!(f.bus.handle_hub_packet(&f.hub_state, &p, no_delay));
+            let mut fut = pin!(f.bus.handle_hub_packet(
+                &f.hub_state,
+                p.view(),
+                no_delay
+            ));
 
             let poll = fut.as_mut().poll(f.c);
             assert!(poll.is_pending());
@@ -1677,6 +2431,7 @@ fn handle_hub_packet_connection_clear_pends() {
     );
 }
 
+#[cfg(feature = "hubs")]
 #[test]
 fn handle_hub_packet_connection_set_fails() {
     do_test(
@@ -1696,16 +2451,26 @@ fn handle_hub_packet_connection_set_fails() {
             p.address = 5;
             p.size = 1;
             p.data[0] = 0b10; // bit 1 set => port 1 needs attention
-            let fut =
-                pin!(f.bus.handle_hub_packet(&f.hub_state, &p, no_delay));
+            let fut = pin!(f.bus.handle_hub_packet(
+                &f.hub_state,
+                p.view(),
+                no_delay
+            ));
 
             let poll = fut.poll(f.c);
             let result = unwrap_poll(poll).unwrap();
-            assert_eq!(result, Err(UsbError::Timeout));
+            assert_eq!(
+                result,
+                Ok(DeviceEvent::EnumerationError(
+                    f.hub_state.topology().port_path(5, 1),
+                    UsbError::Timeout
+                ))
+            );
         },
     );
 }
 
+#[cfg(feature = "hubs")]
 #[test]
 fn handle_hub_packet_connection_set_pends() {
     do_test(
@@ -1725,8 +2490,11 @@ fn handle_hub_packet_connection_set_pends() {
             p.address = 5;
             p.size = 1;
             p.data[0] = 0b10; // bit 1 set => port 1 needs attention
-            let mut fut =
-                pin!(f.bus.handle_hub_packet(&f.hub_state, &p, no_delay));
+            let mut fut = pin!(f.bus.handle_hub_packet(
+                &f.hub_state,
+                p.view(),
+                no_delay
+            ));
 
             let poll = fut.as_mut().poll(f.c);
             assert!(poll.is_pending());
@@ -1736,6 +2504,7 @@ fn handle_hub_packet_connection_set_pends() {
     );
 }
 
+#[cfg(feature = "hubs")]
 #[test]
 fn handle_hub_packet_connection_second_status_fails() {
     do_test(
@@ -1756,16 +2525,26 @@ fn handle_hub_packet_connection_second_status_fails() {
             p.address = 5;
             p.size = 1;
             p.data[0] = 0b10; // bit 1 set => port 1 needs attention
-            let fut =
-                pin!(f.bus.handle_hub_packet(&f.hub_state, &p, no_delay));
+            let fut = pin!(f.bus.handle_hub_packet(
+                &f.hub_state,
+                p.view(),
+                no_delay
+            ));
 
             let poll = fut.poll(f.c);
             let result = unwrap_poll(poll).unwrap();
-            assert_eq!(result, Err(UsbError::Timeout));
+            assert_eq!(
+                result,
+                Ok(DeviceEvent::EnumerationError(
+                    f.hub_state.topology().port_path(5, 1),
+                    UsbError::Timeout
+                ))
+            );
         },
     );
 }
 
+#[cfg(feature = "hubs")]
 #[test]
 fn handle_hub_packet_connection_delay_pends() {
     do_test(
@@ -1780,8 +2559,11 @@ fn handle_hub_packet_connection_delay_pends() {
             p.address = 5;
             p.size = 1;
             p.data[0] = 0b10; // bit 1 set => port 1 needs attention
-            let mut fut =
-                pin!(f.bus.handle_hub_packet(&f.hub_state, &p, long_delay));
+            let mut fut = pin!(f.bus.handle_hub_packet(
+                &f.hub_state,
+                p.view(),
+                long_delay
+            ));
 
             let poll = fut.as_mut().poll(f.c);
             assert!(poll.is_pending());
@@ -1791,6 +2573,7 @@ fn handle_hub_packet_connection_delay_pends() {
     );
 }
 
+#[cfg(feature = "hubs")]
 #[test]
 fn handle_hub_packet_connection_second_status_pends() {
     do_test(
@@ -1811,8 +2594,11 @@ fn handle_hub_packet_connection_second_status_pends() {
             p.address = 5;
             p.size = 1;
             p.data[0] = 0b10; // bit 1 set => port 1 needs attention
-            let mut fut =
-                pin!(f.bus.handle_hub_packet(&f.hub_state, &p, no_delay));
+            let mut fut = pin!(f.bus.handle_hub_packet(
+                &f.hub_state,
+                p.view(),
+                no_delay
+            ));
 
             let poll = fut.as_mut().poll(f.c);
             assert!(poll.is_pending());
@@ -1822,6 +2608,7 @@ fn handle_hub_packet_connection_second_status_pends() {
     );
 }
 
+#[cfg(feature = "hubs")]
 #[test]
 fn handle_hub_packet_connection_second_status_not_connected() {
     do_test(
@@ -1837,8 +2624,11 @@ fn handle_hub_packet_connection_second_status_not_connected() {
             p.address = 5;
             p.size = 1;
             p.data[0] = 0b10; // bit 1 set => port 1 needs attention
-            let fut =
-                pin!(f.bus.handle_hub_packet(&f.hub_state, &p, no_delay));
+            let fut = pin!(f.bus.handle_hub_packet(
+                &f.hub_state,
+                p.view(),
+                no_delay
+            ));
             let poll = fut.poll(f.c);
             let result = unwrap_poll(poll).unwrap();
             assert_eq!(result, Ok(DeviceEvent::None));
@@ -1846,6 +2636,7 @@ fn handle_hub_packet_connection_second_status_not_connected() {
     );
 }
 
+#[cfg(feature = "hubs")]
 #[test]
 fn handle_hub_packet_disconnection() {
     do_test(
@@ -1856,34 +2647,34 @@ fn handle_hub_packet_disconnection() {
         },
         |f| {
             {
-                // Set up topology so there's a device (31) on hub 5 port 1
+                // Set up topology so there's a device (127) on hub 5 port 1
                 let mut b = f.hub_state.topology.borrow_mut();
                 b.device_connect(0, 1, true); // 1
                 b.device_connect(1, 1, true); // 2
                 b.device_connect(1, 2, true); // 3
                 b.device_connect(1, 3, true); // 4
                 b.device_connect(1, 4, true); // 5
-                b.device_connect(5, 1, false); // 31
+                b.device_connect(5, 1, false); // 127
             }
 
             assert_eq!(
                 format!("{:?}", f.hub_state.topology()),
-                "0:(1:(2 3 4 5:(31)))"
+                "0:(1:(2 3 4 5:(127)))"
             );
 
             let mut p = InterruptPacket::new();
             p.address = 5;
             p.size = 1;
             p.data[0] = 0b10; // bit 1 set => port 1 needs attention
-            let fut =
-                pin!(f.bus.handle_hub_packet(&f.hub_state, &p, no_delay));
+            let fut = pin!(f.bus.handle_hub_packet(
+                &f.hub_state,
+                p.view(),
+                no_delay
+            ));
 
             let poll = fut.poll(f.c);
             let result = unwrap_poll(poll).unwrap();
-            assert_eq!(
-                result,
-                Ok(DeviceEvent::Disconnect(BitSet(0x8000_0000)))
-            );
+            assert_eq!(result, Ok(DeviceEvent::Disconnect(BitSet(1 << 127))));
         },
     );
 }
@@ -1891,6 +2682,7 @@ fn handle_hub_packet_disconnection() {
 // A bit unlikely as we only have FS hardware, but the protocol
 // allows for it
 #[test]
+#[cfg(feature = "hubs")]
 fn handle_hub_packet_connected_high_speed() {
     do_test(
         |hc| {
@@ -1901,15 +2693,18 @@ fn handle_hub_packet_connected_high_speed() {
             hc.expect_get_port_status::<1, 0x413, 0>(); // ENABLED
             hc.expect_get_device_descriptor_prefix();
             hc.expect_get_device_descriptor();
-            hc.expect_set_address::<31>();
+            hc.expect_set_address::<127>();
         },
         |f| {
             let mut p = InterruptPacket::new();
             p.address = 5;
             p.size = 1;
             p.data[0] = 0b10; // bit 1 set => port 1 needs attention
-            let fut =
-                pin!(f.bus.handle_hub_packet(&f.hub_state, &p, no_delay));
+            let fut = pin!(f.bus.handle_hub_packet(
+                &f.hub_state,
+                p.view(),
+                no_delay
+            ));
 
             let poll = fut.poll(f.c);
             let result = unwrap_poll(poll).unwrap();
@@ -1917,7 +2712,7 @@ fn handle_hub_packet_connected_high_speed() {
                 result,
                 Ok(DeviceEvent::Connect(
                     UnconfiguredDevice {
-                        usb_address: 31,
+                        usb_address: 127,
                         usb_speed: UsbSpeed::High480,
                         packet_size_ep0: 8
                     },
@@ -1926,13 +2721,18 @@ fn handle_hub_packet_connected_high_speed() {
                         pid: 0x5678,
                         class: 0,
                         subclass: 0,
-                    }
+                        usb_version: 0,
+                        device_release: 0,
+                        serial_number_index: 0,
+                    },
+                    f.hub_state.topology().port_path(5, 1)
                 ))
             );
         },
     );
 }
 
+#[cfg(feature = "hubs")]
 #[test]
 fn handle_hub_packet_connected_low_speed() {
     do_test(
@@ -1944,15 +2744,18 @@ fn handle_hub_packet_connected_low_speed() {
             hc.expect_get_port_status::<1, 0x213, 0>(); // ENABLED
             hc.expect_get_device_descriptor_prefix();
             hc.expect_get_device_descriptor();
-            hc.expect_set_address::<31>();
+            hc.expect_set_address::<127>();
         },
         |f| {
             let mut p = InterruptPacket::new();
             p.address = 5;
             p.size = 1;
             p.data[0] = 0b10; // bit 1 set => port 1 needs attention
-            let fut =
-                pin!(f.bus.handle_hub_packet(&f.hub_state, &p, no_delay));
+            let fut = pin!(f.bus.handle_hub_packet(
+                &f.hub_state,
+                p.view(),
+                no_delay
+            ));
 
             let poll = fut.poll(f.c);
             let result = unwrap_poll(poll).unwrap();
@@ -1960,7 +2763,7 @@ fn handle_hub_packet_connected_low_speed() {
                 result,
                 Ok(DeviceEvent::Connect(
                     UnconfiguredDevice {
-                        usb_address: 31,
+                        usb_address: 127,
                         usb_speed: UsbSpeed::Low1_5,
                         packet_size_ep0: 8
                     },
@@ -1969,13 +2772,18 @@ fn handle_hub_packet_connected_low_speed() {
                         pid: 0x5678,
                         class: 0,
                         subclass: 0,
-                    }
+                        usb_version: 0,
+                        device_release: 0,
+                        serial_number_index: 0,
+                    },
+                    f.hub_state.topology().port_path(5, 1)
                 ))
             );
         },
     );
 }
 
+#[cfg(feature = "hubs")]
 #[test]
 fn handle_hub_packet_enabled_port_reset_fails() {
     do_test(
@@ -1994,16 +2802,26 @@ fn handle_hub_packet_enabled_port_reset_fails() {
             p.address = 5;
             p.size = 1;
             p.data[0] = 0b10; // bit 1 set => port 1 needs attention
-            let fut =
-                pin!(f.bus.handle_hub_packet(&f.hub_state, &p, no_delay));
+            let fut = pin!(f.bus.handle_hub_packet(
+                &f.hub_state,
+                p.view(),
+                no_delay
+            ));
 
             let poll = fut.poll(f.c);
             let result = unwrap_poll(poll).unwrap();
-            assert_eq!(result, Err(UsbError::Timeout));
+            assert_eq!(
+                result,
+                Ok(DeviceEvent::EnumerationError(
+                    f.hub_state.topology().port_path(5, 1),
+                    UsbError::Timeout
+                ))
+            );
         },
     );
 }
 
+#[cfg(feature = "hubs")]
 #[test]
 fn handle_hub_packet_enabled_port_reset_pends() {
     do_test(
@@ -2022,8 +2840,11 @@ fn handle_hub_packet_enabled_port_reset_pends() {
             p.address = 5;
             p.size = 1;
             p.data[0] = 0b10; // bit 1 set => port 1 needs attention
-            let mut fut =
-                pin!(f.bus.handle_hub_packet(&f.hub_state, &p, no_delay));
+            let mut fut = pin!(f.bus.handle_hub_packet(
+                &f.hub_state,
+                p.view(),
+                no_delay
+            ));
 
             let poll = fut.as_mut().poll(f.c);
             assert!(poll.is_pending());
@@ -2033,6 +2854,7 @@ fn handle_hub_packet_enabled_port_reset_pends() {
     );
 }
 
+#[cfg(feature = "hubs")]
 #[test]
 fn handle_hub_packet_connected_new_device_fails() {
     do_test(
@@ -2049,21 +2871,33 @@ fn handle_hub_packet_connected_new_device_fails() {
                 .withf(is_get_device_descriptor::<8>)
                 .returning(control_transfer_timeout);
         },
-        |f| {
+        |mut f| {
+            f.bus.set_control_retry_policy(NO_CONTROL_RETRIES);
             let mut p = InterruptPacket::new();
             p.address = 5;
             p.size = 1;
             p.data[0] = 0b10; // bit 1 set => port 1 needs attention
-            let fut =
-                pin!(f.bus.handle_hub_packet(&f.hub_state, &p, no_delay));
+            let fut = pin!(f.bus.handle_hub_packet(
+                &f.hub_state,
+                p.view(),
+                no_delay
+            ));
 
             let poll = fut.poll(f.c);
             let result = unwrap_poll(poll).unwrap();
-            assert_eq!(result, Err(UsbError::Timeout));
+            assert_eq!(
+                result,
+                Ok(DeviceEvent::EnumerationRetry(
+                    f.hub_state.topology().port_path(5, 1),
+                    1,
+                    UsbError::Timeout
+                ))
+            );
         },
     );
 }
 
+#[cfg(feature = "hubs")]
 #[test]
 fn handle_hub_packet_connected_new_device_pends() {
     do_test(
@@ -2085,8 +2919,11 @@ fn handle_hub_packet_connected_new_device_pends() {
             p.address = 5;
             p.size = 1;
             p.data[0] = 0b10; // bit 1 set => port 1 needs attention
-            let mut fut =
-                pin!(f.bus.handle_hub_packet(&f.hub_state, &p, no_delay));
+            let mut fut = pin!(f.bus.handle_hub_packet(
+                &f.hub_state,
+                p.view(),
+                no_delay
+            ));
 
             let poll = fut.as_mut().poll(f.c);
             assert!(poll.is_pending());
@@ -2096,6 +2933,7 @@ fn handle_hub_packet_connected_new_device_pends() {
     );
 }
 
+#[cfg(feature = "hubs")]
 #[test]
 fn handle_hub_packet_enabled_set_address_fails() {
     do_test(
@@ -2108,10 +2946,10 @@ fn handle_hub_packet_enabled_set_address_fails() {
             hc.expect_get_device_descriptor_prefix();
             hc.expect_get_device_descriptor();
 
-            // Set address (31)
+            // Set address (127)
             hc.expect_control_transfer()
                 .times(1)
-                .withf(is_set_address::<31>)
+                .withf(is_set_address::<127>)
                 .returning(control_transfer_timeout);
         },
         |f| {
@@ -2119,18 +2957,29 @@ fn handle_hub_packet_enabled_set_address_fails() {
             p.address = 5;
             p.size = 1;
             p.data[0] = 0b10; // bit 1 set => port 1 needs attention
-            let fut =
-                pin!(f.bus.handle_hub_packet(&f.hub_state, &p, no_delay));
+            let fut = pin!(f.bus.handle_hub_packet(
+                &f.hub_state,
+                p.view(),
+                no_delay
+            ));
 
             let poll = fut.poll(f.c);
             let result = unwrap_poll(poll).unwrap();
-            assert_eq!(result, Err(UsbError::Timeout));
+            assert_eq!(
+                result,
+                Ok(DeviceEvent::EnumerationRetry(
+                    f.hub_state.topology().port_path(5, 1),
+                    1,
+                    UsbError::Timeout
+                ))
+            );
         },
     );
 }
 
+#[cfg(feature = "hubs")]
 #[test]
-fn handle_hub_packet_connected_set_address_pends() {
+fn handle_hub_packet_retry_succeeds() {
     do_test(
         |hc| {
             hc.expect_multi_interrupt_pipe_ignored();
@@ -2138,95 +2987,57 @@ fn handle_hub_packet_connected_set_address_pends() {
             hc.expect_clear_port_feature::<1, 16>(); // C_PORT_CONNECTION
             hc.expect_set_port_feature::<1, 4>(); // PORT_RESET
             hc.expect_get_port_status::<1, 3, 0>(); // ENABLED
-            hc.expect_get_device_descriptor_prefix();
-            hc.expect_get_device_descriptor();
-
-            // Set address (31)
             hc.expect_control_transfer()
                 .times(1)
-                .withf(is_set_address::<31>)
-                .returning(control_transfer_pending);
-        },
-        |f| {
-            let mut p = InterruptPacket::new();
-            p.address = 5;
-            p.size = 1;
-            p.data[0] = 0b10; // bit 1 set => port 1 needs attention
-            let mut fut =
-                pin!(f.bus.handle_hub_packet(&f.hub_state, &p, no_delay));
-
-            let poll = fut.as_mut().poll(f.c);
-            assert!(poll.is_pending());
-            let poll = fut.as_mut().poll(f.c);
-            assert!(poll.is_pending());
-        },
-    );
-}
-
-fn device_descriptor_prefix_hub(bytes: &mut [u8]) -> usize {
-    bytes[0] = 18;
-    bytes[1] = DEVICE_DESCRIPTOR;
-    bytes[4] = HUB_CLASSCODE;
-    bytes[7] = 8;
-    8
-}
-
-fn device_descriptor_hub(bytes: &mut [u8]) -> usize {
-    device_descriptor_prefix(bytes);
-    bytes[8] = 0x34;
-    bytes[9] = 0x12;
-    bytes[10] = 0x78;
-    bytes[11] = 0x56;
-    18
-}
+                .withf(is_get_device_descriptor::<8>)
+                .returning(control_transfer_timeout);
 
-#[test]
-fn handle_hub_packet_connected_hub() {
-    do_test(
-        |hc| {
-            hc.expect_add_to_multi_interrupt_pipe();
-            hc.expect_get_port_status::<1, 1, 1>(); // CONNECTION, C_PORT_CONNECTION
-            hc.expect_clear_port_feature::<1, 16>(); // C_PORT_CONNECTION
+            // Retry goes straight to the reset
             hc.expect_set_port_feature::<1, 4>(); // PORT_RESET
             hc.expect_get_port_status::<1, 3, 0>(); // ENABLED
-            hc.expect_get_device_descriptor_prefix_hub();
-            hc.expect_get_device_descriptor_hub();
-            hc.expect_set_address::<1>();
-
-            // new_hub()
-            hc.expect_get_configuration::<1>();
-            hc.expect_set_configuration::<1, 1>();
-            hc.expect_get_configuration::<1>();
-            hc.expect_get_hub_descriptor::<1>();
-            hc.expect_set_port_power::<1, 1>();
-            hc.expect_set_port_power::<1, 2>();
+            hc.expect_get_device_descriptor_prefix();
+            hc.expect_get_device_descriptor();
+            hc.expect_set_address::<127>();
         },
-        |f| {
+        |mut f| {
+            f.bus.set_control_retry_policy(NO_CONTROL_RETRIES);
             let mut p = InterruptPacket::new();
             p.address = 5;
             p.size = 1;
             p.data[0] = 0b10; // bit 1 set => port 1 needs attention
-            let fut =
-                pin!(f.bus.handle_hub_packet(&f.hub_state, &p, no_delay));
+            let fut = pin!(f.bus.handle_hub_packet(
+                &f.hub_state,
+                p.view(),
+                no_delay
+            ));
 
             let poll = fut.poll(f.c);
             let result = unwrap_poll(poll).unwrap();
             assert_eq!(
                 result,
-                Ok(DeviceEvent::HubConnect(UsbDevice {
-                    usb_address: 1,
-                    usb_speed: UsbSpeed::Full12,
-                    packet_size_ep0: 8,
-                    in_endpoints_bitmap: 4,
-                    out_endpoints_bitmap: 2,
-                },))
+                Ok(DeviceEvent::EnumerationRetry(
+                    f.hub_state.topology().port_path(5, 1),
+                    1,
+                    UsbError::Timeout
+                ))
             );
+            assert!(f.hub_state.has_pending_ports());
+
+            let fut = pin!(f.bus.handle_pending_ports(&f.hub_state, no_delay));
+            let poll = fut.poll(f.c);
+            let result = unwrap_poll(poll).unwrap();
+            let DeviceEvent::Connect(device, _, _) = result else {
+                panic!("Connect expected");
+            };
+            assert_eq!(device.address(), 127);
+            assert!(!f.hub_state.has_pending_ports());
         },
     );
 }
 
+#[cfg(feature = "hubs")]
 #[test]
-fn handle_hub_packet_connected_hub_new_hub_fails() {
+fn handle_hub_packet_retries_exhausted() {
     do_test(
         |hc| {
             hc.expect_multi_interrupt_pipe_ignored();
@@ -2234,376 +3045,734 @@ fn handle_hub_packet_connected_hub_new_hub_fails() {
             hc.expect_clear_port_feature::<1, 16>(); // C_PORT_CONNECTION
             hc.expect_set_port_feature::<1, 4>(); // PORT_RESET
             hc.expect_get_port_status::<1, 3, 0>(); // ENABLED
-            hc.expect_get_device_descriptor_prefix_hub();
-            hc.expect_get_device_descriptor_hub();
-            hc.expect_set_address::<1>();
-
-            // new_hub(): get_basic_configuration
+            hc.expect_set_port_feature::<1, 4>(); // PORT_RESET
+            hc.expect_get_port_status::<1, 3, 0>(); // ENABLED
             hc.expect_control_transfer()
-                .times(1)
-                .withf(is_get_configuration_descriptor::<1>)
+                .times(2)
+                .withf(is_get_device_descriptor::<8>)
                 .returning(control_transfer_timeout);
         },
-        |f| {
+        |mut f| {
+            f.bus.set_control_retry_policy(NO_CONTROL_RETRIES);
+            f.hub_state = HubState::with_retry_policy(RetryPolicy {
+                attempts: 2,
+                retry_delay_ms: 0,
+            });
             let mut p = InterruptPacket::new();
             p.address = 5;
             p.size = 1;
             p.data[0] = 0b10; // bit 1 set => port 1 needs attention
-            let fut =
-                pin!(f.bus.handle_hub_packet(&f.hub_state, &p, no_delay));
+            let fut = pin!(f.bus.handle_hub_packet(
+                &f.hub_state,
+                p.view(),
+                no_delay
+            ));
+
+            let poll = fut.poll(f.c);
+            let result = unwrap_poll(poll).unwrap();
+            assert_eq!(
+                result,
+                Ok(DeviceEvent::EnumerationRetry(
+                    f.hub_state.topology().port_path(5, 1),
+                    1,
+                    UsbError::Timeout
+                ))
+            );
 
+            let fut = pin!(f.bus.handle_pending_ports(&f.hub_state, no_delay));
             let poll = fut.poll(f.c);
             let result = unwrap_poll(poll).unwrap();
-            assert_eq!(result, Err(UsbError::Timeout));
+            assert_eq!(
+                result,
+                DeviceEvent::EnumerationError(
+                    f.hub_state.topology().port_path(5, 1),
+                    UsbError::Timeout
+                )
+            );
+            assert!(!f.hub_state.has_pending_ports());
         },
     );
 }
 
+#[cfg(feature = "hubs")]
 #[test]
-fn handle_hub_packet_connected_hub_new_hub_pends() {
+fn handle_pending_ports_retry_waits() {
     do_test(
         |hc| {
             hc.expect_multi_interrupt_pipe_ignored();
-            hc.expect_get_port_status::<1, 1, 1>(); // CONNECTION, C_PORT_CONNECTION
-            hc.expect_clear_port_feature::<1, 16>(); // C_PORT_CONNECTION
-            hc.expect_set_port_feature::<1, 4>(); // PORT_RESET
-            hc.expect_get_port_status::<1, 3, 0>(); // ENABLED
-            hc.expect_get_device_descriptor_prefix_hub();
-            hc.expect_get_device_descriptor_hub();
-            hc.expect_set_address::<1>();
+        },
+        |mut f| {
+            f.hub_state = HubState::with_retry_policy(RetryPolicy {
+                attempts: 3,
+                retry_delay_ms: 10,
+            });
+            f.hub_state.retries.set({
+                let mut r = [0u16; 16];
+                r[5] = 0b10;
+                r
+            });
 
-            // new_hub(): get_basic_configuration
-            hc.expect_control_transfer()
-                .times(1)
-                .withf(is_get_configuration_descriptor::<1>)
-                .returning(control_transfer_pending);
-        },
-        |f| {
-            let mut p = InterruptPacket::new();
-            p.address = 5;
-            p.size = 1;
-            p.data[0] = 0b10; // bit 1 set => port 1 needs attention
-            let mut fut =
-                pin!(f.bus.handle_hub_packet(&f.hub_state, &p, no_delay));
-
-            let poll = fut.as_mut().poll(f.c);
-            assert!(poll.is_pending());
-            let poll = fut.as_mut().poll(f.c);
-            assert!(poll.is_pending());
+            // short_delay() only completes delays of more than 20ms
+            let mut fut =
+                pin!(f.bus.handle_pending_ports(&f.hub_state, short_delay));
+            let poll = fut.as_mut().poll(f.c);
+            assert!(poll.is_pending());
         },
     );
 }
 
+#[cfg(feature = "hubs")]
 #[test]
-fn handle_hub_packet_enabled_too_many_devices() {
+fn port_error_policy_backoff() {
+    let policy = PortErrorPolicy::default();
+    assert_eq!(policy.backoff_ms(1), 100);
+    assert_eq!(policy.backoff_ms(2), 200);
+    assert_eq!(policy.backoff_ms(4), 800);
+    let policy = PortErrorPolicy {
+        reenable_attempts: 255,
+        backoff_ms: usize::MAX / 2,
+    };
+    assert_eq!(policy.backoff_ms(255), usize::MAX);
+}
+
+#[cfg(feature = "hubs")]
+#[test]
+fn retry_delay_after_port_errors() {
     do_test(
         |hc| {
             hc.expect_multi_interrupt_pipe_ignored();
-            hc.expect_get_port_status::<1, 1, 1>(); // CONNECTION, C_PORT_CONNECTION
-            hc.expect_clear_port_feature::<1, 16>(); // C_PORT_CONNECTION
-            hc.expect_set_port_feature::<1, 4>(); // PORT_RESET
-            hc.expect_get_port_status::<1, 3, 0>(); // ENABLED
-            hc.expect_get_device_descriptor_prefix_hub();
-            hc.expect_get_device_descriptor_hub();
         },
-        |f| {
-            {
-                let mut state = f.hub_state.topology.borrow_mut();
-                for i in 1..16 {
-                    state.device_connect(0, i, true);
-                }
-            }
-
-            let mut p = InterruptPacket::new();
-            p.address = 5;
-            p.size = 1;
-            p.data[0] = 0b10; // bit 1 set => port 1 needs attention
-            let fut =
-                pin!(f.bus.handle_hub_packet(&f.hub_state, &p, no_delay));
-            let poll = fut.poll(f.c);
-            let result = unwrap_poll(poll).unwrap();
-            assert_eq!(result, Err(UsbError::TooManyDevices));
+        |mut f| {
+            f.hub_state = HubState::with_retry_policy(RetryPolicy {
+                attempts: 3,
+                retry_delay_ms: 10,
+            });
+            assert_eq!(f.hub_state.retry_delay_ms(5, 1), 10);
+            f.hub_state.port_errors.borrow_mut()[5][1] = 1;
+            assert_eq!(f.hub_state.retry_delay_ms(5, 1), 100);
+            f.hub_state.port_errors.borrow_mut()[5][1] = 3;
+            assert_eq!(f.hub_state.retry_delay_ms(5, 1), 400);
+            f.hub_state.forget_port_errors(5, 1);
+            assert_eq!(f.hub_state.retry_delay_ms(5, 1), 10);
         },
     );
 }
 
+#[cfg(feature = "hubs")]
 #[test]
-fn device_events_nh() {
+fn handle_hub_packet_port_error_reenables() {
     do_test(
         |hc| {
             hc.expect_multi_interrupt_pipe_ignored();
-            hc.expect_device_detect().returning(|| {
-                let mut mdd = MockDeviceDetect::new();
-                mdd.expect_poll_next().returning(|_| {
-                    Poll::Ready(Some(DeviceStatus::Present(UsbSpeed::Full12)))
-                });
-                mdd
-            });
-            hc.expect_reset_root_port().withf(|r| *r).return_const(());
-            hc.expect_reset_root_port().withf(|r| !*r).return_const(());
+            hc.expect_get_port_status::<1, 1, 2>(); // CONNECTION, C_PORT_ENABLE
+            hc.expect_clear_port_feature::<1, 17>(); // C_PORT_ENABLE
+
+            // Re-enabled by resetting it
+            hc.expect_set_port_feature::<1, 4>(); // PORT_RESET
+            hc.expect_get_port_status::<1, 3, 0>(); // ENABLED
             hc.expect_get_device_descriptor_prefix();
             hc.expect_get_device_descriptor();
-            hc.expect_set_address::<1>();
+            hc.expect_set_address::<127>();
         },
         |f| {
-            let stream = pin!(f.bus.device_events_no_hubs(no_delay));
-            let poll = stream.poll_next(f.c);
+            let device = {
+                // Hub 5 is on port 4 of hub 1, and has a device on port 1
+                let mut b = f.hub_state.topology.borrow_mut();
+                b.device_connect(0, 1, true); // 1
+                b.device_connect(1, 1, true); // 2
+                b.device_connect(1, 2, true); // 3
+                b.device_connect(1, 3, true); // 4
+                b.device_connect(1, 4, true); // 5
+                b.device_connect(5, 1, false) // 6
+            }
+            .unwrap();
+
+            let mut p = InterruptPacket::new();
+            p.address = 5;
+            p.size = 1;
+            p.data[0] = 0b10; // bit 1 set => port 1 needs attention
+            let fut = pin!(f.bus.handle_hub_packet(
+                &f.hub_state,
+                p.view(),
+                no_delay
+            ));
+            let poll = fut.poll(f.c);
             let result = unwrap_poll(poll).unwrap();
+            let mut devices = BitSet::new();
+            devices.set(device);
             assert_eq!(
                 result,
-                Some(DeviceEvent::Connect(
-                    UnconfiguredDevice {
-                        usb_address: 1,
-                        usb_speed: UsbSpeed::Full12,
-                        packet_size_ep0: 8
-                    },
-                    DeviceInfo {
-                        vid: 0x1234,
-                        pid: 0x5678,
-                        class: 0,
-                        subclass: 0,
-                    }
+                Ok(DeviceEvent::PortError(
+                    f.hub_state.topology().port_path(5, 1),
+                    1,
+                    devices
                 ))
             );
+            assert!(!f.hub_state.topology().is_present(device));
+            assert!(f.hub_state.has_pending_ports());
+
+            let fut = pin!(f.bus.handle_pending_ports(&f.hub_state, no_delay));
+            let poll = fut.poll(f.c);
+            let result = unwrap_poll(poll).unwrap();
+            let DeviceEvent::Connect(_, _, path) = result else {
+                panic!("Connect expected");
+            };
+            assert_eq!(path.ports(), &[1, 4, 1]);
+            assert!(!f.hub_state.has_pending_ports());
+
+            // Still counted, in case it babbles again
+            assert_eq!(f.hub_state.port_errors.borrow()[5][1], 1);
         },
     );
 }
 
+#[cfg(feature = "hubs")]
 #[test]
-fn device_events_nh_first_delay_pends() {
+fn handle_hub_packet_port_error_waits() {
     do_test(
         |hc| {
             hc.expect_multi_interrupt_pipe_ignored();
-            hc.expect_device_detect().returning(|| {
-                let mut mdd = MockDeviceDetect::new();
-                mdd.expect_poll_next().returning(|_| {
-                    Poll::Ready(Some(DeviceStatus::Present(UsbSpeed::Full12)))
-                });
-                mdd
-            });
-            hc.expect_reset_root_port().withf(|r| *r).return_const(());
+            hc.expect_get_port_status::<1, 1, 2>(); // CONNECTION, C_PORT_ENABLE
+            hc.expect_clear_port_feature::<1, 17>(); // C_PORT_ENABLE
         },
         |f| {
-            let mut stream = pin!(f.bus.device_events_no_hubs(long_delay));
+            let mut p = InterruptPacket::new();
+            p.address = 5;
+            p.size = 1;
+            p.data[0] = 0b10; // bit 1 set => port 1 needs attention
+            let fut = pin!(f.bus.handle_hub_packet(
+                &f.hub_state,
+                p.view(),
+                no_delay
+            ));
+            let poll = fut.poll(f.c);
+            let result = unwrap_poll(poll).unwrap();
+            assert!(matches!(result, Ok(DeviceEvent::PortError(_, 1, _))));
 
-            let poll = stream.as_mut().poll_next(f.c);
-            assert!(poll.is_pending());
-            let poll = stream.as_mut().poll_next(f.c);
+            // The 100ms backoff isn't over
+            let mut fut =
+                pin!(f.bus.handle_pending_ports(&f.hub_state, long_delay));
+            let poll = fut.as_mut().poll(f.c);
             assert!(poll.is_pending());
         },
     );
 }
 
+#[cfg(feature = "hubs")]
 #[test]
-fn device_events_nh_second_delay_pends() {
+fn handle_hub_packet_port_error_gives_up() {
     do_test(
         |hc| {
             hc.expect_multi_interrupt_pipe_ignored();
-            hc.expect_device_detect().returning(|| {
-                let mut mdd = MockDeviceDetect::new();
-                mdd.expect_poll_next().returning(|_| {
-                    Poll::Ready(Some(DeviceStatus::Present(UsbSpeed::Full12)))
-                });
-                mdd
-            });
-            hc.expect_reset_root_port().withf(|r| *r).return_const(());
-            hc.expect_reset_root_port().withf(|r| !*r).return_const(());
+            hc.expect_get_port_status::<1, 1, 2>(); // CONNECTION, C_PORT_ENABLE
+            hc.expect_clear_port_feature::<1, 17>(); // C_PORT_ENABLE
         },
-        |f| {
-            let mut stream = pin!(f.bus.device_events_no_hubs(short_delay));
+        |mut f| {
+            f.hub_state.set_port_error_policy(PortErrorPolicy {
+                reenable_attempts: 2,
+                backoff_ms: 100,
+            });
+            f.hub_state.port_errors.borrow_mut()[5][1] = 2;
 
-            let poll = stream.as_mut().poll_next(f.c);
-            assert!(poll.is_pending());
-            let poll = stream.as_mut().poll_next(f.c);
-            assert!(poll.is_pending());
+            let mut p = InterruptPacket::new();
+            p.address = 5;
+            p.size = 1;
+            p.data[0] = 0b10; // bit 1 set => port 1 needs attention
+            let fut = pin!(f.bus.handle_hub_packet(
+                &f.hub_state,
+                p.view(),
+                no_delay
+            ));
+            let poll = fut.poll(f.c);
+            let result = unwrap_poll(poll).unwrap();
+            assert_eq!(
+                result,
+                Ok(DeviceEvent::PortDisabled(
+                    f.hub_state.topology().port_path(5, 1),
+                    BitSet::new()
+                ))
+            );
+            assert!(!f.hub_state.has_pending_ports());
         },
     );
 }
 
+#[cfg(feature = "hubs")]
 #[test]
-fn device_events_nh_new_device_fails() {
+fn handle_hub_packet_disabled_port_disconnected() {
     do_test(
         |hc| {
             hc.expect_multi_interrupt_pipe_ignored();
-            hc.expect_device_detect().returning(|| {
-                let mut mdd = MockDeviceDetect::new();
-                mdd.expect_poll_next().returning(|_| {
-                    Poll::Ready(Some(DeviceStatus::Present(UsbSpeed::Full12)))
-                });
-                mdd
-            });
-            hc.expect_reset_root_port().withf(|r| *r).return_const(());
-            hc.expect_reset_root_port().withf(|r| !*r).return_const(());
-
-            // new_device(): first call (wLength == 8)
-            hc.expect_control_transfer()
-                .times(1)
-                .withf(is_get_device_descriptor::<8>)
-                .returning(control_transfer_timeout);
+            // C_PORT_ENABLE, but nothing is connected any more
+            hc.expect_get_port_status::<1, 0, 2>();
+            hc.expect_clear_port_feature::<1, 17>(); // C_PORT_ENABLE
         },
         |f| {
-            let stream = pin!(f.bus.device_events_no_hubs(no_delay));
-            let poll = stream.poll_next(f.c);
+            let mut p = InterruptPacket::new();
+            p.address = 5;
+            p.size = 1;
+            p.data[0] = 0b10; // bit 1 set => port 1 needs attention
+            let fut = pin!(f.bus.handle_hub_packet(
+                &f.hub_state,
+                p.view(),
+                no_delay
+            ));
+            let poll = fut.poll(f.c);
             let result = unwrap_poll(poll).unwrap();
-            assert_eq!(
-                result,
-                Some(DeviceEvent::EnumerationError(0, 1, UsbError::Timeout))
-            );
+            assert_eq!(result, Ok(DeviceEvent::None));
+            assert!(!f.hub_state.has_pending_ports());
         },
     );
 }
 
+#[cfg(feature = "hubs")]
 #[test]
-fn device_events_nh_new_device_pends() {
+fn port_connection_forgets_port_errors() {
     do_test(
         |hc| {
             hc.expect_multi_interrupt_pipe_ignored();
-            hc.expect_device_detect().returning(|| {
-                let mut mdd = MockDeviceDetect::new();
-                mdd.expect_poll_next().returning(|_| {
-                    Poll::Ready(Some(DeviceStatus::Present(UsbSpeed::Full12)))
-                });
-                mdd
-            });
-            hc.expect_reset_root_port().withf(|r| *r).return_const(());
-            hc.expect_reset_root_port().withf(|r| !*r).return_const(());
-
-            // new_device(): first call (wLength == 8)
-            hc.expect_control_transfer()
-                .times(1)
-                .withf(is_get_device_descriptor::<8>)
-                .returning(control_transfer_pending);
+            hc.expect_get_port_status::<1, 0, 1>(); // C_PORT_CONNECTION
+            hc.expect_clear_port_feature::<1, 16>(); // C_PORT_CONNECTION
         },
         |f| {
-            let mut stream = pin!(f.bus.device_events_no_hubs(no_delay));
-            let poll = stream.as_mut().poll_next(f.c);
-            assert!(poll.is_pending());
-            let poll = stream.as_mut().poll_next(f.c);
-            assert!(poll.is_pending());
+            f.hub_state.port_errors.borrow_mut()[5][1] = 3;
+            let mut p = InterruptPacket::new();
+            p.address = 5;
+            p.size = 1;
+            p.data[0] = 0b10; // bit 1 set => port 1 needs attention
+            let fut = pin!(f.bus.handle_hub_packet(
+                &f.hub_state,
+                p.view(),
+                no_delay
+            ));
+            let poll = fut.poll(f.c);
+            let result = unwrap_poll(poll).unwrap();
+            assert!(matches!(result, Ok(DeviceEvent::Disconnect(_))));
+            assert_eq!(f.hub_state.port_errors.borrow()[5][1], 0);
         },
     );
 }
 
+#[cfg(feature = "hubs")]
 #[test]
-fn device_events_nh_set_address_fails() {
+fn handle_hub_packet_connection_change_resets_attempts() {
     do_test(
         |hc| {
             hc.expect_multi_interrupt_pipe_ignored();
-            hc.expect_device_detect().returning(|| {
-                let mut mdd = MockDeviceDetect::new();
-                mdd.expect_poll_next().returning(|_| {
-                    Poll::Ready(Some(DeviceStatus::Present(UsbSpeed::Full12)))
-                });
-                mdd
-            });
-            hc.expect_reset_root_port().withf(|r| *r).return_const(());
-            hc.expect_reset_root_port().withf(|r| !*r).return_const(());
-            hc.expect_get_device_descriptor_prefix();
-            hc.expect_get_device_descriptor();
-            // Set address (1)
-            hc.expect_control_transfer()
-                .times(1)
-                .withf(is_set_address::<1>)
-                .returning(control_transfer_timeout);
+            hc.expect_get_port_status::<1, 0, 1>(); // C_PORT_CONNECTION
+            hc.expect_clear_port_feature::<1, 16>(); // C_PORT_CONNECTION
         },
         |f| {
-            let stream = pin!(f.bus.device_events_no_hubs(no_delay));
-            let poll = stream.poll_next(f.c);
+            f.hub_state.attempts.borrow_mut()[5][1] = 1;
+            f.hub_state.retries.set({
+                let mut r = [0u16; 16];
+                r[5] = 0b10;
+                r
+            });
+
+            // Handling the disconnection cancels the pending retry
+            let fut =
+                pin!(f.bus.handle_hub_port(&f.hub_state, 5, 1, no_delay));
+            let poll = fut.poll(f.c);
             let result = unwrap_poll(poll).unwrap();
-            assert_eq!(
-                result,
-                Some(DeviceEvent::EnumerationError(0, 1, UsbError::Timeout))
-            );
+            assert!(matches!(result, Ok(DeviceEvent::Disconnect(_))));
+            assert_eq!(f.hub_state.attempts.borrow()[5][1], 0);
+            assert!(!f.hub_state.has_pending_ports());
         },
     );
 }
 
+#[cfg(feature = "hubs")]
 #[test]
-fn device_events_nh_set_address_pends() {
+fn handle_hub_packet_connected_set_address_pends() {
     do_test(
         |hc| {
             hc.expect_multi_interrupt_pipe_ignored();
-            hc.expect_device_detect().returning(|| {
-                let mut mdd = MockDeviceDetect::new();
-                mdd.expect_poll_next().returning(|_| {
-                    Poll::Ready(Some(DeviceStatus::Present(UsbSpeed::Full12)))
-                });
-                mdd
-            });
-            hc.expect_reset_root_port().withf(|r| *r).return_const(());
-            hc.expect_reset_root_port().withf(|r| !*r).return_const(());
+            hc.expect_get_port_status::<1, 1, 1>(); // CONNECTION, C_PORT_CONNECTION
+            hc.expect_clear_port_feature::<1, 16>(); // C_PORT_CONNECTION
+            hc.expect_set_port_feature::<1, 4>(); // PORT_RESET
+            hc.expect_get_port_status::<1, 3, 0>(); // ENABLED
             hc.expect_get_device_descriptor_prefix();
             hc.expect_get_device_descriptor();
 
-            // Set address (1)
+            // Set address (127)
             hc.expect_control_transfer()
                 .times(1)
-                .withf(is_set_address::<1>)
+                .withf(is_set_address::<127>)
                 .returning(control_transfer_pending);
         },
         |f| {
-            let mut stream = pin!(f.bus.device_events_no_hubs(no_delay));
-            let poll = stream.as_mut().poll_next(f.c);
+            let mut p = InterruptPacket::new();
+            p.address = 5;
+            p.size = 1;
+            p.data[0] = 0b10; // bit 1 set => port 1 needs attention
+            let mut fut = pin!(f.bus.handle_hub_packet(
+                &f.hub_state,
+                p.view(),
+                no_delay
+            ));
+
+            let poll = fut.as_mut().poll(f.c);
             assert!(poll.is_pending());
-            let poll = stream.as_mut().poll_next(f.c);
+            let poll = fut.as_mut().poll(f.c);
             assert!(poll.is_pending());
         },
     );
 }
 
-#[test]
-fn device_events_nh_disconnect() {
-    do_test(
-        |hc| {
-            hc.expect_multi_interrupt_pipe_ignored();
-            hc.expect_device_detect().returning(|| {
-                let mut mdd = MockDeviceDetect::new();
-                mdd.expect_poll_next()
-                    .returning(|_| Poll::Ready(Some(DeviceStatus::Absent)));
-                mdd
-            });
+#[cfg(feature = "hubs")]
+fn device_descriptor_prefix_hub(bytes: &mut [u8]) -> usize {
+    bytes[0] = 18;
+    bytes[1] = DEVICE_DESCRIPTOR;
+    bytes[4] = HUB_CLASSCODE;
+    bytes[7] = 8;
+    8
+}
+
+#[cfg(feature = "hubs")]
+fn device_descriptor_hub(bytes: &mut [u8]) -> usize {
+    device_descriptor_prefix(bytes);
+    bytes[8] = 0x34;
+    bytes[9] = 0x12;
+    bytes[10] = 0x78;
+    bytes[11] = 0x56;
+    18
+}
+
+#[cfg(feature = "hubs")]
+#[test]
+fn handle_hub_packet_connected_hub() {
+    do_test(
+        |hc| {
+            hc.expect_add_to_multi_interrupt_pipe();
+            hc.expect_get_port_status::<1, 1, 1>(); // CONNECTION, C_PORT_CONNECTION
+            hc.expect_clear_port_feature::<1, 16>(); // C_PORT_CONNECTION
+            hc.expect_set_port_feature::<1, 4>(); // PORT_RESET
+            hc.expect_get_port_status::<1, 3, 0>(); // ENABLED
+            hc.expect_get_device_descriptor_prefix_hub();
+            hc.expect_get_device_descriptor_hub();
+            hc.expect_set_address::<1>();
+
+            // new_hub()
+            hc.expect_get_configuration::<1>();
+            hc.expect_set_configuration::<1, 1>();
+            hc.expect_get_hub_descriptor::<1>();
+            hc.expect_set_port_power::<1, 1>();
+            hc.expect_set_port_power::<1, 2>();
         },
         |f| {
-            let stream = pin!(f.bus.device_events_no_hubs(no_delay));
-            let poll = stream.poll_next(f.c);
+            let mut p = InterruptPacket::new();
+            p.address = 5;
+            p.size = 1;
+            p.data[0] = 0b10; // bit 1 set => port 1 needs attention
+            let fut = pin!(f.bus.handle_hub_packet(
+                &f.hub_state,
+                p.view(),
+                no_delay
+            ));
+
+            let poll = fut.poll(f.c);
             let result = unwrap_poll(poll).unwrap();
             assert_eq!(
                 result,
-                Some(DeviceEvent::Disconnect(BitSet(0xFFFF_FFFF)))
+                Ok(DeviceEvent::HubConnect(
+                    UsbDevice {
+                        usb_address: 1,
+                        usb_speed: UsbSpeed::Full12,
+                        packet_size_ep0: 8,
+                        in_endpoints_bitmap: 4,
+                        out_endpoints_bitmap: 2,
+                    },
+                    f.hub_state.topology().port_path(5, 1)
+                ))
             );
         },
     );
 }
 
+#[cfg(feature = "hubs")]
 #[test]
-fn device_events_root_connect() {
+fn handle_hub_packet_connected_hub_new_hub_fails() {
+    do_test(
+        |hc| {
+            hc.expect_multi_interrupt_pipe_ignored();
+            hc.expect_get_port_status::<1, 1, 1>(); // CONNECTION, C_PORT_CONNECTION
+            hc.expect_clear_port_feature::<1, 16>(); // C_PORT_CONNECTION
+            hc.expect_set_port_feature::<1, 4>(); // PORT_RESET
+            hc.expect_get_port_status::<1, 3, 0>(); // ENABLED
+            hc.expect_get_device_descriptor_prefix_hub();
+            hc.expect_get_device_descriptor_hub();
+            hc.expect_set_address::<1>();
+
+            // new_hub(): get_basic_configuration
+            hc.expect_control_transfer()
+                .times(1)
+                .withf(is_get_configuration_descriptor::<1>)
+                .returning(control_transfer_timeout);
+        },
+        |f| {
+            let mut p = InterruptPacket::new();
+            p.address = 5;
+            p.size = 1;
+            p.data[0] = 0b10; // bit 1 set => port 1 needs attention
+            let fut = pin!(f.bus.handle_hub_packet(
+                &f.hub_state,
+                p.view(),
+                no_delay
+            ));
+
+            let poll = fut.poll(f.c);
+            let result = unwrap_poll(poll).unwrap();
+            assert_eq!(
+                result,
+                Ok(DeviceEvent::EnumerationRetry(
+                    f.hub_state.topology().port_path(5, 1),
+                    1,
+                    UsbError::Timeout
+                ))
+            );
+
+            // The hub's address has been freed again
+            assert!(!f.hub_state.topology().is_present(1));
+        },
+    );
+}
+
+#[cfg(feature = "hubs")]
+#[test]
+fn handle_hub_packet_connected_hub_new_hub_pends() {
+    do_test(
+        |hc| {
+            hc.expect_multi_interrupt_pipe_ignored();
+            hc.expect_get_port_status::<1, 1, 1>(); // CONNECTION, C_PORT_CONNECTION
+            hc.expect_clear_port_feature::<1, 16>(); // C_PORT_CONNECTION
+            hc.expect_set_port_feature::<1, 4>(); // PORT_RESET
+            hc.expect_get_port_status::<1, 3, 0>(); // ENABLED
+            hc.expect_get_device_descriptor_prefix_hub();
+            hc.expect_get_device_descriptor_hub();
+            hc.expect_set_address::<1>();
+
+            // new_hub(): get_basic_configuration
+            hc.expect_control_transfer()
+                .times(1)
+                .withf(is_get_configuration_descriptor::<1>)
+                .returning(control_transfer_pending);
+        },
+        |f| {
+            let mut p = InterruptPacket::new();
+            p.address = 5;
+            p.size = 1;
+            p.data[0] = 0b10; // bit 1 set => port 1 needs attention
+            let mut fut = pin!(f.bus.handle_hub_packet(
+                &f.hub_state,
+                p.view(),
+                no_delay
+            ));
+
+            let poll = fut.as_mut().poll(f.c);
+            assert!(poll.is_pending());
+            let poll = fut.as_mut().poll(f.c);
+            assert!(poll.is_pending());
+        },
+    );
+}
+
+#[cfg(feature = "hubs")]
+#[test]
+fn handle_hub_packet_enabled_too_many_devices() {
+    do_test(
+        |hc| {
+            hc.expect_multi_interrupt_pipe_ignored();
+            hc.expect_get_port_status::<1, 1, 1>(); // CONNECTION, C_PORT_CONNECTION
+            hc.expect_clear_port_feature::<1, 16>(); // C_PORT_CONNECTION
+            hc.expect_set_port_feature::<1, 4>(); // PORT_RESET
+            hc.expect_get_port_status::<1, 3, 0>(); // ENABLED
+            hc.expect_get_device_descriptor_prefix_hub();
+            hc.expect_get_device_descriptor_hub();
+        },
+        |f| {
+            {
+                let mut state = f.hub_state.topology.borrow_mut();
+                for i in 1..16 {
+                    state.device_connect(0, i, true);
+                }
+            }
+
+            let mut p = InterruptPacket::new();
+            p.address = 5;
+            p.size = 1;
+            p.data[0] = 0b10; // bit 1 set => port 1 needs attention
+            let fut = pin!(f.bus.handle_hub_packet(
+                &f.hub_state,
+                p.view(),
+                no_delay
+            ));
+            let poll = fut.poll(f.c);
+            let result = unwrap_poll(poll).unwrap();
+            assert_eq!(
+                result,
+                Ok(DeviceEvent::EnumerationRetry(
+                    f.hub_state.topology().port_path(5, 1),
+                    1,
+                    UsbError::TooManyDevices
+                ))
+            );
+        },
+    );
+}
+
+#[cfg(feature = "hubs")]
+#[test]
+fn handle_hub_packet_simultaneous_connections() {
+    do_test(
+        |hc| {
+            hc.expect_multi_interrupt_pipe_ignored();
+            hc.expect_get_port_status::<1, 1, 1>(); // CONNECTION, C_PORT_CONNECTION
+            hc.expect_clear_port_feature::<1, 16>(); // C_PORT_CONNECTION
+            hc.expect_set_port_feature::<1, 4>(); // PORT_RESET
+            hc.expect_get_port_status::<1, 3, 0>(); // ENABLED
+            hc.expect_get_device_descriptor_prefix();
+            hc.expect_get_device_descriptor();
+            hc.expect_set_address::<127>();
+            hc.expect_get_port_status::<2, 1, 1>(); // CONNECTION, C_PORT_CONNECTION
+            hc.expect_clear_port_feature::<2, 16>(); // C_PORT_CONNECTION
+            hc.expect_set_port_feature::<2, 4>(); // PORT_RESET
+            hc.expect_get_port_status::<2, 3, 0>(); // ENABLED
+            hc.expect_get_device_descriptor_prefix();
+            hc.expect_get_device_descriptor();
+            hc.expect_set_address::<126>();
+        },
+        |f| {
+            let mut p = InterruptPacket::new();
+            p.address = 5;
+            p.size = 1;
+            p.data[0] = 0b110; // ports 1 and 2 both need attention
+            let fut = pin!(f.bus.handle_hub_packet(
+                &f.hub_state,
+                p.view(),
+                no_delay
+            ));
+            let poll = fut.poll(f.c);
+            let result = unwrap_poll(poll).unwrap().unwrap();
+            let DeviceEvent::Connect(device, _, _) = result else {
+                panic!("Connect expected");
+            };
+            assert_eq!(device.address(), 127);
+
+            // Port 2 must not have been forgotten
+            assert!(f.hub_state.has_pending_ports());
+
+            let fut = pin!(f.bus.handle_pending_ports(&f.hub_state, no_delay));
+            let poll = fut.poll(f.c);
+            let result = unwrap_poll(poll).unwrap();
+            let DeviceEvent::Connect(device, _, _) = result else {
+                panic!("Connect expected");
+            };
+            assert_eq!(device.address(), 126);
+            assert!(!f.hub_state.has_pending_ports());
+        },
+    );
+}
+
+#[cfg(feature = "hubs")]
+#[test]
+fn handle_hub_packet_waits_for_enumeration_lock() {
+    do_test(
+        |hc| {
+            hc.expect_multi_interrupt_pipe_ignored();
+            hc.expect_get_port_status::<1, 1, 1>(); // CONNECTION, C_PORT_CONNECTION
+            hc.expect_clear_port_feature::<1, 16>(); // C_PORT_CONNECTION
+            hc.expect_set_port_feature::<1, 4>(); // PORT_RESET
+            hc.expect_get_port_status::<1, 3, 0>(); // ENABLED
+            hc.expect_get_device_descriptor_prefix();
+            hc.expect_get_device_descriptor();
+            hc.expect_set_address::<127>();
+        },
+        |f| {
+            // Some other port is busy being enumerated
+            let other = f.hub_state.enumeration.try_alloc().unwrap();
+
+            let mut p = InterruptPacket::new();
+            p.address = 5;
+            p.size = 1;
+            p.data[0] = 0b10; // bit 1 set => port 1 needs attention
+            let mut fut = pin!(f.bus.handle_hub_packet(
+                &f.hub_state,
+                p.view(),
+                no_delay
+            ));
+            let poll = fut.as_mut().poll(f.c);
+            assert!(poll.is_pending());
+            let poll = fut.as_mut().poll(f.c);
+            assert!(poll.is_pending());
+
+            drop(other);
+
+            let poll = fut.as_mut().poll(f.c);
+            let result = unwrap_poll(poll).unwrap().unwrap();
+            let DeviceEvent::Connect(device, _, _) = result else {
+                panic!("Connect expected");
+            };
+            assert_eq!(device.address(), 127);
+            assert!(f.hub_state.enumeration.try_alloc().is_some());
+        },
+    );
+}
+
+#[cfg(feature = "hubs")]
+#[test]
+fn hub_state_queues_ports() {
+    let hub_state = HubState::<MockHostController>::default();
+    assert!(!hub_state.has_pending_ports());
+    assert_eq!(hub_state.next_pending_port(), None);
+
+    hub_state.queue_ports(5, 0b1010).unwrap();
+    hub_state.queue_ports(2, 0b100).unwrap();
+    assert!(hub_state.has_pending_ports());
+
+    assert_eq!(hub_state.next_pending_port(), Some((2, 2)));
+    assert_eq!(hub_state.next_pending_port(), Some((5, 1)));
+    assert_eq!(hub_state.next_pending_port(), Some((5, 3)));
+    assert_eq!(hub_state.next_pending_port(), None);
+    assert!(!hub_state.has_pending_ports());
+}
+
+#[cfg(feature = "hubs")]
+#[test]
+#[should_panic]
+fn hub_state_refuses_non_hub_ports() {
+    let hub_state = HubState::<MockHostController>::default();
+    let _ = hub_state.queue_ports(200, 0b100); // not a hub address
+}
+
+#[test]
+fn device_events_nh() {
     do_test(
         |hc| {
             hc.expect_multi_interrupt_pipe_ignored();
             hc.expect_device_detect().returning(|| {
                 let mut mdd = MockDeviceDetect::new();
                 mdd.expect_poll_next().returning(|_| {
-                    Poll::Ready(Some(DeviceStatus::Present(UsbSpeed::Low1_5)))
+                    Poll::Ready(Some(DeviceStatus::Present(UsbSpeed::Full12)))
                 });
                 mdd
             });
-
             hc.expect_reset_root_port().withf(|r| *r).return_const(());
             hc.expect_reset_root_port().withf(|r| !*r).return_const(());
             hc.expect_get_device_descriptor_prefix();
             hc.expect_get_device_descriptor();
-            hc.expect_set_address::<31>();
+            hc.expect_set_address::<1>();
         },
         |f| {
-            let stream = pin!(f.bus.device_events(&f.hub_state, no_delay));
+            let stream = pin!(f.bus.device_events_no_hubs(no_delay));
             let poll = stream.poll_next(f.c);
             let result = unwrap_poll(poll).unwrap();
             assert_eq!(
                 result,
                 Some(DeviceEvent::Connect(
                     UnconfiguredDevice {
-                        usb_address: 31,
-                        usb_speed: UsbSpeed::Low1_5,
+                        usb_address: 1,
+                        usb_speed: UsbSpeed::Full12,
                         packet_size_ep0: 8
                     },
                     DeviceInfo {
@@ -2611,7 +3780,11 @@ fn device_events_root_connect() {
                         pid: 0x5678,
                         class: 0,
                         subclass: 0,
-                    }
+                        usb_version: 0,
+                        device_release: 0,
+                        serial_number_index: 0,
+                    },
+                    PortPath::root()
                 ))
             );
         },
@@ -2619,23 +3792,21 @@ fn device_events_root_connect() {
 }
 
 #[test]
-fn device_events_first_delay_pends() {
+fn device_events_nh_first_delay_pends() {
     do_test(
         |hc| {
             hc.expect_multi_interrupt_pipe_ignored();
             hc.expect_device_detect().returning(|| {
                 let mut mdd = MockDeviceDetect::new();
                 mdd.expect_poll_next().returning(|_| {
-                    Poll::Ready(Some(DeviceStatus::Present(UsbSpeed::Low1_5)))
+                    Poll::Ready(Some(DeviceStatus::Present(UsbSpeed::Full12)))
                 });
                 mdd
             });
-
             hc.expect_reset_root_port().withf(|r| *r).return_const(());
         },
         |f| {
-            let mut stream =
-                pin!(f.bus.device_events(&f.hub_state, long_delay));
+            let mut stream = pin!(f.bus.device_events_no_hubs(long_delay));
 
             let poll = stream.as_mut().poll_next(f.c);
             assert!(poll.is_pending());
@@ -2646,7 +3817,7 @@ fn device_events_first_delay_pends() {
 }
 
 #[test]
-fn device_events_second_delay_pends() {
+fn device_events_nh_second_delay_pends() {
     do_test(
         |hc| {
             hc.expect_multi_interrupt_pipe_ignored();
@@ -2657,13 +3828,12 @@ fn device_events_second_delay_pends() {
                 });
                 mdd
             });
-
             hc.expect_reset_root_port().withf(|r| *r).return_const(());
             hc.expect_reset_root_port().withf(|r| !*r).return_const(());
         },
         |f| {
-            let mut stream =
-                pin!(f.bus.device_events(&f.hub_state, short_delay));
+            let mut stream = pin!(f.bus.device_events_no_hubs(short_delay));
+
             let poll = stream.as_mut().poll_next(f.c);
             assert!(poll.is_pending());
             let poll = stream.as_mut().poll_next(f.c);
@@ -2673,7 +3843,7 @@ fn device_events_second_delay_pends() {
 }
 
 #[test]
-fn device_events_new_device_fails() {
+fn device_events_nh_new_device_fails() {
     do_test(
         |hc| {
             hc.expect_multi_interrupt_pipe_ignored();
@@ -2693,20 +3863,24 @@ fn device_events_new_device_fails() {
                 .withf(is_get_device_descriptor::<8>)
                 .returning(control_transfer_timeout);
         },
-        |f| {
-            let stream = pin!(f.bus.device_events(&f.hub_state, no_delay));
+        |mut f| {
+            f.bus.set_control_retry_policy(NO_CONTROL_RETRIES);
+            let stream = pin!(f.bus.device_events_no_hubs(no_delay));
             let poll = stream.poll_next(f.c);
             let result = unwrap_poll(poll).unwrap();
             assert_eq!(
                 result,
-                Some(DeviceEvent::EnumerationError(0, 1, UsbError::Timeout))
+                Some(DeviceEvent::EnumerationError(
+                    PortPath::root(),
+                    UsbError::Timeout
+                ))
             );
         },
     );
 }
 
 #[test]
-fn device_events_new_device_pends() {
+fn device_events_nh_new_device_pends() {
     do_test(
         |hc| {
             hc.expect_multi_interrupt_pipe_ignored();
@@ -2727,7 +3901,7 @@ fn device_events_new_device_pends() {
                 .returning(control_transfer_pending);
         },
         |f| {
-            let mut stream = pin!(f.bus.device_events(&f.hub_state, no_delay));
+            let mut stream = pin!(f.bus.device_events_no_hubs(no_delay));
             let poll = stream.as_mut().poll_next(f.c);
             assert!(poll.is_pending());
             let poll = stream.as_mut().poll_next(f.c);
@@ -2737,7 +3911,7 @@ fn device_events_new_device_pends() {
 }
 
 #[test]
-fn device_events_set_address_fails() {
+fn device_events_nh_set_address_fails() {
     do_test(
         |hc| {
             hc.expect_multi_interrupt_pipe_ignored();
@@ -2752,27 +3926,29 @@ fn device_events_set_address_fails() {
             hc.expect_reset_root_port().withf(|r| !*r).return_const(());
             hc.expect_get_device_descriptor_prefix();
             hc.expect_get_device_descriptor();
-
-            // Set address (31)
+            // Set address (1)
             hc.expect_control_transfer()
                 .times(1)
-                .withf(is_set_address::<31>)
+                .withf(is_set_address::<1>)
                 .returning(control_transfer_timeout);
         },
         |f| {
-            let stream = pin!(f.bus.device_events(&f.hub_state, no_delay));
+            let stream = pin!(f.bus.device_events_no_hubs(no_delay));
             let poll = stream.poll_next(f.c);
             let result = unwrap_poll(poll).unwrap();
             assert_eq!(
                 result,
-                Some(DeviceEvent::EnumerationError(0, 1, UsbError::Timeout))
+                Some(DeviceEvent::EnumerationError(
+                    PortPath::root(),
+                    UsbError::Timeout
+                ))
             );
         },
     );
 }
 
 #[test]
-fn device_events_set_address_pends() {
+fn device_events_nh_set_address_pends() {
     do_test(
         |hc| {
             hc.expect_multi_interrupt_pipe_ignored();
@@ -2788,14 +3964,14 @@ fn device_events_set_address_pends() {
             hc.expect_get_device_descriptor_prefix();
             hc.expect_get_device_descriptor();
 
-            // Set address (31)
+            // Set address (1)
             hc.expect_control_transfer()
                 .times(1)
-                .withf(is_set_address::<31>)
+                .withf(is_set_address::<1>)
                 .returning(control_transfer_pending);
         },
         |f| {
-            let mut stream = pin!(f.bus.device_events(&f.hub_state, no_delay));
+            let mut stream = pin!(f.bus.device_events_no_hubs(no_delay));
             let poll = stream.as_mut().poll_next(f.c);
             assert!(poll.is_pending());
             let poll = stream.as_mut().poll_next(f.c);
@@ -2805,7 +3981,7 @@ fn device_events_set_address_pends() {
 }
 
 #[test]
-fn device_events_root_disconnect() {
+fn device_events_nh_disconnect() {
     do_test(
         |hc| {
             hc.expect_multi_interrupt_pipe_ignored();
@@ -2817,61 +3993,115 @@ fn device_events_root_disconnect() {
             });
         },
         |f| {
-            let stream = pin!(f.bus.device_events(&f.hub_state, no_delay));
+            let stream = pin!(f.bus.device_events_no_hubs(no_delay));
             let poll = stream.poll_next(f.c);
             let result = unwrap_poll(poll).unwrap();
             assert_eq!(
                 result,
-                Some(DeviceEvent::Disconnect(BitSet(0xFFFF_FFFF)))
+                Some(DeviceEvent::Disconnect(BitSet(u128::MAX)))
             );
         },
     );
 }
 
+/// Every variant, matched without a wildcard, so that this stops
+/// compiling if any variant comes and goes with the `hubs` feature
+fn event_name(event: &DeviceEvent) -> &'static str {
+    match event {
+        DeviceEvent::Connect(..) => "Connect",
+        DeviceEvent::HubConnect(..) => "HubConnect",
+        DeviceEvent::Resumed(..) => "Resumed",
+        DeviceEvent::Disconnect(..) => "Disconnect",
+        DeviceEvent::EnumerationError(..) => "EnumerationError",
+        DeviceEvent::EnumerationRetry(..) => "EnumerationRetry",
+        DeviceEvent::ErrorRateWarning(..) => "ErrorRateWarning",
+        DeviceEvent::Unresponsive(..) => "Unresponsive",
+        DeviceEvent::PortError(..) => "PortError",
+        DeviceEvent::PortDisabled(..) => "PortDisabled",
+        DeviceEvent::Rejected(..) => "Rejected",
+        DeviceEvent::Offered(..) => "Offered",
+        DeviceEvent::Interrupt(..) => "Interrupt",
+        DeviceEvent::InterruptError(..) => "InterruptError",
+        DeviceEvent::None => "None",
+    }
+}
+
+#[test]
+fn device_event_variants_dont_depend_on_hubs() {
+    let events = [
+        DeviceEvent::HubConnect(EXAMPLE_DEVICE, PortPath::root()),
+        DeviceEvent::EnumerationRetry(PortPath::root(), 1, UsbError::Timeout),
+        DeviceEvent::ErrorRateWarning(1, TransferStatistics::default()),
+        DeviceEvent::Unresponsive(1, BitSet::new()),
+        DeviceEvent::PortError(PortPath::root(), 1, BitSet::new()),
+        DeviceEvent::PortDisabled(PortPath::root(), BitSet::new()),
+        DeviceEvent::Interrupt(BindingId(0), InterruptPacket::new()),
+        DeviceEvent::InterruptError(BindingId(0), 1, UsbError::AllPipesInUse),
+    ];
+    let names = events.iter().map(event_name).collect::<Vec<_>>();
+    assert_eq!(
+        names,
+        [
+            "HubConnect",
+            "EnumerationRetry",
+            "ErrorRateWarning",
+            "Unresponsive",
+            "PortError",
+            "PortDisabled",
+            "Interrupt",
+            "InterruptError",
+        ]
+    );
+}
+
 #[test]
-fn device_events_root_connect_is_hub() {
+fn device_events_nh_connect_then_disconnect() {
     do_test(
         |hc| {
-            hc.expect_add_to_multi_interrupt_pipe();
+            hc.expect_multi_interrupt_pipe_ignored();
             hc.expect_device_detect().returning(|| {
                 let mut mdd = MockDeviceDetect::new();
-                mdd.expect_poll_next().returning(|_| {
-                    Poll::Ready(Some(DeviceStatus::Present(UsbSpeed::Low1_5)))
+                let mut present = true;
+                mdd.expect_poll_next().returning(move |_| {
+                    let status = if present {
+                        DeviceStatus::Present(UsbSpeed::Full12)
+                    } else {
+                        DeviceStatus::Absent
+                    };
+                    present = false;
+                    Poll::Ready(Some(status))
                 });
                 mdd
             });
             hc.expect_reset_root_port().withf(|r| *r).return_const(());
             hc.expect_reset_root_port().withf(|r| !*r).return_const(());
-            hc.expect_get_device_descriptor_prefix_hub();
-            hc.expect_get_device_descriptor_hub();
+            hc.expect_get_device_descriptor_prefix();
+            hc.expect_get_device_descriptor();
             hc.expect_set_address::<1>();
-            hc.expect_get_configuration::<1>();
-            hc.expect_set_configuration::<1, 1>();
-            hc.expect_get_configuration::<1>();
-            hc.expect_get_hub_descriptor::<1>();
-            hc.expect_set_port_power::<1, 1>();
-            hc.expect_set_port_power::<1, 2>();
         },
         |f| {
-            let stream = pin!(f.bus.device_events(&f.hub_state, no_delay));
-            let poll = stream.poll_next(f.c);
+            let mut stream = pin!(f.bus.device_events_no_hubs(no_delay));
+            let poll = stream.as_mut().poll_next(f.c);
+            let result = unwrap_poll(poll).unwrap();
+            let Some(DeviceEvent::Connect(device, _, path)) = result else {
+                panic!("Connect expected");
+            };
+            assert_eq!(device.address(), 1);
+            assert_eq!(path, PortPath::root());
+
+            let poll = stream.as_mut().poll_next(f.c);
             let result = unwrap_poll(poll).unwrap();
             assert_eq!(
                 result,
-                Some(DeviceEvent::HubConnect(UsbDevice {
-                    usb_address: 1,
-                    usb_speed: UsbSpeed::Low1_5,
-                    packet_size_ep0: 8,
-                    in_endpoints_bitmap: 4,
-                    out_endpoints_bitmap: 2,
-                },))
+                Some(DeviceEvent::Disconnect(BitSet(u128::MAX)))
             );
         },
     );
 }
 
+#[cfg(feature = "hubs")]
 #[test]
-fn device_events_root_connect_new_hub_fails() {
+fn device_events_root_connect() {
     do_test(
         |hc| {
             hc.expect_multi_interrupt_pipe_ignored();
@@ -2882,17 +4112,12 @@ fn device_events_root_connect_new_hub_fails() {
                 });
                 mdd
             });
+
             hc.expect_reset_root_port().withf(|r| *r).return_const(());
             hc.expect_reset_root_port().withf(|r| !*r).return_const(());
-            hc.expect_get_device_descriptor_prefix_hub();
-            hc.expect_get_device_descriptor_hub();
-            hc.expect_set_address::<1>();
-
-            // Call to get_basic_configuration
-            hc.expect_control_transfer()
-                .times(1)
-                .withf(is_get_configuration_descriptor::<1>)
-                .returning(control_transfer_timeout);
+            hc.expect_get_device_descriptor_prefix();
+            hc.expect_get_device_descriptor();
+            hc.expect_set_address::<127>();
         },
         |f| {
             let stream = pin!(f.bus.device_events(&f.hub_state, no_delay));
@@ -2900,14 +4125,31 @@ fn device_events_root_connect_new_hub_fails() {
             let result = unwrap_poll(poll).unwrap();
             assert_eq!(
                 result,
-                Some(DeviceEvent::EnumerationError(0, 1, UsbError::Timeout))
+                Some(DeviceEvent::Connect(
+                    UnconfiguredDevice {
+                        usb_address: 127,
+                        usb_speed: UsbSpeed::Low1_5,
+                        packet_size_ep0: 8
+                    },
+                    DeviceInfo {
+                        vid: 0x1234,
+                        pid: 0x5678,
+                        class: 0,
+                        subclass: 0,
+                        usb_version: 0,
+                        device_release: 0,
+                        serial_number_index: 0,
+                    },
+                    PortPath::root()
+                ))
             );
         },
     );
 }
 
+#[cfg(feature = "hubs")]
 #[test]
-fn device_events_root_connect_new_hub_pends() {
+fn device_events_first_delay_pends() {
     do_test(
         |hc| {
             hc.expect_multi_interrupt_pipe_ignored();
@@ -2918,20 +4160,13 @@ fn device_events_root_connect_new_hub_pends() {
                 });
                 mdd
             });
-            hc.expect_reset_root_port().withf(|r| *r).return_const(());
-            hc.expect_reset_root_port().withf(|r| !*r).return_const(());
-            hc.expect_get_device_descriptor_prefix_hub();
-            hc.expect_get_device_descriptor_hub();
-            hc.expect_set_address::<1>();
 
-            // Call to get_basic_configuration
-            hc.expect_control_transfer()
-                .times(1)
-                .withf(is_get_configuration_descriptor::<1>)
-                .returning(control_transfer_pending);
+            hc.expect_reset_root_port().withf(|r| *r).return_const(());
         },
         |f| {
-            let mut stream = pin!(f.bus.device_events(&f.hub_state, no_delay));
+            let mut stream =
+                pin!(f.bus.device_events(&f.hub_state, long_delay));
+
             let poll = stream.as_mut().poll_next(f.c);
             assert!(poll.is_pending());
             let poll = stream.as_mut().poll_next(f.c);
@@ -2940,95 +4175,96 @@ fn device_events_root_connect_new_hub_pends() {
     );
 }
 
+#[cfg(feature = "hubs")]
 #[test]
-fn device_events_hub_packet() {
+fn device_events_second_delay_pends() {
     do_test(
         |hc| {
+            hc.expect_multi_interrupt_pipe_ignored();
             hc.expect_device_detect().returning(|| {
                 let mut mdd = MockDeviceDetect::new();
-                mdd.expect_poll_next().returning(|_| Poll::Pending);
+                mdd.expect_poll_next().returning(|_| {
+                    Poll::Ready(Some(DeviceStatus::Present(UsbSpeed::Full12)))
+                });
                 mdd
             });
+
+            hc.expect_reset_root_port().withf(|r| *r).return_const(());
+            hc.expect_reset_root_port().withf(|r| !*r).return_const(());
         },
         |f| {
-            f.hub_state.pipes.borrow_mut()[0] = {
-                let mut ip = MockInterruptPipe::new();
-                ip.expect_poll_next().returning(|_| {
-                    let mut ip = InterruptPacket::new();
-                    ip.size = 1;
-                    Poll::Ready(Some(ip))
-                });
-                Some(ip)
-            };
-            let stream = pin!(f.bus.device_events(&f.hub_state, no_delay));
-
-            let poll = stream.poll_next(f.c);
-            let result = unwrap_poll(poll).unwrap();
-            assert_eq!(result, Some(DeviceEvent::None));
+            let mut stream =
+                pin!(f.bus.device_events(&f.hub_state, short_delay));
+            let poll = stream.as_mut().poll_next(f.c);
+            assert!(poll.is_pending());
+            let poll = stream.as_mut().poll_next(f.c);
+            assert!(poll.is_pending());
         },
     );
 }
 
+#[cfg(feature = "hubs")]
 #[test]
-fn device_events_hub_packet_fails() {
+fn device_events_new_device_fails() {
     do_test(
         |hc| {
+            hc.expect_multi_interrupt_pipe_ignored();
             hc.expect_device_detect().returning(|| {
                 let mut mdd = MockDeviceDetect::new();
-                mdd.expect_poll_next().returning(|_| Poll::Pending);
+                mdd.expect_poll_next().returning(|_| {
+                    Poll::Ready(Some(DeviceStatus::Present(UsbSpeed::Full12)))
+                });
                 mdd
             });
+            hc.expect_reset_root_port().withf(|r| *r).return_const(());
+            hc.expect_reset_root_port().withf(|r| !*r).return_const(());
+
+            // new_device(): first call (wLength == 8)
+            hc.expect_control_transfer()
+                .times(1)
+                .withf(is_get_device_descriptor::<8>)
+                .returning(control_transfer_timeout);
         },
-        |f| {
-            f.hub_state.pipes.borrow_mut()[0] = {
-                let mut ip = MockInterruptPipe::new();
-                ip.expect_poll_next().returning(|_| {
-                    Poll::Ready(Some(InterruptPacket::new())) // 0-length packet
-                });
-                Some(ip)
-            };
+        |mut f| {
+            f.bus.set_control_retry_policy(NO_CONTROL_RETRIES);
             let stream = pin!(f.bus.device_events(&f.hub_state, no_delay));
             let poll = stream.poll_next(f.c);
             let result = unwrap_poll(poll).unwrap();
             assert_eq!(
                 result,
-                Some(DeviceEvent::EnumerationError(
-                    0,
+                Some(DeviceEvent::EnumerationRetry(
+                    PortPath::root(),
                     1,
-                    UsbError::ProtocolError
+                    UsbError::Timeout
                 ))
             );
         },
     );
 }
 
+#[cfg(feature = "hubs")]
 #[test]
-fn device_events_hub_packet_pends() {
+fn device_events_new_device_pends() {
     do_test(
         |hc| {
+            hc.expect_multi_interrupt_pipe_ignored();
             hc.expect_device_detect().returning(|| {
                 let mut mdd = MockDeviceDetect::new();
-                mdd.expect_poll_next().returning(|_| Poll::Pending);
+                mdd.expect_poll_next().returning(|_| {
+                    Poll::Ready(Some(DeviceStatus::Present(UsbSpeed::Full12)))
+                });
                 mdd
             });
+            hc.expect_reset_root_port().withf(|r| *r).return_const(());
+            hc.expect_reset_root_port().withf(|r| !*r).return_const(());
 
+            // new_device(): first call (wLength == 8)
             hc.expect_control_transfer()
                 .times(1)
-                .withf(is_get_port_status::<1>)
+                .withf(is_get_device_descriptor::<8>)
                 .returning(control_transfer_pending);
         },
         |f| {
-            f.hub_state.pipes.borrow_mut()[0] = {
-                let mut mip = MockInterruptPipe::new();
-                mip.expect_poll_next().returning(|_| {
-                    let mut ip = InterruptPacket::new();
-                    ip.size = 1;
-                    ip.address = 5;
-                    ip.data[0] = 2;
-                    Poll::Ready(Some(ip))
-                });
-                Some(mip)
-            };
             let mut stream = pin!(f.bus.device_events(&f.hub_state, no_delay));
             let poll = stream.as_mut().poll_next(f.c);
             assert!(poll.is_pending());
@@ -3038,419 +4274,3404 @@ fn device_events_hub_packet_pends() {
     );
 }
 
-fn is_read_mac_address(
-    a: &u8,
-    p: &u8,
-    s: &SetupPacket,
-    d: &DataPhase,
-) -> bool {
-    *a == 5
-        && *p == 8
-        && s.bmRequestType == DEVICE_TO_HOST | VENDOR_REQUEST
-        && s.bRequest == 0x13
-        && s.wValue == 0
-        && s.wIndex == 0
-        && s.wLength == 6
-        && d.is_in()
-}
-
+#[cfg(feature = "hubs")]
 #[test]
-fn control_transfer() {
+fn device_events_set_address_fails() {
     do_test(
         |hc| {
+            hc.expect_multi_interrupt_pipe_ignored();
+            hc.expect_device_detect().returning(|| {
+                let mut mdd = MockDeviceDetect::new();
+                mdd.expect_poll_next().returning(|_| {
+                    Poll::Ready(Some(DeviceStatus::Present(UsbSpeed::Full12)))
+                });
+                mdd
+            });
+            hc.expect_reset_root_port().withf(|r| *r).return_const(());
+            hc.expect_reset_root_port().withf(|r| !*r).return_const(());
+            hc.expect_get_device_descriptor_prefix();
+            hc.expect_get_device_descriptor();
+
+            // Set address (127)
             hc.expect_control_transfer()
                 .times(1)
-                .withf(is_read_mac_address)
-                .returning(control_transfer_ok_with(|b| {
-                    b[0] = 1;
-                    6
-                }));
+                .withf(is_set_address::<127>)
+                .returning(control_transfer_timeout);
         },
         |f| {
-            let mut data = [0u8; 6];
-            let fut = pin!(f.bus.control_transfer(
-                &EXAMPLE_DEVICE,
-                SetupPacket {
-                    bmRequestType: DEVICE_TO_HOST | VENDOR_REQUEST,
-                    bRequest: 0x13,
-                    wValue: 0,
-                    wIndex: 0,
-                    wLength: 6,
-                },
-                DataPhase::In(&mut data),
-            ));
-
-            let poll = fut.poll(f.c);
-            assert!(poll.is_ready());
+            let stream = pin!(f.bus.device_events(&f.hub_state, no_delay));
+            let poll = stream.poll_next(f.c);
+            let result = unwrap_poll(poll).unwrap();
+            assert_eq!(
+                result,
+                Some(DeviceEvent::EnumerationRetry(
+                    PortPath::root(),
+                    1,
+                    UsbError::Timeout
+                ))
+            );
         },
     );
 }
 
+#[cfg(feature = "hubs")]
 #[test]
-fn control_transfer_pends() {
+fn device_events_set_address_pends() {
     do_test(
         |hc| {
-            hc.expect_control_transfer()
-                .times(1)
-                .withf(is_read_mac_address)
-                .returning(control_transfer_pending);
-        },
-        |f| {
-            let mut data = [0u8; 6];
-            let mut fut = pin!(f.bus.control_transfer(
-                &EXAMPLE_DEVICE,
-                SetupPacket {
+            hc.expect_multi_interrupt_pipe_ignored();
+            hc.expect_device_detect().returning(|| {
+                let mut mdd = MockDeviceDetect::new();
+                mdd.expect_poll_next().returning(|_| {
+                    Poll::Ready(Some(DeviceStatus::Present(UsbSpeed::Full12)))
+                });
+                mdd
+            });
+            hc.expect_reset_root_port().withf(|r| *r).return_const(());
+            hc.expect_reset_root_port().withf(|r| !*r).return_const(());
+            hc.expect_get_device_descriptor_prefix();
+            hc.expect_get_device_descriptor();
+
+            // Set address (127)
+            hc.expect_control_transfer()
+                .times(1)
+                .withf(is_set_address::<127>)
+                .returning(control_transfer_pending);
+        },
+        |f| {
+            let mut stream = pin!(f.bus.device_events(&f.hub_state, no_delay));
+            let poll = stream.as_mut().poll_next(f.c);
+            assert!(poll.is_pending());
+            let poll = stream.as_mut().poll_next(f.c);
+            assert!(poll.is_pending());
+        },
+    );
+}
+
+#[cfg(feature = "hubs")]
+fn expect_root_connect_once(hc: &mut MockHostControllerInner) {
+    hc.expect_device_detect().returning(|| {
+        let mut mdd = MockDeviceDetect::new();
+        mdd.expect_poll_next().times(1).returning(|_| {
+            Poll::Ready(Some(DeviceStatus::Present(UsbSpeed::Full12)))
+        });
+        mdd.expect_poll_next().returning(|_| Poll::Pending);
+        mdd
+    });
+}
+
+#[cfg(feature = "hubs")]
+#[test]
+fn device_events_retry_succeeds() {
+    do_test(
+        |hc| {
+            hc.expect_multi_interrupt_pipe_ignored();
+            expect_root_connect_once(hc);
+            hc.expect_reset_root_port()
+                .times(2)
+                .withf(|r| *r)
+                .return_const(());
+            hc.expect_reset_root_port()
+                .times(2)
+                .withf(|r| !*r)
+                .return_const(());
+
+            // First attempt fails in new_device()
+            hc.expect_control_transfer()
+                .times(1)
+                .withf(is_get_device_descriptor::<8>)
+                .returning(control_transfer_timeout);
+
+            // Second attempt succeeds
+            hc.expect_get_device_descriptor_prefix();
+            hc.expect_get_device_descriptor();
+            hc.expect_set_address::<127>();
+        },
+        |mut f| {
+            f.bus.set_control_retry_policy(NO_CONTROL_RETRIES);
+            let mut stream = pin!(f.bus.device_events(&f.hub_state, no_delay));
+            let poll = stream.as_mut().poll_next(f.c);
+            let result = unwrap_poll(poll).unwrap();
+            assert_eq!(
+                result,
+                Some(DeviceEvent::EnumerationRetry(
+                    PortPath::root(),
+                    1,
+                    UsbError::Timeout
+                ))
+            );
+
+            let poll = stream.as_mut().poll_next(f.c);
+            let result = unwrap_poll(poll).unwrap();
+            let Some(DeviceEvent::Connect(device, _, _)) = result else {
+                panic!("Connect expected");
+            };
+            assert_eq!(device.address(), 127);
+
+            let poll = stream.as_mut().poll_next(f.c);
+            assert!(poll.is_pending());
+        },
+    );
+}
+
+#[cfg(feature = "hubs")]
+#[test]
+fn device_events_retries_exhausted() {
+    do_test(
+        |hc| {
+            hc.expect_multi_interrupt_pipe_ignored();
+            expect_root_connect_once(hc);
+            hc.expect_reset_root_port()
+                .times(2)
+                .withf(|r| *r)
+                .return_const(());
+            hc.expect_reset_root_port()
+                .times(2)
+                .withf(|r| !*r)
+                .return_const(());
+            hc.expect_get_device_descriptor_prefix();
+            hc.expect_get_device_descriptor();
+            hc.expect_get_device_descriptor_prefix();
+            hc.expect_get_device_descriptor();
+            hc.expect_control_transfer()
+                .times(2)
+                .withf(is_set_address::<127>)
+                .returning(control_transfer_timeout);
+        },
+        |mut f| {
+            f.hub_state = HubState::with_retry_policy(RetryPolicy {
+                attempts: 2,
+                retry_delay_ms: 0,
+            });
+            let mut stream = pin!(f.bus.device_events(&f.hub_state, no_delay));
+            let poll = stream.as_mut().poll_next(f.c);
+            let result = unwrap_poll(poll).unwrap();
+            assert_eq!(
+                result,
+                Some(DeviceEvent::EnumerationRetry(
+                    PortPath::root(),
+                    1,
+                    UsbError::Timeout
+                ))
+            );
+
+            let poll = stream.as_mut().poll_next(f.c);
+            let result = unwrap_poll(poll).unwrap();
+            assert_eq!(
+                result,
+                Some(DeviceEvent::EnumerationError(
+                    PortPath::root(),
+                    UsbError::Timeout
+                ))
+            );
+
+            // Address 127 was freed each time
+            assert!(!f.hub_state.topology().is_present(127));
+
+            let poll = stream.as_mut().poll_next(f.c);
+            assert!(poll.is_pending());
+        },
+    );
+}
+
+#[cfg(feature = "hubs")]
+#[test]
+fn device_events_no_retries() {
+    do_test(
+        |hc| {
+            hc.expect_multi_interrupt_pipe_ignored();
+            expect_root_connect_once(hc);
+            hc.expect_reset_root_port()
+                .times(1)
+                .withf(|r| *r)
+                .return_const(());
+            hc.expect_reset_root_port()
+                .times(1)
+                .withf(|r| !*r)
+                .return_const(());
+            hc.expect_control_transfer()
+                .times(1)
+                .withf(is_get_device_descriptor::<8>)
+                .returning(control_transfer_timeout);
+        },
+        |mut f| {
+            f.bus.set_control_retry_policy(NO_CONTROL_RETRIES);
+            f.hub_state = HubState::with_retry_policy(RetryPolicy {
+                attempts: 1,
+                retry_delay_ms: 0,
+            });
+            let mut stream = pin!(f.bus.device_events(&f.hub_state, no_delay));
+            let poll = stream.as_mut().poll_next(f.c);
+            let result = unwrap_poll(poll).unwrap();
+            assert_eq!(
+                result,
+                Some(DeviceEvent::EnumerationError(
+                    PortPath::root(),
+                    UsbError::Timeout
+                ))
+            );
+
+            let poll = stream.as_mut().poll_next(f.c);
+            assert!(poll.is_pending());
+        },
+    );
+}
+
+#[cfg(feature = "hubs")]
+#[test]
+fn device_events_root_disconnect() {
+    do_test(
+        |hc| {
+            hc.expect_multi_interrupt_pipe_ignored();
+            hc.expect_device_detect().returning(|| {
+                let mut mdd = MockDeviceDetect::new();
+                mdd.expect_poll_next()
+                    .returning(|_| Poll::Ready(Some(DeviceStatus::Absent)));
+                mdd
+            });
+        },
+        |f| {
+            let stream = pin!(f.bus.device_events(&f.hub_state, no_delay));
+            let poll = stream.poll_next(f.c);
+            let result = unwrap_poll(poll).unwrap();
+            assert_eq!(
+                result,
+                Some(DeviceEvent::Disconnect(BitSet(u128::MAX)))
+            );
+        },
+    );
+}
+
+#[cfg(feature = "hubs")]
+#[test]
+fn device_events_root_connect_is_hub() {
+    do_test(
+        |hc| {
+            hc.expect_add_to_multi_interrupt_pipe();
+            hc.expect_device_detect().returning(|| {
+                let mut mdd = MockDeviceDetect::new();
+                mdd.expect_poll_next().returning(|_| {
+                    Poll::Ready(Some(DeviceStatus::Present(UsbSpeed::Low1_5)))
+                });
+                mdd
+            });
+            hc.expect_reset_root_port().withf(|r| *r).return_const(());
+            hc.expect_reset_root_port().withf(|r| !*r).return_const(());
+            hc.expect_get_device_descriptor_prefix_hub();
+            hc.expect_get_device_descriptor_hub();
+            hc.expect_set_address::<1>();
+            hc.expect_get_configuration::<1>();
+            hc.expect_set_configuration::<1, 1>();
+            hc.expect_get_hub_descriptor::<1>();
+            hc.expect_set_port_power::<1, 1>();
+            hc.expect_set_port_power::<1, 2>();
+        },
+        |f| {
+            let stream = pin!(f.bus.device_events(&f.hub_state, no_delay));
+            let poll = stream.poll_next(f.c);
+            let result = unwrap_poll(poll).unwrap();
+            assert_eq!(
+                result,
+                Some(DeviceEvent::HubConnect(
+                    UsbDevice {
+                        usb_address: 1,
+                        usb_speed: UsbSpeed::Low1_5,
+                        packet_size_ep0: 8,
+                        in_endpoints_bitmap: 4,
+                        out_endpoints_bitmap: 2,
+                    },
+                    PortPath::root()
+                ))
+            );
+        },
+    );
+}
+
+#[cfg(feature = "hubs")]
+#[test]
+fn device_events_root_connect_new_hub_fails() {
+    do_test(
+        |hc| {
+            hc.expect_multi_interrupt_pipe_ignored();
+            hc.expect_device_detect().returning(|| {
+                let mut mdd = MockDeviceDetect::new();
+                mdd.expect_poll_next().returning(|_| {
+                    Poll::Ready(Some(DeviceStatus::Present(UsbSpeed::Low1_5)))
+                });
+                mdd
+            });
+            hc.expect_reset_root_port().withf(|r| *r).return_const(());
+            hc.expect_reset_root_port().withf(|r| !*r).return_const(());
+            hc.expect_get_device_descriptor_prefix_hub();
+            hc.expect_get_device_descriptor_hub();
+            hc.expect_set_address::<1>();
+
+            // Call to get_basic_configuration
+            hc.expect_control_transfer()
+                .times(1)
+                .withf(is_get_configuration_descriptor::<1>)
+                .returning(control_transfer_timeout);
+        },
+        |f| {
+            let stream = pin!(f.bus.device_events(&f.hub_state, no_delay));
+            let poll = stream.poll_next(f.c);
+            let result = unwrap_poll(poll).unwrap();
+            assert_eq!(
+                result,
+                Some(DeviceEvent::EnumerationError(
+                    PortPath::root(),
+                    UsbError::Timeout
+                ))
+            );
+        },
+    );
+}
+
+#[cfg(feature = "hubs")]
+#[test]
+fn device_events_root_connect_new_hub_pends() {
+    do_test(
+        |hc| {
+            hc.expect_multi_interrupt_pipe_ignored();
+            hc.expect_device_detect().returning(|| {
+                let mut mdd = MockDeviceDetect::new();
+                mdd.expect_poll_next().returning(|_| {
+                    Poll::Ready(Some(DeviceStatus::Present(UsbSpeed::Low1_5)))
+                });
+                mdd
+            });
+            hc.expect_reset_root_port().withf(|r| *r).return_const(());
+            hc.expect_reset_root_port().withf(|r| !*r).return_const(());
+            hc.expect_get_device_descriptor_prefix_hub();
+            hc.expect_get_device_descriptor_hub();
+            hc.expect_set_address::<1>();
+
+            // Call to get_basic_configuration
+            hc.expect_control_transfer()
+                .times(1)
+                .withf(is_get_configuration_descriptor::<1>)
+                .returning(control_transfer_pending);
+        },
+        |f| {
+            let mut stream = pin!(f.bus.device_events(&f.hub_state, no_delay));
+            let poll = stream.as_mut().poll_next(f.c);
+            assert!(poll.is_pending());
+            let poll = stream.as_mut().poll_next(f.c);
+            assert!(poll.is_pending());
+        },
+    );
+}
+
+#[cfg(feature = "hubs")]
+#[test]
+fn device_events_hub_packet() {
+    do_test(
+        |hc| {
+            hc.expect_device_detect().returning(|| {
+                let mut mdd = MockDeviceDetect::new();
+                mdd.expect_poll_next().returning(|_| Poll::Pending);
+                mdd
+            });
+        },
+        |f| {
+            f.hub_state.pipes.borrow_mut()[0] = {
+                let mut ip = MockInterruptPipe::new();
+                ip.expect_poll_next().returning(|_| {
+                    let mut ip = InterruptPacket::new();
+                    ip.size = 1;
+                    Poll::Ready(Some(ip))
+                });
+                Some(ip)
+            };
+            let stream = pin!(f.bus.device_events(&f.hub_state, no_delay));
+
+            let poll = stream.poll_next(f.c);
+            let result = unwrap_poll(poll).unwrap();
+            assert_eq!(result, Some(DeviceEvent::None));
+        },
+    );
+}
+
+#[cfg(feature = "hubs")]
+#[test]
+fn device_events_hub_packet_fails() {
+    do_test(
+        |hc| {
+            hc.expect_device_detect().returning(|| {
+                let mut mdd = MockDeviceDetect::new();
+                mdd.expect_poll_next().returning(|_| Poll::Pending);
+                mdd
+            });
+        },
+        |f| {
+            f.hub_state.pipes.borrow_mut()[0] = {
+                let mut ip = MockInterruptPipe::new();
+                ip.expect_poll_next().returning(|_| {
+                    Poll::Ready(Some(InterruptPacket::new())) // 0-length packet
+                });
+                Some(ip)
+            };
+            let stream = pin!(f.bus.device_events(&f.hub_state, no_delay));
+            let poll = stream.poll_next(f.c);
+            let result = unwrap_poll(poll).unwrap();
+            assert_eq!(
+                result,
+                Some(DeviceEvent::EnumerationError(
+                    PortPath::root(),
+                    UsbError::ProtocolError
+                ))
+            );
+        },
+    );
+}
+
+#[cfg(feature = "hubs")]
+#[test]
+fn device_events_hub_packet_fails_port_path() {
+    do_test(
+        |hc| {
+            hc.expect_device_detect().returning(|| {
+                let mut mdd = MockDeviceDetect::new();
+                mdd.expect_poll_next().returning(|_| Poll::Pending);
+                mdd
+            });
+        },
+        |f| {
+            {
+                // Set up topology so that hub 2 is on port 3 of hub 1
+                let mut b = f.hub_state.topology.borrow_mut();
+                b.device_connect(0, 1, true); // 1
+                b.device_connect(1, 3, true); // 2
+            }
+            f.hub_state.pipes.borrow_mut()[0] = {
+                let mut ip = MockInterruptPipe::new();
+                ip.expect_poll_next().returning(|_| {
+                    let mut ip = InterruptPacket::new(); // 0-length packet
+                    ip.address = 2;
+                    Poll::Ready(Some(ip))
+                });
+                Some(ip)
+            };
+            let stream = pin!(f.bus.device_events(&f.hub_state, no_delay));
+            let poll = stream.poll_next(f.c);
+            let result = unwrap_poll(poll).unwrap();
+            let Some(DeviceEvent::EnumerationError(path, e)) = result else {
+                panic!("EnumerationError expected");
+            };
+            assert_eq!(e, UsbError::ProtocolError);
+            assert_eq!(path.ports(), &[1, 3]);
+        },
+    );
+}
+
+#[cfg(feature = "hubs")]
+#[test]
+fn device_events_hub_packet_pends() {
+    do_test(
+        |hc| {
+            hc.expect_device_detect().returning(|| {
+                let mut mdd = MockDeviceDetect::new();
+                mdd.expect_poll_next().returning(|_| Poll::Pending);
+                mdd
+            });
+
+            hc.expect_control_transfer()
+                .times(1)
+                .withf(is_get_port_status::<1>)
+                .returning(control_transfer_pending);
+        },
+        |f| {
+            f.hub_state.pipes.borrow_mut()[0] = {
+                let mut mip = MockInterruptPipe::new();
+                mip.expect_poll_next().returning(|_| {
+                    let mut ip = InterruptPacket::new();
+                    ip.size = 1;
+                    ip.address = 5;
+                    ip.data[0] = 2;
+                    Poll::Ready(Some(ip))
+                });
+                Some(mip)
+            };
+            let mut stream = pin!(f.bus.device_events(&f.hub_state, no_delay));
+            let poll = stream.as_mut().poll_next(f.c);
+            assert!(poll.is_pending());
+            let poll = stream.as_mut().poll_next(f.c);
+            assert!(poll.is_pending());
+        },
+    );
+}
+
+#[cfg(feature = "hubs")]
+#[test]
+fn poll_hubs_once_nothing_ready() {
+    do_test(
+        |_| {},
+        |f| {
+            let fut = pin!(f.bus.poll_hubs_once(&f.hub_state, no_delay));
+            let result = unwrap_poll(fut.poll(f.c)).unwrap();
+            assert_eq!(result, None);
+        },
+    );
+}
+
+#[cfg(feature = "hubs")]
+#[test]
+fn poll_hubs_once_hub_packet() {
+    do_test(
+        |_| {},
+        |f| {
+            f.hub_state.pipes.borrow_mut()[0] = {
+                let mut ip = MockInterruptPipe::new();
+                ip.expect_poll_next().returning(|_| {
+                    let mut ip = InterruptPacket::new();
+                    ip.size = 1;
+                    Poll::Ready(Some(ip))
+                });
+                Some(ip)
+            };
+            let fut = pin!(f.bus.poll_hubs_once(&f.hub_state, no_delay));
+            let result = unwrap_poll(fut.poll(f.c)).unwrap();
+            assert_eq!(result, None); // no ports changed
+        },
+    );
+}
+
+#[cfg(feature = "hubs")]
+#[test]
+fn poll_hubs_once_hub_packet_fails() {
+    do_test(
+        |_| {},
+        |f| {
+            f.hub_state.pipes.borrow_mut()[0] = {
+                let mut ip = MockInterruptPipe::new();
+                ip.expect_poll_next().returning(|_| {
+                    Poll::Ready(Some(InterruptPacket::new())) // 0-length packet
+                });
+                Some(ip)
+            };
+            let fut = pin!(f.bus.poll_hubs_once(&f.hub_state, no_delay));
+            let result = unwrap_poll(fut.poll(f.c)).unwrap();
+            assert_eq!(
+                result,
+                Some(DeviceEvent::EnumerationError(
+                    PortPath::root(),
+                    UsbError::ProtocolError
+                ))
+            );
+        },
+    );
+}
+
+#[cfg(feature = "hubs")]
+#[test]
+fn poll_hubs_once_hub_packet_pends() {
+    do_test(
+        |hc| {
+            hc.expect_control_transfer()
+                .times(1)
+                .withf(is_get_port_status::<1>)
+                .returning(control_transfer_pending);
+        },
+        |f| {
+            f.hub_state.pipes.borrow_mut()[0] = {
+                let mut mip = MockInterruptPipe::new();
+                mip.expect_poll_next().returning(|_| {
+                    let mut ip = InterruptPacket::new();
+                    ip.size = 1;
+                    ip.address = 5;
+                    ip.data[0] = 2;
+                    Poll::Ready(Some(ip))
+                });
+                Some(mip)
+            };
+            let mut fut = pin!(f.bus.poll_hubs_once(&f.hub_state, no_delay));
+            assert!(fut.as_mut().poll(f.c).is_pending());
+            assert!(fut.as_mut().poll(f.c).is_pending());
+        },
+    );
+}
+
+#[cfg(feature = "hubs")]
+#[test]
+fn device_events_hub_packet_simultaneous_connections() {
+    do_test(
+        |hc| {
+            hc.expect_device_detect().returning(|| {
+                let mut mdd = MockDeviceDetect::new();
+                mdd.expect_poll_next().returning(|_| Poll::Pending);
+                mdd
+            });
+            hc.expect_get_port_status::<1, 1, 1>(); // CONNECTION, C_PORT_CONNECTION
+            hc.expect_clear_port_feature::<1, 16>(); // C_PORT_CONNECTION
+            hc.expect_set_port_feature::<1, 4>(); // PORT_RESET
+            hc.expect_get_port_status::<1, 3, 0>(); // ENABLED
+            hc.expect_get_device_descriptor_prefix();
+            hc.expect_get_device_descriptor();
+            hc.expect_set_address::<127>();
+            hc.expect_get_port_status::<2, 1, 1>(); // CONNECTION, C_PORT_CONNECTION
+            hc.expect_clear_port_feature::<2, 16>(); // C_PORT_CONNECTION
+            hc.expect_set_port_feature::<2, 4>(); // PORT_RESET
+            hc.expect_get_port_status::<2, 3, 0>(); // ENABLED
+            hc.expect_get_device_descriptor_prefix();
+            hc.expect_get_device_descriptor();
+            hc.expect_set_address::<126>();
+        },
+        |f| {
+            f.hub_state.pipes.borrow_mut()[0] = {
+                let mut mip = MockInterruptPipe::new();
+                mip.expect_poll_next().times(1).returning(|_| {
+                    let mut ip = InterruptPacket::new();
+                    ip.size = 1;
+                    ip.address = 5;
+                    ip.data[0] = 0b110;
+                    Poll::Ready(Some(ip))
+                });
+                mip.expect_poll_next().returning(|_| Poll::Pending);
+                Some(mip)
+            };
+            let mut stream = pin!(f.bus.device_events(&f.hub_state, no_delay));
+
+            let poll = stream.as_mut().poll_next(f.c);
+            let result = unwrap_poll(poll).unwrap();
+            let Some(DeviceEvent::Connect(device, _, _)) = result else {
+                panic!("Connect expected");
+            };
+            assert_eq!(device.address(), 127);
+
+            // No further packet from the hub, but port 2 is still pending
+            let poll = stream.as_mut().poll_next(f.c);
+            let result = unwrap_poll(poll).unwrap();
+            let Some(DeviceEvent::Connect(device, _, _)) = result else {
+                panic!("Connect expected");
+            };
+            assert_eq!(device.address(), 126);
+
+            let poll = stream.as_mut().poll_next(f.c);
+            assert!(poll.is_pending());
+        },
+    );
+}
+
+fn is_read_mac_address(
+    a: &u8,
+    p: &u8,
+    s: &SetupPacket,
+    d: &DataPhase,
+) -> bool {
+    *a == 5
+        && *p == 8
+        && s.bmRequestType == DEVICE_TO_HOST | VENDOR_REQUEST
+        && s.bRequest == 0x13
+        && s.wValue == 0
+        && s.wIndex == 0
+        && s.wLength == 6
+        && d.is_in()
+}
+
+#[test]
+fn control_transfer() {
+    do_test(
+        |hc| {
+            hc.expect_control_transfer()
+                .times(1)
+                .withf(is_read_mac_address)
+                .returning(control_transfer_ok_with(|b| {
+                    b[0] = 1;
+                    6
+                }));
+        },
+        |f| {
+            let mut data = [0u8; 6];
+            let fut = pin!(f.bus.control_transfer(
+                &EXAMPLE_DEVICE,
+                SetupPacket {
+                    bmRequestType: DEVICE_TO_HOST | VENDOR_REQUEST,
+                    bRequest: 0x13,
+                    wValue: 0,
+                    wIndex: 0,
+                    wLength: 6,
+                },
+                DataPhase::In(&mut data),
+            ));
+
+            let poll = fut.poll(f.c);
+            assert!(poll.is_ready());
+        },
+    );
+}
+
+#[test]
+fn control_transfer_pends() {
+    do_test(
+        |hc| {
+            hc.expect_control_transfer()
+                .times(1)
+                .withf(is_read_mac_address)
+                .returning(control_transfer_pending);
+        },
+        |f| {
+            let mut data = [0u8; 6];
+            let mut fut = pin!(f.bus.control_transfer(
+                &EXAMPLE_DEVICE,
+                SetupPacket {
+                    bmRequestType: DEVICE_TO_HOST | VENDOR_REQUEST,
+                    bRequest: 0x13,
+                    wValue: 0,
+                    wIndex: 0,
+                    wLength: 6,
+                },
+                DataPhase::In(&mut data),
+            ));
+
+            let poll = fut.as_mut().poll(f.c);
+            assert!(poll.is_pending());
+            let poll = fut.as_mut().poll(f.c);
+            assert!(poll.is_pending());
+        },
+    );
+}
+
+#[cfg(feature = "hubs")]
+#[test]
+fn hub_state_fills_up() {
+    let mut hc = MockHostController::default();
+    hc.inner
+        .expect_try_alloc_interrupt_pipe()
+        .returning(|_, _, _, _| Ok(MockInterruptPipe::default()));
+    let hub_state = HubState::default();
+
+    for i in 0..15 {
+        hub_state.try_add(&hc, i, i, i as u16, i).unwrap();
+    }
+
+    let r = hub_state.try_add(&hc, 0, 0, 0, 0);
+    assert_eq!(r, Err(UsbError::TooManyDevices));
+}
+
+#[cfg(feature = "hubs")]
+#[test]
+fn empty_hub_state_pends() {
+    do_test(
+        |_hc| {},
+        |f| {
+            let stream = pin!(HubStateStream {
+                state: &f.hub_state,
+                bus: &f.bus,
+            });
+            let r = stream.poll_next(f.c);
+            assert!(r.is_pending());
+        },
+    );
+}
+
+#[cfg(feature = "hubs")]
+#[test]
+fn hub_state_passes_on_pend() {
+    do_test(
+        |_hc| {},
+        |f| {
+            f.hub_state.pipes.borrow_mut()[0] = {
+                let mut ip = MockInterruptPipe::new();
+                ip.expect_poll_next().returning(|_| Poll::Pending);
+                Some(ip)
+            };
+            let stream = pin!(HubStateStream {
+                state: &f.hub_state,
+                bus: &f.bus,
+            });
+            let r = stream.poll_next(f.c);
+            assert!(r.is_pending());
+        },
+    );
+}
+
+#[test]
+fn device_has_in_endpoints() {
+    let d = UsbDevice {
+        usb_address: 1,
+        usb_speed: UsbSpeed::Full12,
+        packet_size_ep0: 8,
+        in_endpoints_bitmap: 0,
+        out_endpoints_bitmap: 0x8001,
+    };
+
+    let in_endpoints = d.in_endpoints();
+    assert_eq!(in_endpoints.0, 0);
+
+    let out_endpoints = d.out_endpoints();
+    assert_eq!(out_endpoints.0, 0x8001);
+}
+
+#[test]
+fn open_in_endpoint() {
+    let mut d = UsbDevice {
+        usb_address: 1,
+        usb_speed: UsbSpeed::Full12,
+        packet_size_ep0: 8,
+        in_endpoints_bitmap: 0x100,
+        out_endpoints_bitmap: 0x8001,
+    };
+
+    let _r = d.open_in_endpoint(8).unwrap();
+}
+
+#[test]
+fn open_in_endpoint_zero() {
+    let mut d = UsbDevice {
+        usb_address: 1,
+        usb_speed: UsbSpeed::Full12,
+        packet_size_ep0: 8,
+        in_endpoints_bitmap: 0x1,
+        out_endpoints_bitmap: 0x1,
+    };
+
+    // EP0 is always control, not bulk
+    assert!(d.open_in_endpoint(0).is_err());
+}
+
+#[test]
+fn open_in_endpoint_fails() {
+    let mut d = UsbDevice {
+        usb_address: 1,
+        usb_speed: UsbSpeed::Full12,
+        packet_size_ep0: 8,
+        in_endpoints_bitmap: 0x100,
+        out_endpoints_bitmap: 0x8001,
+    };
+
+    assert!(d.open_in_endpoint(7).is_err());
+}
+
+#[test]
+fn open_in_endpoint_ludicrous() {
+    let mut d = UsbDevice {
+        usb_address: 1,
+        usb_speed: UsbSpeed::Full12,
+        packet_size_ep0: 8,
+        in_endpoints_bitmap: 0x100,
+        out_endpoints_bitmap: 0x8001,
+    };
+
+    assert!(d.open_in_endpoint(70).is_err());
+}
+
+#[test]
+fn open_out_endpoint() {
+    let mut d = UsbDevice {
+        usb_address: 1,
+        usb_speed: UsbSpeed::Full12,
+        packet_size_ep0: 8,
+        in_endpoints_bitmap: 0x100,
+        out_endpoints_bitmap: 0x8001,
+    };
+
+    let _r = d.open_out_endpoint(15).unwrap();
+}
+
+#[test]
+fn open_out_endpoint_zero() {
+    let mut d = UsbDevice {
+        usb_address: 1,
+        usb_speed: UsbSpeed::Full12,
+        packet_size_ep0: 8,
+        in_endpoints_bitmap: 0x1,
+        out_endpoints_bitmap: 0x1,
+    };
+
+    // EP0 is always control, not bulk
+    assert!(d.open_out_endpoint(0).is_err());
+}
+
+#[test]
+fn open_out_endpoint_fails() {
+    let mut d = UsbDevice {
+        usb_address: 1,
+        usb_speed: UsbSpeed::Full12,
+        packet_size_ep0: 8,
+        in_endpoints_bitmap: 0x100,
+        out_endpoints_bitmap: 0x8001,
+    };
+
+    assert!(d.open_out_endpoint(7).is_err());
+}
+
+#[test]
+fn open_out_endpoint_ludicrous() {
+    let mut d = UsbDevice {
+        usb_address: 1,
+        usb_speed: UsbSpeed::Full12,
+        packet_size_ep0: 8,
+        in_endpoints_bitmap: 0x100,
+        out_endpoints_bitmap: 0x8001,
+    };
+
+    assert!(d.open_out_endpoint(70).is_err());
+}
+
+fn is_clear_endpoint_feature<const EP: u8, const FEATURE: u16>(
+    a: &u8,
+    p: &u8,
+    s: &SetupPacket,
+    d: &DataPhase,
+) -> bool {
+    *a == 5
+        && *p == 8
+        && s.bmRequestType == HOST_TO_DEVICE | RECIPIENT_ENDPOINT
+        && s.bRequest == 1
+        && s.wValue == FEATURE
+        && s.wIndex == EP as u16
+        && s.wLength == 0
+        && d.is_none()
+}
+
+#[test]
+fn clear_halt() {
+    do_test(
+        |hc| {
+            hc.expect_clear_endpoint_feature::<0x88, 0>();
+        },
+        |f| {
+            let mut d = UsbDevice {
+                usb_address: 5,
+                usb_speed: UsbSpeed::Full12,
+                packet_size_ep0: 8,
+                in_endpoints_bitmap: 0x100,
+                out_endpoints_bitmap: 0x8001,
+            };
+
+            let ep = d.open_in_endpoint(8).unwrap();
+            let r = pin!(f.bus.clear_halt(&ep));
+            let rr = r.poll(f.c).to_option().unwrap();
+            assert_eq!(rr, Ok(()));
+        },
+    );
+}
+
+#[test]
+fn clear_halt_fails() {
+    do_test(
+        |hc| {
+            hc.expect_control_transfer()
+                .times(1)
+                .withf(is_clear_endpoint_feature::<0x88, 0>)
+                .returning(control_transfer_timeout);
+        },
+        |f| {
+            let mut d = UsbDevice {
+                usb_address: 5,
+                usb_speed: UsbSpeed::Full12,
+                packet_size_ep0: 8,
+                in_endpoints_bitmap: 0x100,
+                out_endpoints_bitmap: 0x8001,
+            };
+
+            let ep = d.open_in_endpoint(8).unwrap();
+            let r = pin!(f.bus.clear_halt(&ep));
+            let rr = r.poll(f.c).to_option().unwrap();
+            assert_eq!(rr, Err(UsbError::Timeout));
+        },
+    );
+}
+
+#[test]
+fn get_device_status() {
+    do_test(
+        |hc| {
+            hc.expect_control_transfer()
+                .times(1)
+                .withf(is_get_device_status::<5>)
+                .returning(control_transfer_ok_with(|bytes| {
+                    bytes[0] = 3;
+                    bytes[1] = 0;
+                    2
+                }));
+        },
+        |f| {
+            let r = pin!(f.bus.get_device_status(&EXAMPLE_DEVICE));
+            let rr = r.poll(f.c).to_option().unwrap();
+            let status = rr.unwrap();
+            assert!(status.self_powered());
+            assert!(status.remote_wakeup());
+        },
+    );
+}
+
+#[test]
+fn get_device_status_fails() {
+    do_test(
+        |hc| {
+            hc.expect_control_transfer()
+                .times(1)
+                .withf(is_get_device_status::<5>)
+                .returning(control_transfer_timeout);
+        },
+        |f| {
+            let r = pin!(f.bus.get_device_status(&EXAMPLE_DEVICE));
+            let rr = r.poll(f.c).to_option().unwrap();
+            assert_eq!(rr, Err(UsbError::Timeout));
+        },
+    );
+}
+
+fn is_remote_wakeup_request<const REQUEST: u8>(
+    a: &u8,
+    p: &u8,
+    s: &SetupPacket,
+    d: &DataPhase,
+) -> bool {
+    *a == 5
+        && *p == 8
+        && s.bmRequestType == HOST_TO_DEVICE
+        && s.bRequest == REQUEST
+        && s.wValue == DEVICE_REMOTE_WAKEUP
+        && s.wIndex == 0
+        && s.wLength == 0
+        && d.is_none()
+}
+
+#[test]
+fn enable_remote_wakeup() {
+    do_test(
+        |hc| {
+            hc.expect_control_transfer()
+                .times(1)
+                .withf(is_remote_wakeup_request::<SET_FEATURE>)
+                .returning(control_transfer_ok::<0>);
+        },
+        |f| {
+            let r = pin!(f.bus.set_remote_wakeup(&EXAMPLE_DEVICE, true));
+            let rr = r.poll(f.c).to_option().unwrap();
+            assert_eq!(rr, Ok(()));
+        },
+    );
+}
+
+#[test]
+fn disable_remote_wakeup() {
+    do_test(
+        |hc| {
+            hc.expect_control_transfer()
+                .times(1)
+                .withf(is_remote_wakeup_request::<CLEAR_FEATURE>)
+                .returning(control_transfer_ok::<0>);
+        },
+        |f| {
+            let r = pin!(f.bus.set_remote_wakeup(&EXAMPLE_DEVICE, false));
+            let rr = r.poll(f.c).to_option().unwrap();
+            assert_eq!(rr, Ok(()));
+        },
+    );
+}
+
+#[test]
+fn remote_wakeup_unsupported() {
+    do_test(
+        |hc| {
+            hc.expect_control_transfer()
+                .times(1)
+                .withf(is_remote_wakeup_request::<SET_FEATURE>)
+                .returning(|_, _, _, _| {
+                    Box::pin(future::ready(Err(UsbError::Stall)))
+                });
+        },
+        |f| {
+            let r = pin!(f.bus.set_remote_wakeup(&EXAMPLE_DEVICE, true));
+            let rr = r.poll(f.c).to_option().unwrap();
+            assert_eq!(rr, Err(UsbError::Stall));
+        },
+    );
+}
+
+#[test]
+fn clear_halt_pends() {
+    do_test(
+        |hc| {
+            hc.expect_control_transfer()
+                .times(1)
+                .withf(is_clear_endpoint_feature::<0x88, 0>)
+                .returning(control_transfer_pending);
+        },
+        |f| {
+            let mut d = UsbDevice {
+                usb_address: 5,
+                usb_speed: UsbSpeed::Full12,
+                packet_size_ep0: 8,
+                in_endpoints_bitmap: 0x100,
+                out_endpoints_bitmap: 0x8001,
+            };
+
+            let ep = d.open_in_endpoint(8).unwrap();
+            let mut fut = pin!(f.bus.clear_halt(&ep));
+
+            let poll = fut.as_mut().poll(f.c);
+            assert!(poll.is_pending());
+            let poll = fut.as_mut().poll(f.c);
+            assert!(poll.is_pending());
+        },
+    );
+}
+
+#[test]
+fn bulk_in_transfer() {
+    do_test(
+        |hc| {
+            hc.expect_bulk_in_transfer()
+                .withf(|a, e, _, d, t, p| {
+                    *a == 5
+                        && *e == 8
+                        && d.len() == 16
+                        && *t == TransferType::VariableSize
+                        && p.get() == false
+                })
+                .returning(bulk_in_ok::<16>);
+        },
+        |f| {
+            let mut d = UsbDevice {
+                usb_address: 5,
+                usb_speed: UsbSpeed::Full12,
+                packet_size_ep0: 8,
+                in_endpoints_bitmap: 0x100,
+                out_endpoints_bitmap: 0x8001,
+            };
+
+            let ep = d.open_in_endpoint(8).unwrap();
+            let mut data = [0u8; 16];
+            let fut = pin!(f.bus.bulk_in_transfer(
+                &ep,
+                &mut data,
+                TransferType::VariableSize
+            ));
+            let rr = fut.poll(f.c).to_option().unwrap();
+            assert_eq!(rr, Ok(16));
+        },
+    );
+}
+
+#[test]
+fn bulk_out_transfer() {
+    do_test(
+        |hc| {
+            hc.expect_bulk_out_transfer()
+                .withf(|a, e, _, d, t, p| {
+                    *a == 5
+                        && *e == 8
+                        && d.len() == 16
+                        && *t == TransferType::FixedSize
+                        && p.get() == false
+                })
+                .returning(bulk_out_ok::<16>);
+        },
+        |f| {
+            let mut d = UsbDevice {
+                usb_address: 5,
+                usb_speed: UsbSpeed::Full12,
+                packet_size_ep0: 8,
+                in_endpoints_bitmap: 0x100,
+                out_endpoints_bitmap: 0x8102,
+            };
+
+            let ep = d.open_out_endpoint(8).unwrap();
+            let data = [0u8; 16];
+            let fut = pin!(f.bus.bulk_out_transfer(
+                &ep,
+                &data,
+                TransferType::FixedSize
+            ));
+            let rr = fut.poll(f.c).to_option().unwrap();
+            assert_eq!(rr, Ok(16));
+        },
+    );
+}
+
+#[cfg(feature = "hubs")]
+#[test]
+fn error_rate_policy() {
+    let policy = ErrorRatePolicy::default();
+    let mut s = TransferStatistics {
+        transfers: 99,
+        crc_errors: 50,
+        ..Default::default()
+    };
+    assert!(!policy.is_exceeded_by(&s)); // too few transfers to tell
+    s.transfers = 1000;
+    assert!(policy.is_exceeded_by(&s));
+    s.crc_errors = 5;
+    s.bit_stuff_errors = 5;
+    assert!(!policy.is_exceeded_by(&s)); // exactly 1% is allowed
+    s.bit_stuff_errors = 6;
+    assert!(policy.is_exceeded_by(&s));
+    s.bit_stuff_errors = 0;
+    s.timeouts = 100; // not signal-quality errors
+    s.data_seq_errors = 100;
+    assert!(!policy.is_exceeded_by(&s));
+}
+
+#[test]
+fn control_transfer_records_statistics() {
+    do_test(
+        |hc| {
+            hc.expect_control_transfer()
+                .times(1)
+                .withf(is_read_mac_address)
+                .returning(|_, _, _, _| {
+                    Box::pin(future::ready(Err(UsbError::CrcError)))
+                });
+        },
+        |f| {
+            let mut data = [0u8; 6];
+            let fut = pin!(f.bus.control_transfer(
+                &EXAMPLE_DEVICE,
+                SetupPacket {
                     bmRequestType: DEVICE_TO_HOST | VENDOR_REQUEST,
                     bRequest: 0x13,
                     wValue: 0,
                     wIndex: 0,
-                    wLength: 6,
+                    wLength: 6,
+                },
+                DataPhase::In(&mut data),
+            ));
+
+            let rr = fut.poll(f.c).to_option().unwrap();
+            assert_eq!(rr, Err(UsbError::CrcError));
+            let s = f.bus.statistics(5);
+            assert_eq!(s.transfers, 1);
+            assert_eq!(s.crc_errors, 1);
+            assert_eq!(f.bus.statistics(6), TransferStatistics::default());
+        },
+    );
+}
+
+#[test]
+fn bulk_in_transfer_records_statistics() {
+    do_test(
+        |hc| {
+            hc.expect_bulk_in_transfer().returning(bulk_in_ok::<16>);
+        },
+        |f| {
+            let mut d = EXAMPLE_DEVICE;
+            d.in_endpoints_bitmap = 0x100;
+            let ep = d.open_in_endpoint(8).unwrap();
+            let mut data = [0u8; 16];
+            let fut = pin!(f.bus.bulk_in_transfer(
+                &ep,
+                &mut data,
+                TransferType::VariableSize
+            ));
+            let rr = fut.poll(f.c).to_option().unwrap();
+            assert_eq!(rr, Ok(16));
+            let s = f.bus.statistics(5);
+            assert_eq!(s.transfers, 1);
+            assert_eq!(s.signal_errors(), 0);
+        },
+    );
+}
+
+#[test]
+fn set_address_resets_statistics() {
+    let w = Waker::from(Arc::new(NoOpWaker));
+    let mut c = core::task::Context::from_waker(&w);
+
+    let mut hc = MockHostController::default();
+    hc.inner
+        .expect_control_transfer()
+        .times(1)
+        .withf(is_set_address::<5>)
+        .returning(control_transfer_ok::<0>);
+    hc.statistics.record(5, &Err(UsbError::BitStuffError));
+
+    let bus = UsbBus::new(hc);
+    assert_eq!(bus.statistics(5).bit_stuff_errors, 1);
+
+    let r = pin!(bus.set_address(unaddressed_device(), 5));
+    let rr = r.poll(&mut c);
+    assert!(rr == Poll::Ready(Ok(unconfigured_device())));
+    assert_eq!(bus.statistics(5), TransferStatistics::default());
+}
+
+#[cfg(feature = "hubs")]
+fn record_crc_errors(hc: &MockHostController, address: u8) {
+    for _ in 0..90 {
+        hc.statistics.record(address, &Ok(64));
+    }
+    for _ in 0..10 {
+        hc.statistics.record(address, &Err(UsbError::CrcError));
+    }
+}
+
+#[cfg(feature = "hubs")]
+#[test]
+fn hub_state_warns_about_error_rate() {
+    do_test(
+        |_hc| {},
+        |f| {
+            let address = f
+                .hub_state
+                .topology
+                .borrow_mut()
+                .device_connect(0, 1, false);
+            let address = address.unwrap();
+            record_crc_errors(&f.bus.driver, address);
+
+            let mut stream = pin!(HubStateStream {
+                state: &f.hub_state,
+                bus: &f.bus,
+            });
+            let r = stream.as_mut().poll_next(f.c);
+            let Poll::Ready(Some(InternalEvent::ErrorRate(a, s))) = r else {
+                panic!("expected error-rate warning");
+            };
+            assert_eq!(a, address);
+            assert_eq!(s.crc_errors, 10);
+
+            // Only warned once
+            let r = stream.poll_next(f.c);
+            assert!(r.is_pending());
+        },
+    );
+}
+
+#[cfg(feature = "hubs")]
+#[test]
+fn hub_state_ignores_absent_devices() {
+    do_test(
+        |_hc| {},
+        |f| {
+            record_crc_errors(&f.bus.driver, 5);
+
+            let stream = pin!(HubStateStream {
+                state: &f.hub_state,
+                bus: &f.bus,
+            });
+            let r = stream.poll_next(f.c);
+            assert!(r.is_pending());
+        },
+    );
+}
+
+#[cfg(feature = "hubs")]
+#[test]
+fn hub_state_warns_again_after_reconnect() {
+    do_test(
+        |_hc| {},
+        |f| {
+            let address = f
+                .hub_state
+                .topology
+                .borrow_mut()
+                .device_connect(0, 1, false);
+            let address = address.unwrap();
+            record_crc_errors(&f.bus.driver, address);
+
+            let mut stream = pin!(HubStateStream {
+                state: &f.hub_state,
+                bus: &f.bus,
+            });
+            let r = stream.as_mut().poll_next(f.c);
+            assert!(r.is_ready());
+
+            f.hub_state.topology.borrow_mut().device_disconnect(0, 1);
+            let r = stream.as_mut().poll_next(f.c);
+            assert!(r.is_pending());
+
+            f.hub_state
+                .topology
+                .borrow_mut()
+                .device_connect(0, 1, false);
+            let r = stream.poll_next(f.c);
+            assert!(r.is_ready());
+        },
+    );
+}
+
+#[cfg(feature = "hubs")]
+#[test]
+fn hub_state_uses_error_rate_policy() {
+    do_test(
+        |_hc| {},
+        |mut f| {
+            f.hub_state.set_error_rate_policy(ErrorRatePolicy {
+                min_transfers: 100,
+                max_errors_per_1000: 200,
+            });
+            let address = f
+                .hub_state
+                .topology
+                .borrow_mut()
+                .device_connect(0, 1, false);
+            record_crc_errors(&f.bus.driver, address.unwrap());
+
+            let stream = pin!(HubStateStream {
+                state: &f.hub_state,
+                bus: &f.bus,
+            });
+            let r = stream.poll_next(f.c);
+            assert!(r.is_pending());
+        },
+    );
+}
+
+#[cfg(feature = "hubs")]
+struct CountingWaker(std::sync::atomic::AtomicUsize);
+
+#[cfg(feature = "hubs")]
+impl Wake for CountingWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+    }
+}
+
+#[cfg(feature = "hubs")]
+#[test]
+fn failed_transfer_wakes_hub_state() {
+    let counter = Arc::new(CountingWaker(0.into()));
+    let w = Waker::from(counter.clone());
+    let mut c = core::task::Context::from_waker(&w);
+
+    let mut hc = MockHostController::default();
+    hc.inner
+        .expect_bulk_out_transfer()
+        .times(1)
+        .returning(bulk_out_ok::<16>);
+    hc.inner.expect_bulk_out_transfer().times(1).returning(
+        |_, _, _, _, _, _| Box::pin(future::ready(Err(UsbError::CrcError))),
+    );
+    let hub_state = HubState::default();
+    let bus = UsbBus::new(hc);
+
+    let stream = pin!(HubStateStream {
+        state: &hub_state,
+        bus: &bus,
+    });
+    let r = stream.poll_next(&mut c);
+    assert!(r.is_pending());
+
+    let mut d = EXAMPLE_DEVICE;
+    d.out_endpoints_bitmap = 0x100;
+    let ep = d.open_out_endpoint(8).unwrap();
+    let data = [0u8; 16];
+
+    let rr = pin!(bus.bulk_out_transfer(&ep, &data, TransferType::FixedSize))
+        .poll(&mut c);
+    assert_eq!(rr, Poll::Ready(Ok(16)));
+    assert_eq!(counter.0.load(std::sync::atomic::Ordering::SeqCst), 0);
+
+    let rr = pin!(bus.bulk_out_transfer(&ep, &data, TransferType::FixedSize))
+        .poll(&mut c);
+    assert_eq!(rr, Poll::Ready(Err(UsbError::CrcError)));
+    assert_eq!(counter.0.load(std::sync::atomic::Ordering::SeqCst), 1);
+}
+
+fn is_get_bos_descriptor(
+    a: &u8,
+    p: &u8,
+    s: &SetupPacket,
+    d: &DataPhase,
+) -> bool {
+    *a == 5
+        && *p == 8
+        && s.bmRequestType == DEVICE_TO_HOST
+        && s.bRequest == GET_DESCRIPTOR
+        && s.wValue == 0xF00
+        && s.wIndex == 0
+        && s.wLength >= 5
+        && d.is_in()
+}
+
+fn bos_descriptor(b: &mut [u8]) -> usize {
+    // USB 2.0 Extension (LPM), SuperSpeed USB
+    let bos = [
+        5, 15, 22, 0, 2, 7, 16, 2, 2, 0, 0, 0, 10, 16, 3, 0, 14, 0, 1, 10,
+        255, 7,
+    ];
+    b[0..bos.len()].copy_from_slice(&bos);
+    bos.len()
+}
+
+#[test]
+fn get_bos_descriptor() {
+    do_test(
+        |hc| {
+            hc.expect_control_transfer()
+                .times(1)
+                .withf(is_get_bos_descriptor)
+                .returning(control_transfer_ok_with(bos_descriptor));
+        },
+        |f| {
+            let mut caps = BosCapabilities::default();
+            {
+                let r = pin!(f
+                    .bus
+                    .get_bos_descriptor(&UNCONFIGURED_DEVICE, &mut caps));
+                let rr = r.poll(f.c);
+                assert_eq!(rr, Poll::Ready(Ok(true)));
+            }
+            assert_eq!(
+                caps,
+                BosCapabilities {
+                    lpm: true,
+                    besl: false,
+                    superspeed: true,
+                }
+            );
+        },
+    );
+}
+
+#[test]
+fn get_bos_descriptor_stalls() {
+    do_test(
+        |hc| {
+            hc.expect_control_transfer()
+                .times(1)
+                .withf(is_get_bos_descriptor)
+                .returning(|_, _, _, _| {
+                    Box::pin(future::ready(Err(UsbError::Stall)))
+                });
+        },
+        |f| {
+            let mut caps = BosCapabilities::default();
+            {
+                let r = pin!(f
+                    .bus
+                    .get_bos_descriptor(&UNCONFIGURED_DEVICE, &mut caps));
+                let rr = r.poll(f.c);
+                assert_eq!(rr, Poll::Ready(Ok(false)));
+            }
+            assert_eq!(caps, BosCapabilities::default());
+        },
+    );
+}
+
+#[test]
+fn get_bos_descriptor_fails() {
+    do_test(
+        |hc| {
+            hc.expect_control_transfer()
+                .times(1)
+                .withf(is_get_bos_descriptor)
+                .returning(control_transfer_timeout);
+        },
+        |f| {
+            let mut caps = BosCapabilities::default();
+            let r = pin!(f
+                .bus
+                .get_bos_descriptor(&UNCONFIGURED_DEVICE, &mut caps));
+            let rr = r.poll(f.c);
+            assert_eq!(rr, Poll::Ready(Err(UsbError::Timeout)));
+        },
+    );
+}
+
+#[test]
+fn get_bos_descriptor_pends() {
+    do_test(
+        |hc| {
+            hc.expect_control_transfer()
+                .times(1)
+                .withf(is_get_bos_descriptor)
+                .returning(control_transfer_pending);
+        },
+        |f| {
+            let mut caps = BosCapabilities::default();
+            let r = pin!(f
+                .bus
+                .get_bos_descriptor(&UNCONFIGURED_DEVICE, &mut caps));
+            let rr = r.poll(f.c);
+            assert!(rr.is_pending());
+        },
+    );
+}
+
+#[test]
+fn get_bos_descriptor_bad_descriptor() {
+    do_test(
+        |hc| {
+            hc.expect_control_transfer()
+                .times(1)
+                .withf(is_get_bos_descriptor)
+                .returning(control_transfer_ok::<4>);
+        },
+        |f| {
+            let mut caps = BosCapabilities::default();
+            let r = pin!(f
+                .bus
+                .get_bos_descriptor(&UNCONFIGURED_DEVICE, &mut caps));
+            let rr = r.poll(f.c);
+            assert_eq!(rr, Poll::Ready(Err(UsbError::ProtocolError)));
+        },
+    );
+}
+
+fn is_get_device_status<const ADDR: u8>(
+    a: &u8,
+    _: &u8,
+    s: &SetupPacket,
+    d: &DataPhase,
+) -> bool {
+    *a == ADDR
+        && s.bmRequestType == DEVICE_TO_HOST
+        && s.bRequest == GET_STATUS
+        && s.wValue == 0
+        && s.wIndex == 0
+        && s.wLength == 2
+        && d.is_in()
+}
+
+#[cfg(feature = "hubs")]
+#[test]
+fn liveness_policy_default() {
+    let policy = LivenessPolicy::default();
+    assert!(!policy.is_enabled());
+    let policy = LivenessPolicy {
+        interval_ms: 1000,
+        ..Default::default()
+    };
+    assert!(policy.is_enabled());
+}
+
+#[cfg(feature = "hubs")]
+#[test]
+fn check_liveness_nothing_present() {
+    do_test(
+        |_hc| {},
+        |f| {
+            let rr = pin!(f.bus.check_liveness(&f.hub_state)).poll(f.c);
+            assert_eq!(rr, Poll::Ready(DeviceEvent::None));
+        },
+    );
+}
+
+#[cfg(feature = "hubs")]
+#[test]
+fn check_liveness_ok() {
+    do_test(
+        |hc| {
+            hc.expect_control_transfer()
+                .times(1)
+                .withf(is_get_device_status::<127>)
+                .returning(control_transfer_ok::<2>);
+        },
+        |f| {
+            f.hub_state
+                .topology
+                .borrow_mut()
+                .device_connect(0, 1, false);
+            let rr = pin!(f.bus.check_liveness(&f.hub_state)).poll(f.c);
+            assert_eq!(rr, Poll::Ready(DeviceEvent::None));
+        },
+    );
+}
+
+#[cfg(feature = "hubs")]
+#[test]
+fn check_liveness_skips_active_devices() {
+    do_test(
+        |hc| {
+            hc.expect_control_transfer()
+                .times(1)
+                .withf(is_set_configuration::<127, 1>)
+                .returning(control_transfer_ok::<0>);
+        },
+        |f| {
+            let address = f
+                .hub_state
+                .topology
+                .borrow_mut()
+                .device_connect(0, 1, false);
+            assert_eq!(address, Some(127));
+            let mut d = EXAMPLE_DEVICE;
+            d.usb_address = 127;
+            let rr = pin!(f.bus.control_transfer(
+                &d,
+                SetupPacket {
+                    bmRequestType: HOST_TO_DEVICE,
+                    bRequest: SET_CONFIGURATION,
+                    wValue: 1,
+                    wIndex: 0,
+                    wLength: 0,
                 },
-                DataPhase::In(&mut data),
+                DataPhase::None,
+            ))
+            .poll(f.c);
+            assert_eq!(rr, Poll::Ready(Ok(0)));
+
+            // No GET_STATUS is sent
+            let rr = pin!(f.bus.check_liveness(&f.hub_state)).poll(f.c);
+            assert_eq!(rr, Poll::Ready(DeviceEvent::None));
+        },
+    );
+}
+
+#[cfg(feature = "hubs")]
+#[test]
+fn check_liveness_pends() {
+    do_test(
+        |hc| {
+            hc.expect_control_transfer()
+                .times(1)
+                .withf(is_get_device_status::<127>)
+                .returning(control_transfer_pending);
+        },
+        |f| {
+            f.hub_state
+                .topology
+                .borrow_mut()
+                .device_connect(0, 1, false);
+            let rr = pin!(f.bus.check_liveness(&f.hub_state)).poll(f.c);
+            assert!(rr.is_pending());
+        },
+    );
+}
+
+#[cfg(feature = "hubs")]
+#[test]
+fn check_liveness_disconnects_unresponsive_device() {
+    do_test(
+        |hc| {
+            hc.expect_control_transfer()
+                .times(3)
+                .withf(is_get_device_status::<127>)
+                .returning(control_transfer_timeout);
+        },
+        |f| {
+            f.hub_state
+                .topology
+                .borrow_mut()
+                .device_connect(0, 1, false);
+            for _ in 0..2 {
+                let rr = pin!(f.bus.check_liveness(&f.hub_state)).poll(f.c);
+                assert_eq!(rr, Poll::Ready(DeviceEvent::None));
+            }
+            let rr = pin!(f.bus.check_liveness(&f.hub_state)).poll(f.c);
+            assert_eq!(
+                rr,
+                Poll::Ready(DeviceEvent::Unresponsive(127, BitSet(1 << 127)))
+            );
+            assert!(!f.hub_state.topology.borrow().is_present(127));
+
+            // Now absent, so no further GET_STATUS
+            let rr = pin!(f.bus.check_liveness(&f.hub_state)).poll(f.c);
+            assert_eq!(rr, Poll::Ready(DeviceEvent::None));
+        },
+    );
+}
+
+#[cfg(feature = "hubs")]
+#[test]
+fn check_liveness_disconnects_downstream_devices() {
+    do_test(
+        |hc| {
+            hc.expect_control_transfer()
+                .times(1)
+                .withf(is_get_device_status::<1>)
+                .returning(control_transfer_timeout);
+        },
+        |mut f| {
+            f.hub_state.set_liveness_policy(LivenessPolicy {
+                interval_ms: 1000,
+                max_failures: 1,
+            });
+            let hub = f
+                .hub_state
+                .topology
+                .borrow_mut()
+                .device_connect(0, 1, true)
+                .unwrap();
+            let device = f
+                .hub_state
+                .topology
+                .borrow_mut()
+                .device_connect(hub, 2, false)
+                .unwrap();
+            let rr = pin!(f.bus.check_liveness(&f.hub_state)).poll(f.c);
+            assert_eq!(
+                rr,
+                Poll::Ready(DeviceEvent::Unresponsive(
+                    hub,
+                    BitSet((1 << hub) | (1 << device))
+                ))
+            );
+        },
+    );
+}
+
+#[cfg(feature = "hubs")]
+#[test]
+fn check_liveness_success_resets_failures() {
+    do_test(
+        |hc| {
+            let mut seq = mockall::Sequence::new();
+            for ok in [false, false, true, false, false] {
+                let e = hc
+                    .expect_control_transfer()
+                    .times(1)
+                    .in_sequence(&mut seq)
+                    .withf(is_get_device_status::<127>);
+                if ok {
+                    e.returning(control_transfer_ok::<2>);
+                } else {
+                    e.returning(control_transfer_timeout);
+                }
+            }
+        },
+        |f| {
+            f.hub_state
+                .topology
+                .borrow_mut()
+                .device_connect(0, 1, false);
+            for _ in 0..5 {
+                let rr = pin!(f.bus.check_liveness(&f.hub_state)).poll(f.c);
+                assert_eq!(rr, Poll::Ready(DeviceEvent::None));
+            }
+        },
+    );
+}
+
+#[cfg(feature = "hubs")]
+#[test]
+fn device_events_keep_alive() {
+    do_test(
+        |hc| {
+            hc.expect_multi_interrupt_pipe_ignored();
+            hc.expect_device_detect().returning(|| {
+                let mut mdd = MockDeviceDetect::new();
+                mdd.expect_poll_next().returning(|_| Poll::Pending);
+                mdd
+            });
+            hc.expect_control_transfer()
+                .times(1)
+                .withf(is_get_device_status::<127>)
+                .returning(control_transfer_timeout);
+        },
+        |mut f| {
+            f.hub_state.set_liveness_policy(LivenessPolicy {
+                interval_ms: 1000,
+                max_failures: 1,
+            });
+            f.hub_state
+                .topology
+                .borrow_mut()
+                .device_connect(0, 1, false);
+            let stream = pin!(f.bus.device_events(&f.hub_state, no_delay));
+            let poll = stream.poll_next(f.c);
+            assert_eq!(
+                poll,
+                Poll::Ready(Some(DeviceEvent::Unresponsive(
+                    127,
+                    BitSet(1 << 127)
+                )))
+            );
+        },
+    );
+}
+
+#[cfg(feature = "hubs")]
+#[test]
+fn device_events_no_keep_alive_by_default() {
+    do_test(
+        |hc| {
+            hc.expect_multi_interrupt_pipe_ignored();
+            hc.expect_device_detect().returning(|| {
+                let mut mdd = MockDeviceDetect::new();
+                mdd.expect_poll_next().returning(|_| Poll::Pending);
+                mdd
+            });
+        },
+        |f| {
+            f.hub_state
+                .topology
+                .borrow_mut()
+                .device_connect(0, 1, false);
+            let mut stream = pin!(f.bus.device_events(&f.hub_state, no_delay));
+            assert!(stream.as_mut().poll_next(f.c).is_pending());
+            assert!(stream.as_mut().poll_next(f.c).is_pending());
+        },
+    );
+}
+
+const FILTERED_DEVICE: DeviceInfo = DeviceInfo {
+    vid: 0x1234,
+    pid: 0x5678,
+    class: 0,
+    subclass: 0,
+    usb_version: 0,
+    device_release: 0,
+    serial_number_index: 0,
+};
+
+fn refuse_address(info: &DeviceInfo) -> Admission {
+    if *info == FILTERED_DEVICE {
+        Admission::RefuseAddress
+    } else {
+        Admission::Accept
+    }
+}
+
+fn refuse_configure(info: &DeviceInfo) -> Admission {
+    if *info == FILTERED_DEVICE {
+        Admission::RefuseConfigure
+    } else {
+        Admission::Accept
+    }
+}
+
+fn root_device_present(hc: &mut MockHostControllerInner) {
+    hc.expect_multi_interrupt_pipe_ignored();
+    hc.expect_device_detect().returning(|| {
+        let mut mdd = MockDeviceDetect::new();
+        mdd.expect_poll_next().returning(|_| {
+            Poll::Ready(Some(DeviceStatus::Present(UsbSpeed::Full12)))
+        });
+        mdd
+    });
+    hc.expect_reset_root_port().withf(|r| *r).return_const(());
+    hc.expect_reset_root_port().withf(|r| !*r).return_const(());
+    hc.expect_get_device_descriptor_prefix();
+    hc.expect_get_device_descriptor();
+}
+
+#[cfg(feature = "hubs")]
+const PERSISTED_DEVICE: UsbDevice = UsbDevice {
+    usb_address: 127,
+    usb_speed: UsbSpeed::Full12,
+    packet_size_ep0: 8,
+    in_endpoints_bitmap: 0,
+    out_endpoints_bitmap: 0,
+};
+
+#[cfg(feature = "hubs")]
+const PERSISTED_HUB: UsbDevice = UsbDevice {
+    usb_address: 1,
+    usb_speed: UsbSpeed::Full12,
+    packet_size_ep0: 8,
+    in_endpoints_bitmap: 4,
+    out_endpoints_bitmap: 2,
+};
+
+#[cfg(feature = "hubs")]
+fn hub_port(port: u8) -> PortPath {
+    let mut topology = Topology::new();
+    topology.device_connect(0, 1, true);
+    topology.port_path(1, port)
+}
+
+#[cfg(feature = "hubs")]
+#[test]
+fn persisted_bus_new_is_valid_and_empty() {
+    let p = PersistedBus::new();
+    assert!(p.is_valid());
+    assert_eq!(p.devices().count(), 0);
+}
+
+#[cfg(feature = "hubs")]
+#[test]
+fn persisted_bus_garbage_is_invalid() {
+    // As if read from RAM which was never written
+    let mut p = PersistedBus::new();
+    p.magic = 0xDEAD_BEEF;
+    assert!(!p.is_valid());
+    assert_eq!(p.devices().count(), 0);
+
+    let mut p = PersistedBus::new();
+    p.record(&PERSISTED_DEVICE, &PortPath::root(), 1).unwrap();
+    p.devices[0].configuration_value = 3;
+    assert!(!p.is_valid());
+    assert_eq!(p.devices().count(), 0);
+}
+
+#[cfg(feature = "hubs")]
+#[test]
+fn persisted_bus_record() {
+    let mut p = PersistedBus::new();
+    p.record(&PERSISTED_DEVICE, &PortPath::root(), 2).unwrap();
+    assert!(p.is_valid());
+    let d: Vec<_> = p.devices().collect();
+    assert_eq!(d.len(), 1);
+    assert_eq!(d[0].address(), 127);
+    assert_eq!(d[0].parent(), (0, 1));
+    assert!(!d[0].is_hub());
+    assert_eq!(d[0].configuration_value, 2);
+}
+
+#[cfg(feature = "hubs")]
+#[test]
+fn persisted_bus_record_replaces_same_address() {
+    let mut p = PersistedBus::new();
+    p.record(&PERSISTED_DEVICE, &hub_port(1), 1).unwrap();
+    p.record(&PERSISTED_DEVICE, &hub_port(2), 1).unwrap();
+    let d: Vec<_> = p.devices().collect();
+    assert_eq!(d.len(), 1);
+    assert_eq!(d[0].parent(), (1, 2));
+}
+
+#[cfg(feature = "hubs")]
+#[test]
+fn persisted_bus_record_replaces_same_port() {
+    let mut p = PersistedBus::new();
+    p.record(&PERSISTED_DEVICE, &hub_port(1), 1).unwrap();
+    let other = UsbDevice {
+        usb_address: 126,
+        ..PERSISTED_DEVICE
+    };
+    p.record(&other, &hub_port(1), 1).unwrap();
+    let d: Vec<_> = p.devices().collect();
+    assert_eq!(d.len(), 1);
+    assert_eq!(d[0].address(), 126);
+}
+
+#[cfg(feature = "hubs")]
+#[test]
+fn persisted_bus_record_into_invalid_clears_it() {
+    let mut p = PersistedBus::new();
+    p.record(&PERSISTED_HUB, &PortPath::root(), 1).unwrap();
+    p.checksum ^= 1;
+    p.record(&PERSISTED_DEVICE, &hub_port(1), 1).unwrap();
+    assert!(p.is_valid());
+    let d: Vec<_> = p.devices().collect();
+    assert_eq!(d.len(), 1);
+    assert_eq!(d[0].address(), 127);
+}
+
+#[cfg(feature = "hubs")]
+#[test]
+fn persisted_bus_full() {
+    let mut p = PersistedBus::new();
+    for i in 0..MAX_PERSISTED_DEVICES {
+        let device = UsbDevice {
+            usb_address: 100 + i as u8,
+            ..PERSISTED_DEVICE
+        };
+        p.record(&device, &hub_port(i as u8 + 1), 1).unwrap();
+    }
+    let device = UsbDevice {
+        usb_address: 99,
+        ..PERSISTED_DEVICE
+    };
+    assert_eq!(
+        p.record(&device, &hub_port(0), 1),
+        Err(UsbError::TooManyDevices)
+    );
+    assert_eq!(p.devices().count(), MAX_PERSISTED_DEVICES);
+}
+
+#[cfg(feature = "hubs")]
+#[test]
+fn persisted_bus_forget() {
+    let mut p = PersistedBus::new();
+    p.record(&PERSISTED_HUB, &PortPath::root(), 1).unwrap();
+    p.record(&PERSISTED_DEVICE, &hub_port(1), 1).unwrap();
+    p.forget(BitSet(1 << 1));
+    let d: Vec<_> = p.devices().collect();
+    assert_eq!(d.len(), 1);
+    assert_eq!(d[0].address(), 127);
+    assert!(p.is_valid());
+}
+
+#[cfg(feature = "hubs")]
+#[test]
+fn persisted_bus_clear() {
+    let mut p = PersistedBus::new();
+    p.record(&PERSISTED_DEVICE, &PortPath::root(), 1).unwrap();
+    p.clear();
+    assert!(p.is_valid());
+    assert_eq!(p.devices().count(), 0);
+}
+
+#[cfg(feature = "hubs")]
+#[test]
+fn persisted_bus_tracks_hubs() {
+    let mut p = PersistedBus::new();
+    p.track(&DeviceEvent::HubConnect(PERSISTED_HUB, PortPath::root()));
+    let d: Vec<_> = p.devices().collect();
+    assert_eq!(d.len(), 1);
+    assert_eq!(d[0].address(), 1);
+    assert!(d[0].is_hub());
+
+    p.track(&DeviceEvent::Disconnect(BitSet(1 << 1)));
+    assert_eq!(p.devices().count(), 0);
+}
+
+#[cfg(feature = "hubs")]
+#[test]
+fn persisted_bus_tracks_reuse() {
+    let mut p = PersistedBus::new();
+    p.record(&PERSISTED_DEVICE, &hub_port(1), 1).unwrap();
+    p.track(&DeviceEvent::Connect(
+        unconfigured_device(),
+        FILTERED_DEVICE,
+        hub_port(1),
+    ));
+    assert_eq!(p.devices().count(), 0);
+
+    p.record(&PERSISTED_DEVICE, &hub_port(1), 1).unwrap();
+    p.track(&DeviceEvent::EnumerationError(hub_port(1), UsbError::Stall));
+    assert_eq!(p.devices().count(), 0);
+
+    p.record(&PERSISTED_DEVICE, &hub_port(1), 1).unwrap();
+    p.track(&DeviceEvent::Offered(127, FILTERED_DEVICE));
+    assert_eq!(p.devices().count(), 0);
+
+    p.record(&PERSISTED_DEVICE, &hub_port(1), 1).unwrap();
+    p.track(&DeviceEvent::Unresponsive(127, BitSet(1 << 127)));
+    assert_eq!(p.devices().count(), 0);
+}
+
+#[cfg(feature = "hubs")]
+fn is_get_device_descriptor_at<const ADDR: u8>(
+    a: &u8,
+    p: &u8,
+    s: &SetupPacket,
+    d: &DataPhase,
+) -> bool {
+    *a == ADDR
+        && *p == 8
+        && s.bmRequestType == DEVICE_TO_HOST
+        && s.bRequest == GET_DESCRIPTOR
+        && s.wValue == 0x100
+        && s.wLength == 18
+        && d.is_in()
+}
+
+#[cfg(feature = "hubs")]
+fn expect_resumed_device<const ADDR: u8>(hc: &mut MockHostControllerInner) {
+    hc.expect_control_transfer()
+        .times(1)
+        .withf(is_get_device_status::<ADDR>)
+        .returning(control_transfer_ok::<2>);
+    hc.expect_control_transfer()
+        .times(1)
+        .withf(is_get_device_descriptor_at::<ADDR>)
+        .returning(control_transfer_ok_with(device_descriptor));
+    hc.expect_get_configuration::<ADDR>();
+    hc.expect_set_configuration::<ADDR, 1>();
+}
+
+#[cfg(feature = "hubs")]
+#[test]
+fn device_events_resume_root_device() {
+    do_test(
+        |hc| {
+            hc.expect_multi_interrupt_pipe_ignored();
+            expect_root_connect_once(hc);
+            hc.expect_reset_root_port().times(0);
+            expect_resumed_device::<127>(hc);
+        },
+        |mut f| {
+            let mut p = PersistedBus::new();
+            p.record(&PERSISTED_DEVICE, &PortPath::root(), 1).unwrap();
+            f.hub_state.resume_from(&p);
+            let mut stream = pin!(f.bus.device_events(&f.hub_state, no_delay));
+            let poll = stream.as_mut().poll_next(f.c);
+            assert_eq!(poll, Poll::Ready(Some(DeviceEvent::None)));
+            let poll = stream.as_mut().poll_next(f.c);
+            let result = unwrap_poll(poll).unwrap();
+            assert_eq!(
+                result,
+                Some(DeviceEvent::Resumed(
+                    UsbDevice {
+                        in_endpoints_bitmap: 1 << 2,
+                        out_endpoints_bitmap: 1 << 1,
+                        ..PERSISTED_DEVICE
+                    },
+                    FILTERED_DEVICE,
+                    PortPath::root()
+                ))
+            );
+            assert_eq!(
+                f.hub_state.topology.borrow().parent(127),
+                Some((0, 1))
+            );
+            assert!(!f.hub_state.is_resuming());
+        },
+    );
+}
+
+#[cfg(feature = "hubs")]
+#[test]
+fn device_events_resume_failure_enumerates() {
+    do_test(
+        |hc| {
+            hc.expect_multi_interrupt_pipe_ignored();
+            expect_root_connect_once(hc);
+            hc.expect_control_transfer()
+                .times(1)
+                .withf(is_get_device_status::<127>)
+                .returning(control_transfer_timeout);
+            hc.expect_reset_root_port().withf(|r| *r).return_const(());
+            hc.expect_reset_root_port().withf(|r| !*r).return_const(());
+            hc.expect_get_device_descriptor_prefix();
+            hc.expect_get_device_descriptor();
+            hc.expect_set_address::<127>();
+        },
+        |mut f| {
+            let mut p = PersistedBus::new();
+            p.record(&PERSISTED_DEVICE, &PortPath::root(), 1).unwrap();
+            f.hub_state.resume_from(&p);
+            let mut stream = pin!(f.bus.device_events(&f.hub_state, no_delay));
+            let poll = stream.as_mut().poll_next(f.c);
+            assert_eq!(poll, Poll::Ready(Some(DeviceEvent::None)));
+            let poll = stream.as_mut().poll_next(f.c);
+            let result = unwrap_poll(poll).unwrap();
+            assert!(matches!(
+                result,
+                Some(DeviceEvent::Connect(_, FILTERED_DEVICE, _))
+            ));
+            assert!(!f.hub_state.is_resuming());
+        },
+    );
+}
+
+#[cfg(feature = "hubs")]
+#[test]
+fn device_events_resume_invalid_enumerates() {
+    do_test(
+        |hc| {
+            root_device_present(hc);
+            hc.expect_set_address::<127>();
+        },
+        |mut f| {
+            let mut p = PersistedBus::new();
+            p.record(&PERSISTED_DEVICE, &PortPath::root(), 1).unwrap();
+            p.checksum ^= 1;
+            f.hub_state.resume_from(&p);
+            let stream = pin!(f.bus.device_events(&f.hub_state, no_delay));
+            let poll = stream.poll_next(f.c);
+            let result = unwrap_poll(poll).unwrap();
+            assert!(matches!(result, Some(DeviceEvent::Connect(..))));
+        },
+    );
+}
+
+#[cfg(feature = "hubs")]
+#[test]
+fn device_events_resume_hub_and_device() {
+    do_test(
+        |hc| {
+            hc.expect_try_alloc_interrupt_pipe()
+                .returning(|_, _, _, _| {
+                    let mut ip = MockInterruptPipe::new();
+                    ip.expect_poll_next().returning(|_| Poll::Pending);
+                    Ok(ip)
+                });
+            expect_root_connect_once(hc);
+            hc.expect_reset_root_port().times(0);
+            hc.expect_control_transfer()
+                .times(1)
+                .withf(is_get_device_status::<1>)
+                .returning(control_transfer_ok::<2>);
+            hc.expect_get_configuration::<1>();
+            hc.expect_get_hub_descriptor::<1>();
+            expect_resumed_device::<127>(hc);
+        },
+        |mut f| {
+            let mut p = PersistedBus::new();
+            p.track(&DeviceEvent::HubConnect(PERSISTED_HUB, PortPath::root()));
+            p.record(&PERSISTED_DEVICE, &hub_port(1), 1).unwrap();
+            f.hub_state.resume_from(&p);
+            let mut stream = pin!(f.bus.device_events(&f.hub_state, no_delay));
+            let poll = stream.as_mut().poll_next(f.c);
+            assert_eq!(poll, Poll::Ready(Some(DeviceEvent::None)));
+            let poll = stream.as_mut().poll_next(f.c);
+            let result = unwrap_poll(poll).unwrap();
+            assert_eq!(
+                result,
+                Some(DeviceEvent::HubConnect(PERSISTED_HUB, PortPath::root()))
+            );
+            let poll = stream.as_mut().poll_next(f.c);
+            let result = unwrap_poll(poll).unwrap();
+            assert!(matches!(
+                result,
+                Some(DeviceEvent::Resumed(
+                    UsbDevice {
+                        usb_address: 127,
+                        ..
+                    },
+                    _,
+                    path
+                )) if path == hub_port(1)
+            ));
+            // Port 2 had no recorded device, so is enumerated afresh
+            assert_eq!(f.hub_state.retries.get()[1], 1 << 2);
+            assert!(!f.hub_state.is_resuming());
+        },
+    );
+}
+
+#[cfg(feature = "hubs")]
+#[test]
+fn device_events_resume_orphan_forgotten() {
+    do_test(
+        |hc| {
+            hc.expect_multi_interrupt_pipe_ignored();
+            expect_root_connect_once(hc);
+            expect_resumed_device::<127>(hc);
+        },
+        |mut f| {
+            let mut p = PersistedBus::new();
+            p.record(&PERSISTED_DEVICE, &PortPath::root(), 1).unwrap();
+            // Behind a hub which isn't recorded
+            let other = UsbDevice {
+                usb_address: 126,
+                ..PERSISTED_DEVICE
+            };
+            let mut topology = Topology::new();
+            topology.device_connect(0, 1, true);
+            topology.device_connect(1, 1, true);
+            p.record(&other, &topology.port_path(2, 1), 1).unwrap();
+            f.hub_state.resume_from(&p);
+            let mut stream = pin!(f.bus.device_events(&f.hub_state, no_delay));
+            let poll = stream.as_mut().poll_next(f.c);
+            assert_eq!(poll, Poll::Ready(Some(DeviceEvent::None)));
+            let poll = stream.as_mut().poll_next(f.c);
+            let result = unwrap_poll(poll).unwrap();
+            assert!(matches!(result, Some(DeviceEvent::Resumed(..))));
+            assert_eq!(f.hub_state.next_resumable(), None);
+            assert!(!f.hub_state.is_resuming());
+            assert!(!f.hub_state.topology.borrow().is_present(126));
+        },
+    );
+}
+
+#[test]
+fn device_events_nh_refuse_address() {
+    do_test(
+        |hc| {
+            root_device_present(hc);
+            hc.expect_control_transfer()
+                .withf(is_set_address::<1>)
+                .times(0);
+        },
+        |mut f| {
+            f.bus.set_device_filter(refuse_address);
+            let stream = pin!(f.bus.device_events_no_hubs(no_delay));
+            let poll = stream.poll_next(f.c);
+            let result = unwrap_poll(poll).unwrap();
+            assert_eq!(
+                result,
+                Some(DeviceEvent::Rejected(PortPath::root(), FILTERED_DEVICE))
+            );
+        },
+    );
+}
+
+#[test]
+fn device_events_nh_refuse_configure() {
+    do_test(
+        |hc| {
+            root_device_present(hc);
+            hc.expect_set_address::<1>();
+        },
+        |mut f| {
+            f.bus.set_device_filter(refuse_configure);
+            let stream = pin!(f.bus.device_events_no_hubs(no_delay));
+            let poll = stream.poll_next(f.c);
+            let result = unwrap_poll(poll).unwrap();
+            assert_eq!(
+                result,
+                Some(DeviceEvent::Rejected(PortPath::root(), FILTERED_DEVICE))
+            );
+        },
+    );
+}
+
+#[cfg(feature = "hubs")]
+#[test]
+fn device_events_filter_accepts() {
+    do_test(
+        |hc| {
+            root_device_present(hc);
+            hc.expect_set_address::<127>();
+        },
+        |mut f| {
+            f.bus.set_device_filter(|_| Admission::Accept);
+            let stream = pin!(f.bus.device_events(&f.hub_state, no_delay));
+            let poll = stream.poll_next(f.c);
+            let result = unwrap_poll(poll).unwrap();
+            assert!(matches!(result, Some(DeviceEvent::Connect(_, _, _))));
+        },
+    );
+}
+
+#[cfg(feature = "hubs")]
+#[test]
+fn device_events_refuse_address() {
+    do_test(
+        |hc| {
+            root_device_present(hc);
+            hc.expect_control_transfer()
+                .withf(is_set_address::<127>)
+                .times(0);
+        },
+        |mut f| {
+            f.bus.set_device_filter(refuse_address);
+            let stream = pin!(f.bus.device_events(&f.hub_state, no_delay));
+            let poll = stream.poll_next(f.c);
+            let result = unwrap_poll(poll).unwrap();
+            assert_eq!(
+                result,
+                Some(DeviceEvent::Rejected(PortPath::root(), FILTERED_DEVICE))
+            );
+            assert!(!f.hub_state.topology.borrow().is_present(127));
+        },
+    );
+}
+
+#[cfg(feature = "hubs")]
+#[test]
+fn device_events_refuse_configure() {
+    do_test(
+        |hc| {
+            root_device_present(hc);
+            hc.expect_set_address::<127>();
+        },
+        |mut f| {
+            f.bus.set_device_filter(refuse_configure);
+            let stream = pin!(f.bus.device_events(&f.hub_state, no_delay));
+            let poll = stream.poll_next(f.c);
+            let result = unwrap_poll(poll).unwrap();
+            assert_eq!(
+                result,
+                Some(DeviceEvent::Rejected(PortPath::root(), FILTERED_DEVICE))
+            );
+            assert!(f.hub_state.topology.borrow().is_present(127));
+        },
+    );
+}
+
+#[cfg(feature = "hubs")]
+fn hub_port_connected(hc: &mut MockHostControllerInner) {
+    hc.expect_multi_interrupt_pipe_ignored();
+    hc.expect_get_port_status::<1, 1, 1>(); // CONNECTION, C_PORT_CONNECTION
+    hc.expect_clear_port_feature::<1, 16>(); // C_PORT_CONNECTION
+    hc.expect_set_port_feature::<1, 4>(); // PORT_RESET
+    hc.expect_get_port_status::<1, 3, 0>(); // ENABLED
+    hc.expect_get_device_descriptor_prefix();
+    hc.expect_get_device_descriptor();
+}
+
+#[cfg(feature = "hubs")]
+fn port_1_packet() -> InterruptPacket {
+    let mut p = InterruptPacket::new();
+    p.address = 5;
+    p.size = 1;
+    p.data[0] = 0b10; // bit 1 set => port 1 needs attention
+    p
+}
+
+#[cfg(feature = "hubs")]
+#[test]
+fn handle_hub_packet_refuse_address() {
+    do_test(
+        |hc| {
+            hub_port_connected(hc);
+            hc.expect_clear_port_feature::<1, 1>(); // PORT_ENABLE
+        },
+        |mut f| {
+            f.bus.set_device_filter(refuse_address);
+            let p = port_1_packet();
+            let fut = pin!(f.bus.handle_hub_packet(
+                &f.hub_state,
+                p.view(),
+                no_delay
+            ));
+            let poll = fut.poll(f.c);
+            let result = unwrap_poll(poll).unwrap();
+            assert_eq!(
+                result,
+                Ok(DeviceEvent::Rejected(
+                    f.hub_state.topology().port_path(5, 1),
+                    FILTERED_DEVICE
+                ))
+            );
+        },
+    );
+}
+
+#[cfg(feature = "hubs")]
+#[test]
+fn handle_hub_packet_refuse_address_disable_fails() {
+    do_test(
+        |hc| {
+            hub_port_connected(hc);
+            hc.expect_control_transfer()
+                .times(1)
+                .withf(is_clear_port_feature::<1, 1>)
+                .returning(control_transfer_timeout);
+        },
+        |mut f| {
+            f.bus.set_device_filter(refuse_address);
+            let p = port_1_packet();
+            let fut = pin!(f.bus.handle_hub_packet(
+                &f.hub_state,
+                p.view(),
+                no_delay
             ));
-
-            let poll = fut.as_mut().poll(f.c);
-            assert!(poll.is_pending());
-            let poll = fut.as_mut().poll(f.c);
-            assert!(poll.is_pending());
+            let poll = fut.poll(f.c);
+            let result = unwrap_poll(poll).unwrap();
+            assert_eq!(
+                result,
+                Ok(DeviceEvent::EnumerationError(
+                    f.hub_state.topology().port_path(5, 1),
+                    UsbError::Timeout
+                ))
+            );
         },
     );
 }
 
+#[cfg(feature = "hubs")]
 #[test]
-fn hub_state_fills_up() {
-    let mut hc = MockHostController::default();
-    hc.inner
-        .expect_try_alloc_interrupt_pipe()
-        .returning(|_, _, _, _| Ok(MockInterruptPipe::default()));
-    let hub_state = HubState::default();
+fn handle_hub_packet_refuse_configure() {
+    do_test(
+        |hc| {
+            hub_port_connected(hc);
+            hc.expect_set_address::<127>();
+        },
+        |mut f| {
+            f.bus.set_device_filter(refuse_configure);
+            let p = port_1_packet();
+            let fut = pin!(f.bus.handle_hub_packet(
+                &f.hub_state,
+                p.view(),
+                no_delay
+            ));
+            let poll = fut.poll(f.c);
+            let result = unwrap_poll(poll).unwrap();
+            assert_eq!(
+                result,
+                Ok(DeviceEvent::Rejected(
+                    f.hub_state.topology().port_path(5, 1),
+                    FILTERED_DEVICE
+                ))
+            );
+        },
+    );
+}
 
-    for i in 0..15 {
-        hub_state.try_add(&hc, i, i, i, i).unwrap();
+fn offer(info: &DeviceInfo) -> Admission {
+    if *info == FILTERED_DEVICE {
+        Admission::Offer
+    } else {
+        Admission::Accept
     }
+}
 
-    let r = hub_state.try_add(&hc, 0, 0, 0, 0);
-    assert_eq!(r, Err(UsbError::TooManyDevices));
+fn offered_device(address: u8) -> UnconfiguredDevice {
+    UnconfiguredDevice {
+        usb_address: address,
+        usb_speed: UsbSpeed::Full12,
+        packet_size_ep0: 8,
+    }
 }
 
 #[test]
-fn empty_hub_state_pends() {
+fn device_events_nh_offer() {
     do_test(
-        |_hc| {},
+        |hc| {
+            root_device_present(hc);
+            hc.expect_set_address::<1>();
+        },
+        |mut f| {
+            f.bus.set_device_filter(offer);
+            let stream = pin!(f.bus.device_events_no_hubs(no_delay));
+            let poll = stream.poll_next(f.c);
+            let result = unwrap_poll(poll).unwrap();
+            assert_eq!(result, Some(DeviceEvent::Offered(1, FILTERED_DEVICE)));
+            assert!(f
+                .bus
+                .unclaimed_devices()
+                .eq([(1, FILTERED_DEVICE)].into_iter()));
+        },
+    );
+}
+
+#[cfg(feature = "hubs")]
+#[test]
+fn device_events_offer() {
+    do_test(
+        |hc| {
+            root_device_present(hc);
+            hc.expect_set_address::<127>();
+        },
+        |mut f| {
+            f.bus.set_device_filter(offer);
+            let stream = pin!(f.bus.device_events(&f.hub_state, no_delay));
+            let poll = stream.poll_next(f.c);
+            let result = unwrap_poll(poll).unwrap();
+            assert_eq!(
+                result,
+                Some(DeviceEvent::Offered(127, FILTERED_DEVICE))
+            );
+            assert!(f.hub_state.topology.borrow().is_present(127));
+        },
+    );
+}
+
+#[cfg(feature = "hubs")]
+#[test]
+fn handle_hub_packet_offer() {
+    do_test(
+        |hc| {
+            hub_port_connected(hc);
+            hc.expect_set_address::<127>();
+        },
+        |mut f| {
+            f.bus.set_device_filter(offer);
+            let p = port_1_packet();
+            let fut = pin!(f.bus.handle_hub_packet(
+                &f.hub_state,
+                p.view(),
+                no_delay
+            ));
+            let poll = fut.poll(f.c);
+            let result = unwrap_poll(poll).unwrap();
+            assert_eq!(result, Ok(DeviceEvent::Offered(127, FILTERED_DEVICE)));
+            assert!(f.bus.claim_device(127).is_some());
+        },
+    );
+}
+
+#[test]
+fn claim_is_exclusive() {
+    do_test(
+        |hc| {
+            hc.expect_multi_interrupt_pipe_ignored();
+        },
         |f| {
-            let stream = pin!(HubStateStream {
-                state: &f.hub_state
-            });
-            let r = stream.poll_next(f.c);
-            assert!(r.is_pending());
+            f.bus
+                .offer(offered_device(5), FILTERED_DEVICE, PortPath::root());
+            let claimed = f.bus.claim_device(5).unwrap();
+            assert_eq!(claimed.address(), 5);
+            assert_eq!(*claimed.info(), FILTERED_DEVICE);
+            assert_eq!(claimed.device().address(), 5);
+            assert!(f.bus.claim_device(5).is_none());
+            assert_eq!(f.bus.unclaimed_devices().count(), 0);
+
+            f.bus.release_device(claimed);
+            assert_eq!(f.bus.unclaimed_devices().count(), 1);
+            assert!(f.bus.claim_device(5).is_some());
         },
     );
 }
 
 #[test]
-fn hub_state_passes_on_pend() {
+fn claim_unknown_device() {
     do_test(
-        |_hc| {},
+        |hc| {
+            hc.expect_multi_interrupt_pipe_ignored();
+        },
         |f| {
-            f.hub_state.pipes.borrow_mut()[0] = {
-                let mut ip = MockInterruptPipe::new();
-                ip.expect_poll_next().returning(|_| Poll::Pending);
-                Some(ip)
-            };
-            let stream = pin!(HubStateStream {
-                state: &f.hub_state
-            });
-            let r = stream.poll_next(f.c);
-            assert!(r.is_pending());
+            f.bus
+                .offer(offered_device(5), FILTERED_DEVICE, PortPath::root());
+            assert!(f.bus.claim_device(6).is_none());
         },
     );
 }
 
 #[test]
-fn device_has_in_endpoints() {
-    let d = UsbDevice {
-        usb_address: 1,
-        usb_speed: UsbSpeed::Full12,
-        packet_size_ep0: 8,
-        in_endpoints_bitmap: 0,
-        out_endpoints_bitmap: 0x8001,
-    };
+fn configure_claimed_device() {
+    do_test(
+        |hc| {
+            hc.expect_multi_interrupt_pipe_ignored();
+            hc.expect_set_configuration::<5, 1>();
+            hc.expect_get_double_configuration::<5>();
+        },
+        |f| {
+            f.bus
+                .offer(offered_device(5), FILTERED_DEVICE, PortPath::root());
+            let claimed = f.bus.claim_device(5).unwrap();
+            let r = pin!(f.bus.configure(claimed.into_device(), 1));
+            let rr = r.poll(f.c).to_option().unwrap();
+            assert_eq!(rr, Ok(EXAMPLE_DEVICE));
+            // No longer on offer at all
+            assert!(f.bus.offered.borrow().iter().all(Option::is_none));
+        },
+    );
+}
 
-    let in_endpoints = d.in_endpoints();
-    assert_eq!(in_endpoints.0, 0);
+#[test]
+fn offer_withdrawn_on_disconnect() {
+    do_test(
+        |hc| {
+            hc.expect_multi_interrupt_pipe_ignored();
+        },
+        |f| {
+            f.bus
+                .offer(offered_device(5), FILTERED_DEVICE, PortPath::root());
+            f.bus
+                .offer(offered_device(6), FILTERED_DEVICE, PortPath::root());
+            let claimed = f.bus.claim_device(6).unwrap();
+            f.bus.forget_offers(&DeviceEvent::Disconnect(BitSet(
+                (1 << 5) | (1 << 6),
+            )));
+            assert_eq!(f.bus.unclaimed_devices().count(), 0);
+            f.bus.release_device(claimed);
+            assert_eq!(f.bus.unclaimed_devices().count(), 0);
+        },
+    );
+}
 
-    let out_endpoints = d.out_endpoints();
-    assert_eq!(out_endpoints.0, 0x8001);
+#[test]
+fn offers_overflow_to_connect() {
+    do_test(
+        |hc| {
+            hc.expect_multi_interrupt_pipe_ignored();
+        },
+        |f| {
+            for address in 1..=(MAX_OFFERED as u8) {
+                assert_eq!(
+                    f.bus.offer(
+                        offered_device(address),
+                        FILTERED_DEVICE,
+                        PortPath::root()
+                    ),
+                    DeviceEvent::Offered(address, FILTERED_DEVICE)
+                );
+            }
+            assert_eq!(
+                f.bus.offer(
+                    offered_device(100),
+                    FILTERED_DEVICE,
+                    PortPath::root()
+                ),
+                DeviceEvent::Connect(
+                    offered_device(100),
+                    FILTERED_DEVICE,
+                    PortPath::root()
+                )
+            );
+            // But re-offering at a known address replaces the old offer
+            assert_eq!(
+                f.bus.offer(
+                    offered_device(1),
+                    FILTERED_DEVICE,
+                    PortPath::root()
+                ),
+                DeviceEvent::Offered(1, FILTERED_DEVICE)
+            );
+            assert_eq!(f.bus.unclaimed_devices().count(), MAX_OFFERED);
+        },
+    );
+}
+
+#[cfg(feature = "hubs")]
+fn is_example_device(info: &DeviceInfo) -> bool {
+    info.vid == 0x1234 && info.pid == 0x5678
+}
+
+#[cfg(feature = "hubs")]
+const EXAMPLE_BINDING: InterruptBinding = InterruptBinding {
+    matcher: is_example_device,
+    endpoint: 2,
+    max_packet_size: 8,
+    interval_ms: 10,
+};
+
+#[cfg(feature = "hubs")]
+fn example_packet(address: u8) -> InterruptPacket {
+    let mut packet = InterruptPacket::new();
+    packet.address = address;
+    packet.endpoint = 2;
+    packet.size = 2;
+    packet.data[0] = 0xAB;
+    packet.data[1] = 0xCD;
+    packet
+}
+
+#[cfg(feature = "hubs")]
+fn expect_bound_pipe<const ADDR: u8>(hc: &mut MockHostControllerInner) {
+    hc.expect_try_alloc_interrupt_pipe()
+        .times(1)
+        .withf(|a, e, m, i| *a == ADDR && *e == 2 && *m == 8 && *i == 10)
+        .returning(|_, _, _, _| {
+            let mut ip = MockInterruptPipe::new();
+            ip.expect_poll_next()
+                .times(1)
+                .returning(|_| Poll::Ready(Some(example_packet(ADDR))));
+            ip.expect_poll_next().returning(|_| Poll::Pending);
+            Ok(ip)
+        });
+}
+
+#[cfg(feature = "hubs")]
+/// As `expect_bound_pipe`, but for tests which never poll the pipe
+fn expect_idle_bound_pipe<const ADDR: u8>(hc: &mut MockHostControllerInner) {
+    hc.expect_try_alloc_interrupt_pipe()
+        .times(1)
+        .withf(|a, _, _, _| *a == ADDR)
+        .returning(|_, _, _, _| Ok(MockInterruptPipe::new()));
+}
+
+#[cfg(feature = "hubs")]
+fn connect_event(address: u8) -> DeviceEvent {
+    DeviceEvent::Connect(
+        UnconfiguredDevice {
+            usb_address: address,
+            usb_speed: UsbSpeed::Full12,
+            packet_size_ep0: 8,
+        },
+        FILTERED_DEVICE,
+        PortPath::root(),
+    )
+}
+
+#[cfg(feature = "hubs")]
+fn bound_addresses(hub_state: &HubState<MockHostController>) -> Vec<u8> {
+    hub_state
+        .bound_pipes
+        .borrow()
+        .iter()
+        .flatten()
+        .map(|p| p.usb_address)
+        .collect()
 }
 
+#[cfg(feature = "hubs")]
 #[test]
-fn open_in_endpoint() {
-    let mut d = UsbDevice {
-        usb_address: 1,
-        usb_speed: UsbSpeed::Full12,
-        packet_size_ep0: 8,
-        in_endpoints_bitmap: 0x100,
-        out_endpoints_bitmap: 0x8001,
-    };
+fn bind_interrupt_limit() {
+    let mut hub_state = HubState::<MockHostController>::default();
+    for i in 0..MAX_INTERRUPT_BINDINGS {
+        assert_eq!(
+            hub_state.bind_interrupt(EXAMPLE_BINDING),
+            Ok(BindingId(i as u8))
+        );
+    }
+    assert_eq!(
+        hub_state.bind_interrupt(EXAMPLE_BINDING),
+        Err(UsbError::AllPipesInUse)
+    );
+}
 
-    let _r = d.open_in_endpoint(8).unwrap();
+#[cfg(feature = "hubs")]
+#[test]
+fn device_events_bound_interrupt() {
+    do_test(
+        |hc| {
+            expect_root_connect_once(hc);
+            hc.expect_reset_root_port().withf(|r| *r).return_const(());
+            hc.expect_reset_root_port().withf(|r| !*r).return_const(());
+            hc.expect_get_device_descriptor_prefix();
+            hc.expect_get_device_descriptor();
+            hc.expect_set_address::<127>();
+            hc.expect_set_configuration::<127, 1>();
+            hc.expect_get_configuration::<127>();
+            expect_bound_pipe::<127>(hc);
+        },
+        |mut f| {
+            let binding = f.hub_state.bind_interrupt(EXAMPLE_BINDING).unwrap();
+            let mut stream = pin!(f.bus.device_events(&f.hub_state, no_delay));
+
+            let poll = stream.as_mut().poll_next(f.c);
+            let Some(Some(DeviceEvent::Connect(device, _, _))) =
+                unwrap_poll(poll)
+            else {
+                panic!("Connect expected");
+            };
+
+            // Not opened until configured
+            assert!(stream.as_mut().poll_next(f.c).is_pending());
+
+            let r = pin!(f.bus.configure(device, 1)).poll(f.c);
+            assert!(matches!(r, Poll::Ready(Ok(_))));
+
+            let poll = stream.as_mut().poll_next(f.c);
+            assert_eq!(
+                poll,
+                Poll::Ready(Some(DeviceEvent::Interrupt(
+                    binding,
+                    example_packet(127)
+                )))
+            );
+            assert!(stream.as_mut().poll_next(f.c).is_pending());
+        },
+    );
 }
 
+#[cfg(feature = "hubs")]
 #[test]
-fn open_in_endpoint_zero() {
-    let mut d = UsbDevice {
-        usb_address: 1,
-        usb_speed: UsbSpeed::Full12,
-        packet_size_ep0: 8,
-        in_endpoints_bitmap: 0x1,
-        out_endpoints_bitmap: 0x1,
-    };
+fn unmatched_device_not_bound() {
+    do_test(
+        |hc| {
+            hc.expect_try_alloc_interrupt_pipe().times(0);
+        },
+        |mut f| {
+            f.hub_state
+                .bind_interrupt(InterruptBinding {
+                    matcher: |info| info.class == 3,
+                    ..EXAMPLE_BINDING
+                })
+                .unwrap();
+            f.hub_state.track_bindings(&f.bus, &connect_event(4));
+            f.bus.configured.set(BitSet(1 << 4));
+            assert_eq!(f.hub_state.open_bound_pipes(&f.bus), None);
+            assert!(bound_addresses(&f.hub_state).is_empty());
+        },
+    );
+}
 
-    // EP0 is always control, not bulk
-    assert!(d.open_in_endpoint(0).is_err());
+#[cfg(feature = "hubs")]
+#[test]
+fn bound_pipe_follows_reconnection() {
+    do_test(
+        |hc| {
+            expect_idle_bound_pipe::<4>(hc);
+            expect_idle_bound_pipe::<6>(hc);
+        },
+        |mut f| {
+            f.hub_state.bind_interrupt(EXAMPLE_BINDING).unwrap();
+
+            f.hub_state.track_bindings(&f.bus, &connect_event(4));
+            f.bus.configured.set(BitSet(1 << 4));
+            assert_eq!(f.hub_state.open_bound_pipes(&f.bus), None);
+            assert_eq!(bound_addresses(&f.hub_state), vec![4]);
+
+            // Disconnecting closes the pipe
+            f.hub_state.track_bindings(
+                &f.bus,
+                &DeviceEvent::Disconnect(BitSet(1 << 4)),
+            );
+            assert!(bound_addresses(&f.hub_state).is_empty());
+            assert!(!f.bus.configured.get().contains(4));
+
+            // Re-enumerated at a different address: re-opened once
+            // configured
+            f.hub_state.track_bindings(&f.bus, &connect_event(6));
+            assert_eq!(f.hub_state.open_bound_pipes(&f.bus), None);
+            assert!(bound_addresses(&f.hub_state).is_empty());
+            f.bus.configured.set(BitSet(1 << 6));
+            assert_eq!(f.hub_state.open_bound_pipes(&f.bus), None);
+            assert_eq!(bound_addresses(&f.hub_state), vec![6]);
+        },
+    );
 }
 
+#[cfg(feature = "hubs")]
 #[test]
-fn open_in_endpoint_fails() {
-    let mut d = UsbDevice {
-        usb_address: 1,
-        usb_speed: UsbSpeed::Full12,
-        packet_size_ep0: 8,
-        in_endpoints_bitmap: 0x100,
-        out_endpoints_bitmap: 0x8001,
-    };
+fn bound_pipe_closed_when_unresponsive() {
+    do_test(
+        |hc| {
+            expect_idle_bound_pipe::<4>(hc);
+        },
+        |mut f| {
+            f.hub_state.bind_interrupt(EXAMPLE_BINDING).unwrap();
+            f.hub_state.track_bindings(&f.bus, &connect_event(4));
+            f.bus.configured.set(BitSet(1 << 4));
+            assert_eq!(f.hub_state.open_bound_pipes(&f.bus), None);
+
+            f.hub_state.track_bindings(
+                &f.bus,
+                &DeviceEvent::Unresponsive(4, BitSet(1 << 4)),
+            );
+            assert!(bound_addresses(&f.hub_state).is_empty());
+        },
+    );
+}
+
+#[cfg(feature = "hubs")]
+#[test]
+fn device_events_bound_pipe_fails() {
+    do_test(
+        |hc| {
+            hc.expect_device_detect().returning(|| {
+                let mut mdd = MockDeviceDetect::new();
+                mdd.expect_poll_next().returning(|_| Poll::Pending);
+                mdd
+            });
+            hc.expect_try_alloc_interrupt_pipe()
+                .times(1)
+                .returning(|_, _, _, _| Err(UsbError::AllPipesInUse));
+        },
+        |mut f| {
+            let binding = f.hub_state.bind_interrupt(EXAMPLE_BINDING).unwrap();
+            f.hub_state.track_bindings(&f.bus, &connect_event(4));
+            f.bus.configured.set(BitSet(1 << 4));
 
-    assert!(d.open_in_endpoint(7).is_err());
+            let mut stream = pin!(f.bus.device_events(&f.hub_state, no_delay));
+            assert_eq!(
+                stream.as_mut().poll_next(f.c),
+                Poll::Ready(Some(DeviceEvent::InterruptError(
+                    binding,
+                    4,
+                    UsbError::AllPipesInUse
+                )))
+            );
+            assert!(stream.as_mut().poll_next(f.c).is_pending());
+        },
+    );
 }
 
+#[cfg(feature = "hubs")]
 #[test]
-fn open_in_endpoint_ludicrous() {
-    let mut d = UsbDevice {
-        usb_address: 1,
-        usb_speed: UsbSpeed::Full12,
-        packet_size_ep0: 8,
-        in_endpoints_bitmap: 0x100,
-        out_endpoints_bitmap: 0x8001,
-    };
-
-    assert!(d.open_in_endpoint(70).is_err());
+fn bound_pipes_beyond_sized_limit() {
+    let mut hc = MockHostController::default();
+    expect_idle_bound_pipe::<4>(&mut hc.inner);
+    let bus = UsbBus::new(hc);
+    let mut hub_state =
+        HubState::<MockHostController, 15, 1>::sized_with_retry_policy(
+            RetryPolicy::default(),
+        );
+    let binding = hub_state.bind_interrupt(EXAMPLE_BINDING).unwrap();
+    hub_state.track_bindings(&bus, &connect_event(4));
+    hub_state.track_bindings(&bus, &connect_event(6));
+    bus.configured.set(BitSet((1 << 4) | (1 << 6)));
+
+    assert_eq!(
+        hub_state.open_bound_pipes(&bus),
+        Some((binding, 6, UsbError::AllPipesInUse))
+    );
+    assert_eq!(
+        hub_state
+            .bound_pipes
+            .borrow()
+            .iter()
+            .flatten()
+            .map(|p| p.usb_address)
+            .collect::<Vec<_>>(),
+        vec![4]
+    );
 }
 
-#[test]
-fn open_out_endpoint() {
-    let mut d = UsbDevice {
-        usb_address: 1,
-        usb_speed: UsbSpeed::Full12,
-        packet_size_ep0: 8,
-        in_endpoints_bitmap: 0x100,
-        out_endpoints_bitmap: 0x8001,
-    };
+/// A host controller whose futures and streams are all `Send`
+#[cfg(feature = "send-futures")]
+struct SendController;
 
-    let _r = d.open_out_endpoint(15).unwrap();
-}
+#[cfg(feature = "send-futures")]
+impl HostController for SendController {
+    type InterruptPipe = futures::stream::Pending<InterruptPacket>;
+    type DeviceDetect = futures::stream::Pending<DeviceStatus>;
 
-#[test]
-fn open_out_endpoint_zero() {
-    let mut d = UsbDevice {
-        usb_address: 1,
-        usb_speed: UsbSpeed::Full12,
-        packet_size_ep0: 8,
-        in_endpoints_bitmap: 0x1,
-        out_endpoints_bitmap: 0x1,
-    };
+    fn device_detect(&self) -> Self::DeviceDetect {
+        futures::stream::pending()
+    }
 
-    // EP0 is always control, not bulk
-    assert!(d.open_out_endpoint(0).is_err());
-}
+    fn reset_root_port(&self, _rst: bool) {}
 
-#[test]
-fn open_out_endpoint_fails() {
-    let mut d = UsbDevice {
-        usb_address: 1,
-        usb_speed: UsbSpeed::Full12,
-        packet_size_ep0: 8,
-        in_endpoints_bitmap: 0x100,
-        out_endpoints_bitmap: 0x8001,
-    };
+    fn control_transfer(
+        &self,
+        _address: u8,
+        _packet_size: u8,
+        _setup: SetupPacket,
+        _data_phase: DataPhase<'_>,
+    ) -> impl Future<Output = Result<usize, UsbError>> {
+        future::ready(Err(UsbError::Timeout))
+    }
 
-    assert!(d.open_out_endpoint(7).is_err());
-}
+    fn bulk_in_transfer(
+        &self,
+        _address: u8,
+        _endpoint: u8,
+        _packet_size: u16,
+        _data: &mut [u8],
+        _transfer_type: TransferType,
+        _data_toggle: &Cell<bool>,
+    ) -> impl Future<Output = Result<usize, UsbError>> {
+        future::ready(Err(UsbError::Timeout))
+    }
 
-#[test]
-fn open_out_endpoint_ludicrous() {
-    let mut d = UsbDevice {
-        usb_address: 1,
-        usb_speed: UsbSpeed::Full12,
-        packet_size_ep0: 8,
-        in_endpoints_bitmap: 0x100,
-        out_endpoints_bitmap: 0x8001,
-    };
+    fn bulk_out_transfer(
+        &self,
+        _address: u8,
+        _endpoint: u8,
+        _packet_size: u16,
+        _data: &[u8],
+        _transfer_type: TransferType,
+        _data_toggle: &Cell<bool>,
+    ) -> impl Future<Output = Result<usize, UsbError>> {
+        future::ready(Err(UsbError::Timeout))
+    }
 
-    assert!(d.open_out_endpoint(70).is_err());
+    fn alloc_interrupt_pipe(
+        &self,
+        _address: u8,
+        _endpoint: u8,
+        _max_packet_size: u16,
+        _interval_ms: u8,
+    ) -> impl Future<Output = Self::InterruptPipe> {
+        future::ready(futures::stream::pending())
+    }
+
+    fn try_alloc_interrupt_pipe(
+        &self,
+        _address: u8,
+        _endpoint: u8,
+        _max_packet_size: u16,
+        _interval_ms: u8,
+    ) -> Result<Self::InterruptPipe, UsbError> {
+        Ok(futures::stream::pending())
+    }
 }
 
-fn is_clear_endpoint_feature<const EP: u8, const FEATURE: u16>(
-    a: &u8,
-    p: &u8,
-    s: &SetupPacket,
-    d: &DataPhase,
-) -> bool {
-    *a == 5
-        && *p == 8
-        && s.bmRequestType == HOST_TO_DEVICE | RECIPIENT_ENDPOINT
-        && s.bRequest == 1
-        && s.wValue == FEATURE
-        && s.wIndex == EP as u16
-        && s.wLength == 0
-        && d.is_none()
+#[cfg(feature = "hubs")]
+fn device_at(usb_address: u8) -> UsbDevice {
+    UsbDevice {
+        usb_address,
+        ..EXAMPLE_DEVICE
+    }
 }
 
+#[cfg(feature = "hubs")]
 #[test]
-fn clear_halt() {
+fn reset_device_root() {
     do_test(
         |hc| {
-            hc.expect_clear_endpoint_feature::<0x88, 0>();
+            hc.expect_reset_root_port().withf(|r| *r).return_const(());
+            hc.expect_reset_root_port().withf(|r| !*r).return_const(());
+            hc.expect_get_device_descriptor_prefix();
+            hc.expect_get_device_descriptor();
+            hc.expect_set_address::<127>();
         },
         |f| {
-            let mut d = UsbDevice {
-                usb_address: 5,
-                usb_speed: UsbSpeed::Full12,
-                packet_size_ep0: 8,
-                in_endpoints_bitmap: 0x100,
-                out_endpoints_bitmap: 0x8001,
-            };
-
-            let ep = d.open_in_endpoint(8).unwrap();
-            let r = pin!(f.bus.clear_halt(&ep));
-            let rr = r.poll(f.c).to_option().unwrap();
-            assert_eq!(rr, Ok(()));
+            f.hub_state
+                .topology
+                .borrow_mut()
+                .device_connect(0, 1, false);
+            f.hub_state.liveness_failures.borrow_mut()[127] = 2;
+            let r = pin!(f.bus.reset_device(
+                &f.hub_state,
+                device_at(127),
+                no_delay
+            ))
+            .poll(f.c);
+            assert_eq!(
+                r,
+                Poll::Ready(Ok(UnconfiguredDevice {
+                    usb_address: 127,
+                    usb_speed: UsbSpeed::Full12,
+                    packet_size_ep0: 8,
+                }))
+            );
+            assert!(f.hub_state.topology.borrow().is_present(127));
+            assert_eq!(f.hub_state.liveness_failures.borrow()[127], 0);
         },
     );
 }
 
+#[cfg(feature = "hubs")]
 #[test]
-fn clear_halt_fails() {
+fn reset_device_on_hub() {
     do_test(
         |hc| {
-            hc.expect_control_transfer()
-                .times(1)
-                .withf(is_clear_endpoint_feature::<0x88, 0>)
-                .returning(control_transfer_timeout);
+            hc.expect_set_port_feature::<1, 4>(); // PORT_RESET
+            hc.expect_get_port_status::<1, 0x403, 0>(); // ENABLED, HIGH_SPEED
+            hc.expect_get_device_descriptor_prefix();
+            hc.expect_get_device_descriptor();
+            hc.expect_set_address::<127>();
         },
         |f| {
-            let mut d = UsbDevice {
-                usb_address: 5,
-                usb_speed: UsbSpeed::Full12,
-                packet_size_ep0: 8,
-                in_endpoints_bitmap: 0x100,
-                out_endpoints_bitmap: 0x8001,
-            };
-
-            let ep = d.open_in_endpoint(8).unwrap();
-            let r = pin!(f.bus.clear_halt(&ep));
-            let rr = r.poll(f.c).to_option().unwrap();
-            assert_eq!(rr, Err(UsbError::Timeout));
+            {
+                // Set up topology so there's a device (127) on hub 5 port 1
+                let mut b = f.hub_state.topology.borrow_mut();
+                b.device_connect(0, 1, true); // 1
+                b.device_connect(1, 1, true); // 2
+                b.device_connect(1, 2, true); // 3
+                b.device_connect(1, 3, true); // 4
+                b.device_connect(1, 4, true); // 5
+                b.device_connect(5, 1, false); // 127
+            }
+            let r = pin!(f.bus.reset_device(
+                &f.hub_state,
+                device_at(127),
+                no_delay
+            ))
+            .poll(f.c);
+            assert_eq!(
+                r,
+                Poll::Ready(Ok(UnconfiguredDevice {
+                    usb_address: 127,
+                    usb_speed: UsbSpeed::High480,
+                    packet_size_ep0: 8,
+                }))
+            );
+            assert_eq!(
+                format!("{:?}", f.hub_state.topology()),
+                "0:(1:(2 3 4 5:(127)))"
+            );
         },
     );
 }
 
+#[cfg(feature = "hubs")]
 #[test]
-fn clear_halt_pends() {
+fn reset_device_not_present() {
     do_test(
         |hc| {
-            hc.expect_control_transfer()
-                .times(1)
-                .withf(is_clear_endpoint_feature::<0x88, 0>)
-                .returning(control_transfer_pending);
+            hc.expect_reset_root_port().times(0);
+            hc.expect_control_transfer().times(0);
         },
         |f| {
-            let mut d = UsbDevice {
-                usb_address: 5,
-                usb_speed: UsbSpeed::Full12,
-                packet_size_ep0: 8,
-                in_endpoints_bitmap: 0x100,
-                out_endpoints_bitmap: 0x8001,
-            };
-
-            let ep = d.open_in_endpoint(8).unwrap();
-            let mut fut = pin!(f.bus.clear_halt(&ep));
-
-            let poll = fut.as_mut().poll(f.c);
-            assert!(poll.is_pending());
-            let poll = fut.as_mut().poll(f.c);
-            assert!(poll.is_pending());
+            let r = pin!(f.bus.reset_device(
+                &f.hub_state,
+                device_at(127),
+                no_delay
+            ))
+            .poll(f.c);
+            assert_eq!(r, Poll::Ready(Err(UsbError::NoSuchDevice)));
         },
     );
 }
 
+#[cfg(feature = "hubs")]
 #[test]
-fn bulk_in_transfer() {
+fn reset_device_refuses_hubs() {
     do_test(
         |hc| {
-            hc.expect_bulk_in_transfer()
-                .withf(|a, e, _, d, t, p| {
-                    *a == 5
-                        && *e == 8
-                        && d.len() == 16
-                        && *t == TransferType::VariableSize
-                        && p.get() == false
-                })
-                .returning(bulk_in_ok::<16>);
+            hc.expect_reset_root_port().times(0);
+            hc.expect_control_transfer().times(0);
         },
         |f| {
-            let mut d = UsbDevice {
-                usb_address: 5,
-                usb_speed: UsbSpeed::Full12,
-                packet_size_ep0: 8,
-                in_endpoints_bitmap: 0x100,
-                out_endpoints_bitmap: 0x8001,
-            };
-
-            let ep = d.open_in_endpoint(8).unwrap();
-            let mut data = [0u8; 16];
-            let fut = pin!(f.bus.bulk_in_transfer(
-                &ep,
-                &mut data,
-                TransferType::VariableSize
-            ));
-            let rr = fut.poll(f.c).to_option().unwrap();
-            assert_eq!(rr, Ok(16));
+            {
+                let mut b = f.hub_state.topology.borrow_mut();
+                b.device_connect(0, 1, true); // 1
+                b.device_connect(1, 1, false); // 127
+            }
+            let r =
+                pin!(f.bus.reset_device(&f.hub_state, device_at(1), no_delay))
+                    .poll(f.c);
+            assert_eq!(r, Poll::Ready(Err(UsbError::NoSuchDevice)));
         },
     );
 }
 
+#[cfg(feature = "hubs")]
 #[test]
-fn bulk_out_transfer() {
+fn reset_device_fails() {
     do_test(
         |hc| {
-            hc.expect_bulk_out_transfer()
-                .withf(|a, e, _, d, t, p| {
-                    *a == 5
-                        && *e == 8
-                        && d.len() == 16
-                        && *t == TransferType::FixedSize
-                        && p.get() == false
-                })
-                .returning(bulk_out_ok::<16>);
+            hc.expect_set_port_feature::<1, 4>(); // PORT_RESET
+            hc.expect_get_port_status::<1, 1, 0>(); // CONNECTED but not ENABLED
         },
         |f| {
-            let mut d = UsbDevice {
-                usb_address: 5,
-                usb_speed: UsbSpeed::Full12,
-                packet_size_ep0: 8,
-                in_endpoints_bitmap: 0x100,
-                out_endpoints_bitmap: 0x8102,
-            };
+            {
+                let mut b = f.hub_state.topology.borrow_mut();
+                b.device_connect(0, 1, true); // 1
+                b.device_connect(1, 1, true); // 2
+                b.device_connect(1, 2, true); // 3
+                b.device_connect(1, 3, true); // 4
+                b.device_connect(1, 4, true); // 5
+                b.device_connect(5, 1, false); // 127
+            }
+            let r = pin!(f.bus.reset_device(
+                &f.hub_state,
+                device_at(127),
+                no_delay
+            ))
+            .poll(f.c);
+            assert_eq!(r, Poll::Ready(Err(UsbError::NoSuchDevice)));
+
+            // Treated like a failed enumeration, so it's tried again
+            assert!(!f.hub_state.topology.borrow().is_present(127));
+            assert_eq!(f.hub_state.next_retry_port(), Some((5, 1)));
+        },
+    );
+}
 
-            let ep = d.open_out_endpoint(8).unwrap();
-            let data = [0u8; 16];
-            let fut = pin!(f.bus.bulk_out_transfer(
-                &ep,
-                &data,
-                TransferType::FixedSize
-            ));
-            let rr = fut.poll(f.c).to_option().unwrap();
-            assert_eq!(rr, Ok(16));
+#[cfg(feature = "hubs")]
+#[test]
+fn reset_device_reopens_bound_pipe() {
+    do_test(
+        |hc| {
+            hc.expect_reset_root_port().withf(|r| *r).return_const(());
+            hc.expect_reset_root_port().withf(|r| !*r).return_const(());
+            hc.expect_get_device_descriptor_prefix();
+            hc.expect_get_device_descriptor();
+            hc.expect_set_address::<127>();
+            hc.expect_try_alloc_interrupt_pipe()
+                .times(2)
+                .withf(|a, _, _, _| *a == 127)
+                .returning(|_, _, _, _| Ok(MockInterruptPipe::new()));
+        },
+        |mut f| {
+            f.hub_state.bind_interrupt(EXAMPLE_BINDING).unwrap();
+            f.hub_state
+                .topology
+                .borrow_mut()
+                .device_connect(0, 1, false);
+            f.hub_state.track_bindings(&f.bus, &connect_event(127));
+            f.bus.configured.set(BitSet(1 << 127));
+            assert_eq!(f.hub_state.open_bound_pipes(&f.bus), None);
+            assert_eq!(bound_addresses(&f.hub_state), vec![127]);
+
+            let r = pin!(f.bus.reset_device(
+                &f.hub_state,
+                device_at(127),
+                no_delay
+            ))
+            .poll(f.c);
+            assert!(matches!(r, Poll::Ready(Ok(_))));
+            assert!(bound_addresses(&f.hub_state).is_empty());
+            assert!(!f.bus.configured.get().contains(127));
+
+            // Re-opened once configured again
+            f.bus.configured.set(BitSet(1 << 127));
+            assert_eq!(f.hub_state.open_bound_pipes(&f.bus), None);
+            assert_eq!(bound_addresses(&f.hub_state), vec![127]);
         },
     );
 }
+
+#[cfg(feature = "send-futures")]
+fn assert_send<T: Send>(_: &T) {}
+
+#[cfg(feature = "send-futures")]
+#[test]
+fn futures_are_send() {
+    let bus = UsbBus::new(SendController);
+    #[cfg(feature = "hubs")]
+    let hub_state = HubState::default();
+    let delay = |_ms: usize| future::ready(());
+    let mut device = EXAMPLE_DEVICE;
+    let bulk_in = device.open_in_endpoint(2).unwrap();
+    let bulk_out = device.open_out_endpoint(1).unwrap();
+    let setup = SetupPacket {
+        bmRequestType: DEVICE_TO_HOST,
+        bRequest: GET_STATUS,
+        wValue: 0,
+        wIndex: 0,
+        wLength: 2,
+    };
+    let mut buf = [0u8; 64];
+
+    #[cfg(feature = "hubs")]
+    {
+        assert_send(&bus.device_events(&hub_state, delay));
+        assert_send(&bus.reset_device(&hub_state, EXAMPLE_DEVICE, delay));
+    }
+    assert_send(&bus.device_events_no_hubs(delay));
+    assert_send(&bus.configure(unconfigured_device(), 1));
+    assert_send(&bus.control_transfer(&device, setup, DataPhase::None));
+    assert_send(&bus.clear_halt(&bulk_in));
+    assert_send(&bus.get_device_status(&device));
+    assert_send(&bus.set_remote_wakeup(&device, true));
+    assert_send(&bus.bulk_out_transfer(
+        &bulk_out,
+        &[1, 2, 3],
+        TransferType::FixedSize,
+    ));
+    assert_send(&bus.bulk_in_transfer(
+        &bulk_in,
+        &mut buf,
+        TransferType::VariableSize,
+    ));
+    assert_send(&bus.interrupt_endpoint_in(5, 1, 8, 10));
+    assert_send(&bus.get_configuration(
+        &unconfigured_device(),
+        &mut crate::wire::ShowDescriptors,
+    ));
+}
+
+#[cfg(feature = "send-futures")]
+#[test]
+fn bus_can_be_shared_between_threads() {
+    let bus = UsbBus::new(SendController);
+    std::thread::scope(|s| {
+        for _ in 0..4 {
+            s.spawn(|| {
+                for i in 1..100 {
+                    let _ = bus.transfer_result(i, Ok(0));
+                }
+            });
+        }
+    });
+    #[cfg(feature = "hubs")]
+    assert_eq!(bus.active.get().iter().count(), 99);
+}
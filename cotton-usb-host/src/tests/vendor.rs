@@ -0,0 +1,184 @@
+use super::*;
+use crate::mocks::MockHostController;
+use crate::usb_bus::create_test_device;
+use core::future::Future;
+use futures::future;
+use std::pin::pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Wake, Waker};
+
+struct NoOpWaker;
+
+impl Wake for NoOpWaker {
+    fn wake(self: Arc<Self>) {}
+}
+
+fn run<F: Future>(fut: F) -> F::Output {
+    let w = Waker::from(Arc::new(NoOpWaker));
+    let mut c = Context::from_waker(&w);
+    let Poll::Ready(r) = pin!(fut).poll(&mut c) else {
+        panic!("future pended");
+    };
+    r
+}
+
+/// Run `test` against a device which answers IN requests with
+/// `reply`, and records every setup packet and OUT data phase
+fn with_device<F>(endian: Endian, reply: &'static [u8], test: F)
+where
+    F: FnOnce(&VendorDevice<MockHostController>),
+{
+    with_device_log(endian, reply, |d, _| test(d));
+}
+
+type Log = Arc<Mutex<Vec<(SetupPacket, Vec<u8>)>>>;
+
+fn with_device_log<F>(endian: Endian, reply: &'static [u8], test: F)
+where
+    F: FnOnce(&VendorDevice<MockHostController>, &Log),
+{
+    let log = Log::default();
+    let log2 = log.clone();
+    let mut hc = MockHostController::default();
+    hc.inner.expect_control_transfer().returning(
+        move |_, _, setup, mut data| {
+            let mut out = Vec::new();
+            let n = match data {
+                DataPhase::Out(b) => {
+                    out.extend_from_slice(b);
+                    b.len()
+                }
+                _ => {
+                    let n = reply.len().min(data.len());
+                    data.in_with(|b| b[0..n].copy_from_slice(&reply[0..n]));
+                    n
+                }
+            };
+            log2.lock().unwrap().push((setup, out));
+            Box::pin(future::ready(Ok(n)))
+        },
+    );
+    let bus = UsbBus::new(hc);
+    let device = unsafe { create_test_device(0, 0) };
+    let vendor = VendorDevice::new(&bus, device, endian);
+    test(&vendor, &log);
+}
+
+#[test]
+fn read_mac_address() {
+    with_device_log(
+        Endian::Little,
+        &[0x00, 0x0E, 0xC6, 0x01, 0x02, 0x03],
+        |d, log| {
+            assert_eq!(
+                run(d.read_reg::<[u8; 6]>(0x13, 0)),
+                Ok([0x00, 0x0E, 0xC6, 0x01, 0x02, 0x03])
+            );
+            let log = log.lock().unwrap();
+            assert_eq!(log.len(), 1);
+            let setup = &log[0].0;
+            assert_eq!(setup.bmRequestType, 0xC0);
+            assert_eq!(setup.bRequest, 0x13);
+            assert_eq!(setup.wValue, 0);
+            assert_eq!(setup.wIndex, 0);
+            assert_eq!(setup.wLength, 6);
+        },
+    );
+}
+
+#[test]
+fn read_u16_little_endian() {
+    with_device(Endian::Little, &[0x34, 0x12], |d| {
+        assert_eq!(run(d.read_reg::<u16>(1, 2)), Ok(0x1234));
+    });
+}
+
+#[test]
+fn read_u16_big_endian() {
+    with_device(Endian::Big, &[0x12, 0x34], |d| {
+        assert_eq!(run(d.read_reg::<u16>(1, 2)), Ok(0x1234));
+    });
+}
+
+#[test]
+fn read_u32_both_endians() {
+    with_device(Endian::Little, &[1, 2, 3, 4], |d| {
+        assert_eq!(run(d.read_reg::<u32>(1, 2)), Ok(0x0403_0201));
+    });
+    with_device(Endian::Big, &[1, 2, 3, 4], |d| {
+        assert_eq!(run(d.read_reg::<u32>(1, 2)), Ok(0x0102_0304));
+    });
+}
+
+#[test]
+fn read_signed() {
+    with_device(Endian::Big, &[0xFF, 0xFE], |d| {
+        assert_eq!(run(d.read_reg::<i16>(1, 2)), Ok(-2));
+    });
+}
+
+#[test]
+fn short_read_is_protocol_error() {
+    with_device(Endian::Little, &[1, 2], |d| {
+        assert_eq!(run(d.read_reg::<u32>(1, 2)), Err(UsbError::ProtocolError));
+    });
+}
+
+#[test]
+fn oversize_register_is_rejected() {
+    with_device_log(Endian::Little, &[], |d, log| {
+        assert_eq!(
+            run(d.read_reg::<[u8; 65]>(1, 2)),
+            Err(UsbError::BufferTooSmall)
+        );
+        assert_eq!(
+            run(d.write_reg(1, 2, &[0u8; 65])),
+            Err(UsbError::BufferTooSmall)
+        );
+        assert!(log.lock().unwrap().is_empty());
+    });
+}
+
+#[test]
+fn largest_register() {
+    with_device(Endian::Little, &[7u8; 64], |d| {
+        assert_eq!(run(d.read_reg::<[u8; 64]>(1, 2)), Ok([7u8; 64]));
+    });
+}
+
+#[test]
+fn write_little_endian() {
+    with_device_log(Endian::Little, &[], |d, log| {
+        assert_eq!(run(d.write_reg(0x10, 0x20, &0x1234_5678u32)), Ok(()));
+        let log = log.lock().unwrap();
+        assert_eq!(log.len(), 1);
+        let (setup, data) = &log[0];
+        assert_eq!(setup.bmRequestType, 0x40);
+        assert_eq!(setup.bRequest, 0x10);
+        assert_eq!(setup.wValue, 0);
+        assert_eq!(setup.wIndex, 0x20);
+        assert_eq!(setup.wLength, 4);
+        assert_eq!(data, &[0x78, 0x56, 0x34, 0x12]);
+    });
+}
+
+#[test]
+fn write_big_endian() {
+    with_device_log(Endian::Big, &[], |d, log| {
+        assert_eq!(run(d.write_reg(0x10, 0x20, &0x1234u16)), Ok(()));
+        assert_eq!(log.lock().unwrap()[0].1, &[0x12, 0x34]);
+    });
+}
+
+#[test]
+fn write_error_is_passed_on() {
+    let mut hc = MockHostController::default();
+    hc.inner
+        .expect_control_transfer()
+        .returning(|_, _, _, _| Box::pin(future::ready(Err(UsbError::Stall))));
+    let bus = UsbBus::new(hc);
+    let device = unsafe { create_test_device(0, 0) };
+    let d = VendorDevice::new(&bus, device, Endian::Little);
+    assert_eq!(run(d.write_reg(1, 2, &3u8)), Err(UsbError::Stall));
+    assert_eq!(run(d.read_reg::<u8>(1, 2)), Err(UsbError::Stall));
+}
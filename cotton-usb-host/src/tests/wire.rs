@@ -60,6 +60,7 @@ const ELLA: &[u8] = &[
     5, 9, 13, 64, 2, 4, 8, 37, 1, 0, 0, 1, 0, 0, 9, 4, 4, 0, 0, 1, 2, 32, 5,
 ];
 
+#[cfg(feature = "hubs")]
 const HUB: &[u8] = &[9, 41, 4, 0, 0, 50, 100, 0, 255];
 
 #[test]
@@ -81,6 +82,7 @@ fn ignore_ella() {
     parse_descriptors(ELLA, &mut IgnoreVisitor);
 }
 
+#[cfg(feature = "hubs")]
 #[test]
 fn hub() {
     let h: &HubDescriptor = bytemuck::from_bytes(HUB);
@@ -88,6 +90,15 @@ fn hub() {
     assert_eq!(h.bHubContrCurrent, 100);
 }
 
+#[cfg(feature = "hubs")]
+#[test]
+fn hub_power() {
+    let h: &HubDescriptor = bytemuck::from_bytes(HUB);
+    assert_eq!(h.num_ports(), 4);
+    assert_eq!(h.power_on_to_power_good_ms(), 100);
+    assert_eq!(h.controller_current_ma(), 100);
+}
+
 #[test]
 fn invalid_descriptors() {
     // Mostly a test for Miri
@@ -102,3 +113,257 @@ fn reserved_descriptor() {
     // Mostly a test for Miri
     parse_descriptors(&[3, 96, 1], &mut ShowDescriptors);
 }
+
+// A BOS descriptor as returned by a USB 3.0 flash drive: USB 2.0
+// Extension (LPM and BESL), SuperSpeed USB, and Container ID
+const BOS: &[u8] = &[
+    5, 15, 42, 0, 3, //
+    7, 16, 2, 6, 0, 0, 0, //
+    10, 16, 3, 0, 14, 0, 1, 10, 255, 7, //
+    20, 16, 4, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16,
+];
+
+#[derive(Default)]
+struct TestCapabilityVisitor {
+    num_caps: u8,
+    lpm: Option<bool>,
+    besl: Option<bool>,
+    speeds: Option<u16>,
+    others: Vec<u8>,
+}
+
+impl CapabilityVisitor for TestCapabilityVisitor {
+    fn on_bos(&mut self, b: &BosDescriptor) {
+        self.num_caps = b.bNumDeviceCaps;
+    }
+    fn on_usb20_extension(&mut self, u: &Usb20ExtensionDescriptor) {
+        self.lpm = Some(u.supports_lpm());
+        self.besl = Some(u.supports_besl());
+    }
+    fn on_superspeed_usb(&mut self, s: &SuperSpeedUsbDescriptor) {
+        self.speeds = Some(s.speeds_supported());
+    }
+    fn on_other_capability(&mut self, d: &[u8]) {
+        self.others.push(d[2]);
+    }
+}
+
+struct IgnoreCapabilityVisitor;
+
+impl CapabilityVisitor for IgnoreCapabilityVisitor {}
+
+#[test]
+fn parse_bos() {
+    let mut v = TestCapabilityVisitor::default();
+    assert!(parse_bos_descriptor(BOS, &mut v));
+    assert_eq!(v.num_caps, 3);
+    assert_eq!(v.lpm, Some(true));
+    assert_eq!(v.besl, Some(true));
+    assert_eq!(v.speeds, Some(14));
+    assert_eq!(v.others, vec![4]);
+}
+
+#[test]
+fn ignore_bos() {
+    assert!(parse_bos_descriptor(BOS, &mut IgnoreCapabilityVisitor));
+}
+
+#[test]
+fn parse_bos_truncated() {
+    // Only the complete capabilities are reported
+    let mut v = TestCapabilityVisitor::default();
+    assert!(parse_bos_descriptor(&BOS[0..20], &mut v));
+    assert_eq!(v.lpm, Some(true));
+    assert_eq!(v.speeds, None);
+    assert!(v.others.is_empty());
+}
+
+#[test]
+fn parse_bos_respects_total_length() {
+    let mut bos = BOS.to_vec();
+    bos[2] = 12; // wTotalLength covers only USB 2.0 Extension
+    let mut v = TestCapabilityVisitor::default();
+    assert!(parse_bos_descriptor(&bos, &mut v));
+    assert_eq!(v.lpm, Some(true));
+    assert_eq!(v.speeds, None);
+}
+
+#[test]
+fn parse_bos_no_lpm() {
+    let bos = [5, 15, 12, 0, 1, 7, 16, 2, 0, 0, 0, 0];
+    let mut v = TestCapabilityVisitor::default();
+    assert!(parse_bos_descriptor(&bos, &mut v));
+    assert_eq!(v.lpm, Some(false));
+    assert_eq!(v.besl, Some(false));
+}
+
+#[test]
+fn parse_bos_wrong_sizes() {
+    // Capabilities of the wrong size are ignored
+    let bos = [5, 15, 11, 0, 2, 3, 16, 2, 3, 16, 3];
+    let mut v = TestCapabilityVisitor::default();
+    assert!(parse_bos_descriptor(&bos, &mut v));
+    assert_eq!(v.num_caps, 2);
+    assert_eq!(v.lpm, None);
+    assert_eq!(v.speeds, None);
+}
+
+#[test]
+fn parse_bos_skips_other_descriptors() {
+    let bos = [5, 15, 10, 0, 1, 5, 5, 1, 2, 3];
+    let mut v = TestCapabilityVisitor::default();
+    assert!(parse_bos_descriptor(&bos, &mut v));
+    assert!(v.others.is_empty());
+}
+
+#[test]
+fn parse_bos_invalid() {
+    let mut v = TestCapabilityVisitor::default();
+    assert!(!parse_bos_descriptor(&[], &mut v));
+    assert!(!parse_bos_descriptor(&[5, 15, 5, 0], &mut v));
+    assert!(!parse_bos_descriptor(&[5, 2, 5, 0, 0], &mut v));
+    assert!(!parse_bos_descriptor(&[4, 15, 5, 0, 0], &mut v));
+    // Bogus capability length stops the parse, but harmlessly
+    assert!(parse_bos_descriptor(&[5, 15, 8, 0, 1, 0, 16, 2], &mut v));
+    assert_eq!(v.num_caps, 1);
+    assert_eq!(v.lpm, None);
+}
+
+#[cfg(feature = "hubs")]
+#[test]
+fn hub_port_feature_selectors() {
+    // USB 2.0 table 11-17
+    assert_eq!(HubPortFeature::PortEnable as u16, PORT_ENABLE);
+    assert_eq!(HubPortFeature::PortReset as u16, PORT_RESET);
+    assert_eq!(HubPortFeature::PortPower as u16, PORT_POWER);
+    for selector in 0..32 {
+        if let Some(f) = HubPortFeature::from_selector(selector) {
+            assert_eq!(f as u16, selector);
+        }
+    }
+    assert_eq!(HubPortFeature::from_selector(5), None);
+    assert_eq!(HubPortFeature::from_selector(23), None);
+}
+
+#[cfg(feature = "hubs")]
+#[test]
+fn hub_port_change_features() {
+    // USB 2.0 table 11-22
+    assert_eq!(
+        HubPortFeature::change(0),
+        Some(HubPortFeature::CPortConnection)
+    );
+    assert_eq!(HubPortFeature::change(1), Some(HubPortFeature::CPortEnable));
+    assert_eq!(HubPortFeature::change(4), Some(HubPortFeature::CPortReset));
+    assert_eq!(HubPortFeature::change(5), None);
+    assert_eq!(HubPortFeature::change(15), None);
+}
+
+#[cfg(feature = "hubs")]
+#[test]
+fn hub_port_requests() {
+    let s = SetupPacket::set_port_feature(3, HubPortFeature::PortReset);
+    assert_eq!(s.bmRequestType, 0x23);
+    assert_eq!(s.bRequest, SET_FEATURE);
+    assert_eq!(s.wValue, 4);
+    assert_eq!(s.wIndex, 3);
+    assert_eq!(s.wLength, 0);
+
+    let s =
+        SetupPacket::clear_port_feature(2, HubPortFeature::CPortConnection);
+    assert_eq!(s.bmRequestType, 0x23);
+    assert_eq!(s.bRequest, CLEAR_FEATURE);
+    assert_eq!(s.wValue, 16);
+    assert_eq!(s.wIndex, 2);
+    assert_eq!(s.wLength, 0);
+
+    let s = SetupPacket::get_port_status(1);
+    assert_eq!(s.bmRequestType, 0xA3);
+    assert_eq!(s.bRequest, GET_STATUS);
+    assert_eq!(s.wValue, 0);
+    assert_eq!(s.wIndex, 1);
+    assert_eq!(s.wLength, 4);
+
+    let s = SetupPacket::get_hub_descriptor(64);
+    assert_eq!(s.bmRequestType, 0xA0);
+    assert_eq!(s.bRequest, GET_DESCRIPTOR);
+    assert_eq!(s.wValue, 0x2900);
+    assert_eq!(s.wIndex, 0);
+    assert_eq!(s.wLength, 64);
+}
+
+#[test]
+fn setup_packet_bytes() {
+    let bytes = [0x80, 6, 0x00, 0x01, 0x09, 0x04, 0x12, 0x00];
+    let s = SetupPacket::from_bytes(&bytes);
+    assert_eq!(s.bmRequestType, DEVICE_TO_HOST);
+    assert_eq!(s.bRequest, GET_DESCRIPTOR);
+    assert_eq!(s.wValue, 0x100);
+    assert_eq!(s.wIndex, 0x409);
+    assert_eq!(s.wLength, 18);
+    assert_eq!(s.direction(), Direction::In);
+    assert_eq!(s.to_bytes(), bytes);
+}
+
+#[cfg(feature = "hubs")]
+#[test]
+fn setup_packet_direction() {
+    let s = SetupPacket::set_port_feature(1, HubPortFeature::PortPower);
+    assert_eq!(s.direction(), Direction::Out);
+    assert_eq!(SetupPacket::from_bytes(&s.to_bytes()), s);
+}
+
+#[test]
+fn device_descriptor_bytes() {
+    let bytes = [
+        18, 1, 0x00, 0x02, 0, 0, 0, 64, 0x09, 0x12, 0x01, 0x00, 0x00, 0x01, 1,
+        2, 3, 1,
+    ];
+    let d: &DeviceDescriptor = bytemuck::from_bytes(&bytes);
+    assert_eq!(d.bDescriptorType, DEVICE_DESCRIPTOR);
+    assert_eq!(d.bMaxPacketSize0, 64);
+    assert_eq!(u16::from_le_bytes(d.idVendor), 0x1209);
+    assert_eq!(d.iSerialNumber, 3);
+    assert_eq!(bytemuck::bytes_of(d), bytes);
+}
+
+#[test]
+fn endpoint_descriptor_fields() {
+    let bytes = [7, 5, 0x81, 3, 0x08, 0x1A, 0];
+    let e: &EndpointDescriptor = bytemuck::from_bytes(&bytes);
+    // Top bits are high-bandwidth transaction count, not packet size
+    assert_eq!(e.max_packet_size(), 0x208);
+    // Zero interval would be meaningless; treat as "every frame"
+    assert_eq!(e.interval_ms(), 1);
+
+    let bytes = [7, 5, 0x81, 3, 64, 0, 32];
+    let e: &EndpointDescriptor = bytemuck::from_bytes(&bytes);
+    assert_eq!(e.max_packet_size(), 64);
+    assert_eq!(e.interval_ms(), 32);
+}
+
+#[test]
+fn configuration_descriptor_remote_wakeup() {
+    let bytes = [9, 2, 32, 0, 1, 1, 0, 0xA0, 50];
+    let c: &ConfigurationDescriptor = bytemuck::from_bytes(&bytes);
+    assert!(c.supports_remote_wakeup());
+
+    let bytes = [9, 2, 32, 0, 1, 1, 0, 0xC0, 50];
+    let c: &ConfigurationDescriptor = bytemuck::from_bytes(&bytes);
+    assert!(!c.supports_remote_wakeup());
+}
+
+#[test]
+fn device_status_flags() {
+    let s = DeviceStatusFlags::default();
+    assert!(!s.self_powered());
+    assert!(!s.remote_wakeup());
+
+    let s = DeviceStatusFlags(1);
+    assert!(s.self_powered());
+    assert!(!s.remote_wakeup());
+
+    let s = DeviceStatusFlags(2);
+    assert!(!s.self_powered());
+    assert!(s.remote_wakeup());
+}
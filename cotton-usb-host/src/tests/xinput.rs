@@ -0,0 +1,237 @@
+use super::*;
+use crate::host_controller::InterruptPacket;
+use crate::mocks::{MockHostController, MockInterruptPipe};
+use crate::usb_bus::create_test_device;
+use crate::wire::parse_descriptors;
+use core::future::Future;
+use futures::future;
+use std::pin::pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Wake, Waker};
+
+struct NoOpWaker;
+
+impl Wake for NoOpWaker {
+    fn wake(self: Arc<Self>) {}
+}
+
+fn run<F: Future>(fut: F) -> F::Output {
+    let w = Waker::from(Arc::new(NoOpWaker));
+    let mut c = Context::from_waker(&w);
+    let Poll::Ready(r) = pin!(fut).poll(&mut c) else {
+        panic!("future pended");
+    };
+    r
+}
+
+// The first two interfaces of a wired Xbox 360 controller: the gamepad,
+// with its (undocumented) class-specific descriptor, and the headset
+const XBOX360_CONFIG: &[u8] = &[
+    9, 2, 72, 0, 2, 1, 0, 160, 250, // configuration
+    9, 4, 0, 0, 2, 255, 93, 1, 0, // gamepad interface
+    17, 33, 0, 1, 1, 37, 129, 20, 0, 0, 0, 0, 19, 1, 8, 0, 0, // unknown
+    7, 5, 129, 3, 32, 0, 4, // interrupt IN
+    7, 5, 1, 3, 32, 0, 8, // interrupt OUT
+    9, 4, 1, 0, 2, 255, 93, 3, 0, // headset interface
+    7, 5, 130, 3, 32, 0, 2, // interrupt IN
+    7, 5, 2, 3, 32, 0, 4, // interrupt OUT
+];
+
+// Captured input reports
+const REPORT_IDLE: [u8; 20] =
+    [0, 20, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+const REPORT_A_AND_LB: [u8; 20] =
+    [0, 20, 0, 17, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+const REPORT_STICKS: [u8; 20] = [
+    0, 20, 1, 0, 255, 40, 246, 251, 59, 5, 196, 254, 142, 1, 0, 0, 0, 0, 0, 0,
+];
+// LED status, which controllers send after an LED command
+const REPORT_LED: [u8; 3] = [1, 3, 6];
+
+fn identify() -> IdentifyXInput {
+    let mut id = IdentifyXInput::default();
+    parse_descriptors(XBOX360_CONFIG, &mut id);
+    id
+}
+
+fn packet(data: &[u8]) -> InterruptPacket {
+    let mut p = InterruptPacket {
+        address: 255,
+        endpoint: 1,
+        size: data.len() as u8,
+        ..Default::default()
+    };
+    p.data[0..data.len()].copy_from_slice(data);
+    p
+}
+
+#[test]
+fn identify_xbox360() {
+    let id = identify();
+    assert_eq!(id.identify(), Some(1));
+    assert_eq!(id.interface(), 0);
+    let e = id.in_endpoint().unwrap();
+    assert_eq!(e.bEndpointAddress, 0x81);
+    assert_eq!(e.max_packet_size(), 32);
+    assert_eq!(e.bInterval, 4);
+    assert_eq!(id.out_endpoint(), Some(1));
+}
+
+#[test]
+fn identify_ignores_other_interfaces() {
+    let mut config = XBOX360_CONFIG.to_vec();
+    config[16] = 2; // gamepad interface now claims another protocol
+    let mut id = IdentifyXInput::default();
+    parse_descriptors(&config, &mut id);
+    assert_eq!(id.identify(), None);
+}
+
+#[test]
+fn identify_needs_both_endpoints() {
+    let mut id = IdentifyXInput::default();
+    parse_descriptors(&XBOX360_CONFIG[0..42], &mut id);
+    assert_eq!(id.identify(), None);
+    assert!(id.in_endpoint().is_some());
+    assert_eq!(id.out_endpoint(), None);
+}
+
+#[test]
+fn parse_idle() {
+    assert_eq!(
+        GamepadState::parse(&REPORT_IDLE),
+        Some(GamepadState::default())
+    );
+}
+
+#[test]
+fn parse_buttons() {
+    let s = GamepadState::parse(&REPORT_A_AND_LB).unwrap();
+    assert!(s.pressed(buttons::A));
+    assert!(s.pressed(buttons::A | buttons::LEFT_SHOULDER));
+    assert!(!s.pressed(buttons::B));
+    assert!(!s.pressed(buttons::A | buttons::B));
+}
+
+#[test]
+fn parse_sticks_and_triggers() {
+    let s = GamepadState::parse(&REPORT_STICKS).unwrap();
+    assert_eq!(s.buttons, buttons::DPAD_UP);
+    assert_eq!(s.left_trigger, 255);
+    assert_eq!(s.right_trigger, 40);
+    assert_eq!(s.left_stick, (-1034, 1339));
+    assert_eq!(s.right_stick, (-316, 398));
+}
+
+#[test]
+fn parse_rejects_other_messages() {
+    assert_eq!(GamepadState::parse(&REPORT_LED), None);
+    assert_eq!(GamepadState::parse(&REPORT_IDLE[0..13]), None);
+    let mut r = REPORT_IDLE;
+    r[1] = 3;
+    assert_eq!(GamepadState::parse(&r), None);
+}
+
+fn expect_sends(hc: &mut MockHostController, sent: &Arc<Mutex<Vec<Vec<u8>>>>) {
+    let sent = sent.clone();
+    hc.inner
+        .expect_bulk_out_transfer()
+        .withf(|a, e, _, _, _, _| *a == 255 && *e == 1)
+        .returning(move |_, _, _, data, _, _| {
+            sent.lock().unwrap().push(data.to_vec());
+            Box::pin(future::ready(Ok(data.len())))
+        });
+}
+
+#[test]
+fn new_needs_endpoints() {
+    let bus = UsbBus::new(MockHostController::default());
+    let device = unsafe { create_test_device(0, 0) };
+    let r = XInput::new(&bus, device, &IdentifyXInput::default());
+    assert_eq!(r.err(), Some(UsbError::NoSuchEndpoint));
+}
+
+#[test]
+fn init() {
+    let sent = Arc::new(Mutex::new(Vec::new()));
+    let mut hc = MockHostController::default();
+    hc.inner
+        .expect_control_transfer()
+        .withf(|a, _, s, d| {
+            *a == 255
+                && s.bmRequestType == 0xC1
+                && s.bRequest == 1
+                && s.wValue == 0x100
+                && s.wIndex == 0
+                && s.wLength == 20
+                && d.is_in()
+        })
+        .returning(|_, _, _, _| Box::pin(future::ready(Ok(20))));
+    expect_sends(&mut hc, &sent);
+    let bus = UsbBus::new(hc);
+    let device = unsafe { create_test_device(2, 2) };
+    let x = XInput::new(&bus, device, &identify()).unwrap();
+
+    assert_eq!(run(x.init()), Ok(()));
+    assert_eq!(*sent.lock().unwrap(), [vec![1, 3, 6]]);
+}
+
+#[test]
+fn init_fails() {
+    let mut hc = MockHostController::default();
+    hc.inner
+        .expect_control_transfer()
+        .returning(|_, _, _, _| Box::pin(future::ready(Err(UsbError::Stall))));
+    let bus = UsbBus::new(hc);
+    let device = unsafe { create_test_device(2, 2) };
+    let x = XInput::new(&bus, device, &identify()).unwrap();
+
+    assert_eq!(run(x.init()), Err(UsbError::Stall));
+}
+
+#[test]
+fn rumble_and_led() {
+    let sent = Arc::new(Mutex::new(Vec::new()));
+    let mut hc = MockHostController::default();
+    expect_sends(&mut hc, &sent);
+    let bus = UsbBus::new(hc);
+    let device = unsafe { create_test_device(2, 2) };
+    let x = XInput::new(&bus, device, &identify()).unwrap();
+
+    assert_eq!(run(x.set_rumble(200, 10)), Ok(()));
+    assert_eq!(run(x.set_led(LedPattern::Rotating)), Ok(()));
+    assert_eq!(
+        *sent.lock().unwrap(),
+        [vec![0, 8, 0, 200, 10, 0, 0, 0], vec![1, 3, 10]]
+    );
+}
+
+#[test]
+fn states() {
+    let w = Waker::from(Arc::new(NoOpWaker));
+    let mut c = Context::from_waker(&w);
+
+    let mut hc = MockHostController::default();
+    hc.inner
+        .expect_alloc_interrupt_pipe()
+        .withf(|a, e, m, i| *a == 255 && *e == 1 && *m == 32 && *i == 4)
+        .returning(|_, _, _, _| {
+            Box::pin(future::ready({
+                let mut ip = MockInterruptPipe::new();
+                let mut reports =
+                    vec![packet(&REPORT_STICKS), packet(&REPORT_LED)];
+                ip.expect_poll_next()
+                    .returning(move |_| Poll::Ready(reports.pop()));
+                ip
+            }))
+        });
+    let bus = UsbBus::new(hc);
+    let device = unsafe { create_test_device(2, 2) };
+    let x = XInput::new(&bus, device, &identify()).unwrap();
+
+    let mut s = pin!(x.states());
+    assert_eq!(
+        s.as_mut().poll_next(&mut c),
+        Poll::Ready(GamepadState::parse(&REPORT_STICKS))
+    );
+    assert_eq!(s.as_mut().poll_next(&mut c), Poll::Ready(None));
+}
@@ -2,10 +2,130 @@ use crate::bitset::BitSet;
 #[cfg(feature = "std")]
 use std::fmt::{Debug, Error, Formatter};
 
-const MAX_DEVICES: u8 = 32;
+const MAX_DEVICES: u8 = 128;
 const MAX_PORTS: u8 = 16;
 const MAX_HUBS: u8 = 16;
 
+/// Power characteristics of a hub, as read from its hub descriptor
+///
+/// Stored in the same units as the descriptor itself, so as to keep
+/// [`Topology`] small.
+#[derive(Copy, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+pub struct HubPower {
+    pwr_on_2_pwr_good: u8,
+    hub_contr_current: u8,
+}
+
+impl HubPower {
+    /// Create a `HubPower` from hub-descriptor values
+    ///
+    /// # Parameters
+    ///  - power_on_to_power_good_ms: Port power-good delay, in ms
+    ///    (rounded up to a multiple of 2ms, up to 510ms)
+    ///  - controller_current_ma: Hub controller current, in mA
+    pub fn new(
+        power_on_to_power_good_ms: u16,
+        controller_current_ma: u8,
+    ) -> Self {
+        Self {
+            pwr_on_2_pwr_good: power_on_to_power_good_ms
+                .div_ceil(2)
+                .try_into()
+                .unwrap_or(u8::MAX),
+            hub_contr_current: controller_current_ma,
+        }
+    }
+
+    /// Time from powering-on a port until its power is good, in ms
+    pub const fn power_on_to_power_good_ms(&self) -> u16 {
+        self.pwr_on_2_pwr_good as u16 * 2
+    }
+
+    /// Maximum current drawn by the hub controller itself, in mA
+    pub const fn controller_current_ma(&self) -> u8 {
+        self.hub_contr_current
+    }
+}
+
+/// Maximum length of a [`PortPath`]
+///
+/// The USB standard allows at most five hubs between the host and
+/// any device, so six ports including the root port.
+const MAX_PATH: usize = 6;
+
+/// The physical location of a device: the chain of ports leading to it
+///
+/// The first entry is always 1, the host controller's (single) root
+/// port; each later entry is a port number on the hub attached at the
+/// previous position. So a device plugged straight into the host is
+/// at path `1`, and one on port 3 of a hub plugged into the host is at
+/// path `1.3` (which is how [`Display`](core::fmt::Display) shows it).
+///
+/// Unlike USB addresses, which are handed out in order of enumeration,
+/// port paths stay the same when the bus is re-enumerated, so are
+/// suitable for presenting to users.
+#[derive(Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "std", derive(Debug))]
+pub struct PortPath {
+    hub: u8,
+    len: u8,
+    ports: [u8; MAX_PATH],
+}
+
+impl PortPath {
+    /// The path of the host controller's root port
+    pub const fn root() -> Self {
+        let mut ports = [0; MAX_PATH];
+        ports[0] = 1;
+        Self {
+            hub: 0,
+            len: 1,
+            ports,
+        }
+    }
+
+    /// The port numbers, starting from the root port
+    pub fn ports(&self) -> &[u8] {
+        &self.ports[..self.len as usize]
+    }
+
+    /// The port number, on its parent hub, that the device is attached to
+    pub const fn port(&self) -> u8 {
+        self.ports[self.len as usize - 1]
+    }
+
+    /// The USB address of the parent hub (0 for the root port)
+    pub const fn hub_address(&self) -> u8 {
+        self.hub
+    }
+}
+
+impl core::fmt::Display for PortPath {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        for (i, port) in self.ports().iter().enumerate() {
+            if i > 0 {
+                f.write_str(".")?;
+            }
+            write!(f, "{}", port)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for PortPath {
+    fn format(&self, f: defmt::Formatter<'_>) {
+        for (i, port) in self.ports().iter().enumerate() {
+            if i > 0 {
+                defmt::write!(f, ".");
+            }
+            defmt::write!(f, "{}", port);
+        }
+    }
+}
+
 /// Representing the topology of the USB bus attached to this host controller
 ///
 /// This includes which devices are hubs, and which devices are downstream of
@@ -13,14 +133,20 @@ const MAX_HUBS: u8 = 16;
 /// using the implemented `Debug` or `defmt::Format` traits.
 ///
 /// The topology is represented in a compact form: for each possible
-/// device (0-31, but really 1-31 as 0 isn't valid), a u8 stores its
+/// device (0-127, but really 1-127 as 0 isn't valid), a u8 stores its
 /// parent hub in the lower 4 bits, and the port number on that hub in
-/// the upper four bits.
-#[derive(Default, Clone)]
+/// the upper four bits. (So hubs themselves are always given addresses
+/// 1-15.) Alongside that, each hub's power characteristics are kept,
+/// and shown in the diagnostic output as `hub[Nms NmA]`.
+#[derive(Clone)]
 pub struct Topology {
     parent: [u8; MAX_DEVICES as usize],
+    hub_power: [HubPower; MAX_HUBS as usize],
 }
 
+// Topology is often kept in a static, on small microcontrollers
+const _: () = assert!(core::mem::size_of::<Topology>() <= 160);
+
 #[cfg(feature = "std")]
 impl Debug for Topology {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
@@ -30,6 +156,15 @@ impl Debug for Topology {
             f: &mut Formatter<'_>,
         ) -> Result<(), Error> {
             write!(f, "{}", i).unwrap();
+            if let Some(power) = bus.hub_power(i as u8) {
+                write!(
+                    f,
+                    "[{}ms {}mA]",
+                    power.power_on_to_power_good_ms(),
+                    power.controller_current_ma()
+                )
+                .unwrap();
+            }
 
             let mut any = false;
             for j in 1..(MAX_DEVICES as usize) {
@@ -65,6 +200,14 @@ impl defmt::Format for Topology {
     fn format(&self, f: defmt::Formatter<'_>) {
         fn fmt_inner(bus: &Topology, i: usize, f: defmt::Formatter<'_>) {
             defmt::write!(f, "{}", i);
+            if let Some(power) = bus.hub_power(i as u8) {
+                defmt::write!(
+                    f,
+                    "[{}ms {}mA]",
+                    power.power_on_to_power_good_ms(),
+                    power.controller_current_ma()
+                );
+            }
 
             let mut any = false;
             for j in 1..(MAX_DEVICES as usize) {
@@ -94,10 +237,19 @@ impl defmt::Format for Topology {
     }
 }
 
+impl Default for Topology {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Topology {
     /// Create a new Topology object representing an empty bus (0 devices)
     pub fn new() -> Self {
-        Self { parent: [0u8; 32] }
+        Self {
+            parent: [0u8; MAX_DEVICES as usize],
+            hub_power: [HubPower::default(); MAX_HUBS as usize],
+        }
     }
 
     /// Is this USB device address believed present on the bus?
@@ -105,6 +257,62 @@ impl Topology {
         self.parent.get(device as usize).is_some_and(|x| *x > 0)
     }
 
+    /// Where a device is attached, if it's present
+    ///
+    /// Returns `Some((parent_hub, parent_port))`, in the form passed to
+    /// [`Topology::device_connect()`].
+    pub fn parent(&self, device: u8) -> Option<(u8, u8)> {
+        self.parent
+            .get(device as usize)
+            .filter(|x| **x > 0)
+            .map(|x| (x & 15, x >> 4))
+    }
+
+    /// The physical location of port `port` on hub `hub`
+    ///
+    /// Found by following the chain of parent hubs back to the root.
+    /// For the root port itself, pass `hub` 0 and `port` 1.
+    pub fn port_path(&self, hub: u8, port: u8) -> PortPath {
+        let mut reversed = [0; MAX_PATH];
+        let mut len = 1;
+        reversed[0] = port;
+        let mut current = hub;
+        while current != 0 && len < MAX_PATH {
+            let Some((parent_hub, parent_port)) = self.parent(current) else {
+                break;
+            };
+            reversed[len] = parent_port;
+            len += 1;
+            current = parent_hub;
+        }
+        let mut ports = [0; MAX_PATH];
+        for (i, p) in reversed[..len].iter().rev().enumerate() {
+            ports[i] = *p;
+        }
+        PortPath {
+            hub,
+            len: len as u8,
+            ports,
+        }
+    }
+
+    /// Record the power characteristics of a hub
+    ///
+    /// Ignored if `hub` isn't a valid hub address.
+    pub fn set_hub_power(&mut self, hub: u8, power: HubPower) {
+        if let Some(p) = self.hub_power.get_mut(hub as usize) {
+            *p = power;
+        }
+    }
+
+    /// The power characteristics of a hub, if known
+    pub fn hub_power(&self, hub: u8) -> Option<HubPower> {
+        self.hub_power
+            .get(hub as usize)
+            .copied()
+            .filter(|p| *p != HubPower::default())
+    }
+
     /// A new USB device has been connected
     ///
     /// # Parameters
@@ -146,6 +354,37 @@ impl Topology {
         None
     }
 
+    /// A USB device is known to be at a particular address already
+    ///
+    /// Used when resuming after a soft reset, where the device kept
+    /// the address it was given before; otherwise as
+    /// [`Topology::device_connect()`], except that the address is
+    /// chosen by the caller.
+    ///
+    /// Returns `false`, changing nothing, if the address or port is
+    /// invalid, or if a different device already has the address.
+    pub fn device_restore(
+        &mut self,
+        address: u8,
+        parent_hub: u8,
+        parent_port: u8,
+    ) -> bool {
+        if address == 0
+            || address >= MAX_DEVICES
+            || parent_hub >= MAX_HUBS
+            || parent_port >= MAX_PORTS
+        {
+            return false;
+        }
+        let entry = (parent_port << 4) + parent_hub;
+        let current = self.parent[address as usize];
+        if current != 0 && current != entry {
+            return false;
+        }
+        self.parent[address as usize] = entry;
+        true
+    }
+
     /// A USB device has been disconnected
     ///
     /// Because the device has *already* gone, we aren't told *its* address,
@@ -168,7 +407,7 @@ impl Topology {
             return BitSet::default();
         }
 
-        let mut bitset = 0u32;
+        let mut bitset = 0u128;
 
         loop {
             let old_bitset = bitset;
@@ -195,6 +434,12 @@ impl Topology {
                 break;
             }
         }
+
+        for (i, power) in self.hub_power.iter_mut().enumerate() {
+            if (bitset & 1 << i) != 0 {
+                *power = HubPower::default();
+            }
+        }
         BitSet(bitset)
     }
 }
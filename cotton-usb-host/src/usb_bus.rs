@@ -1,23 +1,36 @@
+#[cfg(feature = "hubs")]
+use crate::async_pool::Pool;
 use crate::bitset::BitSet;
+#[cfg(feature = "hubs")]
+use crate::cell;
+use crate::cell::{Cell, RefCell};
+#[cfg(feature = "alloc")]
+use crate::config_tree::ConfigurationTree;
 use crate::debug;
-use crate::topology::Topology;
+use crate::delay::DelayProvider;
+pub use crate::topology::PortPath;
+#[cfg(feature = "hubs")]
+use crate::topology::{HubPower, Topology};
 use crate::wire::{
-    ConfigurationDescriptor, DescriptorVisitor, EndpointDescriptor,
-    HubDescriptor, SetupPacket, CLASS_REQUEST, CLEAR_FEATURE,
-    CONFIGURATION_DESCRIPTOR, DEVICE_DESCRIPTOR, DEVICE_TO_HOST,
-    GET_DESCRIPTOR, GET_STATUS, HOST_TO_DEVICE, HUB_CLASSCODE, HUB_DESCRIPTOR,
-    PORT_POWER, PORT_RESET, RECIPIENT_OTHER, SET_ADDRESS, SET_CONFIGURATION,
-    SET_FEATURE,
+    CapabilityVisitor, ConfigurationDescriptor, DescriptorVisitor,
+    DeviceStatusFlags, EndpointDescriptor, SetupPacket,
+    SuperSpeedUsbDescriptor, Usb20ExtensionDescriptor, BOS_DESCRIPTOR,
+    CLEAR_FEATURE, CONFIGURATION_DESCRIPTOR, DEVICE_DESCRIPTOR,
+    DEVICE_REMOTE_WAKEUP, DEVICE_TO_HOST, ENDPOINT_HALT, GET_DESCRIPTOR,
+    GET_STATUS, HOST_TO_DEVICE, SET_ADDRESS, SET_CONFIGURATION, SET_FEATURE,
 };
-use core::cell::{Cell, RefCell};
+#[cfg(feature = "hubs")]
+use crate::wire::{HubDescriptor, HubPortFeature, HUB_CLASSCODE};
+#[cfg(feature = "hubs")]
 use core::pin::Pin;
-use core::task::{Context, Poll};
+#[cfg(feature = "hubs")]
+use core::task::{Context, Poll, Waker};
 use futures::future::FutureExt;
 use futures::{Future, Stream, StreamExt};
 
 pub use crate::host_controller::{
-    DataPhase, DeviceStatus, HostController, InterruptPacket, TransferType,
-    UsbError, UsbSpeed,
+    DataPhase, DeviceStatus, HostController, InterruptPacket,
+    InterruptPacketView, TransferStatistics, TransferType, UsbError, UsbSpeed,
 };
 
 /// Basic information about a USB device, perhaps sufficient to select a driver
@@ -39,6 +52,43 @@ pub struct DeviceInfo {
     pub class: u8,
     /// Subclass code (from device descriptor)
     pub subclass: u8,
+    /// USB specification release number, in BCD (e.g. 0x0200 for USB 2.0)
+    pub usb_version: u16,
+    /// Device release number, in BCD (firmware revision, chosen by the
+    /// vendor)
+    pub device_release: u16,
+    /// String descriptor index of the serial number, or 0 if none
+    pub serial_number_index: u8,
+}
+
+/// What to do with a newly-connected device
+///
+/// Returned by the filter function passed to
+/// [`UsbBus::set_device_filter()`].
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum Admission {
+    /// Enumerate the device as usual
+    Accept,
+
+    /// Give the device an address, but don't configure it (or, if
+    /// it's a hub, its downstream ports); report it as
+    /// [`DeviceEvent::Rejected`] instead of [`DeviceEvent::Connect`]
+    RefuseConfigure,
+
+    /// Don't even give the device an address, and disable its hub
+    /// port; report it as [`DeviceEvent::Rejected`]
+    RefuseAddress,
+
+    /// Give the device an address, but leave it (even if it's a hub)
+    /// for a driver to claim with [`UsbBus::claim_device()`] and
+    /// configure; report it as [`DeviceEvent::Offered`]
+    Offer,
+}
+
+fn accept_all(_: &DeviceInfo) -> Admission {
+    Admission::Accept
 }
 
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
@@ -69,13 +119,65 @@ pub struct UnconfiguredDevice {
 impl UnconfiguredDevice {
     /// The USB address assigned to this device
     ///
-    /// By the spec, must be in the range 1-127 (currently
-    /// cotton-usb-host only assigns addresses in the range 1-31).
+    /// By the spec, must be in the range 1-127.
     pub fn address(&self) -> u8 {
         self.usb_address
     }
 }
 
+/// A device which one driver has claimed, and no other driver can
+///
+/// Obtained from [`UsbBus::claim_device()`]. The driver can inspect
+/// the device's descriptors, and perform any vendor-specific setup,
+/// before configuring it with [`UsbBus::configure()`] (via
+/// [`ClaimedDevice::into_device()`]) -- or can decide it doesn't want
+/// the device after all, and hand it back with
+/// [`UsbBus::release_device()`] for another driver to claim.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(PartialEq, Eq)]
+pub struct ClaimedDevice {
+    device: UnconfiguredDevice,
+    info: DeviceInfo,
+}
+
+impl ClaimedDevice {
+    /// The USB address assigned to this device
+    pub fn address(&self) -> u8 {
+        self.device.usb_address
+    }
+
+    /// Basic information about the device
+    pub fn info(&self) -> &DeviceInfo {
+        &self.info
+    }
+
+    /// The device, for reading its descriptors
+    pub fn device(&self) -> &UnconfiguredDevice {
+        &self.device
+    }
+
+    /// The device, for passing to [`UsbBus::configure()`]
+    ///
+    /// The device stays claimed until it's configured or disconnected.
+    pub fn into_device(self) -> UnconfiguredDevice {
+        self.device
+    }
+}
+
+/// A device offered to drivers, in a [`UsbBus`]'s table of offers
+#[derive(Copy, Clone)]
+struct Offer {
+    usb_address: u8,
+    usb_speed: UsbSpeed,
+    packet_size_ep0: u8,
+    info: DeviceInfo,
+    claimed: bool,
+}
+
+/// The most devices which can be offered (claimed or not) at once
+const MAX_OFFERED: usize = 8;
+
 /// A Bulk IN endpoint on a particular USB device
 ///
 /// For use with [`UsbBus::bulk_in_transfer`].
@@ -121,20 +223,20 @@ pub struct UsbDevice {
 impl UsbDevice {
     /// USB address of the device
     ///
-    /// By the standard, 1-127, though cotton-usb-host only hands out
-    /// addresses in the range 1-31.
+    /// By the standard, 1-127. Hubs are always given addresses in the
+    /// range 1-15.
     pub fn address(&self) -> u8 {
         self.usb_address
     }
 
     /// Return a bitmap of available IN endpoints
     pub fn in_endpoints(&self) -> BitSet {
-        BitSet(self.in_endpoints_bitmap as u32)
+        BitSet(self.in_endpoints_bitmap.into())
     }
 
     /// Return a bitmap of available OUT endpoints
     pub fn out_endpoints(&self) -> BitSet {
-        BitSet(self.out_endpoints_bitmap as u32)
+        BitSet(self.out_endpoints_bitmap.into())
     }
 
     /// Open one of the IN endpoints for reading
@@ -189,6 +291,11 @@ impl UsbDevice {
 /// detect the presence of USB devices and start to communicate with
 /// them.
 ///
+/// The variants are the same whether or not the `hubs` Cargo feature
+/// is enabled, so that code matching on them builds either way; but
+/// without it, only `device_events_no_hubs()` is available, and the
+/// events it doesn't produce (such as `HubConnect`) never occur.
+///
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 #[cfg_attr(feature = "std", derive(Debug))]
 #[derive(PartialEq, Eq)]
@@ -208,7 +315,9 @@ pub enum DeviceEvent {
     /// before configuring, the device's configuration descriptors can
     /// be fetched using [`UsbBus::get_basic_configuration()`] or
     /// [`UsbBus::get_configuration()`].
-    Connect(UnconfiguredDevice, DeviceInfo),
+    ///
+    /// The [`PortPath`] says where, physically, the device is attached.
+    Connect(UnconfiguredDevice, DeviceInfo, PortPath),
 
     /// A new hub has been connected and configured (when using
     /// [`UsbBus::device_events()`] and not
@@ -218,7 +327,19 @@ pub enum DeviceEvent {
     /// actions e.g. powering-down particular ports. Normal
     /// powering-up and enumerating of hub ports is done by this crate
     /// in the [`UsbBus::device_events`] call.
-    HubConnect(UsbDevice),
+    ///
+    /// The [`PortPath`] says where, physically, the hub is attached.
+    HubConnect(UsbDevice, PortPath),
+
+    /// A device recorded in the [`PersistedBus`] passed to
+    /// [`HubState::resume_from()`] is still present, and responding
+    /// at its old address, so has been reconfigured without being
+    /// reset or re-enumerated.
+    ///
+    /// Unlike [`DeviceEvent::Connect`], the device is already
+    /// configured, with the same configuration value as before; the
+    /// tuple members are as for `Connect`.
+    Resumed(UsbDevice, DeviceInfo, PortPath),
 
     /// A previously-reported device has become disconnected. This event
     /// includes a _set_ of affected devices -- if a hub has become
@@ -237,10 +358,128 @@ pub enum DeviceEvent {
     /// This usually indicates inadequate power supply, or perhaps
     /// damaged cabling.
     ///
-    /// The first two tuple members are the USB address of the hub to which
-    /// the device failed to connect (0 if it failed directly attached to the
-    /// host), and the port number on that hub (1-based numbering).
-    EnumerationError(u8, u8, UsbError),
+    /// The first tuple member is the location of the port at which the
+    /// device failed to connect; [`PortPath::hub_address()`] and
+    /// [`PortPath::port()`] give the hub (0 if it failed directly
+    /// attached to the host) and the port number on that hub (1-based
+    /// numbering).
+    EnumerationError(PortPath, UsbError),
+
+    /// A device failed to enumerate, but another attempt will be made
+    /// (when using [`UsbBus::device_events()`] and not
+    /// [`UsbBus::device_events_no_hubs()`]).
+    ///
+    /// Some devices fail their first enumeration after power-up, but
+    /// succeed after a further port reset. How many attempts are made,
+    /// and how far apart, is governed by the [`RetryPolicy`] given to
+    /// [`HubState::with_retry_policy()`]. If the final attempt also
+    /// fails, [`DeviceEvent::EnumerationError`] is reported instead.
+    ///
+    /// The tuple members are the location of the port (as for
+    /// `EnumerationError`), the number of the attempt which failed
+    /// (1-based), and the error itself.
+    EnumerationRetry(PortPath, u8, UsbError),
+
+    /// A device's transfers are failing, due to (probable) poor signal
+    /// quality, more often than the [`ErrorRatePolicy`] allows (when
+    /// using [`UsbBus::device_events()`] and not
+    /// [`UsbBus::device_events_no_hubs()`]).
+    ///
+    /// This usually indicates a damaged, over-long, or poorly-shielded
+    /// cable, rather than a software problem. It is reported at most
+    /// once per connected device; the device remains usable.
+    ///
+    /// The tuple members are the USB address of the device, and its
+    /// statistics at the time the warning was raised.
+    ErrorRateWarning(u8, TransferStatistics),
+
+    /// A device has stopped responding to keep-alive requests, and has
+    /// been treated as disconnected (when using
+    /// [`UsbBus::device_events()`] with a [`LivenessPolicy`] set via
+    /// [`HubState::set_liveness_policy()`]).
+    ///
+    /// This is like [`DeviceEvent::Disconnect`], but reports a device
+    /// which is (as far as the hub can tell) still attached, yet has
+    /// failed several GET_STATUS requests in a row -- perhaps because its
+    /// firmware has crashed.
+    ///
+    /// The tuple members are the USB address of the unresponsive device,
+    /// and the set of devices now considered disconnected (as for
+    /// `Disconnect`, this includes anything downstream of it if it was
+    /// a hub).
+    Unresponsive(u8, BitSet),
+
+    /// A hub has disabled one of its ports because of an error, such
+    /// as the device on it babbling, and the port will be re-enabled
+    /// after a delay (when using [`UsbBus::device_events()`]).
+    ///
+    /// As with [`DeviceEvent::Disconnect`], the device on the port
+    /// (and anything downstream of it) is gone; if re-enabling the port
+    /// succeeds, it's reported as newly connected. How many times that's
+    /// tried, and how long the delays are, is governed by the
+    /// [`PortErrorPolicy`] given to [`HubState::set_port_error_policy()`];
+    /// once the attempts are used up, [`DeviceEvent::PortDisabled`] is
+    /// reported instead.
+    ///
+    /// The tuple members are the location of the port (as for
+    /// `EnumerationError`), the number of errors on it so far
+    /// (1-based), and the set of devices now considered disconnected.
+    PortError(PortPath, u8, BitSet),
+
+    /// A hub has disabled one of its ports because of an error, and it
+    /// has happened too often for the port to be re-enabled again (when
+    /// using [`UsbBus::device_events()`]).
+    ///
+    /// The port stays disabled until the device on it is unplugged,
+    /// which is worth telling the user: the rest of the bus carries on
+    /// working, but the faulty device won't. The tuple members are as
+    /// for [`DeviceEvent::PortError`], without the count.
+    PortDisabled(PortPath, BitSet),
+
+    /// A device has been connected, but was refused by the filter
+    /// passed to [`UsbBus::set_device_filter()`].
+    ///
+    /// Depending on the [`Admission`] returned by the filter, the
+    /// device has either been given an address but not configured, or
+    /// not even been given an address.
+    ///
+    /// The tuple members are the location of the port (as for
+    /// `EnumerationError`), and the basic information about the device
+    /// which the filter was given.
+    Rejected(PortPath, DeviceInfo),
+
+    /// A new device has been given an address, and offered for drivers
+    /// to claim, because the filter function passed to
+    /// [`UsbBus::set_device_filter()`] returned [`Admission::Offer`].
+    ///
+    /// The device isn't configured: a driver that wants it should call
+    /// [`UsbBus::claim_device()`] with its address. Only one claim
+    /// can succeed; until one does, the device remains among the
+    /// [`UsbBus::unclaimed_devices()`], so drivers registered later
+    /// can still find it.
+    ///
+    /// The tuple members are the USB address of the device, and the
+    /// basic information about the device which the filter was given.
+    /// (If too many devices are already on offer, the device is
+    /// reported as [`DeviceEvent::Connect`] instead.)
+    Offered(u8, DeviceInfo),
+
+    /// A packet has arrived from an interrupt endpoint bound with
+    /// [`HubState::bind_interrupt()`] (when using
+    /// [`UsbBus::device_events()`]).
+    ///
+    /// The tuple members are the binding (as returned by
+    /// `bind_interrupt()`), which tells your code which driver should
+    /// handle the packet, and the packet itself (which includes the
+    /// USB address of the device).
+    Interrupt(BindingId, InterruptPacket),
+
+    /// An interrupt endpoint bound with [`HubState::bind_interrupt()`]
+    /// couldn't be opened on a newly-configured device.
+    ///
+    /// The tuple members are the binding, the USB address of the
+    /// device, and the error (often [`UsbError::AllPipesInUse`]).
+    InterruptError(BindingId, u8, UsbError),
 
     /// There is nothing currently to report. (This event is sometimes sent
     /// for internal reasons, and can be ignored.)
@@ -294,6 +533,47 @@ impl DescriptorVisitor for BasicConfiguration {
     }
 }
 
+/// Finds a hub's status-change endpoint: its (only) IN endpoint
+#[cfg(feature = "hubs")]
+#[derive(Default)]
+struct StatusChangeEndpoint(Option<EndpointDescriptor>);
+
+#[cfg(feature = "hubs")]
+impl DescriptorVisitor for StatusChangeEndpoint {
+    fn on_endpoint(&mut self, e: &EndpointDescriptor) {
+        if (e.bEndpointAddress & 0x80) == 0x80 && self.0.is_none() {
+            self.0 = Some(*e);
+        }
+    }
+}
+
+/// A simplified summary of a device's BOS (device capability) descriptors
+///
+/// Can be obtained by passing it to [`UsbBus::get_bos_descriptor()`].
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Default, PartialEq, Eq)]
+pub struct BosCapabilities {
+    /// The device supports USB 2.0 Link Power Management
+    pub lpm: bool,
+
+    /// The device supports BESL (Best Effort Service Latency) LPM
+    pub besl: bool,
+
+    /// The device is capable of SuperSpeed operation
+    pub superspeed: bool,
+}
+
+impl CapabilityVisitor for BosCapabilities {
+    fn on_usb20_extension(&mut self, u: &Usb20ExtensionDescriptor) {
+        self.lpm = u.supports_lpm();
+        self.besl = u.supports_besl();
+    }
+    fn on_superspeed_usb(&mut self, _s: &SuperSpeedUsbDescriptor) {
+        self.superspeed = true;
+    }
+}
+
 struct SpecificConfiguration {
     configuration_value: u8,
     ok: bool,
@@ -327,248 +607,1740 @@ impl DescriptorVisitor for SpecificConfiguration {
     }
 }
 
-/// Encapsulating the bus-wide USB hub state machine
+/// How hard to try when enumerating a newly-connected device
 ///
-/// This mostly exists to be passed-in to [`UsbBus::device_events()`]; it
-/// keeps hub-management data out of `struct UsbBus` for users who don't
-/// need hub support.
-pub struct HubState<HC: HostController> {
-    topology: RefCell<Topology>,
-    pipes: RefCell<[Option<HC::InterruptPipe>; 15]>,
+/// Used by [`UsbBus::device_events()`], via
+/// [`HubState::with_retry_policy()`]. If enumeration fails (that is,
+/// if the device doesn't respond properly to reading its device
+/// descriptor or setting its address), the port is reset and
+/// enumeration tried again, up to a total of `attempts` times.
+#[cfg(feature = "hubs")]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Total number of enumeration attempts, including the first
+    ///
+    /// A value of 1 (or 0) means no retries.
+    pub attempts: u8,
+
+    /// Delay before each retry, in milliseconds
+    ///
+    /// This is in addition to the usual port-reset timings.
+    pub retry_delay_ms: usize,
 }
 
-impl<HC: HostController> Default for HubState<HC> {
+#[cfg(feature = "hubs")]
+impl Default for RetryPolicy {
+    /// Three attempts, 100ms apart, as desktop operating systems do
     fn default() -> Self {
         Self {
-            topology: Default::default(),
-            pipes: Default::default(),
+            attempts: 3,
+            retry_delay_ms: 100,
         }
     }
 }
 
-impl<HC: HostController> HubState<HC> {
-    /// Return a snapshot of the current physical bus layout
-    ///
-    /// This snapshot includes a representation of all the hubs and
-    /// devices currently detected, and how they are linked together.
+/// How hard to try with the first requests to a newly-reset device
+///
+/// Used by [`UsbBus`], via [`UsbBus::set_control_retry_policy()`].
+/// The first GET_DESCRIPTOR requests after a port reset commonly fail
+/// on marginal hardware, so if one of them fails (other than for
+/// lack of resources on the host), it's repeated, after a short
+/// delay, up to a total of `attempts` times -- before resorting to
+/// resetting the port again as governed by [`RetryPolicy`].
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct ControlRetryPolicy {
+    /// Total number of attempts at each request, including the first
     ///
-    /// This is useful for logging/debugging.
-    pub fn topology(&self) -> Topology {
-        self.topology.borrow().clone()
-    }
+    /// A value of 1 (or 0) means no retries.
+    pub attempts: u8,
 
-    fn try_add(
-        &self,
-        hc: &HC,
-        address: u8,
-        endpoint: u8,
-        max_packet_size: u8,
-        interval_ms: u8,
-    ) -> Result<(), UsbError> {
-        for p in self.pipes.borrow_mut().iter_mut() {
-            if p.is_none() {
-                *p = Some(hc.try_alloc_interrupt_pipe(
-                    address,
-                    endpoint,
-                    max_packet_size as u16,
-                    interval_ms,
-                )?);
-                return Ok(());
-            }
+    /// Delay before each retry, in milliseconds
+    pub retry_delay_ms: usize,
+}
+
+impl Default for ControlRetryPolicy {
+    /// Three attempts, 10ms apart
+    fn default() -> Self {
+        Self {
+            attempts: 3,
+            retry_delay_ms: 10,
         }
-        Err(UsbError::TooManyDevices)
     }
 }
 
-struct HubStateStream<'a, HC: HostController> {
-    state: &'a HubState<HC>,
+impl ControlRetryPolicy {
+    /// Is a request that failed with `error` worth trying again?
+    fn should_retry(&self, error: UsbError, attempt: u8) -> bool {
+        attempt < self.attempts
+            && !matches!(
+                error,
+                UsbError::BufferTooSmall
+                    | UsbError::AllPipesInUse
+                    | UsbError::TooManyDevices
+                    | UsbError::NoSuchEndpoint
+                    | UsbError::NoSuchDevice
+            )
+    }
 }
 
-impl<HC: HostController> Stream for HubStateStream<'_, HC> {
-    type Item = InterruptPacket;
+/// When to warn that a device's transfers are failing too often
+///
+/// Used by [`UsbBus::device_events()`], via
+/// [`HubState::set_error_rate_policy()`]. Once a device has attempted at
+/// least `min_transfers` transfers, and more than `max_errors_per_1000`
+/// out of every thousand of them have failed with CRC or bit-stuffing
+/// errors, a [`DeviceEvent::ErrorRateWarning`] is raised.
+#[cfg(feature = "hubs")]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct ErrorRatePolicy {
+    /// Number of transfers needed before the error rate means anything
+    pub min_transfers: u16,
 
-    fn poll_next(
-        self: Pin<&mut Self>,
-        cx: &mut Context,
-    ) -> Poll<Option<Self::Item>> {
-        for pipe in self.state.pipes.borrow_mut().iter_mut().flatten() {
-            let poll = pipe.poll_next_unpin(cx);
-            if poll.is_ready() {
-                return poll;
-            }
+    /// Highest acceptable signal-error rate, in errors per 1000 transfers
+    pub max_errors_per_1000: u16,
+}
+
+#[cfg(feature = "hubs")]
+impl Default for ErrorRatePolicy {
+    /// Warn if more than 1% of at least 100 transfers have failed
+    fn default() -> Self {
+        Self {
+            min_transfers: 100,
+            max_errors_per_1000: 10,
         }
-        Poll::Pending
     }
 }
 
-/// A USB host bus.
-///
-/// This object represents the (portable) concept of a host's view of
-/// a whole bus of USB devices. It is constructed from a
-/// [`HostController`] object which encapsulates the driver for
-/// specific USB-host-controller hardware.
+#[cfg(feature = "hubs")]
+impl ErrorRatePolicy {
+    /// Do these statistics represent an unacceptable error rate?
+    pub fn is_exceeded_by(&self, statistics: &TransferStatistics) -> bool {
+        statistics.transfers >= self.min_transfers
+            && statistics.signal_errors() * 1000
+                > statistics.transfers as u32 * self.max_errors_per_1000 as u32
+    }
+}
+
+/// Whether, and how often, to check that idle devices are still alive
 ///
-/// Starting from a `UsbBus` object, you can obtain details of any USB
-/// devices attached to this host, and start communicating with them as
-/// needed.
+/// Used by [`UsbBus::device_events()`], via
+/// [`HubState::set_liveness_policy()`]. Every `interval_ms`, each
+/// device which hasn't successfully completed a transfer since the
+/// last check is sent a GET_STATUS request; after `max_failures`
+/// consecutive failures, it's reported as
+/// [`DeviceEvent::Unresponsive`].
 ///
-/// Devices with multiple USB host controllers will require a `UsbBus`
-/// object for each of them.
+/// By default, no such checks are made.
+#[cfg(feature = "hubs")]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct LivenessPolicy {
+    /// Time between checks, in milliseconds (0 to disable checking)
+    pub interval_ms: usize,
+
+    /// Number of consecutive failed checks before giving up on a device
+    pub max_failures: u8,
+}
+
+#[cfg(feature = "hubs")]
+impl Default for LivenessPolicy {
+    /// Disabled; if enabled, give up after three failures
+    fn default() -> Self {
+        Self {
+            interval_ms: 0,
+            max_failures: 3,
+        }
+    }
+}
+
+#[cfg(feature = "hubs")]
+impl LivenessPolicy {
+    /// Are keep-alive checks to be made at all?
+    pub fn is_enabled(&self) -> bool {
+        self.interval_ms != 0
+    }
+}
+
+/// What to do when a hub disables a port because of an error
 ///
-pub struct UsbBus<HC: HostController> {
-    driver: HC,
+/// Used by [`UsbBus::device_events()`], via
+/// [`HubState::set_port_error_policy()`]. A hub disables a downstream
+/// port if the device on it misbehaves badly enough to endanger the
+/// rest of the bus -- typically by "babbling", transmitting beyond
+/// the end of its allotted time (USB 2.0 section 11.8.1). The port is
+/// then re-enabled, and the device enumerated afresh, up to
+/// `reenable_attempts` times: the first attempt after `backoff_ms`,
+/// and each further one after twice as long as the one before. After
+/// that, the port is left disabled until the device is unplugged.
+#[cfg(feature = "hubs")]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct PortErrorPolicy {
+    /// Number of times to re-enable a port after errors (0 for never)
+    pub reenable_attempts: u8,
+
+    /// Delay before the first re-enable attempt, in milliseconds
+    pub backoff_ms: usize,
 }
 
-impl<HC: HostController> UsbBus<HC> {
-    /// Create a new USB host bus from a host-controller driver
-    pub fn new(driver: HC) -> Self {
-        Self { driver }
+#[cfg(feature = "hubs")]
+impl Default for PortErrorPolicy {
+    /// Four attempts, after 100ms, 200ms, 400ms and 800ms
+    fn default() -> Self {
+        Self {
+            reenable_attempts: 4,
+            backoff_ms: 100,
+        }
     }
+}
 
-    /// Obtain a stream of hotplug/hot-unplug events
-    ///
-    /// This stream is how the USB host stack informs your code that a
-    /// USB device is available for use. Once you have [created a
-    /// `UsbBus` object](UsbBus::new()), you can call `device_events()` and
-    /// get a stream of [`DeviceEvent`] objects:
-    ///
-    /// ```no_run
-    /// # use cotton_usb_host::host_controller::HostController;
-    /// # use std::pin::{pin, Pin};
-    /// # use cotton_usb_host::usb_bus::{HubState, UsbBus, DeviceEvent};
-    /// # use futures::{future, Future, Stream, StreamExt};
-    /// # fn delay_ms(_ms: usize) -> impl Future<Output = ()> {
-    /// #  future::ready(())
-    /// # }
-    /// # async fn foo<D: HostController>(driver: D) -> () {
-    /// let hub_state = HubState::default();
-    /// let bus = UsbBus::new(driver);
-    /// let mut device_stream = pin!(bus.device_events(&hub_state, delay_ms));
-    /// loop {
-    ///     let event = device_stream.next().await;
-    ///     if let Some(DeviceEvent::Connect(device, info)) = event {
-    ///         // ... process the device ...
-    ///     }
-    /// }
-    /// # }
-    /// ```
-    ///
-    /// You need to supply an implementation of the "delay" function which,
-    /// given a parameter in milliseconds, returns a Future that waits for
-    /// that long before coming ready. See the examples for how to implement
-    /// that (simple!) function for RTIC2 and for Embassy; other executors
-    /// will require their own implementations.
-    ///
-    /// When using this method, the cotton-usb-host crate itself takes
-    /// care of detecting and configuring hubs, and of detecting
-    /// devices downstream of hubs. At present, the hubs do themselves
-    /// appear as `DeviceEvent`s, but your code doesn't need to do
-    /// anything with them.
-    ///
-    /// If you know for a fact that your hardware setup does not
-    /// include any hubs (or if you wish to operate the hubs
-    /// yourself), you can use
-    /// [`device_events_no_hubs()`](`UsbBus::device_events_no_hubs()`)
-    /// instead of `device_events()` and get smaller, simpler code.
-    ///
-    pub fn device_events<
-        'a,
-        D: Future<Output = ()>,
-        F: Fn(usize) -> D + 'static + Clone,
-    >(
-        &'a self,
-        hub_state: &'a HubState<HC>,
-        delay_ms_in: F,
-    ) -> impl Stream<Item = DeviceEvent> + 'a {
-        let root_device = self.driver.device_detect();
+#[cfg(feature = "hubs")]
+impl PortErrorPolicy {
+    /// The delay before re-enabling a port after its `errors`th error
+    pub fn backoff_ms(&self, errors: u8) -> usize {
+        let doublings = errors.saturating_sub(1).min(16);
+        self.backoff_ms.saturating_mul(1 << doublings)
+    }
+}
+
+/// How many devices a [`PersistedBus`] can record
+#[cfg(feature = "hubs")]
+pub const MAX_PERSISTED_DEVICES: usize = 16;
+
+/// Marks a [`PersistedBus`] as having been written by this crate
+#[cfg(feature = "hubs")]
+const PERSISTED_MAGIC: u32 = 0x5553_4250; // "USBP"
+
+/// One device's entry in a [`PersistedBus`]
+///
+/// Just enough to talk to the device again without enumerating it:
+/// where it is, what address and speed it has, and (for devices other
+/// than hubs) which configuration it was put in.
+#[cfg(feature = "hubs")]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone, Default, PartialEq, Eq)]
+#[repr(C)]
+pub struct PersistedDevice {
+    address: u8,
+    hub: u8,
+    port: u8,
+    speed: u8,
+    packet_size_ep0: u8,
+    /// Configuration value, or 0 for a hub
+    configuration_value: u8,
+}
 
-        enum InternalEvent {
-            Root(DeviceStatus),
-            Packet(InterruptPacket),
+#[cfg(feature = "hubs")]
+impl PersistedDevice {
+    fn new(
+        device: &UsbDevice,
+        path: &PortPath,
+        configuration_value: u8,
+    ) -> Self {
+        Self {
+            address: device.usb_address,
+            hub: path.hub_address(),
+            port: path.port(),
+            speed: device.usb_speed as u8,
+            packet_size_ep0: device.packet_size_ep0,
+            configuration_value,
         }
+    }
 
-        futures::stream::select(
-            root_device.map(InternalEvent::Root),
-            HubStateStream { state: hub_state }
-                /*
-                MultiInterruptStream::<HC::MultiInterruptPipe> {
-                    pipe: &hub_state.pipes,
-                } */
-                .map(InternalEvent::Packet),
-        )
-        .then(move |ev| {
-            let delay_ms = delay_ms_in.clone();
-            async move {
-                match ev {
-                    InternalEvent::Root(status) => {
-                        if let DeviceStatus::Present(speed) = status {
-                            self.driver.reset_root_port(true);
-                            delay_ms(50).await;
-                            self.driver.reset_root_port(false);
-                            delay_ms(10).await;
-                            let (device, info) =
-                                match self.new_device(speed).await {
-                                    Ok((device, info)) => (device, info),
-                                    Err(e) => {
-                                        return DeviceEvent::EnumerationError(
-                                            0, 1, e,
-                                        )
-                                    }
-                                };
-                            let is_hub = info.class == HUB_CLASSCODE;
-                            let address = hub_state
-                                .topology
-                                .borrow_mut()
-                                .device_connect(0, 1, is_hub)
-                                .expect("Root connect should always succeed");
-                            let device = match self
-                                .set_address(device, address)
-                                .await
-                            {
-                                Ok(device) => device,
-                                Err(e) => {
-                                    return DeviceEvent::EnumerationError(
-                                        0, 1, e,
-                                    );
-                                }
-                            };
-                            if is_hub {
-                                debug::println!("It's a hub");
-                                match self.new_hub(hub_state, device).await {
-                                    Ok(device) => {
-                                        return DeviceEvent::HubConnect(device)
-                                    }
-                                    Err(e) => {
-                                        return DeviceEvent::EnumerationError(
-                                            0, 1, e,
-                                        )
-                                    }
-                                };
-                            }
-                            DeviceEvent::Connect(device, info)
-                        } else {
-                            hub_state
-                                .topology
-                                .borrow_mut()
-                                .device_disconnect(0, 1);
-                            DeviceEvent::Disconnect(BitSet(0xFFFF_FFFF))
-                        }
-                    }
-                    InternalEvent::Packet(packet) => self
-                        .handle_hub_packet(hub_state, &packet, delay_ms)
-                        .await
-                        .unwrap_or_else(|e| {
-                            DeviceEvent::EnumerationError(0, 1, e)
-                        }),
-                }
-            }
-        })
+    /// USB address of the device
+    pub fn address(&self) -> u8 {
+        self.address
     }
 
-    /// Obtain a stream of hotplug/hot-unplug events
+    /// Where the device is attached, as (hub address, port number)
     ///
-    /// This stream is how the USB host stack informs your code that a
-    /// USB device is available for use. Once you have [created a
+    /// Hub address 0 means the root port.
+    pub fn parent(&self) -> (u8, u8) {
+        (self.hub, self.port)
+    }
+
+    /// Is the device a hub?
+    pub fn is_hub(&self) -> bool {
+        self.configuration_value == 0
+    }
+
+    fn usb_speed(&self) -> UsbSpeed {
+        match self.speed {
+            0 => UsbSpeed::Low1_5,
+            2 => UsbSpeed::High480,
+            _ => UsbSpeed::Full12,
+        }
+    }
+
+    fn unconfigured(&self) -> UnconfiguredDevice {
+        UnconfiguredDevice {
+            usb_address: self.address,
+            usb_speed: self.usb_speed(),
+            packet_size_ep0: self.packet_size_ep0,
+        }
+    }
+}
+
+/// The devices on a bus, kept so that they can be resumed after a soft reset
+///
+/// Re-enumerating a whole bus takes seconds (mostly in port resets
+/// and hub power-good delays), which is a long time to be without
+/// input devices after a watchdog reset. If, instead, the firmware
+/// keeps a `PersistedBus` in a region of RAM that isn't cleared on
+/// reset, and passes it to [`HubState::resume_from()`] on the way
+/// back up, [`UsbBus::device_events()`] checks that each recorded
+/// device still answers at its address, reports it as
+/// [`DeviceEvent::Resumed`] (or, for hubs,
+/// [`DeviceEvent::HubConnect`]) without resetting it, and only
+/// enumerates afresh the ports whose devices don't answer.
+///
+/// Where such a region is, and how to place a static in it, depends on
+/// the target (with `cortex-m-rt`, the `.uninit` section serves). Any
+/// bit pattern is a valid `PersistedBus`, so power-on garbage does no
+/// harm: it's detected by [`PersistedBus::is_valid()`], and ignored.
+///
+/// Keep it up to date by passing every [`DeviceEvent`] to
+/// [`PersistedBus::track()`], and calling [`PersistedBus::record()`]
+/// for each device once it's configured. This only helps if the
+/// devices are still powered, and haven't been reset, when the
+/// firmware comes back: that is, after a soft or watchdog reset that
+/// leaves VBUS on, not after a power cycle.
+#[cfg(feature = "hubs")]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone, PartialEq, Eq)]
+#[repr(C)]
+pub struct PersistedBus {
+    magic: u32,
+    checksum: u32,
+    devices: [PersistedDevice; MAX_PERSISTED_DEVICES],
+}
+
+#[cfg(feature = "hubs")]
+impl Default for PersistedBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "hubs")]
+impl PersistedBus {
+    /// A valid, empty, `PersistedBus`
+    pub const fn new() -> Self {
+        let devices = [PersistedDevice {
+            address: 0,
+            hub: 0,
+            port: 0,
+            speed: 0,
+            packet_size_ep0: 0,
+            configuration_value: 0,
+        }; MAX_PERSISTED_DEVICES];
+        Self {
+            magic: PERSISTED_MAGIC,
+            checksum: Self::checksum_of(&devices),
+            devices,
+        }
+    }
+
+    /// Fletcher-style, so that reordered or zeroed bytes are noticed
+    const fn checksum_of(
+        devices: &[PersistedDevice; MAX_PERSISTED_DEVICES],
+    ) -> u32 {
+        let mut a = 1u32;
+        let mut b = 0u32;
+        let mut i = 0;
+        while i < MAX_PERSISTED_DEVICES {
+            let d = &devices[i];
+            let bytes = [
+                d.address,
+                d.hub,
+                d.port,
+                d.speed,
+                d.packet_size_ep0,
+                d.configuration_value,
+            ];
+            let mut j = 0;
+            while j < bytes.len() {
+                a = (a + bytes[j] as u32) % 65521;
+                b = (b + a) % 65521;
+                j += 1;
+            }
+            i += 1;
+        }
+        (b << 16) | a
+    }
+
+    /// Was this written by [`PersistedBus::new()`] and its other methods?
+    ///
+    /// False for uninitialised memory (with overwhelming probability),
+    /// or if any of the contents have been corrupted.
+    pub fn is_valid(&self) -> bool {
+        self.magic == PERSISTED_MAGIC
+            && self.checksum == Self::checksum_of(&self.devices)
+    }
+
+    /// Forget all the devices, and make this valid if it wasn't
+    pub fn clear(&mut self) {
+        *self = Self::new();
+    }
+
+    /// The recorded devices (none, if this isn't valid)
+    pub fn devices(&self) -> impl Iterator<Item = &PersistedDevice> {
+        let valid = self.is_valid();
+        self.devices.iter().filter(move |d| valid && d.address != 0)
+    }
+
+    fn update(&mut self, f: impl FnOnce(&mut [PersistedDevice])) {
+        if !self.is_valid() {
+            self.clear();
+        }
+        f(&mut self.devices);
+        self.checksum = Self::checksum_of(&self.devices);
+    }
+
+    /// Record a device, once it's been configured
+    ///
+    /// `path` is where it was reported connected, and
+    /// `configuration_value` what was passed to
+    /// [`UsbBus::configure()`]. Any other device recorded at the same
+    /// address or the same place is forgotten.
+    ///
+    /// # Errors
+    ///
+    /// [`UsbError::TooManyDevices`] if [`MAX_PERSISTED_DEVICES`] are
+    /// already recorded. (The device is then just enumerated as usual
+    /// after a reset.)
+    pub fn record(
+        &mut self,
+        device: &UsbDevice,
+        path: &PortPath,
+        configuration_value: u8,
+    ) -> Result<(), UsbError> {
+        // Zero would mean a hub; no device is configured with it anyway
+        self.insert(PersistedDevice::new(
+            device,
+            path,
+            configuration_value.max(1),
+        ))
+    }
+
+    fn insert(&mut self, entry: PersistedDevice) -> Result<(), UsbError> {
+        self.forget_where(|d| {
+            d.address == entry.address || d.parent() == entry.parent()
+        });
+        let mut result = Err(UsbError::TooManyDevices);
+        self.update(|devices| {
+            if let Some(slot) = devices.iter_mut().find(|d| d.address == 0) {
+                *slot = entry;
+                result = Ok(());
+            }
+        });
+        result
+    }
+
+    /// Forget the devices at these addresses
+    pub fn forget(&mut self, addresses: BitSet) {
+        self.forget_where(|d| {
+            d.address < 128 && addresses.contains(d.address)
+        });
+    }
+
+    fn forget_where(&mut self, f: impl Fn(&PersistedDevice) -> bool) {
+        self.update(|devices| {
+            for d in devices.iter_mut() {
+                if d.address != 0 && f(d) {
+                    *d = PersistedDevice::default();
+                }
+            }
+        });
+    }
+
+    /// Keep the record in step with what [`UsbBus::device_events()`] reports
+    ///
+    /// Hubs are recorded when connected; other devices must be
+    /// recorded with [`PersistedBus::record()`] once configured.
+    /// Devices which go away, or whose address or port is given to a
+    /// new device, are forgotten.
+    pub fn track(&mut self, event: &DeviceEvent) {
+        match event {
+            DeviceEvent::HubConnect(device, path) => {
+                let _ = self.insert(PersistedDevice::new(device, path, 0));
+            }
+            DeviceEvent::Connect(device, _, path) => {
+                let parent = (path.hub_address(), path.port());
+                self.forget_where(|d| {
+                    d.address == device.usb_address || d.parent() == parent
+                });
+            }
+            DeviceEvent::Offered(address, _) => {
+                self.forget_where(|d| d.address == *address);
+            }
+            DeviceEvent::Rejected(path, _)
+            | DeviceEvent::EnumerationError(path, _) => {
+                let parent = (path.hub_address(), path.port());
+                self.forget_where(|d| d.parent() == parent);
+            }
+            DeviceEvent::Disconnect(devices)
+            | DeviceEvent::Unresponsive(_, devices)
+            | DeviceEvent::PortError(_, _, devices)
+            | DeviceEvent::PortDisabled(_, devices) => {
+                self.forget(*devices);
+            }
+            _ => (),
+        }
+    }
+}
+
+/// How many interrupt endpoints can be bound, see [`HubState::bind_interrupt()`]
+#[cfg(feature = "hubs")]
+pub const MAX_INTERRUPT_BINDINGS: usize = 8;
+
+/// An interrupt endpoint to be read automatically, on all devices of a kind
+///
+/// Passed to [`HubState::bind_interrupt()`]. Whenever a device for
+/// which `matcher` returns true is connected, and then configured
+/// with [`UsbBus::configure()`], a pipe is opened for the endpoint,
+/// and packets arriving on it are reported by
+/// [`UsbBus::device_events()`] as [`DeviceEvent::Interrupt`]. When
+/// the device is disconnected, the pipe is closed again; if it's
+/// re-enumerated (perhaps at a different address), a new pipe is
+/// opened once it's configured again, without the driver needing to
+/// notice.
+#[cfg(feature = "hubs")]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone)]
+pub struct InterruptBinding {
+    /// Which devices have this endpoint
+    pub matcher: fn(&DeviceInfo) -> bool,
+    /// Endpoint number (1-15)
+    pub endpoint: u8,
+    /// Maximum packet size of the endpoint (from its descriptor)
+    pub max_packet_size: u16,
+    /// Polling interval, in milliseconds
+    pub interval_ms: u8,
+}
+
+/// Identifies an [`InterruptBinding`], as returned by
+/// [`HubState::bind_interrupt()`]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct BindingId(u8);
+
+/// An open pipe for an interrupt endpoint bound in a `HubState`
+#[cfg(feature = "hubs")]
+struct BoundPipe<P> {
+    binding: BindingId,
+    usb_address: u8,
+    pipe: P,
+}
+
+/// Encapsulating the bus-wide USB hub state machine
+///
+/// This mostly exists to be passed-in to [`UsbBus::device_events()`]; it
+/// keeps hub-management data out of `struct UsbBus` for users who don't
+/// need hub support.
+///
+/// The two const parameters size its tables at compile time, so that
+/// systems which know what they'll be connected to needn't spend RAM
+/// on capability they don't use:
+///
+///  - `HUBS`: how many hubs (not counting the root port) can be in
+///    use at once, each needing a pipe for its interrupt endpoint. At
+///    most 15 can ever be used, as hubs are given USB addresses 1-15.
+///    Further hubs fail to enumerate, with [`UsbError::TooManyDevices`].
+///  - `PIPES`: how many interrupt pipes can be open at once for
+///    endpoints bound with [`HubState::bind_interrupt()`]. Further
+///    pipes are reported as [`DeviceEvent::InterruptError`], with
+///    [`UsbError::AllPipesInUse`].
+///
+/// The defaults (15 and 8) allow for anything the USB topology does.
+/// A keyboard-only host, for instance, might use
+/// `HubState::<HC, 0, 1>::sized()`; one which supports a single hub,
+/// `HubState::<HC, 1, 4>::sized()`.
+#[cfg(feature = "hubs")]
+pub struct HubState<
+    HC: HostController,
+    const HUBS: usize = 15,
+    const PIPES: usize = 8,
+> {
+    topology: RefCell<Topology>,
+    pipes: RefCell<[Option<HC::InterruptPipe>; HUBS]>,
+    /// Bus-wide lock: only one newly-reset device may be at address zero
+    enumeration: Pool,
+    /// Hub ports (indexed by hub address) still awaiting investigation
+    pending: Cell<[u16; 16]>,
+    /// Ports (indexed by hub address, 0 for root) awaiting re-enumeration
+    retries: Cell<[u16; 16]>,
+    /// Failed enumeration attempts so far, by hub address and port
+    attempts: RefCell<[[u8; 16]; 16]>,
+    /// Speed of the device on the root port, if one is present
+    root_speed: Cell<Option<UsbSpeed>>,
+    retry_policy: RetryPolicy,
+    error_rate_policy: ErrorRatePolicy,
+    /// Devices already warned about via `DeviceEvent::ErrorRateWarning`
+    warned: Cell<BitSet>,
+    liveness_policy: LivenessPolicy,
+    /// Consecutive failed keep-alive checks, by device address
+    liveness_failures: RefCell<[u8; 128]>,
+    port_error_policy: PortErrorPolicy,
+    /// Ports disabled by errors so far, by hub address and port
+    port_errors: RefCell<[[u8; 16]; 16]>,
+    bindings: [Option<InterruptBinding>; MAX_INTERRUPT_BINDINGS],
+    /// Bindings (as a bitmap) matched by each device but not yet opened
+    unopened: RefCell<[u8; 128]>,
+    bound_pipes: RefCell<[Option<BoundPipe<HC::InterruptPipe>>; PIPES]>,
+    /// Devices from a [`PersistedBus`] not yet checked and resumed
+    resume: RefCell<[PersistedDevice; MAX_PERSISTED_DEVICES]>,
+}
+
+#[cfg(feature = "hubs")]
+impl<HC: HostController> Default for HubState<HC> {
+    fn default() -> Self {
+        Self::with_retry_policy(RetryPolicy::default())
+    }
+}
+
+#[cfg(feature = "hubs")]
+impl<HC: HostController> HubState<HC> {
+    /// Create a `HubState` which retries failed enumerations as specified
+    ///
+    /// `HubState::default()` uses [`RetryPolicy::default()`]. For
+    /// tables of other sizes, see [`HubState::sized_with_retry_policy()`].
+    pub fn with_retry_policy(retry_policy: RetryPolicy) -> Self {
+        Self::sized_with_retry_policy(retry_policy)
+    }
+}
+
+#[cfg(feature = "hubs")]
+impl<HC: HostController, const HUBS: usize, const PIPES: usize>
+    HubState<HC, HUBS, PIPES>
+{
+    /// Create a `HubState` with tables of the sizes given by its type
+    ///
+    /// Failed enumerations are retried according to
+    /// [`RetryPolicy::default()`].
+    pub fn sized() -> Self {
+        Self::sized_with_retry_policy(RetryPolicy::default())
+    }
+
+    /// Create a `HubState` with tables of the sizes given by its
+    /// type, which retries failed enumerations as specified
+    pub fn sized_with_retry_policy(retry_policy: RetryPolicy) -> Self {
+        Self {
+            topology: Default::default(),
+            pipes: RefCell::new(core::array::from_fn(|_| None)),
+            enumeration: Pool::new(1),
+            pending: Default::default(),
+            retries: Default::default(),
+            attempts: Default::default(),
+            root_speed: Cell::new(None),
+            retry_policy,
+            error_rate_policy: ErrorRatePolicy::default(),
+            warned: Cell::new(BitSet::new()),
+            liveness_policy: LivenessPolicy::default(),
+            liveness_failures: RefCell::new([0; 128]),
+            port_error_policy: PortErrorPolicy::default(),
+            port_errors: Default::default(),
+            bindings: [None; MAX_INTERRUPT_BINDINGS],
+            unopened: RefCell::new([0; 128]),
+            bound_pipes: RefCell::new(core::array::from_fn(|_| None)),
+            resume: Default::default(),
+        }
+    }
+
+    /// Change when [`DeviceEvent::ErrorRateWarning`] is raised
+    ///
+    /// By default, [`ErrorRatePolicy::default()`] is used.
+    pub fn set_error_rate_policy(&mut self, policy: ErrorRatePolicy) {
+        self.error_rate_policy = policy;
+    }
+
+    /// Change whether, and how often, idle devices are checked
+    ///
+    /// By default, [`LivenessPolicy::default()`] is used, which makes
+    /// no checks.
+    pub fn set_liveness_policy(&mut self, policy: LivenessPolicy) {
+        self.liveness_policy = policy;
+    }
+
+    /// Change what happens when a hub disables a port after an error
+    ///
+    /// By default, [`PortErrorPolicy::default()`] is used.
+    pub fn set_port_error_policy(&mut self, policy: PortErrorPolicy) {
+        self.port_error_policy = policy;
+    }
+
+    /// Have an interrupt endpoint read automatically on matching devices
+    ///
+    /// See [`InterruptBinding`]. Packets from the endpoint are
+    /// reported as [`DeviceEvent::Interrupt`], tagged with the
+    /// returned `BindingId` so that they can be routed to the right
+    /// driver.
+    ///
+    /// Bindings only take effect for devices connected after they're
+    /// made, and only when using [`UsbBus::device_events()`].
+    ///
+    /// # Errors
+    ///
+    /// Returns [`UsbError::AllPipesInUse`] if there are already
+    /// [`MAX_INTERRUPT_BINDINGS`] bindings.
+    pub fn bind_interrupt(
+        &mut self,
+        binding: InterruptBinding,
+    ) -> Result<BindingId, UsbError> {
+        let (index, slot) = self
+            .bindings
+            .iter_mut()
+            .enumerate()
+            .find(|(_, b)| b.is_none())
+            .ok_or(UsbError::AllPipesInUse)?;
+        *slot = Some(binding);
+        Ok(BindingId(index as u8))
+    }
+
+    /// Resume the devices recorded before a soft reset, instead of
+    /// enumerating them
+    ///
+    /// See [`PersistedBus`]. Call this before starting
+    /// [`UsbBus::device_events()`]. If `persisted` isn't valid, or is
+    /// empty, the bus is enumerated as usual.
+    ///
+    /// Once the root port reports a device, each recorded device is
+    /// sent a GET_STATUS request at its old address. A device which
+    /// answers is put back into the topology without being reset:
+    /// hubs have their interrupt pipes re-opened, and are reported as
+    /// [`DeviceEvent::HubConnect`]; other devices are configured
+    /// again, which also resets their endpoints' data toggles, and are
+    /// reported as [`DeviceEvent::Resumed`]. A device which doesn't
+    /// answer has its port enumerated afresh, as do any ports on
+    /// resumed hubs which have no recorded device -- or, if it's the
+    /// device on the root port, the whole bus is.
+    pub fn resume_from(&mut self, persisted: &PersistedBus) {
+        let mut resume = self.resume.borrow_mut();
+        *resume = Default::default();
+        for (slot, device) in resume.iter_mut().zip(persisted.devices()) {
+            *slot = *device;
+        }
+    }
+
+    fn is_resuming(&self) -> bool {
+        self.resume.borrow().iter().any(|d| d.address != 0)
+    }
+
+    fn resumes_root(&self) -> bool {
+        self.resume
+            .borrow()
+            .iter()
+            .any(|d| d.address != 0 && d.parent() == (0, 1))
+    }
+
+    fn abandon_resume(&self) {
+        *self.resume.borrow_mut() = Default::default();
+    }
+
+    /// Remove and return the next device to resume, if any
+    ///
+    /// That's one on the root port, or on a hub that's already been
+    /// resumed. Devices on hubs that weren't (because they didn't
+    /// answer) are dropped: their hubs are being enumerated afresh.
+    fn next_resumable(&self) -> Option<PersistedDevice> {
+        let topology = self.topology.borrow();
+        let mut resume = self.resume.borrow_mut();
+        let next = resume.iter_mut().find(|d| {
+            d.address != 0 && (d.hub == 0 || topology.is_present(d.hub))
+        });
+        if let Some(d) = next {
+            return Some(core::mem::take(d));
+        }
+        *resume = Default::default();
+        None
+    }
+
+    /// Return a snapshot of the current physical bus layout
+    ///
+    /// This snapshot includes a representation of all the hubs and
+    /// devices currently detected, and how they are linked together.
+    ///
+    /// This is useful for logging/debugging.
+    pub fn topology(&self) -> Topology {
+        self.topology.borrow().clone()
+    }
+
+    fn try_add(
+        &self,
+        hc: &HC,
+        address: u8,
+        endpoint: u8,
+        max_packet_size: u16,
+        interval_ms: u8,
+    ) -> Result<(), UsbError> {
+        for p in self.pipes.borrow_mut().iter_mut() {
+            if p.is_none() {
+                *p = Some(hc.try_alloc_interrupt_pipe(
+                    address,
+                    endpoint,
+                    max_packet_size,
+                    interval_ms,
+                )?);
+                // Errors on a previous hub at this address don't count
+                if let Some(errors) =
+                    self.port_errors.borrow_mut().get_mut(address as usize)
+                {
+                    *errors = [0; 16];
+                }
+                return Ok(());
+            }
+        }
+        Err(UsbError::TooManyDevices)
+    }
+
+    /// Add some ports (given as a bitmap) to a hub's list of pending ports
+    ///
+    /// Hubs always have addresses below 16, so any other address is
+    /// refused with `UsbError::ProtocolError`.
+    fn queue_ports(
+        &self,
+        hub_address: u8,
+        port_bitmap: u16,
+    ) -> Result<(), UsbError> {
+        let mut pending = self.pending.get();
+        let Some(ports) = pending.get_mut(hub_address as usize) else {
+            debug_assert!(false, "status change from non-hub {hub_address}");
+            return Err(UsbError::ProtocolError);
+        };
+        *ports |= port_bitmap;
+        self.pending.set(pending);
+        Ok(())
+    }
+
+    /// The physical location of a device (or hub)
+    fn device_path(&self, address: u8) -> PortPath {
+        let topology = self.topology.borrow();
+        topology
+            .parent(address)
+            .map_or(PortPath::root(), |(hub, port)| {
+                topology.port_path(hub, port)
+            })
+    }
+
+    /// Remove and return the next pending hub port, if any
+    ///
+    /// Returns (hub address, port number).
+    fn next_pending_port(&self) -> Option<(u8, u8)> {
+        let mut pending = self.pending.get();
+        let (hub, ports) = pending
+            .iter_mut()
+            .enumerate()
+            .find(|(_, ports)| **ports != 0)?;
+        let port = ports.trailing_zeros() as u8;
+        *ports &= !(1 << port);
+        let hub = hub as u8;
+        self.pending.set(pending);
+        Some((hub, port))
+    }
+
+    fn has_pending_ports(&self) -> bool {
+        self.pending.get().iter().any(|ports| *ports != 0)
+            || self.retries.get().iter().any(|ports| *ports != 0)
+    }
+
+    /// Remove and return the next port awaiting re-enumeration, if any
+    ///
+    /// Returns (hub address, port number); hub address 0 is the root port.
+    fn next_retry_port(&self) -> Option<(u8, u8)> {
+        let mut retries = self.retries.get();
+        let (hub, ports) = retries
+            .iter_mut()
+            .enumerate()
+            .find(|(_, ports)| **ports != 0)?;
+        let port = ports.trailing_zeros() as u8;
+        *ports &= !(1 << port);
+        let hub = hub as u8;
+        self.retries.set(retries);
+        Some((hub, port))
+    }
+
+    /// Record a failed enumeration, and decide whether to try again
+    fn enumeration_failed(
+        &self,
+        hub: u8,
+        port: u8,
+        e: UsbError,
+    ) -> DeviceEvent {
+        // Any address allocated to the device is no longer in use
+        let path = {
+            let mut topology = self.topology.borrow_mut();
+            topology.device_disconnect(hub, port);
+            topology.port_path(hub, port)
+        };
+
+        let attempt = {
+            let mut attempts = self.attempts.borrow_mut();
+            let Some(attempt) = attempts
+                .get_mut(hub as usize)
+                .and_then(|a| a.get_mut(port as usize))
+            else {
+                return DeviceEvent::EnumerationError(path, e);
+            };
+            *attempt = attempt.saturating_add(1);
+            *attempt
+        };
+
+        if attempt < self.retry_policy.attempts {
+            let mut retries = self.retries.get();
+            retries[hub as usize] |= 1 << port;
+            self.retries.set(retries);
+            DeviceEvent::EnumerationRetry(path, attempt, e)
+        } else {
+            self.forget_attempts(hub, port);
+            DeviceEvent::EnumerationError(path, e)
+        }
+    }
+
+    /// Record a port disabled by its hub, and decide whether to re-enable it
+    fn port_failed(&self, hub: u8, port: u8) -> DeviceEvent {
+        let (devices, path) = {
+            let mut topology = self.topology.borrow_mut();
+            (
+                topology.device_disconnect(hub, port),
+                topology.port_path(hub, port),
+            )
+        };
+        // Any enumeration retry would re-enable it too soon
+        self.forget_attempts(hub, port);
+
+        let errors = {
+            let mut port_errors = self.port_errors.borrow_mut();
+            let Some(errors) = port_errors
+                .get_mut(hub as usize)
+                .and_then(|e| e.get_mut(port as usize))
+            else {
+                return DeviceEvent::PortDisabled(path, devices);
+            };
+            *errors = errors.saturating_add(1);
+            *errors
+        };
+
+        if errors <= self.port_error_policy.reenable_attempts {
+            let mut retries = self.retries.get();
+            retries[hub as usize] |= 1 << port;
+            self.retries.set(retries);
+            DeviceEvent::PortError(path, errors, devices)
+        } else {
+            DeviceEvent::PortDisabled(path, devices)
+        }
+    }
+
+    /// How long to wait before re-enumerating a port
+    ///
+    /// That's longer, and increasingly so, if it's being re-enabled
+    /// after errors.
+    fn retry_delay_ms(&self, hub: u8, port: u8) -> usize {
+        let errors = self
+            .port_errors
+            .borrow()
+            .get(hub as usize)
+            .and_then(|e| e.get(port as usize))
+            .copied()
+            .unwrap_or(0);
+        if errors == 0 {
+            self.retry_policy.retry_delay_ms
+        } else {
+            self.port_error_policy.backoff_ms(errors)
+        }
+    }
+
+    /// Clear any record of errors on this port (its device is gone)
+    fn forget_port_errors(&self, hub: u8, port: u8) {
+        if let Some(errors) = self
+            .port_errors
+            .borrow_mut()
+            .get_mut(hub as usize)
+            .and_then(|e| e.get_mut(port as usize))
+        {
+            *errors = 0;
+        }
+    }
+
+    /// Find a connected device with too high an error rate, if any
+    ///
+    /// Each device is only reported once (until it's disconnected).
+    fn next_error_rate_warning(
+        &self,
+        hc: &HC,
+    ) -> Option<(u8, TransferStatistics)> {
+        let topology = self.topology.borrow();
+        let mut warned = self.warned.get();
+        let mut result = None;
+        for address in 1..128 {
+            if !topology.is_present(address) {
+                warned.clear(address);
+            } else if result.is_none() && !warned.contains(address) {
+                let statistics = hc.statistics(address);
+                if self.error_rate_policy.is_exceeded_by(&statistics) {
+                    warned.set(address);
+                    result = Some((address, statistics));
+                }
+            }
+        }
+        self.warned.set(warned);
+        result
+    }
+
+    /// Keep the bound interrupt pipes in step with connects and disconnects
+    ///
+    /// A newly-connected device notes which bindings it matches, ready
+    /// for when it's configured; disconnected devices lose their pipes.
+    fn track_bindings<const D: usize>(
+        &self,
+        bus: &UsbBus<HC, D>,
+        event: &DeviceEvent,
+    ) {
+        match event {
+            DeviceEvent::Connect(
+                UnconfiguredDevice { usb_address, .. },
+                info,
+                _,
+            )
+            | DeviceEvent::Offered(usb_address, info) => {
+                self.forget_bindings(bus, *usb_address);
+                self.note_bindings(*usb_address, info);
+            }
+            DeviceEvent::Resumed(device, info, _) => {
+                // Already configured, so bindings open straight away
+                self.note_bindings(device.address(), info);
+            }
+            DeviceEvent::Disconnect(devices)
+            | DeviceEvent::Unresponsive(_, devices)
+            | DeviceEvent::PortError(_, _, devices)
+            | DeviceEvent::PortDisabled(_, devices) => {
+                for address in devices.iter() {
+                    self.forget_bindings(bus, address);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// Note which bindings a device matches, ready for when it's configured
+    fn note_bindings(&self, address: u8, info: &DeviceInfo) {
+        let mut matched = 0u8;
+        for (i, binding) in self.bindings.iter().enumerate() {
+            if binding.is_some_and(|b| (b.matcher)(info)) {
+                matched |= 1 << i;
+            }
+        }
+        if let Some(unopened) =
+            self.unopened.borrow_mut().get_mut(address as usize)
+        {
+            *unopened = matched;
+        }
+    }
+
+    /// Close any bound pipes to a device, which has gone away
+    fn forget_bindings<const D: usize>(
+        &self,
+        bus: &UsbBus<HC, D>,
+        address: u8,
+    ) {
+        if let Some(unopened) =
+            self.unopened.borrow_mut().get_mut(address as usize)
+        {
+            *unopened = 0;
+        }
+        for slot in self.bound_pipes.borrow_mut().iter_mut() {
+            if slot.as_ref().is_some_and(|p| p.usb_address == address) {
+                *slot = None;
+            }
+        }
+        cell::modify(&bus.configured, |configured| configured.clear(address));
+    }
+
+    /// Open pipes for bindings matched by devices now configured
+    ///
+    /// Returns the first failure, if any; any further bindings are
+    /// left until next time.
+    fn open_bound_pipes<const D: usize>(
+        &self,
+        bus: &UsbBus<HC, D>,
+    ) -> Option<(BindingId, u8, UsbError)> {
+        let mut unopened = self.unopened.borrow_mut();
+        for address in bus.configured.get().iter() {
+            let Some(bitmap) = unopened.get_mut(address as usize) else {
+                continue;
+            };
+            while *bitmap != 0 {
+                let index = bitmap.trailing_zeros() as usize;
+                *bitmap &= !(1 << index);
+                let Some(binding) = self.bindings[index] else {
+                    continue;
+                };
+                let id = BindingId(index as u8);
+                if let Err(e) =
+                    self.open_bound_pipe(&bus.driver, id, address, &binding)
+                {
+                    return Some((id, address, e));
+                }
+            }
+        }
+        None
+    }
+
+    fn open_bound_pipe(
+        &self,
+        hc: &HC,
+        binding: BindingId,
+        usb_address: u8,
+        endpoint: &InterruptBinding,
+    ) -> Result<(), UsbError> {
+        let mut pipes = self.bound_pipes.borrow_mut();
+        let slot = pipes
+            .iter_mut()
+            .find(|p| p.is_none())
+            .ok_or(UsbError::AllPipesInUse)?;
+        *slot = Some(BoundPipe {
+            binding,
+            usb_address,
+            pipe: hc.try_alloc_interrupt_pipe(
+                usb_address,
+                endpoint.endpoint,
+                endpoint.max_packet_size,
+                endpoint.interval_ms,
+            )?,
+        });
+        Ok(())
+    }
+
+    /// Clear any record of failed enumerations on this port
+    fn forget_attempts(&self, hub: u8, port: u8) {
+        if let Some(attempt) = self
+            .attempts
+            .borrow_mut()
+            .get_mut(hub as usize)
+            .and_then(|a| a.get_mut(port as usize))
+        {
+            *attempt = 0;
+        }
+        let mut retries = self.retries.get();
+        if let Some(ports) = retries.get_mut(hub as usize) {
+            *ports &= !(1 << (port & 15));
+            self.retries.set(retries);
+        }
+    }
+}
+
+#[cfg(feature = "hubs")]
+enum InternalEvent {
+    Root(DeviceStatus),
+    Packet(InterruptPacket),
+    PendingPorts,
+    ErrorRate(u8, TransferStatistics),
+    KeepAlive,
+    Resume,
+    Bound(BindingId, InterruptPacket),
+    BindingError(BindingId, u8, UsbError),
+}
+
+#[cfg(feature = "hubs")]
+struct HubStateStream<
+    'a,
+    HC: HostController,
+    const HUBS: usize,
+    const PIPES: usize,
+    const D: usize,
+> {
+    state: &'a HubState<HC, HUBS, PIPES>,
+    bus: &'a UsbBus<HC, D>,
+}
+
+#[cfg(feature = "hubs")]
+impl<
+        HC: HostController,
+        const HUBS: usize,
+        const PIPES: usize,
+        const D: usize,
+    > Stream for HubStateStream<'_, HC, HUBS, PIPES, D>
+{
+    type Item = InternalEvent;
+
+    fn poll_next(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+    ) -> Poll<Option<Self::Item>> {
+        for pipe in self.state.pipes.borrow_mut().iter_mut().flatten() {
+            let poll = pipe.poll_next_unpin(cx);
+            if poll.is_ready() {
+                return poll.map(|p| p.map(InternalEvent::Packet));
+            }
+        }
+        if let Some((binding, address, e)) =
+            self.state.open_bound_pipes(self.bus)
+        {
+            return Poll::Ready(Some(InternalEvent::BindingError(
+                binding, address, e,
+            )));
+        }
+        for slot in self.state.bound_pipes.borrow_mut().iter_mut() {
+            if let Some(bound) = slot {
+                match bound.pipe.poll_next_unpin(cx) {
+                    Poll::Ready(Some(packet)) => {
+                        return Poll::Ready(Some(InternalEvent::Bound(
+                            bound.binding,
+                            packet,
+                        )));
+                    }
+                    Poll::Ready(None) => *slot = None,
+                    Poll::Pending => (),
+                }
+            }
+        }
+        if self.state.root_speed.get().is_some() && self.state.is_resuming() {
+            return Poll::Ready(Some(InternalEvent::Resume));
+        }
+        if self.state.has_pending_ports() {
+            // Ports left over from an earlier packet, because only one
+            // port at a time can be enumerated (or ports whose
+            // enumeration is to be retried)
+            return Poll::Ready(Some(InternalEvent::PendingPorts));
+        }
+        if let Some((address, statistics)) =
+            self.state.next_error_rate_warning(&self.bus.driver)
+        {
+            return Poll::Ready(Some(InternalEvent::ErrorRate(
+                address, statistics,
+            )));
+        }
+        self.bus.register_events_waker(cx.waker());
+        Poll::Pending
+    }
+}
+
+/// A USB host bus.
+///
+/// This object represents the (portable) concept of a host's view of
+/// a whole bus of USB devices. It is constructed from a
+/// [`HostController`] object which encapsulates the driver for
+/// specific USB-host-controller hardware.
+///
+/// Starting from a `UsbBus` object, you can obtain details of any USB
+/// devices attached to this host, and start communicating with them as
+/// needed.
+///
+/// Devices with multiple USB host controllers will require a `UsbBus`
+/// object for each of them.
+///
+/// The const parameter `DESCRIPTOR_BYTES` is the size of the largest
+/// set of configuration descriptors that can be read, which `UsbBus`
+/// keeps a copy of while the device is being configured (and,
+/// briefly, on the stack while reading it). Devices with larger sets
+/// fail to configure, with [`UsbError::Overflow`]. The default, 512,
+/// allows for almost anything; a keyboard-only host, whose devices'
+/// descriptors are typically less than 100 bytes, might use
+/// `UsbBus::<HC, 128>::sized(driver)`. It can't exceed 65535, the
+/// most that a USB control transfer can carry.
+///
+pub struct UsbBus<HC: HostController, const DESCRIPTOR_BYTES: usize = 512> {
+    driver: HC,
+    descriptor_cache: RefCell<DescriptorCache<DESCRIPTOR_BYTES>>,
+    /// Woken when a transfer fails, so that error rates get checked, or
+    /// when a device is configured, so that bound pipes get opened
+    #[cfg(feature = "hubs")]
+    events_waker: RefCell<Option<Waker>>,
+    /// Devices which have completed a transfer since the last keep-alive
+    #[cfg(feature = "hubs")]
+    active: Cell<BitSet>,
+    /// Devices configured by `configure()` since they were connected
+    #[cfg(feature = "hubs")]
+    configured: Cell<BitSet>,
+    device_filter: fn(&DeviceInfo) -> Admission,
+    control_retry_policy: ControlRetryPolicy,
+    /// Devices given [`Admission::Offer`], until configured or gone
+    offered: RefCell<[Option<Offer>; MAX_OFFERED]>,
+}
+
+/// Largest BOS descriptor set that can be read
+const BOS_BUFFER_SIZE: usize = 256;
+
+/// The configuration descriptors of the device most recently read
+///
+/// Between being given an address and being configured, a device's
+/// configuration descriptors are typically consulted several times (for
+/// driver matching, for [`UsbBus::get_basic_configuration()`], and by
+/// [`UsbBus::configure()`] itself); this avoids re-reading them each time.
+struct DescriptorCache<const N: usize> {
+    /// Zero if nothing is cached (zero is never a configured address)
+    usb_address: u8,
+    len: usize,
+    bytes: [u8; N],
+}
+
+impl<const N: usize> DescriptorCache<N> {
+    const fn new() -> Self {
+        Self {
+            usb_address: 0,
+            len: 0,
+            bytes: [0u8; N],
+        }
+    }
+
+    fn get(&self, usb_address: u8) -> Option<&[u8]> {
+        if usb_address != 0 && usb_address == self.usb_address {
+            Some(&self.bytes[0..self.len])
+        } else {
+            None
+        }
+    }
+
+    fn set(&mut self, usb_address: u8, bytes: &[u8]) {
+        self.usb_address = usb_address;
+        self.len = bytes.len();
+        self.bytes[0..bytes.len()].copy_from_slice(bytes);
+    }
+
+    fn invalidate(&mut self, usb_address: u8) {
+        if usb_address == self.usb_address {
+            self.usb_address = 0;
+            self.len = 0;
+        }
+    }
+}
+
+impl<HC: HostController> UsbBus<HC> {
+    /// Create a new USB host bus from a host-controller driver
+    ///
+    /// For a descriptor buffer of another size, see [`UsbBus::sized()`].
+    pub fn new(driver: HC) -> Self {
+        Self::sized(driver)
+    }
+}
+
+impl<HC: HostController, const DESCRIPTOR_BYTES: usize>
+    UsbBus<HC, DESCRIPTOR_BYTES>
+{
+    /// Create a new USB host bus from a host-controller driver, with
+    /// a descriptor buffer of the size given by its type
+    pub fn sized(driver: HC) -> Self {
+        const {
+            assert!(
+                DESCRIPTOR_BYTES <= u16::MAX as usize,
+                "UsbBus descriptor buffer larger than a control transfer"
+            )
+        };
+        Self {
+            driver,
+            descriptor_cache: RefCell::new(DescriptorCache::new()),
+            #[cfg(feature = "hubs")]
+            events_waker: RefCell::new(None),
+            #[cfg(feature = "hubs")]
+            active: Cell::new(BitSet::new()),
+            #[cfg(feature = "hubs")]
+            configured: Cell::new(BitSet::new()),
+            device_filter: accept_all,
+            control_retry_policy: ControlRetryPolicy::default(),
+            offered: RefCell::new([None; MAX_OFFERED]),
+        }
+    }
+
+    /// Stop using the bus, and return the host-controller driver
+    ///
+    /// For instance, so that the hardware can be handed over to a USB
+    /// device stack, see [`RoleManager`](crate::role::RoleManager).
+    pub fn into_host_controller(self) -> HC {
+        self.driver
+    }
+
+    /// Change how the first requests to a new device are retried
+    ///
+    /// By default, [`ControlRetryPolicy::default()`] is used.
+    pub fn set_control_retry_policy(&mut self, policy: ControlRetryPolicy) {
+        self.control_retry_policy = policy;
+    }
+
+    /// Decide which newly-connected devices are enumerated
+    ///
+    /// `filter` is called with the [`DeviceInfo`] of each
+    /// newly-connected device, before it's given an address, and
+    /// returns whether to enumerate it as usual or to refuse it. This
+    /// lets products which only ever expect particular peripherals
+    /// ignore anything else -- such as random flash drives -- rather
+    /// than exposing it to the rest of the system.
+    ///
+    /// Refused devices are reported as [`DeviceEvent::Rejected`].
+    /// By default, all devices are accepted.
+    pub fn set_device_filter(&mut self, filter: fn(&DeviceInfo) -> Admission) {
+        self.device_filter = filter;
+    }
+
+    /// Claim an offered device, so that no other driver can
+    ///
+    /// Returns `None` if there's no device at `usb_address` on offer
+    /// (see [`Admission::Offer`]), or if it's already been claimed.
+    pub fn claim_device(&self, usb_address: u8) -> Option<ClaimedDevice> {
+        let mut offered = self.offered.borrow_mut();
+        let offer = offered
+            .iter_mut()
+            .flatten()
+            .find(|o| o.usb_address == usb_address && !o.claimed)?;
+        offer.claimed = true;
+        Some(ClaimedDevice {
+            device: UnconfiguredDevice {
+                usb_address: offer.usb_address,
+                usb_speed: offer.usb_speed,
+                packet_size_ep0: offer.packet_size_ep0,
+            },
+            info: offer.info,
+        })
+    }
+
+    /// Give up a claimed device, so that another driver can claim it
+    pub fn release_device(&self, device: ClaimedDevice) {
+        let address = device.address();
+        for offer in self.offered.borrow_mut().iter_mut().flatten() {
+            if offer.usb_address == address {
+                offer.claimed = false;
+            }
+        }
+    }
+
+    /// The devices on offer which no driver has yet claimed
+    ///
+    /// Yields the address and basic information of each one; a
+    /// driver registered after the [`DeviceEvent::Offered`] event
+    /// can use this to find devices it should claim.
+    pub fn unclaimed_devices(&self) -> impl Iterator<Item = (u8, DeviceInfo)> {
+        let offered = *self.offered.borrow();
+        offered
+            .into_iter()
+            .flatten()
+            .filter(|o| !o.claimed)
+            .map(|o| (o.usb_address, o.info))
+    }
+
+    /// Put a newly-addressed device on offer, if there's room
+    fn offer(
+        &self,
+        device: UnconfiguredDevice,
+        info: DeviceInfo,
+        path: PortPath,
+    ) -> DeviceEvent {
+        let mut offered = self.offered.borrow_mut();
+        let address = device.usb_address;
+        let slot = offered
+            .iter()
+            .position(|o| o.is_some_and(|o| o.usb_address == address))
+            .or_else(|| offered.iter().position(Option::is_none));
+        let Some(slot) = slot else {
+            return DeviceEvent::Connect(device, info, path);
+        };
+        offered[slot] = Some(Offer {
+            usb_address: address,
+            usb_speed: device.usb_speed,
+            packet_size_ep0: device.packet_size_ep0,
+            info,
+            claimed: false,
+        });
+        DeviceEvent::Offered(address, info)
+    }
+
+    /// Withdraw the offers of devices which have gone away
+    fn forget_offers(&self, event: &DeviceEvent) {
+        let devices = match event {
+            DeviceEvent::Disconnect(devices) => devices,
+            DeviceEvent::Unresponsive(_, devices)
+            | DeviceEvent::PortError(_, _, devices)
+            | DeviceEvent::PortDisabled(_, devices) => devices,
+            _ => return,
+        };
+        for slot in self.offered.borrow_mut().iter_mut() {
+            if slot.is_some_and(|o| devices.contains(o.usb_address)) {
+                *slot = None;
+            }
+        }
+    }
+
+    /// Return the transfer statistics for the device at `usb_address`
+    ///
+    /// These are kept by the [`HostController`], and are useful for
+    /// distinguishing signal-quality problems (such as bad cables) from
+    /// software problems. Not all host controllers keep statistics;
+    /// those that don't always return empty statistics.
+    pub fn statistics(&self, usb_address: u8) -> TransferStatistics {
+        self.driver.statistics(usb_address)
+    }
+
+    #[cfg(feature = "hubs")]
+    fn register_events_waker(&self, waker: &Waker) {
+        let mut events_waker = self.events_waker.borrow_mut();
+        if !events_waker.as_ref().is_some_and(|w| w.will_wake(waker)) {
+            *events_waker = Some(waker.clone());
+        }
+    }
+
+    /// Wake the `device_events()` stream, if it's waiting
+    #[cfg(feature = "hubs")]
+    fn wake_device_events(&self) {
+        if let Some(waker) = self.events_waker.borrow_mut().take() {
+            waker.wake();
+        }
+    }
+
+    /// Note the result of a transfer
+    ///
+    /// A failed transfer might have pushed a device's error rate over
+    /// the limit, so the `device_events()` stream is woken to check. A
+    /// successful one shows that the device is alive, so it needn't be
+    /// sent a keep-alive request.
+    fn transfer_result(
+        &self,
+        usb_address: u8,
+        result: Result<usize, UsbError>,
+    ) -> Result<usize, UsbError> {
+        #[cfg(feature = "hubs")]
+        if result.is_err() {
+            self.wake_device_events();
+        } else if usb_address < 128 {
+            cell::modify(&self.active, |active| active.set(usb_address));
+        }
+        #[cfg(not(feature = "hubs"))]
+        let _ = usb_address;
+        result
+    }
+
+    /// Obtain a stream of hotplug/hot-unplug events
+    ///
+    /// This stream is how the USB host stack informs your code that a
+    /// USB device is available for use. Once you have [created a
+    /// `UsbBus` object](UsbBus::new()), you can call `device_events()` and
+    /// get a stream of [`DeviceEvent`] objects:
+    ///
+    /// ```no_run
+    /// # use cotton_usb_host::host_controller::HostController;
+    /// # use std::pin::{pin, Pin};
+    /// # use cotton_usb_host::usb_bus::{HubState, UsbBus, DeviceEvent};
+    /// # use futures::{future, Future, Stream, StreamExt};
+    /// # fn delay_ms(_ms: usize) -> impl Future<Output = ()> {
+    /// #  future::ready(())
+    /// # }
+    /// # async fn foo<D: HostController>(driver: D) -> () {
+    /// let hub_state = HubState::default();
+    /// let bus = UsbBus::new(driver);
+    /// let mut device_stream = pin!(bus.device_events(&hub_state, delay_ms));
+    /// loop {
+    ///     let event = device_stream.next().await;
+    ///     if let Some(DeviceEvent::Connect(device, info, _)) = event {
+    ///         // ... process the device ...
+    ///     }
+    /// }
+    /// # }
+    /// ```
+    ///
+    /// You need to supply a [`DelayProvider`], which, given a
+    /// parameter in milliseconds, returns a Future that waits for
+    /// that long before coming ready. Any function or closure of that
+    /// shape will do, or see the [`delay`](crate::delay) module for
+    /// ready-made implementations for Embassy and for RTIC2.
+    ///
+    /// When using this method, the cotton-usb-host crate itself takes
+    /// care of detecting and configuring hubs, and of detecting
+    /// devices downstream of hubs. At present, the hubs do themselves
+    /// appear as `DeviceEvent`s, but your code doesn't need to do
+    /// anything with them.
+    ///
+    /// Devices which fail to enumerate are reset and tried again, as
+    /// specified by the [`RetryPolicy`] in the `HubState`; each failed
+    /// attempt but the last is reported as a
+    /// [`DeviceEvent::EnumerationRetry`].
+    ///
+    /// If a [`LivenessPolicy`] has been set, idle devices are
+    /// periodically checked, and any which stop responding are
+    /// reported as [`DeviceEvent::Unresponsive`].
+    ///
+    /// Interrupt endpoints bound with [`HubState::bind_interrupt()`]
+    /// are opened on each matching device once it's configured, and
+    /// their packets reported as [`DeviceEvent::Interrupt`].
+    ///
+    /// If you know for a fact that your hardware setup does not
+    /// include any hubs (or if you wish to operate the hubs
+    /// yourself), you can use
+    /// [`device_events_no_hubs()`](`UsbBus::device_events_no_hubs()`)
+    /// instead of `device_events()` and get smaller, simpler code.
+    ///
+    #[cfg(feature = "hubs")]
+    pub fn device_events<
+        'a,
+        P: DelayProvider + 'static + Clone,
+        const HUBS: usize,
+        const PIPES: usize,
+    >(
+        &'a self,
+        hub_state: &'a HubState<HC, HUBS, PIPES>,
+        delay_in: P,
+    ) -> impl Stream<Item = DeviceEvent> + 'a {
+        let root_device = self.driver.device_detect();
+        let interval_ms = hub_state.liveness_policy.interval_ms;
+        let keep_alive = futures::stream::unfold(
+            delay_in.clone(),
+            move |delay| async move {
+                if interval_ms == 0 {
+                    futures::future::pending::<()>().await;
+                }
+                delay.delay_ms(interval_ms).await;
+                Some((InternalEvent::KeepAlive, delay))
+            },
+        );
+
+        futures::stream::select(
+            futures::stream::select(
+                root_device.map(InternalEvent::Root),
+                HubStateStream {
+                    state: hub_state,
+                    bus: self,
+                },
+            ),
+            keep_alive,
+        )
+        .then(move |ev| {
+            self.handle_internal_event(hub_state, ev, delay_in.clone())
+        })
+    }
+
+    /// Check the hubs once for work, without waiting for any
+    ///
+    /// This is the hub-handling part of
+    /// [`device_events()`](UsbBus::device_events()), exposed for
+    /// callers who run their own event loop (for instance, an RTOS
+    /// task that wakes on a timer) rather than awaiting a stream. If
+    /// any hub, resumption, bound interrupt endpoint, or queued port
+    /// needs attention, it is dealt with exactly as `device_events()`
+    /// would deal with it, and the resulting event returned. If
+    /// nothing is ready, or the work yields nothing to report, `None`
+    /// is returned straight away.
+    ///
+    /// ```no_run
+    /// # use cotton_usb_host::host_controller::HostController;
+    /// # use cotton_usb_host::usb_bus::{HubState, UsbBus, DeviceEvent};
+    /// # use futures::{future, Future};
+    /// # fn delay_ms(_ms: usize) -> impl Future<Output = ()> {
+    /// #  future::ready(())
+    /// # }
+    /// # async fn foo<D: HostController>(driver: D) -> () {
+    /// let hub_state = HubState::default();
+    /// let bus = UsbBus::new(driver);
+    /// loop {
+    ///     // ... wait for this loop's own wake-up source ...
+    ///     while let Some(event) =
+    ///         bus.poll_hubs_once(&hub_state, delay_ms).await
+    ///     {
+    ///         // ... process the event ...
+    ///     }
+    /// }
+    /// # }
+    /// ```
+    ///
+    /// Changes on the root port, and [`LivenessPolicy`] checks, are
+    /// not driven from here: those still come from
+    /// [`device_events()`](UsbBus::device_events()) or
+    /// [`device_events_no_hubs()`](UsbBus::device_events_no_hubs()).
+    /// Both this method and `device_events()` may be used on the same
+    /// `HubState`, though not concurrently.
+    ///
+    #[cfg(feature = "hubs")]
+    pub async fn poll_hubs_once<
+        P: DelayProvider + 'static + Clone,
+        const HUBS: usize,
+        const PIPES: usize,
+    >(
+        &self,
+        hub_state: &HubState<HC, HUBS, PIPES>,
+        delay: P,
+    ) -> Option<DeviceEvent> {
+        let mut stream = HubStateStream {
+            state: hub_state,
+            bus: self,
+        };
+        let ev =
+            core::future::poll_fn(|cx| match stream.poll_next_unpin(cx) {
+                Poll::Ready(ev) => Poll::Ready(ev),
+                Poll::Pending => Poll::Ready(None),
+            })
+            .await?;
+        let event = self.handle_internal_event(hub_state, ev, delay).await;
+        (event != DeviceEvent::None).then_some(event)
+    }
+
+    #[cfg(feature = "hubs")]
+    async fn handle_internal_event<
+        P: DelayProvider + 'static + Clone,
+        const HUBS: usize,
+        const PIPES: usize,
+    >(
+        &self,
+        hub_state: &HubState<HC, HUBS, PIPES>,
+        ev: InternalEvent,
+        delay: P,
+    ) -> DeviceEvent {
+        let event = match ev {
+            InternalEvent::Root(status) => {
+                if let DeviceStatus::Present(speed) = status {
+                    hub_state.root_speed.set(Some(speed));
+                    hub_state.forget_attempts(0, 1);
+                    if hub_state.resumes_root() {
+                        // Don't reset it; it's checked, and
+                        // resumed, instead
+                        DeviceEvent::None
+                    } else {
+                        hub_state.abandon_resume();
+                        self.enumerate_root(hub_state, speed, delay).await
+                    }
+                } else {
+                    hub_state.abandon_resume();
+                    hub_state.root_speed.set(None);
+                    hub_state.forget_attempts(0, 1);
+                    hub_state.topology.borrow_mut().device_disconnect(0, 1);
+                    DeviceEvent::Disconnect(BitSet(u128::MAX))
+                }
+            }
+            InternalEvent::Packet(packet) => self
+                .handle_hub_packet(hub_state, packet.view(), delay)
+                .await
+                .unwrap_or_else(|e| {
+                    let path = hub_state.device_path(packet.address);
+                    DeviceEvent::EnumerationError(path, e)
+                }),
+            InternalEvent::PendingPorts => {
+                self.handle_pending_ports(hub_state, delay).await
+            }
+            InternalEvent::ErrorRate(address, statistics) => {
+                DeviceEvent::ErrorRateWarning(address, statistics)
+            }
+            InternalEvent::KeepAlive => self.check_liveness(hub_state).await,
+            InternalEvent::Resume => self.resume_next(hub_state, delay).await,
+            InternalEvent::Bound(binding, packet) => {
+                DeviceEvent::Interrupt(binding, packet)
+            }
+            InternalEvent::BindingError(binding, address, e) => {
+                DeviceEvent::InterruptError(binding, address, e)
+            }
+        };
+        hub_state.track_bindings(self, &event);
+        self.forget_offers(&event);
+        event
+    }
+
+    /// Obtain a stream of hotplug/hot-unplug events
+    ///
+    /// This stream is how the USB host stack informs your code that a
+    /// USB device is available for use. Once you have [created a
     /// `UsbBus` object](UsbBus::new()), you can call `device_events()` and
     /// get a stream of [`DeviceEvent`] objects:
     ///
@@ -585,18 +2357,18 @@ impl<HC: HostController> UsbBus<HC> {
     /// let mut device_stream = pin!(bus.device_events_no_hubs(delay_ms));
     /// loop {
     ///     let event = device_stream.next().await;
-    ///     if let Some(DeviceEvent::Connect(device, info)) = event {
+    ///     if let Some(DeviceEvent::Connect(device, info, _)) = event {
     ///         // ... process the device ...
     ///     }
     /// }
     /// # }
     /// ```
     ///
-    /// You need to supply an implementation of the "delay" function which,
-    /// given a parameter in milliseconds, returns a Future that waits for
-    /// that long before coming ready. See the examples for how to implement
-    /// that (simple!) function for RTIC2 and for Embassy; other executors
-    /// will require their own implementations.
+    /// You need to supply a [`DelayProvider`], which, given a
+    /// parameter in milliseconds, returns a Future that waits for
+    /// that long before coming ready. Any function or closure of that
+    /// shape will do, or see the [`delay`](crate::delay) module for
+    /// ready-made implementations for Embassy and for RTIC2.
     ///
     /// When using this method, the cotton-usb-host crate deals only with
     /// a single USB device attached directly to the USB host controller,
@@ -607,34 +2379,53 @@ impl<HC: HostController> UsbBus<HC> {
     /// [`device_events()`](`UsbBus::device_events()`) instead
     /// of `device_events_no_hubs()`.
     ///
-    pub fn device_events_no_hubs<
-        D: Future<Output = ()>,
-        F: Fn(usize) -> D + 'static + Clone,
-    >(
+    pub fn device_events_no_hubs<P: DelayProvider + 'static + Clone>(
         &self,
-        delay_ms_in: F,
+        delay_in: P,
     ) -> impl Stream<Item = DeviceEvent> + '_ {
         let root_device = self.driver.device_detect();
         root_device.then(move |status| {
-            let delay_ms = delay_ms_in.clone();
+            let delay = delay_in.clone();
             async move {
                 if let DeviceStatus::Present(speed) = status {
                     self.driver.reset_root_port(true);
-                    delay_ms(50).await;
+                    delay.delay_ms(50).await;
                     self.driver.reset_root_port(false);
-                    delay_ms(10).await;
-                    match self.new_device(speed).await {
-                        Ok((device, info)) => match self
-                            .set_address(device, 1)
-                            .await
-                        {
-                            Ok(device) => DeviceEvent::Connect(device, info),
-                            Err(e) => DeviceEvent::EnumerationError(0, 1, e),
-                        },
-                        Err(e) => DeviceEvent::EnumerationError(0, 1, e),
+                    delay.delay_ms(10).await;
+                    let (device, info) =
+                        match self.new_device(speed, &delay).await {
+                            Ok((device, info)) => (device, info),
+                            Err(e) => {
+                                return DeviceEvent::EnumerationError(
+                                    PortPath::root(),
+                                    e,
+                                )
+                            }
+                        };
+                    let admission = (self.device_filter)(&info);
+                    if admission == Admission::RefuseAddress {
+                        return DeviceEvent::Rejected(PortPath::root(), info);
+                    }
+                    match self.set_address(device, 1).await {
+                        Ok(_) if admission == Admission::RefuseConfigure => {
+                            DeviceEvent::Rejected(PortPath::root(), info)
+                        }
+                        Ok(device) if admission == Admission::Offer => {
+                            self.offer(device, info, PortPath::root())
+                        }
+                        Ok(device) => DeviceEvent::Connect(
+                            device,
+                            info,
+                            PortPath::root(),
+                        ),
+                        Err(e) => {
+                            DeviceEvent::EnumerationError(PortPath::root(), e)
+                        }
                     }
                 } else {
-                    DeviceEvent::Disconnect(BitSet(0xFFFF_FFFF))
+                    let event = DeviceEvent::Disconnect(BitSet(u128::MAX));
+                    self.forget_offers(&event);
+                    event
                 }
             }
         })
@@ -664,7 +2455,26 @@ impl<HC: HostController> UsbBus<HC> {
             )
             .await?;
         let mut endpoints = SpecificConfiguration::new(configuration_value);
-        self.get_configuration(&device, &mut endpoints).await?;
+        let result = self.get_configuration(&device, &mut endpoints).await;
+        // Descriptors are only cached until the device is configured
+        self.descriptor_cache
+            .borrow_mut()
+            .invalidate(device.address());
+        result?;
+        for slot in self.offered.borrow_mut().iter_mut() {
+            if slot.is_some_and(|o| o.usb_address == device.usb_address) {
+                *slot = None;
+            }
+        }
+        #[cfg(feature = "hubs")]
+        if device.usb_address < 128 {
+            // Any interrupt endpoints bound in the HubState can now be
+            // opened
+            cell::modify(&self.configured, |configured| {
+                configured.set(device.usb_address)
+            });
+            self.wake_device_events();
+        }
         Ok(UsbDevice {
             usb_address: device.usb_address,
             usb_speed: device.usb_speed,
@@ -674,35 +2484,185 @@ impl<HC: HostController> UsbBus<HC> {
         })
     }
 
-    async fn new_device(
+    /// Reset a device, and give it back its address
+    ///
+    /// For drivers which need to recover a device from a state it
+    /// can't otherwise leave -- for instance, after uploading new
+    /// firmware to it, or after an unrecoverable protocol error. The
+    /// device's port (on its parent hub, as recorded in the
+    /// [`HubState`]'s topology, or the root port) is reset, just as
+    /// when the device was first connected, and the device is
+    /// re-enumerated at its existing USB address, so that the rest of
+    /// the bus is unaffected and no [`DeviceEvent`]s are raised.
+    ///
+    /// As with a newly-connected device, the result must be configured
+    /// with [`UsbBus::configure()`] before use; endpoints opened on the
+    /// device before the reset are no longer valid. Interrupt endpoints
+    /// bound with [`HubState::bind_interrupt()`] are re-opened once
+    /// it's configured again.
+    ///
+    /// Hubs can't be reset like this, as that would disconnect
+    /// everything downstream of them.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`UsbError::NoSuchDevice`] if the device is no longer
+    /// present, or is a hub. If the device doesn't come back after the
+    /// reset, the error is returned, and the port is treated like one
+    /// whose enumeration failed: [`UsbBus::device_events()`] tries
+    /// again as its [`RetryPolicy`] specifies, reporting the device
+    /// (perhaps at a different address) as a new connection if it
+    /// succeeds.
+    #[cfg(feature = "hubs")]
+    pub async fn reset_device<
+        P: DelayProvider,
+        const HUBS: usize,
+        const PIPES: usize,
+    >(
         &self,
-        speed: UsbSpeed,
-    ) -> Result<(UnaddressedDevice, DeviceInfo), UsbError> {
-        // Read prefix of device descriptor
-        let mut descriptors = [0u8; 18];
-        let sz = self
-            .driver
-            .control_transfer(
-                0,
-                8,
-                SetupPacket {
-                    bmRequestType: DEVICE_TO_HOST,
-                    bRequest: GET_DESCRIPTOR,
-                    wValue: ((DEVICE_DESCRIPTOR as u16) << 8),
-                    wIndex: 0,
-                    wLength: 8,
-                },
-                DataPhase::In(&mut descriptors),
-            )
-            .await?;
-        if sz < 8 {
-            debug::println!("control in {}/8", sz);
-            return Err(UsbError::ProtocolError);
+        hub_state: &HubState<HC, HUBS, PIPES>,
+        device: UsbDevice,
+        delay: P,
+    ) -> Result<UnconfiguredDevice, UsbError> {
+        let address = device.usb_address;
+        let (hub, port) = {
+            let topology = hub_state.topology.borrow();
+            let is_hub = topology.hub_power(address).is_some()
+                || (1..128).any(|d| {
+                    topology.parent(d).is_some_and(|(h, _)| h == address)
+                });
+            match topology.parent(address) {
+                Some(parent) if !is_hub => parent,
+                _ => return Err(UsbError::NoSuchDevice),
+            }
+        };
+
+        // Whatever was set up on the device is lost in the reset
+        hub_state.forget_bindings(self, address);
+        hub_state.forget_attempts(hub, port);
+        hub_state.liveness_failures.borrow_mut()[address as usize] = 0;
+        cell::modify(&hub_state.warned, |warned| warned.clear(address));
+
+        let enumerating = hub_state.enumeration.alloc().await;
+        let speed = hub_state.root_speed.get().unwrap_or(device.usb_speed);
+        match self
+            .reset_and_readdress(hub, port, address, speed, &delay)
+            .await
+        {
+            Ok((device, info)) => {
+                drop(enumerating);
+                hub_state.note_bindings(address, &info);
+                Ok(device)
+            }
+            Err(e) => {
+                drop(enumerating);
+                hub_state.enumeration_failed(hub, port, e);
+                self.wake_device_events();
+                Err(e)
+            }
         }
+    }
 
-        let packet_size_ep0 = descriptors[7];
+    /// Reset a hub port (or the root port), and re-enumerate its device
+    ///
+    /// `speed` is only used for the root port; a hub reports the speed
+    /// of the device on each of its ports.
+    #[cfg(feature = "hubs")]
+    async fn reset_and_readdress<P: DelayProvider>(
+        &self,
+        hub: u8,
+        port: u8,
+        address: u8,
+        speed: UsbSpeed,
+        delay: &P,
+    ) -> Result<(UnconfiguredDevice, DeviceInfo), UsbError> {
+        let speed = if hub == 0 {
+            self.driver.reset_root_port(true);
+            delay.delay_ms(50).await;
+            self.driver.reset_root_port(false);
+            delay.delay_ms(10).await;
+            speed
+        } else {
+            self.set_port_feature(hub, port, HubPortFeature::PortReset)
+                .await?;
+            delay.delay_ms(50).await;
+            let (state, _changes) =
+                self.get_hub_port_status(hub, port).await?;
+            if (state & 2) == 0 {
+                return Err(UsbError::NoSuchDevice);
+            }
+            port_speed(state)
+        };
+        let (device, info) = self.new_device(speed, delay).await?;
+        let device = self.set_address(device, address).await?;
+        Ok((device, info))
+    }
 
-        // Fetch rest of device descriptor
+    /// Reset and enumerate the device attached to the root port
+    #[cfg(feature = "hubs")]
+    async fn enumerate_root<
+        P: DelayProvider + 'static + Clone,
+        const HUBS: usize,
+        const PIPES: usize,
+    >(
+        &self,
+        hub_state: &HubState<HC, HUBS, PIPES>,
+        speed: UsbSpeed,
+        delay: P,
+    ) -> DeviceEvent {
+        let enumerating = hub_state.enumeration.alloc().await;
+        self.driver.reset_root_port(true);
+        delay.delay_ms(50).await;
+        self.driver.reset_root_port(false);
+        delay.delay_ms(10).await;
+        let (device, info) = match self.new_device(speed, &delay).await {
+            Ok((device, info)) => (device, info),
+            Err(e) => return hub_state.enumeration_failed(0, 1, e),
+        };
+        let admission = (self.device_filter)(&info);
+        if admission == Admission::RefuseAddress {
+            // Nothing else can be on the root port, so the device can
+            // safely be left at address zero
+            hub_state.forget_attempts(0, 1);
+            return DeviceEvent::Rejected(PortPath::root(), info);
+        }
+        let is_hub = info.class == HUB_CLASSCODE;
+        let address = hub_state
+            .topology
+            .borrow_mut()
+            .device_connect(0, 1, is_hub)
+            .expect("Root connect should always succeed");
+        let device = match self.set_address(device, address).await {
+            Ok(device) => device,
+            Err(e) => return hub_state.enumeration_failed(0, 1, e),
+        };
+        drop(enumerating);
+        hub_state.forget_attempts(0, 1);
+        if admission == Admission::RefuseConfigure {
+            return DeviceEvent::Rejected(PortPath::root(), info);
+        }
+        if admission == Admission::Offer {
+            return self.offer(device, info, PortPath::root());
+        }
+        if is_hub {
+            debug::println!("It's a hub");
+            return match self.new_hub(hub_state, device, delay).await {
+                Ok(device) => {
+                    DeviceEvent::HubConnect(device, PortPath::root())
+                }
+                Err(e) => DeviceEvent::EnumerationError(PortPath::root(), e),
+            };
+        }
+        DeviceEvent::Connect(device, info, PortPath::root())
+    }
+
+    /// Read (some of) the device descriptor of the device at address zero
+    async fn get_device_descriptor_at_zero(
+        &self,
+        packet_size_ep0: u8,
+        descriptors: &mut [u8],
+    ) -> Result<(), UsbError> {
+        let length = descriptors.len();
         let sz = self
             .driver
             .control_transfer(
@@ -713,30 +2673,79 @@ impl<HC: HostController> UsbBus<HC> {
                     bRequest: GET_DESCRIPTOR,
                     wValue: ((DEVICE_DESCRIPTOR as u16) << 8),
                     wIndex: 0,
-                    wLength: 18,
+                    wLength: length as u16,
                 },
-                DataPhase::In(&mut descriptors),
+                DataPhase::In(descriptors),
             )
             .await?;
-        if sz < 18 {
-            debug::println!("control in {}/18", sz);
+        if sz < length {
+            debug::println!("control in {}/{}", sz, length);
             return Err(UsbError::ProtocolError);
         }
+        Ok(())
+    }
+
+    /// As `get_device_descriptor_at_zero()`, but retrying on failure
+    ///
+    /// See [`ControlRetryPolicy`].
+    async fn get_device_descriptor_with_retry<P: DelayProvider>(
+        &self,
+        packet_size_ep0: u8,
+        descriptors: &mut [u8],
+        delay: &P,
+    ) -> Result<(), UsbError> {
+        let policy = self.control_retry_policy;
+        let mut attempt = 1;
+        loop {
+            match self
+                .get_device_descriptor_at_zero(packet_size_ep0, descriptors)
+                .await
+            {
+                Err(e) if policy.should_retry(e, attempt) => {
+                    debug::println!(
+                        "GET_DESCRIPTOR({}) attempt {}/{} failed, retrying",
+                        descriptors.len(),
+                        attempt,
+                        policy.attempts
+                    );
+                    attempt += 1;
+                    delay.delay_ms(policy.retry_delay_ms).await;
+                }
+                rc => return rc,
+            }
+        }
+    }
+
+    async fn new_device<P: DelayProvider>(
+        &self,
+        speed: UsbSpeed,
+        delay: &P,
+    ) -> Result<(UnaddressedDevice, DeviceInfo), UsbError> {
+        // Read prefix of device descriptor
+        let mut descriptors = [0u8; 18];
+        self.get_device_descriptor_with_retry(
+            8,
+            &mut descriptors[0..8],
+            delay,
+        )
+        .await?;
+
+        let packet_size_ep0 = descriptors[7];
 
-        let vid = u16::from_le_bytes([descriptors[8], descriptors[9]]);
-        let pid = u16::from_le_bytes([descriptors[10], descriptors[11]]);
+        // Fetch rest of device descriptor
+        self.get_device_descriptor_with_retry(
+            packet_size_ep0,
+            &mut descriptors,
+            delay,
+        )
+        .await?;
 
         Ok((
             UnaddressedDevice {
                 usb_speed: speed,
                 packet_size_ep0,
             },
-            DeviceInfo {
-                vid,
-                pid,
-                class: descriptors[4],
-                subclass: descriptors[5],
-            },
+            device_info(&descriptors),
         ))
     }
 
@@ -759,6 +2768,9 @@ impl<HC: HostController> UsbBus<HC> {
                 DataPhase::None,
             )
             .await?;
+        // Anything cached for this address belongs to some previous device
+        self.descriptor_cache.borrow_mut().invalidate(address);
+        self.driver.reset_statistics(address);
         Ok(UnconfiguredDevice {
             usb_address: address,
             usb_speed: device.usb_speed,
@@ -805,14 +2817,16 @@ impl<HC: HostController> UsbBus<HC> {
         setup: SetupPacket,
         data_phase: DataPhase<'_>,
     ) -> Result<usize, UsbError> {
-        self.driver
+        let result = self
+            .driver
             .control_transfer(
                 device.usb_address,
                 device.packet_size_ep0,
                 setup,
                 data_phase,
             )
-            .await
+            .await;
+        self.transfer_result(device.usb_address, result)
     }
 
     /// Clear a halt (stall) condition on an IN endpoint
@@ -830,7 +2844,7 @@ impl<HC: HostController> UsbBus<HC> {
                 SetupPacket {
                     bmRequestType: 2,
                     bRequest: CLEAR_FEATURE,
-                    wValue: 0, // EP_HALT
+                    wValue: ENDPOINT_HALT,
                     wIndex: (ep.endpoint | 0x80) as u16,
                     wLength: 0,
                 },
@@ -841,6 +2855,59 @@ impl<HC: HostController> UsbBus<HC> {
         Ok(())
     }
 
+    /// Read a device's status (USB 2.0 section 9.4.5)
+    ///
+    /// This says whether the device is currently self-powered, and
+    /// whether it's allowed to wake the host from suspend (see
+    /// [`UsbBus::set_remote_wakeup()`]).
+    pub async fn get_device_status(
+        &self,
+        device: &UsbDevice,
+    ) -> Result<DeviceStatusFlags, UsbError> {
+        let mut data = [0u8; 2];
+        self.control_transfer(
+            device,
+            SetupPacket {
+                bmRequestType: DEVICE_TO_HOST,
+                bRequest: GET_STATUS,
+                wValue: 0,
+                wIndex: 0,
+                wLength: 2,
+            },
+            DataPhase::In(&mut data),
+        )
+        .await?;
+        Ok(DeviceStatusFlags(u16::from_le_bytes(data)))
+    }
+
+    /// Allow, or forbid, a device to wake the host from suspend
+    ///
+    /// Sends SET_FEATURE or CLEAR_FEATURE(DEVICE_REMOTE_WAKEUP), see
+    /// USB 2.0 sections 9.4.9 and 9.4.1. A device which should be able
+    /// to wake the system must have this enabled before it's
+    /// suspended. Devices which don't support remote wakeup (see
+    /// [`ConfigurationDescriptor::supports_remote_wakeup()`]) stall
+    /// the request, reported as [`UsbError::Stall`].
+    pub async fn set_remote_wakeup(
+        &self,
+        device: &UsbDevice,
+        enable: bool,
+    ) -> Result<(), UsbError> {
+        self.control_transfer(
+            device,
+            SetupPacket {
+                bmRequestType: HOST_TO_DEVICE,
+                bRequest: if enable { SET_FEATURE } else { CLEAR_FEATURE },
+                wValue: DEVICE_REMOTE_WAKEUP,
+                wIndex: 0,
+                wLength: 0,
+            },
+            DataPhase::None,
+        )
+        .await?;
+        Ok(())
+    }
+
     /// Perform a bulk IN transfer
     ///
     /// # Parameters
@@ -858,14 +2925,16 @@ impl<HC: HostController> UsbBus<HC> {
         data: &'a mut [u8],
         transfer_type: TransferType,
     ) -> impl Future<Output = Result<usize, UsbError>> + 'a {
-        self.driver.bulk_in_transfer(
-            ep.usb_address,
-            ep.endpoint,
-            64, // @TODO max packet size
-            data,
-            transfer_type,
-            &ep.data_toggle,
-        )
+        self.driver
+            .bulk_in_transfer(
+                ep.usb_address,
+                ep.endpoint,
+                64, // @TODO max packet size
+                data,
+                transfer_type,
+                &ep.data_toggle,
+            )
+            .map(|result| self.transfer_result(ep.usb_address, result))
     }
 
     /// Perform a bulk OUT transfer
@@ -886,14 +2955,16 @@ impl<HC: HostController> UsbBus<HC> {
         data: &'a [u8],
         transfer_type: TransferType,
     ) -> impl Future<Output = Result<usize, UsbError>> + 'a {
-        self.driver.bulk_out_transfer(
-            ep.usb_address,
-            ep.endpoint,
-            64, // @TODO max packet size
-            data,
-            transfer_type,
-            &ep.data_toggle,
-        )
+        self.driver
+            .bulk_out_transfer(
+                ep.usb_address,
+                ep.endpoint,
+                64, // @TODO max packet size
+                data,
+                transfer_type,
+                &ep.data_toggle,
+            )
+            .map(|result| self.transfer_result(ep.usb_address, result))
     }
 
     /// Open an interrupt endpoint for reading
@@ -927,6 +2998,13 @@ impl<HC: HostController> UsbBus<HC> {
     /// which driver to use for a device (if it's not obvious from the simpler
     /// [`UsbBus::get_basic_configuration()`] call).
     ///
+    /// The descriptors are read from the device only once, and are
+    /// then cached (until the device is configured) so that several
+    /// drivers can inspect them without further bus traffic.
+    ///
+    /// Returns `UsbError::Overflow` if the descriptors don't fit in
+    /// this `UsbBus`'s descriptor buffer (see [`UsbBus`]).
+    ///
     /// # Parameters
     ///  - device: The device to read from
     ///  - visitor: An implementation of [`DescriptorVisitor`] that receives
@@ -936,10 +3014,44 @@ impl<HC: HostController> UsbBus<HC> {
         device: &UnconfiguredDevice,
         visitor: &mut impl DescriptorVisitor,
     ) -> Result<(), UsbError> {
-        // TODO: descriptor suites >64 byte (Ella!)
-        let mut buf = [0u8; 64];
+        if let Some(bytes) =
+            self.descriptor_cache.borrow().get(device.address())
+        {
+            crate::wire::parse_descriptors(bytes, visitor);
+            return Ok(());
+        }
+
+        // Read the configuration descriptor on its own first, to find
+        // out how long the whole set is (USB 2.0 table 9-10)
+        let mut header = [0u8; 9];
         let sz = self
-            .driver
+            .read_configuration_descriptors(device, &mut header)
+            .await?;
+        let total_length = u16::from_le_bytes([header[2], header[3]]) as usize;
+        if sz < 4 || total_length < header.len() {
+            return Err(UsbError::ProtocolError);
+        }
+        if total_length > DESCRIPTOR_BYTES {
+            return Err(UsbError::Overflow);
+        }
+
+        let mut buf = [0u8; DESCRIPTOR_BYTES];
+        let sz = self
+            .read_configuration_descriptors(device, &mut buf[0..total_length])
+            .await?;
+        self.descriptor_cache
+            .borrow_mut()
+            .set(device.address(), &buf[0..sz]);
+        crate::wire::parse_descriptors(&buf[0..sz], visitor);
+        Ok(())
+    }
+
+    async fn read_configuration_descriptors(
+        &self,
+        device: &UnconfiguredDevice,
+        buf: &mut [u8],
+    ) -> Result<usize, UsbError> {
+        self.driver
             .control_transfer(
                 device.address(),
                 device.packet_size_ep0,
@@ -948,13 +3060,11 @@ impl<HC: HostController> UsbBus<HC> {
                     bRequest: GET_DESCRIPTOR,
                     wValue: ((CONFIGURATION_DESCRIPTOR as u16) << 8),
                     wIndex: 0,
-                    wLength: 64,
+                    wLength: buf.len() as u16,
                 },
-                DataPhase::In(&mut buf),
+                DataPhase::In(buf),
             )
-            .await?;
-        crate::wire::parse_descriptors(&buf[0..sz], visitor);
-        Ok(())
+            .await
     }
 
     /// Obtain simplified version of USB configuration descriptors
@@ -975,21 +3085,105 @@ impl<HC: HostController> UsbBus<HC> {
         }
     }
 
-    async fn new_hub(
+    /// Obtain a device's configuration descriptors as an owned tree
+    ///
+    /// As [`UsbBus::get_configuration()`], but instead of making
+    /// callbacks, builds a [`ConfigurationTree`] of configurations,
+    /// interfaces, alternate settings, and endpoints, with any
+    /// class-specific descriptors attached -- so that (for instance)
+    /// the functions of a composite device can be picked out without
+    /// writing a [`DescriptorVisitor`] by hand.
+    ///
+    /// Returns `UsbError::ProtocolError` if the device reports no
+    /// configuration at all.
+    #[cfg(feature = "alloc")]
+    pub async fn get_configuration_tree(
+        &self,
+        device: &UnconfiguredDevice,
+    ) -> Result<ConfigurationTree, UsbError> {
+        let mut tree = ConfigurationTree::default();
+        self.get_configuration(device, &mut tree).await?;
+        if tree.configurations.is_empty() {
+            Err(UsbError::ProtocolError)
+        } else {
+            Ok(tree)
+        }
+    }
+
+    /// Read a device's BOS descriptor and its device capabilities
+    ///
+    /// The BOS descriptor is how a device advertises features such as
+    /// Link Power Management or SuperSpeed capability. Only devices
+    /// claiming USB 2.01 or later are required to have one; older
+    /// devices usually respond with a stall, which is reported as
+    /// `Ok(false)` (with no callbacks made to `visitor`). Otherwise
+    /// `Ok(true)` is returned after the capabilities have been passed
+    /// to `visitor` (for which [`BosCapabilities`] will often do).
+    ///
+    /// # Parameters
+    ///  - device: The device to read from
+    ///  - visitor: An implementation of [`CapabilityVisitor`] that
+    ///    receives callbacks with the capability descriptors
+    pub async fn get_bos_descriptor(
+        &self,
+        device: &UnconfiguredDevice,
+        visitor: &mut impl CapabilityVisitor,
+    ) -> Result<bool, UsbError> {
+        let mut buf = [0u8; BOS_BUFFER_SIZE];
+        let rc = self
+            .driver
+            .control_transfer(
+                device.address(),
+                device.packet_size_ep0,
+                SetupPacket {
+                    bmRequestType: DEVICE_TO_HOST,
+                    bRequest: GET_DESCRIPTOR,
+                    wValue: ((BOS_DESCRIPTOR as u16) << 8),
+                    wIndex: 0,
+                    wLength: BOS_BUFFER_SIZE as u16,
+                },
+                DataPhase::In(&mut buf),
+            )
+            .await;
+        let sz = match rc {
+            Err(UsbError::Stall) => return Ok(false),
+            rc => rc?,
+        };
+        if crate::wire::parse_bos_descriptor(&buf[0..sz], visitor) {
+            Ok(true)
+        } else {
+            Err(UsbError::ProtocolError)
+        }
+    }
+
+    #[cfg(feature = "hubs")]
+    async fn new_hub<
+        P: DelayProvider + 'static + Clone,
+        const HUBS: usize,
+        const PIPES: usize,
+    >(
         &self,
-        hub_state: &HubState<HC>,
+        hub_state: &HubState<HC, HUBS, PIPES>,
         device: UnconfiguredDevice,
+        delay: P,
     ) -> Result<UsbDevice, UsbError> {
         debug::println!("gbc!");
         let bc = self.get_basic_configuration(&device).await?;
         debug::println!("cfg: {:?}", &bc);
+        let mut status = StatusChangeEndpoint::default();
+        self.get_configuration(&device, &mut status).await?;
+        // A hub without a status-change endpoint can't report anything
+        let Some(status) = status.0 else {
+            return Err(UsbError::ProtocolError);
+        };
         let device = self.configure(device, bc.configuration_value).await?;
+        // Poll at the hub's own pace, as given by its descriptor
         hub_state.try_add(
             &self.driver,
             device.address(),
             bc.in_endpoints.trailing_zeros() as u8,
-            device.packet_size_ep0,
-            9,
+            status.max_packet_size(),
+            status.interval_ms(),
         )?;
 
         let mut descriptors = [0u8; 64];
@@ -998,33 +3192,50 @@ impl<HC: HostController> UsbBus<HC> {
             .control_transfer(
                 device.address(),
                 device.packet_size_ep0,
-                SetupPacket {
-                    bmRequestType: DEVICE_TO_HOST | CLASS_REQUEST,
-                    bRequest: GET_DESCRIPTOR,
-                    wValue: (HUB_DESCRIPTOR as u16) << 8,
-                    wIndex: 0,
-                    wLength: 64,
-                },
+                SetupPacket::get_hub_descriptor(64),
                 DataPhase::In(&mut descriptors),
             )
             .await?;
 
-        if sz < core::mem::size_of::<HubDescriptor>() {
+        let size = core::mem::size_of::<HubDescriptor>();
+        if sz < size {
             return Err(UsbError::ProtocolError);
         }
 
-        let ports = descriptors[2];
-        debug::println!("{}-port hub", ports);
+        let hd: &HubDescriptor = bytemuck::from_bytes(&descriptors[0..size]);
+        let ports = hd.num_ports();
+        let power_good_ms = hd.power_on_to_power_good_ms();
+        debug::println!(
+            "{}-port hub, power good after {}ms, {}mA",
+            ports,
+            power_good_ms,
+            hd.controller_current_ma()
+        );
+        hub_state.topology.borrow_mut().set_hub_power(
+            device.address(),
+            HubPower::new(power_good_ms, hd.controller_current_ma()),
+        );
 
         // Ports are numbered from 1..=N (not 0..N)
         for port in 1..=ports {
-            self.set_port_feature(device.address(), port, PORT_POWER)
-                .await?;
+            self.set_port_feature(
+                device.address(),
+                port,
+                HubPortFeature::PortPower,
+            )
+            .await?;
+        }
+
+        // Don't let the ports be reset until their power is good (USB
+        // 2.0 section 11.23.2.1)
+        if power_good_ms > 0 {
+            delay.delay_ms(power_good_ms as usize).await;
         }
 
         Ok(device)
     }
 
+    #[cfg(feature = "hubs")]
     async fn get_hub_port_status(
         &self,
         hub_address: u8,
@@ -1035,15 +3246,7 @@ impl<HC: HostController> UsbBus<HC> {
             .control_transfer(
                 hub_address,
                 8,
-                SetupPacket {
-                    bmRequestType: DEVICE_TO_HOST
-                        | CLASS_REQUEST
-                        | RECIPIENT_OTHER,
-                    bRequest: GET_STATUS,
-                    wValue: 0,
-                    wIndex: port as u16,
-                    wLength: 4,
-                },
+                SetupPacket::get_port_status(port),
                 DataPhase::In(&mut data),
             )
             .await?;
@@ -1056,164 +3259,526 @@ impl<HC: HostController> UsbBus<HC> {
 
     /// Clear C_PORT_CONNECTION (or similar status-change bit); see
     /// USB 2.0 s11.24.2.7.2
+    #[cfg(feature = "hubs")]
     async fn clear_port_feature(
         &self,
         hub_address: u8,
         port: u8,
-        feature: u16,
+        feature: HubPortFeature,
     ) -> Result<(), UsbError> {
         self.driver
             .control_transfer(
                 hub_address,
                 8,
-                SetupPacket {
-                    bmRequestType: HOST_TO_DEVICE
-                        | CLASS_REQUEST
-                        | RECIPIENT_OTHER,
-                    bRequest: CLEAR_FEATURE,
-                    wValue: feature,
-                    wIndex: port as u16,
-                    wLength: 0,
-                },
+                SetupPacket::clear_port_feature(port, feature),
                 DataPhase::None,
             )
             .await?;
         Ok(())
     }
 
+    #[cfg(feature = "hubs")]
     async fn set_port_feature(
         &self,
         hub_address: u8,
         port: u8,
-        feature: u16,
+        feature: HubPortFeature,
     ) -> Result<(), UsbError> {
         self.driver
             .control_transfer(
                 hub_address,
                 8,
-                SetupPacket {
-                    bmRequestType: HOST_TO_DEVICE
-                        | CLASS_REQUEST
-                        | RECIPIENT_OTHER,
-                    bRequest: SET_FEATURE,
-                    wValue: feature,
-                    wIndex: port as u16,
-                    wLength: 0,
-                },
+                SetupPacket::set_port_feature(port, feature),
                 DataPhase::None,
             )
             .await?;
         Ok(())
     }
 
+    #[cfg(feature = "hubs")]
     async fn handle_hub_packet<
-        D: Future<Output = ()>,
-        F: Fn(usize) -> D + 'static + Clone,
+        P: DelayProvider + 'static + Clone,
+        const HUBS: usize,
+        const PIPES: usize,
     >(
         &self,
-        hub_state: &HubState<HC>,
-        packet: &InterruptPacket,
-        delay_ms: F,
+        hub_state: &HubState<HC, HUBS, PIPES>,
+        packet: InterruptPacketView<'_>,
+        delay: P,
     ) -> Result<DeviceEvent, UsbError> {
         // Hub state machine: each hub must have each port powered,
         // then reset. But only one hub port on the whole *bus* can be
         // in reset at any one time, because it becomes sensitive to
         // address zero. So there needs to be a bus-wide hub state
-        // machine.
+        // machine: ports needing attention are queued in the HubState,
+        // and dealt with one at a time.
+
+        if packet.is_empty() {
+            return Err(UsbError::ProtocolError);
+        }
 
         debug::println!(
             "Hub int {} [{}; {}]",
             packet.address,
-            packet.data[0],
-            packet.size
+            packet[0],
+            packet.len()
         );
 
-        if packet.size == 0 {
-            return Err(UsbError::ProtocolError);
+        let mut port_bitmap = packet[0] as u16;
+        if packet.len() > 1 {
+            port_bitmap |= (packet[1] as u16) << 8;
         }
+        hub_state.queue_ports(packet.address, port_bitmap)?;
+        Ok(self.handle_pending_ports(hub_state, delay).await)
+    }
 
-        let mut port_bitmap = packet.data[0] as u32;
-        if packet.size > 1 {
-            port_bitmap |= (packet.data[1] as u32) << 8;
+    /// Investigate queued hub ports until one of them yields an event
+    ///
+    /// Any ports still queued once an event has been found, are left
+    /// for next time. A port which can't be investigated yields an
+    /// `EnumerationError` event, giving its location.
+    #[cfg(feature = "hubs")]
+    async fn handle_pending_ports<
+        P: DelayProvider + 'static + Clone,
+        const HUBS: usize,
+        const PIPES: usize,
+    >(
+        &self,
+        hub_state: &HubState<HC, HUBS, PIPES>,
+        delay: P,
+    ) -> DeviceEvent {
+        let port_error = |hub, port, e| {
+            let path = hub_state.topology.borrow().port_path(hub, port);
+            DeviceEvent::EnumerationError(path, e)
+        };
+
+        if hub_state.is_resuming() {
+            // Resetting a port now might reset a device not yet resumed
+            return DeviceEvent::None;
         }
-        let port_bitmap = BitSet(port_bitmap);
-        for port in port_bitmap.iter() {
-            debug::println!("I'm told to investigate port {}", port);
-
-            let (state, changes) =
-                self.get_hub_port_status(packet.address, port).await?;
-            debug::println!(
-                "  port {} status3 {:x} {:x}",
-                port,
-                state,
-                changes
-            );
-
-            if changes != 0 {
-                let bit = changes.trailing_zeros(); // i.e., least_set_bit
-
-                if bit < 5 {
-                    // "+16" to clear the change version C_xx rather than the
-                    // feature itself, see USB 2.0 table 11-17
-                    self.clear_port_feature(
-                        packet.address,
-                        port,
-                        (bit + 16) as u16,
-                    )
-                    .await?;
-                }
-                if bit == 0 {
-                    // C_PORT_CONNECTION
-                    if (state & 1) == 0 {
-                        // now disconnected
-                        let mask = hub_state
-                            .topology
-                            .borrow_mut()
-                            .device_disconnect(packet.address, port);
-
-                        return Ok(DeviceEvent::Disconnect(mask));
+        while let Some((hub, port)) = hub_state.next_retry_port() {
+            delay.delay_ms(hub_state.retry_delay_ms(hub, port)).await;
+            let event = if hub == 0 {
+                match hub_state.root_speed.get() {
+                    Some(speed) => {
+                        self.enumerate_root(hub_state, speed, delay.clone())
+                            .await
                     }
+                    None => DeviceEvent::None, // unplugged meanwhile
+                }
+            } else {
+                self.enumerate_hub_port(hub_state, hub, port, delay.clone())
+                    .await
+                    .unwrap_or_else(|e| port_error(hub, port, e))
+            };
+            if event != DeviceEvent::None {
+                return event;
+            }
+        }
+        while let Some((hub, port)) = hub_state.next_pending_port() {
+            let event = self
+                .handle_hub_port(hub_state, hub, port, delay.clone())
+                .await
+                .unwrap_or_else(|e| port_error(hub, port, e));
+            if event != DeviceEvent::None {
+                return event;
+            }
+        }
+        DeviceEvent::None
+    }
 
-                    // now connected
-                    self.set_port_feature(packet.address, port, PORT_RESET)
-                        .await?;
+    #[cfg(feature = "hubs")]
+    async fn handle_hub_port<
+        P: DelayProvider + 'static + Clone,
+        const HUBS: usize,
+        const PIPES: usize,
+    >(
+        &self,
+        hub_state: &HubState<HC, HUBS, PIPES>,
+        hub: u8,
+        port: u8,
+        delay: P,
+    ) -> Result<DeviceEvent, UsbError> {
+        debug::println!("I'm told to investigate port {}", port);
 
-                    delay_ms(50).await;
+        let (state, changes) = self.get_hub_port_status(hub, port).await?;
+        debug::println!("  port {} status3 {:x} {:x}", port, state, changes);
 
-                    let (state, _changes) =
-                        self.get_hub_port_status(packet.address, port).await?;
+        if changes == 0 {
+            return Ok(DeviceEvent::None);
+        }
 
-                    if (state & 2) != 0 {
-                        // port is now ENABLED i.e. operational
+        let bit = changes.trailing_zeros(); // i.e., least_set_bit
 
-                        // USB 2.0 table 11-21
-                        let speed = match state & 0x600 {
-                            0 => UsbSpeed::Full12,
-                            0x400 => UsbSpeed::High480,
-                            _ => UsbSpeed::Low1_5,
-                        };
+        if let Some(feature) = HubPortFeature::change(bit) {
+            self.clear_port_feature(hub, port, feature).await?;
+        }
+        if bit == 1 && (state & 3) == 1 {
+            // C_PORT_ENABLE, and still connected: the hub has disabled
+            // the port because of an error, such as babble (USB 2.0
+            // section 11.24.2.7.2.2)
+            return Ok(hub_state.port_failed(hub, port));
+        }
+        if bit != 0 {
+            return Ok(DeviceEvent::None);
+        }
 
-                        let (device, info) = self.new_device(speed).await?;
-                        let is_hub = info.class == HUB_CLASSCODE;
-                        let address = hub_state
-                            .topology
-                            .borrow_mut()
-                            .device_connect(packet.address, port, is_hub)
-                            .ok_or(UsbError::TooManyDevices)?;
-                        let device = self.set_address(device, address).await?;
-                        if is_hub {
-                            debug::println!("It's a hub");
-                            return Ok(DeviceEvent::HubConnect(
-                                self.new_hub(hub_state, device).await?,
-                            ));
-                        }
+        // C_PORT_CONNECTION
+        hub_state.forget_attempts(hub, port);
+        hub_state.forget_port_errors(hub, port);
+        if (state & 1) == 0 {
+            // now disconnected
+            let mask =
+                hub_state.topology.borrow_mut().device_disconnect(hub, port);
+
+            return Ok(DeviceEvent::Disconnect(mask));
+        }
+
+        self.enumerate_hub_port(hub_state, hub, port, delay).await
+    }
 
-                        return Ok(DeviceEvent::Connect(device, info));
+    /// Send a keep-alive request to every idle device
+    ///
+    /// Devices which have completed a transfer since the last check
+    /// are known to be alive, and aren't disturbed. The first device
+    /// found to have failed too many checks in a row is disconnected
+    /// and reported; any others are dealt with at the next check.
+    #[cfg(feature = "hubs")]
+    async fn check_liveness<const HUBS: usize, const PIPES: usize>(
+        &self,
+        hub_state: &HubState<HC, HUBS, PIPES>,
+    ) -> DeviceEvent {
+        let active = self.active.replace(BitSet::new());
+        for address in 1..128 {
+            let present = hub_state.topology.borrow().is_present(address);
+            let alive = !present
+                || active.contains(address)
+                || self.get_status_by_address(address).await.is_ok();
+
+            let failures = {
+                let mut failures = hub_state.liveness_failures.borrow_mut();
+                let f = &mut failures[address as usize];
+                *f = if alive { 0 } else { f.saturating_add(1) };
+                *f
+            };
+
+            if failures >= hub_state.liveness_policy.max_failures.max(1) {
+                hub_state.liveness_failures.borrow_mut()[address as usize] = 0;
+                let mut topology = hub_state.topology.borrow_mut();
+                if let Some((hub, port)) = topology.parent(address) {
+                    let mask = topology.device_disconnect(hub, port);
+                    drop(topology);
+                    hub_state.forget_attempts(hub, port);
+                    return DeviceEvent::Unresponsive(address, mask);
+                }
+            }
+        }
+        DeviceEvent::None
+    }
+
+    /// Resume the next device recorded in a [`PersistedBus`], if any
+    ///
+    /// See [`HubState::resume_from()`].
+    #[cfg(feature = "hubs")]
+    async fn resume_next<
+        P: DelayProvider + 'static + Clone,
+        const HUBS: usize,
+        const PIPES: usize,
+    >(
+        &self,
+        hub_state: &HubState<HC, HUBS, PIPES>,
+        delay: P,
+    ) -> DeviceEvent {
+        let Some(persisted) = hub_state.next_resumable() else {
+            return DeviceEvent::None;
+        };
+        match self.resume_device(hub_state, &persisted).await {
+            Ok(event) => event,
+            Err(_) => {
+                let (hub, _) = persisted.parent();
+                if hub == 0 {
+                    // Without the device on the root port, nothing
+                    // else can be resumed either
+                    hub_state.abandon_resume();
+                    match hub_state.root_speed.get() {
+                        Some(speed) => {
+                            self.enumerate_root(hub_state, speed, delay).await
+                        }
+                        None => DeviceEvent::None,
                     }
+                } else {
+                    // Its port was queued for enumeration when its hub
+                    // was resumed
+                    DeviceEvent::None
                 }
             }
         }
-        Ok(DeviceEvent::None)
+    }
+
+    #[cfg(feature = "hubs")]
+    async fn resume_device<const HUBS: usize, const PIPES: usize>(
+        &self,
+        hub_state: &HubState<HC, HUBS, PIPES>,
+        persisted: &PersistedDevice,
+    ) -> Result<DeviceEvent, UsbError> {
+        let (hub, port) = persisted.parent();
+        let device = persisted.unconfigured();
+        self.get_status_by_address(device.address()).await?;
+        let event = if persisted.is_hub() {
+            let (device, ports) = self.resume_hub(hub_state, device).await?;
+            // Every port is enumerated afresh, once resuming is
+            // finished, unless a device is resumed on it first
+            let mut retries = hub_state.retries.get();
+            if let Some(r) = retries.get_mut(device.address() as usize) {
+                *r |= (((1u32 << ports) - 1) << 1) as u16;
+            }
+            hub_state.retries.set(retries);
+            DeviceEvent::HubConnect(device, PortPath::root())
+        } else {
+            let info = self.get_device_info(&device).await?;
+            let device = self
+                .configure(device, persisted.configuration_value)
+                .await?;
+            DeviceEvent::Resumed(device, info, PortPath::root())
+        };
+        let path = {
+            let mut topology = hub_state.topology.borrow_mut();
+            if !topology.device_restore(persisted.address, hub, port) {
+                return Err(UsbError::TooManyDevices);
+            }
+            topology.port_path(hub, port)
+        };
+        hub_state.forget_attempts(hub, port);
+        Ok(match event {
+            DeviceEvent::HubConnect(device, _) => {
+                DeviceEvent::HubConnect(device, path)
+            }
+            DeviceEvent::Resumed(device, info, _) => {
+                DeviceEvent::Resumed(device, info, path)
+            }
+            event => event,
+        })
+    }
+
+    /// Re-open a hub's interrupt pipe, without reconfiguring it
+    ///
+    /// Returns the hub, and how many ports it has.
+    #[cfg(feature = "hubs")]
+    async fn resume_hub<const HUBS: usize, const PIPES: usize>(
+        &self,
+        hub_state: &HubState<HC, HUBS, PIPES>,
+        device: UnconfiguredDevice,
+    ) -> Result<(UsbDevice, u8), UsbError> {
+        let bc = self.get_basic_configuration(&device).await?;
+        let mut status = StatusChangeEndpoint::default();
+        self.get_configuration(&device, &mut status).await?;
+        let Some(status) = status.0 else {
+            return Err(UsbError::ProtocolError);
+        };
+        self.descriptor_cache
+            .borrow_mut()
+            .invalidate(device.address());
+
+        let mut descriptors = [0u8; 64];
+        let sz = self
+            .driver
+            .control_transfer(
+                device.address(),
+                device.packet_size_ep0,
+                SetupPacket::get_hub_descriptor(64),
+                DataPhase::In(&mut descriptors),
+            )
+            .await?;
+        let size = core::mem::size_of::<HubDescriptor>();
+        if sz < size {
+            return Err(UsbError::ProtocolError);
+        }
+        let hd: &HubDescriptor = bytemuck::from_bytes(&descriptors[0..size]);
+
+        hub_state.try_add(
+            &self.driver,
+            device.address(),
+            bc.in_endpoints.trailing_zeros() as u8,
+            status.max_packet_size(),
+            status.interval_ms(),
+        )?;
+        hub_state.topology.borrow_mut().set_hub_power(
+            device.address(),
+            HubPower::new(
+                hd.power_on_to_power_good_ms(),
+                hd.controller_current_ma(),
+            ),
+        );
+        Ok((
+            UsbDevice {
+                usb_address: device.usb_address,
+                usb_speed: device.usb_speed,
+                packet_size_ep0: device.packet_size_ep0,
+                in_endpoints_bitmap: bc.in_endpoints,
+                out_endpoints_bitmap: bc.out_endpoints,
+            },
+            hd.num_ports().min(15),
+        ))
+    }
+
+    /// Read a device's device descriptor, at its address
+    #[cfg(feature = "hubs")]
+    async fn get_device_info(
+        &self,
+        device: &UnconfiguredDevice,
+    ) -> Result<DeviceInfo, UsbError> {
+        let mut descriptors = [0u8; 18];
+        let sz = self
+            .driver
+            .control_transfer(
+                device.address(),
+                device.packet_size_ep0,
+                SetupPacket {
+                    bmRequestType: DEVICE_TO_HOST,
+                    bRequest: GET_DESCRIPTOR,
+                    wValue: ((DEVICE_DESCRIPTOR as u16) << 8),
+                    wIndex: 0,
+                    wLength: descriptors.len() as u16,
+                },
+                DataPhase::In(&mut descriptors),
+            )
+            .await?;
+        if sz < descriptors.len() {
+            return Err(UsbError::ProtocolError);
+        }
+        Ok(device_info(&descriptors))
+    }
+
+    /// Issue a standard GET_STATUS request to a device; see USB 2.0 s9.4.5
+    #[cfg(feature = "hubs")]
+    async fn get_status_by_address(
+        &self,
+        address: u8,
+    ) -> Result<u16, UsbError> {
+        let mut data = [0u8; 2];
+        self.driver
+            .control_transfer(
+                address,
+                8,
+                SetupPacket {
+                    bmRequestType: DEVICE_TO_HOST,
+                    bRequest: GET_STATUS,
+                    wValue: 0,
+                    wIndex: 0,
+                    wLength: 2,
+                },
+                DataPhase::In(&mut data),
+            )
+            .await?;
+        Ok(u16::from_le_bytes(data))
+    }
+
+    /// Reset and enumerate the device attached to a hub port
+    #[cfg(feature = "hubs")]
+    async fn enumerate_hub_port<
+        P: DelayProvider + 'static + Clone,
+        const HUBS: usize,
+        const PIPES: usize,
+    >(
+        &self,
+        hub_state: &HubState<HC, HUBS, PIPES>,
+        hub: u8,
+        port: u8,
+        delay: P,
+    ) -> Result<DeviceEvent, UsbError> {
+        // From here until the device has an address, no other port on
+        // the bus may be reset
+        let enumerating = hub_state.enumeration.alloc().await;
+
+        self.set_port_feature(hub, port, HubPortFeature::PortReset)
+            .await?;
+
+        delay.delay_ms(50).await;
+
+        let (state, _changes) = self.get_hub_port_status(hub, port).await?;
+
+        if (state & 2) == 0 {
+            return Ok(DeviceEvent::None);
+        }
+
+        // port is now ENABLED i.e. operational
+        let speed = port_speed(state);
+        let path = hub_state.topology.borrow().port_path(hub, port);
+
+        let (device, info) = match self.new_device(speed, &delay).await {
+            Ok((device, info)) => (device, info),
+            Err(e) => return Ok(hub_state.enumeration_failed(hub, port, e)),
+        };
+        let admission = (self.device_filter)(&info);
+        if admission == Admission::RefuseAddress {
+            // Disable the port, so that the device doesn't linger at
+            // address zero while other devices are enumerated
+            hub_state.forget_attempts(hub, port);
+            self.clear_port_feature(hub, port, HubPortFeature::PortEnable)
+                .await?;
+            return Ok(DeviceEvent::Rejected(path, info));
+        }
+        let is_hub = info.class == HUB_CLASSCODE;
+        let Some(address) = hub_state
+            .topology
+            .borrow_mut()
+            .device_connect(hub, port, is_hub)
+        else {
+            return Ok(hub_state.enumeration_failed(
+                hub,
+                port,
+                UsbError::TooManyDevices,
+            ));
+        };
+        let device = match self.set_address(device, address).await {
+            Ok(device) => device,
+            Err(e) => return Ok(hub_state.enumeration_failed(hub, port, e)),
+        };
+        drop(enumerating);
+        hub_state.forget_attempts(hub, port);
+
+        if admission == Admission::RefuseConfigure {
+            return Ok(DeviceEvent::Rejected(path, info));
+        }
+
+        if admission == Admission::Offer {
+            return Ok(self.offer(device, info, path));
+        }
+
+        if is_hub {
+            debug::println!("It's a hub");
+            return Ok(match self.new_hub(hub_state, device, delay).await {
+                Ok(device) => DeviceEvent::HubConnect(device, path),
+                Err(e) => hub_state.enumeration_failed(hub, port, e),
+            });
+        }
+
+        Ok(DeviceEvent::Connect(device, info, path))
+    }
+}
+
+/// The basic information in a device descriptor
+fn device_info(descriptors: &[u8; 18]) -> DeviceInfo {
+    DeviceInfo {
+        vid: u16::from_le_bytes([descriptors[8], descriptors[9]]),
+        pid: u16::from_le_bytes([descriptors[10], descriptors[11]]),
+        class: descriptors[4],
+        subclass: descriptors[5],
+        usb_version: u16::from_le_bytes([descriptors[2], descriptors[3]]),
+        device_release: u16::from_le_bytes([descriptors[12], descriptors[13]]),
+        serial_number_index: descriptors[16],
+    }
+}
+
+/// The speed of the device on a hub port, from its port status
+///
+/// See USB 2.0 table 11-21.
+#[cfg(feature = "hubs")]
+fn port_speed(state: u16) -> UsbSpeed {
+    match state & 0x600 {
+        0 => UsbSpeed::Full12,
+        0x400 => UsbSpeed::High480,
+        _ => UsbSpeed::Low1_5,
     }
 }
 
@@ -45,6 +45,7 @@ use crate::debug;
 /// in `wLength`.
 ///
 #[repr(C)]
+#[derive(Copy, Clone, PartialEq, Eq)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 #[cfg_attr(feature = "std", derive(Debug))]
 #[allow(non_snake_case)] // These names are from USB 2.0 table 9-2
@@ -63,10 +64,52 @@ pub struct SetupPacket {
     pub wLength: u16,
 }
 
+impl SetupPacket {
+    /// Decode a SETUP packet from its 8-byte wire format
+    ///
+    /// Multi-byte fields are little-endian (USB 2.0 section 8.1).
+    pub const fn from_bytes(bytes: &[u8; 8]) -> Self {
+        Self {
+            bmRequestType: bytes[0],
+            bRequest: bytes[1],
+            wValue: u16::from_le_bytes([bytes[2], bytes[3]]),
+            wIndex: u16::from_le_bytes([bytes[4], bytes[5]]),
+            wLength: u16::from_le_bytes([bytes[6], bytes[7]]),
+        }
+    }
+
+    /// Encode a SETUP packet in its 8-byte wire format
+    pub const fn to_bytes(&self) -> [u8; 8] {
+        let v = self.wValue.to_le_bytes();
+        let i = self.wIndex.to_le_bytes();
+        let l = self.wLength.to_le_bytes();
+        [
+            self.bmRequestType,
+            self.bRequest,
+            v[0],
+            v[1],
+            i[0],
+            i[1],
+            l[0],
+            l[1],
+        ]
+    }
+
+    /// The direction of the data phase (if any) of this request
+    pub const fn direction(&self) -> Direction {
+        if (self.bmRequestType & DEVICE_TO_HOST) != 0 {
+            Direction::In
+        } else {
+            Direction::Out
+        }
+    }
+}
+
 /// A device descriptor, see USB 2.0 section 9.6.1
 #[repr(C)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 #[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone)]
 #[allow(non_snake_case)] // These names are from USB 2.0 table 9-8
 #[allow(missing_docs)]
 pub struct DeviceDescriptor {
@@ -87,6 +130,11 @@ pub struct DeviceDescriptor {
     pub bNumConfigurations: u8,
 }
 
+// SAFETY: all fields zeroable
+unsafe impl bytemuck::Zeroable for DeviceDescriptor {}
+// SAFETY: no padding, no disallowed bit patterns
+unsafe impl bytemuck::Pod for DeviceDescriptor {}
+
 /// A configuration descriptor, see USB 2.0 section 9.6.3
 #[repr(C)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
@@ -110,6 +158,13 @@ unsafe impl bytemuck::Zeroable for ConfigurationDescriptor {}
 // SAFETY: no padding, no disallowed bit patterns
 unsafe impl bytemuck::Pod for ConfigurationDescriptor {}
 
+impl ConfigurationDescriptor {
+    /// Does the device support remote wakeup in this configuration?
+    pub const fn supports_remote_wakeup(&self) -> bool {
+        (self.bmAttributes & 0x20) != 0
+    }
+}
+
 /// An interface descriptor, see USB 2.0 section 9.6.5
 #[repr(C)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
@@ -155,7 +210,28 @@ unsafe impl bytemuck::Zeroable for EndpointDescriptor {}
 // SAFETY: no padding, no disallowed bit patterns
 unsafe impl bytemuck::Pod for EndpointDescriptor {}
 
+impl EndpointDescriptor {
+    /// Largest packet the endpoint sends or receives, in bytes
+    pub const fn max_packet_size(&self) -> u16 {
+        u16::from_le_bytes(self.wMaxPacketSize) & 0x7FF
+    }
+
+    /// How often an interrupt endpoint should be polled, in milliseconds
+    ///
+    /// For full-speed and low-speed devices, `bInterval` counts 1ms
+    /// frames (USB 2.0 section 9.6.6). Zero isn't valid for interrupt
+    /// endpoints, and is treated as 1.
+    pub const fn interval_ms(&self) -> u8 {
+        if self.bInterval == 0 {
+            1
+        } else {
+            self.bInterval
+        }
+    }
+}
+
 /// A hub descriptor, see USB 2.0 section 11.23.2.1
+#[cfg(feature = "hubs")]
 #[repr(C)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 #[cfg_attr(feature = "std", derive(Debug))]
@@ -174,10 +250,116 @@ pub struct HubDescriptor {
 }
 
 // SAFETY: all fields zeroable
+#[cfg(feature = "hubs")]
 unsafe impl bytemuck::Zeroable for HubDescriptor {}
 // SAFETY: no padding, no disallowed bit patterns
+#[cfg(feature = "hubs")]
 unsafe impl bytemuck::Pod for HubDescriptor {}
 
+#[cfg(feature = "hubs")]
+impl HubDescriptor {
+    /// Number of downstream ports
+    pub const fn num_ports(&self) -> u8 {
+        self.bNbrPorts
+    }
+
+    /// Time from powering-on a port until its power is good, in ms
+    pub const fn power_on_to_power_good_ms(&self) -> u16 {
+        // bPwrOn2PwrGood is in units of 2ms
+        self.bPwrOn2PwrGood as u16 * 2
+    }
+
+    /// Maximum current drawn by the hub controller itself, in mA
+    pub const fn controller_current_ma(&self) -> u8 {
+        self.bHubContrCurrent
+    }
+}
+
+/// A BOS (Binary device Object Store) descriptor header, see USB 3.2
+/// section 9.6.2
+///
+/// Introduced by the USB 2.0 LPM ECN; devices with a `bcdUSB` of 0x0201
+/// or higher have one. It is followed by `bNumDeviceCaps` device
+/// capability descriptors.
+#[repr(C)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone)]
+#[allow(non_snake_case)] // These names are from USB 3.2 table 9-12
+#[allow(missing_docs)]
+pub struct BosDescriptor {
+    pub bLength: u8,
+    pub bDescriptorType: u8,
+    pub wTotalLength: [u8; 2],
+    pub bNumDeviceCaps: u8,
+}
+
+// SAFETY: all fields zeroable
+unsafe impl bytemuck::Zeroable for BosDescriptor {}
+// SAFETY: no padding, no disallowed bit patterns
+unsafe impl bytemuck::Pod for BosDescriptor {}
+
+/// A USB 2.0 Extension capability descriptor, see USB 3.2 section 9.6.2.1
+#[repr(C)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone)]
+#[allow(non_snake_case)] // These names are from USB 3.2 table 9-15
+#[allow(missing_docs)]
+pub struct Usb20ExtensionDescriptor {
+    pub bLength: u8,
+    pub bDescriptorType: u8,
+    pub bDevCapabilityType: u8,
+    pub bmAttributes: [u8; 4],
+}
+
+// SAFETY: all fields zeroable
+unsafe impl bytemuck::Zeroable for Usb20ExtensionDescriptor {}
+// SAFETY: no padding, no disallowed bit patterns
+unsafe impl bytemuck::Pod for Usb20ExtensionDescriptor {}
+
+impl Usb20ExtensionDescriptor {
+    /// Does the device support Link Power Management?
+    pub const fn supports_lpm(&self) -> bool {
+        (self.bmAttributes[0] & 2) != 0
+    }
+
+    /// Does the device support BESL (Best Effort Service Latency) LPM?
+    pub const fn supports_besl(&self) -> bool {
+        (self.bmAttributes[0] & 4) != 0
+    }
+}
+
+/// A SuperSpeed USB capability descriptor, see USB 3.2 section 9.6.2.2
+#[repr(C)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone)]
+#[allow(non_snake_case)] // These names are from USB 3.2 table 9-16
+#[allow(missing_docs)]
+pub struct SuperSpeedUsbDescriptor {
+    pub bLength: u8,
+    pub bDescriptorType: u8,
+    pub bDevCapabilityType: u8,
+    pub bmAttributes: u8,
+    pub wSpeedsSupported: [u8; 2],
+    pub bFunctionalitySupport: u8,
+    pub bU1DevExitLat: u8,
+    pub wU2DevExitLat: [u8; 2],
+}
+
+// SAFETY: all fields zeroable
+unsafe impl bytemuck::Zeroable for SuperSpeedUsbDescriptor {}
+// SAFETY: no padding, no disallowed bit patterns
+unsafe impl bytemuck::Pod for SuperSpeedUsbDescriptor {}
+
+impl SuperSpeedUsbDescriptor {
+    /// Bitmask of supported speeds: bit 0 low, 1 full, 2 high, 3 5Gbit/s
+    pub const fn speeds_supported(&self) -> u16 {
+        u16::from_le_bytes(self.wSpeedsSupported)
+    }
+}
+
 // For request_type (USB 2.0 table 9-2)
 
 /// Control transfer: device-to-host
@@ -230,6 +412,9 @@ pub const SET_DESCRIPTOR: u8 = 7;
 /// Set configuration (USB 2.0 section 9.4.7)
 pub const SET_CONFIGURATION: u8 = 9;
 
+/// Set interface, i.e. select an alternate setting (USB 2.0 section 9.4.10)
+pub const SET_INTERFACE: u8 = 11;
+
 // Descriptor types (USB 2.0 table 9-5)
 
 /// Device descriptor (USB 2.0 section 9.6.1)
@@ -247,9 +432,56 @@ pub const INTERFACE_DESCRIPTOR: u8 = 4;
 /// Endpoint descriptor (USB 2.0 section 9.6.6)
 pub const ENDPOINT_DESCRIPTOR: u8 = 5;
 
+/// Interface association descriptor (USB 2.0 ECN "Interface Association
+/// Descriptors")
+pub const INTERFACE_ASSOCIATION_DESCRIPTOR: u8 = 0x0B;
+
 /// Hub descriptor (USB 2.0 section 11.23.3.1 and table 11-13)
 pub const HUB_DESCRIPTOR: u8 = 0x29;
 
+/// Descriptor type for BOS descriptors, see USB 3.2 table 9-6
+pub const BOS_DESCRIPTOR: u8 = 0x0F;
+
+/// Descriptor type for device capability descriptors, see USB 3.2 table 9-6
+pub const DEVICE_CAPABILITY_DESCRIPTOR: u8 = 0x10;
+
+/// Device capability type of USB 2.0 Extension, see USB 3.2 table 9-14
+pub const USB_20_EXTENSION_CAPABILITY: u8 = 2;
+
+/// Device capability type of SuperSpeed USB, see USB 3.2 table 9-14
+pub const SUPERSPEED_USB_CAPABILITY: u8 = 3;
+
+// Standard feature selectors (USB 2.0 table 9-6)
+
+/// Halt an endpoint, using SET_FEATURE or CLEAR_FEATURE (USB 2.0
+/// section 9.4.5)
+pub const ENDPOINT_HALT: u16 = 0;
+
+/// Let a device wake the host from suspend, using SET_FEATURE or
+/// CLEAR_FEATURE (USB 2.0 section 9.4.5)
+pub const DEVICE_REMOTE_WAKEUP: u16 = 1;
+
+/// The status of a device, as returned by GET_STATUS (USB 2.0 figure 9-4)
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone, Default, PartialEq, Eq)]
+pub struct DeviceStatusFlags(
+    /// The status word, as sent by the device
+    pub u16,
+);
+
+impl DeviceStatusFlags {
+    /// Is the device currently self-powered (rather than bus-powered)?
+    pub const fn self_powered(&self) -> bool {
+        (self.0 & 1) != 0
+    }
+
+    /// Is the device currently allowed to wake the host from suspend?
+    pub const fn remote_wakeup(&self) -> bool {
+        (self.0 & 2) != 0
+    }
+}
+
 // Class codes (DeviceDescriptor.bDeviceClass)
 
 /// Class code for USB hubs (USB 2.0 section 11.23.1)
@@ -257,11 +489,137 @@ pub const HUB_CLASSCODE: u8 = 9;
 
 // Values for SET_FEATURE for hubs (USB 2.0 table 11-17)
 
+/// Disable a port, using CLEAR_FEATURE (USB 2.0 section 11.5.1.4)
+#[cfg(feature = "hubs")]
+pub const PORT_ENABLE: u16 = HubPortFeature::PortEnable as u16;
+
 /// Reset a port (USB 2.0 section 11.5.1.5)
-pub const PORT_RESET: u16 = 4;
+#[cfg(feature = "hubs")]
+pub const PORT_RESET: u16 = HubPortFeature::PortReset as u16;
 
 /// Power-on a port (USB 2.0 section 11.5.1.13)
-pub const PORT_POWER: u16 = 8;
+#[cfg(feature = "hubs")]
+pub const PORT_POWER: u16 = HubPortFeature::PortPower as u16;
+
+/// Hub port feature selector, see USB 2.0 table 11-17
+///
+/// Used as the `wValue` of hub class SET_FEATURE and CLEAR_FEATURE
+/// requests; see [`SetupPacket::set_port_feature()`] and
+/// [`SetupPacket::clear_port_feature()`]. The `C_PORT_*` selectors
+/// acknowledge the corresponding bits of wPortChange (USB 2.0 table
+/// 11-22) -- they can only be cleared, never set.
+#[cfg(feature = "hubs")]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone, PartialEq, Eq)]
+#[repr(u16)]
+#[allow(missing_docs)]
+pub enum HubPortFeature {
+    PortConnection = 0,
+    PortEnable = 1,
+    PortSuspend = 2,
+    PortOverCurrent = 3,
+    PortReset = 4,
+    PortPower = 8,
+    PortLowSpeed = 9,
+    CPortConnection = 16,
+    CPortEnable = 17,
+    CPortSuspend = 18,
+    CPortOverCurrent = 19,
+    CPortReset = 20,
+    PortTest = 21,
+    PortIndicator = 22,
+}
+
+#[cfg(feature = "hubs")]
+impl HubPortFeature {
+    /// The feature with this selector value, if there is one
+    pub const fn from_selector(selector: u16) -> Option<Self> {
+        Some(match selector {
+            0 => Self::PortConnection,
+            1 => Self::PortEnable,
+            2 => Self::PortSuspend,
+            3 => Self::PortOverCurrent,
+            4 => Self::PortReset,
+            8 => Self::PortPower,
+            9 => Self::PortLowSpeed,
+            16 => Self::CPortConnection,
+            17 => Self::CPortEnable,
+            18 => Self::CPortSuspend,
+            19 => Self::CPortOverCurrent,
+            20 => Self::CPortReset,
+            21 => Self::PortTest,
+            22 => Self::PortIndicator,
+            _ => return None,
+        })
+    }
+
+    /// The `C_PORT_*` feature which acknowledges bit `bit` of wPortChange
+    ///
+    /// Only bits 0-4 of wPortChange are defined (USB 2.0 table 11-22).
+    pub const fn change(bit: u32) -> Option<Self> {
+        if bit < 5 {
+            Self::from_selector(bit as u16 + 16)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(feature = "hubs")]
+impl SetupPacket {
+    /// A hub class SET_FEATURE request for a port (USB 2.0 s11.24.2.13)
+    ///
+    /// Ports are numbered from 1.
+    pub const fn set_port_feature(port: u8, feature: HubPortFeature) -> Self {
+        Self {
+            bmRequestType: HOST_TO_DEVICE | CLASS_REQUEST | RECIPIENT_OTHER,
+            bRequest: SET_FEATURE,
+            wValue: feature as u16,
+            wIndex: port as u16,
+            wLength: 0,
+        }
+    }
+
+    /// A hub class CLEAR_FEATURE request for a port (USB 2.0 s11.24.2.2)
+    pub const fn clear_port_feature(
+        port: u8,
+        feature: HubPortFeature,
+    ) -> Self {
+        Self {
+            bmRequestType: HOST_TO_DEVICE | CLASS_REQUEST | RECIPIENT_OTHER,
+            bRequest: CLEAR_FEATURE,
+            wValue: feature as u16,
+            wIndex: port as u16,
+            wLength: 0,
+        }
+    }
+
+    /// A hub class GET_STATUS request for a port (USB 2.0 s11.24.2.7)
+    ///
+    /// The 4-byte reply is wPortStatus followed by wPortChange.
+    pub const fn get_port_status(port: u8) -> Self {
+        Self {
+            bmRequestType: DEVICE_TO_HOST | CLASS_REQUEST | RECIPIENT_OTHER,
+            bRequest: GET_STATUS,
+            wValue: 0,
+            wIndex: port as u16,
+            wLength: 4,
+        }
+    }
+
+    /// A hub class GET_DESCRIPTOR request for the hub descriptor
+    /// (USB 2.0 s11.24.2.5)
+    pub const fn get_hub_descriptor(length: u16) -> Self {
+        Self {
+            bmRequestType: DEVICE_TO_HOST | CLASS_REQUEST,
+            bRequest: GET_DESCRIPTOR,
+            wValue: (HUB_DESCRIPTOR as u16) << 8,
+            wIndex: 0,
+            wLength: length,
+        }
+    }
+}
 
 /// Endpoint type, see USB 2.0 sections 9.3.6 and 5.3.1
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
@@ -329,6 +687,76 @@ impl DescriptorVisitor for ShowDescriptors {
     }
 }
 
+/// Callbacks from [`parse_bos_descriptor()`]
+///
+/// And hence from [`UsbBus::get_bos_descriptor()`](crate::usb_bus::UsbBus::get_bos_descriptor).
+pub trait CapabilityVisitor {
+    /// The BOS descriptor header has been reported
+    fn on_bos(&mut self, _b: &BosDescriptor) {}
+
+    /// A USB 2.0 Extension capability has been reported
+    fn on_usb20_extension(&mut self, _u: &Usb20ExtensionDescriptor) {}
+
+    /// A SuperSpeed USB capability has been reported
+    fn on_superspeed_usb(&mut self, _s: &SuperSpeedUsbDescriptor) {}
+
+    /// Some other device capability has been reported
+    fn on_other_capability(&mut self, _d: &[u8]) {}
+}
+
+/// Parse a BOS descriptor and its device capability descriptors
+///
+/// And make callbacks via the [`CapabilityVisitor`] for everything
+/// that's found. Returns `false` (and makes no callbacks) if `buf`
+/// doesn't start with a BOS descriptor.
+pub fn parse_bos_descriptor(
+    buf: &[u8],
+    v: &mut impl CapabilityVisitor,
+) -> bool {
+    let Some(header) = buf.get(0..5) else {
+        return false;
+    };
+    let Ok(bos) = bytemuck::try_from_bytes::<BosDescriptor>(header) else {
+        return false;
+    };
+    if bos.bDescriptorType != BOS_DESCRIPTOR || (bos.bLength as usize) < 5 {
+        return false;
+    }
+    v.on_bos(bos);
+
+    let total = (u16::from_le_bytes(bos.wTotalLength) as usize).min(buf.len());
+    let mut index = bos.bLength as usize;
+
+    while total >= index + 3 {
+        let dlen = buf[index] as usize;
+        let dtype = buf[index + 1];
+
+        if dlen < 3 || total < index + dlen {
+            break;
+        }
+
+        if dtype == DEVICE_CAPABILITY_DESCRIPTOR {
+            let d = &buf[index..index + dlen];
+            match buf[index + 2] {
+                USB_20_EXTENSION_CAPABILITY => {
+                    if let Ok(u) = bytemuck::try_from_bytes(d) {
+                        v.on_usb20_extension(u);
+                    }
+                }
+                SUPERSPEED_USB_CAPABILITY => {
+                    if let Ok(s) = bytemuck::try_from_bytes(d) {
+                        v.on_superspeed_usb(s);
+                    }
+                }
+                _ => v.on_other_capability(d),
+            }
+        }
+
+        index += dlen;
+    }
+    true
+}
+
 /// Parse a configuration-descriptor sequence
 ///
 /// And make callbacks via the [`DescriptorVisitor`] for everything
@@ -18,6 +18,12 @@
 #![warn(missing_docs)]
 #![warn(rustdoc::missing_crate_level_docs)]
 
+/// Checking the SPI connection to a W5500
+pub mod self_test;
+
+/// Handling received frames before they reach the IP stack
+pub mod tap;
+
 /// Using W5500 with smoltcp
 #[cfg(feature = "smoltcp")]
 pub mod smoltcp;
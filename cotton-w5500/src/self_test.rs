@@ -0,0 +1,331 @@
+use w5500::bus::Bus;
+
+// Block-select values, see W5500 datasheet s2.2.2
+const COMMON_BLOCK: u8 = 0;
+const SOCKET0_BLOCK: u8 = 1;
+const SOCKET0_TX_BLOCK: u8 = 2;
+
+// Common registers, see W5500 datasheet s3.1
+const VERSIONR: u16 = 0x0039;
+
+/// The value that every W5500 reports in its VERSIONR register
+pub const EXPECTED_VERSION: u8 = 4;
+
+// Socket registers, see W5500 datasheet s3.2
+const SN_MR: u16 = 0x0000;
+const SN_CR: u16 = 0x0001;
+const SN_IR: u16 = 0x0002;
+const SN_SR: u16 = 0x0003;
+const SN_TX_WR: u16 = 0x0024;
+
+const SN_MR_MACRAW: u8 = 0x04;
+const SN_CR_OPEN: u8 = 0x01;
+const SN_CR_CLOSE: u8 = 0x10;
+const SN_CR_SEND: u8 = 0x20;
+const SN_IR_SEND_OK: u8 = 0x10;
+const SN_SR_MACRAW: u8 = 0x42;
+
+const PATTERN_LEN: usize = 64;
+const LOOPBACK_POLLS: usize = 10_000;
+
+/// Which parts of the self-test to run
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SelfTestOptions {
+    /// Also send a frame, in MACRAW mode, addressed to this MAC address
+    ///
+    /// The W5500 has no true internal loopback, so this tests the
+    /// transmit path as far as the chip reporting SEND_OK; it does
+    /// not require that the frame is received anywhere.
+    pub loopback: Option<[u8; 6]>,
+}
+
+/// The results of [`self_test()`]
+///
+/// Each stage is `None` if it wasn't attempted (either because it wasn't
+/// asked for, or because an earlier stage failed).
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SelfTestReport {
+    /// The contents of the VERSIONR register, or `None` if it couldn't be
+    /// read at all
+    pub version: Option<u8>,
+    /// Whether a pattern written into the TX buffer read back correctly
+    pub buffer_ok: Option<bool>,
+    /// Whether a frame was sent successfully in MACRAW mode
+    pub loopback_ok: Option<bool>,
+}
+
+impl SelfTestReport {
+    /// Did every stage that was attempted succeed?
+    #[must_use]
+    pub fn passed(&self) -> bool {
+        self.version == Some(EXPECTED_VERSION)
+            && self.buffer_ok != Some(false)
+            && self.loopback_ok != Some(false)
+    }
+}
+
+fn read_u8<SPI: Bus>(bus: &mut SPI, block: u8, address: u16) -> Option<u8> {
+    let mut data = [0u8; 1];
+    bus.read_frame(block, address, &mut data).ok()?;
+    Some(data[0])
+}
+
+fn write_u8<SPI: Bus>(
+    bus: &mut SPI,
+    block: u8,
+    address: u16,
+    value: u8,
+) -> Option<()> {
+    bus.write_frame(block, address, &[value]).ok()
+}
+
+fn pattern_test<SPI: Bus>(bus: &mut SPI) -> bool {
+    // Walking-ones and its inverse, so that every data line is seen
+    // both high and low
+    for invert in [0u8, 0xFF] {
+        let mut pattern = [0u8; PATTERN_LEN];
+        for (i, b) in pattern.iter_mut().enumerate() {
+            *b = (1u8 << (i % 8)) ^ invert;
+        }
+        let mut readback = [0u8; PATTERN_LEN];
+        if bus.write_frame(SOCKET0_TX_BLOCK, 0, &pattern).is_err()
+            || bus.read_frame(SOCKET0_TX_BLOCK, 0, &mut readback).is_err()
+            || readback != pattern
+        {
+            return false;
+        }
+    }
+    true
+}
+
+fn loopback_test<SPI: Bus>(bus: &mut SPI, mac_address: &[u8; 6]) -> bool {
+    let result = loopback_inner(bus, mac_address).unwrap_or(false);
+    let _ = write_u8(bus, SOCKET0_BLOCK, SN_CR, SN_CR_CLOSE);
+    result
+}
+
+fn loopback_inner<SPI: Bus>(
+    bus: &mut SPI,
+    mac_address: &[u8; 6],
+) -> Option<bool> {
+    write_u8(bus, SOCKET0_BLOCK, SN_MR, SN_MR_MACRAW)?;
+    write_u8(bus, SOCKET0_BLOCK, SN_CR, SN_CR_OPEN)?;
+    if read_u8(bus, SOCKET0_BLOCK, SN_SR)? != SN_SR_MACRAW {
+        return Some(false);
+    }
+
+    // Minimum-length Ethernet frame, from and to ourselves, using the
+    // IEEE "local experimental" EtherType
+    let mut frame = [0u8; 60];
+    frame[0..6].copy_from_slice(mac_address);
+    frame[6..12].copy_from_slice(mac_address);
+    frame[12] = 0x88;
+    frame[13] = 0xB5;
+
+    let mut cursor = [0u8; 2];
+    bus.read_frame(SOCKET0_BLOCK, SN_TX_WR, &mut cursor).ok()?;
+    let start = u16::from_be_bytes(cursor);
+    bus.write_frame(SOCKET0_TX_BLOCK, start, &frame).ok()?;
+    let end = start.wrapping_add(frame.len() as u16);
+    bus.write_frame(SOCKET0_BLOCK, SN_TX_WR, &end.to_be_bytes())
+        .ok()?;
+    write_u8(bus, SOCKET0_BLOCK, SN_CR, SN_CR_SEND)?;
+
+    for _ in 0..LOOPBACK_POLLS {
+        if (read_u8(bus, SOCKET0_BLOCK, SN_IR)? & SN_IR_SEND_OK) != 0 {
+            write_u8(bus, SOCKET0_BLOCK, SN_IR, SN_IR_SEND_OK)?;
+            return Some(true);
+        }
+    }
+    Some(false)
+}
+
+/// Check that a W5500 is present and that the SPI link to it works
+///
+/// This is intended for diagnosing wiring or SPI-configuration
+/// problems, and should be called *before* handing the bus to
+/// `smoltcp::Device::new()`, as it overwrites buffer memory and socket 0
+/// settings.
+///
+/// The stages are: reading the VERSIONR register; writing and reading
+/// back a pattern in socket 0's TX buffer; and, if requested in
+/// `options`, sending a frame in MACRAW mode.
+pub fn self_test<SPI: Bus>(
+    bus: &mut SPI,
+    options: &SelfTestOptions,
+) -> SelfTestReport {
+    let mut report = SelfTestReport {
+        version: read_u8(bus, COMMON_BLOCK, VERSIONR),
+        ..Default::default()
+    };
+    if report.version != Some(EXPECTED_VERSION) {
+        return report;
+    }
+
+    let buffer_ok = pattern_test(bus);
+    report.buffer_ok = Some(buffer_ok);
+    if !buffer_ok {
+        return report;
+    }
+
+    if let Some(mac_address) = &options.loopback {
+        report.loopback_ok = Some(loopback_test(bus, mac_address));
+    }
+    report
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    /// A pretend W5500, storing everything that's written to it
+    #[derive(Default)]
+    struct FakeBus {
+        memory: HashMap<(u8, u16), u8>,
+        stuck_bit: u8,
+        send_ok: bool,
+        fail: bool,
+    }
+
+    impl FakeBus {
+        fn new() -> Self {
+            let mut bus = FakeBus {
+                send_ok: true,
+                ..Default::default()
+            };
+            bus.memory
+                .insert((COMMON_BLOCK, VERSIONR), EXPECTED_VERSION);
+            bus
+        }
+    }
+
+    impl Bus for FakeBus {
+        type Error = u32;
+
+        fn read_frame(
+            &mut self,
+            block: u8,
+            address: u16,
+            data: &mut [u8],
+        ) -> Result<(), u32> {
+            if self.fail {
+                return Err(1);
+            }
+            for (i, b) in data.iter_mut().enumerate() {
+                let address = address.wrapping_add(i as u16);
+                *b = self.memory.get(&(block, address)).copied().unwrap_or(0)
+                    | self.stuck_bit;
+            }
+            Ok(())
+        }
+
+        fn write_frame(
+            &mut self,
+            block: u8,
+            address: u16,
+            data: &[u8],
+        ) -> Result<(), u32> {
+            if self.fail {
+                return Err(1);
+            }
+            for (i, b) in data.iter().enumerate() {
+                let address = address.wrapping_add(i as u16);
+                self.memory.insert((block, address), *b);
+            }
+            if block == SOCKET0_BLOCK && address == SN_CR {
+                match data[0] {
+                    SN_CR_OPEN => {
+                        let mode = self.memory[&(SOCKET0_BLOCK, SN_MR)];
+                        if mode == SN_MR_MACRAW {
+                            self.memory
+                                .insert((SOCKET0_BLOCK, SN_SR), SN_SR_MACRAW);
+                        }
+                    }
+                    SN_CR_SEND if self.send_ok => {
+                        self.memory
+                            .insert((SOCKET0_BLOCK, SN_IR), SN_IR_SEND_OK);
+                    }
+                    _ => {}
+                }
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn self_test_passes() {
+        let mut bus = FakeBus::new();
+        let r = self_test(&mut bus, &SelfTestOptions::default());
+        assert_eq!(
+            r,
+            SelfTestReport {
+                version: Some(4),
+                buffer_ok: Some(true),
+                loopback_ok: None,
+            }
+        );
+        assert!(r.passed());
+    }
+
+    #[test]
+    fn self_test_bus_error() {
+        let mut bus = FakeBus::new();
+        bus.fail = true;
+        let r = self_test(&mut bus, &SelfTestOptions::default());
+        assert_eq!(r, SelfTestReport::default());
+        assert!(!r.passed());
+    }
+
+    #[test]
+    fn self_test_wrong_version() {
+        let mut bus = FakeBus::new();
+        bus.memory.insert((COMMON_BLOCK, VERSIONR), 0xFF);
+        let r = self_test(&mut bus, &SelfTestOptions::default());
+        assert_eq!(r.version, Some(0xFF));
+        assert_eq!(r.buffer_ok, None);
+        assert!(!r.passed());
+    }
+
+    #[test]
+    fn self_test_stuck_data_line() {
+        let mut bus = FakeBus::new();
+        bus.memory.insert((COMMON_BLOCK, VERSIONR), 0);
+        bus.stuck_bit = 4; // so VERSIONR reads as 4, but buffer is bad
+        let r = self_test(&mut bus, &SelfTestOptions::default());
+        assert_eq!(r.version, Some(4));
+        assert_eq!(r.buffer_ok, Some(false));
+        assert!(!r.passed());
+    }
+
+    #[test]
+    fn self_test_loopback() {
+        let mut bus = FakeBus::new();
+        let r = self_test(
+            &mut bus,
+            &SelfTestOptions {
+                loopback: Some([2, 0, 0, 0, 0, 1]),
+            },
+        );
+        assert_eq!(r.loopback_ok, Some(true));
+        assert!(r.passed());
+        assert_eq!(bus.memory[&(SOCKET0_TX_BLOCK, 0)], 2);
+        assert_eq!(bus.memory[&(SOCKET0_TX_BLOCK, 12)], 0x88);
+        assert_eq!(bus.memory[&(SOCKET0_BLOCK, SN_TX_WR + 1)], 60);
+        assert_eq!(bus.memory[&(SOCKET0_BLOCK, SN_CR)], SN_CR_CLOSE);
+    }
+
+    #[test]
+    fn self_test_loopback_not_sent() {
+        let mut bus = FakeBus::new();
+        bus.send_ok = false;
+        let r = self_test(
+            &mut bus,
+            &SelfTestOptions {
+                loopback: Some([2, 0, 0, 0, 0, 1]),
+            },
+        );
+        assert_eq!(r.loopback_ok, Some(false));
+        assert!(!r.passed());
+    }
+}
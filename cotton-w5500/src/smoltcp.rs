@@ -1,5 +1,7 @@
 /// Smoltcp phy device
 pub mod device {
+    use crate::tap::{FrameTap, NoTap, TapAction};
+
     struct Buffer {
         bytes: [u8; 1536],
     }
@@ -16,10 +18,20 @@ pub mod device {
     ///
     /// This is a simple implementation that uses synchronous transfers, and
     /// so needs just one inbound and one outbound buffer.
-    pub struct Device<Spi: w5500::bus::Bus> {
+    ///
+    /// Received frames are offered to a [`FrameTap`] before smoltcp
+    /// sees them; by default, that's [`NoTap`], which passes them all
+    /// on. See [`Device::with_tap`].
+    ///
+    /// Received frames too big for the buffer are dropped, rather than
+    /// passed on truncated; see [`Device::oversize_frames`].
+    pub struct Device<Spi: w5500::bus::Bus, Tap: FrameTap = NoTap> {
         w5500: w5500::raw_device::RawDevice<Spi>,
         rx: Buffer,
         tx: Buffer,
+        tap: Tap,
+        checksum: smoltcp::phy::ChecksumCapabilities,
+        oversize_frames: u32,
     }
 
     impl<Spi: w5500::bus::Bus> Device<Spi> {
@@ -39,9 +51,78 @@ pub mod device {
                     .unwrap(),
                 rx: Buffer::new(),
                 tx: Buffer::new(),
+                tap: NoTap,
+                checksum: smoltcp::phy::ChecksumCapabilities::default(),
+                oversize_frames: 0,
+            }
+        }
+    }
+
+    impl<Spi: w5500::bus::Bus, Tap: FrameTap> Device<Spi, Tap> {
+        /// Replace the hook which sees received frames before smoltcp
+        ///
+        /// Frames which the tap consumes are never returned from
+        /// `receive`; this allows link-layer protocols such as LLDP
+        /// or PTP to run alongside the IP stack. The tap is called
+        /// from within `smoltcp::iface::Interface::poll`, so should
+        /// be quick.
+        pub fn with_tap<T: FrameTap>(self, tap: T) -> Device<Spi, T> {
+            Device {
+                w5500: self.w5500,
+                rx: self.rx,
+                tx: self.tx,
+                tap,
+                checksum: self.checksum,
+                oversize_frames: self.oversize_frames,
             }
         }
 
+        /// Choose which checksums smoltcp computes and verifies
+        ///
+        /// In MACRAW mode the W5500 neither generates nor checks IP,
+        /// UDP or TCP checksums, so by default smoltcp does both. The
+        /// W5500 does check each received frame's Ethernet FCS,
+        /// discarding corrupted ones; where that's thought enough,
+        /// verifying received checksums can be skipped by setting them
+        /// to `Checksum::Tx`. Transmitted checksums must always be
+        /// computed (`Checksum::Both` or `Checksum::Tx`), as nothing
+        /// else will.
+        #[must_use]
+        pub fn with_checksum_capabilities(
+            mut self,
+            checksum: smoltcp::phy::ChecksumCapabilities,
+        ) -> Self {
+            self.checksum = checksum;
+            self
+        }
+
+        /// The number of received frames dropped for being too big
+        ///
+        /// Frames which don't fit in the receive buffer (of 1536 bytes)
+        /// can't be read whole, so are dropped, and counted here.
+        /// The count wraps on overflow.
+        pub fn oversize_frames(&self) -> u32 {
+            self.oversize_frames
+        }
+
+        /// Access the frame tap
+        pub fn tap_mut(&mut self) -> &mut Tap {
+            &mut self.tap
+        }
+
+        /// Send one raw Ethernet frame, bypassing smoltcp
+        ///
+        /// The frame must include its Ethernet header. This is the
+        /// counterpart of a [`FrameTap`], for sending the frames of
+        /// link-layer protocols.
+        ///
+        /// # Errors
+        ///
+        /// Passes on any error from the SPI bus.
+        pub fn send_frame(&mut self, frame: &[u8]) -> Result<(), Spi::Error> {
+            self.w5500.write_frame(frame).map(|_| ())
+        }
+
         /// Enable chip-level interrupts on pin INTn
         pub fn enable_interrupt(&mut self) {
             let _ = self.w5500.enable_interrupts(4); // RX interrupt
@@ -73,16 +154,40 @@ pub mod device {
         buffer: &'a mut Buffer,
     }
 
-    impl<Spi: w5500::bus::Bus> smoltcp::phy::Device for Device<Spi> {
-        type RxToken<'token> = EthRxToken<'token> where Self: 'token;
-        type TxToken<'token> = EthTxToken<'token, Spi> where Self: 'token;
+    impl<Spi: w5500::bus::Bus, Tap: FrameTap> smoltcp::phy::Device
+        for Device<Spi, Tap>
+    {
+        type RxToken<'token>
+            = EthRxToken<'token>
+        where
+            Self: 'token;
+        type TxToken<'token>
+            = EthTxToken<'token, Spi>
+        where
+            Self: 'token;
 
         fn receive(
             &mut self,
             _timestamp: smoltcp::time::Instant,
         ) -> Option<(Self::RxToken<'_>, Self::TxToken<'_>)> {
-            if let Ok(n) = self.w5500.read_frame(&mut self.rx.bytes) {
-                if n > 0 {
+            loop {
+                let n = match self.w5500.read_frame(&mut self.rx.bytes) {
+                    Ok(n) if n > 0 => n,
+                    _ => return None,
+                };
+                // No Ethernet frame is this big, not even with VLAN
+                // tags, so this one may have been truncated: the w5500
+                // crate skips the rest of it, so the ring stays in
+                // step, but it mustn't be passed on
+                if n >= self.rx.bytes.len() {
+                    self.oversize_frames =
+                        self.oversize_frames.wrapping_add(1);
+                    continue;
+                }
+                // Frames consumed by the tap don't count: look for
+                // another one, as smoltcp takes None to mean that
+                // there are none left
+                if self.tap.on_frame(&self.rx.bytes[0..n]) == TapAction::Pass {
                     return Some((
                         EthRxToken {
                             count: n,
@@ -95,7 +200,6 @@ pub mod device {
                     ));
                 }
             }
-            None
         }
 
         fn transmit(
@@ -116,6 +220,7 @@ pub mod device {
             caps.max_transmission_unit = 1536;
             caps.medium = smoltcp::phy::Medium::Ethernet;
             caps.max_burst_size = Some(1);
+            caps.checksum = self.checksum.clone();
             caps
         }
     }
@@ -229,6 +334,24 @@ mod tests {
         assert_eq!(1536, c.max_transmission_unit);
     }
 
+    #[test]
+    fn test_checksum_capabilities() {
+        let mut bus = MockBus::new();
+        bus.expect_write_frame()
+            .times(SETUP_CALLS)
+            .return_const(Ok(()));
+        let device = super::Device::new(bus, &[0x88u8; 6]);
+        let c = device.capabilities();
+        assert!(matches!(c.checksum.udp, smoltcp::phy::Checksum::Both));
+
+        let mut checksum = smoltcp::phy::ChecksumCapabilities::default();
+        checksum.udp = smoltcp::phy::Checksum::Tx;
+        let device = device.with_checksum_capabilities(checksum);
+        let c = device.capabilities();
+        assert!(matches!(c.checksum.udp, smoltcp::phy::Checksum::Tx));
+        assert!(matches!(c.checksum.tcp, smoltcp::phy::Checksum::Both));
+    }
+
     #[test]
     fn test_transmit() {
         let mut bus = MockBus::new();
@@ -355,6 +478,176 @@ mod tests {
         });
     }
 
+    /// Sets up the mock to receive the two-byte frame "rx", repeatedly
+    fn expect_receive(bus: &mut MockBus) {
+        bus.expect_read_frame()
+            .withf(|block, addr, _data| *block == 1 && *addr == 0x26)
+            .returning(|_block, _addr, data| {
+                data[0] = 0;
+                data[1] = 4;
+                Ok(())
+            });
+        bus.expect_read_frame()
+            .withf(|block, addr, _data| *block == 1 && *addr == 0x28)
+            .returning(|_block, _addr, data| {
+                data[0] = 0;
+                data[1] = 0;
+                Ok(())
+            });
+        bus.expect_read_frame()
+            .withf(|block, addr, _data| *block == 3 && *addr == 0)
+            .returning(|_block, _addr, data| {
+                data[0] = 0;
+                data[1] = 4;
+                Ok(())
+            });
+        bus.expect_read_frame()
+            .withf(|block, addr, _data| *block == 3 && *addr == 2)
+            .returning(|_block, _addr, data| {
+                data[0] = b'r';
+                data[1] = b'x';
+                Ok(())
+            });
+        bus.expect_write_frame().return_const(Ok(()));
+    }
+
+    /// Consumes the first `consume` frames it sees, then passes the rest
+    struct CountingTap {
+        consume: usize,
+        seen: usize,
+    }
+
+    impl crate::tap::FrameTap for CountingTap {
+        fn on_frame(&mut self, frame: &[u8]) -> crate::tap::TapAction {
+            assert_eq!(frame, b"rx");
+            self.seen += 1;
+            if self.seen <= self.consume {
+                crate::tap::TapAction::Consume
+            } else {
+                crate::tap::TapAction::Pass
+            }
+        }
+    }
+
+    #[test]
+    fn test_receive_tap_passes() {
+        let mut bus = MockBus::new();
+        bus.expect_write_frame()
+            .times(SETUP_CALLS)
+            .return_const(Ok(()));
+        expect_receive(&mut bus);
+        let mut device =
+            super::Device::new(bus, &[0x88u8; 6]).with_tap(CountingTap {
+                consume: 0,
+                seen: 0,
+            });
+
+        let (rx, _tx) = device.receive(smoltcp::time::Instant::ZERO).unwrap();
+        rx.consume(|b| assert_eq!(b, b"rx"));
+        assert_eq!(device.tap_mut().seen, 1);
+    }
+
+    #[test]
+    fn test_receive_tap_consumes() {
+        let mut bus = MockBus::new();
+        bus.expect_write_frame()
+            .times(SETUP_CALLS)
+            .return_const(Ok(()));
+        expect_receive(&mut bus);
+        let mut device =
+            super::Device::new(bus, &[0x88u8; 6]).with_tap(CountingTap {
+                consume: 2,
+                seen: 0,
+            });
+
+        // The first two frames go to the tap, the third to smoltcp
+        let (rx, _tx) = device.receive(smoltcp::time::Instant::ZERO).unwrap();
+        rx.consume(|b| assert_eq!(b, b"rx"));
+        assert_eq!(device.tap_mut().seen, 3);
+    }
+
+    #[test]
+    fn test_receive_drops_oversize_frame() {
+        let mut bus = MockBus::new();
+        bus.expect_write_frame()
+            .times(SETUP_CALLS)
+            .return_const(Ok(()));
+        bus.expect_read_frame()
+            .withf(|block, addr, _data| *block == 1 && *addr == 0x26)
+            .returning(|_block, _addr, data| {
+                data[0] = 0x10;
+                data[1] = 0;
+                Ok(())
+            });
+        bus.expect_read_frame()
+            .withf(|block, addr, _data| *block == 1 && *addr == 0x28)
+            .returning(|_block, _addr, data| {
+                data[0] = 0;
+                data[1] = 0;
+                Ok(())
+            });
+        // The first frame is 1600 bytes (plus header), the second "rx"
+        let mut frames = 0;
+        bus.expect_read_frame()
+            .withf(|block, addr, _data| *block == 3 && *addr == 0)
+            .returning(move |_block, _addr, data| {
+                let size: u16 = if frames == 0 { 1602 } else { 4 };
+                frames += 1;
+                data.copy_from_slice(&size.to_be_bytes());
+                Ok(())
+            });
+        bus.expect_read_frame()
+            .withf(|block, addr, _data| *block == 3 && *addr == 2)
+            .returning(|_block, _addr, data| {
+                data[0] = b'r';
+                data[1] = b'x';
+                Ok(())
+            });
+        bus.expect_write_frame().return_const(Ok(()));
+        let mut device = super::Device::new(bus, &[0x88u8; 6]);
+
+        let (rx, _tx) = device.receive(smoltcp::time::Instant::ZERO).unwrap();
+        rx.consume(|b| assert_eq!(b, b"rx"));
+        assert_eq!(device.oversize_frames(), 1);
+    }
+
+    #[test]
+    fn test_send_frame() {
+        let mut bus = MockBus::new();
+        bus.expect_write_frame()
+            .times(SETUP_CALLS)
+            .return_const(Ok(()));
+        // As in test_transmit_consume
+        bus.expect_read_frame()
+            .withf(|block, addr, _data| *block == 1 && *addr == 0x20)
+            .returning(|_block, _addr, data: &mut [u8]| {
+                data[0] = 64;
+                data[1] = 0;
+                Ok(())
+            });
+        bus.expect_read_frame()
+            .withf(|block, addr, _data| *block == 1 && *addr == 0x24)
+            .returning(|_block, _addr, data: &mut [u8]| {
+                data[0] = 0;
+                data[1] = 0;
+                Ok(())
+            });
+        bus.expect_write_frame()
+            .withf(|_block, _addr, data| data[..] == b"LLDP"[..])
+            .times(1)
+            .return_const(Ok(()));
+        bus.expect_write_frame().return_const(Ok(()));
+        bus.expect_read_frame()
+            .withf(|block, addr, _data| *block == 1 && *addr == 2)
+            .returning(|_block, _addr, data: &mut [u8]| {
+                data[0] = 16;
+                Ok(())
+            });
+        let mut device = super::Device::new(bus, &[0x88u8; 6]);
+
+        assert!(device.send_frame(b"LLDP").is_ok());
+    }
+
     #[test]
     fn test_receive_propagates_error() {
         let mut bus = MockBus::new();
@@ -0,0 +1,158 @@
+/// EtherType of Link Layer Discovery Protocol (IEEE 802.1AB) frames
+pub const ETHERTYPE_LLDP: u16 = 0x88CC;
+
+/// EtherType of Precision Time Protocol (IEEE 1588) frames
+pub const ETHERTYPE_PTP: u16 = 0x88F7;
+
+/// EtherType of an IEEE 802.1Q VLAN tag
+pub const ETHERTYPE_VLAN: u16 = 0x8100;
+
+/// What a [`FrameTap`] did with a received frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TapAction {
+    /// The tap has dealt with the frame; the IP stack doesn't see it
+    Consume,
+
+    /// The frame is passed on to the IP stack as usual
+    Pass,
+}
+
+/// A hook which sees each received Ethernet frame before the IP stack
+///
+/// In MACRAW mode, the W5500 receives every frame addressed to it (and
+/// every broadcast and multicast frame); a `FrameTap` lets an
+/// application handle some of them itself -- such as those of
+/// link-layer protocols like LLDP or PTP, which an IP stack would just
+/// discard.
+///
+/// Closures of type `FnMut(&[u8]) -> TapAction` are `FrameTap`s.
+pub trait FrameTap {
+    /// Inspect one received frame, including its Ethernet header
+    fn on_frame(&mut self, frame: &[u8]) -> TapAction;
+}
+
+impl<F: FnMut(&[u8]) -> TapAction> FrameTap for F {
+    fn on_frame(&mut self, frame: &[u8]) -> TapAction {
+        self(frame)
+    }
+}
+
+/// A [`FrameTap`] which passes every frame on to the IP stack
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NoTap;
+
+impl FrameTap for NoTap {
+    fn on_frame(&mut self, _frame: &[u8]) -> TapAction {
+        TapAction::Pass
+    }
+}
+
+/// A [`FrameTap`] which consumes the frames of one EtherType
+///
+/// Each frame with the given EtherType (with or without a VLAN tag) is
+/// passed to the handler, and not to the IP stack; all other frames
+/// are passed on.
+#[derive(Debug, Clone, Copy)]
+pub struct EtherTypeTap<F> {
+    ethertype: u16,
+    handler: F,
+}
+
+impl<F: FnMut(&[u8])> EtherTypeTap<F> {
+    /// Create a tap sending frames of `ethertype` to `handler`
+    pub const fn new(ethertype: u16, handler: F) -> Self {
+        Self { ethertype, handler }
+    }
+}
+
+impl<F: FnMut(&[u8])> FrameTap for EtherTypeTap<F> {
+    fn on_frame(&mut self, frame: &[u8]) -> TapAction {
+        if ethertype(frame) == Some(self.ethertype) {
+            (self.handler)(frame);
+            TapAction::Consume
+        } else {
+            TapAction::Pass
+        }
+    }
+}
+
+/// The EtherType of an Ethernet frame
+///
+/// Looks past an IEEE 802.1Q VLAN tag if there is one. Returns `None`
+/// if the frame is too short to have an EtherType.
+#[must_use]
+pub fn ethertype(frame: &[u8]) -> Option<u16> {
+    let read = |offset: usize| {
+        frame
+            .get(offset..offset + 2)
+            .map(|b| u16::from_be_bytes([b[0], b[1]]))
+    };
+    match read(12)? {
+        ETHERTYPE_VLAN => read(16),
+        ethertype => Some(ethertype),
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+
+    fn frame(ethertype: &[u8]) -> Vec<u8> {
+        let mut f = vec![0xFFu8; 6]; // destination
+        f.extend_from_slice(&[0x88u8; 6]); // source
+        f.extend_from_slice(ethertype);
+        f.extend_from_slice(b"payload");
+        f
+    }
+
+    #[test]
+    fn ethertype_read() {
+        assert_eq!(ethertype(&frame(&[0x88, 0xCC])), Some(ETHERTYPE_LLDP));
+        assert_eq!(ethertype(&frame(&[0x08, 0x00])), Some(0x0800));
+    }
+
+    #[test]
+    fn ethertype_behind_vlan_tag() {
+        assert_eq!(
+            ethertype(&frame(&[0x81, 0x00, 0x00, 0x05, 0x88, 0xF7])),
+            Some(ETHERTYPE_PTP)
+        );
+    }
+
+    #[test]
+    fn short_frame_has_no_ethertype() {
+        assert_eq!(ethertype(&[0u8; 13]), None);
+        assert_eq!(ethertype(&[0u8; 14]), Some(0));
+        let tagged = frame(&[0x81, 0x00, 0x00, 0x05, 0x88, 0xF7]);
+        assert_eq!(ethertype(&tagged[..17]), None);
+    }
+
+    #[test]
+    fn no_tap_passes() {
+        assert_eq!(NoTap.on_frame(&frame(&[0x88, 0xCC])), TapAction::Pass);
+    }
+
+    #[test]
+    fn closure_is_tap() {
+        let mut tap = |f: &[u8]| {
+            if f.len() > 20 {
+                TapAction::Consume
+            } else {
+                TapAction::Pass
+            }
+        };
+        assert_eq!(tap.on_frame(&[0u8; 64]), TapAction::Consume);
+        assert_eq!(tap.on_frame(&[0u8; 14]), TapAction::Pass);
+    }
+
+    #[test]
+    fn ethertype_tap_consumes_matching() {
+        let mut seen = Vec::new();
+        let mut tap =
+            EtherTypeTap::new(ETHERTYPE_LLDP, |f: &[u8]| seen.push(f.len()));
+        assert_eq!(tap.on_frame(&frame(&[0x88, 0xCC])), TapAction::Consume);
+        assert_eq!(tap.on_frame(&frame(&[0x08, 0x00])), TapAction::Pass);
+        assert_eq!(tap.on_frame(&[0u8; 4]), TapAction::Pass);
+        assert_eq!(seen, vec![21]);
+    }
+}
@@ -0,0 +1,253 @@
+//! Example Embassy application using RP2040 + W5500 to obtain a DHCP
+//! address and start doing SSDP
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::string::ToString;
+use core::ptr;
+use cotton_ssdp::refresh_timer::EmbassyTimebase;
+use cotton_ssdp::udp::embassy::{WrappedSocket, WrappedStack};
+use cotton_ssdp::udp::smoltcp::{
+    GenericIpAddress, GenericIpv4Address, GenericSocketAddr,
+};
+use defmt::{println, unwrap};
+use embassy_executor::Spawner;
+use embassy_net::udp::{PacketMetadata, UdpSocket};
+use embassy_net::{Stack, StackResources};
+use embassy_net_wiznet::chip::W5500;
+use embassy_net_wiznet::{Device, Runner, State};
+use embassy_rp::flash::{Blocking, Flash};
+use embassy_rp::gpio::{Input, Level, Output, Pull};
+use embassy_rp::peripherals::{PIN_17, PIN_20, PIN_21, SPI0};
+use embassy_rp::spi::{Async, Config as SpiConfig, Spi};
+use embassy_time::{Delay, WithTimeout};
+use embedded_alloc::Heap;
+use embedded_hal_bus::spi::ExclusiveDevice;
+use static_cell::StaticCell;
+use {defmt_rtt as _, panic_probe as _};
+
+#[global_allocator]
+static ALLOCATOR: Heap = Heap::empty();
+
+/// Set up the heap
+///
+/// As is standard, all memory above the rodata segment and below the
+/// stack, is used as heap.
+pub fn init_heap() {
+    const STACK_SIZE: usize = 16 * 1024;
+    // SAFETY: this relies on the link map being correct, and STACK_SIZE
+    // being large enough for the entire program.
+    unsafe {
+        extern "C" {
+            static mut __sheap: u32;
+            static mut _stack_start: u32;
+        }
+
+        let heap_start = ptr::addr_of!(__sheap) as usize;
+        let heap_end = ptr::addr_of!(_stack_start) as usize;
+        let heap_size = heap_end - heap_start - STACK_SIZE;
+        ALLOCATOR.init(heap_start, heap_size);
+    }
+}
+
+const FLASH_SIZE: usize = 2 * 1024 * 1024;
+
+type SpiDevice =
+    ExclusiveDevice<Spi<'static, SPI0, Async>, Output<'static, PIN_17>, Delay>;
+
+pub struct Listener {}
+
+impl cotton_ssdp::engine::Callback for Listener {
+    fn on_notification(
+        &self,
+        notification: &cotton_ssdp::Notification,
+    ) -> Result<(), cotton_ssdp::engine::CallbackError> {
+        if let cotton_ssdp::Notification::Alive {
+            ref notification_type,
+            location,
+            ..
+        } = notification
+        {
+            defmt::println!(
+                "SSDP! {} {}",
+                &notification_type[..],
+                &location[..]
+            );
+        }
+        Ok(())
+    }
+}
+
+#[embassy_executor::task]
+async fn ethernet_task(
+    runner: Runner<
+        'static,
+        W5500,
+        SpiDevice,
+        Input<'static, PIN_21>,
+        Output<'static, PIN_20>,
+    >,
+) -> ! {
+    runner.run().await
+}
+
+#[embassy_executor::task]
+async fn net_task(stack: &'static Stack<Device<'static>>) -> ! {
+    stack.run().await
+}
+
+#[embassy_executor::main]
+async fn main(spawner: Spawner) -> ! {
+    let p = embassy_rp::init(Default::default());
+    init_heap();
+
+    println!("Hello World!");
+
+    // The RP2040 itself has no unique ID, but the SPI flash does;
+    // see the comments on cross/rp2040-w5500/src/setup.rs
+    let mut flash = Flash::<_, Blocking, FLASH_SIZE>::new_blocking(p.FLASH);
+    let mut unique_bytes = [0u8; 16];
+    unwrap!(flash.blocking_unique_id(&mut unique_bytes));
+    let unique_id = cotton_unique::UniqueId::new(&unique_bytes);
+    let mac_addr = cotton_unique::mac_address(&unique_id, b"w5500-spi0");
+    println!("MAC address: {:x}", mac_addr);
+
+    // W5500-EVB-Pico:
+    //   W5500 SPI on SPI0
+    //         nCS = GPIO17
+    //         TX (MOSI) = GPIO19
+    //         RX (MISO) = GPIO16
+    //         SCK = GPIO18
+    //   W5500 INTn on GPIO21
+    //   W5500 RSTn on GPIO20
+    let mut spi_cfg = SpiConfig::default();
+    spi_cfg.frequency = 50_000_000;
+    let spi = Spi::new(
+        p.SPI0, p.PIN_18, p.PIN_19, p.PIN_16, p.DMA_CH0, p.DMA_CH1, spi_cfg,
+    );
+    let cs = Output::new(p.PIN_17, Level::High);
+    let w5500_int = Input::new(p.PIN_21, Pull::Up);
+    let w5500_reset = Output::new(p.PIN_20, Level::High);
+
+    static STATE: StaticCell<State<8, 8>> = StaticCell::new();
+    let (device, runner) = embassy_net_wiznet::new(
+        mac_addr,
+        STATE.init(State::<8, 8>::new()),
+        ExclusiveDevice::new(spi, cs, Delay),
+        w5500_int,
+        w5500_reset,
+    )
+    .await;
+    unwrap!(spawner.spawn(ethernet_task(runner)));
+
+    let seed = unique_id.id(b"embassy-net");
+    let config = embassy_net::Config::dhcpv4(Default::default());
+
+    // Init network stack
+    static STACK: StaticCell<Stack<Device<'static>>> = StaticCell::new();
+    static RESOURCES: StaticCell<StackResources<3>> = StaticCell::new();
+    let stack = &*STACK.init(Stack::new(
+        device,
+        config,
+        RESOURCES.init(StackResources::new()),
+        seed,
+    ));
+
+    // Launch network task
+    unwrap!(spawner.spawn(net_task(stack)));
+
+    // Ensure DHCP configuration is up before trying connect
+    stack.wait_config_up().await;
+
+    println!("DHCP config acquired!");
+
+    let mut ssdp =
+        cotton_ssdp::engine::Engine::<Listener, EmbassyTimebase>::new(
+            unique_id.id(b"ssdp-refresh") as u32,
+            embassy_time::Instant::now(),
+        );
+
+    let mut rx_buffer = [0; 4096];
+    let mut tx_buffer = [0; 4096];
+    let mut rx_meta = [PacketMetadata::EMPTY; 16];
+    let mut tx_meta = [PacketMetadata::EMPTY; 16];
+    let mut buf = [0; 4096];
+    let mut udp_socket = UdpSocket::new(
+        stack,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+    _ = udp_socket.bind(1900);
+
+    let ix =
+        cotton_netif::InterfaceIndex(core::num::NonZeroU32::new(1).unwrap());
+    let ev = cotton_netif::NetworkEvent::NewLink(
+        ix,
+        "".to_string(),
+        cotton_netif::Flags::UP
+            | cotton_netif::Flags::RUNNING
+            | cotton_netif::Flags::MULTICAST,
+            cotton_netif::OperState::Up,
+    );
+
+    {
+        let wi = WrappedStack::new(stack);
+        let ws = WrappedSocket::new(&udp_socket);
+        _ = ssdp.on_network_event(&ev, &wi, &ws);
+
+        if let Some(ip) = stack.config_v4().map(|cfg| cfg.address.address()) {
+            ssdp.on_new_addr_event(
+                &ix,
+                &no_std_net::IpAddr::V4(GenericIpv4Address::from(ip).into()),
+                &ws,
+            );
+        }
+
+        ssdp.subscribe(
+            "cotton-test-server-rp2040",
+            Listener {},
+            &ws,
+        );
+
+        let uuid = alloc::format!(
+            "{:032x}",
+            cotton_unique::uuid(&unique_id, b"upnp")
+        );
+        ssdp.advertise(
+            uuid,
+            cotton_ssdp::Advertisement::new(
+                "rp2040-w5500-test",
+                "http://127.0.0.1/",
+            ),
+            &ws,
+        );
+    }
+
+    loop {
+        let p = ssdp.poll_timeout();
+        let r = udp_socket.recv_from(&mut buf).with_deadline(p).await;
+        let now = embassy_time::Instant::now();
+
+        if let Ok(Ok((n, wasfrom))) = r {
+            if let Some(wasto) =
+                stack.config_v4().map(|cfg| cfg.address.address())
+            {
+                ssdp.on_data(
+                    &buf[0..n],
+                    GenericIpAddress::from(embassy_net::IpAddress::Ipv4(
+                        wasto,
+                    ))
+                    .into(),
+                    GenericSocketAddr::from(wasfrom).into(),
+                    now,
+                )
+            }
+        } else {
+            ssdp.handle_timeout(&WrappedSocket::new(&udp_socket), now);
+        }
+    }
+}
@@ -145,19 +145,19 @@ mod app {
             defmt::println!("loop");
             let device = p.next().await;
 
-            if let Some(DeviceEvent::EnumerationError(h, p, e)) = device {
-                defmt::println!(
-                    "Enumeration error {} on hub {} port {}",
-                    e,
-                    h,
-                    p
-                );
+            if let Some(DeviceEvent::EnumerationError(path, e)) = device {
+                defmt::println!("Enumeration error {} on port {}", e, path);
             }
 
             defmt::println!("{:?}", hub_state.topology());
 
-            if let Some(DeviceEvent::Connect(device, info)) = device {
-                defmt::println!("Got device {:x} {:x}", device, info);
+            if let Some(DeviceEvent::Connect(device, info, path)) = device {
+                defmt::println!(
+                    "Got device {:x} {:x} on port {}",
+                    device,
+                    info,
+                    path
+                );
 
                 let mut ims = IdentifyMassStorage::default();
                 let Ok(()) = stack.get_configuration(&device, &mut ims).await
@@ -217,21 +217,29 @@ mod app {
                         (capacity + (1 << 29)) >> 30
                     );
 
-                    let mut buf = [0u8; 512];
+                    let mut buf = [0u8; 4096];
+                    let Some(buf) =
+                        buf.get_mut(..device_info.block_size as usize)
+                    else {
+                        defmt::println!("block size too large");
+                        continue;
+                    };
                     buf[42] = 43;
 
-                    let rc = abd.write_blocks(2, 1, &buf).await;
+                    let rc = abd.write_blocks(2, 1, buf).await;
                     defmt::println!("write16: {:?}", rc);
 
                     buf[42] = 0;
 
-                    let rc = abd.read_blocks(2, 1, &mut buf).await;
+                    let rc = abd.read_blocks(2, 1, buf).await;
                     defmt::println!("read10: {:?}", rc);
 
                     assert!(buf[42] == 43);
 
                     rtic_delay(1500).await;
                     defmt::println!("MSC OK");
+
+                    benchmark(&mut abd, device_info.block_size).await;
                 } else if let Err(e) = stack
                     .get_configuration(&device, &mut ShowDescriptors)
                     .await
@@ -242,6 +250,56 @@ mod app {
         }
     }
 
+    /// First block used for benchmarking (well clear of block 2,
+    /// which is used for the basic write/read test above)
+    const BENCH_START_BLOCK: u64 = 64;
+
+    /// Number of bytes transferred in each direction
+    const BENCH_BYTES: u32 = 512 * 1024;
+
+    /// Time sequential writes, then reads, of BENCH_BYTES, and report
+    /// them in the format parsed by systemtests::benchmarks
+    async fn benchmark<D: AsyncBlockDevice>(abd: &mut D, block_size: u32) {
+        let mut buf = [0u8; 4096];
+        let blocks_per_chunk = buf.len() as u32 / block_size;
+        if blocks_per_chunk == 0 {
+            return;
+        }
+        let chunk = &mut buf[..(blocks_per_chunk * block_size) as usize];
+        let chunks = BENCH_BYTES / chunk.len() as u32;
+        let bytes = chunks * chunk.len() as u32;
+
+        for (i, b) in chunk.iter_mut().enumerate() {
+            *b = i as u8;
+        }
+
+        let start = Mono::now();
+        for i in 0..chunks {
+            let lba = BENCH_START_BLOCK + (i * blocks_per_chunk) as u64;
+            if abd
+                .write_blocks(lba, blocks_per_chunk, chunk)
+                .await
+                .is_err()
+            {
+                defmt::println!("benchmark write failed");
+                return;
+            }
+        }
+        let elapsed = (Mono::now() - start).to_micros();
+        defmt::println!("BENCH msc-write {} bytes in {} us", bytes, elapsed);
+
+        let start = Mono::now();
+        for i in 0..chunks {
+            let lba = BENCH_START_BLOCK + (i * blocks_per_chunk) as u64;
+            if abd.read_blocks(lba, blocks_per_chunk, chunk).await.is_err() {
+                defmt::println!("benchmark read failed");
+                return;
+            }
+        }
+        let elapsed = (Mono::now() - start).to_micros();
+        defmt::println!("BENCH msc-read {} bytes in {} us", bytes, elapsed);
+    }
+
     #[task(binds = USBCTRL_IRQ, shared = [&shared], priority = 2)]
     fn usb_interrupt(cx: usb_interrupt::Context) {
         cx.shared.shared.on_irq();
@@ -141,15 +141,13 @@ mod app {
             index: u16,
             data: DataPhase<'_>,
         ) -> Result<(), UsbError> {
-            let (request_type, length) = match data {
-                DataPhase::Out(bytes) => {
-                    (HOST_TO_DEVICE | VENDOR_REQUEST, bytes.len() as u16)
-                }
-                DataPhase::In(ref bytes) => {
-                    (DEVICE_TO_HOST | VENDOR_REQUEST, bytes.len() as u16)
-                }
-                DataPhase::None => (HOST_TO_DEVICE | VENDOR_REQUEST, 0),
+            let direction = if data.is_in() {
+                DEVICE_TO_HOST
+            } else {
+                HOST_TO_DEVICE
             };
+            let request_type = direction | VENDOR_REQUEST;
+            let length = data.len() as u16;
             self.bus
                 .control_transfer(
                     &self.device,
@@ -256,19 +254,19 @@ mod app {
             defmt::println!("loop");
             let device = p.next().await;
 
-            if let Some(DeviceEvent::EnumerationError(h, p, e)) = device {
-                defmt::println!(
-                    "Enumeration error {} on hub {} port {}",
-                    e,
-                    h,
-                    p
-                );
+            if let Some(DeviceEvent::EnumerationError(path, e)) = device {
+                defmt::println!("Enumeration error {} on port {}", e, path);
             }
 
             defmt::println!("{:?}", hub_state.topology());
 
-            if let Some(DeviceEvent::Connect(device, info)) = device {
-                defmt::println!("Got device {:x} {:x}", device, info);
+            if let Some(DeviceEvent::Connect(device, info, path)) = device {
+                defmt::println!(
+                    "Got device {:x} {:x} on port {}",
+                    device,
+                    info,
+                    path
+                );
 
                 if let Some(cfg) = identify_ax88772(&info) {
                     let Ok(device) = stack.configure(device, cfg).await else {
@@ -58,7 +58,18 @@ mod app {
             &mut setup.resets,
         );
 
-        let bus = w5500::bus::FourWire::new(w5500_spi);
+        let mut bus = w5500::bus::FourWire::new(w5500_spi);
+        let report = cotton_w5500::self_test::self_test(
+            &mut bus,
+            &cotton_w5500::self_test::SelfTestOptions {
+                loopback: Some(setup.mac_address),
+            },
+        );
+        defmt::println!("{}", defmt::Debug2Format(&report));
+        if report.passed() {
+            defmt::println!("W5500 self-test OK");
+        }
+
         w5500_irq.set_interrupt_enabled(EdgeLow, true);
         unsafe {
             pac::NVIC::unmask(pac::Interrupt::IO_IRQ_BANK0);
@@ -75,7 +75,10 @@ mod app {
     }
 
     impl cotton_ssdp::engine::Callback for Listener {
-        fn on_notification(&self, notification: &cotton_ssdp::Notification) {
+        fn on_notification(
+            &self,
+            notification: &cotton_ssdp::Notification,
+        ) -> Result<(), cotton_ssdp::engine::CallbackError> {
             if let cotton_ssdp::Notification::Alive {
                 ref notification_type,
                 location,
@@ -88,6 +91,7 @@ mod app {
                     &location[..]
                 );
             }
+            Ok(())
         }
     }
 
@@ -158,6 +162,7 @@ mod app {
             cotton_netif::Flags::UP
                 | cotton_netif::Flags::RUNNING
                 | cotton_netif::Flags::MULTICAST,
+                cotton_netif::OperState::Up,
         );
 
         {
@@ -170,7 +175,7 @@ mod app {
             _ = ssdp.on_network_event(&ev, &wi, &ws);
 
             ssdp.subscribe(
-                "cotton-test-server-rp2040".to_string(),
+                "cotton-test-server-rp2040",
                 Listener {},
                 &ws,
             );
@@ -182,10 +187,10 @@ mod app {
 
             ssdp.advertise(
                 uuid,
-                cotton_ssdp::Advertisement {
-                    notification_type: "rp2040-w5500-test".to_string(),
-                    location: "http://127.0.0.1/".to_string(),
-                },
+                cotton_ssdp::Advertisement::new(
+                    "rp2040-w5500-test",
+                    "http://127.0.0.1/",
+                ),
                 &ws,
             );
         }
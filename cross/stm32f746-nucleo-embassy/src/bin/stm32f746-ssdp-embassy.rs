@@ -4,14 +4,13 @@
 extern crate alloc;
 
 use alloc::string::ToString;
-use core::ops::AddAssign;
-use cotton_netif::InterfaceIndex;
+use cotton_ssdp::refresh_timer::EmbassyTimebase;
+use cotton_ssdp::udp::embassy::{WrappedSocket, WrappedStack};
 use cotton_ssdp::udp::smoltcp::{
     GenericIpAddress, GenericIpv4Address, GenericSocketAddr,
 };
 use defmt::{println, unwrap};
 use embassy_executor::Spawner;
-use embassy_futures::block_on;
 use embassy_net::udp::{PacketMetadata, UdpSocket};
 use embassy_net::{Stack, StackResources};
 use embassy_stm32::eth::generic_smi::GenericSMI;
@@ -22,7 +21,6 @@ use embassy_stm32::time::Hertz;
 use embassy_stm32::{bind_interrupts, eth, peripherals, rng, Config};
 use embassy_time::WithTimeout;
 use linked_list_allocator::LockedHeap;
-use no_std_net::IpAddr;
 use rand_core::RngCore;
 use static_cell::StaticCell;
 use {defmt_rtt as _, panic_probe as _};
@@ -64,7 +62,10 @@ type Device = Ethernet<'static, ETH, GenericSMI>;
 pub struct Listener {}
 
 impl cotton_ssdp::engine::Callback for Listener {
-    fn on_notification(&self, notification: &cotton_ssdp::Notification) {
+    fn on_notification(
+        &self,
+        notification: &cotton_ssdp::Notification,
+    ) -> Result<(), cotton_ssdp::engine::CallbackError> {
         if let cotton_ssdp::Notification::Alive {
             ref notification_type,
             location,
@@ -77,114 +78,7 @@ impl cotton_ssdp::engine::Callback for Listener {
                 &location[..]
             );
         }
-    }
-}
-
-/// A Timebase based on Embassy time
-///
-/// We can use Embassy's Instant directly, but must make a newtype for
-/// Duration because the Embassy one isn't
-/// `From<core::time::Duration>`.
-struct EmbassyTimebase;
-
-#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
-#[repr(transparent)]
-struct EmbassyDuration(pub embassy_time::Duration);
-
-impl From<core::time::Duration> for EmbassyDuration {
-    fn from(d: core::time::Duration) -> Self {
-        Self(embassy_time::Duration::from_ticks(d.as_millis() as u64))
-    }
-}
-
-impl AddAssign<EmbassyDuration> for embassy_time::Instant {
-    fn add_assign(&mut self, d: EmbassyDuration) {
-        *self += d.0;
-    }
-}
-
-impl cotton_ssdp::refresh_timer::Timebase for EmbassyTimebase {
-    type Duration = EmbassyDuration;
-    type Instant = embassy_time::Instant;
-}
-
-struct WrappedStack<'a, D: embassy_net::driver::Driver> {
-    stack: &'a Stack<D>,
-}
-
-impl<'a, D: embassy_net::driver::Driver> WrappedStack<'a, D> {
-    pub const fn new(stack: &'a Stack<D>) -> Self {
-        Self { stack }
-    }
-}
-
-impl<D: embassy_net::driver::Driver> cotton_ssdp::udp::Multicast
-    for WrappedStack<'_, D>
-{
-    fn join_multicast_group(
-        &self,
-        multicast_address: &IpAddr,
-        _interface: InterfaceIndex,
-    ) -> Result<(), cotton_ssdp::udp::error::Error> {
-        let ip: embassy_net::IpAddress =
-            GenericIpAddress::from(*multicast_address).into();
-
-        // @todo This block_on isn't very idiomatic for Embassy
-        block_on(self.stack.join_multicast_group(ip))
-            .map(|_| ())
-            .map_err(|e| {
-                cotton_ssdp::udp::error::Error::SmoltcpMulticast(
-                    cotton_ssdp::udp::Syscall::JoinMulticast,
-                    e,
-                )
-            })
-    }
-
-    fn leave_multicast_group(
-        &self,
-        _multicast_address: &IpAddr,
-        _interface: InterfaceIndex,
-    ) -> Result<(), cotton_ssdp::udp::error::Error> {
-        todo!();
-    }
-}
-
-struct WrappedSocket<'a, 'b> {
-    socket: &'a mut UdpSocket<'b>,
-}
-
-impl<'a, 'b> WrappedSocket<'a, 'b> {
-    pub fn new(socket: &'a mut UdpSocket<'b>) -> Self {
-        Self { socket }
-    }
-}
-
-impl cotton_ssdp::udp::TargetedSend for WrappedSocket<'_, '_> {
-    fn send_with<F>(
-        &self,
-        size: usize,
-        to: &no_std_net::SocketAddr,
-        _from: &no_std_net::IpAddr,
-        f: F,
-    ) -> Result<(), cotton_ssdp::udp::error::Error>
-    where
-        F: FnOnce(&mut [u8]) -> usize,
-    {
-        // @todo This buffer/copy is undesirable
-        //
-        // send_with is coming in the next (0.5?) version of embassy-net
-        let mut buf = [0u8; 1520];
-
-        if size > buf.len() {
-            return Err(cotton_ssdp::udp::error::Error::NotImplemented); // not quite right
-        }
-        let size = f(&mut buf);
-        let ep: embassy_net::IpEndpoint = GenericSocketAddr::from(*to).into();
-
-        // @todo This block_on isn't very idiomatic for Embassy
-        block_on(self.socket.send_to(&buf[0..size], ep))
-            .map(|_| ())
-            .map_err(|_| cotton_ssdp::udp::error::Error::NotImplemented)
+        Ok(())
     }
 }
 
@@ -298,11 +192,12 @@ async fn main(spawner: Spawner) -> ! {
         cotton_netif::Flags::UP
             | cotton_netif::Flags::RUNNING
             | cotton_netif::Flags::MULTICAST,
+            cotton_netif::OperState::Up,
     );
 
     {
         let wi = WrappedStack::new(stack);
-        let ws = WrappedSocket::new(&mut udp_socket);
+        let ws = WrappedSocket::new(&udp_socket);
         _ = ssdp.on_network_event(&ev, &wi, &ws);
 
         if let Some(ip) = stack.config_v4().map(|cfg| cfg.address.address()) {
@@ -316,7 +211,7 @@ async fn main(spawner: Spawner) -> ! {
         }
 
         ssdp.subscribe(
-            "cotton-test-server-stm32f746".to_string(),
+            "cotton-test-server-stm32f746",
             Listener {},
             &ws,
         );
@@ -327,10 +222,10 @@ async fn main(spawner: Spawner) -> ! {
         );
         ssdp.advertise(
             uuid,
-            cotton_ssdp::Advertisement {
-                notification_type: "stm32f746-nucleo-test".to_string(),
-                location: "http://127.0.0.1/".to_string(),
-            },
+            cotton_ssdp::Advertisement::new(
+                "stm32f746-nucleo-test",
+                "http://127.0.0.1/",
+            ),
             &ws,
         );
     }
@@ -355,7 +250,7 @@ async fn main(spawner: Spawner) -> ! {
                 )
             }
         } else {
-            ssdp.handle_timeout(&WrappedSocket::new(&mut udp_socket), now);
+            ssdp.handle_timeout(&WrappedSocket::new(&udp_socket), now);
         }
     }
 }
@@ -41,7 +41,10 @@ mod app {
     }
 
     impl cotton_ssdp::engine::Callback for Listener {
-        fn on_notification(&self, notification: &cotton_ssdp::Notification) {
+        fn on_notification(
+            &self,
+            notification: &cotton_ssdp::Notification,
+        ) -> Result<(), cotton_ssdp::engine::CallbackError> {
             if let cotton_ssdp::Notification::Alive {
                 ref notification_type,
                 location,
@@ -54,6 +57,7 @@ mod app {
                     &location[..]
                 );
             }
+            Ok(())
         }
     }
 
@@ -131,6 +135,7 @@ mod app {
             cotton_netif::Flags::UP
                 | cotton_netif::Flags::RUNNING
                 | cotton_netif::Flags::MULTICAST,
+                cotton_netif::OperState::Up,
         );
 
         {
@@ -143,7 +148,7 @@ mod app {
             let ws = WrappedSocket::new(&mut udp_socket);
             _ = ssdp.on_network_event(&ev, &wi, &ws);
             ssdp.subscribe(
-                "cotton-test-server-stm32f746".to_string(),
+                "cotton-test-server-stm32f746",
                 Listener {},
                 &ws,
             );
@@ -154,10 +159,10 @@ mod app {
             );
             ssdp.advertise(
                 uuid,
-                cotton_ssdp::Advertisement {
-                    notification_type: "stm32f746-nucleo-test".to_string(),
-                    location: "http://127.0.0.1/".to_string(),
-                },
+                cotton_ssdp::Advertisement::new(
+                    "stm32f746-nucleo-test",
+                    "http://127.0.0.1/",
+                ),
                 &ws,
             );
         }
@@ -9,6 +9,7 @@ fn main() {
     println!("cargo:rerun-if-changed=../cross/stm32f746-nucleo-embassy");
     println!("cargo:rerun-if-changed=../cross/stm32f746-nucleo-rtic2");
     println!("cargo:rerun-if-changed=../cross/rp2040-w5500");
+    println!("cargo:rerun-if-changed=../cross/rp2040-w5500-embassy");
     println!("cargo:rerun-if-changed=../cross/rp2040-w55000-rtic2");
 
     println!("cargo:rerun-if-changed=../cotton-ssdp");
@@ -100,6 +101,26 @@ fn main() {
         io::stdout().write_all(&child.stdout).unwrap();
         assert!(child.status.success());
 
+        // cross/rp2040-w5500-embassy
+
+        let filtered_env: HashMap<String, String> = env::vars()
+            .filter(|(k, _)| !k.starts_with("CARGO"))
+            .collect();
+        let child = Command::new("cargo")
+            .arg("build")
+            .arg("-vv")
+            .arg("--bins")
+            .arg("--target")
+            .arg("thumbv6m-none-eabi")
+            .current_dir("../cross/rp2040-w5500-embassy")
+            .env_clear()
+            .envs(&filtered_env)
+            .output()
+            .expect("failed to cross-compile for ARM");
+        io::stdout().write_all(&child.stderr).unwrap();
+        io::stdout().write_all(&child.stdout).unwrap();
+        assert!(child.status.success());
+
         // cross/rp2040-w5500-rtic2
 
         let filtered_env: HashMap<String, String> = env::vars()
@@ -0,0 +1,397 @@
+//! Host-side analysis of throughput figures reported by device firmware
+//!
+//! Benchmarking firmware reports each measurement as a single line of
+//! the form
+//!
+//! ```text
+//! BENCH <name> <bytes> bytes in <micros> us
+//! ```
+//!
+//! which the system tests pick out of the probe-rs output, parse into
+//! a [`Measurement`], and compare against a per-board [`Baseline`].
+//! A measurement more than the allowed tolerance *below* its baseline
+//! is a regression and fails the test; a measurement well above its
+//! baseline is merely reported, as a hint that the baseline wants
+//! raising.
+//!
+//! That's only once a baseline has been measured, though. Until then
+//! it's marked [`provisional`](Baseline::provisional), and falling
+//! below it is reported but doesn't fail the test -- so, as all the
+//! baselines here are still provisional, these tests currently report
+//! throughput rather than gating on it.
+//!
+//! Baselines are meant to be conservative (the slowest a healthy
+//! board has been seen to go, not the fastest), so that changes to a
+//! host controller -- DMA, double-buffering, and so on -- show up as
+//! improvements that can be ratcheted in by editing the relevant
+//! constant here. Each baseline's doc comment says where its figures
+//! came from; when a baseline is re-measured, record the board, the
+//! device under test, and the commit alongside it.
+//!
+//! Only RP2040 has baselines so far. The RP235x has the same USB
+//! host controller, but cotton-usb-host doesn't support it yet, so
+//! there's no firmware to measure; its baselines are deferred until
+//! there is.
+
+use std::fmt;
+
+/// The prefix that marks a benchmark line in firmware output
+pub const PREFIX: &str = "BENCH ";
+
+/// One throughput measurement reported by firmware
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Measurement {
+    /// Which benchmark this is, e.g. "msc-read"
+    pub name: String,
+
+    /// How many bytes were transferred
+    pub bytes: u64,
+
+    /// How long the transfer took, in microseconds
+    pub micros: u64,
+}
+
+impl Measurement {
+    /// Parse one benchmark line
+    ///
+    /// Anything before the [`PREFIX`] (such as a defmt timestamp) is
+    /// ignored. Returns `None` if the line isn't a well-formed
+    /// benchmark report, or if it reports a zero duration.
+    pub fn parse(line: &str) -> Option<Self> {
+        let (_, rest) = line.split_once(PREFIX)?;
+        let mut words = rest.split_whitespace();
+        let name = words.next()?;
+        let bytes = words.next()?.parse().ok()?;
+        if words.next()? != "bytes" || words.next()? != "in" {
+            return None;
+        }
+        let micros = words.next()?.parse().ok()?;
+        if words.next()? != "us" || micros == 0 {
+            return None;
+        }
+        Some(Self {
+            name: name.to_string(),
+            bytes,
+            micros,
+        })
+    }
+
+    /// Throughput in bytes per second
+    pub fn bytes_per_sec(&self) -> u64 {
+        ((self.bytes as u128 * 1_000_000) / self.micros as u128) as u64
+    }
+}
+
+impl fmt::Display for Measurement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} bytes in {} us = {} KB/s",
+            self.name,
+            self.bytes,
+            self.micros,
+            self.bytes_per_sec() / 1024
+        )
+    }
+}
+
+/// Find all the benchmark reports in some firmware output
+pub fn parse_all(output: &str) -> Vec<Measurement> {
+    output.lines().filter_map(Measurement::parse).collect()
+}
+
+/// The expected throughput of one benchmark on one board
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Baseline {
+    /// Which benchmark this applies to
+    pub name: &'static str,
+
+    /// Expected throughput in bytes per second
+    pub bytes_per_sec: u64,
+
+    /// How far below the baseline a measurement may fall, as a
+    /// percentage, before it counts as a regression
+    pub tolerance_percent: u8,
+
+    /// Whether `bytes_per_sec` is an estimate rather than a
+    /// measurement: regressions against a provisional baseline are
+    /// reported, but shouldn't fail a test
+    pub provisional: bool,
+}
+
+impl Baseline {
+    /// The slowest throughput that still passes
+    pub fn minimum(&self) -> u64 {
+        self.bytes_per_sec * (100 - self.tolerance_percent.min(100) as u64)
+            / 100
+    }
+
+    /// Compare a measurement against this baseline
+    pub fn check(&self, m: &Measurement) -> Result<Verdict, Regression> {
+        let measured = m.bytes_per_sec();
+        if measured < self.minimum() {
+            Err(Regression {
+                name: self.name,
+                measured,
+                minimum: self.minimum(),
+                provisional: self.provisional,
+            })
+        } else if measured
+            > self.bytes_per_sec * (100 + self.tolerance_percent as u64) / 100
+        {
+            Ok(Verdict::Improved)
+        } else {
+            Ok(Verdict::Ok)
+        }
+    }
+}
+
+/// Outcome of a successful [`Baseline::check`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    /// Within tolerance of the baseline
+    Ok,
+
+    /// Faster than the baseline by more than the tolerance: the
+    /// baseline could be raised
+    Improved,
+}
+
+/// A benchmark ran more slowly than its baseline allows
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Regression {
+    /// Which benchmark regressed
+    pub name: &'static str,
+
+    /// Measured throughput in bytes per second
+    pub measured: u64,
+
+    /// Slowest acceptable throughput in bytes per second
+    pub minimum: u64,
+
+    /// Whether the baseline was only an estimate, see
+    /// [`Baseline::provisional`]
+    pub provisional: bool,
+}
+
+impl fmt::Display for Regression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} regressed: {} bytes/s, minimum {} bytes/s",
+            self.name, self.measured, self.minimum
+        )?;
+        if self.provisional {
+            write!(f, " (provisional)")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Regression {}
+
+/// Check every baseline against the measurements found in `output`
+///
+/// Each baseline must have exactly one matching measurement; a missing
+/// measurement is reported as a regression to zero bytes/s. On
+/// success, returns the verdict for each baseline, in order.
+pub fn check_all(
+    output: &str,
+    baselines: &[Baseline],
+) -> Result<Vec<(Measurement, Verdict)>, Vec<Regression>> {
+    let measurements = parse_all(output);
+    let mut verdicts = Vec::new();
+    let mut regressions = Vec::new();
+    for b in baselines {
+        match measurements.iter().find(|m| m.name == b.name) {
+            Some(m) => match b.check(m) {
+                Ok(v) => verdicts.push((m.clone(), v)),
+                Err(r) => regressions.push(r),
+            },
+            None => regressions.push(Regression {
+                name: b.name,
+                measured: 0,
+                minimum: b.minimum(),
+                provisional: b.provisional,
+            }),
+        }
+    }
+    if regressions.is_empty() {
+        Ok(verdicts)
+    } else {
+        Err(regressions)
+    }
+}
+
+/// Baselines for USB mass-storage sequential transfers on RP2040
+///
+/// Reported by the rp2040-usb-msc firmware, which writes and then
+/// reads 512KiB in 4KiB chunks. The RP2040 host controller is
+/// full-speed only, and cotton-usb-host moves each 64-byte packet by
+/// hand, so these sit well below full-speed's 1.2MB/s theoretical
+/// maximum.
+///
+/// These figures are provisional: they're estimates, set low enough
+/// that a healthy board should clear them comfortably, and have not
+/// yet been checked against a measurement on the test rig. Replace
+/// them with measured figures (noting the board, the flash drive, and
+/// the commit) once the test has run there, and only then clear
+/// `provisional`.
+pub const RP2040_USB_MSC: &[Baseline] = &[
+    Baseline {
+        name: "msc-write",
+        bytes_per_sec: 200 * 1024,
+        tolerance_percent: 25,
+        provisional: true,
+    },
+    Baseline {
+        name: "msc-read",
+        bytes_per_sec: 400 * 1024,
+        tolerance_percent: 20,
+        provisional: true,
+    },
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_line() {
+        let m =
+            Measurement::parse("BENCH msc-read 524288 bytes in 1000000 us")
+                .unwrap();
+        assert_eq!(m.name, "msc-read");
+        assert_eq!(m.bytes, 524288);
+        assert_eq!(m.micros, 1000000);
+        assert_eq!(m.bytes_per_sec(), 524288);
+    }
+
+    #[test]
+    fn parse_ignores_timestamp() {
+        let m = Measurement::parse(
+            "0.123456 BENCH msc-write 4096 bytes in 2000 us",
+        )
+        .unwrap();
+        assert_eq!(m.name, "msc-write");
+        assert_eq!(m.bytes_per_sec(), 2048000);
+    }
+
+    #[test]
+    fn parse_rejects_malformed() {
+        assert!(Measurement::parse("MSC OK").is_none());
+        assert!(Measurement::parse("BENCH msc-read").is_none());
+        assert!(Measurement::parse("BENCH msc-read x bytes in 1 us").is_none());
+        assert!(Measurement::parse("BENCH msc-read 1 bytes in 1 ms").is_none());
+        assert!(Measurement::parse("BENCH msc-read 1 bytes at 1 us").is_none());
+        assert!(Measurement::parse("BENCH msc-read 1 bytes in 0 us").is_none());
+    }
+
+    #[test]
+    fn display() {
+        let m =
+            Measurement::parse("BENCH msc-read 1048576 bytes in 1000000 us")
+                .unwrap();
+        assert_eq!(
+            format!("{m}"),
+            "msc-read: 1048576 bytes in 1000000 us = 1024 KB/s"
+        );
+    }
+
+    const BASE: Baseline = Baseline {
+        name: "msc-read",
+        bytes_per_sec: 1000,
+        tolerance_percent: 10,
+        provisional: false,
+    };
+
+    fn measure(bytes_per_sec: u64) -> Measurement {
+        Measurement {
+            name: "msc-read".to_string(),
+            bytes: bytes_per_sec,
+            micros: 1_000_000,
+        }
+    }
+
+    #[test]
+    fn minimum() {
+        assert_eq!(BASE.minimum(), 900);
+        let b = Baseline {
+            tolerance_percent: 200,
+            ..BASE
+        };
+        assert_eq!(b.minimum(), 0);
+    }
+
+    #[test]
+    fn check_within_tolerance() {
+        assert_eq!(BASE.check(&measure(900)), Ok(Verdict::Ok));
+        assert_eq!(BASE.check(&measure(1100)), Ok(Verdict::Ok));
+    }
+
+    #[test]
+    fn check_improved() {
+        assert_eq!(BASE.check(&measure(1101)), Ok(Verdict::Improved));
+    }
+
+    #[test]
+    fn check_regressed() {
+        let r = BASE.check(&measure(899)).unwrap_err();
+        assert_eq!(r.measured, 899);
+        assert_eq!(r.minimum, 900);
+        assert!(!r.provisional);
+        assert_eq!(
+            format!("{r}"),
+            "msc-read regressed: 899 bytes/s, minimum 900 bytes/s"
+        );
+    }
+
+    #[test]
+    fn check_regressed_provisional() {
+        let b = Baseline {
+            provisional: true,
+            ..BASE
+        };
+        let r = b.check(&measure(899)).unwrap_err();
+        assert!(r.provisional);
+        assert_eq!(
+            format!("{r}"),
+            "msc-read regressed: 899 bytes/s, minimum 900 bytes/s \
+             (provisional)"
+        );
+        let r = check_all("MSC OK\n", &[b]).unwrap_err();
+        assert!(r[0].provisional);
+    }
+
+    #[test]
+    fn check_all_ok() {
+        let output = "MSC OK\n\
+                      BENCH msc-read 1000 bytes in 1000000 us\n\
+                      BENCH msc-write 5000 bytes in 1000000 us\n";
+        let v = check_all(output, &[BASE]).unwrap();
+        assert_eq!(v.len(), 1);
+        assert_eq!(v[0].1, Verdict::Ok);
+    }
+
+    #[test]
+    fn check_all_missing() {
+        let r = check_all("MSC OK\n", &[BASE]).unwrap_err();
+        assert_eq!(r.len(), 1);
+        assert_eq!(r[0].measured, 0);
+    }
+
+    #[test]
+    fn check_all_regressed() {
+        let output = "BENCH msc-read 10 bytes in 1000000 us\n";
+        let r = check_all(output, &[BASE]).unwrap_err();
+        assert_eq!(r[0].name, "msc-read");
+    }
+
+    #[test]
+    fn rp2040_baselines_sane() {
+        for b in RP2040_USB_MSC {
+            assert!(b.minimum() > 0);
+            assert!(b.bytes_per_sec < 1_200_000);
+        }
+    }
+}
@@ -1 +1,3 @@
-/* empty */
+//! Support code shared by the Cotton system tests
+
+pub mod benchmarks;
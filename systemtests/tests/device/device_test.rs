@@ -117,6 +117,45 @@ impl DeviceTest {
         }
     }
 
+    /// Wait for a complete line of stdout containing `needle`, and
+    /// return it from `needle` onwards (without the newline)
+    pub fn expect_line(&self, needle: &str, timeout: Duration) -> String {
+        let start = Instant::now();
+        eprintln!("{:?}: searching stdout for line {needle}", Instant::now());
+
+        loop {
+            {
+                let mut inner = self.inner.lock().unwrap();
+                inner.poll();
+                if let Some((_before, after)) = inner.output.split_once(needle)
+                {
+                    if let Some((line, rest)) = after.split_once('\n') {
+                        let line = format!("{needle}{}", line.trim_end());
+                        eprintln!("OK: {line}");
+                        inner.output = rest.to_string();
+                        return line;
+                    }
+                }
+
+                if start.elapsed() > timeout {
+                    eprintln!(
+                        "{:?}: FAIL stdout {}",
+                        Instant::now(),
+                        inner.output
+                    );
+                    eprintln!(
+                        "{:?}: FAIL stderr {}",
+                        Instant::now(),
+                        inner.errors
+                    );
+                    assert_contains!(inner.output, needle);
+                    panic!("no complete line after {needle}");
+                }
+            }
+            sleep(Duration::from_millis(200));
+        }
+    }
+
     pub fn expect_stderr(&self, needle: &str, timeout: Duration) {
         let start = Instant::now();
         eprintln!("{:?}: searching stderr for {needle}", Instant::now());
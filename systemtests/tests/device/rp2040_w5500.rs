@@ -3,6 +3,7 @@ use crate::ssdp_test::ssdp_test;
 use serial_test::*;
 use std::panic;
 use std::time::Duration;
+use systemtests::benchmarks;
 
 fn rp2040_test<F: FnOnce(DeviceTest) -> () + panic::UnwindSafe>(
     firmware: &str,
@@ -44,6 +45,7 @@ fn arm_rp2040_w5500macraw_dhcp_rtic() {
         "../cross/rp2040-w5500/target/thumbv6m-none-eabi/debug/rp2040-w5500macraw-dhcp-rtic",
         |t| {
             t.expect_stderr("Finished in", Duration::from_secs(45));
+            t.expect("W5500 self-test OK", Duration::from_secs(10));
             t.expect("DHCP config acquired!", Duration::from_secs(10));
         },
     );
@@ -72,6 +74,29 @@ fn arm_rp2040_w5500macraw_ssdp_rtic() {
     );
 }
 
+#[test]
+#[serial(rp2040_w5500)]
+#[cfg_attr(miri, ignore)]
+fn arm_rp2040_w5500_ssdp_embassy() {
+    rp2040_test(
+        "../cross/rp2040-w5500-embassy/target/thumbv6m-none-eabi/debug/rp2040-w5500-ssdp-embassy",
+        |nt| {
+            nt.expect_stderr("Finished in", Duration::from_secs(45));
+            nt.expect("DHCP config acquired!", Duration::from_secs(10));
+            ssdp_test(
+                "cotton-test-server-rp2040",
+                "rp2040-w5500-test",
+                |st| {
+                    nt.expect("SSDP! cotton-test-server-rp2040",
+                              Duration::from_secs(20));
+                    st.expect_seen("rp2040-w5500-test",
+                              Duration::from_secs(30));
+                }
+            );
+        }
+    );
+}
+
 #[test]
 #[serial(rp2040_w5500)]
 #[cfg_attr(miri, ignore)]
@@ -84,3 +109,45 @@ fn arm_rp2040_usb_otge() {
         }
     );
 }
+
+#[test]
+#[serial(rp2040_w5500)]
+#[cfg_attr(miri, ignore)]
+fn arm_rp2040_usb_msc() {
+    // The debug build, like all the other tests: in these firmware
+    // crates it's optimised exactly as release is (opt-level "s",
+    // LTO, one codegen unit), but unlike release it keeps the symbols
+    // that defmt needs in order to decode the output.
+    rp2040_test(
+        "../cross/rp2040-w5500-rtic2/target/thumbv6m-none-eabi/debug/rp2040-usb-msc",
+        |nt| {
+            nt.expect_stderr("Finished in", Duration::from_secs(45));
+            nt.expect("MSC OK", Duration::from_secs(20));
+
+            let mut output = String::new();
+            for b in benchmarks::RP2040_USB_MSC {
+                let needle = format!("{}{} ", benchmarks::PREFIX, b.name);
+                output += &nt.expect_line(&needle, Duration::from_secs(30));
+                output.push('\n');
+            }
+            match benchmarks::check_all(&output, benchmarks::RP2040_USB_MSC) {
+                Ok(verdicts) => {
+                    for (m, v) in verdicts {
+                        eprintln!("{m} {v:?}");
+                    }
+                }
+                Err(regressions) => {
+                    for r in &regressions {
+                        eprintln!("{r}");
+                    }
+                    // Only regressions against measured baselines count
+                    let failed =
+                        regressions.iter().filter(|r| !r.provisional).count();
+                    if failed > 0 {
+                        panic!("{failed} benchmark(s) regressed");
+                    }
+                }
+            }
+        }
+    );
+}
@@ -64,10 +64,10 @@ pub fn ssdp_test<F: FnOnce(SsdpTest) -> () + panic::UnwindSafe>(
             let uuid = uuid::Uuid::new_v4();
             ssdp.advertise(
                 uuid.to_string(),
-                cotton_ssdp::Advertisement {
-                    notification_type: my_service.to_string(),
-                    location: "http://127.0.0.1/test".to_string(),
-                },
+                cotton_ssdp::Advertisement::new(
+                    my_service.to_string(),
+                    "http://127.0.0.1/test",
+                ),
             );
 
             ssdp.subscribe(
@@ -80,7 +80,7 @@ pub fn ssdp_test<F: FnOnce(SsdpTest) -> () + panic::UnwindSafe>(
                     } = r
                     {
                         let mut v = seen2.lock().unwrap();
-                        v.insert(notification_type.clone());
+                        v.insert(notification_type.into_owned());
                     }
                 }),
             );